@@ -2,8 +2,26 @@ fn android_sth() {
     println!("cargo:rustc-link-lib=c++_shared");
 }
 
+fn git_hash() -> String {
+    std::process::Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|x| x.status.success())
+        .and_then(|x| String::from_utf8(x.stdout).ok())
+        .map(|x| x.trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+fn build_info() {
+    println!("cargo:rustc-env=MP_GIT_HASH={}", git_hash());
+    println!("cargo:rustc-env=MP_TARGET={}", std::env::var("TARGET").unwrap_or_default());
+    println!("cargo:rustc-env=MP_PROFILE={}", std::env::var("PROFILE").unwrap_or_default());
+}
+
 fn main() {
     if std::env::var("CARGO_CFG_TARGET_OS").unwrap_or("".to_string()) == "android" {
         android_sth();
     }
-}
\ No newline at end of file
+    build_info();
+}
@@ -3,9 +3,29 @@
 use log::LevelFilter;
 
 fn main() {
-    env_logger::builder()
+    let logger = env_logger::builder()
         .filter_level(LevelFilter::Info)
         .parse_default_env()
-        .init();
-    mp_core::real_main();
+        .build();
+    // echo the logs to the developer console
+    let level = logger.filter();
+    mp_core::init_logger(logger, level).expect("Set the logger failed");
+    // step all levels without the window with `--smoke [seconds]`
+    if std::env::args().any(|x| x == "--smoke") {
+        let seconds = std::env::args().skip_while(|x| x != "--smoke").nth(1)
+            .and_then(|x| x.parse().ok())
+            .unwrap_or(10.0);
+        std::process::exit(if mp_core::smoke_main(seconds) { 0 } else { 1 });
+    }
+    // run as the dedicated server with `--server 0.0.0.0:23333 [--level rooms:3]`
+    if let Some(listen) = std::env::args().skip_while(|x| x != "--server").nth(1) {
+        let level = std::env::args().skip_while(|x| x != "--level").nth(1).unwrap_or_else(|| "level0".into());
+        mp_core::server_main(&listen, &level);
+        return;
+    }
+    // run as the rendezvous server with `--rendezvous 0.0.0.0:23334`
+    match std::env::args().skip_while(|x| x != "--rendezvous").nth(1) {
+        Some(listen) => mp_core::rendezvous_main(&listen),
+        None => mp_core::real_main(),
+    }
 }
@@ -0,0 +1,167 @@
+use winit::dpi::PhysicalSize;
+use winit::event_loop::{EventLoop, EventLoopBuilder};
+use winit::window::WindowBuilder;
+
+use crate::engine::{GameState, StateData};
+use crate::engine::global::{EngineFeatures, FEATURES, GLOBAL_DATA};
+use crate::engine::window::{EventLoopMessage, WindowManager};
+use crate::state::{InitState, SetupFn};
+use crate::state::registry::StateRegistry;
+
+/// Configure and run the engine, the entry to embed the engine in the other project.
+///
+/// ```ignore
+/// struct MyRenderer(wgpu::RenderPipeline);
+///
+/// impl MyRenderer {
+///     fn new(gpu: &mp_core::WgpuData) -> Self { ... }
+/// }
+///
+/// mp_core::EngineBuilder::new()
+///     .title("My game")
+///     .register_state("menu", MyMenu::default)
+///     .start_state("menu")
+///     .texture("logo", "texture/logo.png")
+///     .setup(|s| s.app.world.insert(MyRenderer::new(s.app.gpu.as_ref().unwrap())))
+///     .run();
+/// ```
+pub struct EngineBuilder {
+    title: String,
+    /// The window size, the config's if not set.
+    size: Option<(u32, u32)>,
+    registry: StateRegistry,
+    textures: Vec<(String, String)>,
+    setups: Vec<SetupFn>,
+    features: EngineFeatures,
+}
+
+impl Default for EngineBuilder {
+    /// The builder with the built-in states.
+    fn default() -> Self {
+        let is_3d = std::env::var("3d").map(|x| x == "1").unwrap_or(true);
+        Self {
+            title: if is_3d { "3D" } else { "RustMeeting" }.into(),
+            size: None,
+            registry: StateRegistry::default(),
+            textures: vec![],
+            setups: vec![],
+            features: Default::default(),
+        }
+    }
+}
+
+#[allow(unused)]
+impl EngineBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn title(mut self, title: impl Into<String>) -> Self {
+        self.title = title.into();
+        self
+    }
+
+    /// Set the window size instead of the config's.
+    pub fn size(mut self, width: u32, height: u32) -> Self {
+        self.size = Some((width, height));
+        self
+    }
+
+    /// Replace the registry, the built-in states are not kept.
+    pub fn registry(mut self, registry: StateRegistry) -> Self {
+        self.registry = registry;
+        self
+    }
+
+    pub fn register_state<S: GameState + Send + 'static>(mut self, name: impl Into<String>, f: impl Fn() -> S + Send + Sync + 'static) -> Self {
+        self.registry.register(name, f);
+        self
+    }
+
+    /// The state to boot into if not selected by the command line or the config.
+    pub fn start_state(mut self, name: impl Into<String>) -> Self {
+        self.registry.set_default(name);
+        self
+    }
+
+    /// Load the texture with the name when the gpu is ready.
+    pub fn texture(mut self, name: impl Into<String>, path: impl Into<String>) -> Self {
+        self.textures.push((name.into(), path.into()));
+        self
+    }
+
+    /// Called once the gpu is ready before the start state, to insert the renderers and the resources.
+    pub fn setup(mut self, f: impl FnOnce(&mut StateData) + Send + 'static) -> Self {
+        self.setups.push(Box::new(f));
+        self
+    }
+
+    pub fn features(mut self, features: EngineFeatures) -> Self {
+        self.features = features;
+        self
+    }
+
+    pub fn audio(mut self, enabled: bool) -> Self {
+        self.features.audio = enabled;
+        self
+    }
+
+    pub fn statistics(mut self, enabled: bool) -> Self {
+        self.features.statistics = enabled;
+        self
+    }
+
+    pub fn console(mut self, enabled: bool) -> Self {
+        self.features.console = enabled;
+        self
+    }
+
+    /// Run the engine until the main window closed.
+    pub fn run(self) {
+        self.run_with(EventLoopBuilder::with_user_event().build());
+    }
+
+    /// Run the engine with the event loop built, for the platforms need the special event loop.
+    pub fn run_with(self, event_loop: EventLoop<EventLoopMessage>) {
+        println!("[Std Stream] Joined the real main");
+        eprintln!("[Err Stream] Joined the real main");
+        log::info!("[Log Info] Joined the real main");
+        if FEATURES.set(self.features).is_err() {
+            log::warn!("The engine features are set already, ignored {:?}", self.features);
+        }
+        let size = self.size.unwrap_or_else(|| {
+            let cfg = GLOBAL_DATA.cfg_data.read().unwrap();
+            (cfg.settings().window.width, cfg.settings().window.height)
+        });
+        let window = WindowBuilder::new()
+            .with_title(&self.title)
+            .with_inner_size(PhysicalSize::new(size.0, size.1))
+            .build(&event_loop)
+            .unwrap();
+
+        log::info!("Got the window");
+
+        let start_state = match self.registry.create_start() {
+            Ok(x) => x,
+            Err(e) => {
+                log::error!("Create the start state failed for {:?}", e);
+                eprintln!("Create the start state failed for {:?}", e);
+                return;
+            }
+        };
+        let init = InitState::new(start_state)
+            .with_textures(self.textures)
+            .with_setups(self.setups);
+
+        match WindowManager::new(window, &event_loop) {
+            Ok(am) => {
+                log::info!("Got the main application");
+                am.run_loop(event_loop, init);
+            }
+            Err(e) => {
+                log::error!("Init the app manager failed for {:?}", e);
+                eprintln!("Init the app manager failed for {:?}", e);
+            }
+        }
+    }
+}
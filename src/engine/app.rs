@@ -1,12 +1,19 @@
 use std::sync::Arc;
 
-use egui::{Context, Style};
-use egui_winit::State;
 use log::{info, warn};
-use specs::{World, WorldExt};
-use winit::window::Window;
+use specs::{Dispatcher, World, WorldExt};
+use winit::dpi::PhysicalSize;
+use winit::window::{Fullscreen, Window};
 
 use crate::engine::{AudioData, BakedInputs, MainRendererData, ResourceManager, WgpuData};
+use crate::engine::config::WindowMode;
+use crate::engine::ecs;
+use crate::engine::ecs::SpawnCommands;
+use crate::engine::global::{features, GLOBAL_DATA};
+use crate::engine::metrics::FrameMetrics;
+use crate::engine::pacing::FramePacing;
+use crate::engine::render::recorder::{FrameRecorder, FrameSink};
+use crate::engine::ui::WindowUi;
 use crate::engine::window::EventLoopTargetType;
 
 pub struct AppInstance {
@@ -15,18 +22,33 @@ pub struct AppInstance {
     pub render: Option<MainRendererData>,
     pub res: Arc<ResourceManager>,
     pub last_render_time: std::time::Instant,
-    pub egui_ctx: Context,
-    pub egui_state: State,
+    pub ui: WindowUi,
 
     pub inputs: BakedInputs,
     pub lua: mlua::Lua,
     pub world: World,
+    /// The systems run on the world after the state updated.
+    pub dispatcher: Dispatcher<'static, 'static>,
+    /// The entities queued by the states, applied after the update.
+    pub commands: SpawnCommands,
 
     pub audio: Option<AudioData>,
+    pub pacing: FramePacing,
+    /// Whether the window is focused, the frames are limited if not.
+    pub focused: bool,
+    /// The timings of the phases in the frames presented.
+    pub metrics: FrameMetrics,
+    /// The frames presented read back for the video if some.
+    pub recorder: Option<FrameRecorder>,
 }
 
 impl AppInstance {
-    fn new_with_gpu(window: Window, event_loop: &EventLoopTargetType, gpu: Option<WgpuData>) -> anyhow::Result<Self> {
+    fn new_with_gpu(window: Window, event_loop: &EventLoopTargetType, mut gpu: Option<WgpuData>) -> anyhow::Result<Self> {
+        let settings = GLOBAL_DATA.cfg_data.read().unwrap().settings().clone();
+        if let Some(gpu) = gpu.as_mut() {
+            gpu.set_render_scale(settings.video.render_scale);
+            gpu.set_vsync(settings.video.vsync);
+        }
         let res = ResourceManager::new()?;
         let render = if let Some(gpu) = &gpu {
             Some(MainRendererData::new(gpu, &res))
@@ -35,37 +57,45 @@ impl AppInstance {
         };
         let rua = mlua::Lua::new();
         info!("Got the lua");
-        let egui_ctx = Context::default();
-        info!("Got the egui context");
-        let mut style = Style::default();
-        style.clone_from(&egui_ctx.style());
-        for (_, s) in &mut style.text_styles {
-            s.size *= 1.25;
-        }
-        egui_ctx.set_style(style);
-        if gpu.is_some() {
-            egui_ctx.set_pixels_per_point(window.scale_factor() as f32);
-            info!("Set the egui context scale factor");
-        }
-        let al = match std::panic::catch_unwind(|| {
-            match AudioData::new() {
-                Ok(al) => Some(al),
+        let mut ui = WindowUi::new(&window, event_loop, gpu.as_ref());
+        ui.set_ui_scale(&window, settings.video.ui_scale);
+        let al = if !features().audio {
+            info!("The audio is disabled");
+            None
+        } else {
+            match std::panic::catch_unwind(|| {
+                match AudioData::new() {
+                    Ok(al) => Some(al),
+                    Err(e) => {
+                        warn!("Load audio failed for {:?}", e);
+                        None
+                    }
+                }
+            }) {
+                Ok(al) => al,
                 Err(e) => {
-                    warn!("Load audio failed for {:?}", e);
+                    warn!("Get audio even panicked for {:?} with type id {:?}", e, e.type_id());
                     None
                 }
             }
-        }) {
-            Ok(al) => al,
-            Err(e) => {
-                warn!("Get audio even panicked for {:?} with type id {:?}", e, e.type_id());
-                None
-            }
         };
 
+        let al = al.map(|mut al| {
+            let audio = &settings.audio;
+            if let Err(e) = al.set_master_volume(audio.master_volume)
+                .and_then(|_| al.set_music_volume(audio.music_volume))
+                .and_then(|_| al.set_sfx_volume(audio.sfx_volume)) {
+                warn!("Set the volumes in config failed for {:?}", e);
+            }
+            al
+        });
+
         info!("Creating thread pool");
 
 
+        let mut world = World::new();
+        let dispatcher = ecs::setup(&mut world);
+
         info!("Almost got all window instance field");
         Ok(Self {
             window,
@@ -73,12 +103,17 @@ impl AppInstance {
             render,
             res: res.into(),
             last_render_time: std::time::Instant::now(),
-            egui_ctx,
-            egui_state: State::new(event_loop),
+            ui,
             inputs: Default::default(),
             lua: rua,
-            world: World::new(),
+            world,
+            dispatcher,
+            commands: Default::default(),
             audio: al,
+            pacing: Default::default(),
+            focused: true,
+            metrics: Default::default(),
+            recorder: None,
         })
     }
 
@@ -91,8 +126,109 @@ impl AppInstance {
 
     #[inline]
     pub fn new(window: Window, event_loop: &EventLoopTargetType) -> anyhow::Result<Self> {
-        let gpu = WgpuData::new(&window).ok();
-        Self::new_with_gpu(window, event_loop, gpu)
+        let video = GLOBAL_DATA.cfg_data.read().unwrap().settings().video.clone();
+        let gpu = WgpuData::new(&window, video.hdr).ok();
+        let mut this = Self::new_with_gpu(window, event_loop, gpu)?;
+        if video.window_mode != WindowMode::Windowed {
+            this.set_window_mode(video.window_mode, video.fullscreen_size);
+        }
+        Ok(this)
+    }
+}
+
+#[allow(unused)]
+impl AppInstance {
+    /// Drop the gpu data and the renderer, then create them again with the video settings.
+    ///
+    /// Return false if no gpu got.
+    pub fn recreate_gpu(&mut self) -> bool {
+        // drop the old device first
+        self.render = None;
+        self.gpu = None;
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.reset();
+        }
+        let video = GLOBAL_DATA.cfg_data.read().unwrap().settings().video.clone();
+        let mut gpu = match WgpuData::new(&self.window, video.hdr) {
+            Ok(x) => x,
+            Err(e) => {
+                warn!("Create the gpu data again failed for {:?}", e);
+                return false;
+            }
+        };
+        gpu.set_render_scale(video.render_scale);
+        gpu.set_vsync(video.vsync);
+        self.render = Some(MainRendererData::new(&gpu, &self.res));
+        self.ui.init(&self.window, &gpu);
+        self.gpu = Some(gpu);
+        true
+    }
+
+    /// The sizes of the video modes of the current monitor, from large to small.
+    pub fn fullscreen_sizes(&self) -> Vec<(u32, u32)> {
+        let mut sizes = self.window.current_monitor()
+            .map(|m| m.video_modes().map(|x| (x.size().width, x.size().height)).collect::<Vec<_>>())
+            .unwrap_or_default();
+        sizes.sort_by(|a, b| b.cmp(a));
+        sizes.dedup();
+        sizes
+    }
+
+    /// Switch the window mode, `size` is the inner size for [`WindowMode::Windowed`]
+    /// or the video mode size for [`WindowMode::Fullscreen`]
+    ///
+    /// The surface is reconfigured to the new window size.
+    pub fn set_window_mode(&mut self, mode: WindowMode, size: Option<(u32, u32)>) {
+        let monitor = self.window.current_monitor();
+        let fullscreen = match mode {
+            WindowMode::Windowed => None,
+            WindowMode::Borderless => Some(Fullscreen::Borderless(monitor)),
+            WindowMode::Fullscreen => {
+                // the largest mode of the size with the highest refresh rate
+                let video_mode = monitor.as_ref().and_then(|m| m.video_modes()
+                    .filter(|x| size.map_or(true, |(w, h)| x.size() == PhysicalSize::new(w, h)))
+                    .max_by_key(|x| (x.size().width, x.size().height, x.refresh_rate_millihertz(), x.bit_depth())));
+                match video_mode {
+                    Some(x) => Some(Fullscreen::Exclusive(x)),
+                    None => {
+                        warn!("No video mode for the size {:?}, use the borderless", size);
+                        Some(Fullscreen::Borderless(monitor))
+                    }
+                }
+            }
+        };
+        self.window.set_fullscreen(fullscreen);
+        if let (WindowMode::Windowed, Some((w, h))) = (mode, size) {
+            self.window.set_inner_size(PhysicalSize::new(w, h));
+        }
+        let size = self.window.inner_size();
+        if let Some(gpu) = self.gpu.as_mut() {
+            if size.width > 1 && size.height > 1 {
+                gpu.resize(size.width, size.height);
+            }
+        }
+    }
+
+    /// Record every nth frame presented to the sink, the recording before is finished.
+    pub fn start_recording(&mut self, sink: FrameSink, every: u32) -> anyhow::Result<()> {
+        self.stop_recording()?;
+        let gpu = if let Some(x) = self.gpu.as_ref() { x } else {
+            anyhow::bail!("No gpu to record");
+        };
+        self.recorder = Some(FrameRecorder::new(gpu, sink, every)?);
+        Ok(())
+    }
+
+    /// Write the frames recorded and stop, return false if not recording.
+    pub fn stop_recording(&mut self) -> anyhow::Result<bool> {
+        let recorder = if let Some(x) = self.recorder.take() { x } else {
+            return Ok(false);
+        };
+        match self.gpu.as_ref() {
+            Some(gpu) => recorder.finish(&gpu.device)?,
+            None => warn!("The frames copied are dropped without the gpu"),
+        }
+        Ok(true)
     }
 }
 
@@ -1,18 +1,243 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use kira::LoopBehavior;
 use kira::manager::{AudioManager, AudioManagerSettings};
 use kira::manager::backend::cpal::CpalBackend;
+use kira::sound::static_sound::{PlaybackState, StaticSoundData, StaticSoundHandle};
+use kira::track::{TrackBuilder, TrackHandle};
+use kira::tween::Tween;
+use kira::Volume;
+use log::warn;
+use nalgebra::{Point3, Vector3};
+
+use crate::engine::{Handle, LoadContext, ResourceManager};
+use crate::engine::lifecycle::Lifecycle;
+use crate::engine::render::camera::Camera;
 
 pub struct AudioData {
     pub manager: AudioManager<CpalBackend>,
+    music_track: TrackHandle,
+    sfx_track: TrackHandle,
+    master_volume: f64,
+    music_volume: f64,
+    sfx_volume: f64,
+    emitters: Vec<Option<Emitter>>,
 }
 
+/// The id of the emitter added by [`AudioData::add_emitter`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct EmitterId(usize);
+
+/// The sound source at the position of the world.
+pub struct Emitter {
+    pub world: usize,
+    pub position: Point3<f32>,
+    /// The sounds are full volume within the distance.
+    pub min_distance: f32,
+    /// The sounds are silent beyond the distance.
+    pub max_distance: f32,
+    sounds: Vec<StaticSoundHandle>,
+}
+
+impl Emitter {
+    /// The volume for the distance to the listener.
+    fn attenuation(&self, distance: f32) -> f64 {
+        let range = (self.max_distance - self.min_distance).max(f32::EPSILON);
+        (1.0 - ((distance - self.min_distance) / range).clamp(0.0, 1.0)) as f64
+    }
+}
+
+/// The tween to update the spatial sounds without clicks.
+const SPATIAL_TWEEN: Tween = Tween {
+    start_time: kira::StartTime::Immediate,
+    duration: Duration::from_millis(50),
+    easing: kira::tween::Easing::Linear,
+};
+
 
 impl AudioData {
     pub fn new() -> anyhow::Result<AudioData> {
+        let mut manager = AudioManager::<CpalBackend>::new(AudioManagerSettings::default())?;
+        let music_track = manager.add_sub_track(TrackBuilder::new())?;
+        let sfx_track = manager.add_sub_track(TrackBuilder::new())?;
         Ok(Self {
-            manager: AudioManager::new(AudioManagerSettings::default())?
+            manager,
+            music_track,
+            sfx_track,
+            master_volume: 1.0,
+            music_volume: 1.0,
+            sfx_volume: 1.0,
+            emitters: vec![],
         })
     }
 }
 
+impl Lifecycle for AudioData {
+    fn suspend(&mut self) {
+        if let Err(e) = self.pause_all() {
+            warn!("Pause the audio for suspending failed for {:?}", e);
+        }
+    }
+
+    fn resume(&mut self) {
+        if let Err(e) = self.resume_all() {
+            warn!("Resume the audio failed for {:?}", e);
+        }
+    }
+}
+
+
+#[allow(unused)]
+impl AudioData {
+    /// Queue the sound to load by the resource manager.
+    pub fn load_sound(res: &Arc<ResourceManager>, ctx: &LoadContext, name: impl Into<String>, path: impl Into<String>) -> Handle<StaticSoundData> {
+        res.load(ctx, name, path)
+    }
 
-impl AudioData {}
\ No newline at end of file
+    /// Play the sound once in the sfx track.
+    pub fn play_sfx(&mut self, res: &ResourceManager, sound: Handle<StaticSoundData>) -> anyhow::Result<StaticSoundHandle> {
+        self.play_sfx_with_volume(res, sound, 1.0)
+    }
+
+    /// Play the sound once in the sfx track with the amplitude.
+    pub fn play_sfx_with_volume(&mut self, res: &ResourceManager, sound: Handle<StaticSoundData>, volume: f64) -> anyhow::Result<StaticSoundHandle> {
+        let track = self.sfx_track.id();
+        self.play(res, sound, |x| x.with_modified_settings(|s| s.track(track).volume(Volume::Amplitude(volume))))
+    }
+
+    /// Play the sound in the music track, loop from the start if `looping`
+    pub fn play_music(&mut self, res: &ResourceManager, sound: Handle<StaticSoundData>, looping: bool) -> anyhow::Result<StaticSoundHandle> {
+        let track = self.music_track.id();
+        self.play(res, sound, |x| x.with_modified_settings(|s| {
+            let s = s.track(track);
+            if looping {
+                s.loop_behavior(LoopBehavior { start_position: 0.0 })
+            } else {
+                s
+            }
+        }))
+    }
+
+    fn play(&mut self, res: &ResourceManager, sound: Handle<StaticSoundData>,
+            settings: impl FnOnce(StaticSoundData) -> StaticSoundData) -> anyhow::Result<StaticSoundHandle> {
+        let data = res.sounds.get(sound).ok_or(anyhow!("The sound {:?} is not loaded", sound))?.clone();
+        self.manager.play(settings(data)).map_err(|e| anyhow!("Play sound failed for {:?}", e))
+    }
+
+    pub fn pause_all(&mut self) -> anyhow::Result<()> {
+        Ok(self.manager.pause(Tween::default())?)
+    }
+
+    pub fn resume_all(&mut self) -> anyhow::Result<()> {
+        Ok(self.manager.resume(Tween::default())?)
+    }
+
+    pub fn master_volume(&self) -> f64 {
+        self.master_volume
+    }
+
+    pub fn music_volume(&self) -> f64 {
+        self.music_volume
+    }
+
+    pub fn sfx_volume(&self) -> f64 {
+        self.sfx_volume
+    }
+
+    /// Set the amplitude of all sounds.
+    pub fn set_master_volume(&mut self, volume: f64) -> anyhow::Result<()> {
+        self.manager.main_track().set_volume(Volume::Amplitude(volume), Tween::default())?;
+        self.master_volume = volume;
+        Ok(())
+    }
+
+    pub fn set_music_volume(&mut self, volume: f64) -> anyhow::Result<()> {
+        self.music_track.set_volume(Volume::Amplitude(volume), Tween::default())?;
+        self.music_volume = volume;
+        Ok(())
+    }
+
+    pub fn set_sfx_volume(&mut self, volume: f64) -> anyhow::Result<()> {
+        self.sfx_track.set_volume(Volume::Amplitude(volume), Tween::default())?;
+        self.sfx_volume = volume;
+        Ok(())
+    }
+
+    pub fn add_emitter(&mut self, world: usize, position: Point3<f32>, min_distance: f32, max_distance: f32) -> EmitterId {
+        let emitter = Emitter {
+            world,
+            position,
+            min_distance,
+            max_distance,
+            sounds: vec![],
+        };
+        match self.emitters.iter().position(Option::is_none) {
+            Some(idx) => {
+                self.emitters[idx] = Some(emitter);
+                EmitterId(idx)
+            }
+            None => {
+                self.emitters.push(Some(emitter));
+                EmitterId(self.emitters.len() - 1)
+            }
+        }
+    }
+
+    pub fn emitter_mut(&mut self, id: EmitterId) -> Option<&mut Emitter> {
+        self.emitters.get_mut(id.0).and_then(Option::as_mut)
+    }
+
+    /// Remove the emitter and stop its sounds.
+    pub fn remove_emitter(&mut self, id: EmitterId) {
+        if let Some(mut emitter) = self.emitters.get_mut(id.0).and_then(Option::take) {
+            for x in &mut emitter.sounds {
+                let _ = x.stop(Tween::default());
+            }
+        }
+    }
+
+    /// Play the sound at the emitter in the sfx track, the volume will be set by [`AudioData::update_spatial`]
+    pub fn play_at(&mut self, res: &ResourceManager, sound: Handle<StaticSoundData>, emitter: EmitterId) -> anyhow::Result<()> {
+        if self.emitter_mut(emitter).is_none() {
+            return Err(anyhow!("The emitter {:?} is removed", emitter));
+        }
+        let track = self.sfx_track.id();
+        let handle = self.play(res, sound, |x| x.with_modified_settings(|s| s.track(track).volume(Volume::Amplitude(0.0))))?;
+        self.emitter_mut(emitter).unwrap().sounds.push(handle);
+        Ok(())
+    }
+
+    /// Update the volume and panning of the sounds by the listener camera in the world.
+    ///
+    /// `route` gets the position heard in the listener world for the emitter world and position,
+    /// None if it cannot be heard.
+    pub fn update_spatial(&mut self, camera: &Camera, world: usize, route: impl Fn(usize, &Point3<f32>) -> Option<Point3<f32>>) {
+        let right = camera.target.cross(&Vector3::z()).try_normalize(f32::EPSILON).unwrap_or_else(Vector3::y);
+        for emitter in self.emitters.iter_mut().flatten() {
+            emitter.sounds.retain(|x| x.state() != PlaybackState::Stopped);
+            if emitter.sounds.is_empty() {
+                continue;
+            }
+            let heard = if emitter.world == world {
+                Some(emitter.position)
+            } else {
+                route(emitter.world, &emitter.position)
+            };
+            let (volume, panning) = match heard {
+                Some(pos) => {
+                    let dir = pos - camera.eye;
+                    let distance = dir.norm();
+                    let side = if distance > f32::EPSILON { right.dot(&dir) / distance } else { 0.0 };
+                    (emitter.attenuation(distance), 0.5 + 0.5 * side as f64)
+                }
+                None => (0.0, 0.5),
+            };
+            for x in &mut emitter.sounds {
+                let _ = x.set_volume(Volume::Amplitude(volume), SPATIAL_TWEEN);
+                let _ = x.set_panning(panning, SPATIAL_TWEEN);
+            }
+        }
+    }
+}
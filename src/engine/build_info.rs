@@ -0,0 +1,32 @@
+//! The information gathered at build time.
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+/// The short hash of the git commit, `unknown` if not built in a git repository.
+pub const GIT_HASH: &str = env!("MP_GIT_HASH");
+pub const TARGET: &str = env!("MP_TARGET");
+pub const PROFILE: &str = env!("MP_PROFILE");
+
+pub struct BundledAsset {
+    pub name: &'static str,
+    pub path: &'static str,
+    pub license: &'static str,
+}
+
+/// The assets bundled with the game.
+pub const BUNDLED_ASSETS: &[BundledAsset] = &[
+    BundledAsset {
+        name: "cjkFonts allseto v1.11",
+        path: "static_res/cjkFonts_allseto_v1.11.ttf",
+        license: "Distributed under the license of the font author",
+    },
+    BundledAsset {
+        name: "Floor textures",
+        path: "res/assets/texture/floor",
+        license: "Part of Maybe Portal",
+    },
+];
+
+/// The version line for bug reports.
+pub fn version_line() -> String {
+    format!("Maybe Portal {} ({}) {} {}", VERSION, GIT_HASH, TARGET, PROFILE)
+}
@@ -1,22 +1,285 @@
-use anyhow::{self, Ok};
-use toml_edit::Document;
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+use toml_edit::{Document, Item};
+use winit::event::VirtualKeyCode;
+
+use crate::engine::render::camera::CameraController;
+
+/// The max portal recursion depth by default.
+pub const DEFAULT_PORTAL_DEPTH: usize = 8;
+/// The resolution scale of the portal views multiplied for each depth by default.
+pub const DEFAULT_PORTAL_VIEW_FALLOFF: f32 = 0.75;
+
+/// The default config file.
+pub const CONFIG_PATH: &str = "cfg.toml";
+
+/// The typed settings in the config file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub window: WindowSettings,
+    pub video: VideoSettings,
+    pub audio: AudioSettings,
+    pub network: NetworkSettings,
+    pub start: StartSettings,
+    pub input: InputSettings,
+    pub font: FontSettings,
+    /// The keys for the actions of [`CameraController`]
+    pub key_bindings: BTreeMap<String, Vec<VirtualKeyCode>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WindowSettings {
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VideoSettings {
+    pub vsync: bool,
+    pub render_scale: f32,
+    pub window_mode: WindowMode,
+    /// The video mode size for the exclusive fullscreen, the monitor's largest if not set.
+    pub fullscreen_size: Option<(u32, u32)>,
+    /// Show the other side of the portal looked at in a small window.
+    pub portal_preview: bool,
+    /// Show the view out of the other end of the portal nearest in the corner of the screen.
+    pub pip: bool,
+    /// Show the frame time and the phase timings over the states.
+    pub perf_hud: bool,
+    /// The max portal recursion depth, the deeper portals are filled by the fallback color.
+    pub portal_depth: usize,
+    /// Render less depth if the frames are over the budget.
+    pub adaptive_portal_depth: bool,
+    pub frame_budget_ms: f32,
+    /// The resolution scale of the portal views multiplied for each depth, the views through the portals seen directly are full size.
+    pub portal_view_falloff: f32,
+    /// Show the labels above the portals naming the worlds connected.
+    pub portal_labels: bool,
+    /// Map the colors by the ACES curve after scaled by the exposure.
+    pub tonemap: bool,
+    pub exposure: f32,
+    pub fxaa: bool,
+    /// The intensity of the bloom added, disabled if zero.
+    pub bloom: f32,
+    /// The vignette and the chromatic aberration near the portals.
+    pub portal_effects: bool,
+    /// The max fps while the window is not focused, the portals seen through the portals are not rendered.
+    pub background_fps: u32,
+    /// The ui scaled after the scale factor of the window.
+    pub ui_scale: f32,
+    /// Render the views in the float format, presented as HDR if the surface supports it.
+    pub hdr: bool,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WindowMode {
+    Windowed,
+    /// The fullscreen window without changing the video mode.
+    Borderless,
+    /// The exclusive fullscreen with the video mode.
+    Fullscreen,
+}
+
+impl Default for WindowMode {
+    fn default() -> Self {
+        WindowMode::Windowed
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AudioSettings {
+    pub master_volume: f64,
+    pub music_volume: f64,
+    pub sfx_volume: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetworkSettings {
+    /// The address of the server to join, empty if not set.
+    pub server: String,
+    /// The address of the rendezvous server to meet the other player behind the NAT.
+    pub rendezvous: String,
+    /// The session to meet in the rendezvous server.
+    pub session: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InputSettings {
+    /// The multiplier of the mouse looking.
+    pub mouse_sensitivity: f32,
+    /// The seconds to ease the looking by the exponential smoothing, not smoothed if zero.
+    pub look_smoothing: f32,
+    /// Look down by moving the mouse up.
+    pub invert_y: bool,
+    /// The length of the stick ignored for the analog moving.
+    pub dead_zone: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FontSettings {
+    /// The font asset used before the built-in fonts, the built-in only if empty.
+    pub path: String,
+    /// The text sizes of the default style multiplied by.
+    pub text_scale: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StartSettings {
+    /// The name of the state to boot into in the state registry, the default if empty.
+    pub state: String,
+}
+
+/// Published to the event bus after the settings changed in the settings menu.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SettingsChanged;
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            window: Default::default(),
+            video: Default::default(),
+            audio: Default::default(),
+            network: Default::default(),
+            start: Default::default(),
+            input: Default::default(),
+            font: Default::default(),
+            key_bindings: CameraController::default_bindings(),
+        }
+    }
+}
+
+impl Default for WindowSettings {
+    fn default() -> Self {
+        Self {
+            width: 1600,
+            height: 900,
+        }
+    }
+}
+
+impl Default for VideoSettings {
+    fn default() -> Self {
+        Self {
+            vsync: true,
+            render_scale: 1.0,
+            window_mode: Default::default(),
+            fullscreen_size: None,
+            portal_preview: false,
+            pip: false,
+            perf_hud: false,
+            portal_depth: DEFAULT_PORTAL_DEPTH,
+            adaptive_portal_depth: true,
+            frame_budget_ms: 25.0,
+            portal_view_falloff: DEFAULT_PORTAL_VIEW_FALLOFF,
+            portal_labels: true,
+            tonemap: false,
+            exposure: 1.0,
+            fxaa: false,
+            bloom: 0.5,
+            portal_effects: true,
+            background_fps: 10,
+            ui_scale: 1.0,
+            hdr: false,
+        }
+    }
+}
+
+impl Default for InputSettings {
+    fn default() -> Self {
+        Self {
+            mouse_sensitivity: 1.0,
+            look_smoothing: 0.0,
+            invert_y: false,
+            dead_zone: 0.15,
+        }
+    }
+}
+
+impl Default for FontSettings {
+    fn default() -> Self {
+        Self {
+            path: String::new(),
+            text_scale: 1.25,
+        }
+    }
+}
+
+impl Default for AudioSettings {
+    fn default() -> Self {
+        Self {
+            master_volume: 1.0,
+            music_volume: 1.0,
+            sfx_volume: 1.0,
+        }
+    }
+}
+
+impl Settings {
+    /// Read the settings from the document, the invalid settings will be default.
+    fn from_document(toml: &Document) -> Self {
+        toml_edit::de::from_document(toml.clone()).unwrap_or_else(|e| {
+            log::warn!(target: "config", "Invalid settings in the config for {:?}, use the default", e);
+            Settings::default()
+        })
+    }
+}
 
 #[allow(unused)]
-#[derive(Default, Debug, Clone)]
+#[derive(Debug, Clone)]
 pub struct Config {
     toml: Document,
+    settings: Settings,
+    path: PathBuf,
     dirty: bool,
 }
 
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            toml: Default::default(),
+            settings: Default::default(),
+            path: CONFIG_PATH.into(),
+            dirty: false,
+        }
+    }
+}
+
 #[allow(unused)]
 impl Config {
+    /// Load the config from the file, empty if the file not exists.
+    pub fn open(path: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let path = path.into();
+        let data = match std::fs::read_to_string(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let mut cfg = Self::load(&data)?;
+        cfg.path = path;
+        Ok(cfg)
+    }
+
     pub fn load(data: &str) -> anyhow::Result<Self> {
-        let toml = data.parse::<Document>();
-        Ok(Self { toml: toml?, dirty: false })
+        let toml = data.parse::<Document>()?;
+        let settings = Settings::from_document(&toml);
+        Ok(Self { toml, settings, dirty: false, ..Default::default() })
     }
 
     pub fn reload(&mut self, data: &str) -> anyhow::Result<()> {
         self.toml = data.parse()?;
+        self.settings = Settings::from_document(&self.toml);
         Ok(())
     }
 
@@ -36,4 +299,71 @@ impl Config {
     pub fn get_str(&self, key: &str) -> Option<&str> {
         self.toml.get(key).and_then(|x| x.as_str())
     }
-}
\ No newline at end of file
+
+    /// The path of the file beside the config, the data saved are kept with the settings.
+    pub fn data_path(&self, name: &str) -> PathBuf {
+        self.path.with_file_name(name)
+    }
+
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    pub fn settings_mut(&mut self) -> &mut Settings {
+        self.dirty = true;
+        &mut self.settings
+    }
+
+    /// Write the settings into the document as the tables, the other keys are kept.
+    fn write_settings(&mut self) -> anyhow::Result<()> {
+        let settings = toml_edit::ser::to_document(&self.settings)?;
+        for (key, item) in settings.iter() {
+            self.toml[key] = item.clone().into_table().map(Item::Table).unwrap_or_else(|x| x);
+        }
+        Ok(())
+    }
+
+    pub fn save(&mut self) -> anyhow::Result<()> {
+        self.write_settings()?;
+        std::fs::write(&self.path, self.toml.to_string())?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    pub fn save_if_dirty(&mut self) {
+        if self.dirty {
+            if let Err(e) = self.save() {
+                log::warn!(target: "config", "Save config to {:?} failed for {:?}", self.path, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use winit::event::VirtualKeyCode;
+
+    use crate::engine::config::{Config, Settings, WindowMode};
+
+    #[test]
+    fn test_settings_round_trip() {
+        let cfg = Config::load("other = 1\n[audio]\nmusic_volume = 0.5\n").unwrap();
+        assert_eq!(cfg.settings().audio.music_volume, 0.5);
+        assert_eq!(cfg.settings().audio.sfx_volume, 1.0);
+        assert_eq!(cfg.settings().key_bindings["forward"], vec![VirtualKeyCode::W, VirtualKeyCode::Up]);
+
+        let mut settings = Settings::default();
+        settings.video.vsync = false;
+        settings.video.window_mode = WindowMode::Fullscreen;
+        settings.video.fullscreen_size = Some((1920, 1080));
+        settings.network.server = "127.0.0.1:23333".into();
+        settings.start.state = "settings".into();
+        let mut cfg = Config::load("other = 1\n").unwrap();
+        *cfg.settings_mut() = settings.clone();
+        cfg.write_settings().unwrap();
+        let data = cfg.toml().to_string();
+        assert!(data.contains("other = 1"));
+        assert!(data.contains("[video]"));
+        assert_eq!(Config::load(&data).unwrap().settings(), &settings);
+    }
+}
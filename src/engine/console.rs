@@ -0,0 +1,307 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use anyhow::anyhow;
+use log::{Level, LevelFilter, Log, Metadata, Record, SetLoggerError};
+use once_cell::sync::Lazy;
+use specs::World;
+
+use crate::engine::global::GLOBAL_DATA;
+use crate::engine::render::recorder::{DEFAULT_FRAMES_DIR, FrameSink};
+use crate::engine::StateData;
+
+/// The max lines kept in the console.
+const MAX_LINES: usize = 256;
+/// The max lines kept in the history.
+const MAX_HISTORY: usize = 64;
+/// The records at the level or more important are echoed to the console.
+pub const ECHO_LEVEL: LevelFilter = LevelFilter::Info;
+
+/// Run with the arguments after the name, the text returned is printed if not empty.
+pub type CommandFn = Arc<dyn Fn(&mut StateData, &[&str]) -> anyhow::Result<String> + Send + Sync>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConsoleLine {
+    /// The level of the log echoed, none for the input and the output.
+    pub level: Option<Level>,
+    pub text: String,
+}
+
+/// The lines shown in the console, shared with the logger.
+static LINES: Lazy<Mutex<VecDeque<ConsoleLine>>> = Lazy::new(Default::default);
+
+/// Print the line to the console.
+pub fn print(level: Option<Level>, text: impl Into<String>) {
+    let mut lines = LINES.lock().unwrap();
+    if lines.len() >= MAX_LINES {
+        lines.pop_front();
+    }
+    lines.push_back(ConsoleLine { level, text: text.into() });
+}
+
+/// Call with the lines in the console, do not log in the function.
+pub fn with_lines<R>(f: impl FnOnce(&VecDeque<ConsoleLine>) -> R) -> R {
+    f(&LINES.lock().unwrap())
+}
+
+pub fn clear() {
+    LINES.lock().unwrap().clear();
+}
+
+/// Log by the inner logger and echo the records to the console.
+pub struct ConsoleLogger<L> {
+    inner: L,
+}
+
+impl<L: Log> Log for ConsoleLogger<L> {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= ECHO_LEVEL || self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.inner.enabled(record.metadata()) {
+            self.inner.log(record);
+        }
+        if record.level() <= ECHO_LEVEL {
+            print(Some(record.level()), format!("[{}] {}", record.target(), record.args()));
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// Set the global logger to the inner logger with the records echoed, the inner filters its records by itself.
+pub fn init_logger(inner: impl Log + 'static, inner_level: LevelFilter) -> Result<(), SetLoggerError> {
+    log::set_boxed_logger(Box::new(ConsoleLogger { inner }))?;
+    log::set_max_level(inner_level.max(ECHO_LEVEL));
+    Ok(())
+}
+
+pub struct Command {
+    /// The arguments shown in the help.
+    pub usage: String,
+    pub help: String,
+    f: CommandFn,
+}
+
+/// The commands registered by the engine modules and the states, inserted to the world by the console state.
+pub struct Console {
+    commands: BTreeMap<String, Command>,
+    /// The console is open or closed by the key this frame, the keys are not for the game.
+    typing: bool,
+}
+
+impl Default for Console {
+    /// The console with the built-in commands.
+    fn default() -> Self {
+        let mut this = Self {
+            commands: Default::default(),
+            typing: false,
+        };
+        this.register("help", "[command]", "List the commands or show the usage of the command", |s, args| {
+            let console = s.app.world.fetch::<Console>();
+            if let Some(name) = args.first() {
+                let x = console.commands.get(*name).ok_or_else(|| anyhow!("Unknown command {}", name))?;
+                return Ok(format!("{} {}\n  {}", name, x.usage, x.help));
+            }
+            Ok(console.commands.iter()
+                .map(|(name, x)| format!("{} {} - {}", name, x.usage, x.help))
+                .collect::<Vec<_>>()
+                .join("\n"))
+        });
+        this.register("clear", "", "Clear the console", |_, _| {
+            clear();
+            Ok(String::new())
+        });
+        this.register("perf_hud", "", "Toggle the performance overlay", |_, _| {
+            let mut cfg = GLOBAL_DATA.cfg_data.write().unwrap();
+            let video = &mut cfg.settings_mut().video;
+            video.perf_hud = !video.perf_hud;
+            Ok(format!("The performance overlay is {}", if video.perf_hud { "shown" } else { "hidden" }))
+        });
+        this.register("record_video", "[dir] [every] | pipe <command> | stop", "Record the frames to the png files in the dir, frames by default, or to the stdin of the encoder", |s, args| {
+            match args {
+                [] => {
+                    s.app.start_recording(FrameSink::Images(DEFAULT_FRAMES_DIR.into()), 1)?;
+                    Ok(String::new())
+                }
+                ["stop"] => {
+                    let stopped = s.app.stop_recording()?;
+                    Ok(if stopped { "Stopped recording".into() } else { "Not recording".into() })
+                }
+                ["pipe", command @ ..] if !command.is_empty() => {
+                    s.app.start_recording(FrameSink::Pipe(command.join(" ")), 1)?;
+                    Ok(String::new())
+                }
+                [dir, every @ ..] if every.len() <= 1 => {
+                    let every = every.first().map(|x| x.parse().map_err(|_| anyhow!("{} is not a number", x))).transpose()?;
+                    s.app.start_recording(FrameSink::Images(dir.into()), every.unwrap_or(1))?;
+                    Ok(String::new())
+                }
+                _ => Err(anyhow!("Wrong arguments, see help record_video")),
+            }
+        });
+        this
+    }
+}
+
+#[allow(unused)]
+impl Console {
+    /// Run the function by the name, replace the old one.
+    pub fn register(&mut self, name: impl Into<String>, usage: impl Into<String>, help: impl Into<String>,
+                    f: impl Fn(&mut StateData, &[&str]) -> anyhow::Result<String> + Send + Sync + 'static) {
+        self.commands.insert(name.into(), Command { usage: usage.into(), help: help.into(), f: Arc::new(f) });
+    }
+
+    pub fn unregister(&mut self, name: &str) -> bool {
+        self.commands.remove(name).is_some()
+    }
+
+    pub fn commands(&self) -> impl Iterator<Item=(&String, &Command)> {
+        self.commands.iter()
+    }
+
+    /// The names starting with the prefix.
+    pub fn complete<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item=&'a String> {
+        self.commands.keys().filter(move |x| x.starts_with(prefix))
+    }
+
+    /// Whether the keys are for the console.
+    pub fn is_typing(&self) -> bool {
+        self.typing
+    }
+
+    pub fn set_typing(&mut self, typing: bool) {
+        self.typing = typing;
+    }
+}
+
+/// Whether the keys are for the console in the world.
+pub fn is_typing(world: &World) -> bool {
+    world.try_fetch::<Console>().is_some_and(|x| x.is_typing())
+}
+
+/// Split the line by the whitespaces, the quoted is one argument.
+pub fn split_args(line: &str) -> Vec<String> {
+    let mut args = vec![];
+    let mut current = String::new();
+    let mut quoted = false;
+    let mut started = false;
+    for c in line.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                started = true;
+            }
+            c if c.is_whitespace() && !quoted => {
+                if started {
+                    args.push(std::mem::take(&mut current));
+                    started = false;
+                }
+            }
+            c => {
+                current.push(c);
+                started = true;
+            }
+        }
+    }
+    if started {
+        args.push(current);
+    }
+    args
+}
+
+/// Run the command line by the console in the world.
+pub fn execute(s: &mut StateData, line: &str) -> anyhow::Result<String> {
+    let args = split_args(line);
+    let (name, args) = if let Some((name, args)) = args.split_first() { (name, args) } else {
+        return Ok(String::new());
+    };
+    let f = {
+        let console = s.app.world.try_fetch::<Console>().ok_or_else(|| anyhow!("No console"))?;
+        let command = console.commands.get(name).ok_or_else(|| anyhow!("Unknown command {}, see help", name))?;
+        command.f.clone()
+    };
+    let args = args.iter().map(|x| x.as_str()).collect::<Vec<_>>();
+    f(s, &args)
+}
+
+/// The lines entered, browsed by up and down.
+#[derive(Debug, Default)]
+pub struct History {
+    lines: VecDeque<String>,
+    /// The index browsing, none for the new line.
+    cursor: Option<usize>,
+}
+
+#[allow(unused)]
+impl History {
+    /// Add the line entered and stop browsing, the same line as the last is not added.
+    pub fn push(&mut self, line: &str) {
+        self.cursor = None;
+        if line.is_empty() || self.lines.back().is_some_and(|x| x == line) {
+            return;
+        }
+        if self.lines.len() >= MAX_HISTORY {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(line.to_string());
+    }
+
+    /// The line before the browsing.
+    pub fn older(&mut self) -> Option<&str> {
+        let idx = match self.cursor {
+            Some(0) => 0,
+            Some(x) => x - 1,
+            None => self.lines.len().checked_sub(1)?,
+        };
+        self.cursor = Some(idx);
+        self.lines.get(idx).map(|x| x.as_str())
+    }
+
+    /// The line after the browsing, the empty line after the last.
+    pub fn newer(&mut self) -> Option<&str> {
+        let idx = self.cursor? + 1;
+        if idx >= self.lines.len() {
+            self.cursor = None;
+            return Some("");
+        }
+        self.cursor = Some(idx);
+        self.lines.get(idx).map(|x| x.as_str())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::engine::console::{History, split_args};
+
+    #[test]
+    fn test_split_args() {
+        assert_eq!(split_args("  teleport 1 2.5  -3 "), vec!["teleport", "1", "2.5", "-3"]);
+        assert_eq!(split_args("net_connect join \"my host:23333\""), vec!["net_connect", "join", "my host:23333"]);
+        assert_eq!(split_args("echo \"\""), vec!["echo", ""]);
+        assert!(split_args("   ").is_empty());
+    }
+
+    #[test]
+    fn test_history() {
+        let mut history = History::default();
+        assert_eq!(history.older(), None);
+        assert_eq!(history.newer(), None);
+        history.push("a");
+        history.push("b");
+        history.push("b");
+        history.push("");
+        assert_eq!(history.older(), Some("b"));
+        assert_eq!(history.older(), Some("a"));
+        assert_eq!(history.older(), Some("a"));
+        assert_eq!(history.newer(), Some("b"));
+        assert_eq!(history.newer(), Some(""));
+        assert_eq!(history.newer(), None);
+        history.older();
+        history.push("c");
+        assert_eq!(history.older(), Some("c"));
+    }
+}
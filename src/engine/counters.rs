@@ -0,0 +1,48 @@
+/// The counts of the last frame, to verify the culling at a glance in the debug overlay.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct FrameCounters {
+    pub planes_drawn: u32,
+    /// The portal planes not visible.
+    pub planes_culled: u32,
+    pub portals_considered: u32,
+    /// The portal views rendered.
+    pub portals_recursed: u32,
+    /// The uniform writes staged before the passes.
+    pub uniform_writes: u32,
+    pub uniform_bytes: u64,
+    /// The collider pairs tracked by the narrow phase, the contacts and the sensor intersections.
+    pub narrow_phase_pairs: u32,
+    /// The pairs touching in the narrow phase.
+    pub active_contacts: u32,
+    pub collision_events: u32,
+    pub contact_force_events: u32,
+}
+
+#[allow(unused)]
+impl FrameCounters {
+    /// Reset the render counts before rendering the frame.
+    pub fn reset_render(&mut self) {
+        self.planes_drawn = 0;
+        self.planes_culled = 0;
+        self.portals_considered = 0;
+        self.portals_recursed = 0;
+        self.uniform_writes = 0;
+        self.uniform_bytes = 0;
+    }
+
+    /// Reset the physics counts before the step.
+    pub fn reset_physics(&mut self) {
+        self.narrow_phase_pairs = 0;
+        self.active_contacts = 0;
+        self.collision_events = 0;
+        self.contact_force_events = 0;
+    }
+
+    pub fn show(&self, ui: &mut egui::Ui) {
+        ui.label(format!("Planes {} drawn, {} culled", self.planes_drawn, self.planes_culled));
+        ui.label(format!("Portals {} considered, {} recursed", self.portals_considered, self.portals_recursed));
+        ui.label(format!("Uniforms {} writes, {} bytes staged", self.uniform_writes, self.uniform_bytes));
+        ui.label(format!("Narrow phase pairs {}, contacts {}", self.narrow_phase_pairs, self.active_contacts));
+        ui.label(format!("Events {} collision, {} contact force", self.collision_events, self.contact_force_events));
+    }
+}
@@ -0,0 +1,322 @@
+use std::collections::HashMap;
+
+use log::warn;
+use nalgebra::{Isometry3, Point3, UnitQuaternion, Vector3};
+use rapier3d::prelude::{ColliderHandle, RigidBodyHandle, SharedShape};
+use specs::{Builder, Component, DenseVecStorage, Dispatcher, DispatcherBuilder, Entity, Join, Read, ReadStorage, System, VecStorage, World, WorldExt, WriteStorage};
+
+use crate::engine::{Handle, TextureWrapper};
+
+/// The pose of the entity in its world.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Transform {
+    pub position: Point3<f32>,
+    pub rotation: UnitQuaternion<f32>,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            position: Point3::origin(),
+            rotation: UnitQuaternion::identity(),
+        }
+    }
+}
+
+#[allow(unused)]
+impl Transform {
+    pub fn from_isometry(iso: &Isometry3<f32>) -> Self {
+        Self {
+            position: iso.translation.vector.into(),
+            rotation: iso.rotation,
+        }
+    }
+
+    pub fn to_isometry(&self) -> Isometry3<f32> {
+        Isometry3::from_parts(self.position.coords.into(), self.rotation)
+    }
+}
+
+impl Component for Transform {
+    type Storage = VecStorage<Self>;
+}
+
+/// The model instance drawn at the transform.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RenderModel {
+    /// The world of the level the model in.
+    pub world: usize,
+    /// The model index in the world.
+    pub model: usize,
+    /// The instance index in the model.
+    pub instance: usize,
+}
+
+impl Component for RenderModel {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// The physics handles of the entity, the transform follows the body if any.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Collider {
+    pub body: Option<RigidBodyHandle>,
+    pub collider: ColliderHandle,
+}
+
+impl Component for Collider {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// The entity able to go through the portals.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PortalTraveler {
+    /// The world the entity in.
+    pub world: usize,
+    /// The scale by the portals gone through.
+    pub scale: f32,
+    pub traversed: u32,
+}
+
+impl PortalTraveler {
+    pub fn new(world: usize) -> Self {
+        Self { world, scale: 1.0, traversed: 0 }
+    }
+}
+
+impl Component for PortalTraveler {
+    type Storage = DenseVecStorage<Self>;
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MeshShape {
+    Cube { half: f32 },
+}
+
+/// The built-in mesh drawn at the transform.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Mesh {
+    /// The world the mesh drawn in.
+    pub world: usize,
+    pub shape: MeshShape,
+    /// The white mesh if none.
+    pub texture: Option<Handle<TextureWrapper>>,
+}
+
+impl Component for Mesh {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// The dynamic body to create by the physics owner, the [`Collider`] is added when created.
+#[derive(Clone)]
+pub struct PhysicsBody {
+    pub shape: SharedShape,
+}
+
+impl Component for PhysicsBody {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// The velocity set to the body when created.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Velocity {
+    pub linear: Vector3<f32>,
+    pub angular: Vector3<f32>,
+}
+
+impl Default for Velocity {
+    fn default() -> Self {
+        Self {
+            linear: Vector3::zeros(),
+            angular: Vector3::zeros(),
+        }
+    }
+}
+
+impl Component for Velocity {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// The poses of the rigid bodies after the physics step, published by the physics owner.
+#[derive(Debug, Default)]
+pub struct BodyPoses(pub HashMap<RigidBodyHandle, Isometry3<f32>>);
+
+/// The colliders of the entities despawned, the physics owner removes the bodies.
+#[derive(Debug, Default)]
+pub struct RemovedBodies(pub Vec<Collider>);
+
+/// The entity to spawn by [`SpawnCommands`]
+#[derive(Clone, Default)]
+pub struct Spawn {
+    pub transform: Transform,
+    pub mesh: Option<Mesh>,
+    pub body: Option<PhysicsBody>,
+    pub velocity: Option<Velocity>,
+    pub traveler: Option<PortalTraveler>,
+}
+
+#[allow(unused)]
+impl Spawn {
+    pub fn new(transform: Transform) -> Self {
+        Self { transform, ..Default::default() }
+    }
+
+    pub fn mesh(mut self, mesh: Mesh) -> Self {
+        self.mesh = Some(mesh);
+        self
+    }
+
+    /// Create the dynamic body with the collider shape.
+    pub fn body(mut self, shape: SharedShape) -> Self {
+        self.body = Some(PhysicsBody { shape });
+        self
+    }
+
+    pub fn velocity(mut self, linear: Vector3<f32>, angular: Vector3<f32>) -> Self {
+        self.velocity = Some(Velocity { linear, angular });
+        self
+    }
+
+    pub fn traveler(mut self, world: usize) -> Self {
+        self.traveler = Some(PortalTraveler::new(world));
+        self
+    }
+}
+
+/// The entities to create and remove queued by the states, applied after the update.
+#[derive(Default)]
+pub struct SpawnCommands {
+    spawns: Vec<Spawn>,
+    despawns: Vec<Entity>,
+}
+
+#[allow(unused)]
+impl SpawnCommands {
+    pub fn spawn(&mut self, spawn: Spawn) {
+        self.spawns.push(spawn);
+    }
+
+    pub fn despawn(&mut self, entity: Entity) {
+        self.despawns.push(entity);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.spawns.is_empty() && self.despawns.is_empty()
+    }
+
+    /// Create and remove the entities queued, return the entities created.
+    pub fn apply(&mut self, world: &mut World) -> Vec<Entity> {
+        if !self.despawns.is_empty() {
+            let colliders = world.read_storage::<Collider>();
+            let removed = self.despawns.iter()
+                .filter_map(|x| colliders.get(*x).copied())
+                .collect::<Vec<_>>();
+            drop(colliders);
+            world.write_resource::<RemovedBodies>().0.extend(removed);
+            if let Err(e) = world.delete_entities(&self.despawns) {
+                warn!("Despawn the entities failed for {:?}", e);
+            }
+            self.despawns.clear();
+        }
+        self.spawns.drain(..).map(|x| {
+            let mut builder = world.create_entity().with(x.transform);
+            if let Some(mesh) = x.mesh {
+                builder = builder.with(mesh);
+            }
+            if let Some(body) = x.body {
+                builder = builder.with(body);
+            }
+            if let Some(velocity) = x.velocity {
+                builder = builder.with(velocity);
+            }
+            if let Some(traveler) = x.traveler {
+                builder = builder.with(traveler);
+            }
+            builder.build()
+        }).collect()
+    }
+}
+
+/// Copy the body poses to the transforms of the entities with the body.
+pub struct SyncTransformSystem;
+
+impl<'a> System<'a> for SyncTransformSystem {
+    type SystemData = (Read<'a, BodyPoses>, ReadStorage<'a, Collider>, WriteStorage<'a, Transform>);
+
+    fn run(&mut self, (poses, colliders, mut transforms): Self::SystemData) {
+        for (collider, transform) in (&colliders, &mut transforms).join() {
+            if let Some(pose) = collider.body.and_then(|x| poses.0.get(&x)) {
+                *transform = Transform::from_isometry(pose);
+            }
+        }
+    }
+}
+
+/// Register the components and build the dispatcher with the engine systems.
+///
+/// The dispatcher is run after the state updated each loop.
+pub fn setup(world: &mut World) -> Dispatcher<'static, 'static> {
+    world.register::<Transform>();
+    world.register::<RenderModel>();
+    world.register::<Collider>();
+    world.register::<PortalTraveler>();
+    world.register::<Mesh>();
+    world.register::<PhysicsBody>();
+    world.register::<Velocity>();
+    world.insert(RemovedBodies::default());
+    let mut dispatcher = DispatcherBuilder::new()
+        .with(SyncTransformSystem, "sync_transform", &[])
+        .build();
+    dispatcher.setup(world);
+    dispatcher
+}
+
+#[cfg(test)]
+mod test {
+    use nalgebra::{Isometry3, vector};
+    use rapier3d::prelude::{ColliderHandle, RigidBodyHandle, SharedShape};
+    use specs::{Builder, World, WorldExt};
+
+    use crate::engine::ecs::{BodyPoses, Collider, PhysicsBody, RemovedBodies, setup, Spawn, SpawnCommands, Transform};
+
+    #[test]
+    fn test_sync_transform() {
+        let mut world = World::new();
+        let mut dispatcher = setup(&mut world);
+        let body = RigidBodyHandle::from_raw_parts(0, 0);
+        let moving = world.create_entity()
+            .with(Transform::default())
+            .with(Collider { body: Some(body), collider: ColliderHandle::from_raw_parts(0, 0) })
+            .build();
+        let fixed = world.create_entity()
+            .with(Transform::default())
+            .with(Collider { body: None, collider: ColliderHandle::from_raw_parts(1, 0) })
+            .build();
+        let pose = Isometry3::translation(1.0, 2.0, 3.0);
+        world.write_resource::<BodyPoses>().0.insert(body, pose);
+        dispatcher.dispatch(&world);
+        world.maintain();
+
+        let transforms = world.read_storage::<Transform>();
+        assert_eq!(transforms.get(moving).unwrap().position.coords, vector![1.0, 2.0, 3.0]);
+        assert_eq!(transforms.get(fixed), Some(&Transform::default()));
+    }
+
+    #[test]
+    fn test_spawn_commands() {
+        let mut world = World::new();
+        let _ = setup(&mut world);
+        let mut commands = SpawnCommands::default();
+        commands.spawn(Spawn::new(Transform::default()).body(SharedShape::cuboid(0.5, 0.5, 0.5)));
+        let spawned = commands.apply(&mut world);
+        assert_eq!(spawned.len(), 1);
+        assert!(commands.is_empty());
+        assert!(world.read_storage::<PhysicsBody>().get(spawned[0]).is_some());
+
+        let collider = Collider { body: Some(RigidBodyHandle::from_raw_parts(0, 0)), collider: ColliderHandle::from_raw_parts(0, 0) };
+        world.write_storage::<Collider>().insert(spawned[0], collider).unwrap();
+        commands.despawn(spawned[0]);
+        commands.apply(&mut world);
+        assert!(!world.is_alive(spawned[0]));
+        assert_eq!(world.read_resource::<RemovedBodies>().0, vec![collider]);
+    }
+}
@@ -4,9 +4,9 @@ use std::sync::RwLock;
 use egui::{FontData, FontDefinitions, FontFamily};
 use futures::executor::ThreadPool;
 use log::info;
-use once_cell::sync::Lazy;
+use once_cell::sync::{Lazy, OnceCell};
 
-use crate::engine::config::Config;
+use crate::engine::config::{Config, CONFIG_PATH};
 #[allow(unused)]
 pub struct StaticData {
     pub font: FontDefinitions,
@@ -24,6 +24,44 @@ pub static IO_POOL: Lazy<ThreadPool> = Lazy::new(|| {
         .create().expect("Create io thread pool failed")
 });
 
+/// The tokio runtime to run the servers and peers.
+pub static NET_RUNTIME: Lazy<tokio::runtime::Runtime> = Lazy::new(|| {
+    tokio::runtime::Builder::new_multi_thread()
+        .thread_name("NET RUNTIME")
+        .worker_threads(2)
+        .enable_all()
+        .build()
+        .expect("Create network runtime failed")
+});
+
+/// The optional parts of the engine, toggled before the windows created.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct EngineFeatures {
+    /// Open the audio device.
+    pub audio: bool,
+    /// Load and save the statistics profile.
+    pub statistics: bool,
+    /// The developer console toggled by the backtick.
+    pub console: bool,
+}
+
+impl Default for EngineFeatures {
+    fn default() -> Self {
+        Self {
+            audio: true,
+            statistics: true,
+            console: true,
+        }
+    }
+}
+
+pub static FEATURES: OnceCell<EngineFeatures> = OnceCell::new();
+
+/// The features set by the builder, all enabled if not set.
+pub fn features() -> EngineFeatures {
+    FEATURES.get().copied().unwrap_or_default()
+}
+
 #[allow(unused)]
 pub static INITED: AtomicBool = AtomicBool::new(false);
 #[allow(unused)]
@@ -38,15 +76,10 @@ pub static GLOBAL_DATA: Lazy<StaticData> = Lazy::new(|| {
     font.families.get_mut(&FontFamily::Proportional)
         .unwrap()
         .insert(0, "cjk".into());
-    let cfg_data = std::fs::read_to_string("cfg.toml").unwrap_or_else(|_| {
-        if let Err(e) = std::fs::File::create("cfg.toml") {
-            log::error!("Create config file failed for {:?}", e);
-            panic!("{:?}", e);
-        }
-        "".into()
+    let cfg_data = Config::open(CONFIG_PATH).unwrap_or_else(|e| {
+        log::error!("Load config failed for {:?}, use the default", e);
+        Config::default()
     });
-    // Load config failed. Why not panic?
-    let cfg_data = Config::load(&cfg_data).expect("Load config data failed");
 
     StaticData {
         font,
@@ -7,15 +7,18 @@ use winit::event::{Touch, TouchPhase, VirtualKeyCode};
 #[allow(unused)]
 #[derive(Debug, Clone)]
 pub struct Pointer {
-    id: u64,
-    loc: PhysicalPosition<f64>,
-    phase: TouchPhase,
+    pub id: u64,
+    /// Where the touch started.
+    pub start: PhysicalPosition<f64>,
+    pub loc: PhysicalPosition<f64>,
+    pub phase: TouchPhase,
 }
 
 impl From<Touch> for Pointer {
     fn from(touch: Touch) -> Self {
         Self {
             id: touch.id,
+            start: touch.location,
             loc: touch.location,
             phase: touch.phase,
         }
@@ -37,6 +40,7 @@ pub struct BakedInputs {
     pub cur_temp_game_input: RawInputData,
     /// only swap in states.game tick
     pub last_temp_game_input: RawInputData,
+    /// The touches on the screen, removed after ended.
     pub points: HashMap<u64, Pointer>,
     pub pressed_any_cur_frame: usize,
 }
@@ -71,6 +75,21 @@ impl BakedInputs {
             .count();
     }
 
+    /// Move the touch or start it, remove it if ended.
+    pub fn touch(&mut self, touch: Touch) {
+        match touch.phase {
+            TouchPhase::Ended | TouchPhase::Cancelled => {
+                self.points.remove(&touch.id);
+            }
+            _ => {
+                let start = self.points.get(&touch.id).map(|x| x.start);
+                let mut pointer = Pointer::from(touch);
+                pointer.start = start.unwrap_or(pointer.start);
+                self.points.insert(touch.id, pointer);
+            }
+        }
+    }
+
     #[allow(unused)]
     pub fn is_pressed(&self, keys: &[VirtualKeyCode]) -> bool {
         keys.iter().any(|k| !self.last_frame_input.pressing.contains(k))
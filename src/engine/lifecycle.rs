@@ -0,0 +1,10 @@
+/// The service notified when the app goes to the background and comes back, like on the android.
+///
+/// The window manager notifies the services it owns, the states notify theirs by [`crate::engine::StateEvent::Suspended`]
+pub trait Lifecycle {
+    /// Stop the work not needed in the background.
+    fn suspend(&mut self) {}
+
+    /// Continue the work stopped by [`Lifecycle::suspend`]
+    fn resume(&mut self) {}
+}
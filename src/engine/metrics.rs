@@ -0,0 +1,193 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use egui::{Align2, Color32, Context, Rect, Sense, Stroke, Ui, vec2};
+
+use crate::engine::pacing::FramePacing;
+
+/// The frames kept to show.
+const HISTORY: usize = 240;
+/// The size of the graph in points.
+const GRAPH_SIZE: [f32; 2] = [240.0, 48.0];
+
+/// The parts of the frame timed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Phase {
+    /// The update of the state on the top.
+    Update,
+    /// The physics stepped in the update.
+    Physics,
+    /// The systems run after the update.
+    Systems,
+    /// The render of the states, to record the commands.
+    Render,
+    /// The portal passes recorded in the render.
+    Portals,
+    /// Painting the ui to the screen.
+    Egui,
+}
+
+impl Phase {
+    pub const ALL: [Phase; 6] = [Phase::Update, Phase::Physics, Phase::Systems, Phase::Render, Phase::Portals, Phase::Egui];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Phase::Update => "update",
+            Phase::Physics => "physics",
+            Phase::Systems => "systems",
+            Phase::Render => "render",
+            Phase::Portals => "portals",
+            Phase::Egui => "egui",
+        }
+    }
+}
+
+/// The timings and the portal counts of the frame.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct FrameSample {
+    /// The milliseconds of each phase in the order of [`Phase::ALL`]
+    pub phases: [f32; Phase::ALL.len()],
+    /// The portal recursion depth used.
+    pub recursion_depth: u32,
+    pub portal_views: u32,
+}
+
+impl FrameSample {
+    pub fn phase(&self, phase: Phase) -> f32 {
+        self.phases[phase as usize]
+    }
+}
+
+/// Collect the timings of the phases for the frames presented.
+#[derive(Debug, Default)]
+pub struct FrameMetrics {
+    current: FrameSample,
+    history: VecDeque<FrameSample>,
+    /// The milliseconds of the passes on the gpu in the last frame read back.
+    gpu_passes: Vec<(&'static str, f32)>,
+}
+
+#[allow(unused)]
+impl FrameMetrics {
+    /// Add the time of the phase in this frame, the phase may run more than once before presenting.
+    pub fn add(&mut self, phase: Phase, dur: Duration) {
+        self.current.phases[phase as usize] += dur.as_secs_f32() * 1000.0;
+    }
+
+    /// Run the function and add the time to the phase.
+    pub fn time<R>(&mut self, phase: Phase, f: impl FnOnce() -> R) -> R {
+        let start = std::time::Instant::now();
+        let result = f();
+        self.add(phase, start.elapsed());
+        result
+    }
+
+    pub fn set_portals(&mut self, recursion_depth: u32, portal_views: u32) {
+        self.current.recursion_depth = recursion_depth;
+        self.current.portal_views = portal_views;
+    }
+
+    pub fn set_gpu_passes(&mut self, passes: Vec<(&'static str, f32)>) {
+        self.gpu_passes = passes;
+    }
+
+    /// Empty if the gpu timing is not supported.
+    pub fn gpu_passes(&self) -> &[(&'static str, f32)] {
+        &self.gpu_passes
+    }
+
+    /// Keep the frame presented and start the next.
+    pub fn end_frame(&mut self) {
+        if self.history.len() == HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(std::mem::take(&mut self.current));
+    }
+
+    /// The recent frames, the last is the newest.
+    pub fn history(&self) -> &VecDeque<FrameSample> {
+        &self.history
+    }
+
+    /// The average milliseconds of the phase in the recent frames.
+    pub fn average(&self, phase: Phase) -> f32 {
+        if self.history.is_empty() {
+            return 0.0;
+        }
+        self.history.iter().map(|x| x.phase(phase)).sum::<f32>() / self.history.len() as f32
+    }
+
+    /// Show the frame time and the portal graphs at the right top.
+    pub fn show_overlay(&self, ctx: &Context, pacing: &FramePacing) {
+        egui::Window::new("Performance")
+            .anchor(Align2::RIGHT_TOP, [-8.0, 8.0])
+            .resizable(false)
+            .collapsible(true)
+            .show(ctx, |ui| {
+                let intervals = pacing.intervals();
+                ui.label(format!("Frame {:.1}ms", pacing.median() * 1000.0));
+                // scaled to 30 fps, the line at 60 fps
+                graph(ui, intervals.iter().map(|x| x * 1000.0), 1000.0 / 30.0, 1000.0 / 60.0, Color32::LIGHT_GREEN);
+                for x in Phase::ALL {
+                    ui.monospace(format!("{:<8}{:>6.2}ms", x.name(), self.average(x)));
+                }
+                if !self.gpu_passes.is_empty() {
+                    ui.label("GPU");
+                    for (name, ms) in &self.gpu_passes {
+                        ui.monospace(format!("{:<14}{:>6.2}ms", name, ms));
+                    }
+                }
+                let (depth, views) = self.history.back().map(|x| (x.recursion_depth, x.portal_views)).unwrap_or_default();
+                let max_views = self.history.iter().map(|x| x.portal_views).max().unwrap_or(0).max(1);
+                ui.label(format!("Portal views {}, depth {}", views, depth));
+                graph(ui, self.history.iter().map(|x| x.portal_views as f32), max_views as f32, 0.0, Color32::LIGHT_BLUE);
+            });
+    }
+}
+
+/// Draw the values as the bars from the left, clamped to the max, with the mark line if not zero.
+fn graph(ui: &mut Ui, values: impl ExactSizeIterator<Item=f32>, max: f32, mark: f32, color: Color32) {
+    let (response, painter) = ui.allocate_painter(vec2(GRAPH_SIZE[0], GRAPH_SIZE[1]), Sense::hover());
+    let rect = response.rect;
+    painter.rect_filled(rect, 0.0, Color32::from_black_alpha(128));
+    let width = rect.width() / HISTORY as f32;
+    let offset = HISTORY.saturating_sub(values.len());
+    for (idx, x) in values.enumerate() {
+        let height = (x / max).clamp(0.0, 1.0) * rect.height();
+        let left = rect.left() + (offset + idx) as f32 * width;
+        let bar = Rect::from_min_max([left, rect.bottom() - height].into(), [left + width, rect.bottom()].into());
+        painter.rect_filled(bar, 0.0, color);
+    }
+    if mark > 0.0 {
+        let y = rect.bottom() - (mark / max).clamp(0.0, 1.0) * rect.height();
+        painter.hline(rect.x_range(), y, Stroke::new(1.0, Color32::YELLOW));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::engine::metrics::{FrameMetrics, Phase};
+
+    #[test]
+    fn test_metrics() {
+        let mut metrics = FrameMetrics::default();
+        assert_eq!(metrics.average(Phase::Update), 0.0);
+        metrics.add(Phase::Update, Duration::from_millis(2));
+        metrics.add(Phase::Update, Duration::from_millis(2));
+        metrics.set_portals(3, 12);
+        metrics.end_frame();
+        metrics.add(Phase::Update, Duration::from_millis(2));
+        metrics.end_frame();
+        assert!((metrics.average(Phase::Update) - 3.0).abs() < 1e-4);
+        assert_eq!(metrics.average(Phase::Egui), 0.0);
+        assert_eq!(metrics.history()[0].portal_views, 12);
+        // counted again in the next frame
+        assert_eq!(metrics.history()[1].recursion_depth, 0);
+        for _ in 0..1000 {
+            metrics.end_frame();
+        }
+        assert_eq!(metrics.history().len(), 240);
+    }
+}
@@ -16,6 +16,16 @@ pub mod network;
 pub mod config;
 pub mod task;
 pub mod physics;
+pub mod stats;
+pub mod pacing;
+pub mod counters;
+pub mod build_info;
+pub mod ecs;
+pub mod lifecycle;
+pub mod ui;
+pub mod console;
+pub mod metrics;
+pub mod touch;
 
 pub mod prelude {
     pub use rayon::prelude::*;
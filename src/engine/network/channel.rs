@@ -0,0 +1,218 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use anyhow::anyhow;
+
+/// The bytes of the header before the payload: channel id, kind and the sequence.
+const HEADER_LEN: usize = 6;
+/// The max packets buffered for the ordered channel waiting for the missing one.
+const MAX_PENDING: usize = 1024;
+
+/// The delivery semantics of the channel.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ChannelKind {
+    /// Delivered once in the sent order.
+    ReliableOrdered,
+    /// Delivered once in the received order.
+    ReliableUnordered,
+    /// Sent once, the packets older than the received are dropped.
+    UnreliableSequenced,
+}
+
+impl ChannelKind {
+    fn to_byte(self) -> u8 {
+        match self {
+            ChannelKind::ReliableOrdered => 0,
+            ChannelKind::ReliableUnordered => 1,
+            ChannelKind::UnreliableSequenced => 2,
+        }
+    }
+
+    fn from_byte(x: u8) -> Option<Self> {
+        match x {
+            0 => Some(ChannelKind::ReliableOrdered),
+            1 => Some(ChannelKind::ReliableUnordered),
+            2 => Some(ChannelKind::UnreliableSequenced),
+            _ => None,
+        }
+    }
+
+    pub fn is_reliable(self) -> bool {
+        !matches!(self, ChannelKind::UnreliableSequenced)
+    }
+}
+
+/// The channel with its own sequence, picked by the sender.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Channel {
+    pub id: u8,
+    pub kind: ChannelKind,
+}
+
+#[allow(unused)]
+impl Channel {
+    /// The channel of [`crate::engine::network::NetworkMessage::Rely`]
+    pub const RELIABLE: Channel = Channel { id: 0, kind: ChannelKind::ReliableOrdered };
+    /// The channel of [`crate::engine::network::NetworkMessage::Once`]
+    pub const UNRELIABLE: Channel = Channel { id: 1, kind: ChannelKind::UnreliableSequenced };
+
+    pub const fn new(id: u8, kind: ChannelKind) -> Self {
+        Self { id, kind }
+    }
+}
+
+/// Whether the sequence `a` is after `b` with wrapping.
+fn is_after(a: u32, b: u32) -> bool {
+    a != b && a.wrapping_sub(b) < u32::MAX / 2
+}
+
+/// Frame the payload with the channel header.
+pub fn encode_packet(channel: Channel, seq: u32, payload: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(HEADER_LEN + payload.len());
+    packet.push(channel.id);
+    packet.push(channel.kind.to_byte());
+    packet.extend_from_slice(&seq.to_le_bytes());
+    packet.extend_from_slice(payload);
+    packet
+}
+
+/// Get the channel, sequence and payload of the packet.
+pub fn decode_packet(packet: &[u8]) -> anyhow::Result<(Channel, u32, &[u8])> {
+    if packet.len() < HEADER_LEN {
+        return Err(anyhow!("The packet with {} bytes is too short", packet.len()));
+    }
+    let kind = ChannelKind::from_byte(packet[1]).ok_or(anyhow!("Unknown channel kind {}", packet[1]))?;
+    let seq = u32::from_le_bytes([packet[2], packet[3], packet[4], packet[5]]);
+    Ok((Channel::new(packet[0], kind), seq, &packet[HEADER_LEN..]))
+}
+
+/// The sequences of the channels to send.
+#[derive(Debug, Default)]
+pub struct ChannelSender {
+    next: HashMap<u8, u32>,
+}
+
+impl ChannelSender {
+    /// Frame the payload with the next sequence of the channel.
+    pub fn frame(&mut self, channel: Channel, payload: &[u8]) -> Vec<u8> {
+        let seq = self.next.entry(channel.id).or_insert(0);
+        let packet = encode_packet(channel, *seq, payload);
+        *seq = seq.wrapping_add(1);
+        packet
+    }
+}
+
+#[derive(Debug)]
+enum ReceiveState {
+    Ordered {
+        next: u32,
+        pending: BTreeMap<u32, Vec<u8>>,
+    },
+    Unordered {
+        /// All the sequences before are received.
+        next: u32,
+        received: BTreeSet<u32>,
+    },
+    Sequenced {
+        last: Option<u32>,
+    },
+}
+
+impl ReceiveState {
+    fn new(kind: ChannelKind) -> Self {
+        match kind {
+            ChannelKind::ReliableOrdered => ReceiveState::Ordered { next: 0, pending: Default::default() },
+            ChannelKind::ReliableUnordered => ReceiveState::Unordered { next: 0, received: Default::default() },
+            ChannelKind::UnreliableSequenced => ReceiveState::Sequenced { last: None },
+        }
+    }
+
+    fn kind(&self) -> ChannelKind {
+        match self {
+            ReceiveState::Ordered { .. } => ChannelKind::ReliableOrdered,
+            ReceiveState::Unordered { .. } => ChannelKind::ReliableUnordered,
+            ReceiveState::Sequenced { .. } => ChannelKind::UnreliableSequenced,
+        }
+    }
+}
+
+/// Apply the channel semantics to the packets received.
+#[derive(Debug, Default)]
+pub struct ChannelReceiver {
+    states: HashMap<u8, ReceiveState>,
+}
+
+impl ChannelReceiver {
+    /// Receive the packet, call `deliver` with the payloads ready in order.
+    pub fn receive(&mut self, packet: &[u8], mut deliver: impl FnMut(&[u8])) -> anyhow::Result<()> {
+        let (channel, seq, payload) = decode_packet(packet)?;
+        let state = self.states.entry(channel.id).or_insert_with(|| ReceiveState::new(channel.kind));
+        if state.kind() != channel.kind {
+            log::warn!(target: "network", "The channel {} changed to {:?}, reset the sequence", channel.id, channel.kind);
+            *state = ReceiveState::new(channel.kind);
+        }
+        match state {
+            ReceiveState::Ordered { next, pending } => {
+                if seq == *next {
+                    deliver(payload);
+                    *next = next.wrapping_add(1);
+                    while let Some(x) = pending.remove(next) {
+                        deliver(&x);
+                        *next = next.wrapping_add(1);
+                    }
+                } else if is_after(seq, *next) {
+                    if pending.len() >= MAX_PENDING {
+                        return Err(anyhow!("Too many packets waiting for {} in channel {}", next, channel.id));
+                    }
+                    pending.insert(seq, payload.to_vec());
+                }
+            }
+            ReceiveState::Unordered { next, received } => {
+                if (seq == *next || is_after(seq, *next)) && received.insert(seq) {
+                    deliver(payload);
+                    while received.remove(next) {
+                        *next = next.wrapping_add(1);
+                    }
+                }
+            }
+            ReceiveState::Sequenced { last } => {
+                if last.map(|x| is_after(seq, x)).unwrap_or(true) {
+                    *last = Some(seq);
+                    deliver(payload);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::engine::network::channel::{Channel, ChannelKind, ChannelReceiver, ChannelSender, encode_packet};
+
+    fn receive(receiver: &mut ChannelReceiver, channel: Channel, seqs: &[u32]) -> Vec<u8> {
+        let mut got = vec![];
+        for seq in seqs {
+            receiver.receive(&encode_packet(channel, *seq, &[*seq as u8]), |x| got.push(x[0])).unwrap();
+        }
+        got
+    }
+
+    #[test]
+    fn test_channels() {
+        let mut receiver = ChannelReceiver::default();
+        let ordered = Channel::new(0, ChannelKind::ReliableOrdered);
+        assert_eq!(receive(&mut receiver, ordered, &[1, 0, 0, 3, 2]), [0, 1, 2, 3]);
+
+        let unordered = Channel::new(1, ChannelKind::ReliableUnordered);
+        assert_eq!(receive(&mut receiver, unordered, &[1, 0, 1, 3, 2, 0]), [1, 0, 3, 2]);
+
+        let sequenced = Channel::new(2, ChannelKind::UnreliableSequenced);
+        assert_eq!(receive(&mut receiver, sequenced, &[0, 2, 1, 3, 3]), [0, 2, 3]);
+
+        let mut sender = ChannelSender::default();
+        sender.frame(sequenced, &[]);
+        let packet = sender.frame(sequenced, &[7]);
+        assert!(receiver.receive(&packet, |_| panic!("older than 3")).is_ok());
+        assert!(receiver.receive(&[0, 9], |_| {}).is_err());
+    }
+}
@@ -0,0 +1,172 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use dashmap::DashSet;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+
+use crate::engine::network::{DataHandler, NetworkMessage};
+use crate::engine::network::peer::Peer;
+
+/// The version of the protocol, the peers with the other versions are rejected.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// The message between the peers with the data `T` sent by the game.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Message<T> {
+    /// Sent by the client on connect.
+    Hello { version: u32 },
+    /// Replied by the server if the hello accepted.
+    Welcome { version: u32 },
+    /// Sent before closing for the version mismatched.
+    Rejected { version: u32 },
+    Data(T),
+}
+
+#[allow(unused)]
+impl<T: Serialize> Message<T> {
+    pub fn encode(&self) -> anyhow::Result<Vec<u8>> {
+        Ok(bincode::serialize(self)?)
+    }
+}
+
+#[allow(unused)]
+impl<T: DeserializeOwned> Message<T> {
+    pub fn decode(data: &[u8]) -> anyhow::Result<Self> {
+        Ok(bincode::deserialize(data)?)
+    }
+}
+
+/// Encode the data as [`Message::Data`]
+pub fn encode_data<T: Serialize>(data: &T) -> anyhow::Result<Vec<u8>> {
+    Message::Data(data).encode()
+}
+
+/// Decode the [`Message::Data`], error for the other messages.
+#[allow(unused)]
+pub fn decode_data<T: DeserializeOwned>(data: &[u8]) -> anyhow::Result<T> {
+    match Message::decode(data)? {
+        Message::Data(x) => Ok(x),
+        _ => Err(anyhow!("The message is not data")),
+    }
+}
+
+/// Check the version from the hello.
+pub fn check_version(version: u32) -> anyhow::Result<()> {
+    if version == PROTOCOL_VERSION {
+        Ok(())
+    } else {
+        Err(anyhow!("The protocol version {} mismatched, expected {}", version, PROTOCOL_VERSION))
+    }
+}
+
+/// The handler to handle the typed data after the handshake.
+pub trait TypedDataHandler: Send + Sync + Clone + 'static {
+    type Data: Serialize + DeserializeOwned + Send;
+
+    /// `src` The peer that sent the data
+    /// Return true means successful
+    fn handle(&self, src: &Peer, data: Self::Data) -> bool;
+
+    /// Called when the handshake with the peer is done.
+    fn on_connected(&self, _src: &Peer) {}
+}
+
+/// Handle the handshake and decode the data for the [`TypedDataHandler`]
+#[derive(Clone)]
+pub struct Typed<H> {
+    handler: H,
+    /// The peers shaken hands.
+    verified: Arc<DashSet<SocketAddr>>,
+}
+
+#[allow(unused)]
+impl<H: TypedDataHandler> Typed<H> {
+    pub fn new(handler: H) -> Self {
+        Self {
+            handler,
+            verified: Default::default(),
+        }
+    }
+
+    fn verified(&self, src: &Peer) {
+        if self.verified.insert(src.addr) {
+            info!(target: "network", "Shaken hands with {:?}", src.addr);
+            self.handler.on_connected(src);
+        }
+    }
+
+    fn send(src: &Peer, msg: &Message<H::Data>) {
+        match msg.encode() {
+            Ok(data) => {
+                let _ = src.sender.send(NetworkMessage::Rely(data));
+            }
+            Err(e) => warn!(target: "network", "Encode message failed for {:?}", e),
+        }
+    }
+}
+
+impl<H: TypedDataHandler> DataHandler for Typed<H> {
+    fn handle(&self, src: &Peer, data: &[u8]) -> bool {
+        let msg = match Message::<H::Data>::decode(data) {
+            Ok(msg) => msg,
+            Err(e) => {
+                warn!(target: "network", "Invalid message from {:?} for {:?}", src.addr, e);
+                return true;
+            }
+        };
+        match msg {
+            Message::Hello { version } => {
+                if let Err(e) = check_version(version) {
+                    warn!(target: "network", "Rejected {:?} for {:?}", src.addr, e);
+                    // keep the loop to send the rejection, the data from the peer is ignored.
+                    Self::send(src, &Message::Rejected { version: PROTOCOL_VERSION });
+                    self.verified.remove(&src.addr);
+                    return true;
+                }
+                Self::send(src, &Message::Welcome { version: PROTOCOL_VERSION });
+                self.verified(src);
+                true
+            }
+            Message::Welcome { version } => {
+                if let Err(e) = check_version(version) {
+                    warn!(target: "network", "Welcomed by {:?} but {:?}", src.addr, e);
+                    return false;
+                }
+                self.verified(src);
+                true
+            }
+            Message::Rejected { version } => {
+                warn!(target: "network", "Rejected by {:?} with the protocol version {}, ours is {}", src.addr, version, PROTOCOL_VERSION);
+                self.verified.remove(&src.addr);
+                false
+            }
+            Message::Data(data) => {
+                if self.verified.contains(&src.addr) {
+                    self.handler.handle(src, data)
+                } else {
+                    warn!(target: "network", "Ignored the data from {:?} before the handshake", src.addr);
+                    true
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::engine::network::message::{check_version, decode_data, encode_data, Message, PROTOCOL_VERSION};
+
+    #[test]
+    fn test_message() {
+        let data = encode_data(&(1u32, "portal".to_string())).unwrap();
+        assert_eq!(decode_data::<(u32, String)>(&data).unwrap(), (1, "portal".into()));
+
+        let hello = Message::<(u32, String)>::Hello { version: PROTOCOL_VERSION }.encode().unwrap();
+        assert!(decode_data::<(u32, String)>(&hello).is_err());
+        assert!(check_version(PROTOCOL_VERSION).is_ok());
+        assert!(check_version(PROTOCOL_VERSION + 1).is_err());
+    }
+}
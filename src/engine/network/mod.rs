@@ -2,10 +2,14 @@ use std::time::Duration;
 
 use tokio_kcp::{KcpConfig, KcpNoDelayConfig};
 
+use crate::engine::network::channel::Channel;
 use crate::engine::network::peer::Peer;
 
 pub mod server;
 pub mod peer;
+pub mod message;
+pub mod channel;
+pub mod rendezvous;
 
 #[allow(unused)]
 /// The handler to handle the message from `Peer`
@@ -19,8 +23,11 @@ pub trait DataHandler: Send + Clone + 'static {
 #[allow(unused)]
 #[derive(Debug)]
 pub enum NetworkMessage {
+    /// Sent in [`Channel::RELIABLE`]
     Rely(Vec<u8>),
+    /// Sent in [`Channel::UNRELIABLE`]
     Once(Vec<u8>),
+    Channel(Channel, Vec<u8>),
 }
 
 #[allow(unused)]
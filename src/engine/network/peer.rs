@@ -2,16 +2,24 @@ use std::net::SocketAddr;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::task::{Context, Poll, Waker};
+use std::time::Duration;
 
-use log::error;
+use log::{error, info};
+use serde::Serialize;
 use tokio::io::AsyncWriteExt;
 use tokio::select;
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
-use tokio_kcp::KcpStream;
+use tokio_kcp::{KcpListener, KcpStream};
 
-use crate::engine::network::{DataHandler, NetworkMessage};
+use crate::engine::network::{DataHandler, DEFAULT_KCP_CONFIG, NetworkMessage};
+use crate::engine::network::channel::{Channel, ChannelReceiver, ChannelSender};
+use crate::engine::network::message::{encode_data, Message, PROTOCOL_VERSION, Typed, TypedDataHandler};
+use crate::engine::network::rendezvous::Punched;
 use crate::engine::task::wakers::NeverWaker;
 
+/// The time to wait the connection from the client punched.
+const PUNCHED_ACCEPT_TIMEOUT: Duration = Duration::from_secs(10);
+
 /// The peer
 #[derive(Debug, Clone)]
 pub struct Peer {
@@ -36,6 +44,77 @@ impl Peer {
         this
     }
 
+    /// Connect to the server, need call in tokio runtime
+    pub async fn connect(addr: SocketAddr, handler: impl DataHandler) -> anyhow::Result<Self> {
+        let stream = KcpStream::connect(&DEFAULT_KCP_CONFIG, addr).await?;
+        info!("Connected to {:?}", addr);
+        Ok(Self::new(stream, addr, handler))
+    }
+
+    /// Connect to the server and say hello with the protocol version, need call in tokio runtime
+    ///
+    /// The data is handled after the server welcomed.
+    pub async fn connect_typed<H: TypedDataHandler>(addr: SocketAddr, handler: H) -> anyhow::Result<Self> {
+        let this = Self::connect(addr, Typed::new(handler)).await?;
+        this.hello::<H::Data>()?;
+        Ok(this)
+    }
+
+    /// Connect to the client by the hole punched, need call in tokio runtime
+    ///
+    /// The listening side waits the first packet from the other.
+    pub async fn connect_punched(punched: Punched, handler: impl DataHandler) -> anyhow::Result<Self> {
+        let Punched { socket, peer, listen } = punched;
+        if !listen {
+            let stream = KcpStream::connect_with_socket(&DEFAULT_KCP_CONFIG, socket, peer).await?;
+            info!("Connected to {:?} punched", peer);
+            return Ok(Self::new(stream, peer, handler));
+        }
+        let mut listener = KcpListener::from_socket(DEFAULT_KCP_CONFIG, socket).await?;
+        let (stream, addr) = tokio::time::timeout(PUNCHED_ACCEPT_TIMEOUT, listener.accept()).await
+            .map_err(|_| anyhow::anyhow!("No connection from {:?} in {:?}", peer, PUNCHED_ACCEPT_TIMEOUT))??;
+        info!("Accepted {:?} punched", addr);
+        let this = Self::new(stream, addr, handler);
+        // the stream is driven by the listener, keep it until the peer stopped.
+        let listening = this.listening.clone();
+        tokio::spawn(async move {
+            let _listener = listener;
+            while listening.load(Ordering::Acquire) {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        });
+        Ok(this)
+    }
+
+    /// Connect to the client by the hole punched with the handshake like [`Peer::connect_typed`]
+    pub async fn connect_punched_typed<H: TypedDataHandler>(punched: Punched, handler: H) -> anyhow::Result<Self> {
+        let listen = punched.listen;
+        let this = Self::connect_punched(punched, Typed::new(handler)).await?;
+        if !listen {
+            this.hello::<H::Data>()?;
+        }
+        Ok(this)
+    }
+
+    fn hello<T: Serialize>(&self) -> anyhow::Result<()> {
+        let hello = Message::<T>::Hello { version: PROTOCOL_VERSION }.encode()?;
+        self.sender.send(NetworkMessage::Rely(hello))?;
+        Ok(())
+    }
+
+    /// Send the data as [`Message::Data`] to the peer shaken hands.
+    #[allow(unused)]
+    pub fn send_data<T: Serialize>(&self, data: &T, channel: Channel) -> anyhow::Result<()> {
+        self.send_on(channel, encode_data(data)?)
+    }
+
+    /// Send the payload in the channel with its semantics.
+    #[allow(unused)]
+    pub fn send_on(&self, channel: Channel, payload: Vec<u8>) -> anyhow::Result<()> {
+        self.sender.send(NetworkMessage::Channel(channel, payload))?;
+        Ok(())
+    }
+
     async fn run_loop(self, mut stream: KcpStream, mut receiver: UnboundedReceiver<NetworkMessage>, handler: impl DataHandler) {
         let mut errs = 0;
         macro_rules! got_err {
@@ -48,40 +127,45 @@ impl Peer {
         }
         let mut buf = Vec::new();
         buf.resize(65536, 0);
+        let mut channels = ChannelSender::default();
+        let mut incoming = ChannelReceiver::default();
         while self.listening.load(Ordering::Acquire) {
             select! {
                 mut msg = receiver.recv() => {
                     loop {
                         match msg {
                             Some(msg) => {
-                                match msg {
-                                    NetworkMessage::Rely(packet) => {
-                                        if let Err(e) = stream.send(&packet[..]).await {
-                                            error!("Send packet failed for {:?}", e);
-                                            got_err!();
-                                        } else {
-                                            errs = 0;
-                                        }
+                                let (channel, payload) = match msg {
+                                    NetworkMessage::Rely(data) => (Channel::RELIABLE, data),
+                                    NetworkMessage::Once(data) => (Channel::UNRELIABLE, data),
+                                    NetworkMessage::Channel(channel, data) => (channel, data),
+                                };
+                                let packet = channels.frame(channel, &payload);
+                                if channel.kind.is_reliable() {
+                                    if let Err(e) = stream.send(&packet[..]).await {
+                                        error!("Send packet failed for {:?}", e);
+                                        got_err!();
+                                    } else {
+                                        errs = 0;
                                     }
-                                    NetworkMessage::Once(packet) => {
-                                        match stream.poll_send(&mut Context::from_waker(&Waker::from(Arc::new(NeverWaker))), &packet[..]) {
-                                            Poll::Ready(x) => {
-                                                match x {
-                                                    Ok(n) => {
-                                                        if n != packet.len() {
-                                                            error!("Tried to send {} bytes but sent {} bytes. Checking it must not be stream mode!", packet.len(), n);
-                                                        } else {
-                                                            errs = 0;
-                                                        }
-                                                    }
-                                                    Err(e) => {
-                                                        error!("Send packet failed for {:?}", e);
-                                                        got_err!();
+                                } else {
+                                    match stream.poll_send(&mut Context::from_waker(&Waker::from(Arc::new(NeverWaker))), &packet[..]) {
+                                        Poll::Ready(x) => {
+                                            match x {
+                                                Ok(n) => {
+                                                    if n != packet.len() {
+                                                        error!("Tried to send {} bytes but sent {} bytes. Checking it must not be stream mode!", packet.len(), n);
+                                                    } else {
+                                                        errs = 0;
                                                     }
                                                 }
+                                                Err(e) => {
+                                                    error!("Send packet failed for {:?}", e);
+                                                    got_err!();
+                                                }
                                             }
-                                            _ => {}
                                         }
+                                        _ => {}
                                     }
                                 }
                             }
@@ -104,7 +188,12 @@ impl Peer {
                     match data {
                         Ok(n) => {
                             errs = 0;
-                            if !handler.handle(&self, &buf[..n]) {
+                            let mut handled = true;
+                            if let Err(e) = incoming.receive(&buf[..n], |x| handled &= handler.handle(&self, x)) {
+                                error!("Invalid packet for {:?}", e);
+                                got_err!();
+                            }
+                            if !handled {
                                 break;
                             }
                         }
@@ -0,0 +1,101 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use log::info;
+use serde::{Deserialize, Serialize};
+use tokio::net::UdpSocket;
+
+/// The bytes before the rendezvous messages.
+///
+/// The punches are shorter than the kcp header, so they are dropped by the kcp on the same socket.
+const MAGIC: [u8; 2] = *b"MP";
+/// The seconds between registering again until matched.
+const REGISTER_INTERVAL: Duration = Duration::from_secs(1);
+/// The time to wait for the other client in the session.
+pub const MATCH_TIMEOUT: Duration = Duration::from_secs(30);
+const PUNCH_COUNT: usize = 5;
+const PUNCH_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The messages between the clients and the rendezvous server, in plain udp.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum RendezvousMessage {
+    /// Sent by the client to join the session.
+    Register { session: String },
+    /// Sent by the server with the address of the other client observed,
+    /// the first registered listens and the other connects.
+    Matched { peer: SocketAddr, listen: bool },
+    /// Sent between the clients to open the mappings in their NATs.
+    Punch,
+}
+
+#[allow(unused)]
+impl RendezvousMessage {
+    pub fn encode(&self) -> anyhow::Result<Vec<u8>> {
+        let mut data = MAGIC.to_vec();
+        bincode::serialize_into(&mut data, self)?;
+        Ok(data)
+    }
+
+    pub fn decode(data: &[u8]) -> anyhow::Result<Self> {
+        match data.strip_prefix(&MAGIC) {
+            Some(x) => Ok(bincode::deserialize(x)?),
+            None => Err(anyhow!("Not a rendezvous message")),
+        }
+    }
+}
+
+/// The socket with the hole punched to the other client.
+#[derive(Debug)]
+pub struct Punched {
+    pub socket: UdpSocket,
+    /// The address of the other client observed by the server.
+    pub peer: SocketAddr,
+    /// Accept the connection from the peer instead of connecting.
+    pub listen: bool,
+}
+
+/// Register in the session at the rendezvous server, then punch the hole to the client matched.
+///
+/// Need call in tokio runtime.
+pub async fn punch(server: SocketAddr, session: &str) -> anyhow::Result<Punched> {
+    let socket = UdpSocket::bind(if server.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" }).await?;
+    let register = RendezvousMessage::Register { session: session.to_string() }.encode()?;
+    let mut buf = [0u8; 1024];
+    let matched = tokio::time::timeout(MATCH_TIMEOUT, async {
+        loop {
+            socket.send_to(&register, server).await?;
+            if let Ok(Ok((n, src))) = tokio::time::timeout(REGISTER_INTERVAL, socket.recv_from(&mut buf)).await {
+                if src != server {
+                    continue;
+                }
+                if let Ok(RendezvousMessage::Matched { peer, listen }) = RendezvousMessage::decode(&buf[..n]) {
+                    return anyhow::Ok((peer, listen));
+                }
+            }
+        }
+    }).await;
+    let (peer, listen) = matched.map_err(|_| anyhow!("No one joined the session {} in {:?}", session, MATCH_TIMEOUT))??;
+    info!(target: "network", "Matched with {:?} in the session {}, punching", peer, session);
+
+    let punch = RendezvousMessage::Punch.encode()?;
+    for _ in 0..PUNCH_COUNT {
+        socket.send_to(&punch, peer).await?;
+        tokio::time::sleep(PUNCH_INTERVAL).await;
+    }
+    Ok(Punched { socket, peer, listen })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::engine::network::rendezvous::RendezvousMessage;
+
+    #[test]
+    fn test_rendezvous_message() {
+        let msg = RendezvousMessage::Matched { peer: "1.2.3.4:23333".parse().unwrap(), listen: true };
+        assert_eq!(RendezvousMessage::decode(&msg.encode().unwrap()).unwrap(), msg);
+        // the kcp header is 24 bytes
+        assert!(RendezvousMessage::Punch.encode().unwrap().len() < 24);
+        assert!(RendezvousMessage::decode(&[0, 0, 0, 0]).is_err());
+    }
+}
@@ -1,17 +1,23 @@
+use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use egui::epaint::ahash::HashMap;
-use log::info;
+use dashmap::DashMap;
+use log::{info, warn};
 use tokio::{pin, select};
-use tokio::net::ToSocketAddrs;
-use tokio::sync::RwLock;
+use tokio::net::{ToSocketAddrs, UdpSocket};
 use tokio_kcp::KcpListener;
 
-use crate::engine::network::{DataHandler, DEFAULT_KCP_CONFIG};
+use crate::engine::network::{DataHandler, DEFAULT_KCP_CONFIG, NetworkMessage};
+use crate::engine::network::channel::Channel;
+use crate::engine::network::message::{Typed, TypedDataHandler};
 use crate::engine::network::peer::Peer;
+use crate::engine::network::rendezvous::{MATCH_TIMEOUT, RendezvousMessage};
+
+/// The max sessions waiting for the other client.
+const MAX_WAITING_SESSIONS: usize = 4096;
 
 /// The server object which could be clone
 #[allow(unused)]
@@ -19,7 +25,7 @@ use crate::engine::network::peer::Peer;
 pub struct Server {
     pub running: Arc<AtomicBool>,
     /// The peers still running
-    pub peers: Arc<RwLock<HashMap<SocketAddr, Peer>>>,
+    pub peers: Arc<DashMap<SocketAddr, Peer>>,
 }
 
 #[allow(unused)]
@@ -35,6 +41,21 @@ impl Server {
         Ok(this)
     }
 
+    /// Construct the server accepting the peers with the same protocol version.
+    pub async fn new_typed(listen_ip: impl ToSocketAddrs, handler: impl TypedDataHandler) -> anyhow::Result<Self> {
+        Self::new(listen_ip, Typed::new(handler)).await
+    }
+
+    /// Send the payload to all the peers still running except the one at `except`.
+    ///
+    /// Return the count of the peers sent to.
+    pub fn broadcast(&self, channel: Channel, payload: &[u8], except: Option<SocketAddr>) -> usize {
+        self.peers.iter()
+            .filter(|x| Some(*x.key()) != except && x.listening.load(Ordering::Relaxed))
+            .filter(|x| x.sender.send(NetworkMessage::Channel(channel, payload.to_vec())).is_ok())
+            .count()
+    }
+
     async fn run_loop(self, mut listener: KcpListener, handler: impl DataHandler) {
         info!("Server looping");
         while self.running.load(Ordering::Acquire) {
@@ -46,12 +67,11 @@ impl Server {
                         Ok((stream, addr)) => {
                             info!("Accepted KcpStream from {:?}", addr);
                             let peer = Peer::new(stream, addr, handler.clone());
-                            let mut write = self.peers.write().await;
-                            if let Some(old_peer) = write.insert(peer.addr, peer) {
+                            if let Some(old_peer) = self.peers.insert(peer.addr, peer) {
                                 old_peer.listening.store(false, Ordering::Relaxed);
                             }
 
-                            write.retain(|_, p| p.listening.load(Ordering::Relaxed));
+                            self.peers.retain(|_, p| p.listening.load(Ordering::Relaxed));
                         }
                         Err(e) => {
                             log::warn!("accept packet from listener failed for {:?}", e);
@@ -59,8 +79,7 @@ impl Server {
                     }
                 }
                 _ = &mut sleep => {
-                    let mut write = self.peers.write().await;
-                    write.retain(|_, p| p.listening.load(Ordering::Relaxed));
+                    self.peers.retain(|_, p| p.listening.load(Ordering::Relaxed));
                 }
             }
         }
@@ -72,4 +91,90 @@ impl Drop for Server {
     fn drop(&mut self) {
         self.running.store(false, Ordering::Relaxed);
     }
+}
+
+/// The public server to match the clients in the same session,
+/// then they punch the holes to connect each other directly.
+///
+/// See [`crate::engine::network::rendezvous::punch`]
+#[allow(unused)]
+#[derive(Clone, Debug)]
+pub struct RendezvousServer {
+    pub running: Arc<AtomicBool>,
+}
+
+#[allow(unused)]
+impl RendezvousServer {
+    pub async fn new(listen_ip: impl ToSocketAddrs) -> anyhow::Result<Self> {
+        let socket = UdpSocket::bind(listen_ip).await?;
+        info!(target: "network", "Rendezvous server at {:?}", socket.local_addr()?);
+        let this = Self {
+            running: Arc::new(AtomicBool::new(true)),
+        };
+        tokio::spawn(this.clone().run_loop(socket));
+        Ok(this)
+    }
+
+    async fn run_loop(self, socket: UdpSocket) {
+        // session -> the first client
+        let mut waiting: HashMap<String, (SocketAddr, Instant)> = HashMap::new();
+        // client -> (peer, listen), replied again if the client registers for the reply lost
+        let mut matched: HashMap<SocketAddr, (SocketAddr, bool, Instant)> = HashMap::new();
+        let mut buf = [0u8; 1024];
+        while self.running.load(Ordering::Acquire) {
+            let (n, src) = match tokio::time::timeout(Duration::from_secs(1), socket.recv_from(&mut buf)).await {
+                Ok(Ok(x)) => x,
+                Ok(Err(e)) => {
+                    warn!(target: "network", "Receive from the rendezvous socket failed for {:?}", e);
+                    continue;
+                }
+                Err(_) => continue,
+            };
+            waiting.retain(|_, x| x.1.elapsed() < MATCH_TIMEOUT);
+            matched.retain(|_, x| x.2.elapsed() < MATCH_TIMEOUT);
+            let session = match RendezvousMessage::decode(&buf[..n]) {
+                Ok(RendezvousMessage::Register { session }) => session,
+                _ => continue,
+            };
+
+            let replies = if let Some(&(peer, listen, _)) = matched.get(&src) {
+                vec![(src, peer, listen)]
+            } else {
+                match waiting.get(&session) {
+                    Some(&(first, _)) if first != src => {
+                        waiting.remove(&session);
+                        info!(target: "network", "Matched {:?} and {:?} in the session {}", first, src, session);
+                        let now = Instant::now();
+                        matched.insert(first, (src, true, now));
+                        matched.insert(src, (first, false, now));
+                        vec![(first, src, true), (src, first, false)]
+                    }
+                    Some(_) => vec![],
+                    None => {
+                        if waiting.len() < MAX_WAITING_SESSIONS {
+                            waiting.insert(session, (src, Instant::now()));
+                        }
+                        vec![]
+                    }
+                }
+            };
+            for (to, peer, listen) in replies {
+                match (RendezvousMessage::Matched { peer, listen }).encode() {
+                    Ok(data) => {
+                        if let Err(e) = socket.send_to(&data, to).await {
+                            warn!(target: "network", "Send the match to {:?} failed for {:?}", to, e);
+                        }
+                    }
+                    Err(e) => warn!(target: "network", "Encode the match failed for {:?}", e),
+                }
+            }
+        }
+        info!(target: "network", "Rendezvous server exited");
+    }
+}
+
+impl Drop for RendezvousServer {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+    }
 }
\ No newline at end of file
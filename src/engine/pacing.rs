@@ -0,0 +1,150 @@
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use log::warn;
+use once_cell::sync::Lazy;
+
+/// The intervals to get the median.
+const HISTORY: usize = 240;
+/// The stutters kept to show.
+const STUTTER_HISTORY: usize = 8;
+/// The intervals needed before detecting the stutters.
+const MIN_SAMPLES: usize = 30;
+/// The interval should be longer than the median by the seconds at least to be a stutter.
+const MIN_SPIKE: f32 = 0.004;
+
+/// The events happened since the last present, taken by [`FramePacing::on_present`]
+static FRAME_EVENTS: Lazy<Mutex<Vec<Cow<'static, str>>>> = Lazy::new(Default::default);
+
+/// Mark what happened in this frame (level switch, pipeline creation, texture upload...)
+/// to annotate the stutter, can be called from any thread.
+pub fn mark_frame_event(event: impl Into<Cow<'static, str>>) {
+    FRAME_EVENTS.lock().unwrap().push(event.into());
+}
+
+/// The frame that took much longer than the median.
+#[derive(Debug, Clone)]
+pub struct Stutter {
+    pub at: Instant,
+    /// The present to present seconds.
+    pub interval: f32,
+    pub median: f32,
+    pub events: Vec<Cow<'static, str>>,
+}
+
+/// Record the present to present intervals and detect the stutters.
+#[derive(Debug)]
+pub struct FramePacing {
+    last_present: Option<Instant>,
+    intervals: VecDeque<f32>,
+    stutters: VecDeque<Stutter>,
+    stutter_count: u64,
+    /// The frame is a stutter if the interval is above the factor × median.
+    pub stutter_factor: f32,
+}
+
+impl Default for FramePacing {
+    fn default() -> Self {
+        Self {
+            last_present: None,
+            intervals: VecDeque::with_capacity(HISTORY),
+            stutters: Default::default(),
+            stutter_count: 0,
+            stutter_factor: 2.5,
+        }
+    }
+}
+
+#[allow(unused)]
+impl FramePacing {
+    /// Record the frame presented, return the stutter if detected.
+    pub fn on_present(&mut self, now: Instant) -> Option<&Stutter> {
+        let events = std::mem::take(&mut *FRAME_EVENTS.lock().unwrap());
+        let last = self.last_present.replace(now)?;
+        let interval = now.duration_since(last).as_secs_f32();
+        let median = self.median();
+        if self.intervals.len() == HISTORY {
+            self.intervals.pop_front();
+        }
+        self.intervals.push_back(interval);
+
+        if self.intervals.len() <= MIN_SAMPLES
+            || interval <= median * self.stutter_factor
+            || interval - median < MIN_SPIKE {
+            return None;
+        }
+        warn!(target: "pacing", "Stutter {:.1}ms (median {:.1}ms) with {:?}", interval * 1000.0, median * 1000.0, events);
+        if self.stutters.len() == STUTTER_HISTORY {
+            self.stutters.pop_front();
+        }
+        self.stutter_count += 1;
+        self.stutters.push_back(Stutter { at: now, interval, median, events });
+        self.stutters.back()
+    }
+
+    /// Do not measure the next interval, for the loop is going to wait for the events.
+    pub fn pause(&mut self) {
+        self.last_present = None;
+    }
+
+    /// The median interval of the recent frames in seconds.
+    pub fn median(&self) -> f32 {
+        if self.intervals.is_empty() {
+            return 0.0;
+        }
+        let mut sorted = self.intervals.iter().copied().collect::<Vec<_>>();
+        sorted.sort_by(f32::total_cmp);
+        sorted[sorted.len() / 2]
+    }
+
+    pub fn intervals(&self) -> &VecDeque<f32> {
+        &self.intervals
+    }
+
+    /// The recent stutters, the last is the newest.
+    pub fn stutters(&self) -> &VecDeque<Stutter> {
+        &self.stutters
+    }
+
+    pub fn stutter_count(&self) -> u64 {
+        self.stutter_count
+    }
+
+    /// Show the median and the recent stutters in the debug overlay.
+    pub fn show(&self, ui: &mut egui::Ui) {
+        ui.label(format!("Frame {:.1}ms, stutters {}", self.median() * 1000.0, self.stutter_count));
+        if let Some(x) = self.stutters.back() {
+            let events = if x.events.is_empty() { "-".to_string() } else { x.events.join(", ") };
+            ui.label(format!("Last stutter {:.1}ms ({:.1}x) {:.0}s ago: {}", x.interval * 1000.0, x.interval / x.median,
+                             x.at.elapsed().as_secs_f32(), events));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use crate::engine::pacing::{FramePacing, mark_frame_event};
+
+    #[test]
+    fn test_stutter() {
+        let mut pacing = FramePacing::default();
+        let mut now = Instant::now();
+        for _ in 0..60 {
+            now += Duration::from_millis(16);
+            assert!(pacing.on_present(now).is_none());
+        }
+        mark_frame_event("level switch");
+        now += Duration::from_millis(100);
+        let stutter = pacing.on_present(now).unwrap();
+        assert!(stutter.events.iter().any(|x| x == "level switch"));
+        assert_eq!(pacing.stutter_count(), 1);
+
+        pacing.pause();
+        now += Duration::from_secs(5);
+        assert!(pacing.on_present(now).is_none());
+    }
+}
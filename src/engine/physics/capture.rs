@@ -0,0 +1,209 @@
+use std::collections::VecDeque;
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex, Once, Weak};
+
+use log::{error, info};
+use nalgebra::Vector3;
+use once_cell::sync::Lazy;
+use rapier3d::prelude::CollisionEvent;
+
+/// The records kept by default, the oldest dropped beyond it.
+pub const CAPTURE_CAPACITY: usize = 16384;
+/// The file written by the captures alive when panicked.
+pub const PANIC_CAPTURE_PATH: &str = "capture_panic.txt";
+
+/// The captures dumped when panicked, dropped captures are skipped.
+static PANIC_CAPTURES: Lazy<Mutex<Vec<Weak<Mutex<CaptureRing>>>>> = Lazy::new(Default::default);
+static PANIC_HOOK: Once = Once::new();
+
+/// What happened in the physics, in the order recorded.
+#[allow(unused)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum CaptureRecord {
+    /// The frame time and the fixed steps run for it.
+    Frame { dt: f32, steps: u32 },
+    /// The collision started or stopped between the colliders by their raw handles.
+    Collision { collider1: (u32, u32), collider2: (u32, u32), started: bool },
+    /// Went through the portal (world, index) to the world, the eye before and after.
+    Traversal { portal: (usize, usize), to: usize, before: [f32; 3], after: [f32; 3] },
+    /// Out of the bounds at the position in the world, moved to the position in the world.
+    Recovered { world: usize, pos: [f32; 3], to_world: usize, to: [f32; 3] },
+    /// Written by the game, such as the level and its seed.
+    Note(String),
+}
+
+/// The record and the physics step counted when recorded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaptureEntry {
+    pub step: u64,
+    pub record: CaptureRecord,
+}
+
+/// The latest records bounded by the capacity.
+#[derive(Debug)]
+pub struct CaptureRing {
+    entries: VecDeque<CaptureEntry>,
+    capacity: usize,
+    step: u64,
+    /// The records dropped for the capacity.
+    dropped: u64,
+}
+
+impl CaptureRing {
+    fn push(&mut self, record: CaptureRecord) {
+        if self.entries.len() >= self.capacity {
+            self.entries.pop_front();
+            self.dropped += 1;
+        }
+        self.entries.push_back(CaptureEntry { step: self.step, record });
+    }
+
+    fn write_to(&self, w: &mut impl Write) -> std::io::Result<()> {
+        writeln!(w, "# {} records, {} dropped before, {} steps", self.entries.len(), self.dropped, self.step)?;
+        for x in &self.entries {
+            writeln!(w, "{}\t{:?}", x.step, x.record)?;
+        }
+        Ok(())
+    }
+}
+
+/// The debug capture of the physics steps to analyze the glitches offline.
+///
+/// The records are shared with the panic hook to be dumped when panicked.
+#[derive(Debug)]
+pub struct PhysicsCapture {
+    ring: Arc<Mutex<CaptureRing>>,
+}
+
+impl Default for PhysicsCapture {
+    fn default() -> Self {
+        Self::new(CAPTURE_CAPACITY)
+    }
+}
+
+#[allow(unused)]
+impl PhysicsCapture {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            ring: Arc::new(Mutex::new(CaptureRing {
+                entries: VecDeque::with_capacity(capacity.min(CAPTURE_CAPACITY)),
+                capacity: capacity.max(1),
+                step: 0,
+                dropped: 0,
+            })),
+        }
+    }
+
+    /// Dump the records to [`PANIC_CAPTURE_PATH`] when panicked while the capture alive.
+    pub fn dump_on_panic(&self) {
+        PANIC_HOOK.call_once(|| {
+            let last = std::panic::take_hook();
+            std::panic::set_hook(Box::new(move |info| {
+                dump_panic_captures();
+                last(info);
+            }));
+        });
+        let mut captures = PANIC_CAPTURES.lock().unwrap();
+        captures.retain(|x| x.strong_count() > 0);
+        captures.push(Arc::downgrade(&self.ring));
+    }
+
+    pub fn record(&self, record: CaptureRecord) {
+        if let Ok(mut ring) = self.ring.lock() {
+            ring.push(record);
+        }
+    }
+
+    /// Count the physics step run, the records after it are in the next step.
+    pub fn step(&self) {
+        if let Ok(mut ring) = self.ring.lock() {
+            ring.step += 1;
+        }
+    }
+
+    pub fn record_collision(&self, event: &CollisionEvent) {
+        self.record(CaptureRecord::Collision {
+            collider1: event.collider1().into_raw_parts(),
+            collider2: event.collider2().into_raw_parts(),
+            started: event.started(),
+        });
+    }
+
+    pub fn record_traversal(&self, portal: (usize, usize), to: usize, before: &Vector3<f32>, after: &Vector3<f32>) {
+        self.record(CaptureRecord::Traversal { portal, to, before: (*before).into(), after: (*after).into() });
+    }
+
+    pub fn entries(&self) -> Vec<CaptureEntry> {
+        self.ring.lock().map(|x| x.entries.iter().cloned().collect()).unwrap_or_default()
+    }
+
+    pub fn write_to(&self, w: &mut impl Write) -> anyhow::Result<()> {
+        let ring = self.ring.lock().map_err(|_| anyhow::anyhow!("The capture is poisoned"))?;
+        ring.write_to(w)?;
+        Ok(())
+    }
+
+    /// Write the records as the text, one record each line after the step.
+    pub fn dump(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        self.write_to(&mut file)?;
+        file.flush()?;
+        Ok(())
+    }
+}
+
+/// Dump the captures alive, the locked are skipped to not deadlock in the panic.
+fn dump_panic_captures() {
+    let captures = if let Ok(x) = PANIC_CAPTURES.try_lock() { x } else {
+        return;
+    };
+    let rings = captures.iter().filter_map(|x| x.upgrade()).collect::<Vec<_>>();
+    if rings.is_empty() {
+        return;
+    }
+    let result = std::fs::File::create(PANIC_CAPTURE_PATH).and_then(|file| {
+        let mut file = std::io::BufWriter::new(file);
+        for x in &rings {
+            if let Ok(ring) = x.try_lock() {
+                ring.write_to(&mut file)?;
+            }
+        }
+        file.flush()
+    });
+    match result {
+        Ok(_) => info!(target: "capture", "Dumped the physics capture to {}", PANIC_CAPTURE_PATH),
+        Err(e) => error!(target: "capture", "Dump the physics capture failed for {:?}", e),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::engine::physics::capture::{CaptureRecord, PhysicsCapture};
+
+    #[test]
+    fn test_capture_ring() {
+        let capture = PhysicsCapture::new(3);
+        capture.record(CaptureRecord::Note("level".into()));
+        capture.record(CaptureRecord::Frame { dt: 0.016, steps: 1 });
+        capture.step();
+        capture.record(CaptureRecord::Traversal { portal: (0, 1), to: 1, before: [0.0; 3], after: [1.0; 3] });
+        capture.record(CaptureRecord::Frame { dt: 0.02, steps: 2 });
+        capture.step();
+        capture.step();
+
+        let entries = capture.entries();
+        assert_eq!(entries.len(), 3);
+        // the oldest dropped
+        assert_eq!(entries[0].record, CaptureRecord::Frame { dt: 0.016, steps: 1 });
+        assert_eq!(entries[0].step, 0);
+        assert_eq!(entries[2].step, 1);
+
+        let mut text = vec![];
+        capture.write_to(&mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        let lines = text.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "# 3 records, 1 dropped before, 3 steps");
+        assert!(lines[2].starts_with("1\tTraversal"));
+    }
+}
@@ -0,0 +1,80 @@
+use nalgebra::Point3;
+use rapier3d::pipeline::{DebugColor, DebugRenderBackend, DebugRenderMode, DebugRenderObject, DebugRenderPipeline, DebugRenderStyle};
+use wgpu::Device;
+
+use crate::engine::physics::state::RapierData;
+use crate::engine::renderer3d::renderer3d::{LineVertex, StaticLines};
+
+/// The wireframe of the colliders, the sensors and the contacts to render by [`crate::engine::renderer3d::renderer3d::PlaneRenderer::line_rp`]
+pub struct PhysicsDebugLines {
+    pipeline: DebugRenderPipeline,
+    vertices: Vec<LineVertex>,
+    pub lines: Option<StaticLines>,
+}
+
+impl Default for PhysicsDebugLines {
+    fn default() -> Self {
+        Self {
+            pipeline: DebugRenderPipeline::new(DebugRenderStyle::default(),
+                                               DebugRenderMode::COLLIDER_SHAPES | DebugRenderMode::CONTACTS | DebugRenderMode::RIGID_BODY_AXES),
+            vertices: vec![],
+            lines: None,
+        }
+    }
+}
+
+struct LineBackend<'a>(&'a mut Vec<LineVertex>);
+
+impl DebugRenderBackend for LineBackend<'_> {
+    fn draw_line(&mut self, _: DebugRenderObject, a: Point3<f32>, b: Point3<f32>, color: DebugColor) {
+        let color = hsla_to_rgba(color);
+        self.0.push(LineVertex { pos: a.coords, color });
+        self.0.push(LineVertex { pos: b.coords, color });
+    }
+}
+
+/// The debug colors are in hsla.
+fn hsla_to_rgba([h, s, l, a]: DebugColor) -> [f32; 4] {
+    let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
+    let h = (h / 60.0).rem_euclid(6.0);
+    let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+    let (r, g, b) = match h as u32 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    let m = l - c / 2.0;
+    [r + m, g + m, b + m, a]
+}
+
+#[allow(unused)]
+impl PhysicsDebugLines {
+    /// Collect the lines of the physics state and upload them.
+    pub fn update(&mut self, device: &Device, p: &RapierData) {
+        self.vertices.clear();
+        self.pipeline.render(&mut LineBackend(&mut self.vertices), &p.rigid_body_set, &p.collider_set,
+                             &p.impulse_joint_set, &p.multibody_joint_set, &p.narrow_phase);
+        self.lines = Some(StaticLines::new(device, &self.vertices));
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.vertices.len() / 2
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::engine::physics::debug::hsla_to_rgba;
+
+    #[test]
+    fn test_hsla() {
+        let close = |a: [f32; 4], b: [f32; 4]| a.iter().zip(b.iter()).all(|(a, b)| (a - b).abs() < 1e-4);
+        assert!(close(hsla_to_rgba([0.0, 1.0, 0.5, 1.0]), [1.0, 0.0, 0.0, 1.0]));
+        assert!(close(hsla_to_rgba([120.0, 1.0, 0.5, 0.5]), [0.0, 1.0, 0.0, 0.5]));
+        assert!(close(hsla_to_rgba([240.0, 1.0, 0.5, 1.0]), [0.0, 0.0, 1.0, 1.0]));
+        assert!(close(hsla_to_rgba([0.0, 0.0, 1.0, 1.0]), [1.0, 1.0, 1.0, 1.0]));
+    }
+}
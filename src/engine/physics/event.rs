@@ -0,0 +1,344 @@
+use std::collections::HashMap;
+
+use nalgebra::Vector3;
+use rapier3d::prelude::{Collider, ColliderHandle, ContactForceEvent};
+
+/// The low bits of the collider user data used by the tag.
+const TAG_MASK: u128 = 0xff;
+
+/// The kind of the collider stored in the user data, to choose the sounds and so on.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ColliderTag {
+    Unknown,
+    Floor,
+    Wall,
+    Ceiling,
+    Player,
+    Prop,
+    /// The sensor firing the level events.
+    Trigger,
+}
+
+impl Default for ColliderTag {
+    fn default() -> Self {
+        ColliderTag::Unknown
+    }
+}
+
+#[allow(unused)]
+impl ColliderTag {
+    pub fn of(collider: &Collider) -> Self {
+        match collider.user_data & TAG_MASK {
+            1 => ColliderTag::Floor,
+            2 => ColliderTag::Wall,
+            3 => ColliderTag::Ceiling,
+            4 => ColliderTag::Player,
+            5 => ColliderTag::Prop,
+            6 => ColliderTag::Trigger,
+            _ => ColliderTag::Unknown,
+        }
+    }
+
+    /// The tag of the static plane facing `up`.
+    pub fn for_plane(up: &Vector3<f32>) -> Self {
+        if up.z > 0.5 {
+            ColliderTag::Floor
+        } else if up.z < -0.5 {
+            ColliderTag::Ceiling
+        } else {
+            ColliderTag::Wall
+        }
+    }
+
+    /// Replace the tag in the user data and keep the other bits.
+    pub fn set(self, collider: &mut Collider) {
+        collider.user_data = (collider.user_data & !TAG_MASK) | u128::from(self);
+    }
+}
+
+impl From<ColliderTag> for u128 {
+    fn from(tag: ColliderTag) -> Self {
+        match tag {
+            ColliderTag::Unknown => 0,
+            ColliderTag::Floor => 1,
+            ColliderTag::Wall => 2,
+            ColliderTag::Ceiling => 3,
+            ColliderTag::Player => 4,
+            ColliderTag::Prop => 5,
+            ColliderTag::Trigger => 6,
+        }
+    }
+}
+
+/// The contact force of the last physics step between the colliders.
+#[allow(unused)]
+#[derive(Debug, Copy, Clone)]
+pub struct ContactImpact {
+    pub collider1: ColliderHandle,
+    pub collider2: ColliderHandle,
+    pub tag1: ColliderTag,
+    pub tag2: ColliderTag,
+    /// The sum of the force magnitudes of all contact points.
+    pub total_force: f32,
+    pub max_force: f32,
+    pub max_force_direction: Vector3<f32>,
+}
+
+#[allow(unused)]
+impl ContactImpact {
+    pub fn new(e: &ContactForceEvent, tag1: ColliderTag, tag2: ColliderTag) -> Self {
+        Self {
+            collider1: e.collider1,
+            collider2: e.collider2,
+            tag1,
+            tag2,
+            total_force: e.total_force_magnitude,
+            max_force: e.max_force_magnitude,
+            max_force_direction: e.max_force_direction,
+        }
+    }
+
+    /// Get the other collider and its tag if the collider is involved.
+    pub fn other(&self, collider: ColliderHandle) -> Option<(ColliderHandle, ColliderTag)> {
+        if self.collider1 == collider {
+            Some((self.collider2, self.tag2))
+        } else if self.collider2 == collider {
+            Some((self.collider1, self.tag1))
+        } else {
+            None
+        }
+    }
+}
+
+/// The portals by the sensor colliders, (world, portal index)
+#[derive(Debug, Default)]
+pub struct PortalIndex {
+    portals: HashMap<ColliderHandle, (usize, usize)>,
+}
+
+#[allow(unused)]
+impl PortalIndex {
+    pub fn insert(&mut self, collider: ColliderHandle, portal: (usize, usize)) {
+        self.portals.insert(collider, portal);
+    }
+
+    pub fn remove(&mut self, collider: ColliderHandle) -> Option<(usize, usize)> {
+        self.portals.remove(&collider)
+    }
+
+    pub fn get(&self, collider: ColliderHandle) -> Option<(usize, usize)> {
+        self.portals.get(&collider).copied()
+    }
+
+    /// The sensor of the portal, few portals so searched.
+    pub fn sensor(&self, portal: (usize, usize)) -> Option<ColliderHandle> {
+        self.portals.iter().find(|x| *x.1 == portal).map(|x| *x.0)
+    }
+
+    /// Map the portals after some removed, forget the portals mapped to none.
+    pub fn remap(&mut self, f: impl Fn((usize, usize)) -> Option<(usize, usize)>) {
+        self.portals.retain(|_, x| match f(*x) {
+            Some(p) => {
+                *x = p;
+                true
+            }
+            None => false,
+        });
+    }
+
+    /// Get the portal of the collider pair if the other one is the traveler.
+    pub fn get_pair(&self, traveler: ColliderHandle, collider1: ColliderHandle, collider2: ColliderHandle) -> Option<(usize, usize)> {
+        if collider1 == traveler {
+            self.get(collider2)
+        } else if collider2 == traveler {
+            self.get(collider1)
+        } else {
+            None
+        }
+    }
+}
+
+/// The buffers to process the events of the steps, cleared but kept allocated.
+#[derive(Debug, Default)]
+pub struct EventScratch {
+    /// The contact impacts of the steps in the frame.
+    pub impacts: Vec<ContactImpact>,
+}
+
+#[allow(unused)]
+impl EventScratch {
+    pub fn clear(&mut self) {
+        self.impacts.clear();
+    }
+}
+
+/// The time in seconds not going through the portal pair again after gone through.
+pub const TRAVERSE_COOLDOWN: f32 = 0.25;
+
+/// The portals touched by the travelers and the cooldowns after going through, few so not hashed.
+#[derive(Debug, Default)]
+pub struct PortalCrossing {
+    /// (traveler, portal, stopped in the step)
+    touching: Vec<(ColliderHandle, (usize, usize), bool)>,
+    /// (traveler, portal, time left)
+    cooldowns: Vec<(ColliderHandle, (usize, usize), f32)>,
+}
+
+#[allow(unused)]
+impl PortalCrossing {
+    /// Record the traveler started or stopped touching the portal sensor.
+    ///
+    /// The portal stopped is still touching until [`Self::end_step`] to check the crossing in the step.
+    pub fn touch(&mut self, traveler: ColliderHandle, portal: (usize, usize), started: bool) {
+        match self.touching.iter_mut().find(|x| x.0 == traveler && x.1 == portal) {
+            Some(x) => x.2 = !started,
+            None if started => self.touching.push((traveler, portal, false)),
+            None => {}
+        }
+    }
+
+    /// Forget the portals stopped touching in the step.
+    pub fn end_step(&mut self) {
+        self.touching.retain(|x| !x.2);
+    }
+
+    /// The portals touched in the step by the traveler and not cooling down.
+    pub fn touching(&self, traveler: ColliderHandle) -> impl Iterator<Item=(usize, usize)> + '_ {
+        self.touching.iter()
+            .filter(move |x| x.0 == traveler && self.ready(traveler, x.1))
+            .map(|x| x.1)
+    }
+
+    pub fn ready(&self, traveler: ColliderHandle, portal: (usize, usize)) -> bool {
+        !self.cooldowns.iter().any(|x| x.0 == traveler && x.1 == portal)
+    }
+
+    pub fn tick(&mut self, dt: f32) {
+        for x in &mut self.cooldowns {
+            x.2 -= dt;
+        }
+        self.cooldowns.retain(|x| x.2 > 0.0);
+    }
+
+    /// The traveler went through the portal to the connecting one, cool down the both sides.
+    pub fn traversed(&mut self, traveler: ColliderHandle, portal: (usize, usize), connecting: (usize, usize)) {
+        self.touching.retain(|x| x.0 != traveler);
+        self.cooldowns.push((traveler, portal, TRAVERSE_COOLDOWN));
+        self.cooldowns.push((traveler, connecting, TRAVERSE_COOLDOWN));
+    }
+
+    /// Forget the traveler removed.
+    pub fn remove(&mut self, traveler: ColliderHandle) {
+        self.touching.retain(|x| x.0 != traveler);
+        self.cooldowns.retain(|x| x.0 != traveler);
+    }
+
+    /// Map the portals after some removed, forget the portals mapped to none.
+    pub fn remap(&mut self, f: impl Fn((usize, usize)) -> Option<(usize, usize)>) {
+        self.touching.retain_mut(|x| f(x.1).map(|p| x.1 = p).is_some());
+        self.cooldowns.retain_mut(|x| f(x.1).map(|p| x.1 = p).is_some());
+    }
+}
+
+/// The (world, portal index) after the portals removed from the worlds, none if removed.
+pub fn shifted_portal(removed: &[(usize, usize)], (world, idx): (usize, usize)) -> Option<(usize, usize)> {
+    if removed.contains(&(world, idx)) {
+        return None;
+    }
+    Some((world, idx - removed.iter().filter(|x| x.0 == world && x.1 < idx).count()))
+}
+
+/// The distance to the portal plane counted as crossed, the portals on the walls are not able to be passed.
+pub const CROSS_MARGIN: f32 = 0.05;
+
+/// Whether the point moved from the front of the plane into the margin or the back.
+///
+/// Must be out of the margin again to cross again.
+pub fn crossed_plane(before: &Vector3<f32>, now: &Vector3<f32>, pos: &Vector3<f32>, normal: &Vector3<f32>) -> bool {
+    (before - pos).dot(normal) > CROSS_MARGIN && (now - pos).dot(normal) <= CROSS_MARGIN
+}
+
+#[cfg(test)]
+mod test {
+    use rapier3d::prelude::ColliderHandle;
+
+    use nalgebra::{Vector3, vector};
+
+    use crate::engine::physics::event::{crossed_plane, PortalCrossing, PortalIndex, shifted_portal, TRAVERSE_COOLDOWN};
+
+    #[test]
+    fn test_portal_pair() {
+        let me = ColliderHandle::from_raw_parts(0, 0);
+        let portal = ColliderHandle::from_raw_parts(1, 0);
+        let wall = ColliderHandle::from_raw_parts(2, 0);
+        let mut index = PortalIndex::default();
+        index.insert(portal, (1, 2));
+        assert_eq!(index.get_pair(me, portal, me), Some((1, 2)));
+        assert_eq!(index.get_pair(me, me, wall), None);
+        // not the traveler
+        assert_eq!(index.get_pair(me, wall, portal), None);
+    }
+
+    #[test]
+    fn test_shifted_portal() {
+        let me = ColliderHandle::from_raw_parts(0, 0);
+        let removed = [(0, 1), (1, 0)];
+        assert_eq!(shifted_portal(&removed, (0, 0)), Some((0, 0)));
+        assert_eq!(shifted_portal(&removed, (0, 1)), None);
+        assert_eq!(shifted_portal(&removed, (0, 3)), Some((0, 2)));
+        assert_eq!(shifted_portal(&removed, (1, 2)), Some((1, 1)));
+        assert_eq!(shifted_portal(&removed, (2, 2)), Some((2, 2)));
+
+        let mut index = PortalIndex::default();
+        index.insert(ColliderHandle::from_raw_parts(1, 0), (0, 1));
+        index.insert(ColliderHandle::from_raw_parts(2, 0), (0, 2));
+        index.remap(|x| shifted_portal(&removed, x));
+        assert_eq!(index.get(ColliderHandle::from_raw_parts(1, 0)), None);
+        assert_eq!(index.get(ColliderHandle::from_raw_parts(2, 0)), Some((0, 1)));
+
+        let mut crossing = PortalCrossing::default();
+        crossing.touch(me, (0, 1), true);
+        crossing.touch(me, (1, 1), true);
+        crossing.remap(|x| shifted_portal(&removed, x));
+        assert_eq!(crossing.touching(me).collect::<Vec<_>>(), vec![(1, 0)]);
+    }
+
+    #[test]
+    fn test_crossing_cooldown() {
+        let me = ColliderHandle::from_raw_parts(0, 0);
+        let mut crossing = PortalCrossing::default();
+        crossing.touch(me, (0, 1), true);
+        assert_eq!(crossing.touching(me).collect::<Vec<_>>(), vec![(0, 1)]);
+        crossing.traversed(me, (0, 1), (1, 0));
+        assert_eq!(crossing.touching(me).count(), 0);
+
+        // grazing the connecting portal right after going through
+        crossing.touch(me, (1, 0), true);
+        assert_eq!(crossing.touching(me).count(), 0);
+        crossing.tick(TRAVERSE_COOLDOWN * 0.5);
+        assert!(!crossing.ready(me, (1, 0)));
+        crossing.tick(TRAVERSE_COOLDOWN);
+        assert_eq!(crossing.touching(me).collect::<Vec<_>>(), vec![(1, 0)]);
+        // crossed and stopped in the same step
+        crossing.touch(me, (1, 0), false);
+        assert_eq!(crossing.touching(me).count(), 1);
+        crossing.end_step();
+        assert_eq!(crossing.touching(me).count(), 0);
+    }
+
+    #[test]
+    fn test_crossed_plane() {
+        let (pos, normal) = (Vector3::zeros(), Vector3::x());
+        assert!(crossed_plane(&vector![0.1, 0.0, 0.0], &vector![-0.1, 0.0, 0.0], &pos, &normal));
+        assert!(crossed_plane(&vector![0.1, 0.0, 0.0], &vector![0.0, 0.0, 0.0], &pos, &normal));
+        // stopped by the wall
+        assert!(crossed_plane(&vector![0.1, 0.0, 0.0], &vector![0.02, 0.0, 0.0], &pos, &normal));
+        // in the margin after going through
+        assert!(!crossed_plane(&vector![0.02, 0.0, 0.0], &vector![0.0, 0.0, 0.0], &pos, &normal));
+        // from the back
+        assert!(!crossed_plane(&vector![-0.1, 0.0, 0.0], &vector![-0.2, 0.0, 0.0], &pos, &normal));
+        assert!(!crossed_plane(&vector![-0.1, 0.0, 0.0], &vector![0.1, 0.0, 0.0], &pos, &normal));
+        // along the plane
+        assert!(!crossed_plane(&vector![0.01, 0.0, 0.0], &vector![0.01, 1.0, 0.0], &pos, &normal));
+    }
+}
@@ -1,2 +1,8 @@
 pub mod state;
-pub mod obj;
\ No newline at end of file
+pub mod obj;
+pub mod debug;
+pub mod event;
+pub mod scheduler;
+pub mod query;
+pub mod capture;
+pub mod opening;
\ No newline at end of file
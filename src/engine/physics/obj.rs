@@ -1,14 +1,31 @@
+use std::f32::consts::PI;
+
 use nalgebra::{Vector, vector, Vector3};
 use num::Zero;
-use rapier3d::control::{CharacterLength, KinematicCharacterController};
-use rapier3d::prelude::{Collider, ColliderBuilder, ColliderHandle, RigidBody, RigidBodyHandle};
+use rapier3d::control::{CharacterAutostep, CharacterLength, KinematicCharacterController};
+use rapier3d::prelude::{ActiveCollisionTypes, Collider, ColliderBuilder, ColliderHandle, RigidBody, RigidBodyHandle};
 
 use crate::engine::physics::state::RapierData;
 
+/// The speed up when jumping.
+const JUMP_SPEED: f32 = 4.0;
+/// The gravity of the character, not the world gravity since the levels are without it for the props.
+pub const GRAVITY: f32 = -9.81;
+/// The half size of the bounding touching the portals at the scale 1, across the up axis.
+pub const BOUNDING_HALF: f32 = 0.125;
+
+/// The body moved by the character controller, with the gravity and jumping.
 pub struct KinematicObject {
     pub controller: KinematicCharacterController,
     pub handle: RigidBodyHandle,
+    /// The bounding touching the portals, thicker than the step to not pass through the sensors, scaled by the portals.
+    pub body_bounding: ColliderHandle,
     pub collider_handle: ColliderHandle,
+    /// The speed along the up axis.
+    pub vertical_speed: f32,
+    /// The acceleration along the up axis.
+    pub gravity: f32,
+    pub grounded: bool,
 }
 
 #[allow(unused)]
@@ -16,50 +33,91 @@ impl KinematicObject {
     pub fn new(p: &mut RapierData, r: RigidBody, c: Collider) -> Self {
         let controller = KinematicCharacterController {
             up: Vector::z_axis(),
-            offset: CharacterLength::Absolute(0.125),
+            offset: CharacterLength::Absolute(0.01),
+            autostep: Some(CharacterAutostep {
+                max_height: CharacterLength::Absolute(0.25),
+                min_width: CharacterLength::Absolute(0.1),
+                include_dynamic_bodies: false,
+            }),
+            max_slope_climb_angle: PI / 4.0,
+            min_slope_slide_angle: PI / 6.0,
+            snap_to_ground: Some(CharacterLength::Absolute(0.2)),
             ..Default::default()
         };
-        let handle = p.rigid_body_set.insert(r);
-        let collider_handle = p.collider_set.insert_with_parent(c, handle, &mut p.rigid_body_set);
-        Self { controller, collider_handle, handle }
-    }
-}
-
-
-pub struct Object {
-    pub handle: RigidBodyHandle,
-    pub body_bounding: ColliderHandle,
-    pub collider_handle: ColliderHandle,
-}
-
-#[allow(unused)]
-impl Object {
-    pub fn new(p: &mut RapierData, r: RigidBody, c: Collider) -> Self {
         let handle = p.rigid_body_set.insert(r);
         let body_bounding = p.collider_set
-            .insert_with_parent(ColliderBuilder::cuboid(0.125, 0.125, 1.0),
+            .insert_with_parent(ColliderBuilder::cuboid(BOUNDING_HALF, BOUNDING_HALF, 1.0)
+                                    .active_collision_types(ActiveCollisionTypes::default() | ActiveCollisionTypes::KINEMATIC_FIXED),
                                 handle, &mut p.rigid_body_set);
         let collider_handle = p.collider_set.insert_with_parent(c, handle, &mut p.rigid_body_set);
-        Self { collider_handle, handle, body_bounding }
+        Self { controller, collider_handle, handle, body_bounding, vertical_speed: 0.0, gravity: GRAVITY, grounded: false }
     }
 
-    pub fn calc_vel(&self, p: &mut RapierData, camera_mov: &Vector3<f32>, running: bool) {
-        let ddr = camera_mov.component_mul(&vector![1.0, 1.0, 0.0]);
-        let me = &mut p.rigid_body_set[self.handle];
-        if !ddr.is_zero() {
+    /// Move by the camera direction in the step, jump if moving up on the ground.
+    ///
+    /// The body is moved to the position got when the physics stepped.
+    pub fn move_by(&mut self, p: &mut RapierData, dt: f32, camera_mov: &Vector3<f32>, running: bool) {
+        let ddr = camera_mov.xy();
+        let walk = if !ddr.is_zero() {
             let speed = if running {
                 4.0
             } else {
                 2.0
             };
-            me.set_linvel((speed * ddr.normalize()) + vector![0.0, 0.0, 0.0], true);
+            speed * ddr.normalize()
         } else {
-            me.set_linvel(Vector3::zeros(), true);
+            ddr
+        };
+        if self.grounded {
+            self.vertical_speed = if camera_mov.z > 0.0 { JUMP_SPEED } else { 0.0 };
         }
-    }
+        self.vertical_speed += self.gravity * dt;
 
-    pub fn add_vel(&self, p: &mut RapierData, delta: &Vector3<f32>) {
+        let desired = vector![walk.x, walk.y, self.vertical_speed] * dt;
+        let ecm = p.move_obj(dt, self, desired);
+        self.grounded = ecm.grounded;
+        if self.vertical_speed > 0.0 && ecm.translation.z < desired.z * 0.5 {
+            // hit the ceiling
+            self.vertical_speed = 0.0;
+        }
         let me = &mut p.rigid_body_set[self.handle];
-        me.set_linvel(me.linvel() + delta, true);
+        let next = me.translation() + ecm.translation;
+        me.set_next_kinematic_translation(next);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use nalgebra::{vector, Vector3};
+    use rapier3d::prelude::{ActiveCollisionTypes, ColliderBuilder, RigidBodyBuilder};
+
+    use crate::engine::physics::obj::KinematicObject;
+    use crate::engine::physics::state::RapierData;
+
+    #[test]
+    fn test_fall_and_jump() {
+        let mut p = RapierData::new();
+        // the levels are without the world gravity
+        p.g = Vector3::zeros();
+        p.collider_set.insert(ColliderBuilder::cuboid(10.0, 10.0, 0.5).translation(vector![0.0, 0.0, -0.5]).build());
+        let me = RigidBodyBuilder::kinematic_position_based().translation(vector![0.0, 0.0, 2.0]).build();
+        let me_col = ColliderBuilder::cuboid(0.01, 0.01, 1.0)
+            .active_collision_types(ActiveCollisionTypes::default() | ActiveCollisionTypes::KINEMATIC_FIXED)
+            .build();
+        let mut me = KinematicObject::new(&mut p, me, me_col);
+        p.step(1.0 / 64.0);
+        for _ in 0..128 {
+            me.move_by(&mut p, 1.0 / 64.0, &Vector3::x(), false);
+            p.step(1.0 / 64.0);
+        }
+        let pos = *p.rigid_body_set[me.handle].translation();
+        assert!(me.grounded);
+        assert!((pos.z - 1.0).abs() < 0.05, "{:?}", pos);
+        assert!(pos.x > 3.0);
+
+        me.move_by(&mut p, 1.0 / 64.0, &Vector3::z(), false);
+        p.step(1.0 / 64.0);
+        assert!(!me.grounded);
+        assert!(p.rigid_body_set[me.handle].translation().z > pos.z);
     }
-}
\ No newline at end of file
+}
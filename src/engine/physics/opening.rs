@@ -0,0 +1,81 @@
+use nalgebra::{Point3, Vector3};
+use rapier3d::parry::bounding_volume::Aabb;
+use rapier3d::prelude::{ActiveHooks, ColliderHandle, ColliderSet, ContactModificationContext, PhysicsHooks};
+
+/// The distance out of the sensor still in the opening, the sensor is inset from the edge of the portal.
+const OPENING_MARGIN: f32 = 0.125;
+
+/// The walls behind the portal passed through in its opening.
+#[derive(Debug, Clone)]
+pub struct Opening {
+    /// The sensor of the portal, its cuboid across the plane is the opening.
+    pub sensor: ColliderHandle,
+    pub walls: Vec<ColliderHandle>,
+}
+
+impl Opening {
+    /// The half extents and the position of the opening, the axis across the plane is zero.
+    fn rect(&self, colliders: &ColliderSet) -> Option<(Vector3<f32>, Vector3<f32>)> {
+        let sensor = colliders.get(self.sensor)?;
+        let half = sensor.shape().as_cuboid()?.half_extents;
+        let across = half.imin();
+        let mut grown = half.add_scalar(OPENING_MARGIN);
+        grown[across] = 0.0;
+        Some((grown, *sensor.translation()))
+    }
+
+    /// Whether the point is in the opening along its plane.
+    pub fn contains_point(&self, colliders: &ColliderSet, point: &Point3<f32>) -> bool {
+        self.rect(colliders).is_some_and(|(half, pos)| {
+            let d = point.coords - pos;
+            (0..3).all(|i| half[i] == 0.0 || d[i].abs() <= half[i])
+        })
+    }
+
+    /// Whether the aabb is in the opening along its plane, so the walls are not touched.
+    pub fn contains_aabb(&self, colliders: &ColliderSet, aabb: &Aabb) -> bool {
+        aabb.vertices().iter().all(|x| self.contains_point(colliders, x))
+    }
+}
+
+/// The openings of the portals straddled now.
+///
+/// The walls behind them are kept, only the contacts in the openings are ignored.
+#[derive(Debug, Default)]
+pub struct Openings(pub Vec<Opening>);
+
+impl Openings {
+    /// Replace the openings, the walls behind them get the hook to filter the contacts.
+    pub fn set(&mut self, colliders: &mut ColliderSet, openings: Vec<Opening>) {
+        self.clear(colliders);
+        for x in openings.iter().flat_map(|x| &x.walls) {
+            if let Some(c) = colliders.get_mut(*x) {
+                c.set_active_hooks(ActiveHooks::MODIFY_SOLVER_CONTACTS);
+            }
+        }
+        self.0 = openings;
+    }
+
+    pub fn clear(&mut self, colliders: &mut ColliderSet) {
+        for x in self.0.drain(..).flat_map(|x| x.walls) {
+            if let Some(c) = colliders.get_mut(x) {
+                c.set_active_hooks(ActiveHooks::empty());
+            }
+        }
+    }
+
+    /// Whether the collider is a wall the aabb passes through in the opening.
+    pub fn passes(&self, colliders: &ColliderSet, wall: ColliderHandle, aabb: &Aabb) -> bool {
+        self.0.iter().any(|x| x.walls.contains(&wall) && x.contains_aabb(colliders, aabb))
+    }
+}
+
+impl PhysicsHooks for Openings {
+    /// Remove the contacts with the walls in the openings.
+    fn modify_solver_contacts(&self, context: &mut ContactModificationContext) {
+        let (c1, c2, colliders) = (context.collider1, context.collider2, context.colliders);
+        for x in self.0.iter().filter(|x| x.walls.contains(&c1) || x.walls.contains(&c2)) {
+            context.solver_contacts.retain(|c| !x.contains_point(colliders, &c.point));
+        }
+    }
+}
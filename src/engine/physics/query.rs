@@ -0,0 +1,100 @@
+use nalgebra::{Point3, Vector3};
+use rapier3d::parry::query::{RayIntersection, TOIStatus, TOI};
+use rapier3d::prelude::{Collider, ColliderHandle, Ray};
+
+use crate::engine::physics::event::ColliderTag;
+
+/// The closest collider hit by the ray.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RayHit {
+    pub collider: ColliderHandle,
+    pub tag: ColliderTag,
+    /// The distance along the ray in the ray direction length.
+    pub toi: f32,
+    pub point: Point3<f32>,
+    pub normal: Vector3<f32>,
+}
+
+impl RayHit {
+    pub fn new(ray: &Ray, collider: ColliderHandle, c: &Collider, x: &RayIntersection) -> Self {
+        Self {
+            collider,
+            tag: ColliderTag::of(c),
+            toi: x.toi,
+            point: ray.point_at(x.toi),
+            normal: x.normal,
+        }
+    }
+}
+
+/// The first collider hit by the shape moving along the direction.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ShapeHit {
+    pub collider: ColliderHandle,
+    pub tag: ColliderTag,
+    /// The distance moved in the direction length.
+    pub toi: f32,
+    /// The contact point on the collider hit in the world space.
+    pub point: Point3<f32>,
+    /// The normal of the collider hit in the world space.
+    pub normal: Vector3<f32>,
+    /// The shape is in the collider at the start, the point and the normal are undefined.
+    pub penetrating: bool,
+}
+
+impl ShapeHit {
+    /// The first shape of the toi is the colliders of the query pipeline in the world space.
+    pub fn new(collider: ColliderHandle, c: &Collider, x: &TOI) -> Self {
+        Self {
+            collider,
+            tag: ColliderTag::of(c),
+            toi: x.toi,
+            point: x.witness1,
+            normal: x.normal1.into_inner(),
+            penetrating: x.status == TOIStatus::Penetrating,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use nalgebra::{point, vector, Isometry3};
+    use rapier3d::prelude::{Aabb, Ball, ColliderBuilder, QueryFilter, Ray};
+
+    use crate::engine::physics::event::ColliderTag;
+    use crate::engine::physics::state::RapierData;
+
+    #[test]
+    fn test_queries() {
+        let mut p = RapierData::new();
+        let wall = p.collider_set.insert(ColliderBuilder::cuboid(0.1, 5.0, 5.0)
+            .translation(vector![3.0, 0.0, 0.0])
+            .user_data(ColliderTag::Wall.into())
+            .build());
+        let sensor = p.collider_set.insert(ColliderBuilder::cuboid(0.5, 0.5, 0.5)
+            .translation(vector![1.0, 0.0, 0.0])
+            .sensor(true)
+            .build());
+        p.query_pipeline.update(&p.rigid_body_set, &p.collider_set);
+
+        let ray = Ray::new(point![0.0, 0.0, 0.0], vector![1.0, 0.0, 0.0]);
+        let hit = p.raycast_closest(&ray, 10.0, QueryFilter::default().exclude_sensors()).unwrap();
+        assert_eq!(hit.collider, wall);
+        assert_eq!(hit.tag, ColliderTag::Wall);
+        assert!((hit.toi - 2.9).abs() < 1e-4);
+        assert!((hit.normal - vector![-1.0, 0.0, 0.0]).norm() < 1e-4);
+        assert_eq!(p.raycast_closest(&ray, 10.0, QueryFilter::default()).unwrap().collider, sensor);
+        assert!(p.raycast_closest(&ray, 2.0, QueryFilter::default().exclude_sensors()).is_none());
+
+        let hit = p.shapecast(&Ball::new(0.5), &Isometry3::identity(), &vector![1.0, 0.0, 0.0], 10.0,
+                              QueryFilter::default().exclude_sensors()).unwrap();
+        assert_eq!(hit.collider, wall);
+        assert!(!hit.penetrating);
+        assert!((hit.toi - 2.4).abs() < 1e-3);
+        assert!((hit.point.x - 2.9).abs() < 1e-3);
+        assert!((hit.normal - vector![-1.0, 0.0, 0.0]).norm() < 1e-4);
+
+        let overlaps = p.overlap_aabb(&Aabb::new(point![0.0, -1.0, -1.0], point![1.0, 1.0, 1.0]));
+        assert_eq!(overlaps, vec![sensor]);
+    }
+}
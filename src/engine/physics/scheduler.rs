@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+
+use nalgebra::Isometry3;
+use rapier3d::prelude::RigidBodyHandle;
+
+use crate::engine::physics::state::RapierData;
+
+/// The physics steps per second.
+pub const PHYSICS_HZ: f32 = 64.0;
+/// The max steps in a frame, the time beyond is dropped to catch up.
+const MAX_STEPS: u32 = 8;
+
+/// Step the physics in the fixed time and interpolate the poses between the last two steps for rendering.
+pub struct PhysicsScheduler {
+    pub step_dt: f32,
+    /// The time not stepped yet.
+    accumulator: f32,
+    /// The poses of the moving bodies before the last step.
+    previous: HashMap<RigidBodyHandle, Isometry3<f32>>,
+}
+
+impl Default for PhysicsScheduler {
+    fn default() -> Self {
+        Self::new(PHYSICS_HZ)
+    }
+}
+
+#[allow(unused)]
+impl PhysicsScheduler {
+    pub fn new(hz: f32) -> Self {
+        Self {
+            step_dt: 1.0 / hz,
+            accumulator: 0.0,
+            previous: Default::default(),
+        }
+    }
+
+    /// Add the frame time, return the steps to run.
+    pub fn advance(&mut self, dt: f32) -> u32 {
+        self.accumulator += dt.max(0.0);
+        let steps = (self.accumulator / self.step_dt) as u32;
+        self.accumulator -= steps as f32 * self.step_dt;
+        steps.min(MAX_STEPS)
+    }
+
+    /// Remember the poses before the step.
+    pub fn before_step(&mut self, p: &RapierData) {
+        self.previous.clear();
+        self.previous.extend(p.rigid_body_set.iter()
+            .filter(|(_, x)| !x.is_fixed())
+            .map(|(h, x)| (h, *x.position())));
+    }
+
+    /// Not interpolate the body from the pose before it moved, like going through the portal.
+    pub fn teleported(&mut self, p: &RapierData, handle: RigidBodyHandle) {
+        if let Some(x) = p.rigid_body_set.get(handle) {
+            self.previous.insert(handle, *x.position());
+        }
+    }
+
+    /// The time passed since the last step in the steps, in [0, 1)
+    pub fn alpha(&self) -> f32 {
+        (self.accumulator / self.step_dt).clamp(0.0, 1.0)
+    }
+
+    /// The pose between the last two steps by the time passed.
+    pub fn interpolate(&self, p: &RapierData, handle: RigidBodyHandle) -> Option<Isometry3<f32>> {
+        let current = p.rigid_body_set.get(handle)?.position();
+        Some(match self.previous.get(&handle) {
+            Some(previous) => previous.lerp_slerp(current, self.alpha()),
+            None => *current,
+        })
+    }
+
+    /// The poses of all moving bodies to render.
+    pub fn interpolated<'a>(&'a self, p: &'a RapierData) -> impl Iterator<Item=(RigidBodyHandle, Isometry3<f32>)> + 'a {
+        p.rigid_body_set.iter()
+            .filter(|(_, x)| !x.is_fixed())
+            .filter_map(|(h, _)| self.interpolate(p, h).map(|x| (h, x)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::engine::physics::scheduler::PhysicsScheduler;
+
+    #[test]
+    fn test_advance() {
+        let mut scheduler = PhysicsScheduler::new(50.0);
+        assert_eq!(scheduler.advance(0.01), 0);
+        assert!((scheduler.alpha() - 0.5).abs() < 1e-4);
+        assert_eq!(scheduler.advance(0.055), 3);
+        assert!((scheduler.alpha() - 0.25).abs() < 1e-3);
+        // the long frame is not caught up
+        assert_eq!(scheduler.advance(10.0), 8);
+        assert!(scheduler.alpha() < 1.0);
+    }
+}
@@ -2,9 +2,13 @@ use crossbeam::channel::{Receiver, unbounded};
 use log::trace;
 use nalgebra::Vector3;
 use rapier3d::control::EffectiveCharacterMovement;
+use rapier3d::parry::bounding_volume::BoundingVolume;
 use rapier3d::prelude::*;
 
+use crate::engine::physics::event::{ColliderTag, ContactImpact};
 use crate::engine::physics::obj::KinematicObject;
+use crate::engine::physics::opening::Openings;
+use crate::engine::physics::query::{RayHit, ShapeHit};
 
 pub struct RapierData {
     pub rigid_body_set: RigidBodySet,
@@ -19,6 +23,8 @@ pub struct RapierData {
     pub multibody_joint_set: MultibodyJointSet,
     pub ccd_solver: CCDSolver,
     pub g: Vector3<Real>,
+    /// The walls passed through in the openings of the portals, as the hooks of the step.
+    pub openings: Openings,
     pub col_events: Receiver<CollisionEvent>,
     pub contact_events: Receiver<ContactForceEvent>,
     collector: ChannelEventCollector,
@@ -54,18 +60,32 @@ impl RapierData {
             multibody_joint_set,
             ccd_solver,
             g: vector![0.0, 0.0, -9.81],
+            openings: Openings::default(),
             col_events,
             contact_events,
             collector,
         }
     }
 
+    /// Remove the body with its colliders.
+    pub fn remove_body(&mut self, handle: RigidBodyHandle) {
+        self.rigid_body_set.remove(handle, &mut self.island_manager, &mut self.collider_set,
+                                   &mut self.impulse_joint_set, &mut self.multibody_joint_set, true);
+    }
+
+    /// Remove the collider, not waking up its body.
+    pub fn remove_collider(&mut self, handle: ColliderHandle) {
+        self.collider_set.remove(handle, &mut self.island_manager, &mut self.rigid_body_set, false);
+    }
+
     pub fn step(&mut self, dt: Real) {
         self.integration_parameters.dt = dt;
         while let Ok(e) = self.col_events.try_recv() {
             trace!(target: "physics", "unused col event: {:?}", e);
         }
-        while let Ok(e) = self.contact_events.try_recv() {}
+        while let Ok(e) = self.contact_events.try_recv() {
+            trace!(target: "physics", "unused contact event: {:?}", e);
+        }
         self.physics_pipeline.step(&self.g, &self.integration_parameters,
                                    &mut self.island_manager,
                                    &mut self.broad_phase,
@@ -76,14 +96,91 @@ impl RapierData {
                                    &mut self.multibody_joint_set,
                                    &mut self.ccd_solver,
                                    Some(&mut self.query_pipeline),
-                                   &(),
+                                   &self.openings,
                                    &self.collector);
     }
 
+    /// Drain the contact force events of the last step with the tags of the colliders.
+    ///
+    /// Only the colliders with [`ActiveEvents::CONTACT_FORCE_EVENTS`] send the events.
+    pub fn contact_impacts(&self) -> Vec<ContactImpact> {
+        let mut impacts = vec![];
+        self.drain_contact_impacts(&mut impacts);
+        impacts
+    }
+
+    /// Append the contact impacts of the last step to the buffer.
+    pub fn drain_contact_impacts(&self, impacts: &mut Vec<ContactImpact>) {
+        let tag = |h| self.collider_set.get(h).map(ColliderTag::of).unwrap_or_default();
+        impacts.extend(self.contact_events.try_iter()
+            .map(|e| ContactImpact::new(&e, tag(e.collider1), tag(e.collider2))));
+    }
+
+    /// Get the (narrow phase pairs, touching pairs) of the last step.
+    pub fn pair_counts(&self) -> (usize, usize) {
+        let mut pairs = self.narrow_phase.intersection_pairs().count();
+        let mut active = 0;
+        for x in self.narrow_phase.contact_pairs() {
+            pairs += 1;
+            if x.has_any_active_contact {
+                active += 1;
+            }
+        }
+        (pairs, active)
+    }
+
+    /// Get the tag of the collider under the body within the distance, ignoring the sensors.
+    pub fn ground_tag(&self, body: RigidBodyHandle, distance: Real) -> Option<ColliderTag> {
+        let origin = *self.rigid_body_set.get(body)?.translation();
+        let ray = Ray::new(origin.into(), -Vector3::z());
+        let filter = QueryFilter::default().exclude_rigid_body(body).exclude_sensors();
+        self.raycast_closest(&ray, distance, filter).map(|x| x.tag)
+    }
+
+    /// Get the body of the collider under the body within the distance, none if the collider has no body.
+    pub fn ground_body(&self, body: RigidBodyHandle, distance: Real) -> Option<RigidBodyHandle> {
+        let origin = *self.rigid_body_set.get(body)?.translation();
+        let ray = Ray::new(origin.into(), -Vector3::z());
+        let filter = QueryFilter::default().exclude_rigid_body(body).exclude_sensors();
+        self.raycast_closest(&ray, distance, filter).and_then(|x| self.collider_set.get(x.collider)?.parent())
+    }
+
+    /// Get the closest collider hit by the ray within the max toi.
+    ///
+    /// The query pipeline is updated by the step, the colliders moved after the last step are not seen.
+    pub fn raycast_closest(&self, ray: &Ray, max_toi: Real, filter: QueryFilter) -> Option<RayHit> {
+        self.query_pipeline.cast_ray_and_get_normal(&self.rigid_body_set, &self.collider_set, ray, max_toi, true, filter)
+            .map(|(h, x)| RayHit::new(ray, h, &self.collider_set[h], &x))
+    }
+
+    /// Get the first collider hit by the shape at the pos moving along the dir within the max toi.
+    pub fn shapecast(&self, shape: &dyn Shape, pos: &Isometry<Real>, dir: &Vector<Real>, max_toi: Real, filter: QueryFilter) -> Option<ShapeHit> {
+        self.query_pipeline.cast_shape(&self.rigid_body_set, &self.collider_set, pos, dir, shape, max_toi, true, filter)
+            .map(|(h, x)| ShapeHit::new(h, &self.collider_set[h], &x))
+    }
+
+    /// Get the colliders whose bounds intersect the aabb.
+    pub fn overlap_aabb(&self, aabb: &Aabb) -> Vec<ColliderHandle> {
+        let mut result = vec![];
+        self.query_pipeline.colliders_with_aabb_intersecting_aabb(aabb, |h| {
+            if self.collider_set.get(*h).is_some_and(|x| x.compute_aabb().intersects(aabb)) {
+                result.push(*h);
+            }
+            true
+        });
+        result
+    }
+
+    /// Get the movement of the object to the target without going into the colliders, the sensors are passed through.
+    ///
+    /// The walls of the [`Openings`] are passed through if the whole movement is in the opening.
     pub fn move_obj(&mut self, dt: Real, obj: &KinematicObject, target: Vector<Real>) -> EffectiveCharacterMovement {
         let me = &self.rigid_body_set[obj.handle];
         let collider = &self.collider_set[obj.collider_handle];
-        let filter = QueryFilter::default().exclude_rigid_body(obj.handle);
+        let moved = Isometry::from_parts((me.translation() + target).into(), *me.rotation());
+        let swept = collider.shape().compute_aabb(me.position()).merged(&collider.shape().compute_aabb(&moved));
+        let passes = |h: ColliderHandle, _: &Collider| !self.openings.passes(&self.collider_set, h, &swept);
+        let filter = QueryFilter::default().exclude_rigid_body(obj.handle).exclude_sensors().predicate(&passes);
         let mut ecm = obj.controller.move_shape(dt,
                                                 &self.rigid_body_set,
                                                 &self.collider_set,
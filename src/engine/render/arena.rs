@@ -0,0 +1,280 @@
+//! Sub-allocate the vertex and index data of the levels from the large buffers.
+
+use std::ops::Range;
+use std::sync::{Arc, Weak};
+
+use log::debug;
+use wgpu::{Buffer, BufferAddress, BufferDescriptor, BufferSlice, BufferUsages, CommandEncoder, COPY_BUFFER_ALIGNMENT, Device, Queue};
+
+use crate::engine::WgpuData;
+
+/// The size of the buffer allocated in the arena, the data larger is in its own buffer.
+pub const ARENA_CHUNK_SIZE: BufferAddress = 4 << 20;
+/// The alignment of the ranges allocated, for the copies and the u32 indices.
+const ARENA_ALIGN: BufferAddress = 16;
+
+fn align(size: BufferAddress) -> BufferAddress {
+    size.next_multiple_of(ARENA_ALIGN)
+}
+
+/// The first fit allocator of the ranges in the buffer, the free ranges sorted and merged.
+#[derive(Debug, Clone)]
+pub struct RangeAllocator {
+    size: BufferAddress,
+    free: Vec<Range<BufferAddress>>,
+}
+
+#[allow(unused)]
+impl RangeAllocator {
+    pub fn new(size: BufferAddress) -> Self {
+        Self {
+            size,
+            free: std::iter::once(0..size).collect(),
+        }
+    }
+
+    /// Allocate the range of the size aligned, return the offset.
+    pub fn allocate(&mut self, size: BufferAddress) -> Option<BufferAddress> {
+        let size = align(size.max(1));
+        let idx = self.free.iter().position(|x| x.end - x.start >= size)?;
+        let offset = self.free[idx].start;
+        self.free[idx].start += size;
+        if self.free[idx].is_empty() {
+            self.free.remove(idx);
+        }
+        Some(offset)
+    }
+
+    /// Free the range allocated, merged with the free ranges next to it.
+    pub fn free(&mut self, range: Range<BufferAddress>) {
+        let range = range.start..(range.start + align(range.end - range.start)).min(self.size);
+        let idx = self.free.partition_point(|x| x.start < range.start);
+        self.free.insert(idx, range);
+        if idx + 1 < self.free.len() && self.free[idx].end == self.free[idx + 1].start {
+            self.free[idx].end = self.free.remove(idx + 1).end;
+        }
+        if idx > 0 && self.free[idx - 1].end == self.free[idx].start {
+            self.free[idx - 1].end = self.free.remove(idx).end;
+        }
+    }
+
+    pub fn size(&self) -> BufferAddress {
+        self.size
+    }
+
+    /// The bytes allocated.
+    pub fn used(&self) -> BufferAddress {
+        self.size - self.free.iter().map(|x| x.end - x.start).sum::<BufferAddress>()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.used() == 0
+    }
+
+    /// The count of the free ranges, more if fragmented.
+    pub fn fragments(&self) -> usize {
+        self.free.len()
+    }
+}
+
+/// The vertex or index data in the range of the buffer, its own buffer or allocated from the arena.
+///
+/// The range allocated is freed in the arena after dropped and the arena collected.
+#[derive(Debug)]
+pub struct BufferRange {
+    pub buffer: Arc<Buffer>,
+    pub offset: BufferAddress,
+    /// The size of the data.
+    pub size: BufferAddress,
+    /// Alive while the range is used, watched by the arena.
+    _alive: Option<Arc<()>>,
+}
+
+#[allow(unused)]
+impl BufferRange {
+    /// The whole buffer of the size.
+    pub fn whole(buffer: Buffer) -> Self {
+        Self {
+            size: buffer.size(),
+            buffer: Arc::new(buffer),
+            offset: 0,
+            _alive: None,
+        }
+    }
+
+    pub fn slice(&self) -> BufferSlice<'_> {
+        // the slices can not be empty
+        if self.size == 0 {
+            self.buffer.slice(self.offset..)
+        } else {
+            self.buffer.slice(self.offset..self.offset + self.size)
+        }
+    }
+
+    /// Write the data from the start of the range, the buffer should be `COPY_DST`.
+    pub fn write(&self, queue: &Queue, data: &[u8]) {
+        debug_assert!(data.len() as BufferAddress <= self.size);
+        queue.write_buffer(&self.buffer, self.offset, data);
+    }
+}
+
+#[derive(Debug)]
+struct Chunk {
+    buffer: Arc<Buffer>,
+    ranges: RangeAllocator,
+    /// The ranges allocated and the holders.
+    allocated: Vec<(Range<BufferAddress>, Weak<()>)>,
+}
+
+/// The large buffers sub-allocated for the data of the same usage.
+#[derive(Debug)]
+pub struct BufferArena {
+    label: &'static str,
+    usage: BufferUsages,
+    chunks: Vec<Chunk>,
+}
+
+#[allow(unused)]
+impl BufferArena {
+    /// The arena of the buffers of the usage, always able to be written.
+    pub fn new(label: &'static str, usage: BufferUsages) -> Self {
+        Self {
+            label,
+            usage: usage | BufferUsages::COPY_DST,
+            chunks: vec![],
+        }
+    }
+
+    /// The arena of the vertices and the indices.
+    pub fn geometry() -> Self {
+        Self::new("geometry arena", BufferUsages::VERTEX | BufferUsages::INDEX)
+    }
+
+    fn try_allocate(&mut self, size: BufferAddress) -> Option<(usize, BufferAddress)> {
+        self.chunks.iter_mut().enumerate()
+            .find_map(|(idx, x)| x.ranges.allocate(size).map(|offset| (idx, offset)))
+    }
+
+    /// Allocate the range of the size, in the new chunk if no chunk has the space after collected.
+    pub fn allocate(&mut self, device: &Device, size: BufferAddress) -> BufferRange {
+        let found = self.try_allocate(size).or_else(|| {
+            if self.collect() > 0 { self.try_allocate(size) } else { None }
+        });
+        let (idx, offset) = match found {
+            Some(x) => x,
+            None => {
+                let chunk_size = ARENA_CHUNK_SIZE.max(align(size));
+                debug!(target: "arena", "New {} chunk of {} bytes", self.label, chunk_size);
+                self.chunks.push(Chunk {
+                    buffer: Arc::new(device.create_buffer(&BufferDescriptor {
+                        label: Some(self.label),
+                        size: chunk_size,
+                        usage: self.usage,
+                        mapped_at_creation: false,
+                    })),
+                    ranges: RangeAllocator::new(chunk_size),
+                    allocated: vec![],
+                });
+                let idx = self.chunks.len() - 1;
+                let offset = self.chunks[idx].ranges.allocate(size).expect("the new chunk fits");
+                (idx, offset)
+            }
+        };
+        let alive = Arc::new(());
+        let chunk = &mut self.chunks[idx];
+        chunk.allocated.push((offset..offset + size, Arc::downgrade(&alive)));
+        BufferRange {
+            buffer: chunk.buffer.clone(),
+            offset,
+            size,
+            _alive: Some(alive),
+        }
+    }
+
+    /// Allocate the range and write the data, padded to the copy alignment.
+    pub fn upload(&mut self, gpu: &WgpuData, data: &[u8]) -> BufferRange {
+        let range = self.allocate(&gpu.device, data.len() as BufferAddress);
+        let padding = (COPY_BUFFER_ALIGNMENT - data.len() as BufferAddress % COPY_BUFFER_ALIGNMENT) % COPY_BUFFER_ALIGNMENT;
+        if padding == 0 {
+            range.write(&gpu.queue, data);
+        } else {
+            let mut padded = data.to_vec();
+            padded.resize(data.len() + padding as usize, 0);
+            gpu.queue.write_buffer(&range.buffer, range.offset, &padded);
+        }
+        range
+    }
+
+    /// Copy the range into the arena by the encoder, the buffer should be `COPY_SRC` and the size aligned.
+    ///
+    /// The range copied should be alive until the encoder submitted.
+    pub fn pack(&mut self, device: &Device, ce: &mut CommandEncoder, range: &BufferRange) -> BufferRange {
+        let packed = self.allocate(device, range.size);
+        if range.size > 0 {
+            ce.copy_buffer_to_buffer(&range.buffer, range.offset, &packed.buffer, packed.offset, range.size);
+        }
+        packed
+    }
+
+    /// Free the ranges of the data dropped, return the count freed.
+    pub fn collect(&mut self) -> usize {
+        let mut freed = 0;
+        for chunk in &mut self.chunks {
+            let ranges = &mut chunk.ranges;
+            chunk.allocated.retain(|(range, alive)| {
+                let dropped = alive.strong_count() == 0;
+                if dropped {
+                    ranges.free(range.clone());
+                    freed += 1;
+                }
+                !dropped
+            });
+        }
+        freed
+    }
+
+    /// Free the ranges of the data dropped and release the chunks left empty, called after the level unloaded.
+    pub fn defragment(&mut self) {
+        let freed = self.collect();
+        let before = self.chunks.len();
+        self.chunks.retain(|x| !x.allocated.is_empty());
+        debug!(target: "arena", "Freed {} ranges and {} chunks in the {}, {} bytes used in {} chunks",
+            freed, before - self.chunks.len(), self.label, self.used(), self.chunks.len());
+    }
+
+    /// The bytes allocated, including the ranges dropped but not collected.
+    pub fn used(&self) -> BufferAddress {
+        self.chunks.iter().map(|x| x.ranges.used()).sum()
+    }
+
+    pub fn chunks(&self) -> usize {
+        self.chunks.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::engine::render::arena::RangeAllocator;
+
+    #[test]
+    fn test_range_allocator() {
+        let mut ranges = RangeAllocator::new(256);
+        let a = ranges.allocate(10).unwrap();
+        let b = ranges.allocate(64).unwrap();
+        let c = ranges.allocate(100).unwrap();
+        assert_eq!((a, b, c), (0, 16, 80));
+        assert_eq!(ranges.used(), 16 + 64 + 112);
+        assert!(ranges.allocate(100).is_none());
+
+        ranges.free(b..b + 64);
+        assert_eq!(ranges.fragments(), 2);
+        // first fit in the hole
+        assert_eq!(ranges.allocate(32), Some(16));
+        ranges.free(16..48);
+        ranges.free(a..a + 10);
+        ranges.free(c..c + 100);
+        assert_eq!(ranges.fragments(), 1);
+        assert!(ranges.is_empty());
+        assert_eq!(ranges.allocate(256), Some(0));
+    }
+}
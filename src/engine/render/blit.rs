@@ -0,0 +1,151 @@
+use wgpu::{AddressMode, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+           BindGroupLayoutEntry, BindingResource, BindingType, Color, ColorTargetState, ColorWrites,
+           BindGroup, CommandEncoder, FilterMode, include_wgsl, LoadOp, Operations, PrimitiveState, PrimitiveTopology,
+           RenderPassColorAttachment, RenderPassDescriptor, RenderPipeline, Sampler, SamplerBindingType, SamplerDescriptor, ShaderStages, TextureSampleType, TextureView,
+           TextureViewDimension};
+
+use crate::engine::render_ext::CommandEncoderExt;
+use crate::engine::WgpuData;
+
+/// Draw a texture to the whole target with linear filter.
+///
+/// Used to scale the 3d scene to the screen.
+#[allow(unused)]
+#[derive(Debug)]
+pub struct BlitRenderer {
+    layout: BindGroupLayout,
+    sampler: Sampler,
+    render_pipeline: RenderPipeline,
+}
+
+#[allow(unused)]
+impl BlitRenderer {
+    pub fn new(state: &WgpuData) -> Self {
+        let texture_format = state.formats.color;
+        let device = &state.device;
+
+        let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("blit bind layout"),
+            entries: &[BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: true },
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            }, BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                count: None,
+            }],
+        });
+
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            ..Default::default()
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("blit pipeline layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+
+        let wgsl = include_wgsl!("blit.wgsl");
+        let shader = device.create_shader_module(wgsl);
+
+        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("blit pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(ColorTargetState {
+                    format: texture_format,
+                    blend: None,
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleList,
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: Default::default(),
+            multiview: None,
+        });
+
+        Self {
+            layout,
+            sampler,
+            render_pipeline,
+        }
+    }
+
+    /// The bind group to draw the `src`, kept by the caller if drawn every frame.
+    pub fn bind(&self, state: &WgpuData, src: &TextureView) -> BindGroup {
+        state.device.create_bind_group(&BindGroupDescriptor {
+            label: Some("blit bind group"),
+            layout: &self.layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(src),
+            }, BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::Sampler(&self.sampler),
+            }],
+        })
+    }
+
+    /// Draw the `src` to the whole `target`.
+    pub fn blit(&self, state: &WgpuData, encoder: &mut CommandEncoder, src: &TextureView, target: &TextureView) {
+        let bind_group = self.bind(state, src);
+        let mut rp = encoder.begin_clear_color(target, Color::BLACK, true);
+        rp.set_pipeline(&self.render_pipeline);
+        rp.set_bind_group(0, &bind_group, &[]);
+        rp.draw(0..3, 0..1);
+    }
+
+    /// Draw the `src` to the rect `[x, y, width, height]` in pixels of the `target`, the rest kept.
+    pub fn blit_viewport(&self, state: &WgpuData, encoder: &mut CommandEncoder, src: &TextureView, target: &TextureView, rect: [f32; 4]) {
+        let bind_group = self.bind(state, src);
+        self.blit_viewport_bound(encoder, &bind_group, target, rect);
+    }
+
+    /// Draw the source of the bind group from [`BlitRenderer::bind`] to the rect of the `target`.
+    pub fn blit_viewport_bound(&self, encoder: &mut CommandEncoder, bind_group: &BindGroup, target: &TextureView, rect: [f32; 4]) {
+        let mut rp = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("blit viewport pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: Operations { load: LoadOp::Load, store: true },
+            })],
+            depth_stencil_attachment: None,
+        });
+        rp.set_viewport(rect[0], rect[1], rect[2], rect[3], 0.0, 1.0);
+        rp.set_pipeline(&self.render_pipeline);
+        rp.set_bind_group(0, bind_group, &[]);
+        rp.draw(0..3, 0..1);
+    }
+
+    /// Draw the scaled scene to the screen.
+    ///
+    /// Do nothing if the scene is rendered to the screen directly.
+    pub fn blit_scene(&self, state: &WgpuData, encoder: &mut CommandEncoder) {
+        if let Some(scene) = state.views.get_scaled_scene() {
+            self.blit(state, encoder, &scene.view, &state.views.get_screen().view);
+        }
+    }
+}
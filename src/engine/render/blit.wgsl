@@ -0,0 +1,24 @@
+struct VertexOutput {
+    @location(0) uv: vec2<f32>,
+    @builtin(position) position: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var t_src: texture_2d<f32>;
+@group(0) @binding(1)
+var s_src: sampler;
+
+// one triangle covers the whole screen.
+@vertex
+fn vs_main(@builtin(vertex_index) idx: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let uv = vec2<f32>(f32((idx << 1u) & 2u), f32(idx & 2u));
+    out.uv = vec2<f32>(uv.x, 1.0 - uv.y);
+    out.position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(t_src, s_src, in.uv);
+}
@@ -1,8 +1,11 @@
+use std::collections::{BTreeMap, HashMap};
 use std::f32::consts::PI;
 
-use nalgebra::{Matrix4, SimdComplexField, vector, Vector3, Vector4};
+use nalgebra::{Matrix4, SimdComplexField, vector, Vector2, Vector3, Vector4};
 use winit::{dpi::PhysicalPosition, event::*};
 
+use crate::engine::config::InputSettings;
+
 const UP: Vector3<f32> = Vector3::<f32>::new(0.0, 0.0, 1.0);
 
 #[allow(unused)]
@@ -64,8 +67,58 @@ impl CameraUniform {
         self.view_proj = camera.build_view_projection_matrix();
     }
 }
+/// The action of the keys for [`CameraController`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum CameraAction {
+    Up,
+    Down,
+    RotateLeft,
+    RotateRight,
+    Forward,
+    Backward,
+    Left,
+    Right,
+}
+
+impl CameraAction {
+    pub const ALL: [CameraAction; 8] = [CameraAction::Up, CameraAction::Down, CameraAction::RotateLeft, CameraAction::RotateRight,
+        CameraAction::Forward, CameraAction::Backward, CameraAction::Left, CameraAction::Right];
+
+    /// The name in the config.
+    pub fn name(&self) -> &'static str {
+        match self {
+            CameraAction::Up => "up",
+            CameraAction::Down => "down",
+            CameraAction::RotateLeft => "rotate_left",
+            CameraAction::RotateRight => "rotate_right",
+            CameraAction::Forward => "forward",
+            CameraAction::Backward => "backward",
+            CameraAction::Left => "left",
+            CameraAction::Right => "right",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|x| x.name() == name)
+    }
+
+    fn default_keys(&self) -> &'static [VirtualKeyCode] {
+        match self {
+            CameraAction::Up => &[VirtualKeyCode::Space],
+            CameraAction::Down => &[VirtualKeyCode::LShift],
+            CameraAction::RotateLeft => &[VirtualKeyCode::Q],
+            CameraAction::RotateRight => &[VirtualKeyCode::E],
+            CameraAction::Forward => &[VirtualKeyCode::W, VirtualKeyCode::Up],
+            CameraAction::Backward => &[VirtualKeyCode::S, VirtualKeyCode::Down],
+            CameraAction::Left => &[VirtualKeyCode::A, VirtualKeyCode::Left],
+            CameraAction::Right => &[VirtualKeyCode::D, VirtualKeyCode::Right],
+        }
+    }
+}
+
 #[allow(unused)]
 pub struct CameraController {
+    bindings: HashMap<VirtualKeyCode, CameraAction>,
     // Keyboard input
     is_up_pressed: bool,
     is_modifier_shift_pressed: bool,
@@ -89,12 +142,38 @@ pub struct CameraController {
     pub roll: f32,
     pub pitch: f32,
     pub yaw: f32,
+
+    input: InputSettings,
+    /// The degrees of (yaw, pitch) moved by the mouse but not applied for the smoothing.
+    look: Vector2<f32>,
+    /// The analog moving of (right, forward) after the dead zone.
+    move_axis: Vector2<f32>,
+}
+
+/// The part of the look left to apply in the frame, the same after the frames whatever the frame time.
+fn smooth_factor(dt: f32, smoothing: f32) -> f32 {
+    if smoothing <= 0.0 {
+        1.0
+    } else {
+        1.0 - (-dt.max(0.0) / smoothing).exp()
+    }
+}
+
+/// Ignore the stick inside the dead zone and scale the rest to the full range.
+fn apply_dead_zone(axis: Vector2<f32>, dead_zone: f32) -> Vector2<f32> {
+    let len = axis.norm();
+    if len <= dead_zone || len <= f32::EPSILON {
+        return Vector2::zeros();
+    }
+    let scaled = ((len - dead_zone) / (1.0 - dead_zone).max(f32::EPSILON)).min(1.0);
+    axis / len * scaled
 }
 
 #[allow(unused)]
 impl CameraController {
     pub fn new() -> Self {
-        Self {
+        let mut this = Self {
+            bindings: Default::default(),
             is_up_pressed: false,
             is_modifier_shift_pressed: false,
             is_forward_pressed: false,
@@ -110,9 +189,71 @@ impl CameraController {
             roll: 0.0,
             pitch: 0.0,
             yaw: 0.0,
+            input: Default::default(),
+            look: Vector2::zeros(),
+            move_axis: Vector2::zeros(),
+        };
+        this.set_bindings(&Self::default_bindings());
+        this
+    }
+
+    /// The action names with the keys for the config.
+    pub fn default_bindings() -> BTreeMap<String, Vec<VirtualKeyCode>> {
+        CameraAction::ALL.into_iter()
+            .map(|x| (x.name().to_string(), x.default_keys().to_vec()))
+            .collect()
+    }
+
+    /// Replace the key bindings by the action names, the unknown actions are ignored.
+    pub fn set_bindings(&mut self, bindings: &BTreeMap<String, Vec<VirtualKeyCode>>) {
+        self.bindings.clear();
+        for (name, keys) in bindings {
+            if let Some(action) = CameraAction::from_name(name) {
+                for key in keys {
+                    self.bindings.insert(*key, action);
+                }
+            }
+        }
+    }
+
+    /// Use the sensitivity, the smoothing and the dead zone of the settings.
+    pub fn set_input(&mut self, input: &InputSettings) {
+        if &self.input != input {
+            self.input = input.clone();
         }
     }
 
+    /// Move by the stick of (right, forward), the dead zone is ignored.
+    pub fn set_move_axis(&mut self, axis: Vector2<f32>) {
+        self.move_axis = apply_dead_zone(axis, self.input.dead_zone);
+    }
+
+    /// Look by the move on the screen in the screen size, like the mouse moved.
+    pub fn look_by(&mut self, dx: f32, dy: f32) {
+        let y = if self.input.invert_y { -1.0 } else { 1.0 };
+        self.look += vector![dx, dy * y] * 180.0 * self.input.mouse_sensitivity;
+    }
+
+    /// Release the keys held, the key up events are not got while not controlling.
+    pub fn release_keys(&mut self) {
+        self.is_up_pressed = false;
+        self.is_modifier_shift_pressed = false;
+        self.is_forward_pressed = false;
+        self.is_backward_pressed = false;
+        self.is_left_pressed = false;
+        self.is_right_pressed = false;
+        self.is_rotate_left_pressed = false;
+        self.is_rotate_right_pressed = false;
+        self.mouse_diff_position = PhysicalPosition { x: 0.0, y: 0.0 };
+        self.look = Vector2::zeros();
+        self.move_axis = Vector2::zeros();
+    }
+
+    /// Whether the key of the down action is pressing, also used to run.
+    pub fn is_down_pressed(&self) -> bool {
+        self.is_modifier_shift_pressed
+    }
+
     /// Handle keyboard input for camera (like moving camera with WASD keys)
     pub fn process_events(
         &mut self,
@@ -120,41 +261,22 @@ impl CameraController {
         &virtual_keycode: &VirtualKeyCode,
     ) -> bool {
         let is_pressed = *state == ElementState::Pressed;
-        match virtual_keycode {
-            VirtualKeyCode::Space => {
-                self.is_up_pressed = is_pressed;
-                true
-            }
-            VirtualKeyCode::LShift => {
-                self.is_modifier_shift_pressed = is_pressed;
-                true
-            }
-            VirtualKeyCode::Q => {
-                self.is_rotate_left_pressed = is_pressed;
-                true
-            }
-            VirtualKeyCode::E => {
-                self.is_rotate_right_pressed = is_pressed;
-                true
-            }
-            VirtualKeyCode::W | VirtualKeyCode::Up => {
-                self.is_forward_pressed = is_pressed;
-                true
-            }
-            VirtualKeyCode::A | VirtualKeyCode::Left => {
-                self.is_left_pressed = is_pressed;
-                true
-            }
-            VirtualKeyCode::S | VirtualKeyCode::Down => {
-                self.is_backward_pressed = is_pressed;
-                true
-            }
-            VirtualKeyCode::D | VirtualKeyCode::Right => {
-                self.is_right_pressed = is_pressed;
-                true
-            }
-            _ => false,
-        }
+        let action = match self.bindings.get(&virtual_keycode) {
+            Some(action) => *action,
+            None => return false,
+        };
+        let pressed = match action {
+            CameraAction::Up => &mut self.is_up_pressed,
+            CameraAction::Down => &mut self.is_modifier_shift_pressed,
+            CameraAction::RotateLeft => &mut self.is_rotate_left_pressed,
+            CameraAction::RotateRight => &mut self.is_rotate_right_pressed,
+            CameraAction::Forward => &mut self.is_forward_pressed,
+            CameraAction::Backward => &mut self.is_backward_pressed,
+            CameraAction::Left => &mut self.is_left_pressed,
+            CameraAction::Right => &mut self.is_right_pressed,
+        };
+        *pressed = is_pressed;
+        true
     }
 
     /// Handle mouse input for camera (like moving camera based on mouse position)
@@ -209,8 +331,8 @@ impl CameraController {
         }
     }
 
-    /// Update camera angles and return the pos delta unit
-    pub fn update_direction(&mut self, camera: &mut Camera) -> Vector3<f32> {
+    /// Update camera angles by the frame time and return the pos delta unit
+    pub fn update_direction(&mut self, camera: &mut Camera, dt: f32) -> Vector3<f32> {
         let plane_view = camera.target.xy().normalize();
         self.yaw = plane_view.x.acos() * 180.0 / PI;
         if plane_view.y < 0.0 {
@@ -239,6 +361,7 @@ impl CameraController {
         if self.is_left_pressed {
             eye_delta += right;
         }
+        eye_delta += forward * self.move_axis.y - right * self.move_axis.x;
 
         if self.is_modifier_shift_pressed {
             eye_delta -= UP;
@@ -252,13 +375,16 @@ impl CameraController {
         // Mouse input
         if self.is_mouse_right_tracked {
             if self.mouse_diff_position.x.is_finite() && self.mouse_diff_position.y.is_finite() {
-                self.yaw -= self.mouse_diff_position.x * 180.0;
-                self.yaw %= 360.0;
-                self.pitch -= self.mouse_diff_position.y * 180.0;
-                self.pitch = self.pitch.clamp(-90.0 + 1.0, 90.0 - 1.0);
+                self.look_by(self.mouse_diff_position.x, self.mouse_diff_position.y);
             }
             self.mouse_diff_position = Default::default();
         }
+        let look = self.look * smooth_factor(dt, self.input.look_smoothing);
+        self.look -= look;
+        self.yaw -= look.x;
+        self.yaw %= 360.0;
+        self.pitch -= look.y;
+        self.pitch = self.pitch.clamp(-90.0 + 1.0, 90.0 - 1.0);
         camera.target = camera.calc_target(self.yaw, self.pitch);
         eye_delta
     }
@@ -268,7 +394,7 @@ impl CameraController {
 mod test {
     use nalgebra::{point, vector};
 
-    use crate::engine::render::camera::{Camera, UP};
+    use crate::engine::render::camera::{apply_dead_zone, Camera, smooth_factor, UP};
 
     #[test]
     fn test_coord() {
@@ -276,4 +402,19 @@ mod test {
         let camera = Camera::new(point![0.0, 0.0, 0.0]);
         assert_eq!(camera.calc_target(0.0, 0.0), vector![1.0, 0.0, 0.0]);
     }
+
+    #[test]
+    fn test_look_input() {
+        assert_eq!(smooth_factor(0.016, 0.0), 1.0);
+        // two half frames leave the same as one frame
+        let half = 1.0 - smooth_factor(0.01, 0.05);
+        let full = 1.0 - smooth_factor(0.02, 0.05);
+        assert!((half * half - full).abs() < 1e-6);
+
+        assert_eq!(apply_dead_zone(vector![0.1, 0.0], 0.15), vector![0.0, 0.0]);
+        assert!((apply_dead_zone(vector![0.0, 1.0], 0.15) - vector![0.0, 1.0]).norm() < 1e-6);
+        assert!((apply_dead_zone(vector![0.0, 0.575], 0.15).y - 0.5).abs() < 1e-6);
+        // not over the full range outside the unit circle
+        assert!((apply_dead_zone(vector![2.0, 0.0], 0.15).x - 1.0).abs() < 1e-6);
+    }
 }
\ No newline at end of file
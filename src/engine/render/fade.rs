@@ -0,0 +1,114 @@
+use wgpu::{BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor, BindGroupLayoutEntry,
+           BindingType, BlendState, Buffer, BufferBindingType, BufferDescriptor, BufferUsages, ColorTargetState,
+           ColorWrites, CommandEncoder, include_wgsl, LoadOp, Operations, PrimitiveState, PrimitiveTopology,
+           RenderPassColorAttachment, RenderPassDescriptor, RenderPipeline, ShaderStages, TextureView};
+
+use crate::engine::WgpuData;
+
+/// Draw the color blended over the whole target, for fading between the states.
+#[allow(unused)]
+#[derive(Debug)]
+pub struct FadeRenderer {
+    buffer: Buffer,
+    bind_group: BindGroup,
+    render_pipeline: RenderPipeline,
+}
+
+#[allow(unused)]
+impl FadeRenderer {
+    pub fn new(state: &WgpuData) -> Self {
+        let texture_format = state.formats.color;
+        let device = &state.device;
+
+        let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("fade bind layout"),
+            entries: &[BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+
+        let buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("fade color"),
+            size: std::mem::size_of::<[f32; 4]>() as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("fade bind group"),
+            layout: &layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("fade pipeline layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+
+        let wgsl = include_wgsl!("fade.wgsl");
+        let shader = device.create_shader_module(wgsl);
+
+        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("fade pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(ColorTargetState {
+                    format: texture_format,
+                    blend: Some(BlendState::ALPHA_BLENDING),
+                    write_mask: ColorWrites::COLOR,
+                })],
+            }),
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleList,
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: Default::default(),
+            multiview: None,
+        });
+
+        Self {
+            buffer,
+            bind_group,
+            render_pipeline,
+        }
+    }
+
+    /// Blend the rgba `color` over the `target`, nothing drawn if transparent.
+    pub fn render(&self, state: &WgpuData, encoder: &mut CommandEncoder, target: &TextureView, color: [f32; 4]) {
+        if color[3] <= 0.0 {
+            return;
+        }
+        state.queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&color));
+        let mut rp = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("fade pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: Operations { load: LoadOp::Load, store: true },
+            })],
+            depth_stencil_attachment: None,
+        });
+        rp.set_pipeline(&self.render_pipeline);
+        rp.set_bind_group(0, &self.bind_group, &[]);
+        rp.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,18 @@
+struct Fade {
+    color: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> fade: Fade;
+
+// one triangle covers the whole screen.
+@vertex
+fn vs_main(@builtin(vertex_index) idx: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((idx << 1u) & 2u), f32(idx & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+    return fade.color;
+}
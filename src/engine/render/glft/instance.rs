@@ -80,11 +80,13 @@ fn rotation_to_matrix3(rotation: &nalgebra::Quaternion<f32>) -> nalgebra::Matrix
 
 #[allow(unused)]
 impl GltfInstance {
+    pub fn to_matrix(&self) -> nalgebra::Matrix4<f32> {
+        nalgebra::Matrix4::new_translation(&self.position) * rotation_to_matrix4(&self.rotation)
+    }
+
     pub fn to_raw(&self) -> InstanceRaw {
-        let model =
-            nalgebra::Matrix4::new_translation(&self.position) * rotation_to_matrix4(&self.rotation);
         InstanceRaw {
-            model: model.into(),
+            model: self.to_matrix().into(),
             normal: rotation_to_matrix3(&self.rotation).into(),
         }
     }
@@ -98,6 +100,19 @@ pub struct InstanceRaw {
     normal: [[f32; 3]; 3],
 }
 
+#[allow(unused)]
+impl InstanceRaw {
+    /// The instance with the model matrix, the normal matrix is the inverse transpose of it.
+    pub fn from_matrix(model: nalgebra::Matrix4<f32>) -> Self {
+        let linear = model.fixed_view::<3, 3>(0, 0).into_owned();
+        let normal = linear.try_inverse().map(|x| x.transpose()).unwrap_or(linear);
+        Self {
+            model: model.into(),
+            normal: normal.into(),
+        }
+    }
+}
+
 impl Vertex for InstanceRaw {
     fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
         use std::mem;
@@ -1,13 +1,15 @@
-use std::ops::Range;
-
 use anyhow::anyhow;
 use gltf::{Gltf, Node};
 use gltf::buffer::Source;
 use log::trace;
-use nalgebra::vector;
-use wgpu::util::{DeviceExt, RenderEncoder};
-
-use crate::engine::{TextureWrapper, WgpuData};
+use nalgebra::{Matrix4, Point3, Vector3, vector};
+use rapier3d::parry::bounding_volume::Aabb;
+use wgpu::{CommandEncoderDescriptor, Device, Queue};
+use wgpu::util::DeviceExt;
+
+use crate::engine::{ColorSpace, ResourceManager, TextureWrapper, WgpuData};
+use crate::engine::glft::instance::{GltfInstance, InstanceRaw};
+use crate::engine::render::arena::{BufferArena, BufferRange};
 use crate::engine::Vertex;
 
 #[repr(C)]
@@ -16,65 +18,241 @@ pub struct ModelVertex {
     pub position: [f32; 3],
     pub tex_coords: [f32; 2],
     pub normal: [f32; 3],
+    /// The tangent toward the increasing u, w is the sign of the bitangent as glTF.
+    pub tangent: [f32; 4],
+}
+
+/// The factors of the metallic-roughness material multiplied to the textures, in the uniform layout.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MaterialFactors {
+    pub base_color: [f32; 4],
+    pub emissive: [f32; 3],
+    pub metallic: f32,
+    pub roughness: f32,
+    /// The scale of the normal texture in x and y.
+    pub normal_scale: f32,
+    pub _pad: [f32; 2],
+}
+
+/// The defaults of glTF.
+impl Default for MaterialFactors {
+    fn default() -> Self {
+        Self {
+            base_color: [1.0; 4],
+            emissive: [0.0; 3],
+            metallic: 1.0,
+            roughness: 1.0,
+            normal_scale: 1.0,
+            _pad: [0.0; 2],
+        }
+    }
 }
 
 pub struct Material {
     pub name: String,
     pub diffuse_texture: Option<TextureWrapper>,
+    pub normal_texture: Option<TextureWrapper>,
+    /// The roughness in g and the metallic in b.
+    pub metallic_roughness_texture: Option<TextureWrapper>,
+    pub emissive_texture: Option<TextureWrapper>,
+    pub factors: MaterialFactors,
 }
 
 pub struct Mesh {
     pub name: String,
-    pub vertex_buffer: wgpu::Buffer,
-    pub index_buffer: wgpu::Buffer,
+    pub vertex_buffer: BufferRange,
+    pub index_buffer: BufferRange,
     pub num_elements: u32,
     pub material: usize,
+    /// The index of the node in [`Model::nodes`], the vertices are in the node space.
+    pub node: usize,
+}
+
+/// The node of the glTF scene graph.
+#[derive(Debug, Clone)]
+pub struct ModelNode {
+    pub name: Option<String>,
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+    /// The transform relative to the parent.
+    pub local: Matrix4<f32>,
+    /// The transform relative to the model, updated by [`Model::set_local_transform`]
+    pub world: Matrix4<f32>,
 }
 
 #[allow(unused)]
 pub struct Model {
     pub meshes: Vec<Mesh>,
     pub materials: Vec<Material>,
+    /// The bounds of all vertices in model space at the load time, None if no vertex.
+    pub aabb: Option<Aabb>,
+    /// The nodes of all scenes, the parent is always before the children.
+    pub nodes: Vec<ModelNode>,
+    /// Increased when any node transform changed.
+    transform_version: u64,
+}
+
+/// Read the data uri or the file by `load_file` with the decoded uri.
+fn read_uri(uri: &str, load_file: &impl Fn(&str) -> anyhow::Result<Vec<u8>>) -> anyhow::Result<Vec<u8>> {
+    if let Some(data) = uri.strip_prefix("data:") {
+        let (_, data) = data.split_once(";base64,").ok_or(anyhow!("Only base64 data uri is supported"))?;
+        Ok(base64::decode(data)?)
+    } else {
+        load_file(&urlencoding::decode(uri)?)
+    }
+}
+
+/// Load the image of the texture in the buffers or by the uri.
+///
+/// The base color and the emissive are sRGB in glTF, the others are linear.
+fn load_texture(device: &Device, queue: &Queue, texture: gltf::Texture, buffer_data: &[Vec<u8>],
+                load_file: &impl Fn(&str) -> anyhow::Result<Vec<u8>>, label: Option<&str>, space: ColorSpace) -> anyhow::Result<TextureWrapper> {
+    match texture.source().source() {
+        gltf::image::Source::View { view, mime_type: mt } => {
+            trace!(target: "gltf_load", "Loading texture for type: {mt}");
+            TextureWrapper::from_bytes(
+                device, queue,
+                &buffer_data[view.buffer().index()][view.offset()..view.offset() + view.length()],
+                label, false, space)
+        }
+        gltf::image::Source::Uri { uri, mime_type: _ } => {
+            trace!(target: "gltf_load", "Loading texture from {}", uri.get(..64).unwrap_or(uri));
+            TextureWrapper::from_bytes(device, queue, &read_uri(uri, load_file)?, label, false, space)
+        }
+    }
+}
+
+/// Generate the tangents of the triangles by the texture coordinates, averaged for the shared vertices.
+///
+/// The sign is set for the bitangent toward the decreasing v, the up of the normal textures in glTF.
+pub fn generate_tangents(vertices: &mut [ModelVertex], indices: &[u32]) {
+    let mut tangents = vec![Vector3::<f32>::zeros(); vertices.len()];
+    let mut bitangents = vec![Vector3::<f32>::zeros(); vertices.len()];
+    for tri in indices.chunks_exact(3) {
+        let [a, b, c] = [tri[0], tri[1], tri[2]].map(|x| x as usize);
+        if a.max(b).max(c) >= vertices.len() {
+            continue;
+        }
+        let pos = |i: usize| Vector3::from(vertices[i].position);
+        let (e1, e2) = (pos(b) - pos(a), pos(c) - pos(a));
+        let [u0, v0] = vertices[a].tex_coords;
+        let [u1, v1] = vertices[b].tex_coords;
+        let [u2, v2] = vertices[c].tex_coords;
+        let (du1, dv1, du2, dv2) = (u1 - u0, v1 - v0, u2 - u0, v2 - v0);
+        let det = du1 * dv2 - du2 * dv1;
+        if det.abs() < 1e-12 {
+            continue;
+        }
+        let t = (e1 * dv2 - e2 * dv1) / det;
+        let bt = (e2 * du1 - e1 * du2) / det;
+        for i in [a, b, c] {
+            tangents[i] += t;
+            bitangents[i] += bt;
+        }
+    }
+    for (i, x) in vertices.iter_mut().enumerate() {
+        let n = Vector3::from(x.normal);
+        // orthogonal to the normal, any if no texture coordinates
+        let t = (tangents[i] - n * n.dot(&tangents[i])).try_normalize(1e-6)
+            .or_else(|| n.cross(&Vector3::x()).try_normalize(1e-6))
+            .or_else(|| n.cross(&Vector3::y()).try_normalize(1e-6))
+            .unwrap_or_else(Vector3::x);
+        let w = if n.cross(&t).dot(&bitangents[i]) > 0.0 { -1.0 } else { 1.0 };
+        x.tangent = [t.x, t.y, t.z, w];
+    }
+}
+
+/// Get the asset path of the `uri` relative to the asset `base`.
+fn relative_asset_path(base: &str, uri: &str) -> String {
+    match base.rfind('/') {
+        Some(idx) => format!("{}/{}", &base[..idx], uri),
+        None => uri.to_string(),
+    }
 }
 
 #[allow(unused)]
 impl Model {
-    pub fn load(wgpu: &WgpuData, mut gltf: Gltf, label: Option<&str>) -> anyhow::Result<Self> {
-        let mut buffer_data = Vec::new();
-        if let Some(blob) = gltf.blob.take() {
-            buffer_data.push(blob);
+    /// Copy the vertices and the indices of the meshes into the arena, the buffers loaded are released after.
+    pub fn pack(&mut self, gpu: &WgpuData, arena: &mut BufferArena) {
+        let mut ce = gpu.device.create_command_encoder(&CommandEncoderDescriptor { label: Some("pack model") });
+        let mut loaded = vec![];
+        for mesh in &mut self.meshes {
+            let vertices = arena.pack(&gpu.device, &mut ce, &mesh.vertex_buffer);
+            let indices = arena.pack(&gpu.device, &mut ce, &mesh.index_buffer);
+            loaded.push(std::mem::replace(&mut mesh.vertex_buffer, vertices));
+            loaded.push(std::mem::replace(&mut mesh.index_buffer, indices));
         }
+        gpu.queue.submit(Some(ce.finish()));
+        trace!("Packed {} buffers of the model", loaded.len());
+    }
+
+    /// Load the model, only data uri is supported for external buffers and images.
+    pub fn load(device: &Device, queue: &Queue, gltf: Gltf, label: Option<&str>) -> anyhow::Result<Self> {
+        Self::load_with(device, queue, gltf, label, |uri| Err(anyhow!("Cannot load {} without the model path", uri)))
+    }
+
+    /// Load the .gltf or .glb model in the resource packs, the uri is relative to the model path.
+    pub fn load_from_res(device: &Device, queue: &Queue, res: &ResourceManager, path: &str, label: Option<&str>) -> anyhow::Result<Self> {
+        let gltf = Gltf::from_slice(&res.load_asset(path)?)?;
+        Self::load_with(device, queue, gltf, label, |uri| res.load_asset(&relative_asset_path(path, uri)))
+    }
+
+    /// Load the model with the external files loaded by `load_file`.
+    pub fn load_with(device: &Device, queue: &Queue, mut gltf: Gltf, label: Option<&str>,
+                     load_file: impl Fn(&str) -> anyhow::Result<Vec<u8>>) -> anyhow::Result<Self> {
+        let mut blob = gltf.blob.take();
+        let mut buffer_data = Vec::new();
         for buffer in gltf.buffers() {
-            match buffer.source() {
-                Source::Uri(_) => {
-                    return Err(anyhow!("This model has uri source but not impl yet!"));
-                }
-                _ => {}
-            }
+            let data = match buffer.source() {
+                Source::Bin => blob.take().ok_or(anyhow!("This model has no binary blob"))?,
+                Source::Uri(uri) => read_uri(uri, &load_file)?,
+            };
+            buffer_data.push(data);
         }
 
         let mut meshes = Vec::new();
         let mut materials = Vec::new();
+        let mut aabb = None;
+        let mut nodes = Vec::new();
 
         struct NodeData<'a> {
             buffer_data: &'a [Vec<u8>],
-            wgpu: &'a WgpuData,
+            device: &'a Device,
             meshes: &'a mut Vec<Mesh>,
             materials: &'a mut Vec<Material>,
+            aabb: &'a mut Option<Aabb>,
+            nodes: &'a mut Vec<ModelNode>,
         }
 
         impl NodeData<'_> {
-            fn load_node(&mut self, node: Node) {
+            fn load_node(&mut self, node: Node, parent: Option<usize>) {
                 log::trace!(target: "gltf_load", "Node {}", node.index());
+                let local = Matrix4::from(node.transform().matrix());
+                let world = match parent {
+                    Some(parent) => self.nodes[parent].world * local,
+                    None => local,
+                };
+                let node_idx = self.nodes.len();
+                self.nodes.push(ModelNode {
+                    name: node.name().map(ToString::to_string),
+                    parent,
+                    children: vec![],
+                    local,
+                    world,
+                });
+                if let Some(parent) = parent {
+                    self.nodes[parent].children.push(node_idx);
+                }
                 for x in node.children() {
-                    self.load_node(x);
+                    self.load_node(x, Some(node_idx));
                 }
                 let buffer_data = &self.buffer_data;
-                let wgpu = &self.wgpu;
+                let device = self.device;
                 let meshes = &mut self.meshes;
                 let materials = &mut self.materials;
+                let aabb = &mut *self.aabb;
 
-                let trans = nalgebra::Matrix4::from(node.transform().matrix());
                 if let Some(mesh) = node.mesh() {
                     let primitives = mesh.primitives();
                     for primitive in primitives {
@@ -85,11 +263,17 @@ impl Model {
                         let mut vertices = Vec::new();
                         if let Some(vertex_attribute) = reader.read_positions() {
                             vertex_attribute.for_each(|vertex| {
-                                let position = trans * vector![vertex[0], vertex[1], vertex[2], 1.0];
+                                let position = world * vector![vertex[0], vertex[1], vertex[2], 1.0];
+                                let point = Point3::from(position.xyz());
+                                match aabb.as_mut() {
+                                    Some(aabb) => aabb.take_point(point),
+                                    None => *aabb = Some(Aabb::new(point, point)),
+                                }
                                 vertices.push(ModelVertex {
-                                    position: position.xyz().into(),
+                                    position: vertex,
                                     tex_coords: Default::default(),
                                     normal: Default::default(),
+                                    tangent: Default::default(),
                                 })
                             });
                         }
@@ -114,24 +298,33 @@ impl Model {
                         if let Some(indices_raw) = reader.read_indices() {
                             indices.append(&mut indices_raw.into_u32().collect::<Vec<u32>>());
                         }
+                        match reader.read_tangents() {
+                            Some(tangents) => {
+                                for (x, tangent) in vertices.iter_mut().zip(tangents) {
+                                    x.tangent = tangent;
+                                }
+                            }
+                            None => generate_tangents(&mut vertices, &indices),
+                        }
 
                         let mesh_name = mesh.name().unwrap_or("default_mesh_name").into();
-                        let vertex_buffer = wgpu.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                             label: Some(&format!("{} Vertex Buffer", mesh_name)),
                             contents: bytemuck::cast_slice(&vertices),
-                            usage: wgpu::BufferUsages::VERTEX,
+                            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_SRC,
                         });
-                        let index_buffer = wgpu.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                             label: Some(&format!("{} Index Buffer", mesh_name)),
                             contents: bytemuck::cast_slice(&indices),
-                            usage: wgpu::BufferUsages::INDEX,
+                            usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_SRC,
                         });
                         meshes.push(Mesh {
                             name: mesh_name,
-                            vertex_buffer,
-                            index_buffer,
+                            vertex_buffer: BufferRange::whole(vertex_buffer),
+                            index_buffer: BufferRange::whole(index_buffer),
                             num_elements: indices.len() as u32,
                             material: material.unwrap_or(0),
+                            node: node_idx,
                         })
                     }
                 }
@@ -140,48 +333,80 @@ impl Model {
 
         let mut node_data = NodeData {
             buffer_data: &buffer_data[..],
-            wgpu,
+            device,
             meshes: &mut meshes,
             materials: &mut materials,
+            aabb: &mut aabb,
+            nodes: &mut nodes,
         };
 
         for scene in gltf.scenes() {
             for node in scene.nodes() {
-                node_data.load_node(node);
+                node_data.load_node(node, None);
             }
         }
 
         for material in gltf.materials() {
             let pbr = material.pbr_metallic_roughness();
-            let base_color_texture = pbr.base_color_texture();
             let name = material.name().unwrap_or("Default Material").to_string();
-            if let Some(texture_source) = base_color_texture.map(|tex| { tex.texture().source().source() }) {
-                match texture_source {
-                    gltf::image::Source::View { view, mime_type: mt } => {
-                        trace!(target: "gltf_load", "Loading texture for type: {mt}");
-                        let diffuse_texture = Some(TextureWrapper::from_bytes(
-                            &wgpu.device, &wgpu.queue,
-                            &buffer_data[view.buffer().index()][view.offset()..view.offset() + view.length()],
-                            label, false)?);
-
-                        materials.push(Material {
-                            name,
-                            diffuse_texture,
-                        });
-                    }
-                    gltf::image::Source::Uri { uri: _, mime_type: _ } => {
-                        return Err(anyhow!("This model has uri source for image but not impl yet!"));
-                    }
-                };
-            } else {
-                materials.push(Material {
-                    name,
-                    diffuse_texture: None,
-                });
-            }
+            let load = |x: Option<gltf::Texture>, space| x
+                .map(|x| load_texture(device, queue, x, &buffer_data, &load_file, label, space))
+                .transpose();
+            let [r, g, b] = material.emissive_factor();
+            materials.push(Material {
+                name,
+                diffuse_texture: load(pbr.base_color_texture().map(|x| x.texture()), ColorSpace::Srgb)?,
+                normal_texture: load(material.normal_texture().map(|x| x.texture()), ColorSpace::Linear)?,
+                metallic_roughness_texture: load(pbr.metallic_roughness_texture().map(|x| x.texture()), ColorSpace::Linear)?,
+                emissive_texture: load(material.emissive_texture().map(|x| x.texture()), ColorSpace::Srgb)?,
+                factors: MaterialFactors {
+                    base_color: pbr.base_color_factor(),
+                    emissive: [r, g, b],
+                    metallic: pbr.metallic_factor(),
+                    roughness: pbr.roughness_factor(),
+                    normal_scale: material.normal_texture().map(|x| x.scale()).unwrap_or(1.0),
+                    _pad: [0.0; 2],
+                },
+            });
         }
 
-        Ok(Self { meshes, materials })
+        Ok(Self { meshes, materials, aabb, nodes, transform_version: 0 })
+    }
+
+    pub fn find_node(&self, name: &str) -> Option<usize> {
+        self.nodes.iter().position(|x| x.name.as_deref() == Some(name))
+    }
+
+    /// Set the transform of the node relative to its parent and update the world transforms of the subtree.
+    pub fn set_local_transform(&mut self, node: usize, local: Matrix4<f32>) {
+        self.nodes[node].local = local;
+        let mut stack = vec![node];
+        while let Some(idx) = stack.pop() {
+            let n = &self.nodes[idx];
+            let world = match n.parent {
+                Some(parent) => self.nodes[parent].world * n.local,
+                None => n.local,
+            };
+            self.nodes[idx].world = world;
+            stack.extend_from_slice(&self.nodes[idx].children);
+        }
+        self.transform_version += 1;
+    }
+
+    /// The version to check whether the node transforms changed.
+    pub fn transform_version(&self) -> u64 {
+        self.transform_version
+    }
+
+    /// The instance data for every node, `instances.len()` instances for each node in the order of [`Model::nodes`]
+    ///
+    /// The offset is applied in the model space.
+    pub fn instance_raws(&self, instances: &[GltfInstance], offset: &Vector3<f32>) -> Vec<InstanceRaw> {
+        let offset = Matrix4::new_translation(offset);
+        let instances = instances.iter().map(|x| x.to_matrix() * offset).collect::<Vec<_>>();
+        self.nodes.iter()
+            .flat_map(|node| instances.iter().map(|x| InstanceRaw::from_matrix(x * node.world)))
+            .collect()
     }
 }
 
@@ -208,157 +433,43 @@ impl Vertex for ModelVertex {
                     shader_location: 2,
                     format: wgpu::VertexFormat::Float32x3,
                 },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
             ],
         }
     }
 }
 
 
-pub trait DrawModel<'a> {
-    fn draw_mesh(
-        &mut self,
-        mesh: &'a Mesh,
-        local_bind_group: &'a wgpu::BindGroup,
-    );
-    fn draw_mesh_instanced(
-        &mut self,
-        mesh: &'a Mesh,
-        instances: Range<u32>,
-        local_bind_group: &'a wgpu::BindGroup,
-    );
-
-    fn draw_model(&mut self, model: &'a Model, local_bind_group: &'a wgpu::BindGroup);
-    fn draw_model_instanced(
-        &mut self,
-        model: &'a Model,
-        instances: Range<u32>,
-        local_bind_group: &'a wgpu::BindGroup,
-    );
-}
+#[cfg(test)]
+mod test {
+    use crate::engine::glft::model::{generate_tangents, ModelVertex};
 
-impl<'a, 'b, T: RenderEncoder<'a>> DrawModel<'b> for T
-    where
-        'b: 'a,
-{
-    fn draw_mesh(
-        &mut self,
-        mesh: &'b Mesh,
-        local_bind_group: &'b wgpu::BindGroup,
-    ) {
-        self.draw_mesh_instanced(mesh, 0..1, local_bind_group);
-    }
-
-    fn draw_mesh_instanced(
-        &mut self,
-        mesh: &'b Mesh,
-        instances: Range<u32>,
-        local_bind_group: &'b wgpu::BindGroup,
-    ) {
-        self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
-        self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
-        self.set_bind_group(1, local_bind_group, &[]);
-        self.draw_indexed(0..mesh.num_elements, 0, instances);
-    }
-
-    fn draw_model(&mut self, model: &'b Model, local_bind_group: &'b wgpu::BindGroup) {
-        self.draw_model_instanced(model, 0..1, local_bind_group);
-    }
-
-    fn draw_model_instanced(
-        &mut self,
-        model: &'b Model,
-        instances: Range<u32>,
-        local_bind_group: &'b wgpu::BindGroup,
-    ) {
-        for mesh in &model.meshes {
-            self.draw_mesh_instanced(mesh, instances.clone(), local_bind_group);
+    #[test]
+    fn test_generate_tangents() {
+        // the quad in xy with u along x and v along y
+        let vertex = |x: f32, y: f32| ModelVertex {
+            position: [x, y, 0.0],
+            tex_coords: [x, y],
+            normal: [0.0, 0.0, 1.0],
+            tangent: Default::default(),
+        };
+        let mut vertices = [vertex(0.0, 0.0), vertex(1.0, 0.0), vertex(1.0, 1.0), vertex(0.0, 1.0)];
+        generate_tangents(&mut vertices, &[0, 1, 2, 0, 2, 3]);
+        for x in &vertices {
+            // the bitangent cross(n, t) is +y toward the increasing v
+            assert_eq!(x.tangent, [1.0, 0.0, 0.0, -1.0]);
         }
-    }
-}
-
-pub trait DrawLight<'a> {
-    fn draw_light_mesh(
-        &mut self,
-        mesh: &'a Mesh,
-        global_bind_group: &'a wgpu::BindGroup,
-        local_bind_group: &'a wgpu::BindGroup,
-    );
-    fn draw_light_mesh_instanced(
-        &mut self,
-        mesh: &'a Mesh,
-        instances: Range<u32>,
-        global_bind_group: &'a wgpu::BindGroup,
-        local_bind_group: &'a wgpu::BindGroup,
-    );
-
-    fn draw_light_model(
-        &mut self,
-        model: &'a Model,
-        global_bind_group: &'a wgpu::BindGroup,
-        local_bind_group: &'a wgpu::BindGroup,
-    );
-    fn draw_light_model_instanced(
-        &mut self,
-        model: &'a Model,
-        instances: Range<u32>,
-        global_bind_group: &'a wgpu::BindGroup,
-        local_bind_group: &'a wgpu::BindGroup,
-    );
-}
 
-impl<'a, 'b> DrawLight<'b> for wgpu::RenderPass<'a>
-    where
-        'b: 'a,
-{
-    fn draw_light_mesh(
-        &mut self,
-        mesh: &'b Mesh,
-        global_bind_group: &'b wgpu::BindGroup,
-        local_bind_group: &'b wgpu::BindGroup,
-    ) {
-        self.draw_light_mesh_instanced(mesh, 0..1, global_bind_group, local_bind_group);
-    }
-
-    fn draw_light_mesh_instanced(
-        &mut self,
-        mesh: &'b Mesh,
-        instances: Range<u32>,
-        global_bind_group: &'b wgpu::BindGroup,
-        local_bind_group: &'b wgpu::BindGroup,
-    ) {
-        self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
-        self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
-        self.set_bind_group(0, global_bind_group, &[]);
-        self.set_bind_group(1, local_bind_group, &[]);
-        self.draw_indexed(0..mesh.num_elements, 0, instances);
-    }
-
-    fn draw_light_model(
-        &mut self,
-        model: &'b Model,
-        global_bind_group: &'b wgpu::BindGroup,
-        local_bind_group: &'b wgpu::BindGroup,
-    ) {
-        self.draw_light_model_instanced(model, 0..1, global_bind_group, local_bind_group);
-    }
-    fn draw_light_model_instanced(
-        &mut self,
-        model: &'b Model,
-        instances: Range<u32>,
-        global_bind_group: &'b wgpu::BindGroup,
-        local_bind_group: &'b wgpu::BindGroup,
-    ) {
-        for mesh in &model.meshes {
-            self.draw_light_mesh_instanced(
-                mesh,
-                instances.clone(),
-                global_bind_group,
-                local_bind_group,
-            );
-        }
+        // v flipped
+        let mut vertices = vertices.map(|mut x| {
+            x.tex_coords[1] = 1.0 - x.tex_coords[1];
+            x
+        });
+        generate_tangents(&mut vertices, &[0, 1, 2, 0, 2, 3]);
+        assert_eq!(vertices[0].tangent, [1.0, 0.0, 0.0, 1.0]);
     }
 }
-
-
-
-
@@ -92,8 +92,8 @@ fn vs_main(
 // Fragment shader
 
 // We create variables for the bind groups
-// This grabs the texture from the Local uniform
-@group(1) @binding(1)
+// This grabs the texture of the mesh
+@group(2) @binding(0)
 var t_diffuse: texture_2d<f32>;
 // This grabs the sampler from the Global uniform
 @group(0)@binding(2)
@@ -122,6 +122,6 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     let result = (ambient_color + diffuse_color + specular_color) * object_color.xyz;
 
 //    return vec4<f32>(result, object_color.a);
-     return locals.color * vec4<f32>(result, object_color.a);
+     return locals.color * vec4<f32>(linear_to_srgb(result), object_color.a);
 //     return vec4<f32>(1.0,1.0,1.0, 1.0);
 }
@@ -1,14 +1,16 @@
 use std::collections::HashMap;
 
+use nalgebra::Vector3;
 use wgpu::*;
 use wgpu::util::{DeviceExt, RenderEncoder};
 
 use crate::engine::{TextureWrapper, Vertex, WgpuData};
-use crate::engine::glft::{ModelObject, UniformPool};
-use crate::engine::glft::instance::{GltfInstance, InstanceRaw};
-use crate::engine::glft::model::{DrawModel, ModelVertex};
+use crate::engine::glft::ModelObject;
+use crate::engine::glft::instance::InstanceRaw;
+use crate::engine::glft::model::ModelVertex;
 use crate::engine::render::camera::{Camera, CameraUniform};
 use crate::engine::renderer::Renderer;
+use crate::engine::uniform::UniformStager;
 
 // Global uniform data
 // aka camera position and ambient light color
@@ -43,12 +45,32 @@ pub struct LightUniform {
     _padding2: u32,
 }
 
+/// The renderer settings able to be applied at runtime.
+#[derive(Debug, Clone, PartialEq)]
 pub struct RendererConfig {
     pub max_lights: usize,
+    /// The ambient light color in 0-255 rgba.
     pub ambient: [u32; 4],
+    /// Draw the model vertices as points.
     pub point_frame: bool,
 }
 
+impl Default for RendererConfig {
+    fn default() -> Self {
+        Self {
+            max_lights: 16,
+            ambient: [64, 64, 64, 255],
+            point_frame: false,
+        }
+    }
+}
+
+impl RendererConfig {
+    pub fn ambient_color(&self) -> [f32; 4] {
+        self.ambient.map(|x| x.min(255) as f32 / 255.0)
+    }
+}
+
 #[allow(unused)]
 pub struct ModelRenderer {
     // Uniforms
@@ -56,10 +78,21 @@ pub struct ModelRenderer {
     global_uniform_buffer: Buffer,
     global_bind_group: BindGroup,
     local_bind_group_layout: BindGroupLayout,
-    // pub local_uniform_buffer: wgpu::Buffer,
-    local_bind_groups: HashMap<usize, BindGroup>,
-    uniform_pool: UniformPool,
+    /// The locals of the nodes in the stride, bound by the dynamic offsets.
+    locals_buffer: Buffer,
+    locals_bind_group: BindGroup,
+    locals_stride: BufferAddress,
+    /// The nodes the locals buffer holds, grown if more.
+    locals_capacity: usize,
+    texture_bind_group_layout: BindGroupLayout,
+    /// The diffuse texture bound for each node and the id of the view bound.
+    texture_bind_groups: Vec<(Id, BindGroup)>,
     // Render pipeline
+    shader_module: ShaderModule,
+    light_shader: ShaderModule,
+    pipeline_layout: PipelineLayout,
+    format: TextureFormat,
+    config: RendererConfig,
     render_pipeline: RenderPipeline,
     // Lighting
     light_uniform: LightUniform,
@@ -87,7 +120,7 @@ impl ModelRenderer {
         // and also to have the right shader for the uniforms we pass
         let shader_module = device.create_shader_module(ShaderModuleDescriptor {
             label: Some("Normal Shader"),
-            source: ShaderSource::Wgsl(include_str!("model_shader.wgsl").into()),
+            source: ShaderSource::Wgsl(concat!(include_str!("../color.wgsl"), include_str!("model_shader.wgsl")).into()),
         });
 
         // Setup global uniforms
@@ -189,14 +222,22 @@ impl ModelRenderer {
                         visibility: ShaderStages::VERTEX | ShaderStages::FRAGMENT,
                         ty: BindingType::Buffer {
                             ty: BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
+                            has_dynamic_offset: true,
                             min_binding_size: BufferSize::new(local_size),
                         },
                         count: None,
                     },
+                ],
+            });
+        let locals_stride = util::align_to(local_size, device.limits().min_uniform_buffer_offset_alignment as BufferAddress);
+        let (locals_buffer, locals_bind_group) = Self::create_locals(device, &local_bind_group_layout, locals_stride, 1);
+        let texture_bind_group_layout =
+            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("Mesh texture"),
+                entries: &[
                     // Mesh texture
                     BindGroupLayoutEntry {
-                        binding: 1,
+                        binding: 0,
                         visibility: ShaderStages::FRAGMENT,
                         ty: BindingType::Texture {
                             sample_type: TextureSampleType::Float { filterable: true },
@@ -211,9 +252,78 @@ impl ModelRenderer {
         // Setup the render pipeline
         let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
             label: Some("Pipeline"),
-            bind_group_layouts: &[&global_bind_group_layout, &local_bind_group_layout],
+            bind_group_layouts: &[&global_bind_group_layout, &local_bind_group_layout, &texture_bind_group_layout],
             push_constant_ranges: &[],
         });
+        let light_shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Light Shader"),
+            source: ShaderSource::Wgsl(include_str!("light.wgsl").into()),
+        });
+        let (render_pipeline, light_render_pipeline) = Self::create_pipelines(device, &pipeline_layout, &shader_module, &light_shader,
+                                                                              config.format, renderer_config.point_frame);
+
+        // Create depth texture
+        let depth_texture =
+            TextureWrapper::create_depth_texture(&device, &config, "depth_texture");
+
+        // Setup camera uniform
+        let mut camera_uniform = CameraUniform::new();
+        camera_uniform.update_view_proj(&camera);
+
+        // Create instance buffer
+        let instance_buffers = HashMap::new();
+
+        ModelRenderer {
+            shader_module,
+            light_shader,
+            pipeline_layout,
+            format: config.format,
+            config: renderer_config.clone(),
+            global_bind_group_layout,
+            global_uniform_buffer,
+            global_bind_group,
+            local_bind_group_layout,
+            locals_buffer,
+            locals_bind_group,
+            locals_stride,
+            locals_capacity: 1,
+            texture_bind_group_layout,
+            texture_bind_groups: vec![],
+            render_pipeline,
+            camera_uniform,
+            light_uniform,
+            light_buffer,
+            light_render_pipeline,
+            instance_buffers,
+        }
+    }
+
+    /// Create the buffer of the locals of the nodes and the bind group of the first node in it.
+    fn create_locals(device: &Device, layout: &BindGroupLayout, stride: BufferAddress, capacity: usize) -> (Buffer, BindGroup) {
+        let buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Locals"),
+            size: stride * capacity as BufferAddress,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Locals"),
+            layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::Buffer(BufferBinding {
+                    buffer: &buffer,
+                    offset: 0,
+                    size: BufferSize::new(std::mem::size_of::<Locals>() as BufferAddress),
+                }),
+            }],
+        });
+        (buffer, bind_group)
+    }
+
+    /// Create the (model, light) pipelines, the points drawn if `point_frame`
+    fn create_pipelines(device: &Device, pipeline_layout: &PipelineLayout, shader_module: &ShaderModule, light_shader: &ShaderModule,
+                        format: TextureFormat, point_frame: bool) -> (RenderPipeline, RenderPipeline) {
         let vertex_buffers = [ModelVertex::desc(), InstanceRaw::desc()];
         let depth_stencil = Some(DepthStencilState {
             format: TextureFormat::Depth32Float,
@@ -224,7 +334,7 @@ impl ModelRenderer {
         });
 
         // Enable/disable wireframe mode
-        let topology = if renderer_config.point_frame {
+        let topology = if point_frame {
             PrimitiveTopology::PointList
         } else {
             PrimitiveTopology::TriangleList
@@ -241,9 +351,9 @@ impl ModelRenderer {
 
         let render_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
             label: Some("glft renderer pipeline"),
-            layout: Some(&pipeline_layout),
+            layout: Some(pipeline_layout),
             vertex: VertexState {
-                module: &shader_module,
+                module: shader_module,
                 entry_point: "vs_main",
                 buffers: &vertex_buffers,
             },
@@ -251,10 +361,10 @@ impl ModelRenderer {
             depth_stencil: depth_stencil.clone(),
             multisample,
             fragment: Some(FragmentState {
-                module: &shader_module,
+                module: shader_module,
                 entry_point: "fs_main",
                 targets: &[Some(ColorTargetState {
-                    format: config.format,
+                    format,
                     blend: Some(BlendState {
                         alpha: BlendComponent::REPLACE,
                         color: BlendComponent::REPLACE,
@@ -265,25 +375,12 @@ impl ModelRenderer {
             multiview: None,
         });
 
-        // Create depth texture
-        let depth_texture =
-            TextureWrapper::create_depth_texture(&device, &config, "depth_texture");
-
-        // Setup camera uniform
-        let mut camera_uniform = CameraUniform::new();
-        camera_uniform.update_view_proj(&camera);
-
-        let light_shader = device.create_shader_module(ShaderModuleDescriptor {
-            label: Some("Light Shader"),
-            source: ShaderSource::Wgsl(include_str!("light.wgsl").into()),
-        });
-
         let light_render_pipeline =
             device.create_render_pipeline(&RenderPipelineDescriptor {
                 label: Some("Light Pipeline"),
-                layout: Some(&pipeline_layout),
+                layout: Some(pipeline_layout),
                 vertex: VertexState {
-                    module: &light_shader,
+                    module: light_shader,
                     entry_point: "vs_main",
                     buffers: &[ModelVertex::desc()],
                 },
@@ -291,10 +388,10 @@ impl ModelRenderer {
                 depth_stencil,
                 multisample,
                 fragment: Some(FragmentState {
-                    module: &light_shader,
+                    module: light_shader,
                     entry_point: "fs_main",
                     targets: &[Some(ColorTargetState {
-                        format: config.format,
+                        format,
                         blend: Some(BlendState {
                             alpha: BlendComponent::REPLACE,
                             color: BlendComponent::REPLACE,
@@ -305,140 +402,128 @@ impl ModelRenderer {
                 multiview: None,
             });
 
-        // Create instance buffer
-        let instance_buffers = HashMap::new();
-
-        let uniform_pool = UniformPool::new("Locals", local_size);
+        (render_pipeline, light_render_pipeline)
+    }
 
-        ModelRenderer {
-            global_bind_group_layout,
-            global_uniform_buffer,
-            global_bind_group,
-            local_bind_group_layout,
-            local_bind_groups: Default::default(),
-            uniform_pool,
-            render_pipeline,
-            camera_uniform,
-            light_uniform,
-            light_buffer,
-            light_render_pipeline,
-            instance_buffers,
+    /// Apply the config at runtime, the pipelines are created again if the point frame changed.
+    pub fn apply_config(&mut self, device: &Device, config: &RendererConfig) {
+        if config.point_frame != self.config.point_frame {
+            let (render_pipeline, light_render_pipeline) = Self::create_pipelines(device, &self.pipeline_layout, &self.shader_module, &self.light_shader,
+                                                                                  self.format, config.point_frame);
+            self.render_pipeline = render_pipeline;
+            self.light_render_pipeline = light_render_pipeline;
         }
+        self.config = config.clone();
     }
 
     pub fn update_camera(&mut self, camera: &Camera) {
         self.camera_uniform.update_view_proj(camera);
     }
-}
 
-impl Renderer<ModelObject> for ModelRenderer {
-    fn render<'a, T: RenderEncoder<'a>>(&'a mut self, encoder: &mut T, wgpu: &WgpuData, nodes: &'a [ModelObject]) {
+    /// Write the globals, the locals and the instances of the nodes by the stager, before the pass rendering them.
+    pub fn prepare(&mut self, wgpu: &WgpuData, stager: &mut UniformStager, nodes: &[ModelObject]) {
         let device = wgpu.device.as_ref();
-        let queue = wgpu.queue.as_ref();
         let views = &wgpu.views;
 
+        let globals = Globals {
+            view_position: self.camera_uniform.view_position.into(),
+            view_proj: self.camera_uniform.view_proj.into(),
+            ambient: self.config.ambient_color(),
+        };
+        stager.write(&self.global_uniform_buffer, 0, &globals);
 
-        queue.write_buffer(&self.global_uniform_buffer, 0, bytemuck::cast_slice(&[self.camera_uniform]));
-        {
-
-            // Allocate buffers for local uniforms
-            if self.uniform_pool.buffers.len() < nodes.len() {
-                self.uniform_pool.alloc_buffers(nodes.len(), &device);
+        // Grow the locals buffer to hold all nodes, the old one is dropped
+        if self.locals_capacity < nodes.len() {
+            self.locals_capacity = nodes.len().next_power_of_two();
+            (self.locals_buffer, self.locals_bind_group) = Self::create_locals(device, &self.local_bind_group_layout,
+                                                                               self.locals_stride, self.locals_capacity);
+        }
+        // The locals of all nodes are written once, each in its stride
+        let stride = self.locals_stride as usize;
+        let mut locals = vec![0u8; stride * nodes.len()];
+        for (x, node) in locals.chunks_exact_mut(stride).zip(nodes) {
+            let data = bytemuck::bytes_of(&node.locals);
+            x[..data.len()].copy_from_slice(data);
+        }
+        stager.write_bytes(&self.locals_buffer, 0, &locals);
+
+        // The texture binds are checked by the view id, never reused by another view unlike its address
+        self.texture_bind_groups.truncate(nodes.len());
+        for (model_index, node) in nodes.iter().enumerate() {
+            let view = node.model.materials.iter().filter_map(|x| x.diffuse_texture.as_ref())
+                .map(|x| x.srgb_view())
+                .next().unwrap_or(&views.get_off_screen().view);
+            let id = view.global_id();
+            let bound = self.texture_bind_groups.get(model_index).is_some_and(|x| x.0 == id);
+            if !bound {
+                let bind_group = device.create_bind_group(&BindGroupDescriptor {
+                    label: Some("Mesh texture"),
+                    layout: &self.texture_bind_group_layout,
+                    entries: &[BindGroupEntry {
+                        binding: 0,
+                        resource: BindingResource::TextureView(view),
+                    }],
+                });
+                if model_index < self.texture_bind_groups.len() {
+                    self.texture_bind_groups[model_index] = (id, bind_group);
+                } else {
+                    self.texture_bind_groups.push((id, bind_group));
+                }
             }
 
-            // Loop over the nodes/models in a scene and setup the specific models
-            // local uniform bind group and instance buffers to send to shader
-            // This is separate loop from the render because of Rust ownership
-            // (can prob wrap in block instead to limit mutable use)
-            let mut model_index = 0;
-            for node in nodes {
-                let local_buffer = &self.uniform_pool.buffers[model_index];
-                queue.write_buffer(local_buffer, 0, bytemuck::cast_slice(&[node.locals]));
-                // We create a bind group for each model's local uniform data
-                // and store it in a hash map to look up later
-
-                self.local_bind_groups
-                    .entry(model_index)
-                    .or_insert_with(|| {
-                        let view = node.model.materials.iter().filter(|x| x.diffuse_texture.is_some())
-                            .map(|x| &x.diffuse_texture.as_ref().unwrap().view)
-                            .next().unwrap_or(&views.get_off_screen().view);
-                        device.create_bind_group(&BindGroupDescriptor {
-                            label: Some("Locals"),
-                            layout: &self.local_bind_group_layout,
-                            entries: &[
-                                BindGroupEntry {
-                                    binding: 0,
-                                    resource: local_buffer.as_entire_binding(),
-                                },
-                                BindGroupEntry {
-                                    binding: 1,
-                                    resource: BindingResource::TextureView(
-                                        view,
-                                    ),
-                                },
-                            ],
-                        })
-                    });
-
-                // Setup instance buffer for the model
-                // similar process as above using HashMap
-                self.instance_buffers.entry(model_index).or_insert_with(|| {
-                    // We condense the matrix properties into a flat array (aka "raw data")
-                    // (which is how buffers work - so we can "stride" over chunks)
-                    let instance_data = node
-                        .instances
-                        .iter()
-                        .map(GltfInstance::to_raw)
-                        .collect::<Vec<_>>();
+            // Setup instance buffer for the model
+            // The node transforms may be changed by the game, so the data is uploaded every frame
+            // We condense the matrix properties into a flat array (aka "raw data")
+            // (which is how buffers work - so we can "stride" over chunks)
+            let instance_data = node.model.instance_raws(&node.instances, &Vector3::zeros());
+            let contents: &[u8] = bytemuck::cast_slice(&instance_data);
+            match self.instance_buffers.get(&model_index) {
+                Some(buffer) if buffer.size() == contents.len() as BufferAddress => {
+                    stager.write_bytes(buffer, 0, contents);
+                }
+                _ => {
                     // Create the instance buffer with our data
                     let instance_buffer =
                         device.create_buffer_init(&util::BufferInitDescriptor {
                             label: Some("Instance Buffer"),
-                            contents: bytemuck::cast_slice(&instance_data),
-                            usage: BufferUsages::VERTEX,
+                            contents,
+                            usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
                         });
-
-                    instance_buffer
-                });
-
-                model_index += 1;
+                    self.instance_buffers.insert(model_index, instance_buffer);
+                }
             }
+        }
+    }
+}
 
-            // Setup lighting pipeline
-            encoder.set_pipeline(&self.light_render_pipeline);
-            // Draw/calculate the lighting on models
-            // render_pass.draw_light_model(
-            //     &nodes[0].model,
-            //     &self.global_bind_group,
-            //     &self
-            //         .local_bind_groups
-            //         .get(&0)
-            //         .expect("No local bind group found for lighting"),
-            // );
-
-            // Setup render pipeline
-            encoder.set_pipeline(&self.render_pipeline);
-            encoder.set_bind_group(0, &self.global_bind_group, &[]);
-
-            // Render/draw all nodes/models
-            // We reset index here to use again
-            model_index = 0;
-            for node in nodes {
-                // if node.model.materials.len() > 0 {
-                // Set the instance buffer unique to the model
-                encoder.set_vertex_buffer(1, self.instance_buffers[&model_index].slice(..));
+/// Render the nodes prepared by [`ModelRenderer::prepare`].
+impl Renderer<ModelObject> for ModelRenderer {
+    fn render<'a, T: RenderEncoder<'a>>(&'a mut self, encoder: &mut T, _: &WgpuData, nodes: &'a [ModelObject]) {
+        // Setup render pipeline
+        encoder.set_pipeline(&self.render_pipeline);
+        encoder.set_bind_group(0, &self.global_bind_group, &[]);
+
+        // Render/draw all nodes/models
+        for (model_index, node) in nodes.iter().enumerate() {
+            let instance_count = node.instances.len() as u32;
+            let stride = (std::mem::size_of::<InstanceRaw>() * node.instances.len()) as BufferAddress;
+            let (instances, texture) = match (self.instance_buffers.get(&model_index), self.texture_bind_groups.get(model_index)) {
+                (Some(instances), Some(texture)) if instance_count > 0 && model_index < self.locals_capacity => (instances, texture),
+                _ => continue,
+            };
+            let offset = (self.locals_stride * model_index as BufferAddress) as DynamicOffset;
+            encoder.set_bind_group(1, &self.locals_bind_group, &[offset]);
+            encoder.set_bind_group(2, &texture.1, &[]);
+            for mesh in &node.model.meshes {
+                // Set the instances of the node owning the mesh
+                let start = stride * mesh.node as BufferAddress;
+                encoder.set_vertex_buffer(1, instances.slice(start..start + stride));
 
                 // Draw all the model instances
-                encoder.draw_model_instanced(
-                    &node.model,
-                    0..node.instances.len() as u32,
-                    &self.local_bind_groups[&model_index],
-                );
-                // }
-
-                model_index += 1;
+                encoder.set_vertex_buffer(0, mesh.vertex_buffer.slice());
+                encoder.set_index_buffer(mesh.index_buffer.slice(), IndexFormat::Uint32);
+                encoder.draw_indexed(0..mesh.num_elements, 0, 0..instance_count);
             }
         }
     }
-}
\ No newline at end of file
+}
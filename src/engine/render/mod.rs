@@ -2,15 +2,23 @@ use std::any::type_name;
 use std::collections::HashMap;
 use std::fmt::{Debug, Formatter};
 
+use log::debug;
 use once_cell::sync::Lazy;
 use wgpu::*;
 
 pub use texture::*;
 
-use crate::engine::{ResourceManager, TextureInfo, TextureWrapper, WgpuData};
+use crate::engine::{ResourceManager, WgpuData};
+use crate::engine::pacing::mark_frame_event;
+use crate::engine::render::blit::BlitRenderer;
+use crate::engine::render::fade::FadeRenderer;
+use crate::engine::render::post::PostProcessor;
+use crate::engine::render::present::PresentRenderer;
+use crate::engine::render::target::{RenderTarget, TARGET_IDLE_FRAMES, TargetDesc, TargetSize};
 
 pub mod invert_color;
 pub mod point;
+pub mod blit;
 pub mod texture;
 pub mod glft;
 pub mod state;
@@ -19,6 +27,13 @@ pub mod renderer;
 pub mod renderer3d;
 pub mod uniform;
 pub mod camera;
+pub mod timestamp;
+pub mod post;
+pub mod recorder;
+pub mod fade;
+pub mod arena;
+pub mod target;
+pub mod present;
 
 static INSTANCE: Lazy<Instance> = Lazy::new(|| Instance::new(InstanceDescriptor::default()));
 
@@ -30,15 +45,24 @@ pub trait Vertex {
 #[derive(Debug)]
 pub struct MainRenderViews {
     buffers: [TextureWrapper; 2],
+    /// The offscreen scene target, only exists if the render scale is not 1.
+    scene: Option<TextureWrapper>,
     depth: TextureWrapper,
-    extra: HashMap<String, TextureWrapper>,
+    extra: HashMap<String, RenderTarget>,
     main: usize,
+    /// The frames ended, for the targets unused.
+    frame: u64,
+    /// The format of the screen buffers and the scene.
+    format: TextureFormat,
 }
 
 
 pub struct MainRendererData {
     pub staging_belt: util::StagingBelt,
-    pub egui_rpass: egui_wgpu::Renderer,
+    pub blit: BlitRenderer,
+    pub post: PostProcessor,
+    pub fade: FadeRenderer,
+    pub present: PresentRenderer,
 }
 
 impl Debug for MainRendererData {
@@ -50,11 +74,18 @@ impl Debug for MainRendererData {
 
 impl MainRendererData {
     pub fn new(gpu: &WgpuData, _handles: &ResourceManager) -> Self {
+        mark_frame_event("main renderer creation");
         let staging_belt = util::StagingBelt::new(2048);
-        let egui_rpass = egui_wgpu::Renderer::new(&gpu.device, gpu.surface_cfg.format, None, 1);
+        let blit = BlitRenderer::new(gpu);
+        let post = PostProcessor::new(gpu);
+        let fade = FadeRenderer::new(gpu);
+        let present = PresentRenderer::new(gpu);
         Self {
             staging_belt,
-            egui_rpass,
+            blit,
+            post,
+            fade,
+            present,
         }
     }
 }
@@ -62,7 +93,8 @@ impl MainRendererData {
 
 #[allow(unused)]
 impl MainRenderViews {
-    pub fn new(device: &Device, surface_cfg: &SurfaceConfiguration) -> Self {
+    /// `render_size` is the size for the 3d scene and depth, the color buffers in the `format`.
+    pub fn new(device: &Device, surface_cfg: &SurfaceConfiguration, format: TextureFormat, render_size: (u32, u32)) -> Self {
         let size = (surface_cfg.width, surface_cfg.height);
         let texture_desc = TextureDescriptor {
             label: None,
@@ -74,9 +106,9 @@ impl MainRenderViews {
             mip_level_count: 1,
             sample_count: 1,
             dimension: TextureDimension::D2,
-            format: surface_cfg.format,
+            format,
             usage: TextureUsages::COPY_DST | TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_SRC | TextureUsages::RENDER_ATTACHMENT,
-            view_formats: &[surface_cfg.format],
+            view_formats: &[format],
         };
 
         let buffer_a = {
@@ -101,13 +133,22 @@ impl MainRenderViews {
             }
         };
 
-        let depth = TextureWrapper::create_depth_texture(device, surface_cfg, "Main Depth Texture");
+        let scene = if render_size != size {
+            Some(TextureWrapper::new_with_size(device, format, render_size))
+        } else {
+            None
+        };
+
+        let depth = TextureWrapper::new_with_size(device, TextureFormat::Depth32Float, render_size);
 
         Self {
             buffers: [buffer_a, buffer_b],
+            scene,
             depth,
             extra: Default::default(),
             main: 0,
+            frame: 0,
+            format,
         }
     }
 
@@ -121,21 +162,109 @@ impl MainRenderViews {
         &self.buffers[self.main ^ 1]
     }
 
-    /// if not present then create
-    pub fn check_extra_with_size(&mut self, id: &str, device: &Device, size: (u32, u32), format: TextureFormat) {
-        {
-            if let Some(extra) = self.extra.get(id) {
-                if extra.info.width == size.0 && extra.info.height == size.1 {
-                    return;
-                }
+    /// Get the target for the 3d scene.
+    ///
+    /// It is the screen buffer if the render scale is 1.
+    pub fn get_scene(&self) -> &TextureWrapper {
+        self.scene.as_ref().unwrap_or_else(|| self.get_screen())
+    }
+
+    /// Get the scaled scene target if the scene is not rendered to the screen directly.
+    pub fn get_scaled_scene(&self) -> Option<&TextureWrapper> {
+        self.scene.as_ref()
+    }
+
+    /// Create the views again for the new size, the extra targets kept and resized if following the screen.
+    pub fn resize(&mut self, device: &Device, surface_cfg: &SurfaceConfiguration, render_size: (u32, u32)) {
+        let extra = std::mem::take(&mut self.extra);
+        let frame = self.frame;
+        *self = Self::new(device, surface_cfg, self.format, render_size);
+        self.frame = frame;
+        for (id, target) in extra {
+            if target.desc.size.is_relative() {
+                self.create_extra(&id, device, target.desc);
+            } else {
+                self.extra.insert(id, target);
             }
         }
-        let wrapper = TextureWrapper::new_with_size(device, format, size);
-        self.extra.insert(id.into(), wrapper);
+    }
+
+    fn target_size(&self, size: TargetSize) -> (u32, u32) {
+        let screen = &self.buffers[0].info;
+        size.resolve((screen.width, screen.height), (self.depth.info.width, self.depth.info.height))
+    }
+
+    /// Create the extra target if not present or the size or the format changed, return its generation.
+    pub fn create_extra(&mut self, id: &str, device: &Device, desc: TargetDesc) -> u64 {
+        let size = self.target_size(desc.size);
+        if let Some(target) = self.extra.get_mut(id) {
+            if target.matches(&desc, size) {
+                target.desc = desc;
+                target.mark_used(self.frame);
+                return target.generation;
+            }
+        }
+        let target = RenderTarget::new(device, desc, size, self.frame);
+        debug!(target: "views", "Created the extra target {} of {:?} in {:?}", id, size, desc.format);
+        let generation = target.generation;
+        self.extra.insert(id.into(), target);
+        generation
+    }
+
+    /// if not present then create
+    pub fn check_extra_with_size(&mut self, id: &str, device: &Device, size: (u32, u32), format: TextureFormat) {
+        self.create_extra(id, device, TargetDesc { size: TargetSize::Fixed(size.0, size.1), format });
+    }
+
+    /// Get the extra target and mark it used in the frame.
+    pub fn get_target(&self, id: &str) -> Option<&RenderTarget> {
+        let target = self.extra.get(id)?;
+        target.mark_used(self.frame);
+        Some(target)
     }
 
     pub fn get_extra(&self, id: &str) -> Option<&TextureWrapper> {
-        self.extra.get(id)
+        self.get_target(id).map(|x| &x.texture)
+    }
+
+    /// The generation of the extra target, changed after created again.
+    pub fn extra_generation(&self, id: &str) -> Option<u64> {
+        self.extra.get(id).map(|x| x.generation)
+    }
+
+    /// Release the extra target, return true if it existed.
+    pub fn release_extra(&mut self, id: &str) -> bool {
+        self.extra.remove(id).is_some()
+    }
+
+    /// Release the extra targets not used in the last `frames` frames, return the count released.
+    pub fn release_unused(&mut self, frames: u64) -> usize {
+        let before = self.extra.len();
+        let frame = self.frame;
+        self.extra.retain(|id, x| {
+            let keep = frame - x.last_used().min(frame) <= frames;
+            if !keep {
+                debug!(target: "views", "Released the extra target {} unused for {} frames", id, frame - x.last_used());
+            }
+            keep
+        });
+        before - self.extra.len()
+    }
+
+    /// End the frame, the extra targets unused for long released.
+    pub fn end_frame(&mut self) {
+        self.frame += 1;
+        self.release_unused(TARGET_IDLE_FRAMES);
+    }
+
+    /// The bytes of the extra targets.
+    pub fn extra_bytes(&self) -> u64 {
+        self.extra.values().map(|x| x.texture.byte_size()).sum()
+    }
+
+    /// The format of the screen buffers and the scene, the pipelines drawing to them should be in it.
+    pub fn format(&self) -> TextureFormat {
+        self.format
     }
 
     pub fn get_depth_view(&self) -> &TextureWrapper {
@@ -30,7 +30,7 @@ pub struct PointRenderer {
 #[allow(unused)]
 impl PointRenderer {
     pub fn new(state: &WgpuData) -> Self {
-        let texture_format = state.surface_cfg.format;
+        let texture_format = state.formats.color;
         let device = &state.device;
         //done bind group
         let vertex_buffer = device.create_buffer(&BufferDescriptor {
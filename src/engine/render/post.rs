@@ -0,0 +1,427 @@
+use bytemuck::{Pod, Zeroable};
+use wgpu::{AddressMode, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+           BindGroupLayoutEntry, BindingResource, BindingType, BlendComponent, BlendFactor, BlendOperation, BlendState,
+           Buffer, BufferBindingType, BufferDescriptor, BufferUsages, Color, ColorTargetState, ColorWrites,
+           CommandEncoder, Device, FilterMode, LoadOp, Operations, PrimitiveState, PrimitiveTopology,
+           RenderPassColorAttachment, RenderPassDescriptor, RenderPipeline, Sampler, SamplerBindingType,
+           SamplerDescriptor, ShaderModuleDescriptor, ShaderSource, ShaderStages, TextureFormat, TextureSampleType,
+           TextureView, TextureViewDimension};
+
+use crate::engine::{ColorSpace, TextureWrapper, WgpuData};
+use crate::engine::config::VideoSettings;
+
+/// The extra view marking the surfaces glowing, rendered by the states in the size of the scene.
+pub const GLOW_TARGET: &str = "glow";
+/// The distance to the portal starting the effects.
+const NEAR_PORTAL_DISTANCE: f32 = 2.5;
+const MAX_VIGNETTE: f32 = 0.6;
+const MAX_ABERRATION: f32 = 1.0;
+/// The luma starting the bloom.
+const BLOOM_THRESHOLD: f32 = 0.9;
+/// The targets halved from the screen size to blur.
+const BLOOM_LEVELS: usize = 4;
+
+const ADDITIVE: BlendState = BlendState {
+    color: BlendComponent {
+        src_factor: BlendFactor::One,
+        dst_factor: BlendFactor::One,
+        operation: BlendOperation::Add,
+    },
+    alpha: BlendComponent {
+        src_factor: BlendFactor::One,
+        dst_factor: BlendFactor::One,
+        operation: BlendOperation::Add,
+    },
+};
+
+/// The fullscreen pass in the post-processing chain.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PostPass {
+    /// Add the bright and the glow blurred.
+    Bloom,
+    Tonemap,
+    Fxaa,
+    /// The vignette and the chromatic aberration.
+    Vignette,
+}
+
+/// The effects of the frame set by the states, none if not set.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct PostEffects {
+    /// The darkening of the corners, from 0 to 1.
+    pub vignette: f32,
+    /// The splitting of the color channels at the edges.
+    pub aberration: f32,
+    /// The [`GLOW_TARGET`] rendered this frame.
+    pub glow: bool,
+}
+
+#[allow(unused)]
+impl PostEffects {
+    /// The effects near the portal, stronger when closer.
+    pub fn near_portal(distance: f32) -> Self {
+        let t = (1.0 - distance / NEAR_PORTAL_DISTANCE).clamp(0.0, 1.0);
+        let t = t * t;
+        Self {
+            vignette: t * MAX_VIGNETTE,
+            aberration: t * MAX_ABERRATION,
+            glow: false,
+        }
+    }
+
+    /// No vignette nor aberration.
+    pub fn is_none(&self) -> bool {
+        self.vignette <= 0.0 && self.aberration <= 0.0
+    }
+}
+
+/// The passes enabled in order.
+pub fn post_passes(video: &VideoSettings, effects: &PostEffects) -> Vec<PostPass> {
+    let mut passes = vec![];
+    if video.bloom > 0.0 {
+        passes.push(PostPass::Bloom);
+    }
+    if video.tonemap {
+        passes.push(PostPass::Tonemap);
+    }
+    if video.fxaa {
+        passes.push(PostPass::Fxaa);
+    }
+    if video.portal_effects && !effects.is_none() {
+        passes.push(PostPass::Vignette);
+    }
+    passes
+}
+
+/// The sizes of the bloom targets, halved from the screen size.
+fn bloom_sizes(size: (u32, u32)) -> [(u32, u32); BLOOM_LEVELS] {
+    let mut sizes = [(1, 1); BLOOM_LEVELS];
+    for (i, x) in sizes.iter_mut().enumerate() {
+        *x = ((size.0 >> (i + 1)).max(1), (size.1 >> (i + 1)).max(1));
+    }
+    sizes
+}
+
+#[derive(Debug, Copy, Clone, Default, Pod, Zeroable)]
+#[repr(C)]
+struct PostUniform {
+    texel: [f32; 2],
+    exposure: f32,
+    vignette: f32,
+    aberration: f32,
+    threshold: f32,
+    bloom: f32,
+    _pad: f32,
+}
+
+#[derive(Debug)]
+struct BloomTargets {
+    levels: Vec<TextureWrapper>,
+    /// The horizontal blurred of the smallest level.
+    blur: TextureWrapper,
+}
+
+impl BloomTargets {
+    fn new(device: &Device, format: TextureFormat, size: (u32, u32)) -> Self {
+        let sizes = bloom_sizes(size);
+        Self {
+            levels: sizes.iter().map(|x| TextureWrapper::new_with_size(device, format, *x)).collect(),
+            blur: TextureWrapper::new_with_size(device, format, sizes[BLOOM_LEVELS - 1]),
+        }
+    }
+
+    fn is_for(&self, size: (u32, u32)) -> bool {
+        let (width, height) = bloom_sizes(size)[0];
+        self.levels[0].info.width == width && self.levels[0].info.height == height
+    }
+}
+
+/// Draw the fullscreen triangle to the target.
+fn draw(encoder: &mut CommandEncoder, pipeline: &RenderPipeline, bind: &BindGroup, target: &TextureView, load: LoadOp<Color>) {
+    let mut rp = encoder.begin_render_pass(&RenderPassDescriptor {
+        label: None,
+        color_attachments: &[Some(RenderPassColorAttachment {
+            view: target,
+            resolve_target: None,
+            ops: Operations { load, store: true },
+        })],
+        depth_stencil_attachment: None,
+    });
+    rp.set_pipeline(pipeline);
+    rp.set_bind_group(0, bind, &[]);
+    rp.draw(0..3, 0..1);
+}
+
+/// Apply the fullscreen passes to the screen buffer, ping-ponging with the off screen buffer.
+///
+/// Run after the ui composed and before the screen copied to the surface.
+#[allow(unused)]
+#[derive(Debug)]
+pub struct PostProcessor {
+    layout: BindGroupLayout,
+    sampler: Sampler,
+    uniform: Buffer,
+    /// Bound if the pass samples one texture only.
+    black: TextureWrapper,
+    tonemap_rp: RenderPipeline,
+    fxaa_rp: RenderPipeline,
+    vignette_rp: RenderPipeline,
+    bright_rp: RenderPipeline,
+    downsample_rp: RenderPipeline,
+    blur_h_rp: RenderPipeline,
+    blur_v_rp: RenderPipeline,
+    upsample_rp: RenderPipeline,
+    composite_rp: RenderPipeline,
+    bloom: Option<BloomTargets>,
+    /// The effects of this frame, taken when processed.
+    pub effects: PostEffects,
+}
+
+#[allow(unused)]
+impl PostProcessor {
+    pub fn new(state: &WgpuData) -> Self {
+        let texture_format = state.formats.color;
+        let device = &state.device;
+
+        let texture_entry = |binding| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Texture {
+                sample_type: TextureSampleType::Float { filterable: true },
+                view_dimension: TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("post bind layout"),
+            entries: &[texture_entry(0), BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                count: None,
+            }, BindGroupLayoutEntry {
+                binding: 2,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }, texture_entry(3)],
+        });
+
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            ..Default::default()
+        });
+
+        let uniform = device.create_buffer(&BufferDescriptor {
+            label: Some("post uniform"),
+            size: std::mem::size_of::<PostUniform>() as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let black = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, image::Rgba([0, 0, 0, 255])));
+        let black = TextureWrapper::from_image(device, &state.queue, &black, Some("post black"), ColorSpace::Linear)
+            .expect("Create the black texture failed");
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("post pipeline layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("post shader"),
+            source: ShaderSource::Wgsl(concat!(include_str!("color.wgsl"), include_str!("post.wgsl")).into()),
+        });
+
+        let targets = [Some(ColorTargetState {
+            format: texture_format,
+            blend: None,
+            write_mask: ColorWrites::ALL,
+        })];
+        let mut desc = wgpu::RenderPipelineDescriptor {
+            label: Some("tonemap pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "tonemap_fs",
+                targets: &targets,
+            }),
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleList,
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: Default::default(),
+            multiview: None,
+        };
+        let mut pipeline = |label, entry_point| {
+            desc.label = Some(label);
+            desc.fragment.as_mut().unwrap().entry_point = entry_point;
+            device.create_render_pipeline(&desc)
+        };
+        let tonemap_rp = pipeline("tonemap pipeline", "tonemap_fs");
+        let fxaa_rp = pipeline("fxaa pipeline", "fxaa_fs");
+        let vignette_rp = pipeline("vignette pipeline", "vignette_fs");
+        let bright_rp = pipeline("bloom bright pipeline", "bright_fs");
+        let downsample_rp = pipeline("bloom downsample pipeline", "downsample_fs");
+        let blur_h_rp = pipeline("bloom blur h pipeline", "blur_h_fs");
+        let blur_v_rp = pipeline("bloom blur v pipeline", "blur_v_fs");
+        let composite_rp = pipeline("bloom composite pipeline", "composite_fs");
+        let additive = [Some(ColorTargetState {
+            format: texture_format,
+            blend: Some(ADDITIVE),
+            write_mask: ColorWrites::ALL,
+        })];
+        desc.label = Some("bloom upsample pipeline");
+        desc.fragment = Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "upsample_fs",
+            targets: &additive,
+        });
+        let upsample_rp = device.create_render_pipeline(&desc);
+
+        Self {
+            layout,
+            sampler,
+            uniform,
+            black,
+            tonemap_rp,
+            fxaa_rp,
+            vignette_rp,
+            bright_rp,
+            downsample_rp,
+            blur_h_rp,
+            blur_v_rp,
+            upsample_rp,
+            composite_rp,
+            bloom: None,
+            effects: Default::default(),
+        }
+    }
+
+    fn bind(&self, device: &Device, src: &TextureView, extra: Option<&TextureView>) -> BindGroup {
+        device.create_bind_group(&BindGroupDescriptor {
+            label: Some("post bind group"),
+            layout: &self.layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(src),
+            }, BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::Sampler(&self.sampler),
+            }, BindGroupEntry {
+                binding: 2,
+                resource: self.uniform.as_entire_binding(),
+            }, BindGroupEntry {
+                binding: 3,
+                resource: BindingResource::TextureView(extra.unwrap_or(&self.black.view)),
+            }],
+        })
+    }
+
+    /// Blur the bright of the `src` and the `glow` to the first bloom level.
+    fn render_bloom(&self, device: &Device, encoder: &mut CommandEncoder, src: &TextureView, glow: Option<&TextureView>) {
+        let bloom = if let Some(x) = self.bloom.as_ref() { x } else { return; };
+        let levels = &bloom.levels;
+        let clear = LoadOp::Clear(Color::BLACK);
+        draw(encoder, &self.bright_rp, &self.bind(device, src, glow), &levels[0].view, clear);
+        for i in 1..levels.len() {
+            draw(encoder, &self.downsample_rp, &self.bind(device, &levels[i - 1].view, None), &levels[i].view, clear);
+        }
+        let last = &levels[levels.len() - 1];
+        draw(encoder, &self.blur_h_rp, &self.bind(device, &last.view, None), &bloom.blur.view, clear);
+        draw(encoder, &self.blur_v_rp, &self.bind(device, &bloom.blur.view, None), &last.view, clear);
+        for i in (1..levels.len()).rev() {
+            draw(encoder, &self.upsample_rp, &self.bind(device, &levels[i].view, None), &levels[i - 1].view, LoadOp::Load);
+        }
+    }
+
+    /// Apply the passes enabled, the result is left in the screen buffer.
+    pub fn process(&mut self, state: &mut WgpuData, encoder: &mut CommandEncoder, video: &VideoSettings) {
+        let effects = std::mem::take(&mut self.effects);
+        let passes = post_passes(video, &effects);
+        if passes.is_empty() {
+            return;
+        }
+        let size = state.get_screen_size();
+        if passes.contains(&PostPass::Bloom) && !self.bloom.as_ref().is_some_and(|x| x.is_for(size)) {
+            self.bloom = Some(BloomTargets::new(&state.device, state.formats.color, size));
+        }
+        let uniform = PostUniform {
+            texel: [1.0 / size.0 as f32, 1.0 / size.1 as f32],
+            exposure: video.exposure,
+            vignette: effects.vignette,
+            aberration: effects.aberration,
+            threshold: BLOOM_THRESHOLD,
+            bloom: video.bloom,
+            _pad: 0.0,
+        };
+        state.queue.write_buffer(&self.uniform, 0, bytemuck::bytes_of(&uniform));
+        let device = &state.device;
+        for pass in passes {
+            let src = &state.views.get_screen().view;
+            let target = &state.views.get_off_screen().view;
+            let (pipeline, extra) = match pass {
+                PostPass::Bloom => {
+                    let glow = state.views.get_extra(GLOW_TARGET).filter(|_| effects.glow);
+                    self.render_bloom(device, encoder, src, glow.map(|x| &x.view));
+                    (&self.composite_rp, self.bloom.as_ref().map(|x| &x.levels[0].view))
+                }
+                PostPass::Tonemap => (&self.tonemap_rp, None),
+                PostPass::Fxaa => (&self.fxaa_rp, None),
+                PostPass::Vignette => (&self.vignette_rp, None),
+            };
+            draw(encoder, pipeline, &self.bind(device, src, extra), target, LoadOp::Clear(Color::BLACK));
+            state.views.swap_screen();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::engine::config::VideoSettings;
+    use crate::engine::render::post::{bloom_sizes, post_passes, PostEffects, PostPass};
+
+    #[test]
+    fn test_post_passes() {
+        let mut video = VideoSettings::default();
+        video.tonemap = false;
+        video.fxaa = false;
+        video.bloom = 0.0;
+        assert!(post_passes(&video, &PostEffects::default()).is_empty());
+
+        let far = PostEffects::near_portal(10.0);
+        assert!(far.is_none());
+        let near = PostEffects::near_portal(0.5);
+        assert!(near.vignette > PostEffects::near_portal(1.5).vignette);
+        assert_eq!(post_passes(&video, &near), vec![PostPass::Vignette]);
+
+        video.tonemap = true;
+        video.fxaa = true;
+        assert_eq!(post_passes(&video, &far), vec![PostPass::Tonemap, PostPass::Fxaa]);
+        video.portal_effects = false;
+        assert_eq!(post_passes(&video, &near), vec![PostPass::Tonemap, PostPass::Fxaa]);
+        video.bloom = 0.5;
+        assert_eq!(post_passes(&video, &far), vec![PostPass::Bloom, PostPass::Tonemap, PostPass::Fxaa]);
+    }
+
+    #[test]
+    fn test_bloom_sizes() {
+        assert_eq!(bloom_sizes((1600, 900)), [(800, 450), (400, 225), (200, 112), (100, 56)]);
+        assert_eq!(bloom_sizes((6, 3)), [(3, 1), (1, 1), (1, 1), (1, 1)]);
+    }
+}
@@ -0,0 +1,152 @@
+struct VertexOutput {
+    @location(0) uv: vec2<f32>,
+    @builtin(position) position: vec4<f32>,
+};
+
+struct PostUniform {
+    // the size of one pixel in the uv
+    texel: vec2<f32>,
+    exposure: f32,
+    vignette: f32,
+    aberration: f32,
+    // the luma starting the bloom
+    threshold: f32,
+    bloom: f32,
+    _pad: f32,
+};
+
+@group(0) @binding(0)
+var t_src: texture_2d<f32>;
+@group(0) @binding(1)
+var s_src: sampler;
+@group(0) @binding(2)
+var<uniform> post: PostUniform;
+// the glow target or the bloom, black if not used
+@group(0) @binding(3)
+var t_extra: texture_2d<f32>;
+
+// one triangle covers the whole screen.
+@vertex
+fn vs_main(@builtin(vertex_index) idx: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let uv = vec2<f32>(f32((idx << 1u) & 2u), f32(idx & 2u));
+    out.uv = vec2<f32>(uv.x, 1.0 - uv.y);
+    out.position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    return out;
+}
+
+// the curve is on the linear colors, the views hold them encoded.
+@fragment
+fn tonemap_fs(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_src, s_src, in.uv);
+    return vec4<f32>(linear_to_srgb(aces(srgb_to_linear(color.rgb) * post.exposure)), color.a);
+}
+
+fn luma(color: vec3<f32>) -> f32 {
+    return dot(color, vec3<f32>(0.299, 0.587, 0.114));
+}
+
+const FXAA_SPAN_MAX: f32 = 8.0;
+const FXAA_REDUCE_MUL: f32 = 0.125;
+const FXAA_REDUCE_MIN: f32 = 0.0078125;
+
+@fragment
+fn fxaa_fs(in: VertexOutput) -> @location(0) vec4<f32> {
+    let t = post.texel;
+    let nw = luma(textureSample(t_src, s_src, in.uv + vec2<f32>(-1.0, -1.0) * t).rgb);
+    let ne = luma(textureSample(t_src, s_src, in.uv + vec2<f32>(1.0, -1.0) * t).rgb);
+    let sw = luma(textureSample(t_src, s_src, in.uv + vec2<f32>(-1.0, 1.0) * t).rgb);
+    let se = luma(textureSample(t_src, s_src, in.uv + vec2<f32>(1.0, 1.0) * t).rgb);
+    let center = textureSample(t_src, s_src, in.uv);
+    let m = luma(center.rgb);
+    let luma_min = min(m, min(min(nw, ne), min(sw, se)));
+    let luma_max = max(m, max(max(nw, ne), max(sw, se)));
+
+    // blur along the edge
+    var dir = vec2<f32>(-((nw + ne) - (sw + se)), (nw + sw) - (ne + se));
+    let reduce = max((nw + ne + sw + se) * 0.25 * FXAA_REDUCE_MUL, FXAA_REDUCE_MIN);
+    let rcp = 1.0 / (min(abs(dir.x), abs(dir.y)) + reduce);
+    dir = clamp(dir * rcp, vec2<f32>(-FXAA_SPAN_MAX), vec2<f32>(FXAA_SPAN_MAX)) * t;
+
+    let a = 0.5 * (textureSample(t_src, s_src, in.uv + dir * (1.0 / 3.0 - 0.5)).rgb
+        + textureSample(t_src, s_src, in.uv + dir * (2.0 / 3.0 - 0.5)).rgb);
+    let b = a * 0.5 + 0.25 * (textureSample(t_src, s_src, in.uv - dir * 0.5).rgb
+        + textureSample(t_src, s_src, in.uv + dir * 0.5).rgb);
+    let luma_b = luma(b);
+    // the wider blur crossed the edge
+    let color = select(b, a, luma_b < luma_min || luma_b > luma_max);
+    return vec4<f32>(color, center.a);
+}
+
+@fragment
+fn vignette_fs(in: VertexOutput) -> @location(0) vec4<f32> {
+    let centered = in.uv - vec2<f32>(0.5);
+    // split the channels more at the edges
+    let offset = centered * post.aberration * 0.02;
+    let r = textureSample(t_src, s_src, in.uv + offset).r;
+    let center = textureSample(t_src, s_src, in.uv);
+    let b = textureSample(t_src, s_src, in.uv - offset).b;
+    // 0 at the center and 1 at the corners
+    let d = length(centered) * 1.41421356;
+    let v = 1.0 - post.vignette * smoothstep(0.4, 1.0, d);
+    return vec4<f32>(vec3<f32>(r, center.g, b) * v, center.a);
+}
+
+// the bright of the screen and the glow marked, to the half size
+@fragment
+fn bright_fs(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_src, s_src, in.uv).rgb;
+    let bright = color * max(luma(color) - post.threshold, 0.0) / max(1.0 - post.threshold, 0.0001);
+    return vec4<f32>(bright + textureSample(t_extra, s_src, in.uv).rgb, 1.0);
+}
+
+// to the half size of the source
+@fragment
+fn downsample_fs(in: VertexOutput) -> @location(0) vec4<f32> {
+    let t = 1.0 / vec2<f32>(textureDimensions(t_src));
+    var color = textureSample(t_src, s_src, in.uv).rgb * 4.0;
+    color += textureSample(t_src, s_src, in.uv + vec2<f32>(-t.x, -t.y)).rgb;
+    color += textureSample(t_src, s_src, in.uv + vec2<f32>(t.x, -t.y)).rgb;
+    color += textureSample(t_src, s_src, in.uv + vec2<f32>(-t.x, t.y)).rgb;
+    color += textureSample(t_src, s_src, in.uv + vec2<f32>(t.x, t.y)).rgb;
+    return vec4<f32>(color / 8.0, 1.0);
+}
+
+// the 9 taps gaussian by the linear sampling
+fn blur(uv: vec2<f32>, dir: vec2<f32>) -> vec4<f32> {
+    let t = dir / vec2<f32>(textureDimensions(t_src));
+    var color = textureSample(t_src, s_src, uv).rgb * 0.2270270270;
+    color += textureSample(t_src, s_src, uv + t * 1.3846153846).rgb * 0.3162162162;
+    color += textureSample(t_src, s_src, uv - t * 1.3846153846).rgb * 0.3162162162;
+    color += textureSample(t_src, s_src, uv + t * 3.2307692308).rgb * 0.0702702703;
+    color += textureSample(t_src, s_src, uv - t * 3.2307692308).rgb * 0.0702702703;
+    return vec4<f32>(color, 1.0);
+}
+
+@fragment
+fn blur_h_fs(in: VertexOutput) -> @location(0) vec4<f32> {
+    return blur(in.uv, vec2<f32>(1.0, 0.0));
+}
+
+@fragment
+fn blur_v_fs(in: VertexOutput) -> @location(0) vec4<f32> {
+    return blur(in.uv, vec2<f32>(0.0, 1.0));
+}
+
+// added to the larger target
+@fragment
+fn upsample_fs(in: VertexOutput) -> @location(0) vec4<f32> {
+    let t = 0.5 / vec2<f32>(textureDimensions(t_src));
+    var color = textureSample(t_src, s_src, in.uv + vec2<f32>(-t.x, -t.y)).rgb;
+    color += textureSample(t_src, s_src, in.uv + vec2<f32>(t.x, -t.y)).rgb;
+    color += textureSample(t_src, s_src, in.uv + vec2<f32>(-t.x, t.y)).rgb;
+    color += textureSample(t_src, s_src, in.uv + vec2<f32>(t.x, t.y)).rgb;
+    return vec4<f32>(color * 0.25, 1.0);
+}
+
+@fragment
+fn composite_fs(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_src, s_src, in.uv);
+    let bloom = textureSample(t_extra, s_src, in.uv).rgb;
+    return vec4<f32>(color.rgb + bloom * post.bloom, color.a);
+}
@@ -0,0 +1,155 @@
+use wgpu::{AddressMode, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+           BindGroupLayoutEntry, BindingResource, BindingType, Color, ColorTargetState, ColorWrites,
+           CommandEncoder, Device, FilterMode, PipelineLayout, PrimitiveState, PrimitiveTopology, RenderPipeline, Sampler,
+           SamplerBindingType, SamplerDescriptor, ShaderModule, ShaderModuleDescriptor, ShaderSource, ShaderStages,
+           TextureFormat, TextureSampleType, TextureView, TextureViewDimension};
+
+use crate::engine::render_ext::CommandEncoderExt;
+use crate::engine::{OutputFormats, WgpuData};
+
+/// Draw the screen to the surface not able to be copied to, like in the other format or taking the HDR.
+///
+/// The bright colors are tonemapped for the 8 bits target, unless the post pass tonemapped them already.
+#[allow(unused)]
+#[derive(Debug)]
+pub struct PresentRenderer {
+    layout: BindGroupLayout,
+    sampler: Sampler,
+    render_pipeline: RenderPipeline,
+    /// The pipeline for the screen tonemapped already.
+    tonemapped_pipeline: RenderPipeline,
+}
+
+fn create_pipeline(device: &Device, layout: &PipelineLayout, shader: &ShaderModule, entry_point: &str, format: TextureFormat) -> RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("present pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point,
+            targets: &[Some(ColorTargetState {
+                format,
+                blend: None,
+                write_mask: ColorWrites::ALL,
+            })],
+        }),
+        primitive: PrimitiveState {
+            topology: PrimitiveTopology::TriangleList,
+            ..Default::default()
+        },
+        depth_stencil: None,
+        multisample: Default::default(),
+        multiview: None,
+    })
+}
+
+/// The format of the target drawn to the surface and whether it takes the HDR.
+fn surface_target(formats: &OutputFormats) -> (TextureFormat, bool) {
+    (formats.present_format(), formats.is_hdr_output())
+}
+
+#[allow(unused)]
+impl PresentRenderer {
+    pub fn new(state: &WgpuData) -> Self {
+        let (format, hdr) = surface_target(&state.formats);
+        Self::with_target(state, format, hdr)
+    }
+
+    /// Draw to the target in the `format`, taking the extended linear colors if `hdr`.
+    pub fn with_target(state: &WgpuData, format: TextureFormat, hdr: bool) -> Self {
+        let device = &state.device;
+
+        let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("present bind layout"),
+            entries: &[BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: true },
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            }, BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                count: None,
+            }],
+        });
+
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            mag_filter: FilterMode::Nearest,
+            min_filter: FilterMode::Nearest,
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            ..Default::default()
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("present pipeline layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("present shader"),
+            source: ShaderSource::Wgsl(concat!(include_str!("color.wgsl"), include_str!("present.wgsl")).into()),
+        });
+
+        // the surface taking the HDR shows the bright ones as they are
+        let (entry, tonemapped_entry) = if hdr { ("fs_scrgb", "fs_scrgb") } else { ("fs_tonemap", "fs_clamp") };
+        let render_pipeline = create_pipeline(device, &pipeline_layout, &shader, entry, format);
+        let tonemapped_pipeline = create_pipeline(device, &pipeline_layout, &shader, tonemapped_entry, format);
+
+        Self {
+            layout,
+            sampler,
+            render_pipeline,
+            tonemapped_pipeline,
+        }
+    }
+
+    /// Draw the screen to the `target`, `tonemapped` if the post pass tonemapped the screen.
+    pub fn present(&self, state: &WgpuData, encoder: &mut CommandEncoder, target: &TextureView, tonemapped: bool) {
+        let bind_group = state.device.create_bind_group(&BindGroupDescriptor {
+            label: Some("present bind group"),
+            layout: &self.layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(&state.views.get_screen().view),
+            }, BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::Sampler(&self.sampler),
+            }],
+        });
+        let mut rp = encoder.begin_clear_color(target, Color::BLACK, true);
+        rp.set_pipeline(if tonemapped { &self.tonemapped_pipeline } else { &self.render_pipeline });
+        rp.set_bind_group(0, &bind_group, &[]);
+        rp.draw(0..3, 0..1);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use wgpu::TextureFormat;
+
+    use crate::engine::render::present::surface_target;
+    use crate::engine::render::state::{HDR_FORMAT, OutputFormats};
+
+    #[test]
+    fn test_surface_target() {
+        // the colors encoded by the pass are not encoded again by the sRGB surface
+        let sdr = OutputFormats::negotiate(&[TextureFormat::Rgba8UnormSrgb], true);
+        assert_eq!(surface_target(&sdr), (TextureFormat::Rgba8Unorm, false));
+        let bgra = OutputFormats { surface: TextureFormat::Bgra8UnormSrgb, color: HDR_FORMAT };
+        assert_eq!(surface_target(&bgra), (TextureFormat::Bgra8Unorm, false));
+        let hdr = OutputFormats::negotiate(&[HDR_FORMAT], true);
+        assert_eq!(surface_target(&hdr), (HDR_FORMAT, true));
+    }
+}
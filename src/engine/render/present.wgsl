@@ -0,0 +1,40 @@
+struct VertexOutput {
+    @location(0) uv: vec2<f32>,
+    @builtin(position) position: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var t_src: texture_2d<f32>;
+@group(0) @binding(1)
+var s_src: sampler;
+
+// one triangle covers the whole screen.
+@vertex
+fn vs_main(@builtin(vertex_index) idx: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let uv = vec2<f32>(f32((idx << 1u) & 2u), f32(idx & 2u));
+    out.uv = vec2<f32>(uv.x, 1.0 - uv.y);
+    out.position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    return out;
+}
+
+// the colors are mapped to 1 by the post pass already, only the rounding above clamped.
+@fragment
+fn fs_clamp(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_src, s_src, in.uv);
+    return vec4<f32>(clamp(color.rgb, vec3<f32>(0.0), vec3<f32>(1.0)), 1.0);
+}
+
+// the bright ones are mapped under 1 on the linear colors, then encoded again for the 8 bits.
+@fragment
+fn fs_tonemap(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_src, s_src, in.uv);
+    return vec4<f32>(linear_to_srgb(aces(srgb_to_linear(color.rgb))), 1.0);
+}
+
+// the scRGB surface takes the linear colors, 1 as the white of the SDR.
+@fragment
+fn fs_scrgb(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_src, s_src, in.uv);
+    return vec4<f32>(srgb_to_linear(color.rgb), 1.0);
+}
@@ -0,0 +1,321 @@
+use std::collections::VecDeque;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{Receiver, sync_channel, SyncSender, TrySendError};
+use std::thread::JoinHandle;
+
+use anyhow::bail;
+use log::{info, warn};
+use wgpu::*;
+
+use crate::engine::render::present::PresentRenderer;
+use crate::engine::{TextureWrapper, WgpuData};
+
+/// The frames waiting to be read back at most, the frame is dropped if all are waiting.
+const READBACKS: usize = 3;
+/// The frames read back but not written at most, the frame is dropped if the writer is behind.
+const WRITE_QUEUE: usize = 8;
+/// The directory of the images recorded by default.
+pub const DEFAULT_FRAMES_DIR: &str = "frames";
+
+/// Where the frames recorded go.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FrameSink {
+    /// The png files numbered in the directory.
+    Images(PathBuf),
+    /// The raw rgba frames piped to the stdin of the encoder command, such as ffmpeg.
+    Pipe(String),
+}
+
+/// The rgba pixels of the frame presented.
+struct RecordedFrame {
+    index: u64,
+    width: u32,
+    height: u32,
+    rgba: Vec<u8>,
+}
+
+#[derive(Debug, Default)]
+struct Readback {
+    /// The buffer and the size of the frame copied in it, created when first used.
+    buffer: Option<(Buffer, (u32, u32))>,
+    /// The index of the frame copied, none if free.
+    frame: Option<u64>,
+    /// Set when the map finished, false if failed.
+    mapped: Arc<Mutex<Option<bool>>>,
+}
+
+/// Whether the pixels are 8 bits bgra, none if not 8 bits rgba nor bgra.
+fn is_bgra(format: TextureFormat) -> Option<bool> {
+    match format {
+        TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb => Some(false),
+        TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb => Some(true),
+        _ => None,
+    }
+}
+
+/// The bytes of the row copied from the texture, aligned for the copy.
+fn padded_row(width: u32) -> u32 {
+    let align = COPY_BYTES_PER_ROW_ALIGNMENT;
+    (width * 4 + align - 1) / align * align
+}
+
+/// Remove the padding of the rows and swap the blue and red if the pixels are bgra.
+fn unpad_rows(data: &[u8], width: u32, height: u32, padded: u32, bgra: bool) -> Vec<u8> {
+    let row = width as usize * 4;
+    let mut rgba = Vec::with_capacity(row * height as usize);
+    for x in data.chunks(padded as usize).take(height as usize) {
+        rgba.extend_from_slice(&x[..row]);
+    }
+    if bgra {
+        rgba.chunks_exact_mut(4).for_each(|x| x.swap(0, 2));
+    }
+    rgba
+}
+
+fn write_frames(sink: FrameSink, mut child: Option<Child>, frames: Receiver<RecordedFrame>) -> anyhow::Result<()> {
+    let mut stdin = child.as_mut().and_then(|x| x.stdin.take());
+    for frame in frames {
+        match (&sink, stdin.as_mut()) {
+            (FrameSink::Images(dir), _) => {
+                let path = dir.join(format!("frame_{:06}.png", frame.index));
+                image::save_buffer(path, &frame.rgba, frame.width, frame.height, image::ColorType::Rgba8)?;
+            }
+            (FrameSink::Pipe(_), Some(stdin)) => stdin.write_all(&frame.rgba)?,
+            (FrameSink::Pipe(_), None) => bail!("The encoder has no stdin"),
+        }
+    }
+    // the encoder finishes the file after the stdin closed
+    drop(stdin);
+    if let Some(mut child) = child {
+        let status = child.wait()?;
+        if !status.success() {
+            bail!("The encoder exited with {}", status);
+        }
+    }
+    Ok(())
+}
+
+/// Read back the frames presented by the window and write them to the sink for making the videos.
+///
+/// The frames are copied from the screen buffer after the ui painted, every nth frame presented is recorded.
+/// The screen in HDR is drawn to the 8 bits by the present pass first, tonemapped as the surface shows it.
+pub struct FrameRecorder {
+    sink: FrameSink,
+    every: u32,
+    bgra: bool,
+    /// The present pass and the 8 bits target for the screen not in 8 bits, created when first used.
+    convert: Option<(PresentRenderer, TextureWrapper)>,
+    /// The frames presented since started.
+    presented: u64,
+    recorded: u64,
+    dropped: u64,
+    readbacks: Vec<Readback>,
+    /// The readbacks in the order copied.
+    pending: VecDeque<usize>,
+    sender: Option<SyncSender<RecordedFrame>>,
+    writer: Option<JoinHandle<anyhow::Result<()>>>,
+}
+
+#[allow(unused)]
+impl FrameRecorder {
+    /// Start the writer of the sink.
+    pub fn new(gpu: &WgpuData, sink: FrameSink, every: u32) -> anyhow::Result<Self> {
+        let child = match &sink {
+            FrameSink::Images(dir) => {
+                std::fs::create_dir_all(dir)?;
+                None
+            }
+            FrameSink::Pipe(command) => {
+                let mut args = command.split_whitespace();
+                let program = if let Some(x) = args.next() { x } else {
+                    bail!("No encoder command");
+                };
+                Some(Command::new(program)
+                    .args(args)
+                    .stdin(Stdio::piped())
+                    .spawn()?)
+            }
+        };
+        let (sender, receiver) = sync_channel(WRITE_QUEUE);
+        let writer_sink = sink.clone();
+        let writer = std::thread::Builder::new()
+            .name("Frame Writer".into())
+            .spawn(move || write_frames(writer_sink, child, receiver))?;
+        let (width, height) = gpu.get_screen_size();
+        info!(target: "recorder", "Recording the {}x{} rgba frames every {} to {:?}", width, height, every.max(1), sink);
+        Ok(Self {
+            sink,
+            every: every.max(1),
+            bgra: false,
+            convert: None,
+            presented: 0,
+            recorded: 0,
+            dropped: 0,
+            readbacks: (0..READBACKS).map(|_| Readback::default()).collect(),
+            pending: Default::default(),
+            sender: Some(sender),
+            writer: Some(writer),
+        })
+    }
+
+    pub fn sink(&self) -> &FrameSink {
+        &self.sink
+    }
+
+    /// The frames recorded and the frames dropped for the readbacks or the writer behind.
+    pub fn counts(&self) -> (u64, u64) {
+        (self.recorded, self.dropped)
+    }
+
+    /// Copy the screen buffer of the frame to read back, call before presented.
+    ///
+    /// `tonemapped` if the post pass tonemapped the screen.
+    pub fn capture(&mut self, gpu: &WgpuData, tonemapped: bool) {
+        self.presented += 1;
+        if (self.presented - 1) % self.every as u64 != 0 {
+            return;
+        }
+        let idx = if let Some(x) = self.readbacks.iter().position(|x| x.frame.is_none()) { x } else {
+            self.dropped += 1;
+            return;
+        };
+        let size = gpu.get_screen_size();
+        let readback = &mut self.readbacks[idx];
+        if readback.buffer.as_ref().map_or(true, |x| x.1 != size) {
+            readback.buffer = Some((gpu.device.create_buffer(&BufferDescriptor {
+                label: Some("Frame recorder readback"),
+                size: padded_row(size.0) as BufferAddress * size.1 as BufferAddress,
+                usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }), size));
+        }
+        let buffer = &readback.buffer.as_ref().unwrap().0;
+        let mut encoder = gpu.device.create_command_encoder(&CommandEncoderDescriptor { label: Some("Frame recorder encoder") });
+        let texture = if let Some(bgra) = is_bgra(gpu.formats.color) {
+            self.bgra = bgra;
+            &gpu.views.get_screen().texture
+        } else {
+            if self.convert.as_ref().map_or(true, |x| (x.1.info.width, x.1.info.height) != size) {
+                let present = match self.convert.take() {
+                    Some((x, _)) => x,
+                    None => PresentRenderer::with_target(gpu, TextureFormat::Rgba8Unorm, false),
+                };
+                self.convert = Some((present, TextureWrapper::new_with_size(&gpu.device, TextureFormat::Rgba8Unorm, size)));
+            }
+            let (present, target) = self.convert.as_ref().unwrap();
+            present.present(gpu, &mut encoder, &target.view, tonemapped);
+            self.bgra = false;
+            &target.texture
+        };
+        encoder.copy_texture_to_buffer(ImageCopyTexture {
+            texture,
+            mip_level: 0,
+            origin: Origin3d::default(),
+            aspect: TextureAspect::All,
+        }, ImageCopyBuffer {
+            buffer,
+            layout: ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_row(size.0)),
+                rows_per_image: None,
+            },
+        }, Extent3d {
+            width: size.0,
+            height: size.1,
+            depth_or_array_layers: 1,
+        });
+        gpu.queue.submit(Some(encoder.finish()));
+
+        readback.frame = Some(self.presented - 1);
+        let mapped = readback.mapped.clone();
+        buffer.slice(..).map_async(MapMode::Read, move |x| {
+            *mapped.lock().unwrap() = Some(x.is_ok());
+        });
+        self.pending.push_back(idx);
+    }
+
+    /// Send the frames mapped to the writer in the order copied, wait for all copied if `wait`.
+    pub fn poll(&mut self, device: &Device, wait: bool) {
+        device.poll(if wait { Maintain::Wait } else { Maintain::Poll });
+        while let Some(&idx) = self.pending.front() {
+            let readback = &mut self.readbacks[idx];
+            let mapped = readback.mapped.lock().unwrap().take();
+            let ok = if let Some(x) = mapped { x } else {
+                break;
+            };
+            self.pending.pop_front();
+            let index = readback.frame.take().unwrap_or_default();
+            let (buffer, (width, height)) = if let Some(x) = readback.buffer.as_ref() { x } else {
+                continue;
+            };
+            if ok {
+                let rgba = {
+                    let data = buffer.slice(..).get_mapped_range();
+                    unpad_rows(&data, *width, *height, padded_row(*width), self.bgra)
+                };
+                buffer.unmap();
+                let frame = RecordedFrame { index: index / self.every as u64, width: *width, height: *height, rgba };
+                match self.sender.as_ref().map(|x| x.try_send(frame)) {
+                    Some(Ok(_)) => self.recorded += 1,
+                    Some(Err(TrySendError::Full(_))) => self.dropped += 1,
+                    _ => {
+                        warn!(target: "recorder", "The frame writer stopped");
+                        self.dropped += 1;
+                    }
+                }
+            } else {
+                self.dropped += 1;
+            }
+        }
+    }
+
+    /// Forget the frames copied and the buffers, for the gpu created again.
+    pub fn reset(&mut self) {
+        self.dropped += self.pending.len() as u64;
+        self.pending.clear();
+        self.readbacks.iter_mut().for_each(|x| *x = Readback::default());
+        self.convert = None;
+    }
+
+    /// Write the frames copied and wait for the writer finished.
+    pub fn finish(mut self, device: &Device) -> anyhow::Result<()> {
+        self.poll(device, true);
+        self.sender = None;
+        if let Some(writer) = self.writer.take() {
+            match writer.join() {
+                Ok(result) => result?,
+                Err(_) => bail!("The frame writer panicked"),
+            }
+        }
+        info!(target: "recorder", "Recorded {} frames to {:?}, {} dropped", self.recorded, self.sink, self.dropped);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use wgpu::TextureFormat;
+
+    use crate::engine::render::recorder::{is_bgra, padded_row, unpad_rows};
+
+    #[test]
+    fn test_unpad_rows() {
+        assert_eq!(padded_row(64), 256);
+        assert_eq!(padded_row(65), 512);
+
+        let width = 2;
+        let padded = padded_row(width);
+        let mut data = vec![0u8; padded as usize * 2];
+        data[..8].copy_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]);
+        data[padded as usize..padded as usize + 8].copy_from_slice(&[9, 10, 11, 12, 13, 14, 15, 16]);
+        assert_eq!(unpad_rows(&data, width, 2, padded, false), vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16]);
+        assert_eq!(unpad_rows(&data, width, 2, padded, true), vec![3, 2, 1, 4, 7, 6, 5, 8, 11, 10, 9, 12, 15, 14, 13, 16]);
+
+        assert_eq!(is_bgra(TextureFormat::Rgba8UnormSrgb), Some(false));
+        assert_eq!(is_bgra(TextureFormat::Bgra8Unorm), Some(true));
+        assert_eq!(is_bgra(TextureFormat::Rgba16Float), None);
+    }
+}
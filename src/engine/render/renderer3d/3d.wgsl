@@ -10,12 +10,47 @@ struct Light {
     height: f32,
 }
 
+struct PointLight {
+    pos: vec3<f32>,
+    range: f32,
+    color: vec3<f32>,
+    outer_cos: f32,
+    dir: vec3<f32>,
+    inner_cos: f32,
+}
+
+struct PointLights {
+    ambient: vec3<f32>,
+    count: u32,
+    lights: array<PointLight, 16>,
+}
+
 @group(0) @binding(0)
 var<uniform> camera: Camera;
 @group(0) @binding(1)
 var s_diffuse: sampler;
 @group(0) @binding(2)
 var<uniform> light: Light;
+@group(0) @binding(3)
+var<uniform> point_lights: PointLights;
+
+fn point_lights_color(pos: vec3<f32>, normal: vec3<f32>) -> vec3<f32> {
+    var result = vec3<f32>(0.0, 0.0, 0.0);
+    for (var i = 0u; i < point_lights.count; i += 1u) {
+        let l = point_lights.lights[i];
+        let to_light = l.pos - pos;
+        let dist = length(to_light);
+        if (dist >= l.range) {
+            continue;
+        }
+        let dir = to_light / dist;
+        let att = 1.0 - dist / l.range;
+        // point light has outer_cos < -1 so the cone is always 1.
+        let cone = smoothstep(l.outer_cos, l.inner_cos, dot(-dir, l.dir));
+        result += l.color * max(dot(normal, dir), 0.0) * att * att * cone;
+    }
+    return result;
+}
 
 struct PlaneVertexIn {
     @location(0) position: vec3<f32>,
@@ -27,6 +62,7 @@ struct PlaneVertexOut {
     @builtin(position) pos: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
     @location(1) normal: vec3<f32>,
+    @location(2) world_pos: vec3<f32>,
 }
 
 @vertex
@@ -36,6 +72,42 @@ fn plane_vs(input: PlaneVertexIn) -> PlaneVertexOut {
     out.tex_coords = input.tex_coords;
     out.pos = camera.view_proj * vec4<f32>(input.position, 1.0);
     out.normal = input.normal;
+    out.world_pos = input.position;
+
+    return out;
+}
+
+struct ModelInstanceIn {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+    @location(9) normal_matrix_0: vec3<f32>,
+    @location(10) normal_matrix_1: vec3<f32>,
+    @location(11) normal_matrix_2: vec3<f32>,
+}
+
+// the model vertex has the same layout as the plane vertex.
+@vertex
+fn model_vs(input: PlaneVertexIn, instance: ModelInstanceIn) -> PlaneVertexOut {
+    var out: PlaneVertexOut;
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let normal_matrix = mat3x3<f32>(
+        instance.normal_matrix_0,
+        instance.normal_matrix_1,
+        instance.normal_matrix_2,
+    );
+    let world_pos = model_matrix * vec4<f32>(input.position, 1.0);
+
+    out.tex_coords = input.tex_coords;
+    out.pos = camera.view_proj * world_pos;
+    out.normal = normalize(normal_matrix * input.normal);
+    out.world_pos = world_pos.xyz;
 
     return out;
 }
@@ -56,6 +128,7 @@ fn plane_vs_full_tex(input: PlaneVertexIn, @builtin(vertex_index) vidx: u32) ->
     }
     out.pos = camera.view_proj * vec4<f32>(input.position, 1.0);
     out.normal = input.normal;
+    out.world_pos = input.position;
     return out;
 }
 
@@ -66,12 +139,54 @@ var t_diffuse: texture_2d<f32>;
 fn plane_fs(in: PlaneVertexOut) -> @location(0) vec4<f32> {
 
     let object_color: vec4<f32> = textureSample(t_diffuse, s_diffuse, in.tex_coords);
-    let ambient_color = vec3<f32>(1.0, 1.0, 1.0) * 0.25;
-    let diffuse_strength = max(dot(in.normal, light.dir), 0.0) * 0.75;
-    let diffuse_color = light.color * diffuse_strength;
-    let result = vec4<f32>((ambient_color + diffuse_color) * object_color.rgb, object_color.a);
+    return lit_color(object_color, in.world_pos, in.normal);
+}
 
-    return result;
+fn lit_color(object_color: vec4<f32>, world_pos: vec3<f32>, normal: vec3<f32>) -> vec4<f32> {
+    let ambient_color = point_lights.ambient;
+    let diffuse_strength = max(dot(normal, light.dir), 0.0) * 0.75;
+    let diffuse_color = light.color * diffuse_strength + point_lights_color(world_pos, normal);
+    return vec4<f32>(linear_to_srgb((ambient_color + diffuse_color) * object_color.rgb), object_color.a);
+}
+
+struct PlaneArrayVertexIn {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(3) layer: u32,
+}
+
+struct PlaneArrayVertexOut {
+    @builtin(position) pos: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) world_pos: vec3<f32>,
+    @location(3) @interpolate(flat) layer: u32,
+}
+
+@vertex
+fn plane_array_vs(input: PlaneArrayVertexIn) -> PlaneArrayVertexOut {
+    var out: PlaneArrayVertexOut;
+
+    out.tex_coords = input.tex_coords;
+    out.pos = camera.view_proj * vec4<f32>(input.position, 1.0);
+    out.normal = input.normal;
+    out.world_pos = input.position;
+    out.layer = input.layer;
+
+    return out;
+}
+
+// the texture array with its own sampler
+@group(1) @binding(0)
+var t_array: texture_2d_array<f32>;
+@group(1) @binding(1)
+var s_array: sampler;
+
+@fragment
+fn plane_array_fs(in: PlaneArrayVertexOut) -> @location(0) vec4<f32> {
+    let object_color: vec4<f32> = textureSample(t_array, s_array, in.tex_coords, in.layer);
+    return lit_color(object_color, in.world_pos, in.normal);
 }
 
 @fragment
@@ -95,3 +210,165 @@ fn plane_pos_tex_fs(in: PlaneVertexOut) -> @location(0) vec4<f32> {
 
     return object_color;
 }
+
+struct LineVertexIn {
+    @location(0) position: vec3<f32>,
+    @location(1) color: vec4<f32>,
+}
+
+struct LineVertexOut {
+    @builtin(position) pos: vec4<f32>,
+    @location(0) color: vec4<f32>,
+}
+
+@vertex
+fn line_vs(input: LineVertexIn) -> LineVertexOut {
+    var out: LineVertexOut;
+    out.pos = camera.view_proj * vec4<f32>(input.position, 1.0);
+    out.color = input.color;
+    return out;
+}
+
+@fragment
+fn line_fs(in: LineVertexOut) -> @location(0) vec4<f32> {
+    return in.color;
+}
+
+struct PbrVertexOut {
+    @builtin(position) pos: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) world_pos: vec3<f32>,
+    @location(3) tangent: vec4<f32>,
+}
+
+struct PlanePbrVertexIn {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(4) tangent: vec3<f32>,
+}
+
+@vertex
+fn plane_pbr_vs(input: PlanePbrVertexIn) -> PbrVertexOut {
+    var out: PbrVertexOut;
+
+    out.tex_coords = input.tex_coords;
+    out.pos = camera.view_proj * vec4<f32>(input.position, 1.0);
+    out.normal = input.normal;
+    out.world_pos = input.position;
+    out.tangent = vec4<f32>(input.tangent, 1.0);
+
+    return out;
+}
+
+struct ModelPbrVertexIn {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(3) tangent: vec4<f32>,
+}
+
+@vertex
+fn model_pbr_vs(input: ModelPbrVertexIn, instance: ModelInstanceIn) -> PbrVertexOut {
+    var out: PbrVertexOut;
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let normal_matrix = mat3x3<f32>(
+        instance.normal_matrix_0,
+        instance.normal_matrix_1,
+        instance.normal_matrix_2,
+    );
+    let world_pos = model_matrix * vec4<f32>(input.position, 1.0);
+    let tangent_matrix = mat3x3<f32>(model_matrix[0].xyz, model_matrix[1].xyz, model_matrix[2].xyz);
+
+    out.tex_coords = input.tex_coords;
+    out.pos = camera.view_proj * world_pos;
+    out.normal = normalize(normal_matrix * input.normal);
+    out.world_pos = world_pos.xyz;
+    out.tangent = vec4<f32>(normalize(tangent_matrix * input.tangent.xyz), input.tangent.w);
+
+    return out;
+}
+
+struct Material {
+    base_color: vec4<f32>,
+    emissive: vec3<f32>,
+    metallic: f32,
+    roughness: f32,
+    normal_scale: f32,
+    _pad: vec2<f32>,
+}
+
+// the base color is t_diffuse in binding 0
+@group(1) @binding(1)
+var t_normal: texture_2d<f32>;
+@group(1) @binding(2)
+var t_metallic_roughness: texture_2d<f32>;
+@group(1) @binding(3)
+var t_emissive: texture_2d<f32>;
+@group(1) @binding(4)
+var<uniform> material: Material;
+
+const PI: f32 = 3.14159265;
+
+// the normal in the normal texture by the tangent space
+fn pbr_normal(in: PbrVertexOut) -> vec3<f32> {
+    let n = normalize(in.normal);
+    let t = normalize(in.tangent.xyz - n * dot(n, in.tangent.xyz));
+    let b = cross(n, t) * in.tangent.w;
+    let sampled = textureSample(t_normal, s_diffuse, in.tex_coords).xyz * 2.0 - 1.0;
+    let local = vec3<f32>(sampled.xy * material.normal_scale, sampled.z);
+    return normalize(mat3x3<f32>(t, b, n) * local);
+}
+
+// the cook-torrance specular and the lambert diffuse lit by the directional light
+fn pbr_direct(base: vec3<f32>, metallic: f32, roughness: f32, n: vec3<f32>, v: vec3<f32>) -> vec3<f32> {
+    let l = normalize(light.dir);
+    let h = normalize(v + l);
+    let n_l = max(dot(n, l), 0.0);
+    let n_v = max(dot(n, v), 0.0001);
+    let n_h = max(dot(n, h), 0.0);
+    let v_h = max(dot(v, h), 0.0);
+
+    let a = roughness * roughness;
+    let a2 = a * a;
+    let d_denom = n_h * n_h * (a2 - 1.0) + 1.0;
+    let d = a2 / (PI * d_denom * d_denom);
+    let k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+    let g = (n_l / (n_l * (1.0 - k) + k)) * (n_v / (n_v * (1.0 - k) + k));
+    let f0 = mix(vec3<f32>(0.04, 0.04, 0.04), base, metallic);
+    let f = f0 + (1.0 - f0) * pow(1.0 - v_h, 5.0);
+
+    let specular = d * g * f / max(4.0 * n_l * n_v, 0.0001);
+    let diffuse = (1.0 - f) * (1.0 - metallic) * base / PI;
+    // scaled by pi to match the intensity of the lambert planes
+    return (diffuse + specular) * light.color * n_l * PI;
+}
+
+@fragment
+fn pbr_fs(in: PbrVertexOut) -> @location(0) vec4<f32> {
+    let base = textureSample(t_diffuse, s_diffuse, in.tex_coords) * material.base_color;
+    let mr = textureSample(t_metallic_roughness, s_diffuse, in.tex_coords);
+    let metallic = clamp(mr.b * material.metallic, 0.0, 1.0);
+    let roughness = clamp(mr.g * material.roughness, 0.04, 1.0);
+    let emissive = textureSample(t_emissive, s_diffuse, in.tex_coords).rgb * material.emissive;
+    let n = pbr_normal(in);
+    let v = normalize(camera.view_pos.xyz - in.world_pos);
+
+    let ambient_color = point_lights.ambient * base.rgb;
+    let direct = pbr_direct(base.rgb, metallic, roughness, n, v);
+    let points = point_lights_color(in.world_pos, n) * base.rgb;
+    return vec4<f32>(linear_to_srgb(ambient_color + direct + points + emissive), base.a);
+}
+
+// the emissive only, to the glow target for the bloom
+@fragment
+fn emissive_fs(in: PbrVertexOut) -> @location(0) vec4<f32> {
+    let emissive = textureSample(t_emissive, s_diffuse, in.tex_coords).rgb * material.emissive;
+    return vec4<f32>(linear_to_srgb(emissive), 1.0);
+}
@@ -1,2 +1,4 @@
 pub mod renderer3d;
+pub mod skybox;
+pub mod text;
 
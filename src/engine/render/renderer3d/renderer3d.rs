@@ -1,4 +1,4 @@
-//! Render 3d renderer3d with texture without lights.
+//! Render 3d renderer3d with texture with a directional light and point / spot lights.
 //!
 //! Vertex use world pos.
 //!
@@ -8,11 +8,19 @@ use std::array::from_ref;
 use std::mem::size_of;
 
 use bytemuck::{Pod, Zeroable};
-use nalgebra::{vector, Vector2, Vector3};
+use nalgebra::{Quaternion, vector, Vector2, Vector3};
 use wgpu::util::{BufferInitDescriptor, DeviceExt, RenderEncoder};
 
+use crate::engine::glft::instance::{GltfInstance, InstanceRaw};
+use crate::engine::glft::model::{MaterialFactors, Model, ModelVertex};
+use crate::engine::glft::ModelObject;
+use crate::engine::glft::renderer::RendererConfig;
+use crate::engine::pacing::mark_frame_event;
+use crate::engine::render::arena::{BufferArena, BufferRange};
+use crate::engine::renderer3d::skybox::SkyboxRenderer;
+use crate::engine::renderer3d::text::WorldTextRenderer;
 use crate::engine::prelude::*;
-use crate::engine::uniform::{CAMERA_BIND_GROUP_ENTRY, uniform_bind_buffer_layout_entry};
+use crate::engine::uniform::{CAMERA_BIND_GROUP_ENTRY, uniform_bind_buffer_layout_entry, UniformStager};
 
 #[repr(C)]
 #[derive(Pod, Zeroable, Default, Copy, Clone, Debug)]
@@ -20,6 +28,10 @@ pub struct PlaneVertex {
     pub pos: Vector3<f32>,
     pub tex_coord: Vector2<f32>,
     pub normal: Vector3<f32>,
+    /// The layer of the texture array, ignored by the single texture.
+    pub layer: u32,
+    /// Toward the increasing u, for the normal textures.
+    pub tangent: Vector3<f32>,
 }
 
 
@@ -32,6 +44,59 @@ pub struct LightUniform {
     pub height: f32,
 }
 
+/// The max point / spot lights in [`PlaneRenderer`]
+pub const MAX_POINT_LIGHTS: usize = 16;
+
+/// A point light or spot light.
+#[repr(C)]
+#[derive(Pod, Zeroable, Default, Copy, Clone, Debug)]
+pub struct PointLight {
+    pub pos: Vector3<f32>,
+    /// The distance where the light fades to zero.
+    pub range: f32,
+    pub color: Vector3<f32>,
+    /// The cos of the outer cone angle, less than -1 for point light.
+    pub outer_cos: f32,
+    /// The direction for spot light.
+    pub dir: Vector3<f32>,
+    /// The cos of the inner cone angle, less than -1 for point light.
+    pub inner_cos: f32,
+}
+
+#[allow(unused)]
+impl PointLight {
+    pub fn point(pos: Vector3<f32>, color: Vector3<f32>, range: f32) -> Self {
+        Self {
+            pos,
+            range,
+            color,
+            outer_cos: -2.0,
+            dir: Vector3::z(),
+            inner_cos: -1.5,
+        }
+    }
+
+    /// The light is full in the `inner` angle and fades to zero at the `outer` angle (in radians).
+    pub fn spot(pos: Vector3<f32>, dir: Vector3<f32>, color: Vector3<f32>, range: f32, inner: f32, outer: f32) -> Self {
+        Self {
+            pos,
+            range,
+            color,
+            outer_cos: outer.cos(),
+            dir: dir.normalize(),
+            inner_cos: inner.cos(),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Pod, Zeroable, Copy, Clone)]
+struct PointLightsUniform {
+    ambient: Vector3<f32>,
+    count: u32,
+    lights: [PointLight; MAX_POINT_LIGHTS],
+}
+
 #[repr(C)]
 #[derive(Pod, Zeroable, Default, Copy, Clone, Debug)]
 pub struct PlaneObject {
@@ -68,18 +133,28 @@ impl PlaneObject {
                 pos: axis_left + axis_forward + center,
                 tex_coord,
                 normal: *up,
+                layer: 0,
+                tangent: forward,
             }
         }).collect::<Vec<_>>().try_into().unwrap();
         Self {
             vertex,
         }
     }
+
+    /// Sample the layer of the texture array bound.
+    pub fn with_layer(mut self, layer: u32) -> Self {
+        for x in &mut self.vertex {
+            x.layer = layer;
+        }
+        self
+    }
 }
 
 impl Vertex for PlaneVertex {
     fn desc<'a>() -> VertexBufferLayout<'a> {
         VertexBufferLayout {
-            array_stride: size_of::<[f32; 8]>() as _,
+            array_stride: size_of::<PlaneVertex>() as _,
             step_mode: VertexStepMode::Vertex,
             attributes: &[VertexAttribute {
                 format: VertexFormat::Float32x3,
@@ -93,13 +168,47 @@ impl Vertex for PlaneVertex {
                 format: VertexFormat::Float32x3,
                 offset: 20,
                 shader_location: 2,
+            }, VertexAttribute {
+                format: VertexFormat::Uint32,
+                offset: 32,
+                shader_location: 3,
+            }, VertexAttribute {
+                format: VertexFormat::Float32x3,
+                offset: 36,
+                shader_location: 4,
             }],
         }
     }
 }
 
 
-// group 0 for base layout: camera sampler light
+/// The vertex of [`StaticLines`], two vertices a line.
+#[repr(C)]
+#[derive(Pod, Zeroable, Default, Copy, Clone, Debug)]
+pub struct LineVertex {
+    pub pos: Vector3<f32>,
+    pub color: [f32; 4],
+}
+
+impl Vertex for LineVertex {
+    fn desc<'a>() -> VertexBufferLayout<'a> {
+        VertexBufferLayout {
+            array_stride: size_of::<LineVertex>() as _,
+            step_mode: VertexStepMode::Vertex,
+            attributes: &[VertexAttribute {
+                format: VertexFormat::Float32x3,
+                offset: 0,
+                shader_location: 0,
+            }, VertexAttribute {
+                format: VertexFormat::Float32x4,
+                offset: 12,
+                shader_location: 1,
+            }],
+        }
+    }
+}
+
+// group 0 for base layout: camera sampler light point_lights
 // group 1 for planes using the same texture
 pub struct PlaneRenderer {
     /// Group0.
@@ -107,12 +216,54 @@ pub struct PlaneRenderer {
     /// Group1.
     /// Bindings 0: texture view
     pub obj_layout: BindGroupLayout,
+    /// Group1 of the materials.
+    /// Bindings 0: base color, 1: normal, 2: metallic roughness, 3: emissive, 4: factors
+    pub material_layout: BindGroupLayout,
+    /// Group1 of the texture arrays.
+    /// Bindings 0: texture array view, 1: sampler
+    pub array_layout: BindGroupLayout,
     pub light_uniform: Buffer,
+    pub point_lights_uniform: Buffer,
     pub bindgroup_zero: BindGroup,
     pub normal_rp: RenderPipeline,
     pub no_cull_rp: RenderPipeline,
     pub screen_tex_no_cull_rp: RenderPipeline,
+    /// Render the planes sampling the layers of the texture array.
+    pub array_rp: RenderPipeline,
+    /// Render the planes of the materials.
+    pub pbr_rp: RenderPipeline,
+    /// Render the emissive of the planes of the materials to the glow target.
+    pub glow_rp: RenderPipeline,
     pub depth_only_rp: RenderPipeline,
+    /// Render [`StaticModel`] with the materials.
+    pub model_rp: RenderPipeline,
+    /// Render the emissive of [`StaticModel`] to the glow target.
+    pub model_glow_rp: RenderPipeline,
+    /// Render [`StaticLines`] over the scene without the depth test.
+    pub line_rp: RenderPipeline,
+    /// The vertices and the indices of the levels.
+    pub arena: BufferArena,
+    /// The ambient light color applied.
+    ambient: Vector3<f32>,
+    /// The max point / spot lights used, not more than [`MAX_POINT_LIGHTS`]
+    max_lights: usize,
+    /// The point / spot lights set, uploaded again when the config applied.
+    lights: Vec<PointLight>,
+    /// The lights or the config changed and not staged.
+    lights_dirty: bool,
+    /// Group1 for the meshes without texture.
+    pub white_bind: BindGroup,
+    /// The material group1 for the meshes without material.
+    pub default_material: BindGroup,
+    white: TextureWrapper,
+    /// The white of the linear data, the default metallic roughness.
+    white_data: TextureWrapper,
+    /// The normal texture not changing the normal.
+    flat_normal: TextureWrapper,
+    /// Render the sky after the opaque objects.
+    pub sky: SkyboxRenderer,
+    /// Render the labels in the world after the portals.
+    pub text: WorldTextRenderer,
 }
 
 #[derive(Debug)]
@@ -124,10 +275,74 @@ pub struct Planes {
 #[derive(Debug)]
 pub struct StaticPlanes {
     pub count: u32,
-    pub buffer: Buffer,
+    pub buffer: BufferRange,
     pub texture_bind: Option<BindGroup>,
 }
 
+#[derive(Debug)]
+pub struct StaticLines {
+    /// The vertex count.
+    pub count: u32,
+    pub buffer: Buffer,
+}
+
+impl StaticLines {
+    pub fn new(device: &Device, vertices: &[LineVertex]) -> Self {
+        let buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("lines buffer"),
+            contents: bytemuck::cast_slice(vertices),
+            usage: BufferUsages::VERTEX,
+        });
+        Self {
+            count: vertices.len() as u32,
+            buffer,
+        }
+    }
+}
+
+/// The glTF model with the instances uploaded, rendered by [`PlaneRenderer::render_models`]
+///
+/// The instance buffer holds `instance_count` instances for each node of the model.
+pub struct StaticModel {
+    pub model: Model,
+    pub instances: Vec<GltfInstance>,
+    /// The local position of the object in the model space.
+    pub offset: Vector3<f32>,
+    pub instance_buffer: Buffer,
+    pub instance_count: u32,
+    /// The group1 for each material.
+    pub material_binds: Vec<BindGroup>,
+    uploaded_version: u64,
+    /// The instances moved since uploaded.
+    instances_dirty: bool,
+}
+
+#[allow(unused)]
+impl StaticModel {
+    /// Upload the instances again if the node transforms of the model changed.
+    pub fn sync(&mut self, stager: &mut UniformStager) {
+        if self.instances_dirty || self.uploaded_version != self.model.transform_version() {
+            let instances = self.model.instance_raws(&self.instances, &self.offset);
+            stager.write_slice(&self.instance_buffer, 0, &instances);
+            self.uploaded_version = self.model.transform_version();
+            self.instances_dirty = false;
+        }
+    }
+
+    /// Move the instance, uploaded in the next sync. Return whether moved.
+    pub fn set_instance(&mut self, idx: usize, position: Vector3<f32>, rotation: Quaternion<f32>) -> bool {
+        match self.instances.get_mut(idx) {
+            Some(x) if x.position != position || x.rotation != rotation => {
+                x.position = position;
+                x.rotation = rotation;
+                self.instances_dirty = true;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
 
 impl Planes {
     pub fn to_static(self, device: &Device) -> StaticPlanes {
@@ -138,12 +353,72 @@ impl Planes {
         });
         StaticPlanes {
             count: self.objs.len() as u32,
-            buffer,
+            buffer: BufferRange::whole(buffer),
             texture_bind: self.texture_bind,
         }
     }
+
+    /// Upload the planes to the range sub-allocated from the arena, for the planes of the level.
+    pub fn to_arena(self, gpu: &WgpuData, arena: &mut BufferArena) -> StaticPlanes {
+        StaticPlanes {
+            count: self.objs.len() as u32,
+            buffer: arena.upload(gpu, bytemuck::cast_slice(&self.objs[..])),
+            texture_bind: self.texture_bind,
+        }
+    }
+}
+
+/// The textures and the factors of the material, the textures not set are the defaults.
+pub struct MaterialTextures<'a> {
+    pub base_color: Option<&'a TextureView>,
+    pub normal: Option<&'a TextureView>,
+    /// The roughness in g and the metallic in b.
+    pub metallic_roughness: Option<&'a TextureView>,
+    pub emissive: Option<&'a TextureView>,
+    pub factors: MaterialFactors,
+}
+
+impl Default for MaterialTextures<'_> {
+    fn default() -> Self {
+        Self {
+            base_color: None,
+            normal: None,
+            metallic_roughness: None,
+            emissive: None,
+            factors: Default::default(),
+        }
+    }
+}
+
+/// The textures of the material should be sRGB for the base color and the emissive, linear for the others.
+fn create_material_bind(device: &Device, layout: &BindGroupLayout, [white, white_data, flat_normal]: [&TextureWrapper; 3],
+                        material: &MaterialTextures, label: Option<&str>) -> BindGroup {
+    let factors = device.create_buffer_init(&BufferInitDescriptor {
+        label: Some("material factors"),
+        contents: bytemuck::cast_slice(from_ref(&material.factors)),
+        usage: BufferUsages::UNIFORM,
+    });
+    fn texture<'a>(binding: u32, view: Option<&'a TextureView>, default: &'a TextureView) -> BindGroupEntry<'a> {
+        BindGroupEntry {
+            binding,
+            resource: BindingResource::TextureView(view.unwrap_or(default)),
+        }
+    }
+    device.create_bind_group(&BindGroupDescriptor {
+        label,
+        layout,
+        entries: &[texture(0, material.base_color, white.srgb_view()),
+            texture(1, material.normal, flat_normal.linear_view()),
+            texture(2, material.metallic_roughness, white_data.linear_view()),
+            texture(3, material.emissive, white.srgb_view()),
+            BindGroupEntry {
+                binding: 4,
+                resource: factors.as_entire_binding(),
+            }],
+    })
 }
 
+#[allow(unused)]
 impl PlaneRenderer {
     pub fn new(gpu: &WgpuData, shader: &ShaderModule) -> Self {
         let device = &gpu.device;
@@ -155,7 +430,8 @@ impl PlaneRenderer {
                     visibility: ShaderStages::FRAGMENT,
                     ty: BindingType::Sampler(SamplerBindingType::Filtering),
                     count: None,
-                }, uniform_bind_buffer_layout_entry(2, ShaderStages::FRAGMENT, size_of::<LightUniform>() as _)],
+                }, uniform_bind_buffer_layout_entry(2, ShaderStages::FRAGMENT, size_of::<LightUniform>() as _),
+                uniform_bind_buffer_layout_entry(3, ShaderStages::FRAGMENT, size_of::<PointLightsUniform>() as _)],
         });
         let obj_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
             label: Some("plane obj layout"),
@@ -171,6 +447,43 @@ impl PlaneRenderer {
             }],
         });
 
+        let material_texture_entry = |binding| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Texture {
+                sample_type: Default::default(),
+                view_dimension: Default::default(),
+                multisampled: false,
+            },
+            count: None,
+        };
+        let material_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("plane material layout"),
+            entries: &[material_texture_entry(0),
+                material_texture_entry(1),
+                material_texture_entry(2),
+                material_texture_entry(3),
+                uniform_bind_buffer_layout_entry(4, ShaderStages::FRAGMENT, size_of::<MaterialFactors>() as _)],
+        });
+        let array_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("plane array layout"),
+            entries: &[BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    sample_type: Default::default(),
+                    view_dimension: TextureViewDimension::D2Array,
+                    multisampled: false,
+                },
+                count: None,
+            }, BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                count: None,
+            }],
+        });
+
         let sampler = TextureWrapper::create_nearest_sampler(&device);
 
 
@@ -181,6 +494,13 @@ impl PlaneRenderer {
             mapped_at_creation: false,
         });
 
+        let point_lights_uniform = device.create_buffer(&BufferDescriptor {
+            label: Some("point lights uniform"),
+            size: size_of::<PointLightsUniform>() as _,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
 
         let bindgroup_zero = device.create_bind_group(&BindGroupDescriptor {
             label: None,
@@ -194,6 +514,9 @@ impl PlaneRenderer {
             }, BindGroupEntry {
                 binding: 2,
                 resource: light_uniform.as_entire_binding(),
+            }, BindGroupEntry {
+                binding: 3,
+                resource: point_lights_uniform.as_entire_binding(),
             }],
         });
 
@@ -203,7 +526,7 @@ impl PlaneRenderer {
             push_constant_ranges: &[],
         });
         let targets = [Some(ColorTargetState {
-            format: gpu.surface_cfg.format,
+            format: gpu.formats.color,
             blend: Some(BlendState::REPLACE),
             write_mask: ColorWrites::ALL,
         })];
@@ -246,6 +569,37 @@ impl PlaneRenderer {
         rpd.fragment.as_mut().unwrap().entry_point = "plane_pos_tex_fs";
         let screen_tex_no_cull_rp = device.create_render_pipeline(&rpd);
 
+        let rp_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("plane array pipeline layout"),
+            bind_group_layouts: &[&base_bind_layout, &array_layout],
+            push_constant_ranges: &[],
+        });
+        rpd.layout = Some(&rp_layout);
+        rpd.primitive.cull_mode = Some(Face::Back);
+        rpd.vertex.entry_point = "plane_array_vs";
+        rpd.fragment.as_mut().unwrap().entry_point = "plane_array_fs";
+        let array_rp = device.create_render_pipeline(&rpd);
+
+        let rp_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("plane material pipeline layout"),
+            bind_group_layouts: &[&base_bind_layout, &material_layout],
+            push_constant_ranges: &[],
+        });
+        rpd.layout = Some(&rp_layout);
+        rpd.vertex.entry_point = "plane_pbr_vs";
+        rpd.fragment.as_mut().unwrap().entry_point = "pbr_fs";
+        let pbr_rp = device.create_render_pipeline(&rpd);
+        // the emissive only to the glow target, tested by the depth of the scene
+        rpd.fragment.as_mut().unwrap().entry_point = "emissive_fs";
+        if let Some(depth) = rpd.depth_stencil.as_mut() {
+            depth.depth_write_enabled = false;
+        }
+        let glow_rp = device.create_render_pipeline(&rpd);
+        if let Some(depth) = rpd.depth_stencil.as_mut() {
+            depth.depth_write_enabled = true;
+        }
+        rpd.primitive.cull_mode = None;
+
         rpd.fragment = None;
         let rp_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
             label: None,
@@ -256,15 +610,144 @@ impl PlaneRenderer {
 
         rpd.vertex.entry_point = "plane_vs";
         let depth_only_rp = device.create_render_pipeline(&rpd);
-        Self {
+
+        let rp_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&base_bind_layout, &material_layout],
+            push_constant_ranges: &[],
+        });
+        let model_buffers = [ModelVertex::desc(), InstanceRaw::desc()];
+        rpd.layout = Some(&rp_layout);
+        rpd.vertex.entry_point = "model_pbr_vs";
+        rpd.vertex.buffers = &model_buffers;
+        rpd.primitive.topology = PrimitiveTopology::TriangleList;
+        rpd.primitive.cull_mode = None;
+        rpd.fragment = Some(FragmentState {
+            module: &shader,
+            entry_point: "pbr_fs",
+            targets: &targets,
+        });
+        let model_rp = device.create_render_pipeline(&rpd);
+        rpd.fragment.as_mut().unwrap().entry_point = "emissive_fs";
+        if let Some(depth) = rpd.depth_stencil.as_mut() {
+            depth.depth_write_enabled = false;
+        }
+        let model_glow_rp = device.create_render_pipeline(&rpd);
+
+        let rp_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&base_bind_layout],
+            push_constant_ranges: &[],
+        });
+        let line_buffers = [LineVertex::desc()];
+        rpd.layout = Some(&rp_layout);
+        rpd.vertex.entry_point = "line_vs";
+        rpd.vertex.buffers = &line_buffers;
+        rpd.primitive.topology = PrimitiveTopology::LineList;
+        if let Some(depth) = rpd.depth_stencil.as_mut() {
+            depth.depth_write_enabled = false;
+            depth.depth_compare = CompareFunction::Always;
+        }
+        rpd.fragment = Some(FragmentState {
+            module: &shader,
+            entry_point: "line_fs",
+            targets: &targets,
+        });
+        let line_rp = device.create_render_pipeline(&rpd);
+
+        let white = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, image::Rgba([255; 4])));
+        let white_data = TextureWrapper::from_image(device, &gpu.queue, &white, Some("white data texture"), ColorSpace::Linear)
+            .expect("Create white texture failed");
+        let white = TextureWrapper::from_image(device, &gpu.queue, &white, Some("white texture"), ColorSpace::Srgb)
+            .expect("Create white texture failed");
+        let white_bind = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("white texture bind"),
+            layout: &obj_layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(white.srgb_view()),
+            }],
+        });
+        let flat_normal = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, image::Rgba([128, 128, 255, 255])));
+        let flat_normal = TextureWrapper::from_image(device, &gpu.queue, &flat_normal, Some("flat normal texture"), ColorSpace::Linear)
+            .expect("Create flat normal texture failed");
+        let default_material = create_material_bind(device, &material_layout, [&white, &white_data, &flat_normal], &MaterialTextures::default(), Some("default material"));
+        let sky = SkyboxRenderer::new(gpu, &base_bind_layout);
+        let text = WorldTextRenderer::new(gpu);
+        let mut this = Self {
             base_bind_layout,
             obj_layout,
+            material_layout,
+            array_layout,
             light_uniform,
+            point_lights_uniform,
             bindgroup_zero,
             normal_rp,
             no_cull_rp,
             screen_tex_no_cull_rp,
+            array_rp,
+            pbr_rp,
+            glow_rp,
             depth_only_rp,
+            model_rp,
+            model_glow_rp,
+            line_rp,
+            arena: BufferArena::geometry(),
+            white_bind,
+            default_material,
+            white,
+            white_data,
+            flat_normal,
+            ambient: Vector3::zeros(),
+            max_lights: MAX_POINT_LIGHTS,
+            lights: vec![],
+            lights_dirty: true,
+            sky,
+            text,
+        };
+        this.apply_config(&RendererConfig::default());
+        this
+    }
+
+    /// Apply the ambient and the max lights of the config, the point frame is for the models only.
+    ///
+    /// The lights are uploaded in the next [`PlaneRenderer::stage_uniforms`].
+    pub fn apply_config(&mut self, config: &RendererConfig) {
+        let [r, g, b, _] = config.ambient_color();
+        self.ambient = vector![r, g, b];
+        self.max_lights = config.max_lights.min(MAX_POINT_LIGHTS);
+        self.lights_dirty = true;
+    }
+
+    /// Upload the instances and create the material binds for the model.
+    ///
+    /// The local position of the object is applied to the instances.
+    pub fn create_static_model(&self, device: &Device, obj: ModelObject) -> StaticModel {
+        let offset = Vector3::from_row_slice(&obj.locals.position[..3]);
+        let instances = obj.model.instance_raws(&obj.instances, &offset);
+        let instance_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("model instance buffer"),
+            contents: bytemuck::cast_slice(&instances),
+            usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+        });
+        let material_binds = obj.model.materials.iter().map(|x| {
+            self.create_material(device, &MaterialTextures {
+                base_color: x.diffuse_texture.as_ref().map(|x| x.srgb_view()),
+                normal: x.normal_texture.as_ref().map(|x| x.linear_view()),
+                metallic_roughness: x.metallic_roughness_texture.as_ref().map(|x| x.linear_view()),
+                emissive: x.emissive_texture.as_ref().map(|x| x.srgb_view()),
+                factors: x.factors,
+            }, Some(&x.name))
+        }).collect::<Vec<_>>();
+        StaticModel {
+            uploaded_version: obj.model.transform_version(),
+            instance_count: obj.instances.len() as u32,
+            model: obj.model,
+            instances: obj.instances,
+            offset,
+            instance_buffer,
+            material_binds,
+            instances_dirty: false,
         }
     }
 
@@ -283,30 +766,104 @@ impl PlaneRenderer {
         }
     }
 
+    /// The material group1, the textures not set are the defaults.
+    pub fn create_material(&self, device: &Device, material: &MaterialTextures, label: Option<&str>) -> BindGroup {
+        create_material_bind(device, &self.material_layout, [&self.white, &self.white_data, &self.flat_normal], material, label)
+    }
+
+    /// The planes of the material, rendered by [`PlaneRenderer::pbr_rp`]
+    pub fn create_plane_material(&self, device: &Device, material: &MaterialTextures) -> Planes {
+        Planes {
+            objs: vec![],
+            texture_bind: Some(self.create_material(device, material, Some("plane material"))),
+        }
+    }
+
+    /// The planes sampling the texture array, rendered by [`PlaneRenderer::array_rp`]
+    pub fn create_plane_array(&self, device: &Device, array: &TextureArray, sampler: &SamplerOptions) -> Planes {
+        let sampler = sampler.create(device);
+        let texture_bind = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("plane array bind"),
+            layout: &self.array_layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(&array.view),
+            }, BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::Sampler(&sampler),
+            }],
+        });
+        Planes {
+            objs: vec![],
+            texture_bind: Some(texture_bind),
+        }
+    }
+
     pub fn update_light(&mut self, queue: &Queue, light: &LightUniform) {
         queue.write_buffer(&self.light_uniform, 0, bytemuck::cast_slice(from_ref(light)));
     }
+
+    /// Set the point / spot lights, only the first max lights of the config will be used.
+    ///
+    /// The lights are uploaded in the next [`PlaneRenderer::stage_uniforms`].
+    pub fn update_lights(&mut self, lights: &[PointLight]) {
+        if lights.len() > self.max_lights {
+            log::warn!("Too many point lights ({}), only the first {} will be used", lights.len(), self.max_lights);
+        }
+        self.lights.clear();
+        self.lights.extend_from_slice(lights);
+        self.lights_dirty = true;
+    }
+
+    /// Write the uniforms changed by the stager, before the passes of the frame.
+    pub fn stage_uniforms(&mut self, stager: &mut UniformStager) {
+        if !std::mem::take(&mut self.lights_dirty) {
+            return;
+        }
+        let count = self.lights.len().min(self.max_lights);
+        let mut uniform = PointLightsUniform::zeroed();
+        uniform.ambient = self.ambient;
+        uniform.count = count as u32;
+        uniform.lights[..count].copy_from_slice(&self.lights[..count]);
+        stager.write(&self.point_lights_uniform, 0, &uniform);
+    }
 }
 
 #[allow(unused)]
 pub struct General3DRenderer {
     pub plane_renderer: PlaneRenderer,
+    /// The config applied to the renderers.
+    config: RendererConfig,
 }
 
 #[allow(unused)]
 impl General3DRenderer {
     pub fn new(gpu: &WgpuData) -> Self {
+        mark_frame_event("3d pipelines creation");
         let device = &gpu.device;
         // Setup the shader
         // We use specific shaders for each pass to define visual effect
         // and also to have the right shader for the uniforms we pass
         let shader_module = device.create_shader_module(ShaderModuleDescriptor {
             label: Some("General 3d Shader"),
-            source: ShaderSource::Wgsl(include_str!("3d.wgsl").into()),
+            source: ShaderSource::Wgsl(concat!(include_str!("../color.wgsl"), include_str!("3d.wgsl")).into()),
         });
         let plane_renderer = PlaneRenderer::new(gpu, &shader_module);
         Self {
             plane_renderer,
+            config: Default::default(),
+        }
+    }
+
+    pub fn config(&self) -> &RendererConfig {
+        &self.config
+    }
+
+    /// Apply the config at runtime if changed.
+    pub fn set_config(&mut self, gpu: &WgpuData, config: RendererConfig) {
+        if config != self.config {
+            self.plane_renderer.apply_config(&config);
+            self.config = config;
         }
     }
 }
@@ -319,12 +876,37 @@ impl PlaneRenderer {
     }
 
 
+    /// Render the models, the pipeline and group0 should be set.
+    pub fn render_models<'a, T: RenderEncoder<'a>>(&'a self, encoder: &mut T, models: &'a [StaticModel]) {
+        for obj in models.iter().filter(|x| x.instance_count > 0) {
+            let stride = (size_of::<InstanceRaw>() * obj.instance_count as usize) as BufferAddress;
+            for mesh in &obj.model.meshes {
+                let start = stride * mesh.node as BufferAddress;
+                encoder.set_vertex_buffer(1, obj.instance_buffer.slice(start..start + stride));
+                let bind = obj.material_binds.get(mesh.material)
+                    .unwrap_or(&self.default_material);
+                encoder.set_bind_group(1, bind, &[]);
+                encoder.set_vertex_buffer(0, mesh.vertex_buffer.slice());
+                encoder.set_index_buffer(mesh.index_buffer.slice(), IndexFormat::Uint32);
+                encoder.draw_indexed(0..mesh.num_elements, 0, 0..obj.instance_count);
+            }
+        }
+    }
+
+    /// Render the lines, the line pipeline and group0 should be set.
+    pub fn render_lines<'a, T: RenderEncoder<'a>>(&'a self, encoder: &mut T, lines: &'a StaticLines) {
+        if lines.count > 0 {
+            encoder.set_vertex_buffer(0, lines.buffer.slice(..));
+            encoder.draw(0..lines.count, 0..1);
+        }
+    }
+
     pub fn render_static<'a, T: RenderEncoder<'a>>(&'a self, encoder: &mut T, _: &WgpuData, objs: &'a [StaticPlanes]) {
         for obj in objs {
             if let Some(bg) = &obj.texture_bind {
                 encoder.set_bind_group(1, bg, &[]);
             }
-            encoder.set_vertex_buffer(0, obj.buffer.slice(..));
+            encoder.set_vertex_buffer(0, obj.buffer.slice());
             for i in 0..obj.count {
                 let start = i * 4;
                 let end = (i + 1) * 4;
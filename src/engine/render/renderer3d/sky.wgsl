@@ -0,0 +1,48 @@
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+@group(1) @binding(0)
+var sky_texture: texture_cube<f32>;
+@group(1) @binding(1)
+var sky_sampler: sampler;
+
+struct SkyOutput {
+    @builtin(position) pos: vec4<f32>,
+    @location(0) dir: vec3<f32>,
+}
+
+// The unit cube around the camera, 12 triangles from the vertex index.
+@vertex
+fn sky_vs(@builtin(vertex_index) idx: u32) -> SkyOutput {
+    var indices = array<u32, 36>(
+        0u, 1u, 3u, 0u, 3u, 2u,
+        4u, 6u, 7u, 4u, 7u, 5u,
+        0u, 4u, 5u, 0u, 5u, 1u,
+        2u, 3u, 7u, 2u, 7u, 6u,
+        0u, 2u, 6u, 0u, 6u, 4u,
+        1u, 5u, 7u, 1u, 7u, 3u,
+    );
+    let corner = indices[idx];
+    let dir = vec3<f32>(
+        f32(corner & 1u) * 2.0 - 1.0,
+        f32((corner >> 1u) & 1u) * 2.0 - 1.0,
+        f32((corner >> 2u) & 1u) * 2.0 - 1.0,
+    );
+    var out: SkyOutput;
+    // at the far plane
+    out.pos = (camera.view_proj * vec4<f32>(camera.view_pos.xyz + dir, 1.0)).xyww;
+    out.dir = dir;
+    return out;
+}
+
+@fragment
+fn sky_fs(input: SkyOutput) -> @location(0) vec4<f32> {
+    // the world is z up but the cube map is y up
+    let dir = vec3<f32>(input.dir.x, input.dir.z, -input.dir.y);
+    return vec4<f32>(linear_to_srgb(textureSample(sky_texture, sky_sampler, dir).rgb), 1.0);
+}
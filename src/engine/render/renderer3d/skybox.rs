@@ -0,0 +1,141 @@
+//! Render the cube map around the camera at the infinite depth.
+//!
+//! Rendered after the opaque objects, only where nothing drawn.
+
+use wgpu::util::RenderEncoder;
+
+use crate::engine::{CubeTexture, Handle, ResourceManager};
+use crate::engine::prelude::*;
+
+/// The name of the cube map loaded in the [`ResourceManager`]
+pub const SKY_NAME: &str = "sky";
+
+#[allow(unused)]
+pub struct SkyboxRenderer {
+    /// Group1.
+    /// Bindings 0: cube texture view, 1: sampler
+    layout: BindGroupLayout,
+    pipeline: RenderPipeline,
+    sampler: Sampler,
+    /// The cube map bound, none before loaded.
+    bind: Option<(Handle<CubeTexture>, BindGroup)>,
+}
+
+#[allow(unused)]
+impl SkyboxRenderer {
+    pub fn new(gpu: &WgpuData, base_bind_layout: &BindGroupLayout) -> Self {
+        let device = &gpu.device;
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Sky Shader"),
+            source: ShaderSource::Wgsl(concat!(include_str!("../color.wgsl"), include_str!("sky.wgsl")).into()),
+        });
+        let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("sky layout"),
+            entries: &[BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    sample_type: Default::default(),
+                    view_dimension: TextureViewDimension::Cube,
+                    multisampled: false,
+                },
+                count: None,
+            }, BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                count: None,
+            }],
+        });
+        let rp_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("sky pipeline layout"),
+            bind_group_layouts: &[base_bind_layout, &layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("sky pipeline"),
+            layout: Some(&rp_layout),
+            vertex: VertexState {
+                module: &shader,
+                entry_point: "sky_vs",
+                buffers: &[],
+            },
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleList,
+                cull_mode: None,
+                ..Default::default()
+            },
+            depth_stencil: Some(DepthStencilState {
+                format: TextureFormat::Depth32Float,
+                depth_write_enabled: false,
+                depth_compare: CompareFunction::LessEqual,
+                stencil: Default::default(),
+                bias: Default::default(),
+            }),
+            multisample: Default::default(),
+            fragment: Some(FragmentState {
+                module: &shader,
+                entry_point: "sky_fs",
+                targets: &[Some(ColorTargetState {
+                    format: gpu.formats.color,
+                    blend: Some(BlendState::REPLACE),
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+        });
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            label: Some("sky sampler"),
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            ..Default::default()
+        });
+        Self {
+            layout,
+            pipeline,
+            sampler,
+            bind: None,
+        }
+    }
+
+    /// Bind the cube map once loaded or reloaded.
+    pub fn update(&mut self, device: &Device, res: &ResourceManager) {
+        let handle = if let Some(handle) = res.cube_maps.handle(SKY_NAME) { handle } else {
+            self.bind = None;
+            return;
+        };
+        if self.bind.as_ref().is_some_and(|x| x.0 == handle) {
+            return;
+        }
+        if let Some(sky) = res.cube_maps.get(handle) {
+            let bind = device.create_bind_group(&BindGroupDescriptor {
+                label: Some("sky bind"),
+                layout: &self.layout,
+                entries: &[BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(&sky.view),
+                }, BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Sampler(&self.sampler),
+                }],
+            });
+            self.bind = Some((handle, bind));
+        }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.bind.is_some()
+    }
+
+    /// Render the sky where the depth not written, group0 should be set.
+    pub fn render<'a, T: RenderEncoder<'a>>(&'a self, encoder: &mut T) {
+        if let Some((_, bind)) = &self.bind {
+            encoder.set_pipeline(&self.pipeline);
+            encoder.set_bind_group(1, bind, &[]);
+            encoder.draw(0..36, 0..1);
+        }
+    }
+}
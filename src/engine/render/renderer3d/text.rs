@@ -0,0 +1,136 @@
+//! Render the text labels anchored in the world, facing the camera.
+//!
+//! Each label is drawn by its own transform from the glyph space to the clip space.
+
+use log::warn;
+use nalgebra::{Matrix4, Vector3};
+use wgpu_glyph::ab_glyph::FontArc;
+
+use crate::engine::prelude::*;
+use crate::engine::render::camera::Camera;
+
+/// The font size rasterized in pixels, scaled to the label size in the world.
+const GLYPH_SCALE: f32 = 64.0;
+
+/// The text anchored at the world position.
+#[derive(Debug, Clone)]
+pub struct WorldLabel {
+    /// The center of the text.
+    pub pos: Vector3<f32>,
+    pub text: String,
+    pub color: [f32; 4],
+    /// The height of the text in the world.
+    pub size: f32,
+}
+
+/// The alpha multiplied for the label at the distance, eased between the fade distances.
+fn fade_alpha((start, end): (f32, f32), distance: f32) -> f32 {
+    if distance <= start {
+        1.0
+    } else if distance >= end {
+        0.0
+    } else {
+        let t = (distance - start) / (end - start);
+        1.0 - t * t * (3.0 - 2.0 * t)
+    }
+}
+
+#[allow(unused)]
+pub struct WorldTextRenderer {
+    brush: GlyphBrush<DepthStencilState>,
+    pub enabled: bool,
+    /// The distance starting to fade out and the distance invisible.
+    pub fade: (f32, f32),
+}
+
+#[allow(unused)]
+impl WorldTextRenderer {
+    pub fn new(gpu: &WgpuData) -> Self {
+        let font = egui::FontDefinitions::default().font_data.get("Ubuntu-Light")
+            .and_then(|x| FontArc::try_from_vec(x.font.to_vec()).ok())
+            .expect("Load the label font failed");
+        let brush = GlyphBrushBuilder::using_font(font)
+            .depth_stencil_state(DepthStencilState {
+                format: TextureFormat::Depth32Float,
+                depth_write_enabled: false,
+                depth_compare: CompareFunction::LessEqual,
+                stencil: Default::default(),
+                bias: Default::default(),
+            })
+            .build(&gpu.device, gpu.formats.color);
+        Self {
+            brush,
+            enabled: true,
+            fade: (6.0, 12.0),
+        }
+    }
+
+    /// Draw the labels over the target, hidden by the depth written.
+    ///
+    /// The labels behind the clip plane (point, normal) are skipped,
+    /// as the objects between the camera and the portal in the portal views.
+    pub fn draw(&mut self, device: &Device, belt: &mut util::StagingBelt, encoder: &mut CommandEncoder,
+                target: &TextureView, depth: &TextureView,
+                camera: &Camera, view_proj: &Matrix4<f32>, labels: &[WorldLabel],
+                clip: Option<(Vector3<f32>, Vector3<f32>)>) {
+        if !self.enabled {
+            return;
+        }
+        let forward = camera.target.normalize();
+        let right = forward.cross(&Vector3::z()).try_normalize(1e-6).unwrap_or_else(Vector3::x);
+        let up = right.cross(&forward);
+        for label in labels {
+            if clip.is_some_and(|(point, normal)| (label.pos - point).dot(&normal) <= 0.0) {
+                continue;
+            }
+            let to_label = label.pos - camera.eye.coords;
+            if to_label.dot(&forward) <= 0.0 {
+                continue;
+            }
+            let alpha = label.color[3] * fade_alpha(self.fade, to_label.norm());
+            if alpha <= 0.0 {
+                continue;
+            }
+            let [r, g, b, _] = label.color;
+            self.brush.queue(Section::default()
+                .add_text(Text::new(&label.text)
+                    .with_color([r, g, b, alpha])
+                    .with_scale(GLYPH_SCALE))
+                .with_layout(Layout::default_single_line()
+                    .h_align(HorizontalAlign::Center)
+                    .v_align(VerticalAlign::Center)));
+            let k = label.size / GLYPH_SCALE;
+            // the glyph space is y down
+            let model = Matrix4::from_columns(&[
+                (right * k).push(0.0),
+                (-up * k).push(0.0),
+                (forward * k).push(0.0),
+                label.pos.push(1.0),
+            ]);
+            let mut transform = [0.0; 16];
+            transform.copy_from_slice((view_proj * model).as_slice());
+            if let Err(e) = self.brush.draw_queued_with_transform(device, belt, encoder, target, RenderPassDepthStencilAttachment {
+                view: depth,
+                depth_ops: Some(Operations {
+                    load: LoadOp::Load,
+                    store: true,
+                }),
+                stencil_ops: None,
+            }, transform) {
+                warn!(target: "text", "Draw the label {:?} failed for {}", label.text, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::engine::renderer3d::text::fade_alpha;
+
+    #[test]
+    fn test_fade_alpha() {
+        assert_eq!(fade_alpha((6.0, 12.0), 1.0), 1.0);
+        assert_eq!(fade_alpha((6.0, 12.0), 12.0), 0.0);
+        assert!((fade_alpha((6.0, 12.0), 9.0) - 0.5).abs() < 1e-6);
+    }
+}
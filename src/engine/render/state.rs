@@ -1,5 +1,6 @@
 use std::panic::AssertUnwindSafe;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use anyhow::anyhow;
 use futures::executor::block_on;
@@ -8,19 +9,116 @@ use winit::window::Window;
 
 use crate::engine::MainRenderViews;
 use crate::engine::render::INSTANCE;
+use crate::engine::render::timestamp::{GpuScope, GpuTimer};
 use crate::engine::uniform::MainUniformBuffer;
 
+/// The format without the sRGB suffix presented if the surface supports none of them.
+const FALLBACK_FORMAT: TextureFormat = TextureFormat::Bgra8Unorm;
+/// The format of the views for the HDR, also the format of the surface taking the extended linear colors.
+pub const HDR_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+
+/// The formats negotiated with the surface.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct OutputFormats {
+    /// The format presented.
+    pub surface: TextureFormat,
+    /// The format of the views and the pipelines drawing to them.
+    pub color: TextureFormat,
+}
+
+#[allow(unused)]
+impl OutputFormats {
+    /// Choose the formats from the formats supported by the surface, the preferred first.
+    ///
+    /// The colors are encoded already, so the 8 bits formats are preferred and the views are without the sRGB suffix.
+    /// The views are [`HDR_FORMAT`] if `hdr`, presented as the extended linear colors if the surface supports it.
+    pub fn negotiate(supported: &[TextureFormat], hdr: bool) -> Self {
+        if hdr && supported.contains(&HDR_FORMAT) {
+            return Self { surface: HDR_FORMAT, color: HDR_FORMAT };
+        }
+        let surface = supported.iter().copied()
+            .find(|x| matches!(x.remove_srgb_suffix(), TextureFormat::Bgra8Unorm | TextureFormat::Rgba8Unorm))
+            .or_else(|| supported.first().copied())
+            .unwrap_or(FALLBACK_FORMAT);
+        let color = if hdr { HDR_FORMAT } else { surface.remove_srgb_suffix() };
+        Self { surface, color }
+    }
+
+    /// The surface takes the extended linear colors.
+    pub fn is_hdr_output(&self) -> bool {
+        self.surface == HDR_FORMAT
+    }
+
+    /// The screen is copied to the surface, else drawn by the present pass.
+    pub fn is_copied(&self) -> bool {
+        !self.is_hdr_output() && self.color.remove_srgb_suffix() == self.surface.remove_srgb_suffix()
+    }
+
+    /// The format of the surface view drawn by the present pass.
+    ///
+    /// The colors are encoded by the pass already, so the view is without the sRGB suffix not to encode again.
+    pub fn present_format(&self) -> TextureFormat {
+        if self.is_hdr_output() { self.surface } else { self.surface.remove_srgb_suffix() }
+    }
+
+    /// The formats the views of the surface can be in.
+    pub fn surface_view_formats(&self) -> Vec<TextureFormat> {
+        let mut formats = vec![self.surface];
+        if self.present_format() != self.surface {
+            formats.push(self.present_format());
+        }
+        formats
+    }
+
+    /// The usage of the surface to present.
+    pub fn surface_usage(&self) -> TextureUsages {
+        if self.is_copied() { TextureUsages::COPY_DST } else { TextureUsages::RENDER_ATTACHMENT }
+    }
+}
+
 #[derive(Debug)]
 pub struct WgpuData {
-    pub surface: Surface,
+    /// None if headless, nothing presented.
+    pub surface: Option<Surface>,
     pub surface_cfg: SurfaceConfiguration,
+    pub formats: OutputFormats,
+    /// The HDR requested, the surface may not support it.
+    pub hdr: bool,
+    pub adapter: Arc<Adapter>,
     pub device: Arc<Device>,
+    pub adapter_info: AdapterInfo,
     pub queue: Arc<Queue>,
     pub views: MainRenderViews,
     pub uniforms: MainUniformBuffer,
 
     pub size_scale: [f32; 2],
+    /// The scale of the 3d scene size to the screen size.
+    pub render_scale: f32,
+    /// Time the passes if the timestamp queries supported.
+    pub timer: Option<GpuTimer>,
+    /// Set if the device is lost, shared by the gpu data of the same device.
+    lost: Arc<AtomicBool>,
+}
 
+/// Whether the error reports the device lost.
+fn is_device_lost(description: &str) -> bool {
+    description.contains("device is lost") || description.contains("DeviceLost")
+}
+
+/// Flag the device lost instead of panicking, the other errors still panic as the default handler.
+fn watch_lost(device: &Device) -> Arc<AtomicBool> {
+    let lost = Arc::new(AtomicBool::new(false));
+    let flag = lost.clone();
+    device.on_uncaptured_error(Box::new(move |e| {
+        let description = e.to_string();
+        if is_device_lost(&description) {
+            log::error!("The gpu device is lost: {}", description);
+            flag.store(true, Ordering::Release);
+        } else {
+            panic!("wgpu error: {}", description);
+        }
+    }));
+    lost
 }
 
 impl WgpuData {
@@ -29,14 +127,73 @@ impl WgpuData {
         (self.surface_cfg.width, self.surface_cfg.height)
     }
 
+    /// Get the size of the 3d scene target after applying the render scale.
+    #[inline]
+    pub fn get_render_size(&self) -> (u32, u32) {
+        Self::scaled_size(&self.surface_cfg, self.render_scale)
+    }
+
+    fn scaled_size(surface_cfg: &SurfaceConfiguration, scale: f32) -> (u32, u32) {
+        (((surface_cfg.width as f32 * scale) as u32).max(1),
+         ((surface_cfg.height as f32 * scale) as u32).max(1))
+    }
+
+    /// Set the render scale (clamped to 0.5..=2.0) and recreate the views if changed.
+    pub fn set_render_scale(&mut self, scale: f32) {
+        let scale = scale.clamp(0.5, 2.0);
+        if scale != self.render_scale {
+            self.render_scale = scale;
+            let render_size = self.get_render_size();
+            self.views.resize(&self.device, &self.surface_cfg, render_size);
+        }
+    }
+
+
+    /// Begin timing the pass on the gpu if supported, ended by [`WgpuData::end_timing`]
+    pub fn begin_timing(&self, encoder: &mut CommandEncoder, name: &'static str) -> Option<GpuScope> {
+        self.timer.as_ref()?.begin(encoder, name)
+    }
+
+    pub fn end_timing(&self, encoder: &mut CommandEncoder, scope: Option<GpuScope>) {
+        if let Some(timer) = self.timer.as_ref() {
+            timer.end(encoder, scope);
+        }
+    }
+
+    /// The device is lost, the gpu data should be created again.
+    pub fn is_lost(&self) -> bool {
+        self.lost.load(Ordering::Acquire)
+    }
+
+    /// Treat the device as lost, like running out of the memory to present.
+    pub fn mark_lost(&self) {
+        self.lost.store(true, Ordering::Release);
+    }
+
+    pub fn vsync(&self) -> bool {
+        self.surface_cfg.present_mode != PresentMode::AutoNoVsync
+    }
+
+    /// Reconfigure the surface to present with or without vsync.
+    pub fn set_vsync(&mut self, vsync: bool) {
+        if vsync != self.vsync() {
+            self.surface_cfg.present_mode = if vsync { PresentMode::AutoVsync } else { PresentMode::AutoNoVsync };
+            if let Some(surface) = &self.surface {
+                surface.configure(&self.device, &self.surface_cfg);
+            }
+        }
+    }
 
     pub fn resize(&mut self, width: u32, height: u32) {
         self.surface_cfg.width = width;
         self.surface_cfg.height = height;
-        self.surface.configure(&self.device, &self.surface_cfg);
+        if let Some(surface) = &self.surface {
+            surface.configure(&self.device, &self.surface_cfg);
+        }
         let size = [width as f32, height as f32];
         self.size_scale = [size[0] / 1600.0, size[1] / 900.0];
-        self.views = MainRenderViews::new(&self.device, &self.surface_cfg);
+        let render_size = self.get_render_size();
+        self.views.resize(&self.device, &self.surface_cfg, render_size);
     }
 
     pub fn create_from_exists(window: &Window, gpu: &WgpuData) -> anyhow::Result<Self> {
@@ -54,36 +211,47 @@ impl WgpuData {
 
 
             let (device, queue) = (gpu.device.clone(), gpu.queue.clone());
+            let adapter_info = gpu.adapter_info.clone();
             log::info!("Cloned device {:?} and queue {:?}", device, queue);
 
-            let format = TextureFormat::Bgra8Unorm;
-            log::info!("Using {:?} for swap chain format", format);
+            let formats = OutputFormats::negotiate(&surface.get_capabilities(&gpu.adapter).formats, gpu.hdr);
+            log::info!("Using {:?} for swap chain format and {:?} for the views", formats.surface, formats.color);
 
             let surface_cfg = SurfaceConfiguration {
-                usage: TextureUsages::COPY_DST,
-                format,
+                usage: formats.surface_usage(),
+                format: formats.surface,
                 width: size.width,
                 height: size.height,
                 present_mode: PresentMode::Fifo,
                 alpha_mode: Default::default(),
-                view_formats: vec![format],
+                view_formats: formats.surface_view_formats(),
             };
             surface.configure(&device, &surface_cfg);
 
 
+            let timer = GpuTimer::new(&device, &queue);
             let mut uniforms = MainUniformBuffer::new(&device);
             uniforms.uniform_buffer = gpu.uniforms.uniform_buffer.clone();
             let size_scale = [surface_cfg.width as f32 / 1600.0, surface_cfg.height as f32 / 900.0];
-            let views = MainRenderViews::new(&device, &surface_cfg);
+            let render_scale = gpu.render_scale;
+            let views = MainRenderViews::new(&device, &surface_cfg, formats.color, Self::scaled_size(&surface_cfg, render_scale));
             Ok(Self {
-                surface,
+                surface: Some(surface),
                 surface_cfg,
+                formats,
+                hdr: gpu.hdr,
+                adapter: gpu.adapter.clone(),
                 device,
+                adapter_info,
                 queue,
                 views,
 
                 uniforms,
                 size_scale,
+                render_scale,
+
+                timer,
+                lost: gpu.lost.clone(),
             })
         });
         if let Ok(r) = result {
@@ -93,7 +261,8 @@ impl WgpuData {
         Err(anyhow!("Get gpu data failed"))
     }
 
-    pub fn new(window: &Window) -> anyhow::Result<Self> {
+    /// Create the gpu data presenting to the window, the views in [`HDR_FORMAT`] if `hdr`.
+    pub fn new(window: &Window, hdr: bool) -> anyhow::Result<Self> {
         let window = AssertUnwindSafe(&window);
         let result = std::panic::catch_unwind(|| {
             log::info!("New graphics state");
@@ -111,6 +280,7 @@ impl WgpuData {
                     compatible_surface: Some(&surface),
                 })).ok_or(anyhow!("Cannot get adapter"))?;
             log::info!("Got adapter {:?}", adapter);
+            let adapter_info = adapter.get_info();
             let (device, queue) = block_on(adapter
                 .request_device(
                     &DeviceDescriptor {
@@ -126,32 +296,45 @@ impl WgpuData {
 
             let (device, queue) = (Arc::new(device), Arc::new(queue));
             log::info!("Requested device {:?} and queue {:?}", device, queue);
+            let lost = watch_lost(&device);
 
-            let format = TextureFormat::Bgra8Unorm;
-            log::info!("Using {:?} for swap chain format", format);
+            let formats = OutputFormats::negotiate(&surface.get_capabilities(&adapter).formats, hdr);
+            log::info!("Using {:?} for swap chain format and {:?} for the views", formats.surface, formats.color);
+            if hdr && !formats.is_hdr_output() {
+                log::info!("The surface does not support the HDR output, the views presented to {:?}", formats.surface);
+            }
 
             let surface_cfg = SurfaceConfiguration {
-                usage: TextureUsages::COPY_DST,
-                format,
+                usage: formats.surface_usage(),
+                format: formats.surface,
                 width: size.width,
                 height: size.height,
                 present_mode: PresentMode::AutoVsync,
                 alpha_mode: Default::default(),
-                view_formats: vec![format],
+                view_formats: formats.surface_view_formats(),
             };
             surface.configure(&device, &surface_cfg);
 
+            let timer = GpuTimer::new(&device, &queue);
             let uniforms = MainUniformBuffer::new(&device);
             let size_scale = [surface_cfg.width as f32 / 1600.0, surface_cfg.height as f32 / 900.0];
-            let views = MainRenderViews::new(&device, &surface_cfg);
+            let render_scale = 1.0;
+            let views = MainRenderViews::new(&device, &surface_cfg, formats.color, Self::scaled_size(&surface_cfg, render_scale));
             Ok(Self {
-                surface,
+                surface: Some(surface),
                 surface_cfg,
+                formats,
+                hdr,
+                adapter: Arc::new(adapter),
                 device,
+                adapter_info,
                 queue,
                 views,
                 uniforms,
                 size_scale,
+                render_scale,
+                timer,
+                lost,
             })
         });
         if let Ok(r) = result {
@@ -160,4 +343,99 @@ impl WgpuData {
         log::warn!("Failed to get gpu data");
         Err(anyhow!("Get gpu data failed"))
     }
+
+    /// Create the gpu data without the window, the scene is rendered to the views but not presented.
+    pub fn headless(width: u32, height: u32) -> anyhow::Result<Self> {
+        let adapter = block_on(INSTANCE
+            .request_adapter(&RequestAdapterOptions {
+                power_preference: util::power_preference_from_env().unwrap_or(PowerPreference::LowPower),
+                force_fallback_adapter: false,
+                compatible_surface: None,
+            })).ok_or(anyhow!("Cannot get adapter"))?;
+        log::info!("Got headless adapter {:?}", adapter);
+        let adapter_info = adapter.get_info();
+        let (device, queue) = block_on(adapter
+            .request_device(
+                &DeviceDescriptor {
+                    label: None,
+                    features: adapter.features(),
+                    limits: adapter.limits(),
+                },
+                None,
+            ))?;
+        let (device, queue) = (Arc::new(device), Arc::new(queue));
+        let lost = watch_lost(&device);
+
+        let formats = OutputFormats::negotiate(&[], false);
+        let surface_cfg = SurfaceConfiguration {
+            usage: formats.surface_usage(),
+            format: formats.surface,
+            width,
+            height,
+            present_mode: PresentMode::AutoVsync,
+            alpha_mode: Default::default(),
+            view_formats: formats.surface_view_formats(),
+        };
+        let timer = GpuTimer::new(&device, &queue);
+        let uniforms = MainUniformBuffer::new(&device);
+        let size_scale = [width as f32 / 1600.0, height as f32 / 900.0];
+        let render_scale = 1.0;
+        let views = MainRenderViews::new(&device, &surface_cfg, formats.color, Self::scaled_size(&surface_cfg, render_scale));
+        Ok(Self {
+            surface: None,
+            surface_cfg,
+            formats,
+            hdr: false,
+            adapter: Arc::new(adapter),
+            device,
+            adapter_info,
+            queue,
+            views,
+            uniforms,
+            size_scale,
+            render_scale,
+            timer,
+            lost,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use wgpu::TextureFormat;
+
+    use crate::engine::render::state::{HDR_FORMAT, is_device_lost, OutputFormats};
+
+    #[test]
+    fn test_device_lost() {
+        assert!(is_device_lost("Validation Error\n\nCaused by:\n    In Queue::submit\n    Parent device is lost\n"));
+        assert!(!is_device_lost("Validation Error\n\nCaused by:\n    In a RenderPass\n    Buffer is destroyed\n"));
+    }
+
+    #[test]
+    fn test_output_formats() {
+        let srgb = [TextureFormat::Bgra8UnormSrgb, TextureFormat::Bgra8Unorm, HDR_FORMAT];
+        let formats = OutputFormats::negotiate(&srgb, false);
+        assert_eq!(formats, OutputFormats { surface: TextureFormat::Bgra8UnormSrgb, color: TextureFormat::Bgra8Unorm });
+        assert!(formats.is_copied());
+
+        // the platforms preferring rgba
+        let formats = OutputFormats::negotiate(&[TextureFormat::Rgb10a2Unorm, TextureFormat::Rgba8Unorm], false);
+        assert_eq!(formats.color, TextureFormat::Rgba8Unorm);
+        assert!(formats.is_copied());
+        assert_eq!(OutputFormats::negotiate(&[TextureFormat::Rgb10a2Unorm], false).color, TextureFormat::Rgb10a2Unorm);
+        assert_eq!(OutputFormats::negotiate(&[], false).surface, TextureFormat::Bgra8Unorm);
+
+        let hdr = OutputFormats::negotiate(&srgb, true);
+        assert!(hdr.is_hdr_output() && !hdr.is_copied());
+        // tonemapped to the 8 bits surface
+        let sdr = OutputFormats::negotiate(&[TextureFormat::Rgba8UnormSrgb], true);
+        assert_eq!(sdr, OutputFormats { surface: TextureFormat::Rgba8UnormSrgb, color: HDR_FORMAT });
+        assert!(!sdr.is_hdr_output() && !sdr.is_copied());
+        // the present pass encodes the colors itself
+        assert_eq!(sdr.present_format(), TextureFormat::Rgba8Unorm);
+        assert_eq!(sdr.surface_view_formats(), vec![TextureFormat::Rgba8UnormSrgb, TextureFormat::Rgba8Unorm]);
+        assert_eq!(hdr.present_format(), HDR_FORMAT);
+        assert_eq!(hdr.surface_view_formats(), vec![HDR_FORMAT]);
+    }
 }
@@ -0,0 +1,131 @@
+//! The named render targets in the views, resized with the screen and released after unused.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use wgpu::{BindGroup, Device, TextureFormat};
+
+use crate::engine::render::{MainRenderViews, TextureWrapper};
+
+/// The frames a target can be unused before released by [`MainRenderViews::end_frame`].
+pub const TARGET_IDLE_FRAMES: u64 = 600;
+
+/// The generation of the targets created, never reused.
+static GENERATION: AtomicU64 = AtomicU64::new(1);
+
+/// The size of the target, following the screen or the scene if not fixed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TargetSize {
+    Fixed(u32, u32),
+    /// The size of the surface.
+    Screen,
+    /// The size of the 3d scene after the render scale.
+    Scene,
+}
+
+impl TargetSize {
+    /// The size in pixels by the screen and the scene size, at least 1.
+    pub fn resolve(&self, screen: (u32, u32), scene: (u32, u32)) -> (u32, u32) {
+        let (w, h) = match *self {
+            TargetSize::Fixed(w, h) => (w, h),
+            TargetSize::Screen => screen,
+            TargetSize::Scene => scene,
+        };
+        (w.max(1), h.max(1))
+    }
+
+    /// The size changes with the screen.
+    pub fn is_relative(&self) -> bool {
+        !matches!(self, TargetSize::Fixed(..))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TargetDesc {
+    pub size: TargetSize,
+    pub format: TextureFormat,
+}
+
+/// The texture of the named target, created again with the new generation if the size or the format changed.
+#[derive(Debug)]
+pub struct RenderTarget {
+    pub texture: TextureWrapper,
+    pub desc: TargetDesc,
+    pub generation: u64,
+    /// The frame of the views got last time.
+    last_used: AtomicU64,
+}
+
+#[allow(unused)]
+impl RenderTarget {
+    pub fn new(device: &Device, desc: TargetDesc, size: (u32, u32), frame: u64) -> Self {
+        Self {
+            texture: TextureWrapper::new_with_size(device, desc.format, size),
+            desc,
+            generation: GENERATION.fetch_add(1, Ordering::Relaxed),
+            last_used: AtomicU64::new(frame),
+        }
+    }
+
+    /// The target is in the size and the format.
+    pub fn matches(&self, desc: &TargetDesc, size: (u32, u32)) -> bool {
+        self.desc.format == desc.format && (self.texture.info.width, self.texture.info.height) == size
+    }
+
+    pub(crate) fn mark_used(&self, frame: u64) {
+        self.last_used.fetch_max(frame, Ordering::Relaxed);
+    }
+
+    pub fn last_used(&self) -> u64 {
+        self.last_used.load(Ordering::Relaxed)
+    }
+}
+
+/// The bind group of the named targets, created again after any of them recreated or released.
+#[derive(Debug, Default)]
+pub struct TargetBind {
+    generations: Vec<u64>,
+    bind: Option<BindGroup>,
+}
+
+#[allow(unused)]
+impl TargetBind {
+    /// Get the bind group of the targets, created by `create` if the targets changed.
+    ///
+    /// Return none if any of the targets not exists.
+    pub fn get(&mut self, views: &MainRenderViews, ids: &[&str], create: impl FnOnce(&[&TextureWrapper]) -> BindGroup) -> Option<&BindGroup> {
+        let targets = ids.iter().map(|x| views.get_target(x)).collect::<Option<Vec<_>>>();
+        let targets = if let Some(x) = targets { x } else {
+            self.clear();
+            return None;
+        };
+        let generations = targets.iter().map(|x| x.generation);
+        if self.bind.is_none() || !generations.clone().eq(self.generations.iter().copied()) {
+            let textures = targets.iter().map(|x| &x.texture).collect::<Vec<_>>();
+            self.bind = Some(create(&textures));
+            self.generations = generations.collect();
+        }
+        self.bind.as_ref()
+    }
+
+    /// Drop the bind group, created again when got.
+    pub fn clear(&mut self) {
+        self.bind = None;
+        self.generations.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::engine::render::target::TargetSize;
+
+    #[test]
+    fn test_target_size() {
+        let (screen, scene) = ((1600, 900), (800, 450));
+        assert_eq!(TargetSize::Fixed(400, 225).resolve(screen, scene), (400, 225));
+        assert_eq!(TargetSize::Screen.resolve(screen, scene), screen);
+        assert_eq!(TargetSize::Scene.resolve(screen, scene), scene);
+        // never empty
+        assert_eq!(TargetSize::Fixed(0, 10).resolve(screen, scene), (1, 10));
+        assert!(TargetSize::Scene.is_relative() && !TargetSize::Fixed(1, 1).is_relative());
+    }
+}
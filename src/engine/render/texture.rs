@@ -1,7 +1,65 @@
+use std::collections::HashSet;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
 use image::GenericImageView;
+use log::warn;
+use once_cell::sync::Lazy;
 use wgpu::{AddressMode, Device, FilterMode, Queue, Sampler, SamplerDescriptor, SurfaceConfiguration, Texture, TextureFormat, TextureView};
 use wgpu::util::DeviceExt;
 
+/// The file stems of the data textures end with, loaded as linear like `floor_normal.png`.
+const LINEAR_SUFFIXES: [&str; 3] = ["_normal", "_mr", "_data"];
+
+/// Warn the textures bound in the other color space, enabled by the env `validate_color=1`.
+static COLOR_VALIDATION: Lazy<AtomicBool> = Lazy::new(|| {
+    AtomicBool::new(std::env::var("validate_color").map(|x| x == "1").unwrap_or(false))
+});
+/// The textures warned, by the address.
+static WARNED: Lazy<Mutex<HashSet<usize>>> = Lazy::new(Default::default);
+
+/// The color space of the texels.
+///
+/// The colors sampled from the sRGB textures are decoded to linear, lit and encoded by the shaders into the views.
+/// The views hold the colors encoded already, so they are sampled as linear without decoded.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ColorSpace {
+    /// The colors like the albedo and the emissive.
+    Srgb,
+    /// The data like the normals and the metallic roughness.
+    Linear,
+}
+
+#[allow(unused)]
+impl ColorSpace {
+    /// The format of the 8 bits rgba texture in the space.
+    pub fn format(&self) -> TextureFormat {
+        match self {
+            ColorSpace::Srgb => TextureFormat::Rgba8UnormSrgb,
+            ColorSpace::Linear => TextureFormat::Rgba8Unorm,
+        }
+    }
+
+    pub fn of_format(format: TextureFormat) -> Self {
+        if format.is_srgb() { ColorSpace::Srgb } else { ColorSpace::Linear }
+    }
+
+    /// The space of the texture asset by the path, linear if named as the data.
+    pub fn of_asset(path: &str) -> Self {
+        let stem = std::path::Path::new(path).file_stem().and_then(|x| x.to_str()).unwrap_or(path);
+        if LINEAR_SUFFIXES.iter().any(|x| stem.ends_with(x)) { ColorSpace::Linear } else { ColorSpace::Srgb }
+    }
+}
+
+/// Enable or disable warning the textures bound in the other color space.
+pub fn set_color_validation(enabled: bool) {
+    COLOR_VALIDATION.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_color_validation() -> bool {
+    COLOR_VALIDATION.load(Ordering::Relaxed)
+}
+
 #[allow(unused)]
 #[derive(Debug)]
 pub struct TextureWrapper {
@@ -27,6 +85,16 @@ impl TextureInfo {
 
 #[allow(unused)]
 impl TextureWrapper {
+    /// The estimated gpu memory of all mip levels.
+    pub fn byte_size(&self) -> u64 {
+        let size = self.texture.size();
+        let block = self.texture.format().block_size(None).unwrap_or(4) as u64;
+        (0..self.texture.mip_level_count())
+            .map(|level| size.mip_level_size(level, self.texture.dimension()))
+            .map(|x| x.width as u64 * x.height as u64 * x.depth_or_array_layers as u64 * block)
+            .sum()
+    }
+
     pub fn create_depth_texture(
         device: &wgpu::Device,
         config: &wgpu::SurfaceConfiguration,
@@ -127,17 +195,18 @@ impl TextureWrapper {
         Self { texture, view, info: TextureInfo::new(size.width, size.height) }
     }
 
-    pub fn from_bytes(device: &Device, queue: &Queue, bytes: &[u8], label: Option<&str>, flip_y: bool) -> anyhow::Result<Self> {
+    /// Load the image as the texture in the space, the albedo should be [`ColorSpace::Srgb`].
+    pub fn from_bytes(device: &Device, queue: &Queue, bytes: &[u8], label: Option<&str>, flip_y: bool, space: ColorSpace) -> anyhow::Result<Self> {
         let img = image::load_from_memory(bytes)?;
         let img = if flip_y {
             img.flipv()
         } else {
             img
         };
-        Self::from_image(device, queue, &img, label)
+        Self::from_image(device, queue, &img, label, space)
     }
 
-    pub fn from_image(device: &Device, queue: &Queue, img: &image::DynamicImage, label: Option<&str>,
+    pub fn from_image(device: &Device, queue: &Queue, img: &image::DynamicImage, label: Option<&str>, space: ColorSpace,
     ) -> anyhow::Result<Self> {
         let rgba = img.to_rgba8();
         let dimensions = img.dimensions();
@@ -153,9 +222,10 @@ impl TextureWrapper {
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: TextureFormat::Rgba8Unorm,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-            view_formats: &[TextureFormat::Rgba8Unorm],
+            format: space.format(),
+            // copied to the texture arrays
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[space.format()],
         }, rgba.as_ref());
 
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
@@ -163,6 +233,32 @@ impl TextureWrapper {
         Ok(Self { texture, view, info: TextureInfo::new(size.width, size.height) })
     }
 
+    pub fn space(&self) -> ColorSpace {
+        ColorSpace::of_format(self.texture.format())
+    }
+
+    /// The view sampled as the colors, warned once in the color validation if not sRGB.
+    pub fn srgb_view(&self) -> &TextureView {
+        self.check_space(ColorSpace::Srgb);
+        &self.view
+    }
+
+    /// The view sampled as the data, warned once in the color validation if sRGB.
+    pub fn linear_view(&self) -> &TextureView {
+        self.check_space(ColorSpace::Linear);
+        &self.view
+    }
+
+    fn check_space(&self, expected: ColorSpace) {
+        if !is_color_validation() || self.space() == expected {
+            return;
+        }
+        if WARNED.lock().unwrap().insert(&self.texture as *const Texture as usize) {
+            warn!(target: "texture", "The {:?} texture of {}x{} is sampled as {:?}",
+                self.texture.format(), self.info.width, self.info.height, expected);
+        }
+    }
+
     pub fn create_linear_sampler(device: &Device) -> Sampler {
         device.create_sampler(&SamplerDescriptor {
             mag_filter: FilterMode::Linear,
@@ -184,3 +280,217 @@ impl TextureWrapper {
         })
     }
 }
+
+/// The cube map texture with the cube view.
+#[allow(unused)]
+#[derive(Debug)]
+pub struct CubeTexture {
+    pub texture: Texture,
+    pub view: TextureView,
+    /// The size of one face.
+    pub info: TextureInfo,
+}
+
+#[allow(unused)]
+impl CubeTexture {
+    /// The image of the faces stacked vertically in the order of +X, -X, +Y, -Y, +Z, -Z, the colors in sRGB.
+    pub fn from_bytes(device: &Device, queue: &Queue, bytes: &[u8], label: Option<&str>) -> anyhow::Result<Self> {
+        let img = image::load_from_memory(bytes)?;
+        let (width, height) = face_size(img.dimensions())?;
+        let rgba = img.to_rgba8();
+
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 6,
+        };
+        let texture = device.create_texture_with_data(queue, &wgpu::TextureDescriptor {
+            label,
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[TextureFormat::Rgba8UnormSrgb],
+        }, rgba.as_ref());
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label,
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            ..Default::default()
+        });
+
+        Ok(Self { texture, view, info: TextureInfo::new(width, height) })
+    }
+}
+
+/// The textures of the same size as the layers, sampled by the layer index.
+#[allow(unused)]
+#[derive(Debug)]
+pub struct TextureArray {
+    pub texture: Texture,
+    pub view: TextureView,
+    /// The size of one layer.
+    pub info: TextureInfo,
+    pub layers: u32,
+}
+
+#[allow(unused)]
+impl TextureArray {
+    /// Copy the textures to the layers in order, the textures should be the same size and format.
+    pub fn from_textures(device: &Device, queue: &Queue, textures: &[&TextureWrapper], label: Option<&str>) -> anyhow::Result<Self> {
+        let (width, height) = layer_size(&textures.iter().map(|x| (x.info.width, x.info.height)).collect::<Vec<_>>())?;
+        let format = textures[0].texture.format();
+        if let Some(x) = textures.iter().find(|x| x.texture.format() != format) {
+            anyhow::bail!("The layers should be the same format {:?} but got {:?}", format, x.texture.format());
+        }
+        let layers = textures.len() as u32;
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: layers,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label,
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[format],
+        });
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("Texture Array Encoder") });
+        for (layer, x) in textures.iter().enumerate() {
+            encoder.copy_texture_to_texture(x.texture.as_image_copy(), wgpu::ImageCopyTexture {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x: 0, y: 0, z: layer as u32 },
+                aspect: wgpu::TextureAspect::All,
+            }, wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            });
+        }
+        queue.submit(Some(encoder.finish()));
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label,
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        Ok(Self { texture, view, info: TextureInfo::new(width, height), layers })
+    }
+}
+
+/// The size of the layers, all should be the same.
+fn layer_size(sizes: &[(u32, u32)]) -> anyhow::Result<(u32, u32)> {
+    let first = if let Some(x) = sizes.first() { *x } else {
+        anyhow::bail!("The texture array should have at least one layer");
+    };
+    if let Some(x) = sizes.iter().find(|x| **x != first) {
+        anyhow::bail!("The layers should be the same size {:?} but got {:?}", first, x);
+    }
+    Ok(first)
+}
+
+/// The sampler of the plane textures.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SamplerOptions {
+    /// The address mode in u and v.
+    pub address: AddressMode,
+    /// Filter the magnified texels linearly, or nearest as the pixel floors.
+    pub linear: bool,
+    /// The max anisotropy from 1 to 16, all filters are linear if more than 1.
+    pub anisotropy: u16,
+}
+
+impl Default for SamplerOptions {
+    fn default() -> Self {
+        Self {
+            address: AddressMode::Repeat,
+            linear: false,
+            anisotropy: 1,
+        }
+    }
+}
+
+#[allow(unused)]
+impl SamplerOptions {
+    pub fn descriptor(&self) -> SamplerDescriptor<'static> {
+        let anisotropy_clamp = self.anisotropy.clamp(1, 16);
+        let linear = self.linear || anisotropy_clamp > 1;
+        SamplerDescriptor {
+            label: Some("plane sampler"),
+            address_mode_u: self.address,
+            address_mode_v: self.address,
+            mag_filter: if linear { FilterMode::Linear } else { FilterMode::Nearest },
+            min_filter: FilterMode::Linear,
+            mipmap_filter: if anisotropy_clamp > 1 { FilterMode::Linear } else { FilterMode::Nearest },
+            anisotropy_clamp,
+            ..Default::default()
+        }
+    }
+
+    pub fn create(&self, device: &Device) -> Sampler {
+        device.create_sampler(&self.descriptor())
+    }
+}
+
+/// The size of one face of the vertical strip of the cube map.
+fn face_size(dimensions: (u32, u32)) -> anyhow::Result<(u32, u32)> {
+    let (width, height) = dimensions;
+    if width == 0 || height != width * 6 {
+        anyhow::bail!("The cube map should be 6 square faces stacked vertically but got {}x{}", width, height);
+    }
+    Ok((width, width))
+}
+
+#[cfg(test)]
+mod test {
+    use wgpu::{AddressMode, FilterMode};
+
+    use crate::engine::render::texture::{ColorSpace, face_size, layer_size, SamplerOptions};
+
+    #[test]
+    fn test_face_size() {
+        assert_eq!(face_size((64, 384)).unwrap(), (64, 64));
+        assert!(face_size((64, 64)).is_err());
+        assert!(face_size((0, 0)).is_err());
+    }
+
+    #[test]
+    fn test_color_space() {
+        assert_eq!(ColorSpace::of_asset("texture/pf.png"), ColorSpace::Srgb);
+        assert_eq!(ColorSpace::of_asset("texture/floor_normal.png"), ColorSpace::Linear);
+        assert_eq!(ColorSpace::of_asset("floor_mr"), ColorSpace::Linear);
+        // only the stem
+        assert_eq!(ColorSpace::of_asset("texture_data/pf.png"), ColorSpace::Srgb);
+        for space in [ColorSpace::Srgb, ColorSpace::Linear] {
+            assert_eq!(ColorSpace::of_format(space.format()), space);
+        }
+        assert_eq!(ColorSpace::of_format(wgpu::TextureFormat::Bgra8Unorm), ColorSpace::Linear);
+    }
+
+    #[test]
+    fn test_layer_size() {
+        assert_eq!(layer_size(&[(2, 2), (2, 2)]).unwrap(), (2, 2));
+        assert!(layer_size(&[(2, 2), (32, 32)]).is_err());
+        assert!(layer_size(&[]).is_err());
+    }
+
+    #[test]
+    fn test_sampler_options() {
+        let desc = SamplerOptions::default().descriptor();
+        assert_eq!(desc.anisotropy_clamp, 1);
+        assert_eq!(desc.mag_filter, FilterMode::Nearest);
+
+        // the anisotropy needs the linear filters
+        let desc = SamplerOptions { address: AddressMode::ClampToEdge, linear: false, anisotropy: 32 }.descriptor();
+        assert_eq!(desc.anisotropy_clamp, 16);
+        assert_eq!(desc.address_mode_u, AddressMode::ClampToEdge);
+        assert_eq!([desc.mag_filter, desc.min_filter, desc.mipmap_filter], [FilterMode::Linear; 3]);
+    }
+}
@@ -0,0 +1,196 @@
+use std::any::type_name;
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex};
+
+use wgpu::*;
+
+/// The max timestamps written in the frame, the scopes over it are not timed.
+const MAX_QUERIES: u32 = 512;
+/// The frames waiting to be read back at most, the frame is not timed if all are waiting.
+const READBACKS: usize = 3;
+
+/// The pass timed in the frame by [`GpuTimer::begin`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct GpuScope {
+    name: &'static str,
+    start: u32,
+}
+
+#[derive(Debug)]
+struct Readback {
+    buffer: Buffer,
+    /// The names of the scopes in the order of their timestamps, empty if free.
+    names: Vec<&'static str>,
+    /// Set when the map finished, false if failed.
+    mapped: Arc<Mutex<Option<bool>>>,
+}
+
+#[derive(Debug)]
+struct TimerFrame {
+    /// The names of the scopes ended in this frame, the timestamps are at 2 * index.
+    names: Vec<&'static str>,
+    /// The next query to write.
+    next: u32,
+    readbacks: Vec<Readback>,
+    /// The milliseconds of the passes in the last frame read back.
+    results: Vec<(&'static str, f32)>,
+}
+
+/// Time the passes on the gpu by the timestamp queries, read back some frames later.
+pub struct GpuTimer {
+    set: QuerySet,
+    resolve: Buffer,
+    /// The nanoseconds per tick.
+    period: f32,
+    frame: Mutex<TimerFrame>,
+}
+
+/// Sum the milliseconds of the scopes by their names in the order first ended.
+fn pass_millis(names: &[&'static str], ticks: &[u64], period: f32) -> Vec<(&'static str, f32)> {
+    let mut result: Vec<(&'static str, f32)> = vec![];
+    for (name, x) in names.iter().zip(ticks.chunks_exact(2)) {
+        let ms = x[1].saturating_sub(x[0]) as f32 * period / 1_000_000.0;
+        match result.iter_mut().find(|(n, _)| n == name) {
+            Some((_, total)) => *total += ms,
+            None => result.push((name, ms)),
+        }
+    }
+    result
+}
+
+impl Debug for GpuTimer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct(type_name::<Self>())
+            .field("period", &self.period)
+            .finish()
+    }
+}
+
+#[allow(unused)]
+impl GpuTimer {
+    /// None if the timestamp queries are not supported.
+    pub fn new(device: &Device, queue: &Queue) -> Option<Self> {
+        if !device.features().contains(Features::TIMESTAMP_QUERY) {
+            return None;
+        }
+        let size = MAX_QUERIES as BufferAddress * std::mem::size_of::<u64>() as BufferAddress;
+        let set = device.create_query_set(&QuerySetDescriptor {
+            label: Some("Gpu timer queries"),
+            ty: QueryType::Timestamp,
+            count: MAX_QUERIES,
+        });
+        let resolve = device.create_buffer(&BufferDescriptor {
+            label: Some("Gpu timer resolve"),
+            size,
+            usage: BufferUsages::QUERY_RESOLVE | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readbacks = (0..READBACKS).map(|_| Readback {
+            buffer: device.create_buffer(&BufferDescriptor {
+                label: Some("Gpu timer readback"),
+                size,
+                usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+            names: vec![],
+            mapped: Default::default(),
+        }).collect();
+        Some(Self {
+            set,
+            resolve,
+            period: queue.get_timestamp_period(),
+            frame: Mutex::new(TimerFrame { names: vec![], next: 0, readbacks, results: vec![] }),
+        })
+    }
+
+    /// Write the start of the pass before the commands, none if out of the queries.
+    pub fn begin(&self, encoder: &mut CommandEncoder, name: &'static str) -> Option<GpuScope> {
+        let mut frame = self.frame.lock().unwrap();
+        if frame.next + 2 > MAX_QUERIES {
+            return None;
+        }
+        let start = frame.next;
+        frame.next += 2;
+        encoder.write_timestamp(&self.set, start);
+        Some(GpuScope { name, start })
+    }
+
+    /// Write the end of the pass after the commands.
+    pub fn end(&self, encoder: &mut CommandEncoder, scope: Option<GpuScope>) {
+        if let Some(scope) = scope {
+            encoder.write_timestamp(&self.set, scope.start + 1);
+            let mut frame = self.frame.lock().unwrap();
+            let idx = (scope.start / 2) as usize;
+            if frame.names.len() <= idx {
+                frame.names.resize(idx + 1, "");
+            }
+            frame.names[idx] = scope.name;
+        }
+    }
+
+    /// Copy the timestamps of the frame to read back after all the passes submitted.
+    pub fn resolve(&self, device: &Device, queue: &Queue) {
+        let mut frame = self.frame.lock().unwrap();
+        let count = frame.next;
+        let names = std::mem::take(&mut frame.names);
+        frame.next = 0;
+        // the scopes begun but not ended are not written
+        if count == 0 || names.len() * 2 != count as usize || names.iter().any(|x| x.is_empty()) {
+            return;
+        }
+        let readback = if let Some(x) = frame.readbacks.iter_mut().find(|x| x.names.is_empty()) { x } else {
+            return;
+        };
+        let size = count as BufferAddress * std::mem::size_of::<u64>() as BufferAddress;
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor { label: Some("Gpu timer resolve encoder") });
+        encoder.resolve_query_set(&self.set, 0..count, &self.resolve, 0);
+        encoder.copy_buffer_to_buffer(&self.resolve, 0, &readback.buffer, 0, size);
+        queue.submit(Some(encoder.finish()));
+
+        readback.names = names;
+        let mapped = readback.mapped.clone();
+        readback.buffer.slice(..size).map_async(MapMode::Read, move |x| {
+            *mapped.lock().unwrap() = Some(x.is_ok());
+        });
+    }
+
+    /// Read the frames mapped, return the milliseconds of the passes in the newest read.
+    pub fn poll(&self, device: &Device) -> Vec<(&'static str, f32)> {
+        device.poll(Maintain::Poll);
+        let mut frame = self.frame.lock().unwrap();
+        let frame = &mut *frame;
+        for x in &mut frame.readbacks {
+            let mapped = x.mapped.lock().unwrap().take();
+            match mapped {
+                Some(true) => {
+                    let size = x.names.len() as BufferAddress * 2 * std::mem::size_of::<u64>() as BufferAddress;
+                    {
+                        let data = x.buffer.slice(..size).get_mapped_range();
+                        let ticks: &[u64] = bytemuck::cast_slice(&data);
+                        frame.results = pass_millis(&x.names, ticks, self.period);
+                    }
+                    x.buffer.unmap();
+                    x.names.clear();
+                }
+                Some(false) => x.names.clear(),
+                None => {}
+            }
+        }
+        frame.results.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::engine::render::timestamp::pass_millis;
+
+    #[test]
+    fn test_pass_millis() {
+        let names = ["scene", "portal depth", "portal depth", "egui"];
+        let ticks = [0, 1000, 1000, 1500, 2000, 2500, 3000, 3000];
+        let result = pass_millis(&names, &ticks, 1000.0);
+        assert_eq!(result, vec![("scene", 1.0), ("portal depth", 1.0), ("egui", 0.0)]);
+        // the ticks reset are not negative
+        assert_eq!(pass_millis(&["copy"], &[10, 5], 1.0), vec![("copy", 0.0)]);
+    }
+}
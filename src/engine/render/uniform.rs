@@ -88,10 +88,56 @@ impl MainUniformBuffer {
     #[inline]
     /// Write the uniform data to buffer but not submit
     pub fn update_staging(&self, device: &Device, ce: &mut CommandEncoder, staging: &mut StagingBelt) {
-        let data = bytemuck::cast_slice(from_ref(&self.data.camera));
-        let mut view = staging.write_buffer(ce, &self.uniform_buffer, 0, BufferSize::new(data.len() as _).unwrap(),
-                                            device);
-        view[..data.len()].copy_from_slice(data);
+        self.stage(&mut UniformStager::new(device, ce, staging));
+    }
+
+    /// Write the camera by the stager.
+    pub fn stage(&self, stager: &mut UniformStager) {
+        stager.write(&self.uniform_buffer, 0, &self.data.camera);
+    }
+}
+
+/// Write the uniforms of the frame by the staging belt instead of the queue, copied in the order recorded in the encoder.
+///
+/// The belt should be finished before the encoder submitted and recalled after.
+pub struct UniformStager<'a> {
+    device: &'a Device,
+    encoder: &'a mut CommandEncoder,
+    belt: &'a mut StagingBelt,
+    /// The writes in the frame.
+    pub writes: u32,
+    /// The bytes written in the frame.
+    pub bytes: BufferAddress,
+}
+
+#[allow(unused)]
+impl<'a> UniformStager<'a> {
+    pub fn new(device: &'a Device, encoder: &'a mut CommandEncoder, belt: &'a mut StagingBelt) -> Self {
+        Self {
+            device,
+            encoder,
+            belt,
+            writes: 0,
+            bytes: 0,
+        }
+    }
+
+    /// Write the bytes to the buffer at the offset, both should be aligned by 4.
+    pub fn write_bytes(&mut self, buffer: &Buffer, offset: BufferAddress, data: &[u8]) {
+        let size = if let Some(x) = BufferSize::new(data.len() as _) { x } else {
+            return;
+        };
+        self.belt.write_buffer(self.encoder, buffer, offset, size, self.device).copy_from_slice(data);
+        self.writes += 1;
+        self.bytes += size.get();
+    }
+
+    pub fn write<T: bytemuck::Pod>(&mut self, buffer: &Buffer, offset: BufferAddress, data: &T) {
+        self.write_bytes(buffer, offset, bytemuck::bytes_of(data));
+    }
+
+    pub fn write_slice<T: bytemuck::Pod>(&mut self, buffer: &Buffer, offset: BufferAddress, data: &[T]) {
+        self.write_bytes(buffer, offset, bytemuck::cast_slice(data));
     }
 }
 
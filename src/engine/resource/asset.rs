@@ -0,0 +1,244 @@
+use std::fmt::{Debug, Formatter};
+use std::hash::{Hash, Hasher};
+use std::io::Cursor;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use dashmap::mapref::one::Ref;
+use wgpu_glyph::ab_glyph::FontRef;
+use egui::FontData;
+use kira::sound::static_sound::{StaticSoundData, StaticSoundSettings};
+use wgpu::{Device, Queue, ShaderModule, ShaderModuleDescriptor, ShaderSource};
+
+use crate::engine::{ColorSpace, CubeTexture, ResourceManager, TextureWrapper, WgpuData};
+use crate::engine::glft::model::Model;
+
+/// The typed id of the asset in the [`ResourceManager`]
+///
+/// The handle is valid once the load is queued, the asset can be got after loaded.
+pub struct Handle<T> {
+    id: u64,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Handle<T> {
+    pub(super) fn new(id: u64) -> Self {
+        Self {
+            id,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Handle<T> {}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> Hash for Handle<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state)
+    }
+}
+
+impl<T> Debug for Handle<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Handle<{}>({})", std::any::type_name::<T>(), self.id)
+    }
+}
+
+/// The gpu objects to create the assets in the io threads.
+#[derive(Clone)]
+pub struct LoadContext {
+    pub device: Arc<Device>,
+    pub queue: Arc<Queue>,
+}
+
+impl LoadContext {
+    pub fn new(gpu: &WgpuData) -> Self {
+        Self {
+            device: gpu.device.clone(),
+            queue: gpu.queue.clone(),
+        }
+    }
+}
+
+/// The asset type can be loaded by [`ResourceManager::load`]
+pub trait Asset: Sized + Send + Sync + 'static {
+    /// Load the asset in the path of the resource packs.
+    fn load(res: &ResourceManager, ctx: &LoadContext, path: &str) -> anyhow::Result<Self>;
+
+    /// The storage of the asset type in the manager.
+    fn storage(res: &ResourceManager) -> &AssetStorage<Self>;
+
+    /// Called before the asset loaded from the path inserted to the storage.
+    fn on_loaded(_res: &ResourceManager, _handle: Handle<Self>, _asset: &Self) {}
+}
+
+/// The loaded assets of one type with the names to find the handle.
+pub struct AssetStorage<T> {
+    assets: DashMap<Handle<T>, T>,
+    names: DashMap<String, Handle<T>>,
+}
+
+impl<T> Default for AssetStorage<T> {
+    fn default() -> Self {
+        Self {
+            assets: Default::default(),
+            names: Default::default(),
+        }
+    }
+}
+
+impl<T> Debug for AssetStorage<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AssetStorage")
+            .field("loaded", &self.assets.len())
+            .field("names", &self.names.len())
+            .finish()
+    }
+}
+
+#[allow(unused)]
+impl<T> AssetStorage<T> {
+    pub fn get(&self, handle: Handle<T>) -> Option<Ref<'_, Handle<T>, T>> {
+        self.assets.get(&handle)
+    }
+
+    /// Get the asset by the name when queued.
+    pub fn by_name(&self, name: &str) -> Option<Ref<'_, Handle<T>, T>> {
+        self.handle(name).and_then(|x| self.get(x))
+    }
+
+    pub fn handle(&self, name: &str) -> Option<Handle<T>> {
+        self.names.get(name).map(|x| *x)
+    }
+
+    /// The name bound to the handle.
+    pub fn name_of(&self, handle: Handle<T>) -> Option<String> {
+        self.names.iter().find(|x| *x.value() == handle).map(|x| x.key().clone())
+    }
+
+    /// The names with the handles, sorted by the name.
+    pub fn names(&self) -> Vec<(String, Handle<T>)> {
+        let mut names = self.names.iter().map(|x| (x.key().clone(), *x.value())).collect::<Vec<_>>();
+        names.sort_by(|a, b| a.0.cmp(&b.0));
+        names
+    }
+
+    pub fn is_loaded(&self, handle: Handle<T>) -> bool {
+        self.assets.contains_key(&handle)
+    }
+
+    pub fn len(&self) -> usize {
+        self.assets.len()
+    }
+
+    /// Get the handle of the name, or bind the name to the new handle.
+    pub(super) fn name_handle(&self, name: String, new: impl FnOnce() -> Handle<T>) -> Handle<T> {
+        *self.names.entry(name).or_insert_with(new)
+    }
+
+    /// Insert or replace the asset.
+    pub(super) fn insert(&self, handle: Handle<T>, asset: T) {
+        self.assets.insert(handle, asset);
+    }
+
+    pub fn remove(&self, handle: Handle<T>) -> Option<T> {
+        self.names.retain(|_, x| *x != handle);
+        self.assets.remove(&handle).map(|x| x.1)
+    }
+
+    /// Unload the asset but keep the name, the load of the name will get the same handle.
+    pub(super) fn evict(&self, handle: Handle<T>) -> Option<T> {
+        self.assets.remove(&handle).map(|x| x.1)
+    }
+}
+
+impl Asset for TextureWrapper {
+    fn load(res: &ResourceManager, ctx: &LoadContext, path: &str) -> anyhow::Result<Self> {
+        let data = res.load_asset(path)?;
+        TextureWrapper::from_bytes(&ctx.device, &ctx.queue, &data, Some(path), false, ColorSpace::of_asset(path))
+    }
+
+    fn storage(res: &ResourceManager) -> &AssetStorage<Self> {
+        &res.textures
+    }
+
+    fn on_loaded(res: &ResourceManager, handle: Handle<Self>, asset: &Self) {
+        res.texture_budget.track(handle, asset.byte_size());
+    }
+}
+
+/// The cube map not tracked by the texture budget.
+impl Asset for CubeTexture {
+    fn load(res: &ResourceManager, ctx: &LoadContext, path: &str) -> anyhow::Result<Self> {
+        let data = res.load_asset(path)?;
+        CubeTexture::from_bytes(&ctx.device, &ctx.queue, &data, Some(path))
+    }
+
+    fn storage(res: &ResourceManager) -> &AssetStorage<Self> {
+        &res.cube_maps
+    }
+}
+
+impl Asset for Model {
+    fn load(res: &ResourceManager, ctx: &LoadContext, path: &str) -> anyhow::Result<Self> {
+        Model::load_from_res(&ctx.device, &ctx.queue, res, path, Some(path))
+    }
+
+    fn storage(res: &ResourceManager) -> &AssetStorage<Self> {
+        &res.models
+    }
+}
+
+impl Asset for StaticSoundData {
+    fn load(res: &ResourceManager, _: &LoadContext, path: &str) -> anyhow::Result<Self> {
+        let data = res.load_asset(path)?;
+        Ok(StaticSoundData::from_cursor(Cursor::new(data), StaticSoundSettings::default())?)
+    }
+
+    fn storage(res: &ResourceManager) -> &AssetStorage<Self> {
+        &res.sounds
+    }
+}
+
+/// The wgsl shader module.
+impl Asset for ShaderModule {
+    fn load(res: &ResourceManager, ctx: &LoadContext, path: &str) -> anyhow::Result<Self> {
+        let source = String::from_utf8(res.load_asset(path)?)?;
+        Ok(ctx.device.create_shader_module(ShaderModuleDescriptor {
+            label: Some(path),
+            source: ShaderSource::Wgsl(source.into()),
+        }))
+    }
+
+    fn storage(res: &ResourceManager) -> &AssetStorage<Self> {
+        &res.shaders
+    }
+}
+
+/// The ttf or otf font for the egui, checked by parsing it.
+impl Asset for FontData {
+    fn load(res: &ResourceManager, _: &LoadContext, path: &str) -> anyhow::Result<Self> {
+        let data = res.load_asset(path)?;
+        FontRef::try_from_slice(&data)?;
+        Ok(FontData::from_owned(data))
+    }
+
+    fn storage(res: &ResourceManager) -> &AssetStorage<Self> {
+        &res.ui_fonts
+    }
+}
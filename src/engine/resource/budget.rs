@@ -0,0 +1,121 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::engine::{Handle, TextureWrapper};
+
+/// The default bytes of the textures kept loaded.
+#[cfg(not(target_os = "android"))]
+pub const DEFAULT_TEXTURE_BUDGET: u64 = 256 * 1024 * 1024;
+#[cfg(target_os = "android")]
+pub const DEFAULT_TEXTURE_BUDGET: u64 = 64 * 1024 * 1024;
+
+#[derive(Debug, Copy, Clone)]
+struct TextureUsage {
+    bytes: u64,
+    /// The tick of the last use, larger is newer.
+    last_used: u64,
+}
+
+/// Track the gpu memory of the loaded textures to evict the least recently used.
+#[derive(Debug)]
+pub struct TextureBudget {
+    budget: AtomicU64,
+    tick: AtomicU64,
+    usages: Mutex<HashMap<Handle<TextureWrapper>, TextureUsage>>,
+}
+
+impl Default for TextureBudget {
+    fn default() -> Self {
+        Self {
+            budget: AtomicU64::new(DEFAULT_TEXTURE_BUDGET),
+            tick: AtomicU64::new(0),
+            usages: Default::default(),
+        }
+    }
+}
+
+#[allow(unused)]
+impl TextureBudget {
+    pub fn budget(&self) -> u64 {
+        self.budget.load(Ordering::Relaxed)
+    }
+
+    pub fn set_budget(&self, bytes: u64) {
+        self.budget.store(bytes, Ordering::Relaxed);
+    }
+
+    /// The bytes of all textures tracked.
+    pub fn used(&self) -> u64 {
+        self.usages.lock().unwrap().values().map(|x| x.bytes).sum()
+    }
+
+    /// Track the texture loaded, replace the old size if reloaded.
+    pub fn track(&self, handle: Handle<TextureWrapper>, bytes: u64) {
+        let last_used = self.tick.fetch_add(1, Ordering::Relaxed);
+        self.usages.lock().unwrap().insert(handle, TextureUsage { bytes, last_used });
+    }
+
+    pub fn untrack(&self, handle: Handle<TextureWrapper>) {
+        self.usages.lock().unwrap().remove(&handle);
+    }
+
+    /// Mark the texture used now.
+    pub fn touch(&self, handle: Handle<TextureWrapper>) {
+        if let Some(x) = self.usages.lock().unwrap().get_mut(&handle) {
+            x.last_used = self.tick.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// The textures to evict to be within the budget, the least recently used first.
+    ///
+    /// The resident textures are never evicted, so the result may still be over the budget.
+    pub fn over_budget(&self, resident: &HashSet<Handle<TextureWrapper>>) -> Vec<Handle<TextureWrapper>> {
+        let usages = self.usages.lock().unwrap();
+        let budget = self.budget();
+        let mut used: u64 = usages.values().map(|x| x.bytes).sum();
+        if used <= budget {
+            return vec![];
+        }
+        let mut candidates = usages.iter()
+            .filter(|x| !resident.contains(x.0))
+            .map(|(handle, usage)| (*handle, *usage))
+            .collect::<Vec<_>>();
+        candidates.sort_by_key(|x| x.1.last_used);
+        candidates.into_iter()
+            .take_while(|(_, usage)| {
+                let over = used > budget;
+                used = used.saturating_sub(usage.bytes);
+                over
+            })
+            .map(|x| x.0)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use crate::engine::{Handle, TextureBudget};
+
+    #[test]
+    fn test_evict_lru() {
+        let budget = TextureBudget::default();
+        budget.set_budget(100);
+        let handles = (0..4).map(Handle::new).collect::<Vec<_>>();
+        for x in &handles {
+            budget.track(*x, 40);
+        }
+        assert!(budget.over_budget(&HashSet::from_iter(handles.clone())).is_empty());
+
+        // 160 bytes, 0 is resident and 1 is used recently
+        budget.touch(handles[1]);
+        let evict = budget.over_budget(&HashSet::from([handles[0]]));
+        assert_eq!(evict, vec![handles[2], handles[3]]);
+
+        budget.untrack(handles[2]);
+        assert_eq!(budget.used(), 120);
+        assert_eq!(budget.over_budget(&HashSet::new()), vec![handles[0]]);
+    }
+}
@@ -1,12 +1,23 @@
+use std::collections::HashSet;
 use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
 
 use anyhow::anyhow;
 use dashmap::DashMap;
+use dashmap::mapref::one::Ref;
+use egui::FontData;
+use futures::future::{join_all, RemoteHandle};
+use futures::task::SpawnExt;
+use kira::sound::static_sound::StaticSoundData;
 use log::info;
-use wgpu::{Device, Queue};
+use wgpu::ShaderModule;
 use wgpu_glyph::ab_glyph::FontArc;
 
-use crate::engine::TextureWrapper;
+use crate::engine::{Asset, AssetStorage, CounterProgress, CubeTexture, Handle, LoadContext, Progress, ProgressTracker, TextureBudget, TextureWrapper};
+use crate::engine::glft::model::Model;
+use crate::engine::global::IO_POOL;
+use crate::engine::pacing::mark_frame_event;
 
 #[derive(Debug)]
 pub struct ResourcePack {
@@ -42,7 +53,51 @@ pub struct ResourceManager {
     /// Index 0 will be check first
     packs: Vec<ResourcePack>,
     pub fonts: DashMap<String, FontArc>,
-    pub textures: DashMap<String, TextureWrapper>,
+    /// The fonts for the egui, used after loaded by [`crate::engine::ui::WindowUi::apply_loaded_fonts`]
+    pub ui_fonts: AssetStorage<FontData>,
+    pub textures: AssetStorage<TextureWrapper>,
+    pub cube_maps: AssetStorage<CubeTexture>,
+    pub models: AssetStorage<Model>,
+    pub sounds: AssetStorage<StaticSoundData>,
+    pub shaders: AssetStorage<ShaderModule>,
+    pub texture_budget: TextureBudget,
+    next_id: AtomicU64,
+    progress: CounterProgress,
+    /// The load tasks not waited by [`ResourceManager::wait_loading`]
+    loading: Mutex<Vec<RemoteHandle<anyhow::Result<()>>>>,
+    /// The status of the loads queued, in the queue order.
+    records: Mutex<Vec<LoadRecord>>,
+    /// The evicted textures wanted, to load by [`ResourceManager::reload_wanted`]
+    wanted: Mutex<Vec<String>>,
+}
+
+#[derive(Debug, Clone)]
+pub enum LoadStatus {
+    Loading,
+    Loaded,
+    /// The error message.
+    Failed(String),
+    /// Unloaded for the budget, can be loaded again.
+    Evicted,
+}
+
+type ReloadFn = Box<dyn Fn(&Arc<ResourceManager>, &LoadContext) + Send + Sync>;
+
+struct LoadRecord {
+    name: String,
+    path: String,
+    status: LoadStatus,
+    reload: ReloadFn,
+}
+
+impl std::fmt::Debug for LoadRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LoadRecord")
+            .field("name", &self.name)
+            .field("path", &self.path)
+            .field("status", &self.status)
+            .finish()
+    }
 }
 
 #[allow(unused)]
@@ -53,7 +108,18 @@ impl ResourceManager {
             builtin: builtin_pack,
             packs: vec![],
             fonts: Default::default(),
+            ui_fonts: Default::default(),
             textures: Default::default(),
+            cube_maps: Default::default(),
+            models: Default::default(),
+            sounds: Default::default(),
+            shaders: Default::default(),
+            texture_budget: Default::default(),
+            next_id: AtomicU64::new(0),
+            progress: Default::default(),
+            loading: Default::default(),
+            records: Default::default(),
+            wanted: Default::default(),
         })
     }
 
@@ -74,15 +140,182 @@ impl ResourceManager {
         Err(anyhow!("The path {:?} is not valid", path))
     }
 
-    pub fn load_texture(&self, device: &Device, queue: &Queue, key: String, path: &str) -> anyhow::Result<()> {
-        info!("Loading texture {} in {}", &key, path);
-        let img_data = self.load_asset(path)?;
-        let texture = TextureWrapper::from_bytes(device, queue, &img_data, Some(&key), false)?;
-        self.textures.insert(key, texture);
-        Ok(())
+    /// Queue the asset to load in the io pool.
+    ///
+    /// The asset with the same name will be replaced after loaded with the same handle.
+    pub fn load<T: Asset>(self: &Arc<Self>, ctx: &LoadContext, name: impl Into<String>, path: impl Into<String>) -> Handle<T> {
+        let name = name.into();
+        let path = path.into();
+        let handle = T::storage(self).name_handle(name.clone(), || self.new_handle());
+        self.set_status(&name, &path, LoadStatus::Loading, {
+            let (name, path) = (name.clone(), path.clone());
+            Box::new(move |res, ctx| {
+                res.load::<T>(ctx, name.clone(), path.clone());
+            })
+        });
+        let mut tracker = self.progress.create_tracker();
+        let res = self.clone();
+        let ctx = ctx.clone();
+        let task = IO_POOL.spawn_with_handle(async move {
+            info!("Loading {} in {}", name, path);
+            match T::load(&res, &ctx, &path) {
+                Ok(asset) => {
+                    T::on_loaded(&res, handle, &asset);
+                    T::storage(&res).insert(handle, asset);
+                    res.update_status(&name, LoadStatus::Loaded);
+                    mark_frame_event(format!("{} loaded", name));
+                    Ok(())
+                }
+                Err(e) => {
+                    tracker.new_error_num();
+                    res.update_status(&name, LoadStatus::Failed(format!("{:#}", e)));
+                    Err(e.context(format!("Load {} in {} failed", name, path)))
+                }
+            }
+        }).expect("Spawn load task failed");
+        self.loading.lock().unwrap().push(task);
+        handle
+    }
+
+    /// Insert the asset created by the game.
+    ///
+    /// The asset is not tracked by the budget for it cannot be loaded again.
+    pub fn insert<T: Asset>(&self, name: impl Into<String>, asset: T) -> Handle<T> {
+        let handle = T::storage(self).name_handle(name.into(), || self.new_handle());
+        T::storage(self).insert(handle, asset);
+        handle
+    }
+
+    /// Get the texture by the name and mark it used.
+    ///
+    /// The evicted texture will be wanted to load again by [`ResourceManager::reload_wanted`]
+    pub fn texture(&self, name: &str) -> Option<Ref<'_, Handle<TextureWrapper>, TextureWrapper>> {
+        let handle = self.textures.handle(name)?;
+        match self.textures.get(handle) {
+            Some(x) => {
+                self.texture_budget.touch(handle);
+                Some(x)
+            }
+            None => {
+                let evicted = self.records.lock().unwrap().iter()
+                    .any(|x| x.name == name && matches!(x.status, LoadStatus::Evicted));
+                let mut wanted = self.wanted.lock().unwrap();
+                if evicted && !wanted.iter().any(|x| x == name) {
+                    wanted.push(name.into());
+                }
+                None
+            }
+        }
+    }
+
+    /// Queue the loads of the evicted textures wanted, return the number queued.
+    pub fn reload_wanted(self: &Arc<Self>, ctx: &LoadContext) -> usize {
+        let wanted = std::mem::take(&mut *self.wanted.lock().unwrap());
+        wanted.iter().filter(|x| self.reload(x, ctx)).count()
+    }
+
+    /// Evict the least recently used textures not resident until within the budget,
+    /// return the number evicted.
+    pub fn evict_textures(&self, resident: &HashSet<Handle<TextureWrapper>>) -> usize {
+        let evict = self.texture_budget.over_budget(resident);
+        for handle in &evict {
+            self.textures.evict(*handle);
+            self.texture_budget.untrack(*handle);
+            if let Some(name) = self.textures.name_of(*handle) {
+                info!("Evicted texture {}", name);
+                self.update_status(&name, LoadStatus::Evicted);
+            }
+        }
+        evict.len()
+    }
+
+    /// Wait all the queued loads finished, return the first error if any load failed.
+    pub async fn wait_loading(&self) -> anyhow::Result<()> {
+        let tasks = std::mem::take(&mut *self.loading.lock().unwrap());
+        join_all(tasks).await.into_iter().collect()
+    }
+
+    /// The progress of all loads queued, in 0.0..=1.0
+    pub fn progress(&self) -> f32 {
+        let finished = self.progress.num_finished() as f32;
+        let total = finished + self.progress.num_loading() as f32;
+        if total == 0.0 { 1.0 } else { finished / total }
+    }
+
+    pub fn error_nums(&self) -> u16 {
+        self.progress.error_nums()
+    }
+
+    pub fn is_loading(&self) -> bool {
+        self.progress.num_loading() > 0
+    }
+
+    /// Get the (name, path, status) of the loads queued.
+    pub fn load_status(&self) -> Vec<(String, String, LoadStatus)> {
+        self.records.lock().unwrap().iter()
+            .map(|x| (x.name.clone(), x.path.clone(), x.status.clone()))
+            .collect()
+    }
+
+    pub fn has_failed(&self) -> bool {
+        self.records.lock().unwrap().iter().any(|x| matches!(x.status, LoadStatus::Failed(_)))
+    }
+
+    /// Queue the failed loads again.
+    pub fn retry_failed(self: &Arc<Self>, ctx: &LoadContext) {
+        let failed = self.records.lock().unwrap().iter()
+            .filter(|x| matches!(x.status, LoadStatus::Failed(_)))
+            .map(|x| x.name.clone())
+            .collect::<Vec<_>>();
+        for name in failed {
+            self.reload(&name, ctx);
+        }
+    }
+
+    /// Queue the load of the name again, return false if the name was never loaded.
+    pub fn reload(self: &Arc<Self>, name: &str, ctx: &LoadContext) -> bool {
+        // take the reload out to call load without the lock, load will set a new one
+        let reload = self.records.lock().unwrap().iter_mut()
+            .find(|x| x.name == name)
+            .map(|x| std::mem::replace(&mut x.reload, Box::new(|_, _| {})));
+        if let Some(reload) = reload {
+            reload(self, ctx);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// The path of the asset loaded by the name.
+    pub fn asset_path(&self, name: &str) -> Option<String> {
+        self.records.lock().unwrap().iter()
+            .find(|x| x.name == name)
+            .map(|x| x.path.clone())
+    }
+
+    fn set_status(&self, name: &str, path: &str, status: LoadStatus, reload: ReloadFn) {
+        let mut records = self.records.lock().unwrap();
+        if let Some(x) = records.iter_mut().find(|x| x.name == name) {
+            x.path = path.into();
+            x.status = status;
+            x.reload = reload;
+        } else {
+            records.push(LoadRecord {
+                name: name.into(),
+                path: path.into(),
+                status,
+                reload,
+            });
+        }
+    }
+
+    fn update_status(&self, name: &str, status: LoadStatus) {
+        if let Some(x) = self.records.lock().unwrap().iter_mut().find(|x| x.name == name) {
+            x.status = status;
+        }
     }
 
-    pub async fn load_texture_async(&self, device: &Device, queue: &Queue, key: String, path: &str) -> anyhow::Result<()> {
-        self.load_texture(device, queue, key, path)
+    fn new_handle<T>(&self) -> Handle<T> {
+        Handle::new(self.next_id.fetch_add(1, Ordering::Relaxed))
     }
 }
@@ -3,11 +3,15 @@ use std::ops::Deref;
 use egui::ColorImage;
 use wgpu_glyph::ab_glyph::FontArc;
 
+pub use asset::*;
+pub use budget::*;
 pub use manager::*;
 pub use progress::*;
 
 pub mod progress;
 pub mod manager;
+pub mod asset;
+pub mod budget;
 
 
 #[repr(transparent)]
@@ -1,14 +1,14 @@
 use std::sync::Arc;
 use std::sync::atomic::{AtomicU16, Ordering};
 
-#[derive(Default)]
+#[derive(Debug, Default)]
 struct CounterInner {
     loading: AtomicU16,
     finished: AtomicU16,
     errors: AtomicU16,
 }
 
-#[derive(Default, Clone)]
+#[derive(Debug, Default, Clone)]
 pub struct CounterProgress {
     inner: Arc<CounterInner>,
 }
@@ -0,0 +1,180 @@
+use std::any::{Any, TypeId};
+use std::collections::{HashMap, VecDeque};
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use specs::World;
+
+/// The event shared by the states, given to all states by [`crate::engine::StateEvent::Custom`]
+pub type AnyEvent = Arc<dyn Any + Send + Sync>;
+
+/// The events kept in a channel at most, the oldest are dropped for the readers not reading.
+const CHANNEL_CAPACITY: usize = 256;
+
+/// The reader of the events of the type published after subscribed.
+#[derive(Debug)]
+pub struct EventReader<T> {
+    id: u64,
+    _marker: PhantomData<fn() -> T>,
+}
+
+struct Channel<T> {
+    events: VecDeque<T>,
+    /// The index of the first event kept.
+    offset: u64,
+    /// The next index to read by the readers.
+    readers: HashMap<u64, u64>,
+}
+
+impl<T> Default for Channel<T> {
+    fn default() -> Self {
+        Self {
+            events: Default::default(),
+            offset: 0,
+            readers: Default::default(),
+        }
+    }
+}
+
+impl<T> Channel<T> {
+    /// Drop the events read by all readers.
+    fn trim(&mut self) {
+        let read = self.readers.values().copied().min().unwrap_or(self.offset + self.events.len() as u64);
+        while self.offset < read && self.events.pop_front().is_some() {
+            self.offset += 1;
+        }
+    }
+}
+
+/// The typed events published by the states and read by the subscribed states in their update,
+/// in the specs world of [`crate::engine::GlobalData`] shared by the windows.
+///
+/// The events published are also broadcast to all states in all windows after the windows updated.
+#[derive(Default)]
+pub struct EventBus {
+    channels: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+    next_reader: u64,
+    /// The events to broadcast in the next loop.
+    broadcast: Vec<AnyEvent>,
+}
+
+#[allow(unused)]
+impl EventBus {
+    fn channel<T: Send + Sync + 'static>(&mut self) -> &mut Channel<T> {
+        self.channels.entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(Channel::<T>::default()))
+            .downcast_mut()
+            .expect("The channel is of the type id")
+    }
+
+    pub fn publish<T: Clone + Send + Sync + 'static>(&mut self, event: T) {
+        self.broadcast.push(Arc::new(event.clone()));
+        let channel = self.channel::<T>();
+        // no one would read it
+        if !channel.readers.is_empty() {
+            channel.events.push_back(event);
+            if channel.events.len() > CHANNEL_CAPACITY {
+                channel.events.pop_front();
+                channel.offset += 1;
+            }
+        }
+    }
+
+    pub fn subscribe<T: Send + Sync + 'static>(&mut self) -> EventReader<T> {
+        let id = self.next_reader;
+        self.next_reader += 1;
+        let channel = self.channel::<T>();
+        let next = channel.offset + channel.events.len() as u64;
+        channel.readers.insert(id, next);
+        EventReader { id, _marker: PhantomData }
+    }
+
+    pub fn unsubscribe<T: Send + Sync + 'static>(&mut self, reader: EventReader<T>) {
+        let channel = self.channel::<T>();
+        channel.readers.remove(&reader.id);
+        channel.trim();
+    }
+
+    /// The events published since the last read by the reader, the oldest dropped if too many.
+    pub fn read<T: Clone + Send + Sync + 'static>(&mut self, reader: &EventReader<T>) -> Vec<T> {
+        let channel = self.channel::<T>();
+        let end = channel.offset + channel.events.len() as u64;
+        let start = if let Some(x) = channel.readers.get_mut(&reader.id) {
+            std::mem::replace(x, end)
+        } else {
+            return vec![];
+        };
+        let events = channel.events.iter()
+            .skip(start.saturating_sub(channel.offset) as usize)
+            .cloned()
+            .collect();
+        channel.trim();
+        events
+    }
+
+    pub fn has_broadcast(&self) -> bool {
+        !self.broadcast.is_empty()
+    }
+
+    /// Take the events to give to the states.
+    pub(crate) fn take_broadcast(&mut self) -> Vec<AnyEvent> {
+        std::mem::take(&mut self.broadcast)
+    }
+}
+
+/// Publish the event to the bus in the world if any.
+pub fn publish<T: Clone + Send + Sync + 'static>(world: &World, event: T) {
+    if let Some(mut bus) = world.try_fetch_mut::<EventBus>() {
+        bus.publish(event);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::engine::state::bus::{CHANNEL_CAPACITY, EventBus};
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Moved(u32);
+
+    #[test]
+    fn test_event_bus() {
+        let mut bus = EventBus::default();
+        bus.publish(Moved(0));
+        let a = bus.subscribe::<Moved>();
+        bus.publish(Moved(1));
+        let b = bus.subscribe::<Moved>();
+        bus.publish(Moved(2));
+        bus.publish(1.0f32);
+
+        assert_eq!(bus.read(&a), vec![Moved(1), Moved(2)]);
+        assert_eq!(bus.read(&a), vec![]);
+        assert_eq!(bus.read(&b), vec![Moved(2)]);
+        bus.publish(Moved(3));
+        bus.unsubscribe(b);
+        assert_eq!(bus.read(&a), vec![Moved(3)]);
+        assert!(bus.channel::<Moved>().events.is_empty());
+
+        let broadcast = bus.take_broadcast();
+        assert_eq!(broadcast.len(), 5);
+        assert_eq!(broadcast[3].downcast_ref::<f32>(), Some(&1.0));
+        assert!(bus.take_broadcast().is_empty());
+    }
+
+    #[test]
+    fn test_event_bus_capacity() {
+        let mut bus = EventBus::default();
+        let a = bus.subscribe::<Moved>();
+        // never read
+        let _b = bus.subscribe::<Moved>();
+        for i in 0..CHANNEL_CAPACITY as u32 + 10 {
+            bus.publish(Moved(i));
+        }
+        assert_eq!(bus.channel::<Moved>().events.len(), CHANNEL_CAPACITY);
+        let events = bus.read(&a);
+        assert_eq!(events.len(), CHANNEL_CAPACITY);
+        assert_eq!(events[0], Moved(10));
+        bus.publish(Moved(0));
+        assert_eq!(bus.read(&a), vec![Moved(0)]);
+        assert_eq!(bus.channel::<Moved>().events.len(), CHANNEL_CAPACITY);
+    }
+}
@@ -0,0 +1,94 @@
+use std::time::{Duration, Instant};
+
+use crate::engine::GameState;
+
+/// The fade to black over the screen before switching to the next state, then back after switched.
+///
+/// Half of the duration for each.
+pub struct Fade {
+    next: Option<Box<dyn GameState>>,
+    half: Duration,
+    start: Instant,
+    /// When the next state switched to.
+    switched: Option<Instant>,
+}
+
+#[allow(unused)]
+impl Fade {
+    pub fn new(next: Box<dyn GameState>, duration: Duration, now: Instant) -> Self {
+        Self {
+            next: Some(next),
+            half: duration / 2,
+            start: now,
+            switched: None,
+        }
+    }
+
+    /// Whether the fade out is not finished and the state is not switched yet.
+    pub fn is_switching(&self) -> bool {
+        self.next.is_some()
+    }
+
+    /// The next state if the screen is black now.
+    pub fn take_next(&mut self, now: Instant) -> Option<Box<dyn GameState>> {
+        if self.next.is_some() && self.alpha(now) >= 1.0 {
+            self.switched = Some(now);
+            self.next.take()
+        } else {
+            None
+        }
+    }
+
+    /// The opacity of the black over the screen.
+    pub fn alpha(&self, now: Instant) -> f32 {
+        let half = self.half.as_secs_f32();
+        if half <= 0.0 {
+            return if self.next.is_some() { 1.0 } else { 0.0 };
+        }
+        match self.switched {
+            None => (now.saturating_duration_since(self.start).as_secs_f32() / half).min(1.0),
+            Some(x) => (1.0 - now.saturating_duration_since(x).as_secs_f32() / half).max(0.0),
+        }
+    }
+
+    pub fn is_finished(&self, now: Instant) -> bool {
+        self.switched.is_some() && self.alpha(now) <= 0.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use crate::engine::state::fade::Fade;
+    use crate::engine::state::LoopState;
+    use crate::engine::{GameState, StateData, Trans};
+
+    struct Next;
+
+    impl GameState for Next {
+        fn update(&mut self, _: &mut StateData) -> (Trans, LoopState) { (Trans::None, LoopState::WAIT) }
+    }
+
+    #[test]
+    fn test_fade() {
+        let start = Instant::now();
+        let mut fade = Fade::new(Box::new(Next), Duration::from_secs(2), start);
+        assert_eq!(fade.alpha(start), 0.0);
+        assert!((fade.alpha(start + Duration::from_millis(500)) - 0.5).abs() < 1e-4);
+        assert!(fade.take_next(start + Duration::from_millis(500)).is_none());
+
+        // switched late and faded back from then
+        let switched = start + Duration::from_millis(1200);
+        assert!(fade.take_next(switched).is_some());
+        assert!(!fade.is_switching());
+        assert_eq!(fade.alpha(switched), 1.0);
+        assert!((fade.alpha(switched + Duration::from_millis(250)) - 0.75).abs() < 1e-4);
+        assert!(!fade.is_finished(switched + Duration::from_millis(900)));
+        assert!(fade.is_finished(switched + Duration::from_secs(1)));
+
+        let mut fade = Fade::new(Box::new(Next), Duration::ZERO, start);
+        assert!(fade.take_next(start).is_some());
+        assert!(fade.is_finished(start));
+    }
+}
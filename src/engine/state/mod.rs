@@ -1,5 +1,6 @@
+use std::any::Any;
 use std::cell::RefCell;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use egui::epaint::ahash::HashMap;
 use mlua::UserData;
@@ -8,12 +9,16 @@ use winit::event::WindowEvent;
 use winit::event_loop::ControlFlow;
 use winit::window::WindowId;
 
+pub use fade::*;
 pub use wait_future::*;
 
 use crate::engine::app::AppInstance;
+use crate::engine::ecs::SpawnCommands;
 use crate::engine::window::{EventLoopProxyType, EventLoopTargetType, WindowInstance};
 
 mod wait_future;
+mod fade;
+pub mod bus;
 
 #[allow(unused)]
 pub enum Trans {
@@ -21,6 +26,8 @@ pub enum Trans {
     Push(Box<dyn GameState>),
     Pop,
     Switch(Box<dyn GameState>),
+    /// Switch after the screen faded to black, then fade back, in the duration.
+    SwitchFade(Box<dyn GameState>, Duration),
     Exit,
     Vec(Vec<Trans>),
 }
@@ -29,7 +36,13 @@ pub enum Trans {
 pub enum StateEvent<'a> {
     ReloadGPU,
     PostUiRender,
+    /// The app went to the background, the window may be gone.
+    Suspended,
+    /// The app came back from the background.
+    Resumed,
     Window(&'a WindowEvent<'a>),
+    /// The event published to the [`bus::EventBus`] by any state, downcast to know what it is.
+    Custom(&'a (dyn Any + Send + Sync)),
 }
 
 impl Default for Trans {
@@ -54,6 +67,14 @@ pub struct StateData<'a, 'b, 'c> {
 }
 
 
+impl StateData<'_, '_, '_> {
+    /// Queue the entities to spawn and despawn, applied after the update.
+    pub fn commands(&mut self) -> &mut SpawnCommands {
+        &mut self.app.commands
+    }
+}
+
+
 pub trait GameState: 'static {
     fn start(&mut self, _: &mut StateData) {}
 
@@ -70,6 +91,12 @@ pub trait GameState: 'static {
 
     fn stop(&mut self, _: &mut StateData) {}
 
+    /// Callback when the other state pushed on me, I am not updated until resumed.
+    fn on_pause(&mut self, _: &mut StateData) {}
+
+    /// Callback when the state pushed on me popped, I am on the top again.
+    fn on_resume(&mut self, _: &mut StateData) {}
+
     fn on_event(&mut self, _: &mut StateData, _: StateEvent) {}
 }
 
@@ -122,6 +149,24 @@ impl LoopState {
             render,
         }
     }
+
+    /// Render at most the fps from the last render, waiting until the next frame instead of polling.
+    pub fn limit_fps(&mut self, last_render: Instant, now: Instant, fps: u32) {
+        if matches!(self.control_flow, ControlFlow::Exit | ControlFlow::ExitWithCode(_)) {
+            return;
+        }
+        let interval = Duration::from_secs(1) / fps.max(1);
+        let next = last_render + interval;
+        if now < next {
+            if self.render || self.control_flow == ControlFlow::Poll {
+                // render in the next frame
+                self.render = false;
+                self.control_flow = ControlFlow::WaitUntil(next);
+            }
+        } else if self.control_flow == ControlFlow::Poll {
+            self.control_flow = ControlFlow::WaitUntil(now + interval);
+        }
+    }
 }
 
 impl GameState for () {}
@@ -156,6 +201,10 @@ impl std::ops::BitOrAssign for LoopState {
 
 #[cfg(test)]
 mod test {
+    use std::time::{Duration, Instant};
+
+    use winit::event_loop::ControlFlow;
+
     use crate::engine::LoopState;
 
     #[test]
@@ -168,4 +217,28 @@ mod test {
         // we already render
         assert_eq!(s, LoopState::POLL);
     }
+
+    #[test]
+    fn test_limit_fps() {
+        let last = Instant::now();
+        let frame = Duration::from_millis(100);
+        let mut s = LoopState::POLL;
+        s.limit_fps(last, last + Duration::from_millis(10), 10);
+        assert!(!s.render);
+        assert_eq!(s.control_flow, ControlFlow::WaitUntil(last + frame));
+
+        let now = last + Duration::from_millis(120);
+        let mut s = LoopState::POLL;
+        s.limit_fps(last, now, 10);
+        assert!(s.render);
+        assert_eq!(s.control_flow, ControlFlow::WaitUntil(now + frame));
+
+        // nothing to do keeps waiting
+        let mut s = LoopState::WAIT_ALL;
+        s.limit_fps(last, last, 10);
+        assert_eq!(s, LoopState::WAIT_ALL);
+        let mut s = LoopState { control_flow: ControlFlow::Exit, render: true };
+        s.limit_fps(last, last, 10);
+        assert_eq!(s.control_flow, ControlFlow::Exit);
+    }
 }
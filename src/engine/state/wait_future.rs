@@ -17,6 +17,7 @@ pub enum WaitResult {
 
 
 /// The state will pop and execute the trans while the handle has result.
+#[allow(unused)]
 pub struct WaitFutureState {
     handle: Option<RemoteHandle<WaitResult>>,
     result: Option<WaitResult>,
@@ -24,6 +25,7 @@ pub struct WaitFutureState {
 }
 
 
+#[allow(unused)]
 impl WaitFutureState {
     pub fn from_wait_thing(value: RemoteHandle<WaitResult>) -> Box<Self> {
         Self {
@@ -0,0 +1,185 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use toml_edit::{Array, Document, Item, Table, value};
+
+/// The default profile file to persist the statistics.
+pub const PROFILE_PATH: &str = "profile.toml";
+
+/// The achievement defined by the game.
+pub trait Achievement: Send + Sync {
+    /// The unique id to persist.
+    fn id(&self) -> &str;
+
+    /// The name to show.
+    fn name(&self) -> &str;
+
+    fn description(&self) -> &str {
+        ""
+    }
+
+    /// Whether the achievement is reached by the stats now.
+    fn reached(&self, stats: &Statistics) -> bool;
+}
+
+/// The statistics of the player, stored in the specs world of [`crate::engine::GlobalData`].
+#[allow(unused)]
+#[derive(Default)]
+pub struct Statistics {
+    pub portals_traversed: u64,
+    pub distance_walked: f64,
+    pub levels_completed: u64,
+    pub max_recursion_depth: u64,
+    /// The stats defined by the game.
+    pub custom: BTreeMap<String, f64>,
+    /// The unlocked achievement ids.
+    unlocked: BTreeSet<String>,
+    /// The achievements unlocked but not taken by [`Statistics::take_recent_unlocked`]
+    recent_unlocked: Vec<String>,
+    achievements: Vec<Box<dyn Achievement>>,
+    profile: Document,
+    path: PathBuf,
+    dirty: bool,
+}
+
+#[allow(unused)]
+impl Statistics {
+    /// Load the statistics from the profile file, empty if the file not exists.
+    pub fn load(path: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let path = path.into();
+        let data = match std::fs::read_to_string(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let profile: Document = data.parse()?;
+
+        let stats = profile.get("stats");
+        let get_int = |key| stats.and_then(|x| x.get(key))
+            .and_then(|x| x.as_integer())
+            .unwrap_or(0)
+            .max(0) as u64;
+        let get_float = |x: &Item| x.as_float().or_else(|| x.as_integer().map(|x| x as f64));
+
+        let custom = stats.and_then(|x| x.get("custom"))
+            .and_then(|x| x.as_table_like())
+            .map(|t| t.iter()
+                .filter_map(|(k, v)| get_float(v).map(|v| (k.to_string(), v)))
+                .collect())
+            .unwrap_or_default();
+        let unlocked = profile.get("achievements")
+            .and_then(|x| x.get("unlocked"))
+            .and_then(|x| x.as_array())
+            .map(|x| x.iter().filter_map(|x| x.as_str()).map(ToString::to_string).collect())
+            .unwrap_or_default();
+
+        Ok(Self {
+            portals_traversed: get_int("portals_traversed"),
+            distance_walked: stats.and_then(|x| x.get("distance_walked")).and_then(get_float).unwrap_or(0.0),
+            levels_completed: get_int("levels_completed"),
+            max_recursion_depth: get_int("max_recursion_depth"),
+            custom,
+            unlocked,
+            profile,
+            path,
+            ..Default::default()
+        })
+    }
+
+    pub fn save(&mut self) -> anyhow::Result<()> {
+        let stats = &mut self.profile["stats"];
+        stats["portals_traversed"] = value(self.portals_traversed as i64);
+        stats["distance_walked"] = value(self.distance_walked);
+        stats["levels_completed"] = value(self.levels_completed as i64);
+        stats["max_recursion_depth"] = value(self.max_recursion_depth as i64);
+        let mut custom = Table::new();
+        for (k, v) in &self.custom {
+            custom[k.as_str()] = value(*v);
+        }
+        stats["custom"] = Item::Table(custom);
+        self.profile["achievements"]["unlocked"] = value(self.unlocked.iter().map(String::as_str).collect::<Array>());
+
+        std::fs::write(&self.path, self.profile.to_string())?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    pub fn save_if_dirty(&mut self) {
+        if self.dirty {
+            if let Err(e) = self.save() {
+                log::warn!(target: "stats", "Save statistics to {:?} failed for {:?}", self.path, e);
+            }
+        }
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    pub fn add_portal_traversed(&mut self) {
+        self.portals_traversed += 1;
+        self.changed();
+    }
+
+    pub fn add_distance_walked(&mut self, distance: f64) {
+        if distance > 0.0 {
+            self.distance_walked += distance;
+            self.changed();
+        }
+    }
+
+    pub fn add_level_completed(&mut self) {
+        self.levels_completed += 1;
+        self.changed();
+    }
+
+    /// Record the recursion depth of portals rendered.
+    pub fn witness_recursion_depth(&mut self, depth: u64) {
+        if depth > self.max_recursion_depth {
+            self.max_recursion_depth = depth;
+            self.changed();
+        }
+    }
+
+    pub fn add_custom(&mut self, key: &str, delta: f64) {
+        *self.custom.entry(key.into()).or_default() += delta;
+        self.changed();
+    }
+
+    pub fn register_achievement(&mut self, achievement: impl Achievement + 'static) {
+        self.achievements.push(Box::new(achievement));
+        self.check_achievements();
+    }
+
+    pub fn achievements(&self) -> impl Iterator<Item=&dyn Achievement> {
+        self.achievements.iter().map(|x| x.as_ref())
+    }
+
+    pub fn is_unlocked(&self, id: &str) -> bool {
+        self.unlocked.contains(id)
+    }
+
+    /// Take the achievement ids unlocked since last call.
+    pub fn take_recent_unlocked(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.recent_unlocked)
+    }
+
+    fn changed(&mut self) {
+        self.dirty = true;
+        self.check_achievements();
+    }
+
+    fn check_achievements(&mut self) {
+        let reached = self.achievements.iter()
+            .filter(|x| !self.unlocked.contains(x.id()) && x.reached(self))
+            .map(|x| x.id().to_string())
+            .collect::<Vec<_>>();
+        for id in reached {
+            log::info!(target: "stats", "Unlocked achievement {}", id);
+            self.unlocked.insert(id.clone());
+            self.recent_unlocked.push(id);
+            self.dirty = true;
+        }
+    }
+}
@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+
+use egui::{Color32, Context, LayerId, Order, Pos2, Stroke};
+use nalgebra::{vector, Vector2};
+use winit::dpi::{PhysicalPosition, PhysicalSize};
+
+use crate::engine::input::Pointer;
+use crate::engine::render::camera::{Camera, CameraController};
+
+/// The touches started in the left part of the screen width move me.
+const JOYSTICK_WIDTH: f32 = 0.4;
+/// The radius of the joystick by the shorter side of the screen.
+const JOYSTICK_RADIUS: f32 = 0.12;
+/// The zoom of the pinch within.
+const ZOOM_RANGE: (f32, f32) = (0.5, 3.0);
+
+/// The virtual joystick, drag to look and pinch to zoom by the touches on the screen.
+#[derive(Debug, Clone)]
+pub struct TouchControls {
+    /// The touch moving me and where it started.
+    joystick: Option<(u64, PhysicalPosition<f64>)>,
+    /// The touches looking and where they were in the last update.
+    looking: HashMap<u64, PhysicalPosition<f64>>,
+    /// The distance between the two touches pinching in the last update.
+    pinch: Option<f64>,
+    /// The field of view divided by.
+    pub zoom: f32,
+}
+
+impl Default for TouchControls {
+    fn default() -> Self {
+        Self {
+            joystick: None,
+            looking: Default::default(),
+            pinch: None,
+            zoom: 1.0,
+        }
+    }
+}
+
+fn distance(a: &PhysicalPosition<f64>, b: &PhysicalPosition<f64>) -> f64 {
+    ((a.x - b.x).powi(2) + (a.y - b.y).powi(2)).sqrt()
+}
+
+#[allow(unused)]
+impl TouchControls {
+    fn radius(size: PhysicalSize<u32>) -> f64 {
+        size.width.min(size.height) as f64 * JOYSTICK_RADIUS as f64
+    }
+
+    /// Whether any touch is controlling.
+    pub fn is_active(&self) -> bool {
+        self.joystick.is_some() || !self.looking.is_empty()
+    }
+
+    /// Move and look by the touches on the screen of the size.
+    pub fn update(&mut self, points: &HashMap<u64, Pointer>, size: PhysicalSize<u32>, controller: &mut CameraController) {
+        if size.width == 0 || size.height == 0 {
+            return;
+        }
+        if self.joystick.is_some_and(|(id, _)| !points.contains_key(&id)) {
+            self.joystick = None;
+        }
+        self.looking.retain(|id, _| points.contains_key(id));
+        for (id, x) in points {
+            if self.joystick.is_some_and(|j| j.0 == *id) || self.looking.contains_key(id) {
+                continue;
+            }
+            if self.joystick.is_none() && x.start.x < size.width as f64 * JOYSTICK_WIDTH as f64 {
+                self.joystick = Some((*id, x.start));
+            } else {
+                self.looking.insert(*id, x.loc);
+            }
+        }
+
+        let axis = self.joystick.and_then(|(id, start)| points.get(&id).map(|x| {
+            let r = Self::radius(size);
+            vector![(x.loc.x - start.x) / r, (start.y - x.loc.y) / r].cast::<f32>()
+        })).unwrap_or_else(Vector2::zeros);
+        controller.set_move_axis(axis);
+
+        match self.looking.len() {
+            1 => {
+                self.pinch = None;
+                for (id, last) in &mut self.looking {
+                    let now = points[id].loc;
+                    controller.look_by(((now.x - last.x) / size.width as f64) as f32, ((now.y - last.y) / size.height as f64) as f32);
+                    *last = now;
+                }
+            }
+            2 => {
+                let mut now = self.looking.keys().map(|x| points[x].loc);
+                let d = distance(&now.next().unwrap(), &now.next().unwrap());
+                if let Some(last) = self.pinch.filter(|x| *x > f64::EPSILON) {
+                    self.zoom = (self.zoom * (d / last) as f32).clamp(ZOOM_RANGE.0, ZOOM_RANGE.1);
+                }
+                self.pinch = Some(d);
+                for (id, last) in &mut self.looking {
+                    *last = points[id].loc;
+                }
+            }
+            _ => self.pinch = None,
+        }
+    }
+
+    /// Narrow the field of view by the zoom, after the field of view set for the frame.
+    pub fn apply_zoom(&self, camera: &mut Camera) {
+        camera.fovy = (((camera.fovy * 0.5).tan() / self.zoom).atan() * 2.0).max(f32::EPSILON);
+    }
+
+    /// Draw the joystick touched.
+    pub fn show(&self, ctx: &Context, points: &HashMap<u64, Pointer>, size: PhysicalSize<u32>) {
+        let (id, start) = if let Some(x) = self.joystick { x } else {
+            return;
+        };
+        let scale = ctx.pixels_per_point();
+        let pos = |x: PhysicalPosition<f64>| Pos2::new(x.x as f32 / scale, x.y as f32 / scale);
+        let r = Self::radius(size) as f32 / scale;
+        let painter = ctx.layer_painter(LayerId::new(Order::Background, "touch joystick".into()));
+        painter.circle_stroke(pos(start), r, Stroke::new(2.0, Color32::from_white_alpha(96)));
+        if let Some(x) = points.get(&id) {
+            let knob = pos(x.loc) - pos(start);
+            let knob = if knob.length() > r { knob.normalized() * r } else { knob };
+            painter.circle_filled(pos(start) + knob, r * 0.4, Color32::from_white_alpha(64));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use nalgebra::point;
+    use winit::dpi::{PhysicalPosition, PhysicalSize};
+    use winit::event::TouchPhase;
+
+    use crate::engine::input::Pointer;
+    use crate::engine::render::camera::{Camera, CameraController};
+    use crate::engine::touch::TouchControls;
+
+    fn touch(id: u64, start: (f64, f64), loc: (f64, f64)) -> (u64, Pointer) {
+        (id, Pointer {
+            id,
+            start: PhysicalPosition::new(start.0, start.1),
+            loc: PhysicalPosition::new(loc.0, loc.1),
+            phase: TouchPhase::Moved,
+        })
+    }
+
+    #[test]
+    fn test_touch_controls() {
+        let size = PhysicalSize::new(1000, 500);
+        let mut controls = TouchControls::default();
+        let mut controller = CameraController::new();
+        let mut camera = Camera::new(point![0.0, 0.0, 0.0]);
+
+        // push the joystick forward fully
+        let mut points = HashMap::from([touch(1, (100.0, 400.0), (100.0, 300.0))]);
+        controls.update(&points, size, &mut controller);
+        let ddr = controller.update_direction(&mut camera, 0.016);
+        assert!(ddr.x > 0.99 && ddr.y.abs() < 1e-4);
+
+        // drag to look right on the right side
+        points.insert(1, touch(1, (100.0, 400.0), (100.0, 400.0)).1);
+        points.extend([touch(2, (800.0, 200.0), (800.0, 200.0))]);
+        controls.update(&points, size, &mut controller);
+        points.insert(2, touch(2, (800.0, 200.0), (850.0, 200.0)).1);
+        controls.update(&points, size, &mut controller);
+        let ddr = controller.update_direction(&mut camera, 0.016);
+        assert_eq!(ddr, nalgebra::Vector3::zeros());
+        assert!(camera.target.y < 0.0);
+
+        // pinch out to zoom in
+        points.remove(&1);
+        points.extend([touch(3, (600.0, 200.0), (600.0, 200.0))]);
+        controls.update(&points, size, &mut controller);
+        points.insert(3, touch(3, (600.0, 200.0), (550.0, 200.0)).1);
+        controls.update(&points, size, &mut controller);
+        assert!((controls.zoom - 1.2).abs() < 1e-5);
+        let fovy = camera.fovy;
+        controls.apply_zoom(&mut camera);
+        assert!(camera.fovy < fovy);
+
+        points.clear();
+        controls.update(&points, size, &mut controller);
+        assert!(!controls.is_active());
+    }
+}
@@ -0,0 +1,347 @@
+use std::sync::RwLock;
+
+use egui::{Context, FontData, FontDefinitions, FontFamily, FullOutput, PlatformOutput, RawInput, Rect, Style, vec2};
+use egui_wgpu::renderer::ScreenDescriptor;
+use egui_winit::{EventResponse, State};
+use log::info;
+use once_cell::sync::Lazy;
+use wgpu::{CommandEncoderDescriptor, LoadOp, Operations, RenderPassColorAttachment, RenderPassDescriptor, TextureView};
+use winit::event::WindowEvent;
+use winit::window::Window;
+
+use crate::engine::{ResourceManager, WgpuData};
+use crate::engine::global::GLOBAL_DATA;
+use crate::engine::window::EventLoopTargetType;
+
+/// The egui of the window, each window has its own context, input state, renderer and style.
+pub struct WindowUi {
+    pub ctx: Context,
+    pub state: State,
+    /// None before the gpu got.
+    pub renderer: Option<egui_wgpu::Renderer>,
+    /// The style of the window, kept when the context recreated.
+    style: Style,
+    /// The points scaled by it after the scale factor of the window.
+    ui_scale: f32,
+    safe_area: SafeArea,
+    /// The fonts of the window, set again when the context recreated.
+    fonts: FontDefinitions,
+    /// The version of the fonts shared followed.
+    fonts_version: u64,
+}
+
+/// The name of the font asset configured by [`crate::engine::config::FontSettings`]
+pub const UI_FONT: &str = "ui font";
+
+/// The fonts of all windows and their version, the windows follow them when the version changed.
+static SHARED_FONTS: Lazy<RwLock<(u64, FontDefinitions)>> = Lazy::new(|| RwLock::new((0, GLOBAL_DATA.font.clone())));
+
+/// Set the fonts of all windows, each window sets them before its next frame.
+pub fn set_shared_fonts(fonts: FontDefinitions) {
+    let mut shared = SHARED_FONTS.write().unwrap();
+    shared.0 += 1;
+    shared.1 = fonts;
+}
+
+/// The fonts shared and their version if not the `version`.
+fn shared_fonts_since(version: u64) -> Option<(u64, FontDefinitions)> {
+    let shared = SHARED_FONTS.read().unwrap();
+    (shared.0 != version).then(|| shared.clone())
+}
+
+/// Build the egui fonts from the built-in fonts, the fonts added later are used first.
+#[derive(Default)]
+pub struct FontBuilder {
+    fonts: Vec<(String, FontData)>,
+}
+
+#[allow(unused)]
+impl FontBuilder {
+    pub fn font(mut self, name: impl Into<String>, data: FontData) -> Self {
+        self.fonts.push((name.into(), data));
+        self
+    }
+
+    pub fn build(self) -> FontDefinitions {
+        let mut fonts = GLOBAL_DATA.font.clone();
+        for (name, data) in self.fonts {
+            fonts.font_data.insert(name.clone(), data);
+            for family in [FontFamily::Proportional, FontFamily::Monospace] {
+                let names = fonts.families.entry(family).or_default();
+                names.retain(|x| x != &name);
+                names.insert(0, name.clone());
+            }
+        }
+        fonts
+    }
+}
+
+/// The insets of the window covered by the notch and the system bars, in the physical pixels.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct SafeArea {
+    pub left: f32,
+    pub top: f32,
+    pub right: f32,
+    pub bottom: f32,
+}
+
+#[cfg(target_os = "android")]
+static ANDROID_APP: once_cell::sync::OnceCell<android_activity::AndroidApp> = once_cell::sync::OnceCell::new();
+
+/// Keep the app to query the content rect for the safe area.
+#[cfg(target_os = "android")]
+pub fn set_android_app(app: android_activity::AndroidApp) {
+    let _ = ANDROID_APP.set(app);
+}
+
+#[allow(unused)]
+impl SafeArea {
+    /// The insets of the window by the platform, zero if not known.
+    pub fn of_window(window: &Window) -> Self {
+        #[cfg(target_os = "android")]
+        if let Some(app) = ANDROID_APP.get() {
+            let size = window.inner_size();
+            let rect = app.content_rect();
+            return Self {
+                left: rect.left.max(0) as f32,
+                top: rect.top.max(0) as f32,
+                right: (size.width as i32 - rect.right).max(0) as f32,
+                bottom: (size.height as i32 - rect.bottom).max(0) as f32,
+            };
+        }
+        Self::default()
+    }
+
+    /// The screen in the points inside the insets, the whole screen if no room left.
+    pub fn apply(&self, screen: Rect, pixels_per_point: f32) -> Rect {
+        let ppp = pixels_per_point.max(f32::EPSILON);
+        let rect = Rect::from_min_max(screen.min + vec2(self.left, self.top) / ppp,
+                                      screen.max - vec2(self.right, self.bottom) / ppp);
+        if rect.is_positive() { rect } else { screen }
+    }
+}
+
+/// The default style with the text sizes scaled.
+fn default_style(text_scale: f32) -> Style {
+    let mut style = Style::default();
+    for (_, s) in &mut style.text_styles {
+        s.size *= text_scale;
+    }
+    style
+}
+
+#[allow(unused)]
+impl WindowUi {
+    pub fn new(window: &Window, event_loop: &EventLoopTargetType, gpu: Option<&WgpuData>) -> Self {
+        let (fonts_version, fonts) = SHARED_FONTS.read().unwrap().clone();
+        let mut this = Self {
+            ctx: Context::default(),
+            state: State::new(event_loop),
+            renderer: None,
+            style: default_style(GLOBAL_DATA.cfg_data.read().unwrap().settings().font.text_scale),
+            ui_scale: 1.0,
+            safe_area: SafeArea::default(),
+            fonts,
+            fonts_version,
+        };
+        info!("Got the egui context");
+        match gpu {
+            Some(gpu) => this.init(window, gpu),
+            None => {
+                this.ctx.set_style(this.style.clone());
+                this.ctx.set_fonts(this.fonts.clone());
+            }
+        }
+        this
+    }
+
+    /// Create the renderer for the gpu got, the context is recreated to upload the fonts again.
+    ///
+    /// The textures registered to the old renderer are lost.
+    pub fn init(&mut self, window: &Window, gpu: &WgpuData) {
+        self.ctx = Context::default();
+        self.ctx.set_style(self.style.clone());
+        self.ctx.set_fonts(self.fonts.clone());
+        self.renderer = Some(egui_wgpu::Renderer::new(&gpu.device, gpu.formats.color, None, 1));
+        self.resize(window);
+        info!("Set the egui renderer with the scale factor {}", self.pixels_per_point());
+    }
+
+    /// Follow the size and the scale factor of the window.
+    pub fn resize(&mut self, window: &Window) {
+        self.set_scale_factor(window.scale_factor() as f32);
+        let _ = self.state.on_event(&self.ctx, &WindowEvent::Resized(window.inner_size()));
+    }
+
+    fn set_scale_factor(&mut self, scale_factor: f32) {
+        let scale = scale_factor * self.ui_scale;
+        self.state.set_pixels_per_point(scale);
+        self.ctx.set_pixels_per_point(scale);
+    }
+
+    pub fn ui_scale(&self) -> f32 {
+        self.ui_scale
+    }
+
+    /// Scale the ui more than the scale factor of the window, for the phones and the tvs.
+    pub fn set_ui_scale(&mut self, window: &Window, scale: f32) {
+        self.ui_scale = scale.clamp(0.25, 4.0);
+        self.set_scale_factor(window.scale_factor() as f32);
+    }
+
+    /// The insets of the window in the last frame.
+    pub fn safe_area(&self) -> SafeArea {
+        self.safe_area
+    }
+
+    pub fn on_event(&mut self, e: &WindowEvent) -> EventResponse {
+        let response = self.state.on_event(&self.ctx, e);
+        if let WindowEvent::ScaleFactorChanged { scale_factor, .. } = e {
+            // the state follows the scale factor only
+            self.set_scale_factor(*scale_factor as f32);
+        }
+        response
+    }
+
+    pub fn pixels_per_point(&self) -> f32 {
+        self.state.pixels_per_point()
+    }
+
+    pub fn style(&self) -> &Style {
+        &self.style
+    }
+
+    /// Set the fonts of the window only, kept when the gpu created again.
+    pub fn set_fonts(&mut self, fonts: FontDefinitions) {
+        self.ctx.set_fonts(fonts.clone());
+        self.fonts = fonts;
+    }
+
+    /// Use the font configured before the built-in in all windows if loaded by the resource manager.
+    ///
+    /// Return false if not loaded.
+    pub fn apply_loaded_fonts(&mut self, res: &ResourceManager) -> bool {
+        let font = if let Some(x) = res.ui_fonts.handle(UI_FONT).and_then(|x| res.ui_fonts.get(x)) { x.clone() } else {
+            return false;
+        };
+        set_shared_fonts(FontBuilder::default().font(UI_FONT, font).build());
+        self.follow_shared_fonts();
+        true
+    }
+
+    /// Set the fonts shared if changed since followed last.
+    fn follow_shared_fonts(&mut self) {
+        if let Some((version, fonts)) = shared_fonts_since(self.fonts_version) {
+            self.fonts_version = version;
+            self.set_fonts(fonts);
+        }
+    }
+
+    /// Set the style of the window only.
+    pub fn set_style(&mut self, style: Style) {
+        self.ctx.set_style(style.clone());
+        self.style = style;
+    }
+
+    /// The context and the input of the frame inside the safe area, run the context with the states.
+    pub fn begin(&mut self, window: &Window) -> (Context, RawInput) {
+        self.follow_shared_fonts();
+        self.safe_area = SafeArea::of_window(window);
+        let mut input = self.state.take_egui_input(window);
+        input.screen_rect = input.screen_rect.map(|x| self.safe_area.apply(x, self.state.pixels_per_point()));
+        (self.ctx.clone(), input)
+    }
+
+    /// Draw the output to the view, return the output for the window.
+    pub fn paint(&mut self, gpu: &WgpuData, view: &TextureView, output: FullOutput) -> PlatformOutput {
+        let renderer = match self.renderer.as_mut() {
+            Some(x) => x,
+            None => return output.platform_output,
+        };
+        let device = gpu.device.as_ref();
+        let queue = gpu.queue.as_ref();
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("encoder for egui"),
+        });
+
+        let screen_descriptor = ScreenDescriptor {
+            size_in_pixels: [gpu.surface_cfg.width, gpu.surface_cfg.height],
+            pixels_per_point: self.state.pixels_per_point(),
+        };
+        // Upload all resources for the GPU.
+        let paint_jobs = self.ctx.tessellate(output.shapes);
+        for (id, delta) in &output.textures_delta.set {
+            renderer.update_texture(device, queue, *id, delta);
+        }
+        renderer.update_buffers(device, queue, &mut encoder, &paint_jobs, &screen_descriptor);
+        let scope = gpu.begin_timing(&mut encoder, "egui");
+        {
+            let mut rp = encoder.begin_render_pass(&RenderPassDescriptor {
+                label: None,
+                color_attachments: &[Some(RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: Operations {
+                        load: LoadOp::Load,
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            renderer.render(&mut rp, &paint_jobs, &screen_descriptor);
+        }
+        gpu.end_timing(&mut encoder, scope);
+
+        // Submit the commands.
+        queue.submit(std::iter::once(encoder.finish()));
+        output.textures_delta.free.iter().for_each(|id| renderer.free_texture(id));
+        output.platform_output
+    }
+
+    /// Apply the cursor, the clipboard and the ime output to the window.
+    pub fn handle_output(&mut self, window: &Window, output: PlatformOutput) {
+        self.state.handle_platform_output(window, &self.ctx, output);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use egui::{FontData, FontFamily, pos2, Rect};
+
+    use crate::engine::ui::{FontBuilder, SafeArea, set_shared_fonts, shared_fonts_since};
+
+    #[test]
+    fn test_safe_area() {
+        let screen = Rect::from_min_max(pos2(0.0, 0.0), pos2(400.0, 800.0));
+        assert_eq!(SafeArea::default().apply(screen, 2.0), screen);
+        let notch = SafeArea { left: 0.0, top: 80.0, right: 0.0, bottom: 40.0 };
+        assert_eq!(notch.apply(screen, 2.0), Rect::from_min_max(pos2(0.0, 40.0), pos2(400.0, 780.0)));
+        // no room left
+        let covered = SafeArea { left: 500.0, top: 0.0, right: 500.0, bottom: 0.0 };
+        assert_eq!(covered.apply(screen, 1.0), screen);
+    }
+
+    #[test]
+    fn test_font_builder() {
+        let fonts = FontBuilder::default()
+            .font("a", FontData::from_static(&[]))
+            .font("b", FontData::from_static(&[]))
+            .build();
+        for family in [FontFamily::Proportional, FontFamily::Monospace] {
+            let names = &fonts.families[&family];
+            // the later first, then the built-in cjk
+            assert_eq!(&names[..3], ["b", "a", "cjk"]);
+        }
+    }
+
+    #[test]
+    fn test_shared_fonts() {
+        let (version, _) = shared_fonts_since(u64::MAX).unwrap();
+        set_shared_fonts(FontBuilder::default().font("a", FontData::from_static(&[])).build());
+        let (newer, fonts) = shared_fonts_since(version).unwrap();
+        assert_eq!(newer, version + 1);
+        assert_eq!(fonts.families[&FontFamily::Proportional][0], "a");
+        // followed already
+        assert!(shared_fonts_since(newer).is_none());
+    }
+}
@@ -3,19 +3,24 @@ use std::collections::HashSet;
 use std::default::Default;
 use std::ops::DerefMut;
 
-use egui::Context;
 use egui::epaint::ahash::{HashMap, HashMapExt};
-use egui_wgpu::renderer::ScreenDescriptor;
-use log::info;
-use specs::World;
+use log::{info, warn};
+use specs::{World, WorldExt};
 use wgpu::{Color, CommandEncoderDescriptor, Extent3d, ImageCopyTexture, LoadOp,
-           Operations, Origin3d, RenderPassColorAttachment, RenderPassDescriptor, TextureAspect};
+           Operations, Origin3d, RenderPassColorAttachment, RenderPassDescriptor, SurfaceError, TextureAspect,
+           TextureViewDescriptor};
 use winit::event::{ElementState, Event, VirtualKeyCode, WindowEvent};
 use winit::event_loop::{ControlFlow, DeviceEventFilter, EventLoop, EventLoopProxy, EventLoopWindowTarget};
 use winit::window::{Window, WindowBuilder, WindowId};
 
-use crate::engine::{GameState, GlobalData, LoopState, MainRendererData, Pointer, StateEvent, Trans, WgpuData};
+use crate::engine::{Fade, GameState, GlobalData, LoopState, MainRendererData, StateEvent, Trans, WgpuData};
 use crate::engine::app::AppInstance;
+use crate::engine::config::{WindowMode, WindowSettings};
+use crate::engine::global::{features, GLOBAL_DATA};
+use crate::engine::lifecycle::Lifecycle;
+use crate::engine::metrics::Phase;
+use crate::engine::state::bus::{AnyEvent, EventBus};
+use crate::engine::stats::{PROFILE_PATH, Statistics};
 
 #[derive(Default)]
 struct LoopInfo {
@@ -37,6 +42,8 @@ pub struct WindowInstance {
     pub states: Vec<Box<dyn GameState>>,
     running: bool,
     loop_info: LoopInfo,
+    /// The fade switching the top state.
+    fade: Option<Fade>,
 }
 
 #[non_exhaustive]
@@ -75,6 +82,7 @@ impl WindowInstance {
             states: vec![],
             running: true,
             loop_info: Default::default(),
+            fade: None,
         })
     }
 
@@ -91,6 +99,7 @@ impl WindowInstance {
             states: vec![],
             running: true,
             loop_info: Default::default(),
+            fade: None,
         })
     }
 
@@ -101,6 +110,7 @@ impl WindowInstance {
             states: vec![],
             running: true,
             loop_info: Default::default(),
+            fade: None,
         })
     }
 }
@@ -123,38 +133,98 @@ impl WindowInstance {
 
         self.app.inputs.swap_frame();
         {
+            let now = std::time::Instant::now();
+            if let Some(next) = self.fade.as_mut().and_then(|x| x.take_next(now)) {
+                self.process_tran(Trans::Switch(next), wd);
+            }
+            if self.fade.as_ref().is_some_and(|x| x.is_finished(now)) {
+                self.fade = None;
+            }
             for x in &mut self.states {
                 self.loop_info.loop_state |= x.shadow_update();
             }
-            if let Some(last) = self.states.last_mut() {
+            if self.fade.is_some() {
+                self.loop_info.loop_state |= LoopState::POLL;
+            }
+            // the state fading out is not updated to not switch again
+            let fading_out = self.fade.as_ref().is_some_and(Fade::is_switching);
+            if let Some(last) = self.states.last_mut().filter(|_| !fading_out) {
+                let start = std::time::Instant::now();
                 let ((tran, l), wd) = {
                     let mut state_data = get_state!(self.app, wd);
                     (last.update(&mut state_data), state_data.wd)
                 };
+                self.app.metrics.add(Phase::Update, start.elapsed());
                 self.process_tran(tran, wd);
                 self.loop_info.loop_state |= l;
             }
         }
+        {
+            profiling::scope!("Run systems");
+            let start = std::time::Instant::now();
+            self.app.commands.apply(&mut self.app.world);
+            self.app.dispatcher.dispatch(&self.app.world);
+            self.app.world.maintain();
+            self.app.metrics.add(Phase::Systems, start.elapsed());
+        }
+    }
+
+
+    /// Tell the services and the states the app suspended or resumed.
+    fn lifecycle(&mut self, el: &mut GlobalData, suspended: bool) {
+        if let Some(audio) = self.app.audio.as_mut() {
+            if suspended {
+                audio.suspend();
+            } else {
+                audio.resume();
+            }
+        }
+        let e = if suspended { StateEvent::Suspended } else { StateEvent::Resumed };
+        let mut state_data = get_state!(self.app, el);
+        for x in &mut self.states {
+            x.on_event(&mut state_data, e);
+        }
     }
 
+    /// Give the events published to all states.
+    fn broadcast(&mut self, el: &mut GlobalData, events: &[AnyEvent]) {
+        let mut state_data = get_state!(self.app, el);
+        for e in events {
+            for x in &mut self.states {
+                x.on_event(&mut state_data, StateEvent::Custom(e.as_ref()));
+            }
+        }
+        self.loop_info.got_event = true;
+    }
 
     fn process_tran(&mut self, tran: Trans, el: &mut GlobalData) {
-        let last = self.states.last_mut().unwrap();
+        // the states popped to empty, the window is not running after the loop
+        let last = if let Some(x) = self.states.last_mut() { x } else {
+            return;
+        };
         let mut state_data = get_state!(self.app, el);
         match tran {
             Trans::Push(mut x) => {
+                last.on_pause(&mut state_data);
                 x.start(&mut state_data);
                 self.states.push(x);
             }
             Trans::Pop => {
                 last.stop(&mut state_data);
                 self.states.pop().unwrap();
+                if let Some(x) = self.states.last_mut() {
+                    x.on_resume(&mut state_data);
+                }
             }
             Trans::Switch(x) => {
                 last.stop(&mut state_data);
                 *last = x;
                 last.start(&mut state_data);
             }
+            Trans::SwitchFade(x, duration) => {
+                // the state waiting in the last fade is dropped
+                self.fade = Some(Fade::new(x, duration, std::time::Instant::now()));
+            }
             Trans::Exit => {
                 while let Some(mut last) = self.states.pop() {
                     last.stop(&mut state_data);
@@ -171,15 +241,33 @@ impl WindowInstance {
     }
 
 
+    /// Create the gpu data again and tell the states to reload their gpu resources.
+    fn reload_gpu(&mut self, el: &mut GlobalData) {
+        if self.app.recreate_gpu() {
+            let mut sd = get_state!(self.app, el);
+            self.states.iter_mut().for_each(|x| x.on_event(&mut sd, StateEvent::ReloadGPU));
+        }
+    }
+
     fn render_once(&mut self, el: &mut GlobalData) {
+        if self.app.gpu.as_ref().is_some_and(|x| x.is_lost()) {
+            warn!("The gpu device is lost, creating again");
+            self.reload_gpu(el);
+        }
         if let (Some(gpu), ) = (&self.app.gpu, ) {
             profiling::scope!("Render pth once");
             let render_now = std::time::Instant::now();
             let render_dur = render_now.duration_since(self.app.last_render_time);
             let dt = render_dur.as_secs_f32();
-            let swap_chain_frame = if let Ok(s) = gpu.surface.get_current_texture() { s } else {
+            let swap_chain_frame = match gpu.surface.as_ref().map(|x| x.get_current_texture()) {
+                Some(Ok(s)) => s,
+                Some(Err(SurfaceError::OutOfMemory)) => {
+                    // created again in the next frame
+                    gpu.mark_lost();
+                    return;
+                }
                 // it is normal.
-                return;
+                _ => return,
             };
             let surface_output = &swap_chain_frame;
             {
@@ -204,8 +292,11 @@ impl WindowInstance {
                 gpu.queue.submit(Some(encoder.finish()));
             }
 
-            let egui_ctx = &self.app.egui_ctx.clone();
-            let full_output = egui_ctx.run(self.app.egui_state.take_egui_input(&self.app.window), |egui_ctx| {
+            let video = GLOBAL_DATA.cfg_data.read().unwrap().settings().video.clone();
+            let perf_hud = video.perf_hud;
+            let (egui_ctx, input) = self.app.ui.begin(&self.app.window);
+            let start = std::time::Instant::now();
+            let full_output = egui_ctx.run(input, |egui_ctx| {
                 let mut state_data = get_state!(self.app, el);
                 state_data.dt = dt;
 
@@ -217,58 +308,40 @@ impl WindowInstance {
                     let tran = g.render(&mut state_data, egui_ctx);
                     self.process_tran(tran, el);
                 }
+                if perf_hud {
+                    self.app.metrics.show_overlay(egui_ctx, &self.app.pacing);
+                }
             });
-            let gpu = self.app.gpu.as_ref().unwrap();
-            let render = self.app.render.as_mut().unwrap();
-            // render ui output to main screen
-            {
-                let device = gpu.device.as_ref();
-                let queue = gpu.queue.as_ref();
-                let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
-                    label: Some("encoder for egui"),
+            self.app.metrics.add(Phase::Render, start.elapsed());
+            // the scene is post processed before the ui, not blooming the ui
+            if let (Some(gpu), Some(render)) = (self.app.gpu.as_mut(), self.app.render.as_mut()) {
+                let mut encoder = gpu.device.create_command_encoder(&CommandEncoderDescriptor {
+                    label: Some("Post process commands")
                 });
-
-                let screen_descriptor = ScreenDescriptor {
-                    size_in_pixels: [gpu.surface_cfg.width, gpu.surface_cfg.height],
-                    pixels_per_point: self.app.window.scale_factor() as f32,
-                };
-                // Upload all resources for the GPU.
-
-                let egui_renderer = &mut render.egui_rpass;
-                let paint_jobs = self.app.egui_ctx.tessellate(full_output.shapes);
-                for (id, delta) in &full_output.textures_delta.set {
-                    egui_renderer.update_texture(device, queue, *id, &delta);
-                }
-                egui_renderer.update_buffers(&device, &queue, &mut encoder, &paint_jobs, &screen_descriptor);
-                {
-                    let mut rp = encoder.begin_render_pass(&RenderPassDescriptor {
-                        label: None,
-                        color_attachments: &[Some(RenderPassColorAttachment {
-                            view: &gpu.views.get_screen().view,
-                            resolve_target: None,
-                            ops: Operations {
-                                load: LoadOp::Load,
-                                store: true,
-                            },
-                        })],
-                        depth_stencil_attachment: None,
-                    });
-                    egui_renderer.render(
-                        &mut rp,
-                        &paint_jobs,
-                        &screen_descriptor,
-                    );
-                }
-
-                // Submit the commands.
-                queue.submit(std::iter::once(encoder.finish()));
-                full_output.textures_delta.free.iter().for_each(|id| egui_renderer.free_texture(id));
+                let scope = gpu.begin_timing(&mut encoder, "post");
+                render.post.process(gpu, &mut encoder, &video);
+                gpu.end_timing(&mut encoder, scope);
+                gpu.queue.submit(Some(encoder.finish()));
             }
+            // render ui output to main screen
+            let gpu = self.app.gpu.as_ref().unwrap();
+            let start = std::time::Instant::now();
+            let platform_output = self.app.ui.paint(gpu, &gpu.views.get_screen().view, full_output);
+            self.app.metrics.add(Phase::Egui, start.elapsed());
             {
                 let mut sd = get_state!(self.app, el);
                 sd.dt = dt;
                 self.states.iter_mut().for_each(|s| s.on_event(&mut sd, StateEvent::PostUiRender));
             }
+            if let (Some(gpu), Some(render)) = (self.app.gpu.as_ref(), self.app.render.as_mut()) {
+                let mut encoder = gpu.device.create_command_encoder(&CommandEncoderDescriptor {
+                    label: Some("Fade commands")
+                });
+                if let Some(fade) = self.fade.as_ref() {
+                    render.fade.render(gpu, &mut encoder, &gpu.views.get_screen().view, [0.0, 0.0, 0.0, fade.alpha(render_now)]);
+                }
+                gpu.queue.submit(Some(encoder.finish()));
+            }
             let gpu = self.app.gpu.as_ref().unwrap();
 
             {
@@ -276,23 +349,41 @@ impl WindowInstance {
                     label: Some("Copy buffer to screen commands")
                 });
                 let size = gpu.get_screen_size();
-                encoder.copy_texture_to_texture(ImageCopyTexture {
-                    texture: &gpu.views.get_screen().texture,
-                    mip_level: 0,
-                    origin: Origin3d::default(),
-                    aspect: TextureAspect::All,
-                }, ImageCopyTexture {
-                    texture: &surface_output.texture,
-                    mip_level: 0,
-                    origin: Default::default(),
-                    aspect: TextureAspect::All,
-                }, Extent3d {
-                    width: size.0,
-                    height: size.1,
-                    depth_or_array_layers: 1,
-                });
+                let scope = gpu.begin_timing(&mut encoder, "copy");
+                if gpu.formats.is_copied() {
+                    encoder.copy_texture_to_texture(ImageCopyTexture {
+                        texture: &gpu.views.get_screen().texture,
+                        mip_level: 0,
+                        origin: Origin3d::default(),
+                        aspect: TextureAspect::All,
+                    }, ImageCopyTexture {
+                        texture: &surface_output.texture,
+                        mip_level: 0,
+                        origin: Default::default(),
+                        aspect: TextureAspect::All,
+                    }, Extent3d {
+                        width: size.0,
+                        height: size.1,
+                        depth_or_array_layers: 1,
+                    });
+                } else if let Some(render) = self.app.render.as_ref() {
+                    let view = surface_output.texture.create_view(&TextureViewDescriptor {
+                        format: Some(gpu.formats.present_format()),
+                        ..Default::default()
+                    });
+                    render.present.present(gpu, &mut encoder, &view, video.tonemap);
+                }
+                gpu.end_timing(&mut encoder, scope);
                 gpu.queue.submit(Some(encoder.finish()));
             }
+            if let Some(recorder) = self.app.recorder.as_mut() {
+                recorder.capture(gpu, video.tonemap);
+                recorder.poll(&gpu.device, false);
+            }
+            if let Some(timer) = gpu.timer.as_ref() {
+                timer.resolve(&gpu.device, &gpu.queue);
+                self.app.metrics.set_gpu_passes(timer.poll(&gpu.device));
+            }
 
             // if self.window.inputs.is_pressed(&[VirtualKeyCode::F11]) {
             //     self.window.save_screen_shots();
@@ -302,7 +393,16 @@ impl WindowInstance {
 
             self.app.last_render_time = render_now;
             swap_chain_frame.present();
-            self.app.egui_state.handle_platform_output(&self.app.window, &self.app.egui_ctx, full_output.platform_output);
+            self.app.pacing.on_present(std::time::Instant::now());
+            self.app.metrics.end_frame();
+            if let Some(gpu) = self.app.gpu.as_mut() {
+                gpu.views.end_frame();
+            }
+            if self.loop_info.loop_state.control_flow != ControlFlow::Poll {
+                // the next frame waits for the events, not a stutter
+                self.app.pacing.pause();
+            }
+            self.app.ui.handle_output(&self.app.window, platform_output);
         } else {
             // no gpu but we need render it...
             // well...
@@ -318,14 +418,17 @@ impl WindowInstance {
 
     fn on_window_event(&mut self, we: &WindowEvent, wd: &mut GlobalData) {
         self.loop_info.got_event = true;
-        let _ = self.app.egui_state.on_event(&self.app.egui_ctx, we);
+        let _ = self.app.ui.on_event(we);
         let sd = &mut get_state!(self.app, wd);
         for x in &mut self.states {
             x.on_event(sd, StateEvent::Window(we));
         }
         match we {
             WindowEvent::Touch(touch) => {
-                self.app.inputs.points.insert(touch.id, Pointer::from(*touch));
+                self.app.inputs.touch(*touch);
+            }
+            WindowEvent::Focused(focused) => {
+                self.app.focused = *focused;
             }
             WindowEvent::KeyboardInput {
                 input,
@@ -371,6 +474,15 @@ impl WindowManager {
     pub(crate) fn run_loop(mut self, event_loop: EventLoop<EventLoopMessage>, start: impl GameState) {
         let proxy = event_loop.create_proxy();
         let mut world = World::default();
+        if features().statistics {
+            world.insert(Statistics::load(PROFILE_PATH).unwrap_or_else(|e| {
+                log::warn!("Load statistics failed for {:?}", e);
+                Statistics::default()
+            }));
+        } else {
+            world.insert(Statistics::default());
+        }
+        world.insert(EventBus::default());
         {
             let mut created_windows = Vec::new();
             let mut wd = GlobalData { el: &event_loop, elp: &proxy, windows: &self.windows, new_windows: &mut created_windows, world: &mut world };
@@ -382,9 +494,18 @@ impl WindowManager {
             }
         }
         event_loop.set_device_event_filter(DeviceEventFilter::Always);
+        let mut suspended = false;
         event_loop.run(move |event, el, control_flow| {
             log::trace!(target: "winit_event", "{:?}", event);
 
+            if let Event::LoopDestroyed = event {
+                if features().statistics {
+                    world.write_resource::<Statistics>().save_if_dirty();
+                }
+                GLOBAL_DATA.cfg_data.write().unwrap().save_if_dirty();
+                return;
+            }
+
             if *control_flow == ControlFlow::Exit {
                 // Although exit, there are some events.
                 return;
@@ -408,7 +529,11 @@ impl WindowManager {
                     window_id,
                     event: WindowEvent::CloseRequested,
                 } => {
-                    self.windows.remove(&window_id);
+                    if let Some(this) = self.windows.remove(&window_id) {
+                        // the states save their things when stopped
+                        let mut wd = GlobalData { el, elp: &proxy, windows: &self.windows, new_windows: &mut created_windows, world: &mut world };
+                        this.borrow_mut().process_tran(Trans::Exit, &mut wd);
+                    }
                     if window_id == self.root {
                         *control_flow = ControlFlow::Exit
                     }
@@ -433,6 +558,18 @@ impl WindowManager {
                     }
                 }
                 Event::Suspended => {
+                    info!("Suspended");
+                    suspended = true;
+                    {
+                        let mut gd = GlobalData { el, elp: &proxy, windows: &self.windows, new_windows: &mut created_windows, world: &mut world };
+                        for (_, this) in &self.windows {
+                            this.borrow_mut().lifecycle(&mut gd, true);
+                        }
+                    }
+                    if features().statistics {
+                        world.write_resource::<Statistics>().save_if_dirty();
+                    }
+                    GLOBAL_DATA.cfg_data.write().unwrap().save_if_dirty();
                     #[cfg(target_os = "android")]
                     for (_, this) in &mut self.windows {
                         this.get_mut().app.gpu = None;
@@ -443,26 +580,17 @@ impl WindowManager {
                         let mut this = this.borrow_mut();
                         if this.app.gpu.is_none() {
                             info!("gpu not found, try to init");
-                            this.app.gpu = WgpuData::new(&this.app.window).ok();
-                            if let Some(gpu) = &this.app.gpu {
-                                this.app.render = Some(MainRendererData::new(gpu, &this.app.res));
-                                let mut gd = GlobalData { el, elp: &proxy, windows: &self.windows, new_windows: &mut created_windows, world: &mut world };
-                                let WindowInstance {
-                                    ref mut app,
-                                    ref mut states,
-                                    ..
-                                } = this.deref_mut().deref_mut();
-                                let sd = &mut get_state!(*app, &mut gd);
-                                states.iter_mut().for_each(|x| x.on_event(sd, StateEvent::ReloadGPU));
-                            }
-                            this.app.egui_ctx = Context::default();
-                            let size = this.app.window.inner_size();
-                            this.app.egui_ctx.set_pixels_per_point(this.app.window.scale_factor() as f32);
-                            let WindowInstance {
-                                ref mut app,
-                                ..
-                            } = this.deref_mut().deref_mut();
-                            let _ = app.egui_state.on_event(&app.egui_ctx, &WindowEvent::Resized(size));
+                            let mut gd = GlobalData { el, elp: &proxy, windows: &self.windows, new_windows: &mut created_windows, world: &mut world };
+                            this.reload_gpu(&mut gd);
+                        }
+                    }
+                    // the first resumed is on start
+                    if suspended {
+                        info!("Resumed");
+                        suspended = false;
+                        let mut gd = GlobalData { el, elp: &proxy, windows: &self.windows, new_windows: &mut created_windows, world: &mut world };
+                        for (_, this) in &self.windows {
+                            this.borrow_mut().lifecycle(&mut gd, false);
                         }
                     }
                 }
@@ -474,6 +602,14 @@ impl WindowManager {
                             self.windows.remove(&window_id);
                         } else if size.width > 1 && size.height > 1 {
                             let this = this.get_mut();
+                            if window_id == self.root {
+                                let window = WindowSettings { width: size.width, height: size.height };
+                                let mut cfg = GLOBAL_DATA.cfg_data.write().unwrap();
+                                // only remember the size of the normal window
+                                if cfg.settings().video.window_mode == WindowMode::Windowed && cfg.settings().window != window {
+                                    cfg.settings_mut().window = window;
+                                }
+                            }
                             if let Some(gpu) = &mut this.app.gpu {
                                 info!("Window resized, telling gpu data");
                                 gpu.resize(size.width, size.height);
@@ -483,6 +619,9 @@ impl WindowManager {
                                         this.app.render = Some(MainRendererData::new(gpu, &this.app.res));
                                     }
                                 }
+                                if this.app.ui.renderer.is_none() {
+                                    this.app.ui.init(&this.app.window, gpu);
+                                }
                             }
                         }
                     }
@@ -497,6 +636,14 @@ impl WindowManager {
                 Event::MainEventsCleared => {
                     let mut not_running = vec![];
                     let mut f_ls = LoopState::WAIT_ALL;
+                    let background_fps = GLOBAL_DATA.cfg_data.read().unwrap().settings().video.background_fps;
+                    let events = world.write_resource::<EventBus>().take_broadcast();
+                    if !events.is_empty() {
+                        for this in self.windows.values() {
+                            let mut wd = GlobalData { el, elp: &proxy, windows: &self.windows, new_windows: &mut created_windows, world: &mut world };
+                            this.borrow_mut().broadcast(&mut wd, &events);
+                        }
+                    }
                     for (id, this) in &self.windows {
                         let mut this = this.borrow_mut();
                         let this = this.deref_mut();
@@ -515,7 +662,10 @@ impl WindowManager {
                         if this.running {
                             let mut wd = GlobalData { el, elp: &proxy, windows: &self.windows, new_windows: &mut created_windows, world: &mut world };
                             this.loop_once(&mut wd);
-                            let ls = this.loop_info.loop_state;
+                            let mut ls = this.loop_info.loop_state;
+                            if !this.app.focused {
+                                ls.limit_fps(this.app.last_render_time, std::time::Instant::now(), background_fps);
+                            }
                             if ls.render {
                                 this.app.window.request_redraw();
                             }
@@ -530,6 +680,10 @@ impl WindowManager {
                         this.loop_info.updated();
                     }
                     *control_flow = f_ls.control_flow;
+                    if world.read_resource::<EventBus>().has_broadcast() {
+                        // the events published in the update are given in the next loop
+                        *control_flow = ControlFlow::Poll;
+                    }
                     for id in not_running {
                         self.windows.remove(&id);
                     }
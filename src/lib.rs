@@ -1,43 +1,68 @@
-use winit::dpi::PhysicalSize;
-use winit::event_loop::{EventLoop, EventLoopBuilder};
-use winit::window::WindowBuilder;
-
-use crate::engine::window::{EventLoopMessage, WindowManager};
-use crate::state::real_view::test_view::Test3DState;
+pub use crate::builder::EngineBuilder;
+pub use crate::engine::{GameState, GlobalData, LoopState, StateData, StateEvent, Trans};
+// the types in the app for the setup hooks and the states
+pub use crate::engine::{BakedInputs, CubeTexture, LoadContext, MainRendererData, OutputFormats, ResourceManager,
+                        TextureWrapper, WgpuData};
+pub use crate::engine::app::AppInstance;
+pub use crate::engine::state::bus::{EventBus, EventReader};
+pub use crate::engine::ui::WindowUi;
+pub use crate::engine::console::init_logger;
+pub use crate::engine::global::EngineFeatures;
+pub use crate::engine::window::EventLoopMessage;
+pub use crate::state::registry::StateRegistry;
 
 mod engine;
 mod state;
+mod builder;
 
 pub fn real_main() {
-    _main(EventLoopBuilder::with_user_event().build());
+    EngineBuilder::default().run();
+}
+
+/// Boot into the state selected in the registry, see [`StateRegistry::create_start`]
+pub fn real_main_with(registry: StateRegistry) {
+    EngineBuilder::default().registry(registry).run();
 }
 
-fn _main(event_loop: EventLoop<EventLoopMessage>) {
-    println!("[Std Stream] Joined the real main");
-    eprintln!("[Err Stream] Joined the real main");
-    log::info!("[Log Info] Joined the real main");
-    let is_3d = std::env::var("3d").map(|x| x == "1").unwrap_or(true);
-    let window = WindowBuilder::new()
-        .with_title(if is_3d { "3D" } else { "RustMeeting" })
-        .with_inner_size(PhysicalSize::new(1600, 900))
-        .build(&event_loop)
-        .unwrap();
-
-    log::info!("Got the window");
-
-    match WindowManager::new(window, &event_loop) {
-        Ok(am) => {
-            log::info!("Got the main application");
-            am.run_loop(event_loop, state::InitState::new(Box::new(Test3DState::default())));
+/// Run the rendezvous server without the window until killed.
+pub fn rendezvous_main(listen: &str) {
+    let result = engine::global::NET_RUNTIME.block_on(async {
+        let server = engine::network::server::RendezvousServer::new(listen).await?;
+        while server.running.load(std::sync::atomic::Ordering::Acquire) {
+            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        }
+        anyhow::Ok(())
+    });
+    if let Err(e) = result {
+        log::error!("Run the rendezvous server at {} failed for {:?}", listen, e);
+    }
+}
+
+/// Run the dedicated server moving the players in the level without the window and the gpu until killed.
+///
+/// The level is `level0`, `loop`, `rooms:<count>` or `generated:<seed>:<count>`.
+pub fn server_main(listen: &str, level: &str) {
+    if let Err(e) = state::real_view::dedicated::run_dedicated(listen, level) {
+        log::error!("Run the dedicated server at {} failed for {:?}", listen, e);
+    }
+}
+
+/// Step all built-in levels without the window, return false if any failed.
+pub fn smoke_main(seconds: f32) -> bool {
+    match state::real_view::smoke::smoke_levels(seconds) {
+        Ok(results) => {
+            for x in &results {
+                log::info!("Level {} passed: {} portals traversed, in world {}", x.name, x.traversed, x.world);
+            }
+            true
         }
         Err(e) => {
-            log::error!("Init the app manager failed for {:?}", e);
-            eprintln!("Init the app manager failed for {:?}", e);
+            log::error!("The smoke test failed for {:?}", e);
+            false
         }
     }
 }
 
-
 #[no_mangle]
 #[cfg(feature = "android")]
 #[cfg(target_os = "android")]
@@ -48,8 +73,9 @@ pub fn android_main(app: android_activity::AndroidApp) {
     std::env::set_var("RUST_BACKTRACE", "full");
 
     android_logger::init_once(android_logger::Config::default().with_min_level(log::Level::Trace));
+    engine::ui::set_android_app(app.clone());
     let el = EventLoopBuilder::with_user_event()
         .with_android_app(app)
         .build();
-    _main(el);
+    EngineBuilder::default().run_with(el);
 }
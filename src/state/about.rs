@@ -0,0 +1,80 @@
+use egui::{Context, Grid};
+use winit::event::VirtualKeyCode;
+
+use crate::engine::{GameState, LoopState, StateData, Trans};
+use crate::engine::build_info::{BUNDLED_ASSETS, GIT_HASH, PROFILE, TARGET, version_line, VERSION};
+
+/// Show the version, build and gpu information for bug reports.
+#[derive(Default)]
+pub struct AboutState;
+
+impl AboutState {
+    fn report(s: &StateData) -> String {
+        let mut report = version_line();
+        if let Some(gpu) = s.app.gpu.as_ref() {
+            let info = &gpu.adapter_info;
+            report += &format!("\n{} ({:?}, {:?}) {}", info.name, info.backend, info.device_type, info.driver_info);
+        }
+        report
+    }
+}
+
+impl GameState for AboutState {
+    fn update(&mut self, s: &mut StateData) -> (Trans, LoopState) {
+        if s.app.inputs.is_pressed(&[VirtualKeyCode::Escape]) {
+            return (Trans::Pop, LoopState::WAIT);
+        }
+        (Trans::None, LoopState::WAIT)
+    }
+
+    fn render(&mut self, s: &mut StateData, ctx: &Context) -> Trans {
+        let mut tran = Trans::None;
+        egui::CentralPanel::default()
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    if ui.button("返回").clicked() {
+                        tran = Trans::Pop;
+                    }
+                    if ui.button("复制").clicked() {
+                        ctx.output_mut(|o| o.copied_text = Self::report(s));
+                    }
+                });
+                ui.heading("Maybe Portal");
+                Grid::new("build info").striped(true).show(ui, |ui| {
+                    ui.label("版本");
+                    ui.label(VERSION);
+                    ui.end_row();
+                    ui.label("Git");
+                    ui.label(GIT_HASH);
+                    ui.end_row();
+                    ui.label("目标");
+                    ui.label(format!("{} ({})", TARGET, PROFILE));
+                    ui.end_row();
+                    if let Some(gpu) = s.app.gpu.as_ref() {
+                        let info = &gpu.adapter_info;
+                        ui.label("显卡");
+                        ui.label(&info.name);
+                        ui.end_row();
+                        ui.label("后端");
+                        ui.label(format!("{:?} ({:?})", info.backend, info.device_type));
+                        ui.end_row();
+                        ui.label("驱动");
+                        ui.label(format!("{} {}", info.driver, info.driver_info));
+                        ui.end_row();
+                    }
+                });
+
+                ui.separator();
+                ui.heading("资源");
+                Grid::new("assets").striped(true).show(ui, |ui| {
+                    for x in BUNDLED_ASSETS {
+                        ui.label(x.name);
+                        ui.label(x.path);
+                        ui.label(x.license);
+                        ui.end_row();
+                    }
+                });
+            });
+        tran
+    }
+}
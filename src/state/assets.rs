@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+
+use egui::{Context, Grid, ScrollArea, TextureHandle, TextureOptions};
+use winit::event::VirtualKeyCode;
+
+use crate::engine::{AssetStorage, GameState, load_image_from_memory, LoadContext, LoopState, ResourceManager, StateData, Trans};
+
+/// List the assets known by the resource manager with the texture previews.
+#[derive(Default)]
+pub struct AssetBrowserState {
+    cur_kind: AssetKind,
+    /// The egui textures to preview, None if the image cannot be loaded.
+    thumbnails: HashMap<String, Option<TextureHandle>>,
+}
+
+#[derive(PartialEq, Eq)]
+enum AssetKind {
+    Texture,
+    Model,
+    Sound,
+    Shader,
+}
+
+impl Default for AssetKind {
+    fn default() -> Self {
+        AssetKind::Texture
+    }
+}
+
+/// (name, loaded)
+fn list<T>(storage: &AssetStorage<T>) -> Vec<(String, bool)> {
+    storage.names().into_iter().map(|(name, handle)| {
+        let loaded = storage.is_loaded(handle);
+        (name, loaded)
+    }).collect()
+}
+
+fn load_thumbnail(ctx: &Context, res: &ResourceManager, name: &str) -> Option<TextureHandle> {
+    let path = res.asset_path(name)?;
+    let image = res.load_asset(&path).ok()
+        .and_then(|x| load_image_from_memory(&x).ok())?;
+    Some(ctx.load_texture(name, image, TextureOptions::LINEAR))
+}
+
+impl GameState for AssetBrowserState {
+    fn update(&mut self, s: &mut StateData) -> (Trans, LoopState) {
+        if s.app.inputs.is_pressed(&[VirtualKeyCode::Escape]) {
+            return (Trans::Pop, LoopState::WAIT);
+        }
+        (Trans::None, LoopState::WAIT)
+    }
+
+    fn render(&mut self, s: &mut StateData, ctx: &Context) -> Trans {
+        let mut tran = Trans::None;
+        let res = s.app.res.clone();
+        let load_ctx = s.app.gpu.as_ref().map(LoadContext::new);
+        egui::CentralPanel::default()
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    if ui.button("返回").clicked() {
+                        tran = Trans::Pop;
+                    }
+                    ui.separator();
+                    ui.selectable_value(&mut self.cur_kind, AssetKind::Texture, "纹理");
+                    ui.selectable_value(&mut self.cur_kind, AssetKind::Model, "模型");
+                    ui.selectable_value(&mut self.cur_kind, AssetKind::Sound, "声音");
+                    ui.selectable_value(&mut self.cur_kind, AssetKind::Shader, "着色器");
+                });
+                ui.separator();
+                let assets = match self.cur_kind {
+                    AssetKind::Texture => list(&res.textures),
+                    AssetKind::Model => list(&res.models),
+                    AssetKind::Sound => list(&res.sounds),
+                    AssetKind::Shader => list(&res.shaders),
+                };
+                ScrollArea::vertical().show(ui, |ui| {
+                    Grid::new("assets").striped(true).show(ui, |ui| {
+                        for (name, loaded) in assets {
+                            if self.cur_kind == AssetKind::Texture {
+                                let thumbnail = self.thumbnails.entry(name.clone())
+                                    .or_insert_with(|| load_thumbnail(ctx, &res, &name));
+                                match thumbnail {
+                                    Some(x) => ui.image(x.id(), [64.0, 64.0]),
+                                    None => ui.label("-"),
+                                };
+                            }
+                            ui.label(&name);
+                            ui.label(res.asset_path(&name).unwrap_or_default());
+                            ui.label(if loaded { "已加载" } else { "未加载" });
+                            if let Some(load_ctx) = load_ctx.as_ref() {
+                                if ui.button("重新加载").clicked() && res.reload(&name, load_ctx) {
+                                    self.thumbnails.remove(&name);
+                                }
+                            }
+                            ui.end_row();
+                        }
+                    });
+                });
+            });
+        tran
+    }
+}
@@ -0,0 +1,147 @@
+use egui::{Color32, Context, Frame, RichText, ScrollArea, TextEdit, TopBottomPanel};
+use log::Level;
+use winit::event::{ElementState, VirtualKeyCode, WindowEvent};
+
+use crate::engine::{GameState, LoopState, StateData, StateEvent, Trans};
+use crate::engine::console::{self, Console, History};
+
+/// The height of the console in the screen height.
+const HEIGHT_RATIO: f32 = 0.4;
+
+/// The developer console under the other states, toggled by the backtick.
+#[derive(Default)]
+pub struct ConsoleState {
+    open: bool,
+    input: String,
+    history: History,
+    /// Opened or closed by the key this frame.
+    just_toggled: bool,
+}
+
+fn line_color(level: Option<Level>) -> Color32 {
+    match level {
+        None => Color32::WHITE,
+        Some(Level::Error) => Color32::LIGHT_RED,
+        Some(Level::Warn) => Color32::YELLOW,
+        Some(Level::Info) => Color32::LIGHT_GRAY,
+        Some(_) => Color32::GRAY,
+    }
+}
+
+impl ConsoleState {
+    fn set_open(&mut self, s: &mut StateData, open: bool) {
+        self.open = open;
+        self.just_toggled = true;
+        self.input.clear();
+        if let Some(mut console) = s.app.world.try_fetch_mut::<Console>() {
+            console.set_typing(true);
+        }
+    }
+
+    fn submit(&mut self, s: &mut StateData) {
+        let line = std::mem::take(&mut self.input);
+        let line = line.trim();
+        self.history.push(line);
+        if line.is_empty() {
+            return;
+        }
+        console::print(None, format!("> {}", line));
+        match console::execute(s, line) {
+            Ok(x) if x.is_empty() => {}
+            Ok(x) => console::print(None, x),
+            Err(e) => console::print(Some(Level::Error), format!("{:?}", e)),
+        }
+    }
+
+    /// Complete the command name if only one starts with the input.
+    fn complete(&mut self, s: &mut StateData) {
+        if self.input.contains(char::is_whitespace) {
+            return;
+        }
+        if let Some(console) = s.app.world.try_fetch::<Console>() {
+            let names = console.complete(&self.input).collect::<Vec<_>>();
+            match names.as_slice() {
+                [name] => self.input = format!("{} ", name),
+                [] => {}
+                _ => console::print(None, names.iter().map(|x| x.as_str()).collect::<Vec<_>>().join(" ")),
+            }
+        }
+    }
+}
+
+impl GameState for ConsoleState {
+    fn start(&mut self, s: &mut StateData) {
+        if !s.app.world.has_value::<Console>() {
+            s.app.world.insert(Console::default());
+        }
+    }
+
+    /// The states over are all popped.
+    fn update(&mut self, _: &mut StateData) -> (Trans, LoopState) {
+        (Trans::Exit, LoopState::WAIT)
+    }
+
+    fn shadow_render(&mut self, s: &mut StateData, ctx: &Context) {
+        let toggled = std::mem::take(&mut self.just_toggled);
+        if let Some(mut console) = s.app.world.try_fetch_mut::<Console>() {
+            console.set_typing(self.open || toggled);
+        }
+        if !self.open {
+            return;
+        }
+        TopBottomPanel::top("console")
+            .exact_height(ctx.screen_rect().height() * HEIGHT_RATIO)
+            .frame(Frame::none().fill(Color32::from_black_alpha(200)).inner_margin(8.0))
+            .show(ctx, |ui| {
+                TopBottomPanel::bottom("console input")
+                    .frame(Frame::none())
+                    .show_inside(ui, |ui| {
+                        let input = ui.add(TextEdit::singleline(&mut self.input)
+                            .desired_width(f32::INFINITY)
+                            .font(egui::TextStyle::Monospace)
+                            .hint_text("Enter to run, Tab to complete, ` to close"));
+                        input.request_focus();
+                    });
+                ScrollArea::vertical()
+                    .auto_shrink([false, false])
+                    .stick_to_bottom(true)
+                    .show(ui, |ui| {
+                        console::with_lines(|lines| {
+                            for x in lines {
+                                ui.label(RichText::new(&x.text).monospace().color(line_color(x.level)));
+                            }
+                        });
+                    });
+            });
+        // the toggle key typed into the input
+        self.input.retain(|x| x != '`');
+    }
+
+    fn on_event(&mut self, s: &mut StateData, e: StateEvent) {
+        if let StateEvent::Window(WindowEvent::KeyboardInput { input, .. }) = e {
+            if input.state != ElementState::Pressed {
+                return;
+            }
+            match input.virtual_keycode {
+                // not toggled when typing in the other inputs
+                Some(VirtualKeyCode::Grave) if self.open || !s.app.ui.ctx.wants_keyboard_input() => {
+                    self.set_open(s, !self.open);
+                }
+                Some(VirtualKeyCode::Escape) if self.open => self.set_open(s, false),
+                Some(VirtualKeyCode::Return | VirtualKeyCode::NumpadEnter) if self.open => self.submit(s),
+                Some(VirtualKeyCode::Tab) if self.open => self.complete(s),
+                Some(VirtualKeyCode::Up) if self.open => {
+                    if let Some(x) = self.history.older() {
+                        self.input = x.to_string();
+                    }
+                }
+                Some(VirtualKeyCode::Down) if self.open => {
+                    if let Some(x) = self.history.newer() {
+                        self.input = x.to_string();
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
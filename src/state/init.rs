@@ -1,47 +1,63 @@
 use std::sync::Arc;
 use std::sync::atomic::Ordering;
 
-use futures::task::SpawnExt;
-use log::error;
-use wgpu::{Device, Queue};
+use egui::FontData;
 
-use crate::engine::{GameState, LoopState, ResourceManager, StateData, StateEvent, Trans, WaitFutureState, WaitResult};
-use crate::engine::global::{INITED, IO_POOL};
+use crate::engine::{CubeTexture, GameState, LoadContext, LoopState, ResourceManager, StateData, StateEvent, TextureWrapper, Trans};
+use crate::engine::global::{features, GLOBAL_DATA, INITED};
+use crate::engine::ui::UI_FONT;
+use crate::state::console::ConsoleState;
+use crate::state::loading::LoadingState;
+
+/// Called once the gpu is ready, to insert the renderers and the resources to the app.
+pub type SetupFn = Box<dyn FnOnce(&mut StateData) + Send>;
 
 pub struct InitState {
     start_state: Option<Box<dyn GameState + Send + 'static>>,
+    /// The (name, path) of the textures to load besides the built-in.
+    textures: Vec<(String, String)>,
+    setups: Vec<SetupFn>,
 }
 
+#[allow(unused)]
 impl InitState {
     pub fn new(state: Box<dyn GameState + Send + 'static>) -> Self {
         Self {
             start_state: Some(state),
+            textures: vec![],
+            setups: vec![],
         }
     }
-}
 
-async fn load_texture(a_d: Arc<Device>, a_q: Arc<Queue>, a_r: Arc<ResourceManager>) -> anyhow::Result<()> {
-    let device = unsafe { std::mem::transmute::<_, &'static _>(a_d.as_ref()) };
-    let queue = unsafe { std::mem::transmute::<_, &'static _>(a_q.as_ref()) };
-    let res = unsafe { std::mem::transmute::<_, &'static ResourceManager>(a_r.as_ref()) };
-    for x in [
-        res.load_texture_async(device, queue, "bf".into(), "texture/floor/blue.png"),
-        res.load_texture_async(device, queue, "gf".into(), "texture/floor/green.png"),
-        res.load_texture_async(device, queue, "pf".into(), "texture/floor/purple.png"),
-        res.load_texture_async(device, queue, "rf".into(), "texture/floor/red.png"),
-        res.load_texture_async(device, queue, "af".into(), "texture/floor/aqua.png"),
-        res.load_texture_async(device, queue, "yf".into(), "texture/floor/yellow.png"),
-        res.load_texture_async(device, queue, "gray_f".into(), "texture/floor/gray.png"),
-        res.load_texture_async(device, queue, "pink_f".into(), "texture/floor/pink.png"),
-        res.load_texture_async(device, queue, "black_f".into(), "texture/floor/black.png"),
+    pub fn with_textures(mut self, textures: Vec<(String, String)>) -> Self {
+        self.textures = textures;
+        self
+    }
 
-    ]
-        .map(|task| IO_POOL.spawn_with_handle(task))
-    {
-        x?.await?;
+    pub fn with_setups(mut self, setups: Vec<SetupFn>) -> Self {
+        self.setups = setups;
+        self
     }
+}
 
-    anyhow::Ok(())
+pub(crate) fn load_texture(res: &Arc<ResourceManager>, ctx: &LoadContext, extra: &[(String, String)]) {
+    for (key, path) in [
+        ("bf", "texture/floor/blue.png"),
+        ("gf", "texture/floor/green.png"),
+        ("pf", "texture/floor/purple.png"),
+        ("rf", "texture/floor/red.png"),
+        ("af", "texture/floor/aqua.png"),
+        ("yf", "texture/floor/yellow.png"),
+        ("gray_f", "texture/floor/gray.png"),
+        ("pink_f", "texture/floor/pink.png"),
+        ("black_f", "texture/floor/black.png"),
+    ] {
+        res.load::<TextureWrapper>(ctx, key, path);
+    }
+    for (key, path) in extra {
+        res.load::<TextureWrapper>(ctx, key.as_str(), path.as_str());
+    }
+    res.load::<CubeTexture>(ctx, "sky", "texture/sky/sky.png");
 }
 
 
@@ -49,34 +65,26 @@ impl GameState for InitState {
     fn update(&mut self, s: &mut StateData) -> (Trans, LoopState) {
         if let Some(gpu) = s.app.gpu.as_ref() {
             let state = self.start_state.take().unwrap();
-            let device = gpu.device.clone();
-            let queue = gpu.queue.clone();
-            let res = s.app.res.clone();
-            let handle = IO_POOL.spawn_with_handle(async move {
-                let device = device;
-                let queue = queue;
-                let res = res;
-                let task = async move {
-                    if !INITED.load(Ordering::Acquire) {
-                        // Lazy::force(&GLOBAL_DATA);
-                    }
-                    load_texture(device, queue, res).await?;
-
-                    anyhow::Ok(())
-                };
-                if let Err(e) = task.await {
-                    error!("Load failed for {:?}", e);
-                    WaitResult::Exit
-                } else {
-                    WaitResult::Function(Box::new(|_| {
-                        // s.app.egui_ctx.set_fonts(GLOBAL_DATA.font.clone());
-                        Trans::Switch(state)
-                    }))
-                }
-            }).expect("Spawn init task failed");
-
+            if !INITED.load(Ordering::Acquire) {
+                // Lazy::force(&GLOBAL_DATA);
+            }
+            load_texture(&s.app.res, &LoadContext::new(gpu), &self.textures);
+            let font = GLOBAL_DATA.cfg_data.read().unwrap().settings().font.path.clone();
+            if !font.is_empty() {
+                // used by the loading state after loaded
+                s.app.res.load::<FontData>(&LoadContext::new(gpu), UI_FONT, font);
+            }
+            for setup in std::mem::take(&mut self.setups) {
+                setup(s);
+            }
 
-            (Trans::Push(WaitFutureState::from_wait_thing(handle)), LoopState::POLL_WITHOUT_RENDER)
+            let loading = LoadingState::new(state);
+            if features().console {
+                // the console is under all states
+                (Trans::Vec(vec![Trans::Switch(Box::new(ConsoleState::default())), Trans::Push(loading)]), LoopState::POLL)
+            } else {
+                (Trans::Switch(loading), LoopState::POLL)
+            }
         } else {
             (Trans::None, LoopState::WAIT_ALL)
         }
@@ -86,7 +94,8 @@ impl GameState for InitState {
         if matches!(e, StateEvent::ReloadGPU) {
             let gpu = s.app.gpu.as_ref().expect("I FOUND GPU");
             println!("block on loading");
-            futures::executor::block_on(load_texture(gpu.device.clone(), gpu.queue.clone(), s.app.res.clone()))
+            load_texture(&s.app.res, &LoadContext::new(gpu), &self.textures);
+            futures::executor::block_on(s.app.res.wait_loading())
                 .expect("Load texture failed");
             println!("block end");
         }
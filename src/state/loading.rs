@@ -0,0 +1,91 @@
+use std::time::Duration;
+
+use egui::{Color32, Context, Grid, ProgressBar, RichText, ScrollArea, Spinner};
+use log::warn;
+
+use crate::engine::{GameState, LoadContext, LoadStatus, LoopState, StateData, Trans};
+
+/// The fade to the next state after loaded.
+const LOADED_FADE: Duration = Duration::from_millis(600);
+
+/// Show the progress of the assets loading by the resource manager,
+/// switch to the next state after all loaded with the fonts loaded used.
+pub struct LoadingState {
+    next: Option<Box<dyn GameState + Send + 'static>>,
+}
+
+impl LoadingState {
+    pub fn new(next: Box<dyn GameState + Send + 'static>) -> Box<Self> {
+        Self {
+            next: Some(next),
+        }.into()
+    }
+}
+
+impl GameState for LoadingState {
+    fn update(&mut self, s: &mut StateData) -> (Trans, LoopState) {
+        let res = &s.app.res;
+        if res.is_loading() {
+            return (Trans::None, LoopState::POLL);
+        }
+        if res.has_failed() {
+            // wait for retry or exit
+            return (Trans::None, LoopState::WAIT);
+        }
+        // all loaded, drop the finished tasks (the errors are retried already)
+        if let Err(e) = futures::executor::block_on(res.wait_loading()) {
+            warn!("Loaded after retry, the error was {:?}", e);
+        }
+        s.app.ui.apply_loaded_fonts(&s.app.res);
+        match self.next.take() {
+            Some(next) => (Trans::SwitchFade(next, LOADED_FADE), LoopState::POLL),
+            None => (Trans::Pop, LoopState::POLL),
+        }
+    }
+
+    fn render(&mut self, s: &mut StateData, ctx: &Context) -> Trans {
+        let mut tran = Trans::None;
+        let res = s.app.res.clone();
+        egui::CentralPanel::default()
+            .show(ctx, |ui| {
+                ui.vertical_centered(|ui| {
+                    ui.heading("加载中");
+                    ui.horizontal(|ui| {
+                        if res.is_loading() {
+                            ui.add(Spinner::new());
+                        }
+                        ui.add(ProgressBar::new(res.progress()).show_percentage());
+                    });
+                });
+                ui.separator();
+                ScrollArea::vertical().max_height(ui.available_height() - 32.0).show(ui, |ui| {
+                    Grid::new("load status").striped(true).show(ui, |ui| {
+                        for (name, path, status) in res.load_status() {
+                            ui.label(name);
+                            ui.label(path);
+                            match status {
+                                LoadStatus::Loading => ui.label("加载中"),
+                                LoadStatus::Loaded => ui.label("完成"),
+                                LoadStatus::Failed(e) => ui.label(RichText::new(format!("失败: {}", e)).color(Color32::RED)),
+                                LoadStatus::Evicted => ui.label("已卸载"),
+                            };
+                            ui.end_row();
+                        }
+                    });
+                });
+                if res.has_failed() && !res.is_loading() {
+                    ui.horizontal(|ui| {
+                        if ui.button("重试").clicked() {
+                            if let Some(gpu) = s.app.gpu.as_ref() {
+                                res.retry_failed(&LoadContext::new(gpu));
+                            }
+                        }
+                        if ui.button("退出").clicked() {
+                            tran = Trans::Exit;
+                        }
+                    });
+                }
+            });
+        tran
+    }
+}
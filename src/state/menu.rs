@@ -0,0 +1,65 @@
+use std::time::Duration;
+
+use egui::{Align2, Context};
+use log::warn;
+
+use crate::engine::{GameState, LoopState, StateData, Trans};
+use crate::state::about::AboutState;
+use crate::state::real_view::session::{Session, session_path};
+use crate::state::real_view::test_view::Test3DState;
+use crate::state::settings::SettingState;
+
+/// The fade to the level from the menu.
+const START_FADE: Duration = Duration::from_millis(800);
+
+/// The menu shown first, continue the session saved in the last run or start again.
+#[derive(Default)]
+pub struct MainMenuState {
+    session: Option<Session>,
+}
+
+impl GameState for MainMenuState {
+    fn start(&mut self, _: &mut StateData) {
+        let path = session_path();
+        if path.exists() {
+            self.session = Session::load(&path)
+                .map_err(|e| warn!(target: "session", "Load the session failed for {:?}", e))
+                .ok();
+        }
+    }
+
+    fn update(&mut self, _: &mut StateData) -> (Trans, LoopState) {
+        (Trans::None, LoopState::WAIT)
+    }
+
+    fn render(&mut self, _: &mut StateData, ctx: &Context) -> Trans {
+        let mut tran = Trans::None;
+        egui::Window::new("maybe portal")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                ui.style_mut().spacing.button_padding *= 4.0;
+                ui.vertical_centered_justified(|ui| {
+                    if ui.add_enabled(self.session.is_some(), egui::Button::new("继续")).clicked() {
+                        if let Some(session) = self.session.take() {
+                            tran = Trans::SwitchFade(Box::new(Test3DState::continue_session(session)), START_FADE);
+                        }
+                    }
+                    if ui.button("新游戏").clicked() {
+                        tran = Trans::SwitchFade(Box::new(Test3DState::default()), START_FADE);
+                    }
+                    if ui.button("设置").clicked() {
+                        tran = Trans::Push(Box::new(SettingState::default()));
+                    }
+                    if ui.button("关于").clicked() {
+                        tran = Trans::Push(Box::new(AboutState));
+                    }
+                    if ui.button("退出").clicked() {
+                        tran = Trans::Exit;
+                    }
+                });
+            });
+        tran
+    }
+}
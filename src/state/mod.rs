@@ -1,5 +1,13 @@
 pub use init::*;
 
 mod init;
+mod loading;
 mod settings;
+mod stats;
+mod about;
+mod pause;
+mod console;
+mod assets;
+mod menu;
 pub mod real_view;
+pub mod registry;
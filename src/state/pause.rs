@@ -0,0 +1,53 @@
+use egui::{Align2, Color32, Context, LayerId};
+use winit::event::VirtualKeyCode;
+
+use crate::engine::{GameState, LoopState, StateData, Trans};
+use crate::engine::console;
+use crate::state::settings::SettingState;
+
+/// The alpha of the black over the paused scene.
+const DARKEN_ALPHA: u8 = 160;
+
+/// The menu over the paused level, the level below keeps rendering the scene in its shadow render.
+#[derive(Default)]
+pub struct PauseState;
+
+impl GameState for PauseState {
+    fn update(&mut self, s: &mut StateData) -> (Trans, LoopState) {
+        if !console::is_typing(&s.app.world) && s.app.inputs.is_pressed(&[VirtualKeyCode::Escape]) {
+            return (Trans::Pop, LoopState::WAIT);
+        }
+        (Trans::None, LoopState::WAIT)
+    }
+
+    fn render(&mut self, _: &mut StateData, ctx: &Context) -> Trans {
+        let mut tran = Trans::None;
+        egui::Window::new("暂停")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                ui.style_mut().spacing.button_padding *= 4.0;
+                ui.vertical_centered_justified(|ui| {
+                    if ui.button("继续").clicked() {
+                        tran = Trans::Pop;
+                    }
+                    if ui.button("设置").clicked() {
+                        tran = Trans::Push(Box::new(SettingState::default()));
+                    }
+                    if ui.button("退出").clicked() {
+                        tran = Trans::Exit;
+                    }
+                });
+            });
+        tran
+    }
+
+    /// Darken the scene, also under the states pushed by the menu.
+    ///
+    /// Painted in the background layer before the panels of the states over.
+    fn shadow_render(&mut self, _: &mut StateData, ctx: &Context) {
+        ctx.layer_painter(LayerId::background())
+            .rect_filled(ctx.screen_rect(), 0.0, Color32::from_black_alpha(DARKEN_ALPHA));
+    }
+}
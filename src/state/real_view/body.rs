@@ -0,0 +1,68 @@
+use std::slice::from_ref;
+
+use nalgebra::{Point3, Similarity3, UnitQuaternion, Vector3};
+use wgpu::RenderPass;
+
+use crate::engine::ecs::Transform;
+use crate::engine::renderer3d::renderer3d::{PlaneRenderer, Planes, StaticPlanes};
+use crate::engine::WgpuData;
+use crate::state::real_view::meshes::cube;
+
+/// The half size of the head at the eye, the same as the avatars.
+const HEAD: f32 = 0.2;
+/// The half size of the torso below the eye.
+const TORSO: f32 = 0.3;
+const TORSO_DROP: f32 = 0.6;
+
+/// The parts of the body in the world.
+struct BodyPlanes {
+    world: usize,
+    torso: StaticPlanes,
+    head: StaticPlanes,
+}
+
+/// My body, with the clones behind the portals straddled to see myself walking through.
+#[derive(Default)]
+pub struct PlayerBody {
+    /// Mine first and then the clones.
+    planes: Vec<BodyPlanes>,
+}
+
+#[allow(unused)]
+impl PlayerBody {
+    /// Rebuild at my eye facing the forward, and the clones mapped by the portals straddled to their worlds.
+    pub fn rebuild(&mut self, gpu: &WgpuData, world: usize, eye: &Point3<f32>, forward: &Vector3<f32>,
+                   clones: impl Iterator<Item=(usize, Similarity3<f32>)>) {
+        let yaw = UnitQuaternion::from_axis_angle(&Vector3::z_axis(), forward.y.atan2(forward.x));
+        let body = Transform { position: *eye, rotation: yaw };
+        self.planes = std::iter::once((world, Similarity3::identity()))
+            .chain(clones)
+            .map(|(world, similarity)| {
+                let rotation = similarity.isometry.rotation * body.rotation;
+                let scale = similarity.scaling();
+                let head = Transform { position: similarity * body.position, rotation };
+                let torso = Transform { position: similarity * (body.position - Vector3::z() * TORSO_DROP), rotation };
+                BodyPlanes {
+                    world,
+                    torso: Planes { objs: cube(&torso, TORSO * scale), texture_bind: None }.to_static(&gpu.device),
+                    head: Planes { objs: cube(&head, HEAD * scale), texture_bind: None }.to_static(&gpu.device),
+                }
+            })
+            .collect();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.planes.is_empty()
+    }
+
+    /// Render the body in the world with the pipeline set, without my head in my eye.
+    pub fn render<'a>(&'a self, rp: &mut RenderPass<'a>, gpu: &WgpuData, pr: &'a PlaneRenderer, world: usize, in_portal: bool) {
+        rp.set_bind_group(1, &pr.white_bind, &[]);
+        for (idx, x) in self.planes.iter().enumerate().filter(|x| x.1.world == world) {
+            pr.render_static(rp, gpu, from_ref(&x.torso));
+            if in_portal || idx > 0 {
+                pr.render_static(rp, gpu, from_ref(&x.head));
+            }
+        }
+    }
+}
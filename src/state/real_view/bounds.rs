@@ -0,0 +1,109 @@
+use nalgebra::Vector3;
+
+/// The max distance falling below the last safe position before recovered.
+pub const FALL_LIMIT: f32 = 32.0;
+
+/// The box the player able to be in the world.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct WorldBounds {
+    pub min: Vector3<f32>,
+    pub max: Vector3<f32>,
+}
+
+impl WorldBounds {
+    pub fn contains(&self, pos: &Vector3<f32>) -> bool {
+        pos.iter().zip(self.min.iter().zip(self.max.iter()))
+            .all(|(x, (min, max))| min <= x && x <= max)
+    }
+}
+
+/// The bounds of the worlds in the level and the positions to recover to when out of them.
+#[derive(Debug)]
+pub struct LevelBounds {
+    /// The bounds by the world, only the falling limited if none.
+    pub worlds: Vec<Option<WorldBounds>>,
+    pub fall_limit: f32,
+    /// (world, position) recovered to if no safe position.
+    pub checkpoint: (usize, Vector3<f32>),
+    /// (world, position) on the ground last.
+    safe: Option<(usize, Vector3<f32>)>,
+    /// The times recovered.
+    pub recovered: u32,
+}
+
+#[allow(unused)]
+impl LevelBounds {
+    pub fn new(world: usize, pos: Vector3<f32>) -> Self {
+        Self {
+            worlds: vec![],
+            fall_limit: FALL_LIMIT,
+            checkpoint: (world, pos),
+            safe: None,
+            recovered: 0,
+        }
+    }
+
+    pub fn set_world(&mut self, world: usize, bounds: WorldBounds) {
+        if self.worlds.len() <= world {
+            self.worlds.resize(world + 1, None);
+        }
+        self.worlds[world] = Some(bounds);
+    }
+
+    /// Set the checkpoint and forget the safe position.
+    pub fn set_checkpoint(&mut self, world: usize, pos: Vector3<f32>) {
+        self.checkpoint = (world, pos);
+        self.safe = None;
+    }
+
+    pub fn is_out(&self, world: usize, pos: &Vector3<f32>) -> bool {
+        if !pos.iter().all(|x| x.is_finite()) {
+            return true;
+        }
+        if let Some(bounds) = self.worlds.get(world).and_then(|x| x.as_ref()) {
+            if !bounds.contains(pos) {
+                return true;
+            }
+        }
+        let (safe_world, safe) = self.recover_to();
+        world == safe_world && pos.z < safe.z - self.fall_limit
+    }
+
+    /// Remember the position as safe if on the ground, or just went through the portal.
+    pub fn mark_safe(&mut self, world: usize, pos: Vector3<f32>) {
+        if !self.is_out(world, &pos) {
+            self.safe = Some((world, pos));
+        }
+    }
+
+    /// The (world, position) to recover to.
+    pub fn recover_to(&self) -> (usize, Vector3<f32>) {
+        self.safe.unwrap_or(self.checkpoint)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use nalgebra::{vector, Vector3};
+
+    use crate::state::real_view::bounds::{FALL_LIMIT, LevelBounds, WorldBounds};
+
+    #[test]
+    fn test_recover() {
+        let mut bounds = LevelBounds::new(0, vector![0.0, 0.0, 1.0]);
+        bounds.set_world(1, WorldBounds { min: vector![-5.0, -5.0, -5.0], max: vector![5.0, 5.0, 5.0] });
+        assert!(!bounds.is_out(0, &vector![100.0, 0.0, 1.0]));
+        assert!(bounds.is_out(0, &vector![0.0, 0.0, 0.5 - FALL_LIMIT]));
+        assert!(bounds.is_out(0, &vector![f32::NAN, 0.0, 1.0]));
+        assert!(bounds.is_out(1, &vector![6.0, 0.0, 0.0]));
+        assert!(!bounds.is_out(1, &Vector3::zeros()));
+
+        bounds.mark_safe(1, vector![1.0, 0.0, 0.0]);
+        assert_eq!(bounds.recover_to(), (1, vector![1.0, 0.0, 0.0]));
+        // not safe out of the bounds
+        bounds.mark_safe(1, vector![10.0, 0.0, 0.0]);
+        assert_eq!(bounds.recover_to(), (1, vector![1.0, 0.0, 0.0]));
+        bounds.set_checkpoint(0, Vector3::zeros());
+        assert_eq!(bounds.recover_to(), (0, Vector3::zeros()));
+    }
+}
@@ -0,0 +1,128 @@
+use std::str::FromStr;
+
+use anyhow::bail;
+use futures::future::RemoteHandle;
+use futures::FutureExt;
+use futures::task::SpawnExt;
+use serde::{Deserialize, Serialize};
+
+use crate::engine::global::IO_POOL;
+use crate::engine::physics::state::RapierData;
+use crate::engine::prelude::*;
+use crate::engine::renderer3d::renderer3d::PlaneRenderer;
+use crate::state::real_view::level::MagicLevel;
+use crate::state::real_view::level0::Level0Plan;
+use crate::state::real_view::level_loop::LoopPlan;
+use crate::state::real_view::level_gen::GenPlan;
+use crate::state::real_view::level_rooms::{RoomsPlan, RoomTextures};
+
+/// The level to build.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum LevelSpec {
+    Level0,
+    Loop,
+    /// The count of the rooms and their textures.
+    Rooms(usize, RoomTextures),
+    /// The seed and the count of the rooms, connected by the portals and the corridors generated.
+    Generated(u64, usize),
+}
+
+impl FromStr for LevelSpec {
+    type Err = anyhow::Error;
+
+    /// `level0`, `loop`, `rooms:<count>` or `generated:<seed>:<count>`, the rooms are seeded by 0.
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let args = s.split(':').collect::<Vec<_>>();
+        Ok(match args.as_slice() {
+            ["level0"] => LevelSpec::Level0,
+            ["loop"] => LevelSpec::Loop,
+            ["rooms", cnt] => LevelSpec::Rooms(cnt.parse()?, RoomTextures::Seeded(0)),
+            ["generated", seed, cnt] => LevelSpec::Generated(seed.parse()?, cnt.parse()?),
+            _ => bail!("Unknown level {}", s),
+        })
+    }
+}
+
+/// The level planned without the gpu.
+pub enum LevelPlan {
+    Level0(Box<Level0Plan>),
+    Loop(Box<LoopPlan>),
+    Rooms(Box<RoomsPlan>),
+    Generated(Box<GenPlan>),
+}
+
+impl LevelSpec {
+    /// Plan the CPU side of the level, run in the io pool.
+    pub fn plan(&self) -> LevelPlan {
+        match self {
+            LevelSpec::Level0 => LevelPlan::Level0(Box::new(Level0Plan::new())),
+            LevelSpec::Loop => LevelPlan::Loop(Box::new(LoopPlan::new())),
+            LevelSpec::Rooms(cnt, rooms) => LevelPlan::Rooms(Box::new(RoomsPlan::new(*cnt, rooms))),
+            LevelSpec::Generated(seed, cnt) => LevelPlan::Generated(Box::new(GenPlan::new(*seed, *cnt))),
+        }
+    }
+}
+
+impl LevelPlan {
+    /// The colliders of the level without me, the portals are added when built so not in them.
+    pub fn into_physics(self) -> RapierData {
+        let (mut p, me) = match self {
+            LevelPlan::Level0(x) => (x.p, x.me),
+            LevelPlan::Loop(x) => (x.p, x.me),
+            LevelPlan::Rooms(x) => (x.p, x.me),
+            LevelPlan::Generated(x) => (x.p, x.me),
+        };
+        p.remove_body(me.handle);
+        p
+    }
+}
+
+/// The level planning in the io pool, the old level is shown until it is built.
+pub struct LevelTask {
+    pub spec: LevelSpec,
+    handle: RemoteHandle<LevelPlan>,
+}
+
+#[allow(unused)]
+impl LevelTask {
+    pub fn spawn(spec: LevelSpec) -> Self {
+        let task = spec.clone();
+        let handle = IO_POOL.spawn_with_handle(async move {
+            task.plan()
+        }).expect("Spawn level task failed");
+        Self { spec, handle }
+    }
+
+    /// The plan if finished, not blocking.
+    pub fn poll(&mut self) -> Option<LevelPlan> {
+        (&mut self.handle).now_or_never()
+    }
+}
+
+impl MagicLevel {
+    /// Create the GPU resources of the level planned, in the update.
+    pub fn from_plan(plan: LevelPlan, gpu: &WgpuData, pr: &mut PlaneRenderer, res: &ResourceManager) -> anyhow::Result<Self> {
+        match plan {
+            LevelPlan::Level0(plan) => Self::from_level0(*plan, gpu, pr, res),
+            LevelPlan::Loop(plan) => Self::from_loop(*plan, gpu, pr, res),
+            LevelPlan::Rooms(plan) => Self::from_rooms(*plan, gpu, pr, res),
+            LevelPlan::Generated(plan) => Self::from_generated(*plan, gpu, pr, res),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::state::real_view::build::LevelSpec;
+    use crate::state::real_view::level_rooms::RoomTextures;
+
+    #[test]
+    fn test_parse_level_spec() {
+        assert_eq!("level0".parse::<LevelSpec>().unwrap(), LevelSpec::Level0);
+        assert_eq!("loop".parse::<LevelSpec>().unwrap(), LevelSpec::Loop);
+        assert_eq!("rooms:4".parse::<LevelSpec>().unwrap(), LevelSpec::Rooms(4, RoomTextures::Seeded(0)));
+        assert_eq!("generated:233:5".parse::<LevelSpec>().unwrap(), LevelSpec::Generated(233, 5));
+        assert!("rooms".parse::<LevelSpec>().is_err());
+        assert!("rooms:many".parse::<LevelSpec>().is_err());
+    }
+}
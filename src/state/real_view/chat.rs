@@ -0,0 +1,178 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use egui::{Align2, Color32, Context, Frame, RichText, TextEdit};
+use winit::event::{ElementState, VirtualKeyCode, WindowEvent};
+
+use crate::engine::{GameState, StateData, StateEvent};
+
+/// The max lines kept in the log.
+const MAX_LINES: usize = 64;
+/// The lines shown when the input is closed.
+const RECENT_LINES: usize = 8;
+/// The seconds to show the line when the input is closed.
+const LINE_DURATION: Duration = Duration::from_secs(10);
+/// The max chars of the message to send.
+pub const MAX_CHAT_LEN: usize = 256;
+
+#[derive(Debug, Clone)]
+pub struct ChatLine {
+    /// The player id, none for me.
+    pub from: Option<u64>,
+    pub text: String,
+    pub received: Instant,
+}
+
+/// The chat messages shared between the overlay and the network.
+#[derive(Debug, Default)]
+pub struct ChatLog {
+    lines: VecDeque<ChatLine>,
+    /// The messages typed but not sent yet.
+    outgoing: Vec<String>,
+}
+
+pub type SharedChat = Arc<Mutex<ChatLog>>;
+
+#[allow(unused)]
+impl ChatLog {
+    /// Add the message received.
+    pub fn push(&mut self, from: Option<u64>, text: String) {
+        if self.lines.len() >= MAX_LINES {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(ChatLine { from, text, received: Instant::now() });
+    }
+
+    /// Show the message typed and queue it to send.
+    pub fn send(&mut self, text: String) {
+        self.push(None, text.clone());
+        if self.outgoing.len() < MAX_LINES {
+            self.outgoing.push(text);
+        }
+    }
+
+    /// Take the messages to send.
+    pub fn take_outgoing(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.outgoing)
+    }
+
+    pub fn lines(&self) -> impl DoubleEndedIterator<Item=&ChatLine> {
+        self.lines.iter()
+    }
+}
+
+/// Show the recent chat messages, Enter to open the input and send.
+#[derive(Default)]
+pub struct ChatOverlay {
+    log: SharedChat,
+    open: bool,
+    input: String,
+    /// Closed by the key this frame, keep the key from the game.
+    just_closed: bool,
+}
+
+#[allow(unused)]
+impl ChatOverlay {
+    pub fn log(&self) -> SharedChat {
+        self.log.clone()
+    }
+
+    /// Whether the keys are for the input.
+    pub fn is_typing(&self) -> bool {
+        self.open || self.just_closed
+    }
+
+    fn close(&mut self) {
+        self.open = false;
+        self.just_closed = true;
+        self.input.clear();
+    }
+
+    fn submit(&mut self) {
+        let text = self.input.trim().chars().take(MAX_CHAT_LEN).collect::<String>();
+        if !text.is_empty() {
+            self.log.lock().unwrap().send(text);
+        }
+        self.close();
+    }
+}
+
+impl GameState for ChatOverlay {
+    fn shadow_render(&mut self, _: &mut StateData, ctx: &Context) {
+        self.just_closed = false;
+        let log = self.log.lock().unwrap();
+        let lines = log.lines()
+            .rev()
+            .filter(|x| self.open || x.received.elapsed() < LINE_DURATION)
+            .take(if self.open { MAX_LINES } else { RECENT_LINES })
+            .collect::<Vec<_>>();
+        if lines.is_empty() && !self.open {
+            return;
+        }
+        egui::Area::new("chat")
+            .anchor(Align2::LEFT_BOTTOM, [8.0, -8.0])
+            .show(ctx, |ui| {
+                Frame::popup(ui.style())
+                    .fill(Color32::from_black_alpha(if self.open { 160 } else { 96 }))
+                    .show(ui, |ui| {
+                        ui.set_max_width(360.0);
+                        for x in lines.iter().rev() {
+                            let (name, color) = match x.from {
+                                Some(id) => (format!("{:04x}", id & 0xffff), Color32::LIGHT_BLUE),
+                                None => ("me".to_string(), Color32::LIGHT_GREEN),
+                            };
+                            ui.horizontal_wrapped(|ui| {
+                                ui.label(RichText::new(format!("{}:", name)).color(color));
+                                ui.label(RichText::new(&x.text).color(Color32::WHITE));
+                            });
+                        }
+                        if self.open {
+                            let input = ui.add(TextEdit::singleline(&mut self.input)
+                                .char_limit(MAX_CHAT_LEN)
+                                .hint_text("Enter to send, Esc to cancel"));
+                            input.request_focus();
+                        }
+                    });
+            });
+    }
+
+    fn on_event(&mut self, _: &mut StateData, e: StateEvent) {
+        if let StateEvent::Window(WindowEvent::KeyboardInput { input, .. }) = e {
+            if input.state != ElementState::Pressed {
+                return;
+            }
+            match input.virtual_keycode {
+                Some(VirtualKeyCode::Return | VirtualKeyCode::NumpadEnter) => {
+                    if self.open {
+                        self.submit();
+                    } else {
+                        self.open = true;
+                    }
+                }
+                Some(VirtualKeyCode::Escape) if self.open => self.close(),
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::state::real_view::chat::{ChatLog, MAX_LINES};
+
+    #[test]
+    fn test_chat_log() {
+        let mut log = ChatLog::default();
+        log.send("hello".into());
+        log.push(Some(233), "portal".into());
+        assert_eq!(log.take_outgoing(), vec!["hello".to_string()]);
+        assert!(log.take_outgoing().is_empty());
+
+        for i in 0..MAX_LINES {
+            log.push(Some(1), i.to_string());
+        }
+        assert_eq!(log.lines().count(), MAX_LINES);
+        assert_eq!(log.lines().next().unwrap().text, "0");
+    }
+}
@@ -0,0 +1,344 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+use nalgebra::Vector3;
+use once_cell::sync::OnceCell;
+
+use crate::engine::global::NET_RUNTIME;
+use crate::engine::network::message::TypedDataHandler;
+use crate::engine::network::peer::Peer;
+use crate::engine::network::server::Server;
+use crate::engine::physics::obj::KinematicObject;
+use crate::engine::physics::scheduler::PhysicsScheduler;
+use crate::engine::physics::state::RapierData;
+use crate::state::real_view::build::LevelSpec;
+use crate::state::real_view::chat::SharedChat;
+use crate::state::real_view::level_rooms::player_object;
+use crate::state::real_view::multiplayer::{Multiplayer, PlayerMessage, PlayerState, RemotePlayer, ReplicationHandler};
+
+/// The ticks of the dedicated server each second.
+const TICK_HZ: f32 = 64.0;
+/// The time between logging the players.
+const STATUS_INTERVAL: Duration = Duration::from_secs(60);
+/// The seconds between sending the states resolved.
+const SEND_INTERVAL: f32 = 0.05;
+/// The player farther than it from the position resolved is corrected.
+const CORRECT_DISTANCE: f32 = 0.5;
+/// The time between the corrections of the player, the correction sent is arriving.
+const CORRECT_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The body of the player moved by the inputs.
+struct PlayerBody {
+    object: KinematicObject,
+    world: usize,
+    target: [f32; 3],
+    /// The address of the player, the states resolved are sent to the others.
+    addr: SocketAddr,
+    corrected: Option<Instant>,
+}
+
+/// The bodies of the players moved by their inputs in the colliders of the level, stepped in the fixed time.
+///
+/// The portals are not in the colliders, the worlds crossed by the players are trusted.
+pub struct PlayerBodies {
+    p: RapierData,
+    scheduler: PhysicsScheduler,
+    bodies: HashMap<u64, PlayerBody>,
+}
+
+#[allow(unused)]
+impl PlayerBodies {
+    pub fn new(p: RapierData) -> Self {
+        Self {
+            p,
+            scheduler: PhysicsScheduler::new(TICK_HZ),
+            bodies: Default::default(),
+        }
+    }
+
+    /// Move the body of the player by the movement input, return the position resolved if the player should be corrected.
+    pub fn simulate(&mut self, addr: SocketAddr, state: &PlayerState, movement: &Vector3<f32>, dt: f32) -> Option<Vector3<f32>> {
+        let reported = Vector3::from(state.position);
+        let body = match self.bodies.get_mut(&state.id) {
+            Some(x) => x,
+            None => {
+                let object = player_object(&mut self.p, reported);
+                // the players pass through each other like in the clients
+                for x in [object.collider_handle, object.body_bounding] {
+                    self.p.collider_set[x].set_sensor(true);
+                }
+                self.bodies.insert(state.id, PlayerBody { object, world: state.world, target: state.target, addr, corrected: None });
+                return None;
+            }
+        };
+        body.target = state.target;
+        body.addr = addr;
+        if body.world != state.world {
+            body.world = state.world;
+            self.p.rigid_body_set[body.object.handle].set_translation(reported, true);
+            self.scheduler.teleported(&self.p, body.object.handle);
+            return None;
+        }
+        let ecm = self.p.move_obj(dt, &body.object, *movement);
+        let position = self.p.rigid_body_set[body.object.handle].translation() + ecm.translation;
+        self.p.rigid_body_set[body.object.handle].set_translation(position, true);
+        if (position - reported).norm() <= CORRECT_DISTANCE || body.corrected.is_some_and(|x| x.elapsed() < CORRECT_INTERVAL) {
+            return None;
+        }
+        body.corrected = Some(Instant::now());
+        Some(position)
+    }
+
+    /// Remove the bodies of the players left.
+    pub fn retain(&mut self, players: &HashMap<u64, RemotePlayer>) {
+        let left = self.bodies.keys()
+            .filter(|x| !players.contains_key(x))
+            .copied()
+            .collect::<Vec<_>>();
+        for id in left {
+            if let Some(body) = self.bodies.remove(&id) {
+                self.p.remove_body(body.object.handle);
+            }
+        }
+    }
+
+    /// Step the physics by the time passed, return the steps run.
+    pub fn step(&mut self, dt: f32) -> u32 {
+        let steps = self.scheduler.advance(dt);
+        for _ in 0..steps {
+            self.scheduler.before_step(&self.p);
+            self.p.step(self.scheduler.step_dt);
+        }
+        steps
+    }
+
+    pub fn position(&self, id: u64) -> Option<Vector3<f32>> {
+        self.bodies.get(&id)
+            .and_then(|x| self.p.rigid_body_set.get(x.object.handle))
+            .map(|x| *x.translation())
+    }
+
+    /// The states resolved with the addresses of the players.
+    pub fn states(&self) -> impl Iterator<Item=(SocketAddr, PlayerState)> + '_ {
+        self.bodies.iter().map(|(id, x)| (x.addr, PlayerState {
+            id: *id,
+            world: x.world,
+            position: (*self.p.rigid_body_set[x.object.handle].translation()).into(),
+            target: x.target,
+        }))
+    }
+
+    pub fn len(&self) -> usize {
+        self.bodies.len()
+    }
+}
+
+/// The input received from the player, simulated in the next tick.
+struct PlayerInput {
+    addr: SocketAddr,
+    state: PlayerState,
+    movement: Vector3<f32>,
+}
+
+/// Queue the inputs to simulate, the other messages are relayed.
+#[derive(Clone)]
+struct DedicatedHandler {
+    relaying: ReplicationHandler,
+    remote: Arc<Mutex<HashMap<u64, RemotePlayer>>>,
+    inputs: Arc<Mutex<Vec<PlayerInput>>>,
+}
+
+impl TypedDataHandler for DedicatedHandler {
+    type Data = PlayerMessage;
+
+    fn handle(&self, src: &Peer, msg: PlayerMessage) -> bool {
+        match msg {
+            // the states resolved are sent instead
+            PlayerMessage::Input { state, movement } => {
+                self.remote.lock().unwrap().insert(state.id, RemotePlayer { state, updated: Instant::now(), away: false });
+                self.inputs.lock().unwrap().push(PlayerInput { addr: src.addr, state, movement: Vector3::from(movement) });
+                true
+            }
+            msg => self.relaying.handle(src, msg),
+        }
+    }
+}
+
+/// The host without the window and the gpu, moving the players in the level and relaying the others.
+pub struct DedicatedServer {
+    server: Server,
+    remote: Arc<Mutex<HashMap<u64, RemotePlayer>>>,
+    inputs: Arc<Mutex<Vec<PlayerInput>>>,
+    bodies: PlayerBodies,
+    send_timer: f32,
+}
+
+#[allow(unused)]
+impl DedicatedServer {
+    /// Host at the address with the colliders of the level, the clients should play the same level.
+    pub fn host(listen: SocketAddr, level: &LevelSpec) -> anyhow::Result<Self> {
+        let remote: Arc<Mutex<HashMap<u64, RemotePlayer>>> = Default::default();
+        let inputs: Arc<Mutex<Vec<PlayerInput>>> = Default::default();
+        let relay: Arc<OnceCell<Server>> = Default::default();
+        // the chat is relayed only, the log kept is bounded
+        let chat = SharedChat::default();
+        let handler = DedicatedHandler {
+            relaying: ReplicationHandler::relaying(remote.clone(), chat, relay.clone()),
+            remote: remote.clone(),
+            inputs: inputs.clone(),
+        };
+        let bodies = PlayerBodies::new(level.plan().into_physics());
+        let server = NET_RUNTIME.block_on(Server::new_typed(listen, handler))?;
+        let _ = relay.set(server.clone());
+        info!(target: "server", "Dedicated server at {:?} in {:?}", listen, level);
+        Ok(Self {
+            server,
+            remote,
+            inputs,
+            bodies,
+            send_timer: 0.0,
+        })
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.server.running.load(Ordering::Acquire)
+    }
+
+    pub fn player_count(&self) -> usize {
+        self.bodies.len()
+    }
+
+    fn send(&self, addr: Option<SocketAddr>, msg: &PlayerMessage) {
+        let data = match msg.encode() {
+            Ok(data) => data,
+            Err(e) => {
+                warn!(target: "server", "Encode the message failed for {:?}", e);
+                return;
+            }
+        };
+        match addr {
+            Some(addr) => {
+                if let Some(peer) = self.server.peers.get(&addr) {
+                    let _ = peer.send_on(msg.channel(), data);
+                }
+            }
+            None => {
+                self.server.broadcast(msg.channel(), &data, None);
+            }
+        }
+    }
+
+    /// Remove the players timed out, move the others by their inputs and send the states resolved.
+    pub fn tick(&mut self, dt: f32) {
+        let expired = {
+            let mut remote = self.remote.lock().unwrap();
+            let expired = remote.iter()
+                .filter(|x| x.1.is_timeout())
+                .map(|x| *x.0)
+                .collect::<Vec<_>>();
+            for id in &expired {
+                remote.remove(id);
+            }
+            self.bodies.retain(&remote);
+            expired
+        };
+        for id in expired {
+            info!(target: "server", "The player {} timed out", id);
+            self.send(None, &PlayerMessage::Leave(id));
+        }
+
+        let inputs = std::mem::take(&mut *self.inputs.lock().unwrap());
+        for x in inputs {
+            if let Some(position) = self.bodies.simulate(x.addr, &x.state, &x.movement, dt) {
+                info!(target: "server", "Corrected the player {} to {:?}", x.state.id, position);
+                let state = PlayerState { position: position.into(), ..x.state };
+                self.send(Some(x.addr), &PlayerMessage::Correct(state));
+            }
+        }
+        self.bodies.step(dt);
+
+        self.send_timer += dt;
+        if self.send_timer >= SEND_INTERVAL {
+            self.send_timer = 0.0;
+            for (addr, state) in self.bodies.states() {
+                let msg = PlayerMessage::State(state);
+                match msg.encode() {
+                    Ok(data) => {
+                        self.server.broadcast(msg.channel(), &data, Some(addr));
+                    }
+                    Err(e) => warn!(target: "server", "Encode the state failed for {:?}", e),
+                }
+            }
+        }
+    }
+}
+
+/// Host at the address in the level and tick until the server stopped.
+pub fn run_dedicated(listen: &str, level: &str) -> anyhow::Result<()> {
+    let addr = Multiplayer::resolve(listen)?;
+    let mut server = DedicatedServer::host(addr, &level.parse::<LevelSpec>()?)?;
+    let tick = Duration::from_secs_f32(1.0 / TICK_HZ);
+    let mut last = Instant::now();
+    let mut status = last;
+    while server.is_running() {
+        std::thread::sleep(tick.saturating_sub(last.elapsed()));
+        let now = Instant::now();
+        server.tick((now - last).as_secs_f32());
+        last = now;
+        if now - status >= STATUS_INTERVAL {
+            status = now;
+            info!(target: "server", "{} players, {} peers", server.player_count(), server.server.peers.len());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::net::SocketAddr;
+    use std::time::Instant;
+
+    use nalgebra::vector;
+    use rapier3d::prelude::ColliderBuilder;
+
+    use crate::engine::physics::state::RapierData;
+    use crate::state::real_view::dedicated::PlayerBodies;
+    use crate::state::real_view::multiplayer::{PlayerState, RemotePlayer};
+
+    #[test]
+    fn test_player_bodies() {
+        let mut p = RapierData::new();
+        // the wall at x = 1
+        p.collider_set.insert(ColliderBuilder::cuboid(0.1, 5.0, 5.0).translation(vector![1.1, 0.0, 1.0]).build());
+        let mut bodies = PlayerBodies::new(p);
+        let addr: SocketAddr = "127.0.0.1:23333".parse().unwrap();
+        let state = |id, world, position: [f32; 3]| PlayerState { id, world, position, target: [1.0, 0.0, 0.0] };
+
+        assert_eq!(bodies.simulate(addr, &state(1, 0, [0.0, 0.0, 1.0]), &vector![0.0, 0.0, 0.0], 0.05), None);
+        assert_eq!(bodies.simulate(addr, &state(2, 0, [0.0, 3.0, 1.0]), &vector![0.0, 0.0, 0.0], 0.05), None);
+        assert!(bodies.step(0.1) > 0);
+
+        // walked into the wall
+        let corrected = bodies.simulate(addr, &state(1, 0, [2.0, 0.0, 1.0]), &vector![2.0, 0.0, 0.0], 0.05).unwrap();
+        assert!(corrected.x < 1.0);
+        assert_eq!(bodies.position(1), Some(corrected));
+        // walked along the wall
+        assert_eq!(bodies.simulate(addr, &state(2, 0, [0.0, 3.5, 1.0]), &vector![0.0, 0.5, 0.0], 0.05), None);
+        assert!((bodies.position(2).unwrap() - vector![0.0, 3.5, 1.0]).norm() < 1e-3);
+        // crossed the portal to the other world
+        assert_eq!(bodies.simulate(addr, &state(2, 1, [5.0, 0.0, 1.0]), &vector![0.0, 0.0, 0.0], 0.05), None);
+        assert_eq!(bodies.position(2), Some(vector![5.0, 0.0, 1.0]));
+        assert_eq!(bodies.states().find(|x| x.1.id == 2).unwrap().1.world, 1);
+
+        let mut players = HashMap::new();
+        players.insert(1, RemotePlayer { state: state(1, 0, [0.0; 3]), updated: Instant::now(), away: false });
+        bodies.retain(&players);
+        assert_eq!(bodies.len(), 1);
+        assert_eq!(bodies.position(2), None);
+    }
+}
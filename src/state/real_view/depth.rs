@@ -0,0 +1,134 @@
+use crate::engine::config::DEFAULT_PORTAL_DEPTH;
+
+/// The depth rendered in the background, only the portals seen directly.
+const BACKGROUND_DEPTH: usize = 1;
+/// The smallest resolution scale of the portal views.
+pub const MIN_VIEW_SCALE: f32 = 0.25;
+
+/// The frames over the budget in a row to render one less depth.
+const SLOW_FRAMES: u32 = 10;
+/// The frames under the budget with the margin in a row to render one more depth.
+const FAST_FRAMES: u32 = 120;
+/// The frame is fast if under the budget multiplied by the margin.
+const FAST_MARGIN: f32 = 0.75;
+
+/// The portal recursion depth to render, reduced if the frames are slow and restored when fast.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct AdaptiveDepth {
+    depth: usize,
+    slow: u32,
+    fast: u32,
+    /// Render the background depth, the depth adapted is kept for the focus back.
+    background: bool,
+}
+
+impl Default for AdaptiveDepth {
+    fn default() -> Self {
+        Self {
+            depth: DEFAULT_PORTAL_DEPTH,
+            slow: 0,
+            fast: 0,
+            background: false,
+        }
+    }
+}
+
+#[allow(unused)]
+impl AdaptiveDepth {
+    /// The depth of the portals rendered, the deeper are the fallback color.
+    pub fn depth(&self) -> usize {
+        if self.background { BACKGROUND_DEPTH } else { self.depth }
+    }
+
+    pub fn set_background(&mut self, background: bool) {
+        self.background = background;
+    }
+
+    /// Adapt by the seconds of the last frame within 1..=max, the depth is the max if no budget.
+    pub fn update(&mut self, frame: f32, max: usize, budget: Option<f32>) -> usize {
+        let max = max.max(1);
+        let budget = if let Some(x) = budget { x } else {
+            *self = Self { depth: max, ..Default::default() };
+            return self.depth;
+        };
+        self.depth = self.depth.clamp(1, max);
+        if frame > budget {
+            self.fast = 0;
+            self.slow += 1;
+            if self.slow >= SLOW_FRAMES {
+                self.slow = 0;
+                self.depth = (self.depth - 1).max(1);
+            }
+        } else if frame < budget * FAST_MARGIN {
+            self.slow = 0;
+            self.fast += 1;
+            if self.fast >= FAST_FRAMES {
+                self.fast = 0;
+                self.depth = (self.depth + 1).min(max);
+            }
+        } else {
+            self.slow = 0;
+            self.fast = 0;
+        }
+        self.depth
+    }
+}
+
+/// The resolution scale of the portal view at the depth, multiplied by the falloff for each depth deeper.
+pub fn view_scale(falloff: f32, depth: usize) -> f32 {
+    falloff.clamp(MIN_VIEW_SCALE, 1.0).powi(depth as i32).max(MIN_VIEW_SCALE)
+}
+
+/// The size of the portal view at the depth for the render size.
+pub fn view_size((width, height): (u32, u32), falloff: f32, depth: usize) -> (u32, u32) {
+    let scale = view_scale(falloff, depth);
+    (((width as f32 * scale) as u32).max(1), ((height as f32 * scale) as u32).max(1))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::state::real_view::depth::{AdaptiveDepth, view_size};
+
+    #[test]
+    fn test_adaptive_depth() {
+        let mut depth = AdaptiveDepth::default();
+        assert_eq!(depth.update(0.1, 5, None), 5);
+        for _ in 0..9 {
+            assert_eq!(depth.update(0.03, 5, Some(0.02)), 5);
+        }
+        assert_eq!(depth.update(0.03, 5, Some(0.02)), 4);
+        for _ in 0..100 {
+            depth.update(0.03, 5, Some(0.02));
+        }
+        assert_eq!(depth.depth(), 1);
+        // near the budget keeps the depth
+        for _ in 0..200 {
+            depth.update(0.018, 5, Some(0.02));
+        }
+        assert_eq!(depth.depth(), 1);
+        for _ in 0..120 {
+            depth.update(0.01, 5, Some(0.02));
+        }
+        assert_eq!(depth.depth(), 2);
+        // the max lowered by the setting
+        assert_eq!(depth.update(0.01, 1, Some(0.02)), 1);
+        assert_eq!(depth.update(0.01, 0, None), 1);
+
+        assert_eq!(depth.update(0.01, 5, None), 5);
+        depth.set_background(true);
+        assert_eq!(depth.depth(), 1);
+        depth.set_background(false);
+        assert_eq!(depth.depth(), 5);
+    }
+
+    #[test]
+    fn test_view_size() {
+        assert_eq!(view_size((1600, 900), 0.5, 0), (1600, 900));
+        assert_eq!(view_size((1600, 900), 0.5, 1), (800, 450));
+        // not smaller than the min scale
+        assert_eq!(view_size((1600, 900), 0.5, 5), (400, 225));
+        assert_eq!(view_size((1600, 900), 0.0, 1), (400, 225));
+        assert_eq!(view_size((1600, 900), 1.0, 9), (1600, 900));
+        assert_eq!(view_size((1, 1), 0.5, 3), (1, 1));
+    }
+}
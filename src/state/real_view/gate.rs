@@ -0,0 +1,95 @@
+use std::collections::HashSet;
+use std::fmt::{Debug, Formatter};
+
+use serde::{Deserialize, Serialize};
+
+/// The distance bounced back from the portal not passable, by my scale.
+pub const BOUNCE_DISTANCE: f32 = 0.25;
+
+/// Who goes through the portal, the view is rendered whatever the gate.
+#[allow(unused)]
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum PortalGate {
+    #[default]
+    Open,
+    /// Entered only from the connecting portal, bounced back from this one.
+    OneWay,
+    /// Passable only with the key.
+    Locked(String),
+}
+
+impl PortalGate {
+    pub fn allows(&self, keys: &PortalKeys) -> bool {
+        match self {
+            PortalGate::Open => true,
+            PortalGate::OneWay => false,
+            PortalGate::Locked(key) => keys.has(key),
+        }
+    }
+}
+
+/// The keys got in the level, unlocking the portals locked by them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PortalKeys {
+    keys: HashSet<String>,
+}
+
+#[allow(unused)]
+impl PortalKeys {
+    /// Give the key, return true if not got before.
+    pub fn give(&mut self, key: impl Into<String>) -> bool {
+        self.keys.insert(key.into())
+    }
+
+    pub fn take(&mut self, key: &str) -> bool {
+        self.keys.remove(key)
+    }
+
+    pub fn has(&self, key: &str) -> bool {
+        self.keys.contains(key)
+    }
+}
+
+/// Called after going through the portal, able to give or take the keys.
+pub type TraverseCallback = Box<dyn FnMut(&mut PortalKeys) + Send>;
+
+/// The callbacks of the portal in the order added.
+#[derive(Default)]
+pub struct TraverseCallbacks(pub Vec<TraverseCallback>);
+
+impl Debug for TraverseCallbacks {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "TraverseCallbacks({})", self.0.len())
+    }
+}
+
+impl TraverseCallbacks {
+    pub fn call(&mut self, keys: &mut PortalKeys) {
+        for callback in self.0.iter_mut() {
+            callback(keys);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::state::real_view::gate::{PortalGate, PortalKeys, TraverseCallbacks};
+
+    #[test]
+    fn test_portal_gate() {
+        let mut keys = PortalKeys::default();
+        let locked = PortalGate::Locked("red".into());
+        assert!(PortalGate::Open.allows(&keys));
+        assert!(!PortalGate::OneWay.allows(&keys));
+        assert!(!locked.allows(&keys));
+
+        let mut callbacks = TraverseCallbacks::default();
+        callbacks.0.push(Box::new(|keys| { keys.give("red"); }));
+        callbacks.call(&mut keys);
+        assert!(locked.allows(&keys));
+        assert!(!keys.give("red"));
+        assert!(keys.take("red"));
+        assert!(!locked.allows(&keys));
+        assert!(!PortalGate::OneWay.allows(&keys));
+    }
+}
@@ -0,0 +1,182 @@
+use nalgebra::{Isometry3, Point3, Similarity3, Vector3};
+use rapier3d::parry::bounding_volume::BoundingVolume;
+use rapier3d::prelude::{ActiveCollisionTypes, Collider, ColliderBuilder, ColliderHandle, QueryFilter, RigidBodyBuilder, RigidBodyHandle, SharedShape};
+
+use crate::engine::physics::event::ColliderTag;
+use crate::engine::physics::obj::KinematicObject;
+use crate::engine::physics::opening::Opening;
+use crate::engine::physics::state::RapierData;
+
+/// The margin around the portal sensor to find the walls behind it.
+const WALL_MARGIN: f32 = 0.05;
+
+/// The portal straddled by my body.
+#[derive(Debug, Copy, Clone)]
+pub struct Straddled {
+    /// (world, portal index)
+    pub portal: (usize, usize),
+    /// Map my position to behind the connecting portal.
+    pub similarity: Similarity3<f32>,
+    /// The sensors of the portal and the connecting one.
+    pub sensors: [ColliderHandle; 2],
+}
+
+#[derive(Debug)]
+struct Ghost {
+    portal: (usize, usize),
+    similarity: Similarity3<f32>,
+    body: RigidBodyHandle,
+    collider: ColliderHandle,
+}
+
+/// The ghost of my collider behind the portal straddled, so the part through the portal collides there.
+#[derive(Debug, Default)]
+pub struct PortalGhost {
+    ghost: Option<Ghost>,
+}
+
+/// The fixed walls touching the sensor, behind the portal.
+fn walls_behind(p: &RapierData, sensor: ColliderHandle) -> Vec<ColliderHandle> {
+    let collider = if let Some(x) = p.collider_set.get(sensor) { x } else {
+        return vec![];
+    };
+    let shape = match collider.shape().as_cuboid() {
+        Some(x) => SharedShape::cuboid(x.half_extents.x + WALL_MARGIN, x.half_extents.y + WALL_MARGIN, x.half_extents.z + WALL_MARGIN),
+        None => return vec![],
+    };
+    let mut walls = vec![];
+    p.query_pipeline.intersections_with_shape(&p.rigid_body_set, &p.collider_set, collider.position(), &*shape,
+                                              QueryFilter::only_fixed().exclude_sensors(), |x| {
+            if ColliderTag::of(&p.collider_set[x]) == ColliderTag::Wall {
+                walls.push(x);
+            }
+            true
+        });
+    walls
+}
+
+#[allow(unused)]
+impl PortalGhost {
+    /// Create the ghost for the portal straddled and open the walls behind the portals, or remove it if none.
+    pub fn update(&mut self, p: &mut RapierData, me: &KinematicObject, straddled: Option<Straddled>) {
+        if self.ghost.as_ref().map(|x| x.portal) == straddled.map(|x| x.portal) {
+            return;
+        }
+        self.remove(p);
+        let straddled = if let Some(x) = straddled { x } else {
+            return;
+        };
+        let openings = straddled.sensors.iter()
+            .map(|x| Opening { sensor: *x, walls: walls_behind(p, *x) })
+            .collect();
+        p.openings.set(&mut p.collider_set, openings);
+        let scale = straddled.similarity.scaling();
+        let shape = p.collider_set[me.collider_handle].shape().as_cuboid()
+            .map(|x| SharedShape::cuboid(x.half_extents.x * scale, x.half_extents.y * scale, x.half_extents.z * scale))
+            .unwrap_or_else(|| SharedShape::ball(0.01 * scale));
+        let position = straddled.similarity * Point3::from(*p.rigid_body_set[me.handle].translation());
+        let body = p.rigid_body_set.insert(RigidBodyBuilder::kinematic_position_based()
+            .translation(position.coords)
+            .build());
+        let collider = p.collider_set.insert_with_parent(ColliderBuilder::new(shape)
+                                                             .user_data(ColliderTag::Player.into())
+                                                             .active_collision_types(ActiveCollisionTypes::default()),
+                                                         body, &mut p.rigid_body_set);
+        self.ghost = Some(Ghost { portal: straddled.portal, similarity: straddled.similarity, body, collider });
+    }
+
+    /// Limit my movement in the step by the ghost colliding behind the portal, and move the ghost with me.
+    pub fn limit(&self, p: &mut RapierData, me: &KinematicObject, dt: f32) {
+        let ghost = if let Some(x) = self.ghost.as_ref() { x } else {
+            return;
+        };
+        let from = *p.rigid_body_set[me.handle].translation();
+        let desired = p.rigid_body_set[me.handle].next_position().translation.vector - from;
+        let rotation = ghost.similarity.isometry.rotation;
+        let scale = ghost.similarity.scaling();
+        let position = Isometry3::from(ghost.similarity * Point3::from(from));
+        let shape = p.collider_set[ghost.collider].shape();
+        let swept = shape.compute_aabb(&position)
+            .merged(&shape.compute_aabb(&Isometry3::from(ghost.similarity * Point3::from(from + desired))));
+        let passes = |h: ColliderHandle, _: &Collider| !p.openings.passes(&p.collider_set, h, &swept);
+        let filter = QueryFilter::default()
+            .exclude_rigid_body(me.handle)
+            .exclude_rigid_body(ghost.body)
+            .exclude_sensors()
+            .predicate(&passes);
+        let ecm = me.controller.move_shape(dt, &p.rigid_body_set, &p.collider_set, &p.query_pipeline,
+                                           shape, &position, rotation * desired * scale, filter, |_| {});
+        let back: Vector3<f32> = rotation.inverse() * ecm.translation / scale;
+        let moved = if back.norm() < desired.norm() { back } else { desired };
+        p.rigid_body_set[me.handle].set_next_kinematic_translation(from + moved);
+        p.rigid_body_set[ghost.body].set_next_kinematic_translation((ghost.similarity * Point3::from(from + moved)).coords);
+    }
+
+    /// Remove the ghost and close the walls.
+    pub fn remove(&mut self, p: &mut RapierData) {
+        if let Some(ghost) = self.ghost.take() {
+            p.openings.clear(&mut p.collider_set);
+            p.remove_body(ghost.body);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use nalgebra::{Similarity3, vector, Vector3};
+    use rapier3d::prelude::{ActiveCollisionTypes, ColliderBuilder, RigidBodyBuilder};
+
+    use crate::engine::physics::event::ColliderTag;
+    use crate::engine::physics::obj::KinematicObject;
+    use crate::engine::physics::state::RapierData;
+    use crate::state::real_view::ghost::{PortalGhost, Straddled};
+
+    #[test]
+    fn test_ghost_walls() {
+        let mut p = RapierData::new();
+        let wall = |x: f32| ColliderBuilder::cuboid(0.0, 5.0, 5.0)
+            .translation(vector![x, 0.0, 0.0])
+            .user_data(ColliderTag::Wall.into())
+            .build();
+        let sensor = |x: f32| ColliderBuilder::cuboid(0.0, 1.0, 1.0)
+            .translation(vector![x, 0.0, 0.0])
+            .sensor(true)
+            .build();
+        let walls = [p.collider_set.insert(wall(0.0)), p.collider_set.insert(wall(20.0))];
+        let far = p.collider_set.insert(wall(10.0));
+        let sensors = [p.collider_set.insert(sensor(0.0)), p.collider_set.insert(sensor(20.0))];
+        let me = RigidBodyBuilder::kinematic_position_based().translation(vector![0.05, 0.0, 0.0]).build();
+        let me_col = ColliderBuilder::cuboid(0.01, 0.01, 1.0)
+            .active_collision_types(ActiveCollisionTypes::default() | ActiveCollisionTypes::KINEMATIC_FIXED)
+            .build();
+        let me = KinematicObject::new(&mut p, me, me_col);
+        p.step(1.0 / 64.0);
+
+        let mut ghost = PortalGhost::default();
+        let similarity = Similarity3::new(vector![20.0, 0.0, 0.0], Vector3::zeros(), 1.0);
+        ghost.update(&mut p, &me, Some(Straddled { portal: (0, 0), similarity, sensors }));
+        assert_eq!(p.openings.0.iter().map(|x| x.walls.clone()).collect::<Vec<_>>(), [[walls[0]], [walls[1]]]);
+        assert!(!p.openings.0.iter().any(|x| x.walls.contains(&far)));
+        assert!(p.collider_set.iter().all(|(_, x)| x.is_enabled()));
+        assert_eq!(p.rigid_body_set.len(), 2);
+
+        // through the wall in the opening
+        let moved = p.move_obj(1.0 / 64.0, &me, vector![-0.1, 0.0, 0.0]);
+        assert!(moved.translation.x < -0.09, "{:?}", moved.translation);
+
+        // moved by the ghost limited
+        p.rigid_body_set[me.handle].set_next_kinematic_translation(vector![0.1, 0.0, 0.0]);
+        ghost.limit(&mut p, &me, 1.0 / 64.0);
+        assert!(p.rigid_body_set[me.handle].next_position().translation.x > 0.05);
+
+        // stopped by the wall out of the opening
+        p.rigid_body_set[me.handle].set_translation(vector![0.05, 3.0, 0.0], true);
+        p.step(1.0 / 64.0);
+        let moved = p.move_obj(1.0 / 64.0, &me, vector![-0.1, 0.0, 0.0]);
+        assert!(moved.translation.x > -0.05, "{:?}", moved.translation);
+
+        ghost.update(&mut p, &me, None);
+        assert!(p.openings.0.is_empty());
+        assert_eq!(p.rigid_body_set.len(), 1);
+    }
+}
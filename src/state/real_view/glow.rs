@@ -0,0 +1,44 @@
+use std::array::from_ref;
+
+use wgpu::{Color, CommandEncoder, LoadOp};
+
+use crate::engine::render::post::GLOW_TARGET;
+use crate::engine::render_ext::CommandEncoderExt;
+use crate::engine::renderer3d::renderer3d::{PlaneRenderer, Planes};
+use crate::engine::WgpuData;
+use crate::state::real_view::level::{Level, MagicLevel};
+use crate::state::real_view::renderer::portal::PortalRenderer;
+
+#[allow(unused)]
+impl MagicLevel {
+    /// Render the rims of the portals and the emissive of the materials in my world to the glow target.
+    ///
+    /// Tested by the depth of the scene rendered, the glow seen through the portals is not rendered.
+    pub(crate) fn render_glow(&self, ce: &mut CommandEncoder, gpu: &WgpuData, pr: &PlaneRenderer, portal_renderer: &PortalRenderer) {
+        let target = if let Some(x) = gpu.views.get_extra(GLOW_TARGET) { x } else {
+            return;
+        };
+        let level = &self.levels[self.me_world];
+        let rims = level.portals.iter()
+            .filter(|x| !x.mirror && !x.opening.is_closed())
+            .map(|x| Level::portal_quad(&x.this, x.r * x.opening.current(), 1.0))
+            .collect::<Vec<_>>();
+        let rims = (!rims.is_empty()).then(|| Planes { objs: rims, texture_bind: None }.to_static(&gpu.device));
+
+        let mut rp = ce.begin_with_depth(&target.view, LoadOp::Clear(Color::BLACK),
+                                         &gpu.views.get_depth_view().view, LoadOp::Load);
+        pr.bind(&mut rp);
+        if let Some(rims) = rims.as_ref() {
+            rp.set_pipeline(&portal_renderer.rim_rp);
+            pr.render_static(&mut rp, gpu, from_ref(rims));
+        }
+        if !level.pbr.is_empty() {
+            rp.set_pipeline(&pr.glow_rp);
+            pr.render_static(&mut rp, gpu, &level.pbr);
+        }
+        if !level.models.is_empty() {
+            rp.set_pipeline(&pr.model_glow_rp);
+            pr.render_models(&mut rp, &level.models);
+        }
+    }
+}
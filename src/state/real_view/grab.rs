@@ -0,0 +1,260 @@
+use log::debug;
+use nalgebra::{Isometry3, Point3, Vector3};
+use rapier3d::prelude::{Ray, RigidBodyHandle, SharedShape};
+use specs::{Join, World, WorldExt, WriteStorage};
+
+use crate::engine::ecs::{Collider, Mesh, MeshShape, PortalTraveler};
+use crate::engine::physics::event::crossed_plane;
+use crate::engine::render::camera::Camera;
+use crate::state::real_view::level::{MagicLevel, PortalPos};
+
+/// The max distance to grab the prop, by my scale.
+pub const GRAB_DISTANCE: f32 = 3.0;
+/// The distance held in front of the camera, by my scale.
+const HOLD_DISTANCE: f32 = 1.5;
+/// The part of the distance to the hold point moved per second.
+const HOLD_GAIN: f32 = 12.0;
+/// The max speed of the prop held, by its scale.
+const MAX_HOLD_SPEED: f32 = 16.0;
+/// The prop farther than it from the hold point is dropped, like stuck behind the wall.
+const DROP_DISTANCE: f32 = 3.0;
+
+/// The dynamic body carried in front of me.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Held {
+    pub body: RigidBodyHandle,
+    /// The portal in my world the body is behind, none if in my world.
+    pub through: Option<(usize, usize)>,
+}
+
+#[derive(Debug, Default)]
+pub struct Grab {
+    pub held: Option<Held>,
+}
+
+impl Grab {
+    /// Map the portal held through after some removed, drop the body if its portal mapped to none.
+    pub fn remap(&mut self, f: impl Fn((usize, usize)) -> Option<(usize, usize)>) {
+        self.held = self.held.and_then(|held| match held.through {
+            Some(x) => f(x).map(|p| Held { through: Some(p), ..held }),
+            None => Some(held),
+        });
+    }
+}
+
+/// The velocity to move by the offset to the hold point, limited by the scale.
+pub fn servo(offset: &Vector3<f32>, scale: f32) -> Vector3<f32> {
+    let velocity = offset * HOLD_GAIN;
+    let max = MAX_HOLD_SPEED * scale;
+    if velocity.norm() > max {
+        velocity.normalize() * max
+    } else {
+        velocity
+    }
+}
+
+/// The position is in the quad of the portal, along its plane.
+pub fn in_portal(this: &PortalPos, r: f32, pos: &Vector3<f32>) -> bool {
+    let d = pos - this.pos;
+    let right = this.up.cross(&this.out_normal);
+    d.dot(&this.up).abs() <= r && d.dot(&right).abs() <= r
+}
+
+#[allow(unused)]
+impl MagicLevel {
+    /// Grab the dynamic body aimed at in my world, or drop the one held.
+    ///
+    /// Return true if holding after.
+    pub fn toggle_grab(&mut self, camera: &Camera) -> bool {
+        if self.grab.held.take().is_some() {
+            return false;
+        }
+        let dir = if let Some(x) = camera.target.try_normalize(f32::EPSILON) { x } else {
+            return false;
+        };
+        let distance = GRAB_DISTANCE * self.transition.current();
+        let hit = self.raycast_through_portals(self.me_world, Ray::new(camera.eye, dir), distance, Some(self.me.handle))
+            .filter(|x| x.portals == 0);
+        let body = hit.and_then(|x| self.p.collider_set.get(x.hit.collider)?.parent())
+            .filter(|x| self.p.rigid_body_set.get(*x).is_some_and(|b| b.is_dynamic()));
+        self.grab.held = body.map(|body| Held { body, through: None });
+        self.grab.held.is_some()
+    }
+
+    /// The world of the body held.
+    fn held_world(&self, held: &Held) -> usize {
+        held.through.map_or(self.me_world, |(world, idx)| self.levels[world].portals[idx].connecting.0)
+    }
+
+    /// Drive the body held to the point in front of the camera, mapped through the portal if behind it.
+    pub(crate) fn hold(&mut self, camera: &Camera) {
+        let held = if let Some(x) = self.grab.held { x } else {
+            return;
+        };
+        let dir = camera.target.try_normalize(f32::EPSILON).unwrap_or_else(Vector3::x);
+        let mut scale = self.transition.current();
+        let mut target = camera.eye + dir * HOLD_DISTANCE * scale;
+        if let Some((world, idx)) = held.through {
+            let portal = &self.levels[world].portals[idx];
+            let connecting = &self.levels[portal.connecting.0].portals[portal.connecting.1].this;
+            target = portal.similarity(connecting) * target;
+            scale *= portal.scale;
+        }
+        let body = if let Some(x) = self.p.rigid_body_set.get_mut(held.body) { x } else {
+            self.grab.held = None;
+            return;
+        };
+        let offset = target.coords - body.translation();
+        if offset.norm() > DROP_DISTANCE * scale {
+            debug!(target: "grab", "Dropped {:?} away from the hold point by {}", held.body, offset.norm());
+            self.grab.held = None;
+            return;
+        }
+        body.set_linvel(servo(&offset, scale), true);
+        body.set_angvel(Vector3::zeros(), true);
+    }
+
+    /// The position of the body held before the step.
+    pub(crate) fn held_position(&self) -> Option<Vector3<f32>> {
+        self.grab.held.and_then(|x| self.p.rigid_body_set.get(x.body)).map(|x| *x.translation())
+    }
+
+    /// Move the body held through the portal its center crossed in the step, dropped if behind two portals.
+    pub(crate) fn traverse_held(&mut self, before: &Vector3<f32>, world: &World, travelers: &mut WriteStorage<PortalTraveler>) {
+        let held = if let Some(x) = self.grab.held { x } else {
+            return;
+        };
+        let now = if let Some(x) = self.p.rigid_body_set.get(held.body) { *x.translation() } else {
+            return;
+        };
+        let held_world = self.held_world(&held);
+        let crossed = self.levels[held_world].portals.iter()
+            .position(|x| !x.mirror && x.opening.is_open()
+                && crossed_plane(before, &now, &x.this.pos, &x.this.out_normal)
+                && in_portal(&x.this, x.r, &now));
+        let idx = if let Some(x) = crossed { x } else {
+            return;
+        };
+        let portal = (held_world, idx);
+        self.send_through(held.body, portal, world, travelers);
+        self.grab.held = match held.through {
+            None => Some(Held { through: Some(portal), ..held }),
+            Some(x) if self.levels[x.0].portals[x.1].connecting == portal => Some(Held { through: None, ..held }),
+            Some(_) => None,
+        };
+    }
+
+    /// Bring the body held with me through the portal I went through.
+    pub(crate) fn hand_off(&mut self, portal: (usize, usize), world: &World, travelers: &mut WriteStorage<PortalTraveler>) {
+        let held = if let Some(x) = self.grab.held { x } else {
+            return;
+        };
+        self.grab.held = match held.through {
+            // already behind it
+            Some(x) if x == portal => Some(Held { through: None, ..held }),
+            None => {
+                self.send_through(held.body, portal, world, travelers);
+                Some(held)
+            }
+            Some(_) => None,
+        };
+    }
+
+    /// Move the body through the portal and scale it, with its mesh and traveler.
+    fn send_through(&mut self, body: RigidBodyHandle, (world, idx): (usize, usize), ecs: &World, travelers: &mut WriteStorage<PortalTraveler>) {
+        let portal = &self.levels[world].portals[idx];
+        let connecting = &self.levels[portal.connecting.0].portals[portal.connecting.1].this;
+        let similarity = portal.similarity(connecting);
+        let (scale, to_world) = (portal.scale, connecting.world);
+        let colliders = if let Some(b) = self.p.rigid_body_set.get_mut(body) {
+            let rotation = similarity.isometry.rotation;
+            let pos = similarity * Point3::from(*b.translation());
+            b.set_position(Isometry3::from_parts(pos.coords.into(), rotation * b.rotation()), true);
+            b.set_linvel(rotation * b.linvel() * scale, true);
+            b.set_angvel(rotation * b.angvel(), true);
+            b.colliders().to_vec()
+        } else {
+            return;
+        };
+        for c in colliders {
+            let c = if let Some(x) = self.p.collider_set.get_mut(c) { x } else {
+                continue;
+            };
+            let shape = if let Some(x) = c.shape().as_cuboid() {
+                Some(SharedShape::cuboid(x.half_extents.x * scale, x.half_extents.y * scale, x.half_extents.z * scale))
+            } else {
+                c.shape().as_ball().map(|x| SharedShape::ball(x.radius * scale))
+            };
+            if let Some(shape) = shape {
+                c.set_shape(shape);
+            }
+        }
+        self.scheduler.teleported(&self.p, body);
+        debug!(target: "grab", "{:?} through the portal {:?} to world {}", body, (world, idx), to_world);
+
+        let (entities, bodies, mut meshes) = (ecs.entities(), ecs.read_storage::<Collider>(), ecs.write_storage::<Mesh>());
+        let entity = (&entities, &bodies).join().find(|(_, x)| x.body == Some(body)).map(|(e, _)| e);
+        if let Some(entity) = entity {
+            if let Some(mesh) = meshes.get_mut(entity) {
+                mesh.world = to_world;
+                match &mut mesh.shape {
+                    MeshShape::Cube { half } => *half *= scale,
+                }
+            }
+            if let Some(traveler) = travelers.get_mut(entity) {
+                traveler.world = to_world;
+                traveler.scale *= scale;
+                traveler.traversed += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use nalgebra::{vector, Vector3};
+    use rapier3d::prelude::RigidBodyHandle;
+
+    use crate::engine::physics::event::shifted_portal;
+    use crate::state::real_view::grab::{Grab, Held, in_portal, MAX_HOLD_SPEED, servo};
+    use crate::state::real_view::level::PortalPos;
+
+    #[test]
+    fn test_grab_servo() {
+        let v = servo(&vector![0.1, 0.0, 0.0], 1.0);
+        assert!(v.x > 0.0 && v.y == 0.0);
+        assert!((servo(&vector![100.0, 0.0, 0.0], 1.0).norm() - MAX_HOLD_SPEED).abs() < 1e-4);
+        assert!((servo(&vector![0.0, 100.0, 0.0], 2.0).norm() - MAX_HOLD_SPEED * 2.0).abs() < 1e-4);
+        assert_eq!(servo(&Vector3::zeros(), 1.0), Vector3::zeros());
+
+        let this = PortalPos {
+            world: 0,
+            pos: vector![1.0, 0.0, 1.0],
+            out_normal: Vector3::x(),
+            up: Vector3::z(),
+            width: 1.0,
+        };
+        assert!(in_portal(&this, 1.0, &vector![1.0, 0.5, 1.5]));
+        assert!(!in_portal(&this, 1.0, &vector![1.0, 1.5, 1.0]));
+        assert!(!in_portal(&this, 1.0, &vector![1.0, 0.0, 2.5]));
+    }
+
+    #[test]
+    fn test_grab_remap() {
+        let body = RigidBodyHandle::from_raw_parts(1, 0);
+        let mut grab = Grab { held: Some(Held { body, through: Some((0, 2)) }) };
+        // the portals before it removed
+        let removed = [(0, 0), (1, 0)];
+        grab.remap(|x| shifted_portal(&removed, x));
+        assert_eq!(grab.held, Some(Held { body, through: Some((0, 1)) }));
+
+        // the portal held through removed
+        let removed = [(0, 1), (1, 3)];
+        grab.remap(|x| shifted_portal(&removed, x));
+        assert_eq!(grab.held, None);
+
+        let mut grab = Grab { held: Some(Held { body, through: None }) };
+        grab.remap(|x| shifted_portal(&removed, x));
+        assert_eq!(grab.held, Some(Held { body, through: None }));
+    }
+}
@@ -0,0 +1,64 @@
+use nalgebra::{vector, Vector3};
+
+use crate::engine::physics::event::ColliderTag;
+use crate::state::real_view::level::{PortalPos, PortalRayHit};
+
+/// The max distance to place the portals.
+pub const GUN_DISTANCE: f32 = 32.0;
+/// The half size of the portals placed.
+pub const GUN_PORTAL_R: f32 = 1.0;
+pub const GUN_TEX_DELTA: f32 = 0.5;
+/// The distance of the portal placed in front of the wall.
+const SURFACE_OFFSET: f32 = 0.01;
+
+/// The portals placed on the walls at runtime, linked once both ends placed.
+#[derive(Debug, Default)]
+pub struct PortalGun {
+    /// The ends placed before linked.
+    pub ends: [Option<PortalPos>; 2],
+    /// The (world, portal index) of the ends linked.
+    pub pair: Option<[(usize, usize); 2]>,
+}
+
+/// The normal of the wall snapped to the nearest horizontal axis, none if facing up or down.
+fn wall_normal(normal: &Vector3<f32>) -> Option<Vector3<f32>> {
+    if normal.z.abs() >= normal.x.abs().max(normal.y.abs()) {
+        return None;
+    }
+    Some(if normal.x.abs() >= normal.y.abs() {
+        vector![normal.x.signum(), 0.0, 0.0]
+    } else {
+        vector![0.0, normal.y.signum(), 0.0]
+    })
+}
+
+/// The portal on the wall hit, none if not hitting a wall.
+pub fn aim(hit: &PortalRayHit) -> Option<PortalPos> {
+    if hit.hit.tag != ColliderTag::Wall {
+        return None;
+    }
+    let out_normal = wall_normal(&hit.hit.normal)?;
+    Some(PortalPos {
+        world: hit.world,
+        pos: hit.hit.point.coords + out_normal * SURFACE_OFFSET,
+        out_normal,
+        up: Vector3::z(),
+        width: GUN_PORTAL_R,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use nalgebra::vector;
+
+    use crate::state::real_view::gun::wall_normal;
+
+    #[test]
+    fn test_wall_normal() {
+        assert_eq!(wall_normal(&vector![0.9, 0.1, 0.3]), Some(vector![1.0, 0.0, 0.0]));
+        assert_eq!(wall_normal(&vector![0.2, -0.7, 0.1]), Some(vector![0.0, -1.0, 0.0]));
+        // the floor and the ceiling
+        assert_eq!(wall_normal(&vector![0.0, 0.0, 1.0]), None);
+        assert_eq!(wall_normal(&vector![0.3, 0.2, -0.9]), None);
+    }
+}
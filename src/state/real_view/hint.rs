@@ -0,0 +1,244 @@
+use std::collections::{BTreeMap, HashMap};
+
+use egui::{Align2, Color32, Context, Frame, RichText};
+use nalgebra::{Point3, Vector3};
+use winit::event::VirtualKeyCode;
+
+use crate::engine::render::camera::{CameraAction, CameraController};
+
+#[allow(unused)]
+#[derive(Debug, Clone)]
+pub enum HintTrigger {
+    /// Show when the level starts.
+    Start,
+    /// Show when the player enters the box in the world.
+    Area {
+        world: usize,
+        min: Vector3<f32>,
+        max: Vector3<f32>,
+    },
+    /// Show when the event fired by [`HintOverlay::fire`]
+    Event(&'static str),
+}
+
+#[derive(Debug, Clone)]
+pub struct Hint {
+    pub trigger: HintTrigger,
+    /// The text to show, `{action}` will be replaced by the glyph of the action.
+    pub text: String,
+    /// The seconds to show.
+    pub duration: f32,
+    /// Show only once in the level.
+    pub once: bool,
+}
+
+#[allow(unused)]
+impl Hint {
+    pub fn new(trigger: HintTrigger, text: impl Into<String>) -> Self {
+        Self {
+            trigger,
+            text: text.into(),
+            duration: 5.0,
+            once: true,
+        }
+    }
+
+    pub fn duration(mut self, duration: f32) -> Self {
+        self.duration = duration;
+        self
+    }
+
+    pub fn repeat(mut self) -> Self {
+        self.once = false;
+        self
+    }
+}
+
+/// Timed prompts on the screen for the level.
+#[derive(Debug, Default)]
+pub struct HintOverlay {
+    hints: Vec<Hint>,
+    shown: Vec<bool>,
+    /// (hint index, remaining seconds)
+    active: Vec<(usize, f32)>,
+    /// Whether the player is in the area of the hint last update.
+    in_area: Vec<bool>,
+    glyphs: HashMap<&'static str, String>,
+    started: bool,
+}
+
+/// The glyphs for the actions not bound in the config.
+fn default_glyphs() -> HashMap<&'static str, String> {
+    [("look", "Right-click + drag"),
+        ("settings", "Esc")]
+        .into_iter()
+        .map(|(k, v)| (k, v.to_string()))
+        .collect()
+}
+
+fn key_glyph(key: VirtualKeyCode) -> String {
+    match key {
+        VirtualKeyCode::LShift | VirtualKeyCode::RShift => "Shift".into(),
+        VirtualKeyCode::LControl | VirtualKeyCode::RControl => "Ctrl".into(),
+        VirtualKeyCode::LAlt | VirtualKeyCode::RAlt => "Alt".into(),
+        x => format!("{:?}", x),
+    }
+}
+
+#[allow(unused)]
+impl HintOverlay {
+    pub fn new(hints: Vec<Hint>) -> Self {
+        let len = hints.len();
+        let mut this = Self {
+            hints,
+            shown: vec![false; len],
+            active: vec![],
+            in_area: vec![false; len],
+            glyphs: default_glyphs(),
+            started: false,
+        };
+        this.bind_keys(&CameraController::default_bindings());
+        this
+    }
+
+    /// Show the first keys bound to the actions of the camera controller, running by the down key.
+    pub fn bind_keys(&mut self, bindings: &BTreeMap<String, Vec<VirtualKeyCode>>) {
+        let key = |action: CameraAction| bindings.get(action.name())
+            .and_then(|x| x.first())
+            .map(|x| key_glyph(*x));
+        if let Some(x) = key(CameraAction::Down) {
+            self.bind_glyph("down", x.clone());
+            self.bind_glyph("run", x);
+        }
+        if let Some(x) = key(CameraAction::Up) {
+            self.bind_glyph("up", x);
+        }
+        let moves = [CameraAction::Forward, CameraAction::Left, CameraAction::Backward, CameraAction::Right]
+            .map(key);
+        if moves.iter().all(Option::is_some) {
+            self.bind_glyph("move", moves.into_iter().flatten().collect::<Vec<_>>().join(" "));
+        }
+    }
+
+    /// Set the glyph to show for the action.
+    pub fn bind_glyph(&mut self, action: &'static str, glyph: impl Into<String>) {
+        self.glyphs.insert(action, glyph.into());
+    }
+
+    /// Fire the event for [`HintTrigger::Event`]
+    pub fn fire(&mut self, event: &str) {
+        for i in 0..self.hints.len() {
+            if matches!(self.hints[i].trigger, HintTrigger::Event(e) if e == event) {
+                self.show(i);
+            }
+        }
+    }
+
+    /// Update the timers and check the triggers, return whether any hint is showing.
+    pub fn update(&mut self, dt: f32, world: usize, pos: &Point3<f32>) -> bool {
+        for (_, remain) in &mut self.active {
+            *remain -= dt;
+        }
+        self.active.retain(|(_, remain)| *remain > 0.0);
+
+        let started = std::mem::replace(&mut self.started, true);
+        for i in 0..self.hints.len() {
+            match &self.hints[i].trigger {
+                HintTrigger::Start => {
+                    if !started {
+                        self.show(i);
+                    }
+                }
+                HintTrigger::Area { world: w, min, max } => {
+                    let inside = *w == world && (0..3).all(|d| min[d] <= pos[d] && pos[d] <= max[d]);
+                    if inside && !self.in_area[i] {
+                        self.show(i);
+                    }
+                    self.in_area[i] = inside;
+                }
+                HintTrigger::Event(_) => {}
+            }
+        }
+        self.is_active()
+    }
+
+    pub fn is_active(&self) -> bool {
+        !self.active.is_empty()
+    }
+
+    fn show(&mut self, idx: usize) {
+        if self.hints[idx].once && self.shown[idx] {
+            return;
+        }
+        self.shown[idx] = true;
+        let duration = self.hints[idx].duration;
+        if let Some(x) = self.active.iter_mut().find(|x| x.0 == idx) {
+            x.1 = duration;
+        } else {
+            self.active.push((idx, duration));
+        }
+    }
+
+    fn substitute(&self, text: &str) -> String {
+        let mut result = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(start) = rest.find('{') {
+            result.push_str(&rest[..start]);
+            rest = &rest[start..];
+            if let Some(end) = rest.find('}') {
+                let action = &rest[1..end];
+                match self.glyphs.get(action) {
+                    Some(glyph) => result.push_str(glyph),
+                    None => result.push_str(&rest[..=end]),
+                }
+                rest = &rest[end + 1..];
+            } else {
+                break;
+            }
+        }
+        result.push_str(rest);
+        result
+    }
+
+    pub fn render(&self, ctx: &Context) {
+        if self.active.is_empty() {
+            return;
+        }
+        egui::Area::new("hints")
+            .anchor(Align2::CENTER_BOTTOM, [0.0, -64.0])
+            .interactable(false)
+            .show(ctx, |ui| {
+                for (idx, remain) in &self.active {
+                    // fade out in the last second
+                    let alpha = remain.min(1.0);
+                    Frame::popup(ui.style())
+                        .multiply_with_opacity(alpha)
+                        .show(ui, |ui| {
+                            let text = self.substitute(&self.hints[*idx].text);
+                            ui.label(RichText::new(text).size(24.0).color(Color32::WHITE.gamma_multiply(alpha)));
+                        });
+                }
+            });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use winit::event::VirtualKeyCode;
+
+    use crate::engine::render::camera::CameraController;
+    use crate::state::real_view::hint::HintOverlay;
+
+    #[test]
+    fn test_hint_glyphs() {
+        let mut hints = HintOverlay::new(vec![]);
+        assert_eq!(hints.substitute("{move} to walk, {down} to go down"), "W A S D to walk, Shift to go down");
+
+        let mut bindings = CameraController::default_bindings();
+        bindings.insert("down".into(), vec![VirtualKeyCode::C]);
+        bindings.insert("forward".into(), vec![VirtualKeyCode::Z]);
+        hints.bind_keys(&bindings);
+        assert_eq!(hints.substitute("Hold {run} to run, {move}"), "Hold C to run, Z A S D");
+        assert_eq!(hints.substitute("{unknown}"), "{unknown}");
+    }
+}
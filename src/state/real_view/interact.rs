@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+
+use nalgebra::Similarity3;
+use rapier3d::prelude::{Aabb, ColliderHandle};
+use wgpu::Device;
+
+use crate::engine::physics::state::RapierData;
+use crate::engine::renderer3d::renderer3d::{LineVertex, StaticLines};
+use crate::state::real_view::level::PortalRayHit;
+
+/// The max distance of the crosshair ray.
+pub const INTERACT_DISTANCE: f32 = 16.0;
+const HIGHLIGHT_COLOR: [f32; 4] = [1.0, 0.875, 0.25, 1.0];
+/// The half length of the crosshair lines in points.
+const CROSSHAIR_SIZE: f32 = 8.0;
+
+/// Called with the target when interacted.
+pub type InteractFn = Box<dyn FnMut(&PortalRayHit) + Send>;
+
+/// The target of the crosshair and the callbacks of the colliders to interact.
+#[derive(Default)]
+pub struct Interactions {
+    callbacks: HashMap<ColliderHandle, InteractFn>,
+    /// The hit of the ray from the camera in the last update, through the portals.
+    pub target: Option<PortalRayHit>,
+    /// The outline of the target seen from the world of me.
+    pub highlight: Option<StaticLines>,
+}
+
+/// The edges of the aabb mapped by the transform.
+fn outline(aabb: &Aabb, transform: &Similarity3<f32>) -> Vec<LineVertex> {
+    let vertices = aabb.vertices().map(|x| transform * x);
+    Aabb::EDGES_VERTEX_IDS.iter()
+        .flat_map(|(a, b)| [vertices[*a], vertices[*b]])
+        .map(|x| LineVertex { pos: x.coords, color: HIGHLIGHT_COLOR })
+        .collect()
+}
+
+#[allow(unused)]
+impl Interactions {
+    /// Call the function when the collider interacted, replace the old one.
+    pub fn register(&mut self, collider: ColliderHandle, f: impl FnMut(&PortalRayHit) + Send + 'static) {
+        self.callbacks.insert(collider, Box::new(f));
+    }
+
+    pub fn remove(&mut self, collider: ColliderHandle) -> bool {
+        self.callbacks.remove(&collider).is_some()
+    }
+
+    /// The target has the function to call.
+    pub fn interactable(&self) -> bool {
+        self.target.as_ref().is_some_and(|x| self.callbacks.contains_key(&x.hit.collider))
+    }
+
+    /// Paint the crosshair in the screen center, highlighted if interactable.
+    pub fn paint_crosshair(&self, ctx: &egui::Context) {
+        let painter = ctx.layer_painter(egui::LayerId::new(egui::Order::Foreground, egui::Id::new("crosshair")));
+        let center = ctx.screen_rect().center();
+        let color = if self.interactable() {
+            egui::Rgba::from_rgba_unmultiplied(HIGHLIGHT_COLOR[0], HIGHLIGHT_COLOR[1], HIGHLIGHT_COLOR[2], HIGHLIGHT_COLOR[3]).into()
+        } else {
+            egui::Color32::WHITE
+        };
+        let stroke = egui::Stroke::new(2.0, color);
+        painter.line_segment([center - egui::vec2(CROSSHAIR_SIZE, 0.0), center + egui::vec2(CROSSHAIR_SIZE, 0.0)], stroke);
+        painter.line_segment([center - egui::vec2(0.0, CROSSHAIR_SIZE), center + egui::vec2(0.0, CROSSHAIR_SIZE)], stroke);
+    }
+
+    /// Call the function of the target, return false if no target or the target has no function.
+    pub fn interact(&mut self) -> bool {
+        if let Some(target) = self.target.as_ref() {
+            if let Some(f) = self.callbacks.get_mut(&target.hit.collider) {
+                f(target);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Outline the bounds of the target where it is seen through the portals.
+    pub fn update_highlight(&mut self, device: &Device, p: &RapierData) {
+        let target = self.target.as_ref()
+            .and_then(|x| p.collider_set.get(x.hit.collider).map(|c| (x, c)));
+        self.highlight = target.map(|(x, c)| {
+            StaticLines::new(device, &outline(&c.compute_aabb(), &x.to_hit.inverse()))
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use nalgebra::{point, Similarity3, Translation3, UnitQuaternion, vector};
+    use rapier3d::prelude::Aabb;
+
+    use crate::state::real_view::interact::outline;
+
+    #[test]
+    fn test_outline() {
+        let aabb = Aabb::new(point![0.0, 0.0, 0.0], point![1.0, 1.0, 1.0]);
+        let lines = outline(&aabb, &Similarity3::identity());
+        assert_eq!(lines.len(), 24);
+        for x in lines.chunks(2) {
+            // along one axis
+            assert!(((x[0].pos - x[1].pos).norm() - 1.0).abs() < 1e-5);
+        }
+        let moved = Similarity3::from_parts(Translation3::new(0.0, 0.0, -15.0), UnitQuaternion::identity(), 2.0);
+        let lines = outline(&aabb, &moved);
+        assert!(lines.iter().all(|x| x.pos.z == -15.0 || x.pos.z == -13.0));
+        assert!(lines.iter().any(|x| x.pos == vector![2.0, 2.0, -13.0]));
+    }
+}
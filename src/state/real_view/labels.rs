@@ -0,0 +1,35 @@
+use crate::engine::renderer3d::text::WorldLabel;
+use crate::state::real_view::level::MagicLevel;
+
+/// The height of the portal labels in the world.
+const LABEL_SIZE: f32 = 0.3;
+/// The distance of the label above the portal quad.
+const LABEL_MARGIN: f32 = 0.3;
+const LABEL_COLOR: [f32; 3] = [1.0, 1.0, 0.9];
+
+#[allow(unused)]
+impl MagicLevel {
+    /// The name of the world shown, the index if not named.
+    pub(crate) fn world_label(&self, world: usize) -> String {
+        match self.world_names.get(world) {
+            Some(name) => format!("World {} ({})", world, name),
+            None => format!("World {}", world),
+        }
+    }
+
+    /// The labels above the portals in the world naming the worlds connected, faded by the opening.
+    pub(crate) fn portal_labels(&self, world: usize) -> Vec<WorldLabel> {
+        self.levels[world].portals.iter()
+            .filter(|x| !x.mirror && !x.opening.is_closed())
+            .map(|x| {
+                let [r, g, b] = LABEL_COLOR;
+                WorldLabel {
+                    pos: x.this.pos + x.this.up * (x.r + LABEL_MARGIN),
+                    text: self.world_label(x.connecting.0),
+                    color: [r, g, b, x.opening.current()],
+                    size: LABEL_SIZE,
+                }
+            })
+            .collect()
+    }
+}
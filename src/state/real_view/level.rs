@@ -1,28 +1,85 @@
 use std::array::from_ref;
-use std::collections::HashMap;
+use std::collections::HashSet;
 
-use egui::epaint::ahash::HashSet;
-use log::{debug, info, trace};
-use nalgebra::{Matrix4, Point3, vector, Vector2, Vector3};
+use anyhow::bail;
+use log::{debug, info, trace, warn};
+use nalgebra::{Isometry3, Matrix3, Matrix4, Point3, Rotation3, Similarity3, Translation3, UnitQuaternion, vector, Vector2, Vector3};
 use num::Zero;
 use rapier3d::pipeline::ActiveEvents;
-use rapier3d::prelude::{ColliderBuilder, ColliderHandle};
-use wgpu::{BindGroup, Color, CommandEncoder, LoadOp, Operations, RenderBundle, RenderPass, RenderPassDepthStencilAttachment, RenderPassDescriptor};
+use rapier3d::prelude::{ColliderBuilder, ColliderHandle, QueryFilter, Ray, RigidBodyBuilder, RigidBodyHandle, SharedShape};
+use specs::{Builder, Entity, Join, World, WorldExt, WriteStorage};
+use specs::shred::FetchMut;
+use wgpu::{BindGroup, Color, CommandEncoder, LoadOp, Operations, RenderBundle, RenderBundleDepthStencil, RenderBundleDescriptor, RenderBundleEncoderDescriptor, RenderPass, RenderPassDepthStencilAttachment, RenderPassDescriptor, RenderPipeline, TextureFormat};
 use wgpu::util::StagingBelt;
-use winit::event::VirtualKeyCode;
 
-use crate::engine::{StateData, WgpuData};
-use crate::engine::physics::obj::Object;
+use crate::engine::{Handle, ResourceManager, StateData, TextureWrapper, WgpuData};
+use crate::engine::counters::FrameCounters;
+use crate::engine::metrics::Phase;
+use crate::engine::ecs::{BodyPoses, Collider, PhysicsBody, PortalTraveler, RemovedBodies, RenderModel, Transform, Velocity};
+use crate::engine::physics::debug::PhysicsDebugLines;
+use crate::engine::physics::event::{ColliderTag, CROSS_MARGIN, crossed_plane, EventScratch, PortalCrossing, PortalIndex, shifted_portal};
+use crate::engine::physics::obj::{BOUNDING_HALF, KinematicObject};
+use crate::engine::physics::query::RayHit;
+use crate::engine::physics::capture::{CaptureRecord, PhysicsCapture};
+use crate::engine::physics::scheduler::PhysicsScheduler;
 use crate::engine::physics::state::RapierData;
 use crate::engine::render::camera::Camera;
+use crate::engine::render::target::TargetBind;
 use crate::engine::render_ext::CommandEncoderExt;
-use crate::engine::renderer3d::renderer3d::{PlaneObject, PlaneRenderer, Planes, StaticPlanes};
-use crate::state::real_view::renderer::portal::{PortalRenderer, PortalView};
+use crate::engine::uniform::UniformStager;
+use crate::engine::stats::Statistics;
+use crate::state::real_view::body::PlayerBody;
+use crate::state::real_view::bounds::LevelBounds;
+use crate::state::real_view::depth::{AdaptiveDepth, view_size};
+use crate::state::real_view::grab::Grab;
+use crate::state::real_view::gate::{BOUNCE_DISTANCE, PortalGate, PortalKeys, TraverseCallbacks};
+use crate::state::real_view::ghost::{PortalGhost, Straddled};
+use crate::state::real_view::gun::{aim, GUN_DISTANCE, GUN_PORTAL_R, GUN_TEX_DELTA, PortalGun};
+use crate::state::real_view::hint::HintOverlay;
+use crate::state::real_view::interact::{INTERACT_DISTANCE, Interactions};
+use crate::state::real_view::meshes::EntityMeshes;
+use crate::state::real_view::mirror::reflect_camera;
+use crate::state::real_view::multiplayer::Avatars;
+use crate::state::real_view::opening::PortalOpening;
+use crate::state::real_view::platform::Platform;
+use crate::state::real_view::sound::LevelSounds;
+use crate::state::real_view::transition::ScaleTransition;
+use crate::state::real_view::trigger::Triggers;
+use crate::engine::glft::ModelObject;
+use crate::engine::renderer3d::renderer3d::{PlaneObject, PlaneRenderer, Planes, StaticModel, StaticPlanes};
+use crate::state::real_view::renderer::portal::{PortalRenderer, PortalView, PortalViewPool};
 
 pub struct Level {
     pub(crate) portals: Vec<Portal>,
     pub(crate) objs: Vec<StaticPlanes>,
-    pub(crate) bundle: RenderBundle,
+    /// The planes of the texture arrays, rendered after the bundle.
+    pub(crate) layered: Vec<StaticPlanes>,
+    /// The planes of the materials, rendered after the bundle.
+    pub(crate) pbr: Vec<StaticPlanes>,
+    pub(crate) models: Vec<StaticModel>,
+    /// The model instances placed, spawned as the entities.
+    pub(crate) props: Vec<Prop>,
+    pub(crate) bundle: DirtyBundle,
+    /// The moving planes, rendered after the bundle.
+    pub(crate) platforms: Vec<Platform>,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct Prop {
+    pub(crate) model: usize,
+    pub(crate) instance: usize,
+    pub(crate) collider: Option<ColliderHandle>,
+    /// The collider center in the instance space.
+    pub(crate) center: Vector3<f32>,
+}
+
+/// The entities of the level in the app world, spawned in the first update.
+#[derive(Debug, Default)]
+pub(crate) struct LevelEntities {
+    pub(crate) me: Option<Entity>,
+    pub(crate) props: Vec<Entity>,
+    /// The entities spawned with the bodies created in the level.
+    pub(crate) bodies: Vec<Entity>,
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -43,38 +100,302 @@ pub(crate) struct Portal {
     /// (world, portal index)
     pub(crate) connecting: (usize, usize),
     pub(crate) scale: f32,
+    /// The half size of the quad opened.
+    pub(crate) r: f32,
+    pub(crate) tex_delta: f32,
+    pub(crate) opening: PortalOpening,
+    /// Connecting itself and viewed reflected, without the sensor.
+    pub(crate) mirror: bool,
+    pub(crate) gate: PortalGate,
+    pub(crate) on_traverse: TraverseCallbacks,
 }
 
 pub(crate) const Z_OFFSET: f32 = -15.0;
+/// The max portals the ray goes through.
+const MAX_RAY_PORTALS: usize = 8;
+
+#[allow(unused)]
+impl Portal {
+    /// Map the vector in front of this portal to behind the connecting portal, not scaled.
+    pub(crate) fn through(&self, connecting: &PortalPos, v: &Vector3<f32>) -> Vector3<f32> {
+        let this = &self.this;
+        let forward = this.out_normal.dot(v);
+        let up = this.up.dot(v);
+        let right = this.up.cross(&this.out_normal).dot(v);
+        -connecting.out_normal * forward
+            + connecting.up * up
+            - connecting.up.cross(&connecting.out_normal) * right
+    }
+
+    /// Map the position in front of this portal to behind the connecting portal.
+    pub(crate) fn similarity(&self, connecting: &PortalPos) -> Similarity3<f32> {
+        let rotation = Matrix3::from_columns(&[Vector3::x(), Vector3::y(), Vector3::z()].map(|x| self.through(connecting, &x)));
+        let rotation = UnitQuaternion::from_rotation_matrix(&Rotation3::from_matrix_unchecked(rotation));
+        let translation = connecting.pos - rotation * self.this.pos * self.scale;
+        Similarity3::from_parts(Translation3::from(translation), rotation, self.scale)
+    }
+
+    /// The camera seeing the view in this portal, reflected if a mirror.
+    fn view_camera(&self, camera: &Camera, connecting: &PortalPos) -> Camera {
+        if self.mirror {
+            return reflect_camera(camera, &self.this);
+        }
+        let mut portal_camera = *camera;
+        Coord::from_camera_portal_for_view(camera, self).change_camera_for_portal(&mut portal_camera, connecting);
+        portal_camera
+    }
+}
 
 
+/// The handles of the textures by the names.
+pub(crate) fn texture_handles(res: &ResourceManager, names: &[&str]) -> HashSet<Handle<TextureWrapper>> {
+    names.iter().filter_map(|x| res.textures.handle(x)).collect()
+}
+
 pub fn add_plane(p: &mut RapierData, planes: &mut Planes, center: &Vector3<f32>, r: f32, tex: &Vector2<f32>, tex_delta: f32, up: &Vector3<f32>, right: &Vector3<f32>) {
     let v = (vector![1.0, 1.0, 1.0] - up.abs()) * r;
     let f = if up.dot(&Vector3::z()).is_zero() { 0.0 } else { 1.0 };
     p.collider_set.insert(ColliderBuilder::cuboid(v.x, v.y, v.z)
         .translation(*center)
         .friction(f)
+        .user_data(ColliderTag::for_plane(up).into())
         .build());
     planes.objs.push(PlaneObject::new(center, r, tex, tex_delta, up, right));
 }
 
 
+/// The render bundle of the level planes, rebuilt in the next frame after the planes or the textures changed.
+#[derive(Debug)]
+pub struct DirtyBundle {
+    /// None if not built yet or rendered directly.
+    bundle: Option<RenderBundle>,
+    /// Render by the no cull pipeline.
+    no_cull: bool,
+    dirty: bool,
+    /// Render the planes directly instead of the bundle, for the planes changed in most frames.
+    direct: bool,
+    /// The times rebuilt after created.
+    pub rebuilds: u32,
+}
+
+#[allow(unused)]
+impl DirtyBundle {
+    /// Build the bundle of the planes.
+    pub fn new(gpu: &WgpuData, pr: &PlaneRenderer, objs: &[StaticPlanes], no_cull: bool) -> Self {
+        let mut this = Self {
+            bundle: None,
+            no_cull,
+            dirty: true,
+            direct: false,
+            rebuilds: 0,
+        };
+        this.build(gpu, pr, objs);
+        this
+    }
+
+    fn pipeline<'a>(&self, pr: &'a PlaneRenderer) -> &'a RenderPipeline {
+        if self.no_cull { &pr.no_cull_rp } else { &pr.normal_rp }
+    }
+
+    fn build(&mut self, gpu: &WgpuData, pr: &PlaneRenderer, objs: &[StaticPlanes]) {
+        let mut bundle = gpu.device.create_render_bundle_encoder(&RenderBundleEncoderDescriptor {
+            label: Some("level bundle"),
+            color_formats: &[Some(gpu.formats.color)],
+            depth_stencil: Some(RenderBundleDepthStencil {
+                format: TextureFormat::Depth32Float,
+                depth_read_only: false,
+                stencil_read_only: false,
+            }),
+            sample_count: 1,
+            multiview: None,
+        });
+        bundle.set_pipeline(self.pipeline(pr));
+        pr.bind(&mut bundle);
+        pr.render_static(&mut bundle, gpu, objs);
+        self.bundle = Some(bundle.finish(&RenderBundleDescriptor {
+            label: Some("level bundle"),
+        }));
+        self.dirty = false;
+    }
+
+    /// The planes or their textures changed, rebuilt in the next sync.
+    pub fn mark_dirty(&mut self) {
+        self.dirty = true;
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Render the planes directly or by the bundle again.
+    pub fn set_direct(&mut self, direct: bool) {
+        self.direct = direct;
+        if direct {
+            self.bundle = None;
+        } else if self.bundle.is_none() {
+            self.dirty = true;
+        }
+    }
+
+    /// Rebuild the bundle if dirty, return whether rebuilt.
+    pub fn sync(&mut self, gpu: &WgpuData, pr: &PlaneRenderer, objs: &[StaticPlanes]) -> bool {
+        if !self.dirty || self.direct {
+            return false;
+        }
+        self.build(gpu, pr, objs);
+        self.rebuilds += 1;
+        debug!(target: "level", "Rebuilt the level bundle of {} planes", objs.len());
+        true
+    }
+
+    /// Execute the bundle, or render the planes directly if dirty or direct.
+    pub fn render<'a>(&'a self, rp: &mut RenderPass<'a>, gpu: &WgpuData, pr: &'a PlaneRenderer, objs: &'a [StaticPlanes]) {
+        match &self.bundle {
+            Some(bundle) if !self.dirty && !self.direct => rp.execute_bundles(std::iter::once(bundle)),
+            _ => {
+                pr.bind(rp);
+                rp.set_pipeline(self.pipeline(pr));
+                pr.render_static(rp, gpu, objs);
+            }
+        }
+    }
+}
+
+#[allow(unused)]
+impl Level {
+    /// Add the planes to the bundle, return the index.
+    pub fn push_planes(&mut self, planes: StaticPlanes) -> usize {
+        self.objs.push(planes);
+        self.bundle.mark_dirty();
+        self.objs.len() - 1
+    }
+
+    /// Replace the planes in the bundle, return the planes replaced.
+    pub fn set_planes(&mut self, idx: usize, planes: StaticPlanes) -> Option<StaticPlanes> {
+        let old = self.objs.get_mut(idx)?;
+        self.bundle.mark_dirty();
+        Some(std::mem::replace(old, planes))
+    }
+
+    /// Change the texture of the planes in the bundle, return false if no such planes.
+    pub fn set_texture_bind(&mut self, idx: usize, texture_bind: BindGroup) -> bool {
+        match self.objs.get_mut(idx) {
+            Some(x) => {
+                x.texture_bind = Some(texture_bind);
+                self.bundle.mark_dirty();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
 impl Level {
-    pub fn render<'a>(&'a self, rp: &mut RenderPass<'a>, _: &WgpuData, _: &mut PlaneRenderer) {
-        rp.execute_bundles(std::iter::once(&self.bundle));
+    pub fn render<'a>(&'a self, rp: &mut RenderPass<'a>, gpu: &WgpuData, pr: &'a PlaneRenderer) {
+        self.bundle.render(rp, gpu, pr, &self.objs);
+        self.render_materials(rp, gpu, pr);
     }
 
-    fn add_portal(&mut self, p: &mut RapierData, gpu: &WgpuData, _pr: &PlaneRenderer, this: PortalPos, r: f32, tex_delta: f32, scale: f32) -> (ColliderHandle, usize) {
+    /// Render the planes in the bundle by the renderer, for the renderer of the other window.
+    pub fn render_unbundled<'a>(&'a self, rp: &mut RenderPass<'a>, gpu: &WgpuData, pr: &'a PlaneRenderer) {
+        pr.bind(rp);
+        rp.set_pipeline(&pr.normal_rp);
+        pr.render_static(rp, gpu, &self.objs);
+        self.render_materials(rp, gpu, pr);
+    }
+
+    /// Render the planes and the models not in the bundle.
+    fn render_materials<'a>(&'a self, rp: &mut RenderPass<'a>, gpu: &WgpuData, pr: &'a PlaneRenderer) {
+        if !self.layered.is_empty() {
+            pr.bind(rp);
+            rp.set_pipeline(&pr.array_rp);
+            pr.render_static(rp, gpu, &self.layered);
+        }
+        if !self.pbr.is_empty() {
+            pr.bind(rp);
+            rp.set_pipeline(&pr.pbr_rp);
+            pr.render_static(rp, gpu, &self.pbr);
+        }
+        if !self.models.is_empty() {
+            pr.bind(rp);
+            rp.set_pipeline(&pr.model_rp);
+            pr.render_models(rp, &self.models);
+        }
+        if !self.platforms.is_empty() {
+            pr.bind(rp);
+            rp.set_pipeline(&pr.normal_rp);
+            for x in &self.platforms {
+                pr.render_static(rp, gpu, from_ref(&x.planes));
+            }
+        }
+    }
+
+    /// Place the model in the level with a cuboid collider from the model bounds for each instance.
+    pub fn add_model(&mut self, p: &mut RapierData, gpu: &WgpuData, pr: &mut PlaneRenderer, mut obj: ModelObject) {
+        let model = self.models.len();
+        let offset = Vector3::from_row_slice(&obj.locals.position[..3]);
+        for (instance, x) in obj.instances.iter().enumerate() {
+            let (collider, center) = match obj.model.aabb {
+                Some(aabb) => {
+                    let half = aabb.half_extents();
+                    let center = aabb.center().coords + offset;
+                    let rotation = UnitQuaternion::from_quaternion(x.rotation);
+                    let handle = p.collider_set.insert(ColliderBuilder::cuboid(half.x, half.y, half.z)
+                        .position(Isometry3::from_parts((x.position + rotation * center).into(), rotation))
+                        .build());
+                    (Some(handle), center)
+                }
+                None => (None, Vector3::zeros()),
+            };
+            self.props.push(Prop { model, instance, collider, center });
+        }
+        obj.model.pack(gpu, &mut pr.arena);
+        self.models.push(pr.create_static_model(&gpu.device, obj));
+    }
+
+    /// Move the prop model and its collider to the transform.
+    fn move_prop(&mut self, p: &mut RapierData, model: &RenderModel, transform: &Transform) {
+        let m = if let Some(m) = self.models.get_mut(model.model) { m } else {
+            return;
+        };
+        if !m.set_instance(model.instance, transform.position.coords, *transform.rotation.quaternion()) {
+            return;
+        }
+        let prop = self.props.iter().find(|x| x.model == model.model && x.instance == model.instance);
+        if let Some((collider, center)) = prop.and_then(|x| x.collider.map(|c| (c, x.center))) {
+            if let Some(c) = p.collider_set.get_mut(collider) {
+                c.set_position(Isometry3::from_parts((transform.position.coords + transform.rotation * center).into(), transform.rotation));
+            }
+        }
+    }
+
+    /// The quad of the portal, the tex coords centered at zero.
+    pub(crate) fn portal_quad(this: &PortalPos, r: f32, tex_delta: f32) -> PlaneObject {
         let right = if this.out_normal.xy().is_zero() {
             Vector3::x()
         } else {
             vector![this.out_normal.y, -this.out_normal.x, 0.0]
         };
 
-        let plane = PlaneObject::new(&this.pos, r, &Vector2::zeros(), tex_delta, &this.out_normal, &right);
+        PlaneObject::new(&this.pos, r, &Vector2::zeros(), tex_delta, &this.out_normal, &right)
+    }
+
+    /// The quad of the portal and its planes rendered.
+    pub(crate) fn portal_plane(gpu: &WgpuData, this: &PortalPos, r: f32, tex_delta: f32) -> (PlaneObject, StaticPlanes) {
+        let plane = Self::portal_quad(this, r, tex_delta);
         let planes = Planes { objs: vec![plane], texture_bind: None }.to_static(&gpu.device);
+        (plane, planes)
+    }
+
+    /// The half extents of the portal sensor.
+    pub(crate) fn sensor_half(this: &PortalPos, r: f32) -> Vector3<f32> {
+        (vector![1.0, 1.0, 1.0] - this.out_normal.abs()) * (r - 0.0625)
+    }
 
-        let v = (vector![1.0, 1.0, 1.0] - this.out_normal.abs()) * (r - 0.0625);
+    fn add_portal(&mut self, p: &mut RapierData, gpu: &WgpuData, _pr: &PlaneRenderer, this: PortalPos, r: f32, tex_delta: f32, scale: f32) -> (ColliderHandle, usize) {
+        let (plane, planes) = Self::portal_plane(gpu, &this, r, tex_delta);
+
+        let v = Self::sensor_half(&this, r);
         let handle = p.collider_set.insert(ColliderBuilder::cuboid(v.x, v.y, v.z)
             .sensor(true)
             .translation(this.pos)
@@ -87,20 +408,116 @@ impl Level {
             this,
             connecting: (0, 0),
             scale,
+            r,
+            tex_delta,
+            opening: Default::default(),
+            mirror: false,
+            gate: Default::default(),
+            on_traverse: Default::default(),
         });
         (handle, idx)
     }
+
+    /// Rebuild the quad of the portal scaled by its opening.
+    fn rebuild_portal(&mut self, gpu: &WgpuData, idx: usize) {
+        let portal = &mut self.portals[idx];
+        let (_, planes) = Self::portal_plane(gpu, &portal.this, portal.r * portal.opening.current(), portal.tex_delta);
+        portal.portal_render = planes;
+    }
+
+    /// Move the portal and its sensor in the world, keeping the connecting portal.
+    fn move_portal(&mut self, p: &mut RapierData, gpu: &WgpuData, idx: usize, sensor: ColliderHandle, this: PortalPos, r: f32, tex_delta: f32) {
+        let (plane, _) = Self::portal_plane(gpu, &this, r, tex_delta);
+        if let Some(c) = p.collider_set.get_mut(sensor) {
+            let v = Self::sensor_half(&this, r);
+            c.set_shape(SharedShape::cuboid(v.x, v.y, v.z));
+            c.set_translation(this.pos);
+        }
+        let portal = &mut self.portals[idx];
+        portal.plane = plane;
+        portal.this = this;
+        portal.r = r;
+        portal.tex_delta = tex_delta;
+        self.rebuild_portal(gpu, idx);
+    }
 }
 
 pub struct MagicLevel {
     pub levels: Vec<Level>,
     pub p: RapierData,
-    pub me: Object,
+    pub(crate) scheduler: PhysicsScheduler,
+    pub me: KinematicObject,
+    /// The world of me, from the [`PortalTraveler`] of my entity.
     pub me_world: usize,
     /// (Col world, portal index)
-    pub portals_map: HashMap<ColliderHandle, (usize, usize)>,
+    pub portals_map: PortalIndex,
+    pub(crate) events: EventScratch,
+    pub(crate) crossing: PortalCrossing,
+    pub(crate) bounds: LevelBounds,
+    /// The physics wireframe drawn over the scene if some.
+    pub(crate) physics_debug: Option<PhysicsDebugLines>,
+    pub(crate) interactions: Interactions,
     pub(crate) staging_belt: StagingBelt,
+    /// The views of the portals at each depth, taken from the pool when rendered at the depth.
     pub(crate) portal_views: Vec<PortalView>,
+    /// The depth of the portals rendered, adapted by the frame time.
+    pub(crate) depth: AdaptiveDepth,
+    /// The resolution scale of the portal views multiplied for each depth.
+    pub(crate) view_falloff: f32,
+    pub(crate) hints: HintOverlay,
+    pub(crate) sounds: LevelSounds,
+    /// The remote players updated by the multiplayer.
+    pub(crate) avatars: Avatars,
+    /// The meshes of the entities spawned.
+    pub(crate) meshes: EntityMeshes,
+    pub(crate) body: PlayerBody,
+    /// My scale eased after going through the scaled portals.
+    pub(crate) transition: ScaleTransition,
+    /// My collider behind the portal straddled.
+    pub(crate) ghost: PortalGhost,
+    /// The portals placed by me.
+    pub(crate) gun: PortalGun,
+    /// The textures used by the level, not evicted by the budget.
+    pub(crate) textures: HashSet<Handle<TextureWrapper>>,
+    pub(crate) counters: FrameCounters,
+    /// The names of the worlds shown in the HUD, empty if not named.
+    pub(crate) world_names: Vec<String>,
+    /// The portal nearest to the screen center in the last frame.
+    pub(crate) looked_portal: Option<LookedPortal>,
+    pub(crate) entities: LevelEntities,
+    /// The debug capture of the physics steps if some.
+    pub(crate) capture: Option<PhysicsCapture>,
+    /// The keys for the portals locked.
+    pub(crate) keys: PortalKeys,
+    pub(crate) triggers: Triggers,
+    /// The prop carried.
+    pub(crate) grab: Grab,
+    /// The bind group to draw the picture in picture, created again after its target resized.
+    pub(crate) pip_bind: TargetBind,
+}
+
+/// The portal looked at, its view is left in the first portal view after rendering.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) struct LookedPortal {
+    pub world: usize,
+    pub idx: usize,
+    /// The uv of the portal bounds on the screen.
+    pub uv: egui::Rect,
+}
+
+/// The hit of the ray going through the portals.
+#[allow(unused)]
+#[derive(Debug, Copy, Clone)]
+pub struct PortalRayHit {
+    /// The world of the collider hit.
+    pub world: usize,
+    pub hit: RayHit,
+    /// The ray after the last portal, the hit toi is along it.
+    pub ray: Ray,
+    /// The portals gone through.
+    pub portals: usize,
+    /// Map the position in the world casted from to the world hit.
+    pub to_hit: Similarity3<f32>,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -114,6 +531,10 @@ struct Coord {
     target_right: f32,
 }
 
+fn plane_count(objs: &[StaticPlanes]) -> u32 {
+    objs.iter().map(|x| x.count).sum()
+}
+
 fn will_see_face(view: &Matrix4<f32>, plane: &PlaneObject) -> bool {
     let mut mn_x = 2.0;
     let mut mx_x = -2.0;
@@ -139,6 +560,21 @@ fn will_see_face(view: &Matrix4<f32>, plane: &PlaneObject) -> bool {
     }
 }
 
+/// The bounds of the plane on the screen in uv, none if behind the camera.
+fn screen_uv(view: &Matrix4<f32>, plane: &PlaneObject) -> Option<egui::Rect> {
+    let mut rect = egui::Rect::NOTHING;
+    for x in plane.vertex {
+        let result = view * vector![x.pos.x, x.pos.y, x.pos.z, 1.0];
+        if result.w <= 0.0 {
+            return None;
+        }
+        let result = result / result.w;
+        rect.extend_with(egui::pos2((result.x + 1.0) * 0.5, (1.0 - result.y) * 0.5));
+    }
+    let rect = rect.intersect(egui::Rect::from_min_max(egui::Pos2::ZERO, egui::pos2(1.0, 1.0)));
+    rect.is_positive().then_some(rect)
+}
+
 impl Coord {
     /// Get the coord in the portal view
     fn from_camera_portal(camera: &Camera, portal: &Portal) -> Coord {
@@ -220,7 +656,16 @@ impl Coord {
 
 
 impl MagicLevel {
-    pub(crate) fn add_portal(&mut self, gpu: &WgpuData, pr: &PlaneRenderer, p1: PortalPos, p2: PortalPos, r1: f32, tex_delta1: f32, r2: f32, tex_delta2: f32, scale: f32) {
+    /// The distance to the nearest portal opened in the world, none if no portals.
+    pub(crate) fn nearest_portal(&self, world: usize, eye: &Point3<f32>) -> Option<f32> {
+        self.levels[world].portals.iter()
+            .filter(|x| !x.mirror && !x.opening.is_closed())
+            .map(|x| (eye.coords - x.this.pos).norm())
+            .reduce(f32::min)
+    }
+
+    /// Add the portals connecting each other, return the (world, portal index) of them.
+    pub(crate) fn add_portal(&mut self, gpu: &WgpuData, pr: &PlaneRenderer, p1: PortalPos, p2: PortalPos, r1: f32, tex_delta1: f32, r2: f32, tex_delta2: f32, scale: f32) -> [(usize, usize); 2] {
         let (handle, idx) = self.levels[p1.world].add_portal(&mut self.p, gpu, pr, p1, r1, tex_delta1, scale);
         let (handle2, idx2) = self.levels[p2.world].add_portal(&mut self.p, gpu, pr, p2, r2, tex_delta2, 1.0 / scale);
 
@@ -229,68 +674,617 @@ impl MagicLevel {
 
         self.portals_map.insert(handle, (p1.world, idx));
         self.portals_map.insert(handle2, (p2.world, idx2));
+        [(p1.world, idx), (p2.world, idx2)]
     }
 
+    /// Place the end of the portal gun on the wall the camera aims at, and link the ends once both placed.
+    ///
+    /// The end linked is moved in its world. Return the portal of the end, none if the other end not placed.
+    pub(crate) fn shoot_portal(&mut self, gpu: &WgpuData, pr: &PlaneRenderer, camera: &Camera, end: usize) -> anyhow::Result<Option<(usize, usize)>> {
+        let dir = if let Some(x) = camera.target.try_normalize(f32::EPSILON) { x } else {
+            bail!("No direction to shoot");
+        };
+        let hit = if let Some(x) = self.raycast_through_portals(self.me_world, Ray::new(camera.eye, dir), GUN_DISTANCE, Some(self.me.handle)) { x } else {
+            bail!("Nothing hit in {}", GUN_DISTANCE);
+        };
+        let this = if let Some(x) = aim(&hit) { x } else {
+            bail!("Hit {:?} but not a wall", hit.hit.tag);
+        };
+        if let Some(pair) = self.gun.pair {
+            let (world, idx) = pair[end];
+            if world == this.world {
+                let sensor = if let Some(x) = self.portals_map.sensor((world, idx)) { x } else {
+                    bail!("No sensor of the portal {:?}", (world, idx));
+                };
+                // the walls behind the old place
+                self.ghost.remove(&mut self.p);
+                self.levels[world].move_portal(&mut self.p, gpu, idx, sensor, this, GUN_PORTAL_R, GUN_TEX_DELTA);
+                return Ok(Some((world, idx)));
+            }
+            // removed and linked again in the other world
+            let other = pair[1 - end];
+            self.gun.ends[1 - end] = Some(self.levels[other.0].portals[other.1].this);
+            self.remove_portal(pair[end])?;
+        }
+        self.gun.ends[end] = Some(this);
+        if let [Some(p1), Some(p2)] = self.gun.ends {
+            let pair = self.add_portal(gpu, pr, p1, p2, GUN_PORTAL_R, GUN_TEX_DELTA, GUN_PORTAL_R, GUN_TEX_DELTA, 1.0);
+            self.gun.pair = Some(pair);
+            self.gun.ends = [None, None];
+            for (world, idx) in pair {
+                self.levels[world].portals[idx].opening = PortalOpening::closed();
+                self.levels[world].rebuild_portal(gpu, idx);
+            }
+            self.set_portal_open(pair[0], true)?;
+            return Ok(Some(pair[end]));
+        }
+        Ok(None)
+    }
 
+    /// Set who goes through the portal, the connecting portal keeps its gate.
+    pub fn set_portal_gate(&mut self, portal: (usize, usize), gate: PortalGate) -> anyhow::Result<()> {
+        let this = if let Some(x) = self.levels.get_mut(portal.0).and_then(|x| x.portals.get_mut(portal.1)) { x } else {
+            bail!("No portal {:?}", portal);
+        };
+        this.gate = gate;
+        Ok(())
+    }
 
+    /// Call the callback each time after going through the portal.
+    pub fn on_traverse(&mut self, portal: (usize, usize), callback: impl FnMut(&mut PortalKeys) + Send + 'static) -> anyhow::Result<()> {
+        let this = if let Some(x) = self.levels.get_mut(portal.0).and_then(|x| x.portals.get_mut(portal.1)) { x } else {
+            bail!("No portal {:?}", portal);
+        };
+        this.on_traverse.0.push(Box::new(callback));
+        Ok(())
+    }
 
-    pub fn update(&mut self, s: &mut StateData, dt: f32, camera: &mut Camera, ddr: &Vector3<f32>) {
-        self.p.integration_parameters.dt = dt;
+    /// Open or close the portal and the connecting one, animated in the updates.
+    ///
+    /// The sensors are disabled until fully opened.
+    pub fn set_portal_open(&mut self, portal: (usize, usize), open: bool) -> anyhow::Result<()> {
+        let connecting = if let Some(x) = self.levels.get(portal.0).and_then(|x| x.portals.get(portal.1)) { x.connecting } else {
+            bail!("No portal {:?}", portal);
+        };
+        for (world, idx) in [portal, connecting] {
+            let opening = &mut self.levels[world].portals[idx].opening;
+            opening.set_open(open);
+            let enabled = opening.is_open();
+            if let Some(c) = self.portals_map.sensor((world, idx)).and_then(|x| self.p.collider_set.get_mut(x)) {
+                c.set_enabled(enabled);
+            }
+        }
+        if !open {
+            self.ghost.remove(&mut self.p);
+        }
+        Ok(())
+    }
 
-        self.me.calc_vel(&mut self.p, ddr, s.app.inputs.cur_frame_input.pressing.contains(&VirtualKeyCode::LShift));
-        self.p.step(dt);
-        let mut coled = HashSet::default();
-        while let Ok(event) = self.p.col_events.try_recv() {
-            trace!(target:"level::col", "Got col event {:?}", event);
-            if event.stopped() {
-                continue;
+    /// Remove the portal and the connecting one, the portals after them in the worlds are shifted.
+    pub fn remove_portal(&mut self, portal: (usize, usize)) -> anyhow::Result<()> {
+        let connecting = if let Some(x) = self.levels.get(portal.0).and_then(|x| x.portals.get(portal.1)) { x.connecting } else {
+            bail!("No portal {:?}", portal);
+        };
+        let mut removed = vec![portal, connecting];
+        removed.sort();
+        removed.dedup();
+        self.ghost.remove(&mut self.p);
+        for &x in &removed {
+            if let Some(sensor) = self.portals_map.sensor(x) {
+                self.portals_map.remove(sensor);
+                self.p.remove_collider(sensor);
             }
-            let portal_handle = if event.collider1() == self.me.collider_handle {
-                event.collider2()
-            } else {
-                event.collider1()
-            };
-            if let Some((world, idx)) = self.portals_map.get(&portal_handle) {
-                if !coled.insert((*world, *idx)) {
+        }
+        // the later first to keep the indices
+        for &(world, idx) in removed.iter().rev() {
+            self.levels[world].portals.remove(idx);
+        }
+        for level in &mut self.levels {
+            for x in &mut level.portals {
+                if let Some(c) = shifted_portal(&removed, x.connecting) {
+                    x.connecting = c;
+                }
+            }
+        }
+        self.portals_map.remap(|x| shifted_portal(&removed, x));
+        self.crossing.remap(|x| shifted_portal(&removed, x));
+        self.grab.remap(|x| shifted_portal(&removed, x));
+        self.gun.pair = self.gun.pair.and_then(|[a, b]| Some([shifted_portal(&removed, a)?, shifted_portal(&removed, b)?]));
+        self.looked_portal = None;
+        info!(target: "level", "Removed the portals {:?}", removed);
+        Ok(())
+    }
+
+    /// Animate the portals opening or closing, and enable the sensors fully opened.
+    fn tick_portals(&mut self, gpu: Option<&WgpuData>, dt: f32) {
+        for world in 0..self.levels.len() {
+            for idx in 0..self.levels[world].portals.len() {
+                if !self.levels[world].portals[idx].opening.tick(dt) {
                     continue;
                 }
-                let portal = &self.levels[*world].portals[*idx];
-                let before = camera.eye;
-                let camera_view = Coord::from_camera_portal(camera, portal);
+                if let Some(gpu) = gpu {
+                    self.levels[world].rebuild_portal(gpu, idx);
+                }
+                if self.levels[world].portals[idx].opening.is_open() {
+                    if let Some(c) = self.portals_map.sensor((world, idx)).and_then(|x| self.p.collider_set.get_mut(x)) {
+                        c.set_enabled(true);
+                    }
+                }
+            }
+        }
+    }
+
+    /// The slot of the portal fade, in the order of the worlds.
+    fn fade_slot(&self, (world, idx): (usize, usize)) -> usize {
+        self.levels[..world].iter().map(|x| x.portals.len()).sum::<usize>() + idx
+    }
+
+
+
+
+    /// The portals in my world my bounding intersects.
+    fn straddled(&self) -> Vec<Straddled> {
+        self.p.narrow_phase.intersections_with(self.me.body_bounding)
+            .filter(|x| x.2)
+            .map(|(a, b, _)| if a == self.me.body_bounding { b } else { a })
+            .filter_map(|x| self.portals_map.get(x).map(|portal| (x, portal)))
+            .filter(|(_, (world, _))| *world == self.me_world)
+            .filter_map(|(sensor, (world, idx))| {
+                let portal = &self.levels[world].portals[idx];
                 let connecting = &self.levels[portal.connecting.0].portals[portal.connecting.1].this;
-                camera_view.change_camera_without_forward(camera, connecting);
+                Some(Straddled {
+                    portal: (world, idx),
+                    similarity: portal.similarity(connecting),
+                    sensors: [sensor, self.portals_map.sensor(portal.connecting)?],
+                })
+            })
+            .collect()
+    }
 
-                camera.eye.z = connecting.pos.z;
-                camera.eye += connecting.out_normal * 0.02;
+    /// Rebuild my body at the camera and its clones behind the portals my body straddles.
+    pub(crate) fn rebuild_body(&mut self, gpu: &WgpuData, camera: &Camera) {
+        let levels = &self.levels;
+        let clones = self.straddled().into_iter()
+            .map(|x| {
+                let portal = &levels[x.portal.0].portals[x.portal.1];
+                (portal.connecting.0, x.similarity)
+            });
+        let forward = camera.target.xy().push(0.0).try_normalize(f32::EPSILON).unwrap_or_else(Vector3::x);
+        self.body.rebuild(gpu, self.me_world, &camera.eye, &forward, clones);
+    }
 
-                self.p.rigid_body_set[self.me.handle].set_translation(camera.eye.coords, true);
-                if let Some(c) = self.p.collider_set[self.me.body_bounding].shape_mut().as_cuboid_mut() {
-                    c.half_extents.x *= portal.scale;
-                    c.half_extents.y *= portal.scale;
+    /// Spawn me and the props to the world if not spawned.
+    pub fn spawn(&mut self, world: &mut World) {
+        if self.entities.me.is_some() {
+            return;
+        }
+        let me = world.create_entity()
+            .with(Transform::from_isometry(self.p.rigid_body_set[self.me.handle].position()))
+            .with(Collider { body: Some(self.me.handle), collider: self.me.collider_handle })
+            // my scale may be restored from the session
+            .with(PortalTraveler { scale: self.transition.target(), ..PortalTraveler::new(self.me_world) })
+            .build();
+        self.entities.me = Some(me);
+        // the bodies removed are of the last level
+        world.write_resource::<RemovedBodies>().0.clear();
+        for (idx, level) in self.levels.iter().enumerate() {
+            for prop in &level.props {
+                let instance = &level.models[prop.model].instances[prop.instance];
+                let mut builder = world.create_entity()
+                    .with(Transform {
+                        position: instance.position.into(),
+                        rotation: UnitQuaternion::from_quaternion(instance.rotation),
+                    })
+                    .with(RenderModel { world: idx, model: prop.model, instance: prop.instance });
+                if let Some(collider) = prop.collider {
+                    builder = builder.with(Collider { body: None, collider });
                 }
-                info!(target: "level", "From world {} to world {}", self.me_world, connecting.world);
-                self.me_world = connecting.world;
-                debug!(target:"level", "{:?} with {:?} => {:?}", before, camera_view, camera.eye);
+                self.entities.props.push(builder.build());
+            }
+        }
+    }
+
+    /// Delete the entities spawned, removed from the world in the next maintain.
+    /// The portal views are kept in the pool for the next level.
+    pub fn despawn(&mut self, world: &World) {
+        if let Some(mut pool) = world.try_fetch_mut::<PortalViewPool>() {
+            for x in self.portal_views.drain(..) {
+                pool.put(x);
+            }
+        }
+        let entities = world.entities();
+        let spawned = self.entities.me.take().into_iter()
+            .chain(self.entities.props.drain(..))
+            .chain(self.entities.bodies.drain(..));
+        for x in spawned {
+            if let Err(e) = entities.delete(x) {
+                warn!(target: "level", "Delete the entity {:?} failed for {:?}", x, e);
+            }
+        }
+    }
+
+    /// Create the bodies of the entities spawned and remove the bodies of the entities despawned.
+    fn sync_bodies(&mut self, world: &World) {
+        for removed in world.write_resource::<RemovedBodies>().0.drain(..) {
+            self.interactions.remove(removed.collider);
+            if let Some(body) = removed.body.filter(|x| *x != self.me.handle) {
+                self.p.remove_body(body);
+            }
+        }
+        let entities = world.entities();
+        self.entities.bodies.retain(|x| entities.is_alive(*x));
+        let (bodies, transforms, velocities) = (world.read_storage::<PhysicsBody>(), world.read_storage::<Transform>(), world.read_storage::<Velocity>());
+        let mut colliders = world.write_storage::<Collider>();
+        let created = (&entities, &bodies, &transforms, !&colliders).join()
+            .map(|(e, body, transform, _)| (e, body.shape.clone(), *transform, velocities.get(e).copied().unwrap_or_default()))
+            .collect::<Vec<_>>();
+        for (entity, shape, transform, velocity) in created {
+            let body = RigidBodyBuilder::dynamic()
+                .position(transform.to_isometry())
+                .linvel(velocity.linear)
+                .angvel(velocity.angular)
+                .ccd_enabled(true)
+                .build();
+            let body = self.p.rigid_body_set.insert(body);
+            let collider = self.p.collider_set.insert_with_parent(ColliderBuilder::new(shape).build(), body, &mut self.p.rigid_body_set);
+            if let Err(e) = colliders.insert(entity, Collider { body: Some(body), collider }) {
+                warn!(target: "level", "Add the collider to {:?} failed for {:?}", entity, e);
+            }
+            self.entities.bodies.push(entity);
+        }
+    }
+
+    /// Apply the components changed by the systems.
+    fn sync_entities(&mut self, world: &World) {
+        if let Some(traveler) = self.entities.me.and_then(|x| world.read_storage::<PortalTraveler>().get(x).copied()) {
+            self.me_world = traveler.world;
+        }
+        let (transforms, models) = (world.read_storage::<Transform>(), world.read_storage::<RenderModel>());
+        for (transform, model) in (&transforms, &models).join() {
+            if let Some(level) = self.levels.get_mut(model.world) {
+                level.move_prop(&mut self.p, model, transform);
+            }
+        }
+    }
+
+    pub fn update(&mut self, s: &mut StateData, dt: f32, camera: &mut Camera, ddr: &Vector3<f32>, running: bool) {
+        self.spawn(&mut s.app.world);
+        self.sync_entities(&s.app.world);
+        self.sync_bodies(&s.app.world);
+
+        let mut stats = s.wd.world.try_fetch_mut::<Statistics>();
+        let start = std::time::Instant::now();
+        let walked = self.step_physics(&s.app.world, &mut stats, dt, camera, ddr, running);
+        s.app.metrics.add(Phase::Physics, start.elapsed());
+        if let Some(mut poses) = s.app.world.try_fetch_mut::<BodyPoses>() {
+            poses.0.clear();
+            poses.0.extend(self.scheduler.interpolated(&self.p));
+        }
+        if let Some(stats) = stats.as_mut() {
+            stats.add_distance_walked(walked as f64);
+        }
+        self.run_triggers(s.app.audio.as_mut(), &s.app.res);
+        if let Some(audio) = s.app.audio.as_mut() {
+            let ground = self.p.ground_tag(self.me.handle, 1.125);
+            self.sounds.update(audio, &s.app.res, dt, self.me.collider_handle, walked, ground, &self.events.impacts);
+        }
+
+        if let Some(me) = self.scheduler.interpolate(&self.p, self.me.handle) {
+            camera.eye = Point3::from(me.translation.vector);
+        }
+        if self.transition.tick(dt) {
+            self.scale_bounding();
+        }
+        self.transition.apply(camera);
+        self.tick_portals(s.app.gpu.as_ref(), dt);
+        if let Some(gpu) = s.app.gpu.as_ref() {
+            for x in self.levels.iter().flat_map(|x| x.platforms.iter()) {
+                if let Some(pose) = self.scheduler.interpolate(&self.p, x.body) {
+                    x.update_planes(gpu, &pose.translation.vector);
+                }
+            }
+        }
+        self.interactions.target = camera.target.try_normalize(f32::EPSILON)
+            .and_then(|dir| self.raycast_through_portals(self.me_world, Ray::new(camera.eye, dir), INTERACT_DISTANCE, Some(self.me.handle)));
+        self.hints.update(dt, self.me_world, &camera.eye);
+    }
+
+    /// Move me and step the physics for the frame time, going through the portals and keeping in the bounds.
+    ///
+    /// Return the distance walked.
+    pub fn step_physics(&mut self, world: &World, stats: &mut Option<FetchMut<Statistics>>, dt: f32, camera: &mut Camera, ddr: &Vector3<f32>, running: bool) -> f32 {
+        let mut travelers = world.write_storage::<PortalTraveler>();
+        let mut walked = 0.0;
+        self.events.clear();
+        self.counters.reset_physics();
+        let steps = self.scheduler.advance(dt);
+        if let Some(capture) = self.capture.as_ref() {
+            capture.record(CaptureRecord::Frame { dt, steps });
+        }
+        for _ in 0..steps {
+            let straddled = self.straddled().first().copied();
+            self.ghost.update(&mut self.p, &self.me, straddled);
+            self.me.move_by(&mut self.p, self.scheduler.step_dt, ddr, running);
+            self.ghost.limit(&mut self.p, &self.me, self.scheduler.step_dt);
+            let before_step = *self.p.rigid_body_set[self.me.handle].translation();
+            // go through the portals from where the step started
+            camera.eye = Point3::from(before_step);
+            let carrier = if self.me.grounded { self.p.ground_body(self.me.handle, 1.125) } else { None };
+            self.step_platforms();
+            self.hold(camera);
+            let held_before = self.held_position();
+            self.scheduler.before_step(&self.p);
+            self.p.step(self.scheduler.step_dt);
+            walked += (self.p.rigid_body_set[self.me.handle].translation() - before_step).xy().norm();
+            self.carry_me(carrier);
+            self.p.drain_contact_impacts(&mut self.events.impacts);
+            if let Some(before) = held_before {
+                self.traverse_held(&before, world, &mut travelers);
+            }
+            self.crossing.tick(self.scheduler.step_dt);
+            if let Some(portal) = self.traverse_portals(camera, &before_step, &mut travelers, stats) {
+                self.hand_off(portal, world, &mut travelers);
+            }
+            self.keep_in_bounds(&mut travelers);
+            if let Some(capture) = self.capture.as_ref() {
+                capture.step();
+            }
+        }
+        let (pairs, active) = self.p.pair_counts();
+        self.counters.narrow_phase_pairs = pairs as u32;
+        self.counters.active_contacts = active as u32;
+        self.counters.contact_force_events = self.events.impacts.len() as u32;
+        walked
+    }
+
+    /// Move the platforms to where they are after the step.
+    fn step_platforms(&mut self) {
+        let dt = self.scheduler.step_dt;
+        for x in self.levels.iter_mut().flat_map(|x| x.platforms.iter_mut()) {
+            x.step(&mut self.p, dt);
+        }
+    }
+
+    /// Move me with the platform stood on in the step, the controller sees me on it in the next step.
+    fn carry_me(&mut self, carrier: Option<RigidBodyHandle>) {
+        let carrier = if let Some(x) = carrier { x } else {
+            return;
+        };
+        let delta = self.levels.iter()
+            .flat_map(|x| x.platforms.iter())
+            .find(|x| x.body == carrier)
+            .map(|x| x.delta);
+        if let Some(delta) = delta {
+            let me = &mut self.p.rigid_body_set[self.me.handle];
+            let next = me.translation() + delta;
+            me.set_translation(next, true);
+        }
+    }
+
+    /// Move me and the camera through the portal touched if the center crossed its plane from the front in the last step.
+    ///
+    /// Return the portal gone through.
+    fn traverse_portals(&mut self, camera: &mut Camera, before_step: &Vector3<f32>, travelers: &mut WriteStorage<PortalTraveler>, stats: &mut Option<FetchMut<Statistics>>) -> Option<(usize, usize)> {
+        while let Ok(event) = self.p.col_events.try_recv() {
+            trace!(target:"level::col", "Got col event {:?}", event);
+            self.counters.collision_events += 1;
+            if let Some(capture) = self.capture.as_ref() {
+                capture.record_collision(&event);
+            }
+            if let Some(portal) = self.portals_map.get_pair(self.me.body_bounding, event.collider1(), event.collider2()) {
+                self.crossing.touch(self.me.body_bounding, portal, event.started());
+            }
+            self.triggers.touch(self.me.body_bounding, event.collider1(), event.collider2(), event.started());
+        }
+        let now = *self.p.rigid_body_set[self.me.handle].translation();
+        let levels = &self.levels;
+        let crossed = self.crossing.touching(self.me.body_bounding)
+            .find(|(world, idx)| {
+                let portal = &levels[*world].portals[*idx];
+                portal.opening.is_open() && crossed_plane(before_step, &now, &portal.this.pos, &portal.this.out_normal)
+            });
+        self.crossing.end_step();
+        if let Some((world, idx)) = crossed {
+            let portal = &self.levels[world].portals[idx];
+            if !portal.gate.allows(&self.keys) {
+                let back = before_step + portal.this.out_normal * BOUNCE_DISTANCE * self.transition.current();
+                debug!(target: "level", "Bounced back from the portal {:?} for {:?}", (world, idx), portal.gate);
+                self.p.rigid_body_set[self.me.handle].set_translation(back, true);
+                self.scheduler.teleported(&self.p, self.me.handle);
+                camera.eye = Point3::from(back);
+                return None;
+            }
+            let before = camera.eye;
+            let camera_view = Coord::from_camera_portal(camera, portal);
+            let connecting = &self.levels[portal.connecting.0].portals[portal.connecting.1].this;
+            camera_view.change_camera_without_forward(camera, connecting);
+
+            camera.eye.z = connecting.pos.z;
+            camera.eye += connecting.out_normal * 0.02;
+
+            self.p.rigid_body_set[self.me.handle].set_translation(camera.eye.coords, true);
+            self.scheduler.teleported(&self.p, self.me.handle);
+            self.crossing.traversed(self.me.body_bounding, (world, idx), portal.connecting);
+            self.bounds.mark_safe(connecting.world, camera.eye.coords);
+            // the bounding and the camera scaled in the update
+            self.transition.traversed(portal.scale);
+            info!(target: "level", "From world {} to world {}", self.me_world, connecting.world);
+            if let Some(capture) = self.capture.as_ref() {
+                capture.record_traversal((world, idx), connecting.world, &before.coords, &camera.eye.coords);
+            }
+            if let Some(stats) = stats.as_mut() {
+                stats.add_portal_traversed();
+            }
+            self.hints.fire("portal");
+            if let Some(traveler) = self.entities.me.and_then(|x| travelers.get_mut(x)) {
+                traveler.world = connecting.world;
+                traveler.scale *= portal.scale;
+                traveler.traversed += 1;
+            }
+            self.me_world = connecting.world;
+            debug!(target:"level", "{:?} with {:?} => {:?}", before, camera_view, camera.eye);
+            self.levels[world].portals[idx].on_traverse.call(&mut self.keys);
+        }
+        crossed
+    }
+
+    /// Move me back to the last safe position if out of the bounds of the world.
+    fn keep_in_bounds(&mut self, travelers: &mut WriteStorage<PortalTraveler>) {
+        let pos = *self.p.rigid_body_set[self.me.handle].translation();
+        if self.me.grounded {
+            self.bounds.mark_safe(self.me_world, pos);
+        }
+        if !self.bounds.is_out(self.me_world, &pos) {
+            return;
+        }
+        let (world, to) = self.bounds.recover_to();
+        self.bounds.recovered += 1;
+        warn!(target: "level", "Out of the bounds at {:?} in world {}, recovered to {:?} in world {}", pos, self.me_world, to, world);
+        if let Some(capture) = self.capture.as_ref() {
+            capture.record(CaptureRecord::Recovered { world: self.me_world, pos: pos.into(), to_world: world, to: to.into() });
+        }
+        self.move_me(world, to, travelers);
+    }
+
+    /// Put me at the position in the world without going through the portals.
+    fn move_me(&mut self, world: usize, to: Vector3<f32>, travelers: &mut WriteStorage<PortalTraveler>) {
+        self.p.rigid_body_set[self.me.handle].set_translation(to, true);
+        self.scheduler.teleported(&self.p, self.me.handle);
+        self.crossing.remove(self.me.body_bounding);
+        self.me.vertical_speed = 0.0;
+        if let Some(traveler) = self.entities.me.and_then(|x| travelers.get_mut(x)) {
+            traveler.world = world;
+        }
+        self.me_world = world;
+    }
+
+    /// Scale my bounding touching the portals by my scale now.
+    fn scale_bounding(&mut self) {
+        let half = BOUNDING_HALF * self.transition.current();
+        if let Some(c) = self.p.collider_set[self.me.body_bounding].shape_mut().as_cuboid_mut() {
+            c.half_extents.x = half;
+            c.half_extents.y = half;
+        }
+    }
+
+    /// Continue with my scale and the keys saved, the easing in progress goes on.
+    pub fn restore(&mut self, world: &World, scale: ScaleTransition, keys: PortalKeys) {
+        self.transition = scale;
+        self.keys = keys;
+        self.scale_bounding();
+        let mut travelers = world.write_storage::<PortalTraveler>();
+        if let Some(traveler) = self.entities.me.and_then(|x| travelers.get_mut(x)) {
+            traveler.scale = scale.target();
+        }
+    }
+
+    /// Move me to the position in the world, recovered to it if out of the bounds.
+    pub fn teleport(&mut self, world: &World, me_world: usize, to: Vector3<f32>) -> anyhow::Result<()> {
+        if me_world >= self.levels.len() {
+            bail!("No world {} in the {} worlds", me_world, self.levels.len());
+        }
+        if self.bounds.is_out(me_world, &to) {
+            bail!("{:?} is out of the bounds of the world {}", to, me_world);
+        }
+        self.move_me(me_world, to, &mut world.write_storage::<PortalTraveler>());
+        self.bounds.set_checkpoint(me_world, to);
+        Ok(())
+    }
+
+    /// Cast the ray from the world, continuing through the portals hit from the front.
+    ///
+    /// The sensors except the portals are ignored, the portal on the wall is hit before the wall.
+    #[allow(unused)]
+    pub fn raycast_through_portals(&self, mut world: usize, mut ray: Ray, mut max_toi: f32, exclude: Option<RigidBodyHandle>) -> Option<PortalRayHit> {
+        let portals_map = &self.portals_map;
+        let levels = &self.levels;
+        let mut skip = None;
+        let mut to_hit = Similarity3::identity();
+        for portals in 0..=MAX_RAY_PORTALS {
+            let is_portal = |h, _: &_| portals_map.get(h).is_some_and(|x| Some(x) != skip && levels[x.0].portals[x.1].opening.is_open());
+            let mut filter = QueryFilter::default().exclude_sensors();
+            let mut portal_filter = QueryFilter::default().predicate(&is_portal);
+            if let Some(body) = exclude {
+                filter = filter.exclude_rigid_body(body);
+                portal_filter = portal_filter.exclude_rigid_body(body);
             }
+            let hit = self.p.raycast_closest(&ray, max_toi, filter);
+            let portal_toi = hit.map_or(max_toi, |x| (x.toi + CROSS_MARGIN).min(max_toi));
+            let portal_hit = if portals < MAX_RAY_PORTALS {
+                self.p.raycast_closest(&ray, portal_toi, portal_filter)
+            } else {
+                None
+            };
+            let (portal_hit, (portal_world, idx)) = match portal_hit.and_then(|x| portals_map.get(x.collider).map(|p| (x, p))) {
+                Some(x) => x,
+                None => return hit.map(|hit| PortalRayHit { world, hit, ray, portals, to_hit }),
+            };
+            let portal = &self.levels[portal_world].portals[idx];
+            max_toi -= portal_hit.toi;
+            if ray.dir.dot(&portal.this.out_normal) >= 0.0 {
+                // from the back, go on in the same world
+                ray.origin = portal_hit.point;
+                skip = Some((portal_world, idx));
+                continue;
+            }
+            let connecting = &self.levels[portal.connecting.0].portals[portal.connecting.1].this;
+            let similarity = portal.similarity(connecting);
+            ray = Ray::new(similarity * portal_hit.point, portal.through(connecting, &ray.dir));
+            to_hit = similarity * to_hit;
+            max_toi *= portal.scale;
+            world = connecting.world;
+            skip = Some(portal.connecting);
         }
+        None
+    }
 
-        camera.eye = Point3::from(*self.p.rigid_body_set[self.me.handle].translation());
+    /// Get the position heard in the world of me for the sound in the world,
+    /// through the nearest portal connecting to the world.
+    pub fn route_sound(&self, world: usize, pos: &Point3<f32>) -> Option<Point3<f32>> {
+        let me = Point3::from(*self.p.rigid_body_set[self.me.handle].translation());
+        self.levels[self.me_world].portals.iter()
+            .filter(|x| x.connecting.0 == world && x.opening.is_open() && !x.mirror)
+            .map(|here| {
+                let there = &self.levels[world].portals[here.connecting.1].this;
+                let dis = pos - there.pos;
+                let forward = there.out_normal.dot(&dis.coords);
+                let up = there.up.dot(&dis.coords);
+                let right = there.up.cross(&there.out_normal).dot(&dis.coords);
+                let this = &here.this;
+                // the sound outside the connecting portal is behind this portal.
+                let result = (-this.out_normal * forward
+                    + this.up * up
+                    - this.up.cross(&this.out_normal) * right) / here.scale
+                    + this.pos;
+                Point3::from(result)
+            })
+            .min_by(|a, b| (a - me).norm().total_cmp(&(b - me).norm()))
     }
-    //
+
+    /// Render the view in the portal, return the max recursion depth rendered.
     pub fn render_in_portal(&mut self, (world, idx): (usize, usize), rec_dep: usize,
                             camera: Camera,
                             ce: &mut CommandEncoder,
                             gpu: &mut WgpuData,
                             pr: &mut PlaneRenderer,
-                            portal_renderer: &mut PortalRenderer)
+                            portal_renderer: &mut PortalRenderer,
+                            pool: &mut PortalViewPool) -> usize
     {
         gpu.uniforms.data.camera.update_view_proj(&camera);
         gpu.uniforms.update_staging(&gpu.device, ce, &mut self.staging_belt);
 
+        self.counters.portals_recursed += 1;
+        self.counters.planes_drawn += plane_count(&self.levels[world].objs) + plane_count(&self.levels[world].layered)
+            + plane_count(&self.levels[world].pbr);
+        while self.portal_views.len() <= rec_dep {
+            let size = view_size(gpu.get_render_size(), self.view_falloff, self.portal_views.len());
+            self.portal_views.push(pool.take(gpu, pr, portal_renderer, size));
+        }
         let pv = &self.portal_views[rec_dep];
         let level = &self.levels[world];
         let portal = &level.portals[idx];
         // first render the portal frame
+        let scope = gpu.begin_timing(ce, "portal depth");
         {
             let mut rp = ce.begin_render_pass(&RenderPassDescriptor {
                 label: Some("Render portal depth pass"),
@@ -309,6 +1303,8 @@ impl MagicLevel {
             rp.set_pipeline(&pr.depth_only_rp);
             pr.render_static(&mut rp, gpu, from_ref(&portal.portal_render));
         }
+        gpu.end_timing(ce, scope);
+        let scope = gpu.begin_timing(ce, "portal view");
         {
             // then render scenes
             let mut rp = ce.begin_with_depth(&pv.color.view, LoadOp::Clear(Color::TRANSPARENT),
@@ -317,13 +1313,38 @@ impl MagicLevel {
             rp.set_pipeline(&portal_renderer.portal_view_rp);
             rp.set_bind_group(2, &pv.pd.bindgroup, &[]);
             pr.render_static(&mut rp, gpu, &level.objs);
+            if !level.layered.is_empty() {
+                rp.set_pipeline(&portal_renderer.portal_array_rp);
+                pr.render_static(&mut rp, gpu, &level.layered);
+            }
+            if !level.pbr.is_empty() {
+                rp.set_pipeline(&portal_renderer.portal_pbr_rp);
+                pr.render_static(&mut rp, gpu, &level.pbr);
+            }
+            if !level.models.is_empty() {
+                rp.set_pipeline(&portal_renderer.portal_model_rp);
+                pr.render_models(&mut rp, &level.models);
+            }
+            if !level.platforms.is_empty() {
+                rp.set_pipeline(&portal_renderer.portal_view_rp);
+                for x in &level.platforms {
+                    pr.render_static(&mut rp, gpu, from_ref(&x.planes));
+                }
+            }
+            if !self.avatars.is_empty() || !self.meshes.is_empty() || !self.body.is_empty() {
+                rp.set_pipeline(&portal_renderer.portal_view_rp);
+                self.avatars.render(&mut rp, gpu, pr, world);
+                self.meshes.render(&mut rp, gpu, pr, world);
+                self.body.render(&mut rp, gpu, pr, world, true);
+            }
+            pr.sky.render(&mut rp);
         }
+        gpu.end_timing(ce, scope);
 
 
-        // next dep will overflow
-        if rec_dep + 1 >= self.portal_views.len() {
-            return;
-        }
+        let mut max_dep = rec_dep + 1;
+        // the portals deeper are filled by the fallback color
+        let fallback = rec_dep + 1 >= self.depth.depth();
         for p_world in 0..self.levels.len() {
             for portal_idx in 0..self.levels[p_world].portals.len() {
                 if idx == portal_idx && p_world == world {
@@ -331,10 +1352,16 @@ impl MagicLevel {
                 }
 
                 let this_portal = &self.levels[p_world].portals[portal_idx];
+                if this_portal.opening.is_closed() {
+                    continue;
+                }
+                self.counters.portals_considered += 1;
                 if (this_portal.this.pos.z - camera.eye.z).abs() > 5.0 {
+                    self.counters.planes_culled += 1;
                     continue;
                 }
                 if !will_see_face(&gpu.uniforms.data.camera.view_proj, &this_portal.plane) {
+                    self.counters.planes_culled += 1;
                     continue;
                 }
 
@@ -346,14 +1373,24 @@ impl MagicLevel {
                 }
 
                 trace!(target:"level", "We can see portal at world {p_world} [{portal_idx}] (dep={})", rec_dep);
+                if fallback {
+                    let cpv = &self.portal_views[rec_dep];
+                    let mut rp = ce.begin_with_depth(&cpv.color.view, LoadOp::Load,
+                                                     &cpv.depth.view, LoadOp::Load);
+                    pr.bind(&mut rp);
+                    rp.set_bind_group(1, &portal_renderer.fallback_bind, &[]);
+                    rp.set_bind_group(2, &cpv.pd.bindgroup, &[]);
+                    rp.set_bind_group(3, &portal_renderer.fade_bind, &[portal_renderer.fade_offset(self.fade_slot((p_world, portal_idx)))]);
+                    rp.set_pipeline(&portal_renderer.fallback_rp);
+                    pr.render_static(&mut rp, gpu, from_ref(&this_portal.portal_render));
+                    continue;
+                }
 
                 let connecting = &self.levels[this_portal.connecting.0].portals[this_portal.connecting.1];
-                let camera_coord = Coord::from_camera_portal_for_view(&camera, &this_portal);
-                let mut portal_camera = camera;
-                camera_coord.change_camera_for_portal(&mut portal_camera, &connecting.this);
+                let portal_camera = this_portal.view_camera(&camera, &connecting.this);
 
 
-                self.render_in_portal(this_portal.connecting, rec_dep + 1, portal_camera, ce, gpu, pr, portal_renderer);
+                max_dep = max_dep.max(self.render_in_portal(this_portal.connecting, rec_dep + 1, portal_camera, ce, gpu, pr, portal_renderer, pool));
 
                 gpu.uniforms.data.camera.update_view_proj(&camera);
                 gpu.uniforms.update_staging(&gpu.device, ce, &mut self.staging_belt);
@@ -367,71 +1404,168 @@ impl MagicLevel {
                 pr.bind(&mut rp);
                 rp.set_bind_group(1, &self.portal_views[rec_dep + 1].color_bind, &[]);
                 rp.set_bind_group(2, &cpv.pd.bindgroup, &[]);
-                rp.set_pipeline(&portal_renderer.render_portal_view_rp);
+                rp.set_bind_group(3, &portal_renderer.fade_bind, &[portal_renderer.fade_offset(self.fade_slot((p_world, portal_idx)))]);
+                rp.set_pipeline(if this_portal.mirror { &portal_renderer.mirror_view_rp } else { &portal_renderer.render_portal_view_rp });
                 pr.render_static(&mut rp, gpu, from_ref(&this_portal.portal_render));
             }
         }
+        // over the portals in the view, clipped by the portal seeing through
+        let labels = self.portal_labels(world);
+        let pv = &self.portal_views[rec_dep];
+        let portal = &self.levels[world].portals[idx].this;
+        pr.text.draw(&gpu.device, &mut self.staging_belt, ce, &pv.color.view, &pv.depth.view,
+                     &camera, &camera.build_view_projection_matrix(), &labels, Some((portal.pos, portal.out_normal)));
+        max_dep
     }
 
+    /// Render the level to the scene, return the max recursion depth of portals rendered.
     pub fn render<'a>(&'a mut self, camera: Camera,
                       ce: &mut CommandEncoder,
                       gpu: &mut WgpuData,
                       pr: &mut PlaneRenderer,
-                      portal_renderer: &mut PortalRenderer)
+                      portal_renderer: &mut PortalRenderer,
+                      pool: &mut PortalViewPool) -> usize
     {
         self.staging_belt.recall();
-        if self.portal_views[0].color.info.width != gpu.surface_cfg.width || self.portal_views[0].color.info.height != gpu.surface_cfg.height {
-            for x in &mut self.portal_views {
-                *x = PortalView::new(gpu, pr, portal_renderer);
+        if let Some(lights) = self.triggers.lights.take() {
+            pr.update_lights(&lights);
+        }
+        let fades = self.levels.iter()
+            .flat_map(|x| x.portals.iter().map(|x| x.opening.current()))
+            .collect::<Vec<_>>();
+        let staged = {
+            // the uniforms of the frame are copied before the passes
+            let mut stager = UniformStager::new(&gpu.device, ce, &mut self.staging_belt);
+            for level in &mut self.levels {
+                for model in &mut level.models {
+                    model.sync(&mut stager);
+                }
+            }
+            pr.stage_uniforms(&mut stager);
+            portal_renderer.write_fades(&gpu.device, &mut stager, &fades);
+            (stager.writes, stager.bytes)
+        };
+        for level in &mut self.levels {
+            level.bundle.sync(gpu, pr, &level.objs);
+        }
+        let render_size = gpu.get_render_size();
+        let resized = self.portal_views.iter().enumerate().position(|(dep, x)| {
+            view_size(render_size, self.view_falloff, dep) != (x.color.info.width, x.color.info.height)
+        });
+        if let Some(dep) = resized {
+            // taken again in the new size when rendered
+            for x in self.portal_views.drain(dep..) {
+                pool.put(x);
             }
         }
 
-
+        if let Some(debug) = self.physics_debug.as_mut() {
+            debug.update(&gpu.device, &self.p);
+        }
+        self.interactions.update_highlight(&gpu.device, &self.p);
+
+        let mut max_dep = 0;
+        self.counters.reset_render();
+        (self.counters.uniform_writes, self.counters.uniform_bytes) = staged;
+        self.counters.planes_drawn += plane_count(&self.levels[self.me_world].objs) + plane_count(&self.levels[self.me_world].layered)
+            + plane_count(&self.levels[self.me_world].pbr);
+        let scope = gpu.begin_timing(ce, "scene");
         {
-            let mut rp = ce.begin_with_depth(&gpu.views.get_screen().view, LoadOp::Clear(Color::BLACK),
+            let mut rp = ce.begin_with_depth(&gpu.views.get_scene().view, LoadOp::Clear(Color::BLACK),
                                              &gpu.views.get_depth_view().view, LoadOp::Clear(1.0));
             let level = &self.levels[self.me_world];
             level.render(&mut rp, gpu, pr);
+            if !self.avatars.is_empty() || !self.meshes.is_empty() || !self.body.is_empty() {
+                pr.bind(&mut rp);
+                rp.set_pipeline(&pr.no_cull_rp);
+                self.avatars.render(&mut rp, gpu, pr, self.me_world);
+                self.meshes.render(&mut rp, gpu, pr, self.me_world);
+                self.body.render(&mut rp, gpu, pr, self.me_world, false);
+            }
+            // the level rendered by the bundles resets the bind groups
+            pr.bind(&mut rp);
+            pr.sky.render(&mut rp);
+            let lines = self.physics_debug.as_ref().and_then(|x| x.lines.as_ref()).into_iter()
+                .chain(self.interactions.highlight.as_ref())
+                .collect::<Vec<_>>();
+            if !lines.is_empty() {
+                pr.bind(&mut rp);
+                rp.set_pipeline(&pr.line_rp);
+                for x in lines {
+                    pr.render_lines(&mut rp, x);
+                }
+            }
         }
+        gpu.end_timing(ce, scope);
 
+        let mut visible = vec![];
         for world in 0..self.levels.len() {
             for portal_idx in 0..self.levels[world].portals.len() {
                 let this_portal = &self.levels[world].portals[portal_idx];
+                if this_portal.opening.is_closed() {
+                    continue;
+                }
+                self.counters.portals_considered += 1;
 
                 if !will_see_face(&gpu.uniforms.data.camera.view_proj, &this_portal.plane) {
+                    self.counters.planes_culled += 1;
                     continue;
                 }
                 if (this_portal.this.pos.z - camera.eye.z).abs() > 5.0 {
+                    self.counters.planes_culled += 1;
                     continue;
                 }
+                visible.push((world, portal_idx));
+            }
+        }
+        // render the looked last to keep its view in the first portal view
+        self.looked_portal = visible.iter()
+            .filter_map(|&(world, idx)| {
+                let uv = screen_uv(&gpu.uniforms.data.camera.view_proj, &self.levels[world].portals[idx].plane)?;
+                Some(LookedPortal { world, idx, uv })
+            })
+            .min_by(|a, b| {
+                let center = egui::pos2(0.5, 0.5);
+                a.uv.distance_sq_to_pos(center).total_cmp(&b.uv.distance_sq_to_pos(center))
+            });
+        if let Some(looked) = self.looked_portal {
+            visible.retain(|x| *x != (looked.world, looked.idx));
+            visible.push((looked.world, looked.idx));
+        }
 
-                trace!(target:"level", "We can see portal at world {} [{portal_idx}]", world);
-                let connecting = &self.levels[this_portal.connecting.0].portals[this_portal.connecting.1];
-                let camera_coord = Coord::from_camera_portal_for_view(&camera, &this_portal);
-                let mut portal_camera = camera;
-                camera_coord.change_camera_for_portal(&mut portal_camera, &connecting.this);
+        for (world, portal_idx) in visible {
+            let this_portal = &self.levels[world].portals[portal_idx];
+            trace!(target:"level", "We can see portal at world {} [{portal_idx}]", world);
+            let connecting = &self.levels[this_portal.connecting.0].portals[this_portal.connecting.1];
+            let portal_camera = this_portal.view_camera(&camera, &connecting.this);
 
 
-                self.render_in_portal(this_portal.connecting, 0, portal_camera, ce, gpu, pr, portal_renderer);
+            max_dep = max_dep.max(self.render_in_portal(this_portal.connecting, 0, portal_camera, ce, gpu, pr, portal_renderer, pool));
 
-                gpu.uniforms.data.camera.update_view_proj(&camera);
-                gpu.uniforms.update_staging(&gpu.device, ce, &mut self.staging_belt);
+            gpu.uniforms.data.camera.update_view_proj(&camera);
+            gpu.uniforms.update_staging(&gpu.device, ce, &mut self.staging_belt);
 
-                // render the result to screen
+            // render the result to screen
 
-                let mut rp = ce.begin_with_depth(&gpu.views.get_screen().view, LoadOp::Load,
-                                                 &gpu.views.get_depth_view().view, LoadOp::Load);
-                let this_portal = &self.levels[world].portals[portal_idx];
+            let mut rp = ce.begin_with_depth(&gpu.views.get_scene().view, LoadOp::Load,
+                                             &gpu.views.get_depth_view().view, LoadOp::Load);
+            let this_portal = &self.levels[world].portals[portal_idx];
 
-                pr.bind(&mut rp);
-                rp.set_bind_group(1, &self.portal_views[0].color_bind, &[]);
-                rp.set_pipeline(&pr.screen_tex_no_cull_rp);
-                pr.render_static(&mut rp, gpu, from_ref(&this_portal.portal_render));
-            }
+            pr.bind(&mut rp);
+            rp.set_bind_group(1, &self.portal_views[0].color_bind, &[]);
+            // not sampled, the layout shared with the views in the portals
+            rp.set_bind_group(2, &self.portal_views[0].pd.bindgroup, &[]);
+            rp.set_bind_group(3, &portal_renderer.fade_bind, &[portal_renderer.fade_offset(self.fade_slot((world, portal_idx)))]);
+            rp.set_pipeline(if this_portal.mirror { &portal_renderer.screen_mirror_rp } else { &portal_renderer.screen_view_rp });
+            pr.render_static(&mut rp, gpu, from_ref(&this_portal.portal_render));
         }
+        let labels = self.portal_labels(self.me_world);
+        pr.text.draw(&gpu.device, &mut self.staging_belt, ce, &gpu.views.get_scene().view, &gpu.views.get_depth_view().view,
+                     &camera, &camera.build_view_projection_matrix(), &labels, None);
         gpu.uniforms.data.camera.update_view_proj(&camera);
         gpu.uniforms.update_staging(&gpu.device, ce, &mut self.staging_belt);
         self.staging_belt.finish();
+        max_dep
     }
 
     pub fn render_portal<'a: 'rp, 'rp, 'pr: 'rp>(&'a self, _camera: Camera,
@@ -1,4 +1,5 @@
 use anyhow::anyhow;
+use crate::engine::config::DEFAULT_PORTAL_VIEW_FALLOFF;
 use crate::engine::physics::state::RapierData;
 use crate::state::real_view::level::*;
 use crate::engine::prelude::*;
@@ -6,20 +7,32 @@ use crate::engine::renderer3d::renderer3d::*;
 
 use nalgebra::*;
 use num::Zero;
-use rapier3d::prelude::*;
 use wgpu::util::StagingBelt;
-use crate::engine::physics::obj::Object;
-use crate::state::real_view::renderer::portal::{PortalRenderer, PortalView};
+use crate::engine::pacing::mark_frame_event;
+use crate::engine::physics::obj::KinematicObject;
+use crate::state::real_view::bounds::LevelBounds;
+use crate::state::real_view::hint::{Hint, HintOverlay, HintTrigger};
+use crate::state::real_view::gate::PortalGate;
+use crate::state::real_view::level_gen::PORTAL_INSET;
+use crate::state::real_view::level_rooms::player_object;
+use crate::state::real_view::platform::{PLATFORM_THICKNESS, PlatformPath};
+use crate::state::real_view::room_builder::RoomBuilder;
+use crate::state::real_view::trigger::TriggerAction;
+
+/// The planes of a world without the textures, bound when built.
+enum WorldPlanes {
+    /// The floor and the walls in one draw by the layers of gf and bf.
+    Layered { floor: Planes, walls: Planes },
+    /// The planes by their texture, `no_cull` for the tunnels seen from both sides.
+    Textured { planes: Vec<(&'static str, Planes)>, no_cull: bool },
+}
 
-fn normal_level(p: &mut RapierData, gpu: &WgpuData, pr: &mut PlaneRenderer, res: &ResourceManager) -> anyhow::Result<Level> {
-    let gf = res.textures.get("gf").ok_or(anyhow!("NO TEXTURE"))?;
-    let bf = res.textures.get("bf").ok_or(anyhow!("NO TEXTURE"))?;
-    let pf = res.textures.get("pf").ok_or(anyhow!("NO TEXTURE"))?;
-    let mut gfs = pr.create_plane(&gpu.device, Some(&gf.view));
+fn normal_level(p: &mut RapierData) -> WorldPlanes {
+    let mut gfs = Planes { objs: vec![], texture_bind: None };
 
     add_plane(p, &mut gfs, &Vector3::zeros(), 10.0, &Vector2::zeros(), 5.0, &Vector3::z(), &Vector3::x());
 
-    let mut bfs = pr.create_plane(&gpu.device, Some(&bf.view));
+    let mut bfs = Planes { objs: vec![], texture_bind: None };
     add_plane(p, &mut bfs, &vector![0.0, 1.0, 1.0], 1.0, &Vector2::zeros(), 0.5, &Vector3::y(), &Vector3::x());
     add_plane(p, &mut bfs, &vector![0.0, -1.0, 1.0], 1.0, &Vector2::zeros(), 0.5, &-Vector3::y(), &Vector3::x());
     add_plane(p, &mut bfs, &vector![0.0, 0.0, 2.0], 1.0, &Vector2::zeros(), 0.5, &Vector3::z(), &Vector3::x());
@@ -49,217 +62,73 @@ fn normal_level(p: &mut RapierData, gpu: &WgpuData, pr: &mut PlaneRenderer, res:
     add_plane(p, &mut bfs, &vector![-10.0, 9.0, 1.0], 1.0, &Vector2::zeros(), 0.5, &Vector3::x(), &Vector3::y());
     add_plane(p, &mut bfs, &vector![-9.0, 10.0, 1.0], 1.0, &Vector2::zeros(), 0.5, &-Vector3::y(), &Vector3::x());
 
-    let mut pfs = pr.create_plane(&gpu.device, Some(&pf.view));
-    pfs.objs.push(PlaneObject::new(&vector![-1.0, 0.0, 1.0], 1.0, &Vector2::zeros(), 0.5, &-Vector3::x(), &Vector3::y()));
-
-    let mut planes = vec![];
-    planes.push(gfs.to_static(&gpu.device));
-    planes.push(bfs.to_static(&gpu.device));
-
-    let mut bundle = gpu.device.create_render_bundle_encoder(&RenderBundleEncoderDescriptor {
-        label: None,
-        color_formats: &[Some(gpu.surface_cfg.format)],
-        depth_stencil: Some(RenderBundleDepthStencil {
-            format: TextureFormat::Depth32Float,
-            depth_read_only: false,
-            stencil_read_only: false,
-        }),
-        sample_count: 1,
-        multiview: None,
-    });
-    bundle.set_pipeline(&pr.normal_rp);
-    pr.bind(&mut bundle);
-    pr.render_static(&mut bundle, gpu, &planes[..]);
-    let bundle = bundle.finish(&RenderBundleDescriptor {
-        label: None,
-    });
-    Ok(Level {
-        portals: vec![],
-        objs: planes,
-        bundle,
-    })
+    WorldPlanes::Layered { floor: gfs, walls: bfs }
 }
 
-fn long_tunnel(p: &mut RapierData, gpu: &WgpuData, pr: &mut PlaneRenderer, res: &ResourceManager) -> anyhow::Result<Level> {
-    let gf = res.textures.get("gf").ok_or(anyhow!("NO TEXTURE"))?;
-    let bf = res.textures.get("bf").ok_or(anyhow!("NO TEXTURE"))?;
-    let mut gfs = pr.create_plane(&gpu.device, Some(&gf.view));
+fn long_tunnel(p: &mut RapierData) -> WorldPlanes {
+    let mut gfs = Planes { objs: vec![], texture_bind: None };
 
     // we are in -1 ~ 1
     // but in facts 5
     // so -5 ~ 5
     add_plane(p, &mut gfs, &vector![0.0, 0.0, Z_OFFSET * 2.0], 10.0, &Vector2::zeros(), 25.0, &Vector3::z(), &Vector3::x());
 
-    let mut bfs = pr.create_plane(&gpu.device, Some(&bf.view));
+    let mut bfs = Planes { objs: vec![], texture_bind: None };
     add_plane(p, &mut bfs, &vector![0.0, 1.0, 5.0 + Z_OFFSET * 2.0], 5.0, &Vector2::zeros(), 2.5, &Vector3::y(), &Vector3::x());
     add_plane(p, &mut bfs, &vector![0.0, -1.0, 5.0 + Z_OFFSET * 2.0], 5.0, &vector![0.5, 0.0], 2.5, &Vector3::y(), &Vector3::x());
     add_plane(p, &mut bfs, &vector![0.0, 0.0, 2.0 + Z_OFFSET * 2.0], 5.0, &vector![0.5, 0.0], 2.5, &-Vector3::z(), &Vector3::x());
 
-
-    let mut planes = vec![];
-    planes.push(gfs.to_static(&gpu.device));
-    planes.push(bfs.to_static(&gpu.device));
-
-    let mut bundle = gpu.device.create_render_bundle_encoder(&RenderBundleEncoderDescriptor {
-        label: None,
-        color_formats: &[Some(gpu.surface_cfg.format)],
-        depth_stencil: Some(RenderBundleDepthStencil {
-            format: TextureFormat::Depth32Float,
-            depth_read_only: false,
-            stencil_read_only: false,
-        }),
-        sample_count: 1,
-        multiview: None,
-    });
-    bundle.set_pipeline(&pr.no_cull_rp);
-    pr.bind(&mut bundle);
-    pr.render_static(&mut bundle, gpu, &planes[..]);
-    let bundle = bundle.finish(&RenderBundleDescriptor {
-        label: None,
-    });
-    Ok(Level {
-        portals: vec![],
-        objs: planes,
-        bundle,
-    })
+    WorldPlanes::Textured { planes: vec![("gf", gfs), ("bf", bfs)], no_cull: true }
 }
 
-fn long_inside(p: &mut RapierData, gpu: &WgpuData, pr: &mut PlaneRenderer, res: &ResourceManager) -> anyhow::Result<Level> {
-    let gf = res.textures.get("gf").ok_or(anyhow!("NO TEXTURE"))?;
-    let bf = res.textures.get("bf").ok_or(anyhow!("NO TEXTURE"))?;
-    let mut gfs = pr.create_plane(&gpu.device, Some(&gf.view));
+fn long_inside(p: &mut RapierData) -> WorldPlanes {
+    let mut gfs = Planes { objs: vec![], texture_bind: None };
 
     // we are in -1 ~ 1
     // but in facts 5
     // so -5 ~ 5
     add_plane(p, &mut gfs, &vector![0.0, 0.0, Z_OFFSET * 10.0], 5.0, &Vector2::zeros(), 2.5, &Vector3::z(), &Vector3::x());
 
-    let mut bfs = pr.create_plane(&gpu.device, Some(&bf.view));
+    let mut bfs = Planes { objs: vec![], texture_bind: None };
     add_plane(p, &mut bfs, &vector![0.0, 1.0, 5.0 + Z_OFFSET * 10.0], 5.0, &Vector2::zeros(), 2.5, &Vector3::y(), &Vector3::x());
     add_plane(p, &mut bfs, &vector![0.0, -1.0, 5.0 + Z_OFFSET * 10.0], 5.0, &vector![0.5, 0.0], 2.5, &Vector3::y(), &Vector3::x());
     add_plane(p, &mut bfs, &vector![0.0, 0.0, 2.0 + Z_OFFSET * 10.0], 5.0, &vector![0.5, 0.0], 2.5, &-Vector3::z(), &Vector3::x());
 
-
-    let mut planes = vec![];
-    planes.push(gfs.to_static(&gpu.device));
-    planes.push(bfs.to_static(&gpu.device));
-
-    let mut bundle = gpu.device.create_render_bundle_encoder(&RenderBundleEncoderDescriptor {
-        label: None,
-        color_formats: &[Some(gpu.surface_cfg.format)],
-        depth_stencil: Some(RenderBundleDepthStencil {
-            format: TextureFormat::Depth32Float,
-            depth_read_only: false,
-            stencil_read_only: false,
-        }),
-        sample_count: 1,
-        multiview: None,
-    });
-    bundle.set_pipeline(&pr.no_cull_rp);
-    pr.bind(&mut bundle);
-    pr.render_static(&mut bundle, gpu, &planes[..]);
-    let bundle = bundle.finish(&RenderBundleDescriptor {
-        label: None,
-    });
-    Ok(Level {
-        portals: vec![],
-        objs: planes,
-        bundle,
-    })
+    WorldPlanes::Textured { planes: vec![("gf", gfs), ("bf", bfs)], no_cull: true }
 }
 
-fn short_inside(p: &mut RapierData, gpu: &WgpuData, pr: &mut PlaneRenderer, res: &ResourceManager) -> anyhow::Result<Level> {
-    let gf = res.textures.get("gf").ok_or(anyhow!("NO TEXTURE"))?;
-    let bf = res.textures.get("bf").ok_or(anyhow!("NO TEXTURE"))?;
-    let mut gfs = pr.create_plane(&gpu.device, Some(&gf.view));
+fn short_inside(p: &mut RapierData) -> WorldPlanes {
+    let mut gfs = Planes { objs: vec![], texture_bind: None };
 
 
     add_plane(p, &mut gfs, &vector![0.0, 0.0, Z_OFFSET * 15.0], 1.0, &vector![0.5, 0.0], 0.5, &Vector3::z(), &Vector3::x());
 
-    let mut bfs = pr.create_plane(&gpu.device, Some(&bf.view));
+    let mut bfs = Planes { objs: vec![], texture_bind: None };
     add_plane(p, &mut bfs, &vector![0.0, 1.0, 1.0 + Z_OFFSET * 15.0], 1.0, &Vector2::zeros(), 0.5, &Vector3::y(), &Vector3::x());
     add_plane(p, &mut bfs, &vector![0.0, -1.0, 1.0 + Z_OFFSET * 15.0], 1.0, &vector![0.5, 0.0], 0.5, &Vector3::y(), &Vector3::x());
     add_plane(p, &mut bfs, &vector![0.0, 0.0, 2.0 + Z_OFFSET * 15.0], 1.0, &vector![0.5, 0.0], 0.5, &-Vector3::z(), &Vector3::x());
 
-
-    let mut planes = vec![];
-    planes.push(gfs.to_static(&gpu.device));
-    planes.push(bfs.to_static(&gpu.device));
-
-    let mut bundle = gpu.device.create_render_bundle_encoder(&RenderBundleEncoderDescriptor {
-        label: None,
-        color_formats: &[Some(gpu.surface_cfg.format)],
-        depth_stencil: Some(RenderBundleDepthStencil {
-            format: TextureFormat::Depth32Float,
-            depth_read_only: false,
-            stencil_read_only: false,
-        }),
-        sample_count: 1,
-        multiview: None,
-    });
-    bundle.set_pipeline(&pr.no_cull_rp);
-    pr.bind(&mut bundle);
-    pr.render_static(&mut bundle, gpu, &planes[..]);
-    let bundle = bundle.finish(&RenderBundleDescriptor {
-        label: None,
-    });
-    Ok(Level {
-        portals: vec![],
-        objs: planes,
-        bundle,
-    })
+    WorldPlanes::Textured { planes: vec![("gf", gfs), ("bf", bfs)], no_cull: true }
 }
 
-fn fat_tunnel(p: &mut RapierData, gpu: &WgpuData, pr: &mut PlaneRenderer, res: &ResourceManager) -> anyhow::Result<Level> {
-    let gf = res.textures.get("gf").ok_or(anyhow!("NO TEXTURE"))?;
-    let bf = res.textures.get("bf").ok_or(anyhow!("NO TEXTURE"))?;
-    let pf = res.textures.get("pf").ok_or(anyhow!("NO TEXTURE"))?;
-    let mut gfs = pr.create_plane(&gpu.device, Some(&gf.view));
+fn fat_tunnel(p: &mut RapierData) -> WorldPlanes {
+    let mut gfs = Planes { objs: vec![], texture_bind: None };
 
     // we are in -1 ~ 1
     // but in facts 5
     // so -5 ~ 5
     add_plane(p, &mut gfs, &vector![0.0, 0.0, Z_OFFSET], 20.0, &Vector2::zeros(), 20.0, &Vector3::z(), &Vector3::x());
 
-    let mut bfs = pr.create_plane(&gpu.device, Some(&bf.view));
+    let mut bfs = Planes { objs: vec![], texture_bind: None };
     add_plane(p, &mut bfs, &vector![0.0, 5.0, 5.0 + Z_OFFSET], 5.0, &Vector2::zeros(), 2.5, &Vector3::y(), &Vector3::x());
     add_plane(p, &mut bfs, &vector![0.0, -5.0, 5.0 + Z_OFFSET], 5.0, &vector![0.5, 0.0], 2.5, &Vector3::y(), &Vector3::x());
     add_plane(p, &mut bfs, &vector![0.0, 0.0, 10.0 + Z_OFFSET], 5.0, &vector![0.5, 0.0], 2.5, &-Vector3::z(), &Vector3::x());
 
-    let mut pfs = pr.create_plane(&gpu.device, Some(&pf.view));
-    pfs.objs.push(PlaneObject::new(&vector![-1.0, 0.0, 1.0 + Z_OFFSET], 5.0, &Vector2::zeros(), 2.5, &Vector3::x(), &Vector3::y()));
-
-    let mut planes = vec![];
-    planes.push(gfs.to_static(&gpu.device));
-    planes.push(bfs.to_static(&gpu.device));
-
-    let mut bundle = gpu.device.create_render_bundle_encoder(&RenderBundleEncoderDescriptor {
-        label: None,
-        color_formats: &[Some(gpu.surface_cfg.format)],
-        depth_stencil: Some(RenderBundleDepthStencil {
-            format: TextureFormat::Depth32Float,
-            depth_read_only: false,
-            stencil_read_only: false,
-        }),
-        sample_count: 1,
-        multiview: None,
-    });
-    bundle.set_pipeline(&pr.no_cull_rp);
-    pr.bind(&mut bundle);
-    pr.render_static(&mut bundle, gpu, &planes[..]);
-    let bundle = bundle.finish(&RenderBundleDescriptor {
-        label: None,
-    });
-    Ok(Level {
-        portals: vec![],
-        objs: planes,
-        bundle,
-    })
+    WorldPlanes::Textured { planes: vec![("gf", gfs), ("bf", bfs)], no_cull: true }
 }
 
-fn get_color_level_loop(color: &str, zo: f32, p: &mut RapierData, gpu: &WgpuData, pr: &mut PlaneRenderer, res: &ResourceManager) -> anyhow::Result<Level> {
-    let gf = res.textures.get(color).ok_or(anyhow!("NO TEXTURE"))?;
-    let mut gfs = pr.create_plane(&gpu.device, Some(&gf.view));
+fn get_color_level_loop(color: &'static str, zo: f32, p: &mut RapierData) -> WorldPlanes {
+    let mut gfs = Planes { objs: vec![], texture_bind: None };
 
 
     // we are in the rect [-2, 2]
@@ -284,65 +153,131 @@ fn get_color_level_loop(color: &str, zo: f32, p: &mut RapierData, gpu: &WgpuData
     add_plane(p, &mut gfs, &vector![-1.0, -2.0, 1.0 + zo], 1.0, &Vector2::zeros(), 0.5, &Vector3::y(), &Vector3::x());
     add_plane(p, &mut gfs, &vector![-2.0, -1.0, 1.0 + zo], 1.0, &Vector2::zeros(), 0.5, &Vector3::x(), &Vector3::y());
 
+    WorldPlanes::Textured { planes: vec![(color, gfs)], no_cull: false }
+}
+
+/// Bind the textures of the world planned and upload its planes.
+fn build_world(world: WorldPlanes, gpu: &WgpuData, pr: &mut PlaneRenderer, res: &ResourceManager) -> anyhow::Result<Level> {
     let mut planes = vec![];
-    planes.push(gfs.to_static(&gpu.device));
-
-    let mut bundle = gpu.device.create_render_bundle_encoder(&RenderBundleEncoderDescriptor {
-        label: None,
-        color_formats: &[Some(gpu.surface_cfg.format)],
-        depth_stencil: Some(RenderBundleDepthStencil {
-            format: TextureFormat::Depth32Float,
-            depth_read_only: false,
-            stencil_read_only: false,
-        }),
-        sample_count: 1,
-        multiview: None,
-    });
-    bundle.set_pipeline(&pr.normal_rp);
-    pr.bind(&mut bundle);
-    pr.render_static(&mut bundle, gpu, &planes[..]);
-    let bundle = bundle.finish(&RenderBundleDescriptor {
-        label: None,
-    });
+    let mut layers = vec![];
+    let no_cull = match world {
+        WorldPlanes::Layered { floor, walls } => {
+            let gf = res.texture("gf").ok_or(anyhow!("NO TEXTURE gf"))?;
+            let bf = res.texture("bf").ok_or(anyhow!("NO TEXTURE bf"))?;
+            // the floor and the walls in one draw by the layers
+            let floors = TextureArray::from_textures(&gpu.device, &gpu.queue, &[&gf, &bf], Some("normal level floors"))?;
+            let mut layered = pr.create_plane_array(&gpu.device, &floors, &SamplerOptions::default());
+            layered.objs = floor.objs.into_iter()
+                .chain(walls.objs.into_iter().map(|x| x.with_layer(1)))
+                .collect();
+            layers.push(layered.to_arena(gpu, &mut pr.arena));
+            false
+        }
+        WorldPlanes::Textured { planes: textured, no_cull } => {
+            for (name, x) in textured {
+                let texture = res.texture(name).ok_or(anyhow!("NO TEXTURE {}", name))?;
+                let x = Planes { texture_bind: pr.create_plane(&gpu.device, Some(texture.srgb_view())).texture_bind, ..x };
+                planes.push(x.to_arena(gpu, &mut pr.arena));
+            }
+            no_cull
+        }
+    };
+
+    let bundle = DirtyBundle::new(gpu, pr, &planes, no_cull);
     Ok(Level {
         portals: vec![],
         objs: planes,
+        layered: layers,
+        pbr: vec![],
+        models: vec![],
+        props: vec![],
         bundle,
+        platforms: vec![],
     })
 }
-impl MagicLevel {
-    pub fn level0(gpu: &WgpuData, pr: &mut PlaneRenderer, portal_renderer: &PortalRenderer, res: &ResourceManager) -> anyhow::Result<Self> {
-        let mut levels = vec![];
+
+/// The worlds of the level 0 planned without the gpu, its portals and rooms are added when built.
+pub struct Level0Plan {
+    pub(crate) p: RapierData,
+    worlds: Vec<WorldPlanes>,
+    pub(crate) me: KinematicObject,
+}
+
+impl Level0Plan {
+    pub fn new() -> Self {
         let mut p = RapierData::new();
         p.g.set_zero();
 
-        levels.push(normal_level(&mut p, gpu, pr, res)?);
-        levels.push(fat_tunnel(&mut p, gpu, pr, res)?);
-        levels.push(long_tunnel(&mut p, gpu, pr, res)?);
-        levels.push(long_inside(&mut p, gpu, pr, res)?);
-        levels.push(short_inside(&mut p, gpu, pr, res)?);
-        levels.push(get_color_level_loop("black_f", 29.0, &mut p, gpu, pr, res)?);
-        levels.push(get_color_level_loop("gray_f", 57.0, &mut p, gpu, pr, res)?);
-        let me = RigidBodyBuilder::dynamic()
-            .translation(vector![-3.0, 3.0, 1.0])
-            .locked_axes(LockedAxes::ROTATION_LOCKED)
-            .ccd_enabled(true)
-            .build();
-        let me_col = ColliderBuilder::cuboid(0.01, 0.01, 1.0)
-            .translation(vector![0.0, 0.0, 0.0])
-            .friction(0.0)
-            .build();
-
-        let me = Object::new(&mut p, me, me_col);
+        let worlds = vec![
+            normal_level(&mut p),
+            fat_tunnel(&mut p),
+            long_tunnel(&mut p),
+            long_inside(&mut p),
+            short_inside(&mut p),
+            get_color_level_loop("black_f", 29.0, &mut p),
+            get_color_level_loop("gray_f", 57.0, &mut p),
+        ];
+        let me = player_object(&mut p, vector![-3.0, 3.0, 1.0]);
+        Self { p, worlds, me }
+    }
+}
+
+impl MagicLevel {
+    pub fn level0(gpu: &WgpuData, pr: &mut PlaneRenderer, res: &ResourceManager) -> anyhow::Result<Self> {
+        Self::from_level0(Level0Plan::new(), gpu, pr, res)
+    }
+
+    /// Create the worlds, the portals and the rooms of the level 0 planned.
+    pub fn from_level0(plan: Level0Plan, gpu: &WgpuData, pr: &mut PlaneRenderer, res: &ResourceManager) -> anyhow::Result<Self> {
+        mark_frame_event("level switch");
+        let Level0Plan { p, worlds, me } = plan;
+        let levels = worlds.into_iter()
+            .map(|x| build_world(x, gpu, pr, res))
+            .collect::<anyhow::Result<Vec<_>>>()?;
 
         let mut this = Self {
             levels,
             p,
+            scheduler: Default::default(),
             me,
             me_world: 0,
             portals_map: Default::default(),
+            events: Default::default(),
+            crossing: Default::default(),
+            bounds: LevelBounds::new(0, vector![-3.0, 3.0, 1.0]),
+            physics_debug: None,
+            interactions: Default::default(),
             staging_belt: StagingBelt::new(32768 * 2),
-            portal_views: (0..5).map(|_| PortalView::new(gpu, pr, portal_renderer)).collect(),
+            portal_views: vec![],
+            depth: Default::default(),
+            view_falloff: DEFAULT_PORTAL_VIEW_FALLOFF,
+            hints: HintOverlay::new(vec![
+                Hint::new(HintTrigger::Start, "{look} to look around, {move} to walk"),
+                Hint::new(HintTrigger::Area {
+                    world: 0,
+                    min: vector![-3.0, -1.0, 0.0],
+                    max: vector![-1.0, 1.0, 2.0],
+                }, "Walk through the purple wall"),
+                Hint::new(HintTrigger::Event("portal"), "Hold {run} to run"),
+                Hint::new(HintTrigger::Event("booth"), "Walk round the tri world to open the booth"),
+            ]),
+            sounds: Default::default(),
+            avatars: Default::default(),
+            meshes: Default::default(),
+            body: Default::default(),
+            transition: Default::default(),
+            ghost: Default::default(),
+            gun: Default::default(),
+            textures: texture_handles(res, &["gf", "bf", "pf", "black_f", "gray_f"]),
+            counters: Default::default(),
+            world_names: vec![],
+            looked_portal: None,
+            entities: Default::default(),
+            capture: None,
+            keys: Default::default(),
+            triggers: Default::default(),
+            grab: Default::default(),
+            pip_bind: Default::default(),
         };
         // -------------- from normal level to fat level
         this.add_portal(gpu, pr, PortalPos {
@@ -500,7 +435,7 @@ impl MagicLevel {
             up: Vector3::z(),
             width: 1.0,
         }, 1.0, 0.5, 1.0, 0.5, 1.0);
-        this.add_portal(gpu, pr, PortalPos {
+        let tri_back = this.add_portal(gpu, pr, PortalPos {
             world: 6,
             pos: vector![-2.0, 1.0, 1.0 + 57.0],
             out_normal: Vector3::x(),
@@ -513,6 +448,43 @@ impl MagicLevel {
             up: Vector3::z(),
             width: 1.0,
         }, 1.0, 0.5, 1.0, 0.5, 1.0);
+
+        // the mirror on the +y wall of the fat level
+        this.add_mirror(gpu, PortalPos {
+            world: 1,
+            pos: vector![0.0, 4.99, 2.0 + Z_OFFSET],
+            out_normal: -Vector3::y(),
+            up: Vector3::z(),
+            width: 3.0,
+        }, 1.5, 0.75);
+
+        // the booth on the -x side wall, bigger inside, unlocked after walking round the tri world
+        let [booth, _] = RoomBuilder::bigger_inside(PortalPos {
+            world: 0,
+            pos: vector![-10.0 + PORTAL_INSET, 4.0, 1.0],
+            out_normal: Vector3::x(),
+            up: Vector3::z(),
+            width: 1.0,
+        }, 1.0, 2.0)
+            .interior(24.0, 16.0, 8.0)
+            .texture("gray_f")
+            .build(&mut this, gpu, pr, res)?;
+        this.set_portal_gate(booth, PortalGate::Locked("tri".into()))?;
+        this.on_traverse(tri_back[0], |keys| { keys.give("tri"); })?;
+        let booth_front = this.triggers.add(&mut this.p, vector![-9.0, 4.0, 1.0], vector![1.0, 1.0, 1.0]);
+        if let Some(x) = this.triggers.get_mut(booth_front) {
+            x.on_enter.push(TriggerAction::Hint("booth".into()));
+        }
+
+        // the elevator at the -x, -y corner
+        let pf = res.texture("pf").ok_or(anyhow!("NO TEXTURE pf"))?;
+        let texture_bind = pr.create_plane(&gpu.device, Some(pf.srgb_view())).texture_bind;
+        this.levels[0].add_platform(&mut this.p, gpu, texture_bind, &Vector3::z(), 1.0, PlatformPath {
+            points: vec![vector![-6.0, -6.0, PLATFORM_THICKNESS / 2.0], vector![-6.0, -6.0, 2.5]],
+            speed: 1.0,
+            wait: 2.0,
+            looped: false,
+        });
         Ok(this)
     }
 }
\ No newline at end of file
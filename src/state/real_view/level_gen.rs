@@ -0,0 +1,397 @@
+use std::collections::VecDeque;
+
+use anyhow::{anyhow, bail};
+use nalgebra::*;
+use num::Zero;
+use rand::prelude::SliceRandom;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use wgpu::util::StagingBelt;
+
+use crate::engine::config::DEFAULT_PORTAL_VIEW_FALLOFF;
+use crate::engine::pacing::mark_frame_event;
+use crate::engine::physics::obj::KinematicObject;
+use crate::engine::physics::state::RapierData;
+use crate::engine::prelude::*;
+use crate::engine::renderer3d::renderer3d::*;
+use crate::state::real_view::bounds::{LevelBounds, WorldBounds};
+use crate::state::real_view::level::*;
+use crate::state::real_view::level_rooms::{ensure_tint, get_color_level, player_object, RoomTexture, RoomTextures, tint_image};
+
+/// The rooms generated at least and at most.
+pub const GEN_ROOMS: (usize, usize) = (2, 12);
+/// The scales of the rooms to pick, the first room is not scaled.
+const ROOM_SCALES: [f32; 4] = [0.5, 1.0, 1.0, 2.0];
+/// The half size of the room not scaled.
+const ROOM_HALF: f32 = 5.0;
+/// The half size of the door not scaled.
+const DOOR_HALF: f32 = 1.0;
+/// The door centers along the wall, by the room scale.
+const DOOR_SLOTS: [f32; 3] = [-3.0, 0.0, 3.0];
+/// The normals of the walls to the inside of the room.
+const WALLS: [[f32; 3]; 4] = [[1.0, 0.0, 0.0], [-1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, -1.0, 0.0]];
+/// The portals in the rooms are in front of the walls by it, to go through before touching the wall.
+pub(crate) const PORTAL_INSET: f32 = 0.05;
+/// The thickness of the sensors compared, the sensors are flat.
+const SENSOR_DEPTH: f32 = 0.05;
+/// The distance between the worlds along z.
+const WORLD_SPACING: f32 = 50.0;
+/// The segments of the corridors at least and at most, each as long as the corridor width.
+const CORRIDOR_SEGMENTS: (usize, usize) = (2, 6);
+/// The chance to connect the rooms by the corridor instead of the portals directly.
+const CORRIDOR_CHANCE: f64 = 0.5;
+/// The extra connections by the rooms, making the loops.
+const EXTRA_LINKS: f32 = 0.5;
+/// The scales differ beyond it relatively are inconsistent.
+const SCALE_EPSILON: f32 = 1e-4;
+
+/// The shape of the world generated.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) enum GenShape {
+    /// The closed box with the doors on the walls.
+    Room,
+    /// The tunnel along x of the segments, opened at the ends.
+    Corridor(usize),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) struct GenWorld {
+    pub(crate) shape: GenShape,
+    pub(crate) scale: f32,
+    /// The floor height.
+    pub(crate) zo: f32,
+    pub(crate) bounds: WorldBounds,
+}
+
+impl GenWorld {
+    fn new(shape: GenShape, scale: f32, idx: usize) -> Self {
+        let zo = idx as f32 * WORLD_SPACING;
+        let (min, max) = match shape {
+            GenShape::Room => {
+                let h = ROOM_HALF * scale;
+                (vector![-h, -h, zo], vector![h, h, zo + 2.0 * h])
+            }
+            GenShape::Corridor(segments) => {
+                let r = DOOR_HALF * scale;
+                (vector![0.0, -r, zo], vector![2.0 * r * segments as f32, r, zo + 2.0 * r])
+            }
+        };
+        let margin = Vector3::repeat(scale);
+        Self { shape, scale, zo, bounds: WorldBounds { min: min - margin, max: max + margin } }
+    }
+
+    /// The half size of the doors.
+    fn door(&self) -> f32 {
+        DOOR_HALF * self.scale
+    }
+
+    /// The portal of the door at the slot on the wall of the room.
+    fn room_door(&self, world: usize, wall: usize, slot: usize) -> PortalPos {
+        let h = ROOM_HALF * self.scale;
+        let normal = Vector3::from(WALLS[wall]);
+        let tangent = normal.cross(&Vector3::z());
+        PortalPos {
+            world,
+            pos: -normal * (h - PORTAL_INSET) + tangent * DOOR_SLOTS[slot] * self.scale + Vector3::z() * (self.zo + self.door()),
+            out_normal: normal,
+            up: Vector3::z(),
+            width: self.door(),
+        }
+    }
+
+    /// The portal at the start or the end of the corridor.
+    fn corridor_end(&self, world: usize, end: bool) -> PortalPos {
+        let length = if let GenShape::Corridor(segments) = self.shape { 2.0 * self.door() * segments as f32 } else { 0.0 };
+        let (x, normal) = if end { (length, -Vector3::x()) } else { (0.0, Vector3::x()) };
+        PortalPos {
+            world,
+            pos: vector![x, 0.0, self.zo + self.door()],
+            out_normal: normal,
+            up: Vector3::z(),
+            width: self.door(),
+        }
+    }
+
+    /// The planes and the colliders, the texture is bound later.
+    fn planes(&self, p: &mut RapierData) -> Planes {
+        let mut gfs = Planes { objs: vec![], texture_bind: None };
+        let zo = self.zo;
+        match self.shape {
+            GenShape::Room => {
+                let h = ROOM_HALF * self.scale;
+                add_plane(p, &mut gfs, &vector![0.0, 0.0, zo], h, &Vector2::zeros(), h / 2.0, &Vector3::z(), &Vector3::x());
+                add_plane(p, &mut gfs, &vector![0.0, 0.0, zo + 2.0 * h], h, &Vector2::zeros(), h / 2.0, &-Vector3::z(), &Vector3::x());
+                for normal in WALLS.map(Vector3::from) {
+                    let right = if normal.x.is_zero() { Vector3::x() } else { Vector3::y() };
+                    add_plane(p, &mut gfs, &(-normal * h + Vector3::z() * (zo + h)), h, &Vector2::zeros(), h / 2.0, &normal, &right);
+                }
+            }
+            GenShape::Corridor(segments) => {
+                let r = self.door();
+                for i in 0..segments {
+                    let x = r * (2 * i + 1) as f32;
+                    add_plane(p, &mut gfs, &vector![x, 0.0, zo], r, &Vector2::zeros(), r / 2.0, &Vector3::z(), &Vector3::x());
+                    add_plane(p, &mut gfs, &vector![x, 0.0, zo + 2.0 * r], r, &Vector2::zeros(), r / 2.0, &-Vector3::z(), &Vector3::x());
+                    add_plane(p, &mut gfs, &vector![x, r, zo + r], r, &Vector2::zeros(), r / 2.0, &-Vector3::y(), &Vector3::x());
+                    add_plane(p, &mut gfs, &vector![x, -r, zo + r], r, &Vector2::zeros(), r / 2.0, &Vector3::y(), &Vector3::x());
+                }
+            }
+        }
+        gfs
+    }
+}
+
+/// The portals connecting each other, going through `a` to `b` scaled by `scale`.
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct PortalLink {
+    pub(crate) a: PortalPos,
+    pub(crate) ra: f32,
+    pub(crate) b: PortalPos,
+    pub(crate) rb: f32,
+    pub(crate) scale: f32,
+}
+
+impl PortalLink {
+    fn new(a: PortalPos, ra: f32, b: PortalPos, rb: f32) -> Self {
+        Self { a, ra, b, rb, scale: rb / ra }
+    }
+}
+
+/// The random graph of the rooms and the corridors between, the rooms are the first worlds.
+#[derive(Debug, Clone)]
+pub(crate) struct GenLayout {
+    pub(crate) worlds: Vec<GenWorld>,
+    pub(crate) links: Vec<PortalLink>,
+}
+
+impl GenLayout {
+    /// Connect the rooms as a random tree with some loops, the same seed gets the same layout.
+    pub(crate) fn new(seed: u64, room_cnt: usize) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let room_cnt = room_cnt.clamp(GEN_ROOMS.0, GEN_ROOMS.1);
+        let mut worlds = (0..room_cnt)
+            .map(|i| GenWorld::new(GenShape::Room, if i == 0 { 1.0 } else { *ROOM_SCALES.choose(&mut rng).unwrap() }, i))
+            .collect::<Vec<_>>();
+        // the free (wall, slot) of the rooms
+        let mut doors = (0..room_cnt).map(|_| {
+            let mut x = (0..WALLS.len()).flat_map(|w| (0..DOOR_SLOTS.len()).map(move |s| (w, s))).collect::<Vec<_>>();
+            x.shuffle(&mut rng);
+            x
+        }).collect::<Vec<_>>();
+
+        // the tree first to get the doors before the loops
+        let mut edges = (1..room_cnt).map(|i| (rng.gen_range(0..i), i)).collect::<Vec<_>>();
+        for _ in 0..(room_cnt as f32 * EXTRA_LINKS) as usize {
+            let (a, b) = (rng.gen_range(0..room_cnt), rng.gen_range(0..room_cnt));
+            if a != b && !edges.contains(&(a, b)) && !edges.contains(&(b, a)) {
+                edges.push((a, b));
+            }
+        }
+
+        let mut links = vec![];
+        for (a, b) in edges {
+            if doors[a].is_empty() || doors[b].is_empty() {
+                continue;
+            }
+            let (wa, sa) = doors[a].pop().unwrap();
+            let (wb, sb) = doors[b].pop().unwrap();
+            let door_a = worlds[a].room_door(a, wa, sa);
+            let door_b = worlds[b].room_door(b, wb, sb);
+            let (ra, rb) = (worlds[a].door(), worlds[b].door());
+            if rng.gen_bool(CORRIDOR_CHANCE) {
+                let idx = worlds.len();
+                let segments = rng.gen_range(CORRIDOR_SEGMENTS.0..=CORRIDOR_SEGMENTS.1);
+                let corridor = GenWorld::new(GenShape::Corridor(segments), worlds[a].scale.min(worlds[b].scale), idx);
+                links.push(PortalLink::new(door_a, ra, corridor.corridor_end(idx, false), corridor.door()));
+                links.push(PortalLink::new(corridor.corridor_end(idx, true), corridor.door(), door_b, rb));
+                worlds.push(corridor);
+            } else {
+                links.push(PortalLink::new(door_a, ra, door_b, rb));
+            }
+        }
+        Self { worlds, links }
+    }
+
+    /// Check the portals are sized by their scales, inside their worlds and not overlapping,
+    /// and all worlds are reachable from the first with the same scale by any way.
+    pub(crate) fn validate(&self) -> anyhow::Result<()> {
+        let mut sensors: Vec<(usize, Vector3<f32>, Vector3<f32>, usize)> = vec![];
+        for (i, link) in self.links.iter().enumerate() {
+            if (link.ra * link.scale - link.rb).abs() > SCALE_EPSILON * link.rb.abs() {
+                bail!("The portal pair {} sized {} and {} is scaled by {}", i, link.ra, link.rb, link.scale);
+            }
+            for (end, r) in [(&link.a, link.ra), (&link.b, link.rb)] {
+                let world = self.worlds.get(end.world)
+                    .ok_or_else(|| anyhow!("The portal pair {} is in the world {} of {}", i, end.world, self.worlds.len()))?;
+                if (end.out_normal.norm() - 1.0).abs() > SCALE_EPSILON || end.out_normal.dot(&end.up).abs() > SCALE_EPSILON {
+                    bail!("The portal pair {} is not upright in the world {}", i, end.world);
+                }
+                let half = Level::sensor_half(end, r) + end.out_normal.abs() * SENSOR_DEPTH;
+                let (min, max) = (end.pos - half, end.pos + half);
+                if !world.bounds.contains(&min) || !world.bounds.contains(&max) {
+                    bail!("The portal pair {} is out of the world {}", i, end.world);
+                }
+                let overlapped = sensors.iter()
+                    .find(|(w, omin, omax, _)| *w == end.world && (0..3).all(|x| min[x] < omax[x] && omin[x] < max[x]));
+                if let Some((_, _, _, j)) = overlapped {
+                    bail!("The portal pairs {} and {} overlap in the world {}", j, i, end.world);
+                }
+                sensors.push((end.world, min, max, i));
+            }
+        }
+
+        // the scale relative to the first world
+        let mut scales: Vec<Option<f32>> = vec![None; self.worlds.len()];
+        let mut queue = VecDeque::from([(0, 1.0f32)]);
+        while let Some((world, scale)) = queue.pop_front() {
+            match scales.get(world).copied().flatten() {
+                Some(x) if (x - scale).abs() > SCALE_EPSILON * x => bail!("The world {} is scaled by {} and {} by the ways", world, x, scale),
+                Some(_) => continue,
+                None => scales[world] = Some(scale),
+            }
+            for x in &self.links {
+                if x.a.world == world {
+                    queue.push_back((x.b.world, scale * x.scale));
+                }
+                if x.b.world == world {
+                    queue.push_back((x.a.world, scale / x.scale));
+                }
+            }
+        }
+        if let Some(world) = scales.iter().position(Option::is_none) {
+            bail!("The world {} is not reachable from the first", world);
+        }
+        Ok(())
+    }
+}
+
+/// The generated level planned without the gpu, to build in the io pool.
+pub struct GenPlan {
+    pub(crate) p: RapierData,
+    layout: GenLayout,
+    textures: Vec<RoomTexture>,
+    /// The planes of each world without the texture.
+    planes: Vec<Planes>,
+    tints: Vec<(usize, image::RgbaImage)>,
+    pub(crate) me: KinematicObject,
+}
+
+impl GenPlan {
+    pub fn new(seed: u64, room_cnt: usize) -> Self {
+        let mut p = RapierData::new();
+        p.g.set_zero();
+
+        let layout = GenLayout::new(seed, room_cnt);
+        let textures = RoomTextures::Seeded(seed).resolve(layout.worlds.len());
+        let tints = textures.iter()
+            .filter_map(|x| if let RoomTexture::Tint(idx) = x { Some((*idx, tint_image(*idx))) } else { None })
+            .collect();
+        let planes = layout.worlds.iter().map(|x| x.planes(&mut p)).collect();
+        let me = player_object(&mut p, vector![0.0, 0.0, 1.0]);
+        Self { p, layout, textures, planes, tints, me }
+    }
+}
+
+impl MagicLevel {
+    /// Check the level generated and create its textures, buffers and portals.
+    pub fn from_generated(plan: GenPlan, gpu: &WgpuData, pr: &mut PlaneRenderer, res: &ResourceManager) -> anyhow::Result<Self> {
+        mark_frame_event("level switch");
+        let GenPlan { p, layout, textures, planes, tints, me } = plan;
+        layout.validate()?;
+        for (idx, img) in tints {
+            ensure_tint(gpu, res, idx, img)?;
+        }
+        let colors = textures.iter().map(RoomTexture::name).collect::<Vec<_>>();
+        let mut levels = vec![];
+        for (color, gfs) in colors.iter().zip(planes) {
+            levels.push(get_color_level(color, gfs, gpu, pr, res)?);
+        }
+
+        let mut this = Self {
+            levels,
+            p,
+            scheduler: Default::default(),
+            me,
+            me_world: 0,
+            portals_map: Default::default(),
+            events: Default::default(),
+            crossing: Default::default(),
+            bounds: LevelBounds::new(0, vector![0.0, 0.0, 1.0]),
+            physics_debug: None,
+            interactions: Default::default(),
+            staging_belt: StagingBelt::new(32768 * 2),
+            portal_views: vec![],
+            depth: Default::default(),
+            view_falloff: DEFAULT_PORTAL_VIEW_FALLOFF,
+            hints: Default::default(),
+            sounds: Default::default(),
+            avatars: Default::default(),
+            meshes: Default::default(),
+            body: Default::default(),
+            transition: Default::default(),
+            ghost: Default::default(),
+            gun: Default::default(),
+            textures: texture_handles(res, &colors.iter().map(String::as_str).collect::<Vec<_>>()),
+            counters: Default::default(),
+            world_names: colors,
+            looked_portal: None,
+            entities: Default::default(),
+            capture: None,
+            keys: Default::default(),
+            triggers: Default::default(),
+            grab: Default::default(),
+            pip_bind: Default::default(),
+        };
+
+        for (i, world) in layout.worlds.iter().enumerate() {
+            this.bounds.set_world(i, world.bounds);
+        }
+        for x in &layout.links {
+            this.add_portal(gpu, pr, x.a, x.b, x.ra, x.ra / 2.0, x.rb, x.rb / 2.0, x.scale);
+        }
+        Ok(this)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use nalgebra::vector;
+
+    use crate::state::real_view::level_gen::{GEN_ROOMS, GenLayout, GenShape, PortalLink};
+
+    #[test]
+    fn test_gen_layout() {
+        for seed in 0..64 {
+            let layout = GenLayout::new(seed, 2 + seed as usize % 11);
+            layout.validate().unwrap_or_else(|e| panic!("The layout of the seed {} is invalid for {:?}", seed, e));
+            let rooms = layout.worlds.iter().filter(|x| x.shape == GenShape::Room).count();
+            assert_eq!(rooms, (2 + seed as usize % 11).clamp(GEN_ROOMS.0, GEN_ROOMS.1));
+            assert_eq!(layout.worlds[0].scale, 1.0);
+        }
+        let layout = GenLayout::new(42, 6);
+        assert_eq!(layout.links.len(), GenLayout::new(42, 6).links.len());
+        assert!(layout.links.iter().zip(GenLayout::new(42, 6).links).all(|(x, y)| x.a.pos == y.a.pos && x.b.pos == y.b.pos));
+
+        // sized not by the scale
+        let mut broken = layout.clone();
+        broken.links[0].scale *= 2.0;
+        assert!(broken.validate().is_err());
+
+        // on the other portal
+        let mut broken = layout.clone();
+        let link = broken.links[0];
+        broken.links.push(PortalLink::new(link.a, link.ra, link.b, link.rb));
+        assert!(broken.validate().is_err());
+
+        // out of the world
+        let mut broken = layout.clone();
+        broken.links[0].a.pos += vector![0.0, 0.0, 100.0];
+        assert!(broken.validate().is_err());
+
+        // the world not connected
+        let mut broken = layout;
+        let world = broken.worlds[0];
+        broken.worlds.push(world);
+        assert!(broken.validate().is_err());
+    }
+}
@@ -1,4 +1,4 @@
-use anyhow::anyhow;
+use crate::engine::config::DEFAULT_PORTAL_VIEW_FALLOFF;
 use crate::engine::physics::state::RapierData;
 use crate::state::real_view::level::*;
 use crate::engine::prelude::*;
@@ -6,89 +6,96 @@ use crate::engine::renderer3d::renderer3d::*;
 
 use nalgebra::*;
 use num::Zero;
-use rapier3d::prelude::*;
 use wgpu::util::StagingBelt;
-use crate::engine::physics::obj::Object;
-use crate::state::real_view::renderer::portal::{PortalRenderer, PortalView};
+use crate::engine::pacing::mark_frame_event;
+use crate::engine::physics::obj::KinematicObject;
+use crate::state::real_view::bounds::{LevelBounds, WorldBounds};
+use crate::state::real_view::level_rooms::{get_color_level, player_object};
 
 // green
 // blue
 // purple
 
-pub fn get_color_level(color: &str, zo: f32, p: &mut RapierData, gpu: &WgpuData, pr: &mut PlaneRenderer, res: &ResourceManager) -> anyhow::Result<Level> {
-    let gf = res.textures.get(color).ok_or(anyhow!("NO TEXTURE"))?;
-    let mut gfs = pr.create_plane(&gpu.device, Some(&gf.view));
-
-    // floor
-    add_plane(p, &mut gfs, &vector![0.0, 0.0, zo], 5.0, &Vector2::zeros(), 2.5, &Vector3::z(), &Vector3::x());
-    // wall (or portal)
-    add_plane(p, &mut gfs, &vector![0.0, 5.0, 5.0 + zo], 5.0, &Vector2::zeros(), 2.5, &-Vector3::y(), &Vector3::x());
-    add_plane(p, &mut gfs, &vector![0.0, -5.0, 5.0 + zo], 5.0, &Vector2::zeros(), 2.5, &Vector3::y(), &Vector3::x());
-
-
-    // // in fact we can add large
-    // // floor
-    // add_plane(p, &mut gfs, &vector![0.0, 0.0, zo], 5.0 * 1e1, &Vector2::zeros(), 2.5 * 1e1, &Vector3::z(), &Vector3::x());
-    // // wall (or portal)
-    // add_plane(p, &mut gfs, &vector![0.0, 5.0, 5.0 + zo], 5.0 * 1e1, &Vector2::zeros(), 2.5 * 1e1, &-Vector3::y(), &Vector3::x());
-    // add_plane(p, &mut gfs, &vector![0.0, -5.0, 5.0 + zo], 5.0 * 1e1, &Vector2::zeros(), 2.5 * 1e1, &Vector3::y(), &Vector3::x());
-
-    let mut planes = vec![];
-    planes.push(gfs.to_static(&gpu.device));
-
-    let mut bundle = gpu.device.create_render_bundle_encoder(&RenderBundleEncoderDescriptor {
-        label: None,
-        color_formats: &[Some(gpu.surface_cfg.format)],
-        depth_stencil: Some(RenderBundleDepthStencil {
-            format: TextureFormat::Depth32Float,
-            depth_read_only: false,
-            stencil_read_only: false,
-        }),
-        sample_count: 1,
-        multiview: None,
-    });
-    bundle.set_pipeline(&pr.normal_rp);
-    pr.bind(&mut bundle);
-    pr.render_static(&mut bundle, gpu, &planes[..]);
-    let bundle = bundle.finish(&RenderBundleDescriptor {
-        label: None,
-    });
-    Ok(Level {
-        portals: vec![],
-        objs: planes,
-        bundle,
-    })
+/// The loop planned without the gpu, its portal is added when built.
+pub struct LoopPlan {
+    pub(crate) p: RapierData,
+    planes: Planes,
+    pub(crate) me: KinematicObject,
 }
 
-
-impl MagicLevel {
-    pub fn level_loop(gpu: &WgpuData, pr: &mut PlaneRenderer, portal_renderer: &PortalRenderer, res: &ResourceManager) -> anyhow::Result<Self> {
-        let mut levels = vec![];
+impl LoopPlan {
+    pub fn new() -> Self {
         let mut p = RapierData::new();
         p.g.set_zero();
 
-        levels.push(get_color_level("gf", 0.0, &mut p, gpu, pr, res)?);
-        let me = RigidBodyBuilder::dynamic()
-            .translation(vector![-3.0, 3.0, 1.0])
-            .locked_axes(LockedAxes::ROTATION_LOCKED)
-            .ccd_enabled(true)
-            .build();
-        let me_col = ColliderBuilder::cuboid(0.01, 0.01, 1.0)
-            .translation(vector![0.0, 0.0, 0.0])
-            .friction(0.0)
-            .build();
+        let mut gfs = Planes { objs: vec![], texture_bind: None };
+        // floor
+        add_plane(&mut p, &mut gfs, &vector![0.0, 0.0, 0.0], 5.0, &Vector2::zeros(), 2.5, &Vector3::z(), &Vector3::x());
+        // wall (or portal)
+        add_plane(&mut p, &mut gfs, &vector![0.0, 5.0, 5.0], 5.0, &Vector2::zeros(), 2.5, &-Vector3::y(), &Vector3::x());
+        add_plane(&mut p, &mut gfs, &vector![0.0, -5.0, 5.0], 5.0, &Vector2::zeros(), 2.5, &Vector3::y(), &Vector3::x());
+
+
+        // // in fact we can add large
+        // // floor
+        // add_plane(p, &mut gfs, &vector![0.0, 0.0, zo], 5.0 * 1e1, &Vector2::zeros(), 2.5 * 1e1, &Vector3::z(), &Vector3::x());
+        // // wall (or portal)
+        // add_plane(p, &mut gfs, &vector![0.0, 5.0, 5.0 + zo], 5.0 * 1e1, &Vector2::zeros(), 2.5 * 1e1, &-Vector3::y(), &Vector3::x());
+        // add_plane(p, &mut gfs, &vector![0.0, -5.0, 5.0 + zo], 5.0 * 1e1, &Vector2::zeros(), 2.5 * 1e1, &Vector3::y(), &Vector3::x());
+
+        let me = player_object(&mut p, vector![-3.0, 3.0, 1.0]);
+        Self { p, planes: gfs, me }
+    }
+}
+
+
+impl MagicLevel {
+    pub fn level_loop(gpu: &WgpuData, pr: &mut PlaneRenderer, res: &ResourceManager) -> anyhow::Result<Self> {
+        Self::from_loop(LoopPlan::new(), gpu, pr, res)
+    }
 
-        let me = Object::new(&mut p, me, me_col);
+    /// Create the world and the portal of the loop planned.
+    pub fn from_loop(plan: LoopPlan, gpu: &WgpuData, pr: &mut PlaneRenderer, res: &ResourceManager) -> anyhow::Result<Self> {
+        mark_frame_event("level switch");
+        let LoopPlan { p, planes, me } = plan;
+        let levels = vec![get_color_level("gf", planes, gpu, pr, res)?];
 
         let mut this = Self {
             levels,
             p,
+            scheduler: Default::default(),
             me,
             me_world: 0,
             portals_map: Default::default(),
+            events: Default::default(),
+            crossing: Default::default(),
+            bounds: LevelBounds::new(0, vector![-3.0, 3.0, 1.0]),
+            physics_debug: None,
+            interactions: Default::default(),
             staging_belt: StagingBelt::new(32768 * 2),
-            portal_views: (0..10).map(|_| PortalView::new(gpu, pr, portal_renderer)).collect(),
+            portal_views: vec![],
+            depth: Default::default(),
+            view_falloff: DEFAULT_PORTAL_VIEW_FALLOFF,
+            hints: Default::default(),
+            sounds: Default::default(),
+            avatars: Default::default(),
+            meshes: Default::default(),
+            body: Default::default(),
+            transition: Default::default(),
+            ghost: Default::default(),
+            gun: Default::default(),
+            textures: texture_handles(res, &["gf"]),
+            counters: Default::default(),
+            world_names: vec![],
+            looked_portal: None,
+            entities: Default::default(),
+            capture: None,
+            keys: Default::default(),
+            triggers: Default::default(),
+            grab: Default::default(),
+            pip_bind: Default::default(),
         };
+        this.bounds.set_world(0, WorldBounds { min: vector![-6.0, -6.0, -4.0], max: vector![6.0, 6.0, 12.0] });
 
         this.add_portal(gpu, pr, PortalPos {
             world: 0,
@@ -1,4 +1,5 @@
 use anyhow::anyhow;
+use crate::engine::config::DEFAULT_PORTAL_VIEW_FALLOFF;
 use crate::engine::physics::state::RapierData;
 use crate::state::real_view::level::*;
 use crate::engine::prelude::*;
@@ -7,96 +8,242 @@ use crate::engine::renderer3d::renderer3d::*;
 use nalgebra::*;
 use num::Zero;
 use rand::prelude::SliceRandom;
-use rand::thread_rng;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use rapier3d::prelude::*;
+use serde::{Deserialize, Serialize};
 use wgpu::util::StagingBelt;
-use crate::engine::physics::obj::Object;
-use crate::state::real_view::renderer::portal::{PortalRenderer, PortalView};
+use crate::engine::pacing::mark_frame_event;
+use crate::engine::physics::event::ColliderTag;
+use crate::engine::physics::obj::KinematicObject;
+use crate::state::real_view::bounds::{LevelBounds, WorldBounds};
 
-// green
-// blue
-// purple
+/// The floor textures to pick for the rooms.
+const PALETTE: [&str; 9] = ["bf", "gf", "pf", "rf", "af", "yf", "gray_f", "pink_f", "black_f"];
 
-fn get_color_level(color: &str, zo: f32, p: &mut RapierData, gpu: &WgpuData, pr: &mut PlaneRenderer, res: &ResourceManager) -> anyhow::Result<Level> {
-    let gf = res.textures.get(color).ok_or(anyhow!("NO TEXTURE"))?;
-    let mut gfs = pr.create_plane(&gpu.device, Some(&gf.view));
+/// The texture of the room.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RoomTexture {
+    /// The texture loaded by the name.
+    Texture(String),
+    /// The procedural tint by the index, with the distinct hue.
+    Tint(usize),
+}
+
+impl RoomTexture {
+    /// The name of the texture in the resource manager.
+    pub fn name(&self) -> String {
+        match self {
+            RoomTexture::Texture(x) => x.clone(),
+            RoomTexture::Tint(idx) => format!("tint_{}", idx),
+        }
+    }
+}
+
+/// How to assign the textures to the rooms.
+#[allow(unused)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RoomTextures {
+    /// Shuffle the palette by the seed, the same seed gets the same rooms.
+    Seeded(u64),
+    /// The texture names of the rooms in order.
+    Explicit(Vec<String>),
+}
+
+impl RoomTextures {
+    /// The textures of the rooms, the rooms out of the palette or the list get the tints.
+    pub fn resolve(&self, room_cnt: usize) -> Vec<RoomTexture> {
+        let names = match self {
+            RoomTextures::Seeded(seed) => {
+                let mut colors = PALETTE.map(String::from).to_vec();
+                colors.shuffle(&mut StdRng::seed_from_u64(*seed));
+                colors
+            }
+            RoomTextures::Explicit(names) => names.clone(),
+        };
+        let tints = (0..).map(RoomTexture::Tint);
+        names.into_iter().map(RoomTexture::Texture)
+            .chain(tints)
+            .take(room_cnt)
+            .collect()
+    }
+}
+
+/// The floor color of the tint, spread the hues by the golden ratio.
+fn tint_color(idx: usize) -> [u8; 3] {
+    let hue = (idx as f32 * 0.618034 + 0.1).fract() * 6.0;
+    let (s, v) = (0.55, 0.85);
+    let c = v * s;
+    let x = c * (1.0 - (hue % 2.0 - 1.0).abs());
+    let (r, g, b) = match hue as u32 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    let m = v - c;
+    [r, g, b].map(|x| ((x + m) * 255.0) as u8)
+}
+
+/// The image of the tint, with the darker grid like the floor textures.
+pub(crate) fn tint_image(idx: usize) -> image::RgbaImage {
+    let [r, g, b] = tint_color(idx);
+    image::RgbaImage::from_fn(32, 32, |x, y| {
+        if x == 0 || y == 0 || x == 31 || y == 31 {
+            image::Rgba([r / 2, g / 2, b / 2, 255])
+        } else {
+            image::Rgba([r, g, b, 255])
+        }
+    })
+}
+
+/// Create the texture of the tint if not created.
+pub(crate) fn ensure_tint(gpu: &WgpuData, res: &ResourceManager, idx: usize, img: image::RgbaImage) -> anyhow::Result<()> {
+    let name = RoomTexture::Tint(idx).name();
+    if res.textures.by_name(&name).is_some() {
+        return Ok(());
+    }
+    let texture = TextureWrapper::from_image(&gpu.device, &gpu.queue, &image::DynamicImage::ImageRgba8(img), Some(&name), ColorSpace::Srgb)?;
+    res.insert(name, texture);
+    Ok(())
+}
+
+/// The planes and the colliders of the room, the texture is bound later.
+fn color_planes(zo: f32, p: &mut RapierData) -> Planes {
+    let mut gfs = Planes { objs: vec![], texture_bind: None };
 
     add_plane(p, &mut gfs, &vector![0.0, 0.0, zo], 5.0, &Vector2::zeros(), 2.5, &Vector3::z(), &Vector3::x());
     add_plane(p, &mut gfs, &vector![0.0, 0.0, 5.0 + zo], 5.0, &Vector2::zeros(), 2.5, &-Vector3::z(), &Vector3::x());
     add_plane(p, &mut gfs, &vector![5.0, 0.0, 5.0 + zo], 5.0, &Vector2::zeros(), 2.5, &-Vector3::x(), &Vector3::y());
     add_plane(p, &mut gfs, &vector![0.0, 5.0, 5.0 + zo], 5.0, &Vector2::zeros(), 2.5, &-Vector3::y(), &Vector3::x());
+    gfs
+}
+
+pub(crate) fn get_color_level(color: &str, gfs: Planes, gpu: &WgpuData, pr: &mut PlaneRenderer, res: &ResourceManager) -> anyhow::Result<Level> {
+    let gf = res.texture(color).ok_or(anyhow!("NO TEXTURE {}", color))?;
+    let gfs = Planes { texture_bind: pr.create_plane(&gpu.device, Some(gf.srgb_view())).texture_bind, ..gfs };
 
     let mut planes = vec![];
-    planes.push(gfs.to_static(&gpu.device));
-
-    let mut bundle = gpu.device.create_render_bundle_encoder(&RenderBundleEncoderDescriptor {
-        label: None,
-        color_formats: &[Some(gpu.surface_cfg.format)],
-        depth_stencil: Some(RenderBundleDepthStencil {
-            format: TextureFormat::Depth32Float,
-            depth_read_only: false,
-            stencil_read_only: false,
-        }),
-        sample_count: 1,
-        multiview: None,
-    });
-    bundle.set_pipeline(&pr.normal_rp);
-    pr.bind(&mut bundle);
-    pr.render_static(&mut bundle, gpu, &planes[..]);
-    let bundle = bundle.finish(&RenderBundleDescriptor {
-        label: None,
-    });
+    planes.push(gfs.to_arena(gpu, &mut pr.arena));
+
+    let bundle = DirtyBundle::new(gpu, pr, &planes, false);
     Ok(Level {
         portals: vec![],
         objs: planes,
+        layered: vec![],
+        pbr: vec![],
+        models: vec![],
+        props: vec![],
         bundle,
+        platforms: vec![],
     })
 }
 
 
-impl MagicLevel {
-    pub fn level_rooms(gpu: &WgpuData, room_cnt: usize, pr: &mut PlaneRenderer, portal_renderer: &PortalRenderer, res: &ResourceManager) -> anyhow::Result<Self> {
-        let mut levels = vec![];
+/// The rooms planned without the gpu, to build in the io pool.
+pub struct RoomsPlan {
+    pub(crate) p: RapierData,
+    rooms: Vec<RoomTexture>,
+    /// The planes of each room without the texture.
+    planes: Vec<Planes>,
+    /// The images of the tints used.
+    tints: Vec<(usize, image::RgbaImage)>,
+    pub(crate) me: KinematicObject,
+}
+
+impl RoomsPlan {
+    pub fn new(room_cnt: usize, rooms: &RoomTextures) -> Self {
         let mut p = RapierData::new();
         p.g.set_zero();
 
-        let mut colors = vec!["bf",
-                              "gf",
-                              "pf",
-                              "rf",
-                              "af",
-                              "yf",
-                              "gray_f",
-                              "pink_f",
-                              "black_f"];
-        let mut rng = thread_rng();
-        colors.shuffle(&mut rng);
-        for i in 0..room_cnt {
-            levels.push(get_color_level(&colors[i], 0.0 + i as f32 * 20.0, &mut p, gpu, pr, res)?);
+        let rooms = rooms.resolve(room_cnt);
+        let tints = rooms.iter()
+            .filter_map(|x| if let RoomTexture::Tint(idx) = x { Some((*idx, tint_image(*idx))) } else { None })
+            .collect();
+        let planes = (0..room_cnt).map(|i| color_planes(0.0 + i as f32 * 20.0, &mut p)).collect();
+        let me = player_object(&mut p, vector![-3.0, 3.0, 1.0]);
+        Self { p, rooms, planes, tints, me }
+    }
+}
+
+/// My body and collider at the position.
+pub(crate) fn player_object(p: &mut RapierData, pos: Vector3<f32>) -> KinematicObject {
+    let me = RigidBodyBuilder::kinematic_position_based()
+        .translation(pos)
+        .build();
+    let me_col = ColliderBuilder::cuboid(0.01, 0.01, 1.0)
+        .translation(vector![0.0, 0.0, 0.0])
+        .friction(0.0)
+        .user_data(ColliderTag::Player.into())
+        // the portal sensors are fixed
+        .active_collision_types(ActiveCollisionTypes::default() | ActiveCollisionTypes::KINEMATIC_FIXED)
+        .active_events(ActiveEvents::CONTACT_FORCE_EVENTS)
+        .contact_force_event_threshold(1.0)
+        .build();
+
+    KinematicObject::new(p, me, me_col)
+}
+
+impl MagicLevel {
+    pub fn level_rooms(gpu: &WgpuData, room_cnt: usize, rooms: &RoomTextures, pr: &mut PlaneRenderer, res: &ResourceManager) -> anyhow::Result<Self> {
+        Self::from_rooms(RoomsPlan::new(room_cnt, rooms), gpu, pr, res)
+    }
+
+    /// Create the textures, the buffers and the portals of the rooms planned.
+    pub fn from_rooms(plan: RoomsPlan, gpu: &WgpuData, pr: &mut PlaneRenderer, res: &ResourceManager) -> anyhow::Result<Self> {
+        mark_frame_event("level switch");
+        let RoomsPlan { p, rooms, planes, tints, me } = plan;
+        let room_cnt = rooms.len();
+        for (idx, img) in tints {
+            ensure_tint(gpu, res, idx, img)?;
+        }
+        let colors = rooms.iter().map(RoomTexture::name).collect::<Vec<_>>();
+        let mut levels = vec![];
+        for (color, gfs) in colors.iter().zip(planes) {
+            levels.push(get_color_level(color, gfs, gpu, pr, res)?);
         }
-        let me = RigidBodyBuilder::dynamic()
-            .translation(vector![-3.0, 3.0, 1.0])
-            .locked_axes(LockedAxes::ROTATION_LOCKED)
-            .ccd_enabled(true)
-            .build();
-        let me_col = ColliderBuilder::cuboid(0.01, 0.01, 1.0)
-            .translation(vector![0.0, 0.0, 0.0])
-            .friction(0.0)
-            .build();
-
-        let me = Object::new(&mut p, me, me_col);
 
         let mut this = Self {
             levels,
             p,
+            scheduler: Default::default(),
             me,
             me_world: 0,
             portals_map: Default::default(),
+            events: Default::default(),
+            crossing: Default::default(),
+            bounds: LevelBounds::new(0, vector![-3.0, 3.0, 1.0]),
+            physics_debug: None,
+            interactions: Default::default(),
             staging_belt: StagingBelt::new(32768 * 2),
-            portal_views: (0..5).map(|_| PortalView::new(gpu, pr, portal_renderer)).collect(),
+            portal_views: vec![],
+            depth: Default::default(),
+            view_falloff: DEFAULT_PORTAL_VIEW_FALLOFF,
+            hints: Default::default(),
+            sounds: Default::default(),
+            avatars: Default::default(),
+            meshes: Default::default(),
+            body: Default::default(),
+            transition: Default::default(),
+            ghost: Default::default(),
+            gun: Default::default(),
+            textures: texture_handles(res, &colors.iter().map(String::as_str).collect::<Vec<_>>()),
+            counters: Default::default(),
+            world_names: colors,
+            looked_portal: None,
+            entities: Default::default(),
+            capture: None,
+            keys: Default::default(),
+            triggers: Default::default(),
+            grab: Default::default(),
+            pip_bind: Default::default(),
         };
 
         for i in 0..room_cnt {
+            let zo = 20.0 * i as f32;
+            this.bounds.set_world(i, WorldBounds { min: vector![-6.0, -6.0, zo - 4.0], max: vector![6.0, 6.0, zo + 12.0] });
             this.add_portal(gpu, pr, PortalPos {
                 world: i,
                 pos: vector![0.0, -5.0, 1.0 + 20.0 * i as f32],
@@ -114,4 +261,20 @@ impl MagicLevel {
 
         Ok(this)
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod test {
+    use crate::state::real_view::level_rooms::{PALETTE, RoomTexture, RoomTextures};
+
+    #[test]
+    fn test_room_textures() {
+        let rooms = RoomTextures::Seeded(233).resolve(12);
+        assert_eq!(rooms, RoomTextures::Seeded(233).resolve(12));
+        assert_eq!(rooms.len(), 12);
+        assert!(rooms[..PALETTE.len()].iter().all(|x| matches!(x, RoomTexture::Texture(_))));
+        assert_eq!(rooms[PALETTE.len()..], [RoomTexture::Tint(0), RoomTexture::Tint(1), RoomTexture::Tint(2)]);
+
+        let rooms = RoomTextures::Explicit(vec!["gf".into(), "rf".into()]).resolve(3);
+        assert_eq!(rooms.iter().map(RoomTexture::name).collect::<Vec<_>>(), ["gf", "rf", "tint_0"]);
+    }
+}
@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+use std::slice::from_ref;
+
+use nalgebra::{Vector2, Vector3};
+use specs::{Join, World, WorldExt};
+use wgpu::{BindGroup, BindGroupDescriptor, BindGroupEntry, BindingResource, RenderPass};
+
+use crate::engine::{Handle, ResourceManager, TextureWrapper, WgpuData};
+use crate::engine::ecs::{BodyPoses, Collider, Mesh, MeshShape, Transform};
+use crate::engine::renderer3d::renderer3d::{PlaneObject, PlaneRenderer, Planes, StaticPlanes};
+
+/// The built-in meshes of the entities, rebuilt each update.
+#[derive(Default)]
+pub struct EntityMeshes {
+    texture_binds: HashMap<Handle<TextureWrapper>, BindGroup>,
+    /// (world, texture, meshes)
+    planes: Vec<(usize, Option<Handle<TextureWrapper>>, StaticPlanes)>,
+}
+
+#[allow(unused)]
+impl EntityMeshes {
+    /// Rebuild the meshes at the body poses stepped, or the transforms if no body.
+    pub fn rebuild(&mut self, world: &World, gpu: &WgpuData, pr: &PlaneRenderer, res: &ResourceManager) {
+        let poses = world.read_resource::<BodyPoses>();
+        let (meshes, transforms, colliders) = (world.read_storage::<Mesh>(), world.read_storage::<Transform>(), world.read_storage::<Collider>());
+        let mut groups: HashMap<_, Vec<PlaneObject>> = HashMap::new();
+        for (entity, mesh, transform) in (&world.entities(), &meshes, &transforms).join() {
+            let pose = colliders.get(entity).and_then(|x| x.body)
+                .and_then(|x| poses.0.get(&x))
+                .map(Transform::from_isometry)
+                .unwrap_or(*transform);
+            let objs = groups.entry((mesh.world, mesh.texture)).or_default();
+            match mesh.shape {
+                MeshShape::Cube { half } => objs.extend(cube(&pose, half)),
+            }
+        }
+        for texture in groups.keys().filter_map(|x| x.1) {
+            if self.texture_binds.contains_key(&texture) {
+                continue;
+            }
+            if let Some(view) = res.textures.get(texture) {
+                self.texture_binds.insert(texture, gpu.device.create_bind_group(&BindGroupDescriptor {
+                    label: Some("Mesh bind group"),
+                    layout: &pr.obj_layout,
+                    entries: &[BindGroupEntry {
+                        binding: 0,
+                        resource: BindingResource::TextureView(view.srgb_view()),
+                    }],
+                }));
+            }
+        }
+        self.planes = groups.into_iter()
+            .map(|((world, texture), objs)| (world, texture, Planes { objs, texture_bind: None }.to_static(&gpu.device)))
+            .collect();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.planes.is_empty()
+    }
+
+    /// Render the meshes in the world with the pipeline set.
+    pub fn render<'a>(&'a self, rp: &mut RenderPass<'a>, gpu: &WgpuData, pr: &'a PlaneRenderer, world: usize) {
+        for (_, texture, planes) in self.planes.iter().filter(|x| x.0 == world) {
+            let bind = texture.and_then(|x| self.texture_binds.get(&x)).unwrap_or(&pr.white_bind);
+            rp.set_bind_group(1, bind, &[]);
+            pr.render_static(rp, gpu, from_ref(planes));
+        }
+    }
+}
+
+/// The six faces of the cube rotated by the transform.
+pub(crate) fn cube(transform: &Transform, r: f32) -> Vec<PlaneObject> {
+    let [x, y, z] = [Vector3::x(), Vector3::y(), Vector3::z()].map(|x| transform.rotation * x);
+    let faces = [(z, x), (-z, x), (x, y), (-x, y), (y, x), (-y, x)];
+    let center = transform.position.coords;
+    faces.iter()
+        .map(|(up, right)| PlaneObject::new(&(center + up * r), r, &Vector2::zeros(), r * 0.5, up, right))
+        .collect()
+}
@@ -0,0 +1,69 @@
+use crate::engine::render::camera::Camera;
+use crate::engine::WgpuData;
+use crate::state::real_view::level::{Level, MagicLevel, Portal, PortalPos};
+
+/// The camera reflected by the mirror plane.
+///
+/// Not flipping the handedness so the faces keep their winding and culling,
+/// the view rendered is mirrored horizontally when sampled instead.
+pub fn reflect_camera(camera: &Camera, mirror: &PortalPos) -> Camera {
+    let n = mirror.out_normal;
+    let mut reflected = *camera;
+    reflected.eye = camera.eye - n * 2.0 * n.dot(&(camera.eye.coords - mirror.pos));
+    reflected.target = camera.target - n * 2.0 * n.dot(&camera.target);
+    reflected
+}
+
+#[allow(unused)]
+impl MagicLevel {
+    /// Add the mirror rendered as the portal connecting itself, not passable.
+    pub(crate) fn add_mirror(&mut self, gpu: &WgpuData, this: PortalPos, r: f32, tex_delta: f32) -> (usize, usize) {
+        let world = this.world;
+        let level = &mut self.levels[world];
+        let (plane, planes) = Level::portal_plane(gpu, &this, r, tex_delta);
+        let idx = level.portals.len();
+        level.portals.push(Portal {
+            plane,
+            portal_render: planes,
+            this,
+            connecting: (world, idx),
+            scale: 1.0,
+            r,
+            tex_delta,
+            opening: Default::default(),
+            mirror: true,
+            gate: Default::default(),
+            on_traverse: Default::default(),
+        });
+        (world, idx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use nalgebra::{point, vector, Vector3};
+
+    use crate::engine::render::camera::Camera;
+    use crate::state::real_view::level::PortalPos;
+    use crate::state::real_view::mirror::reflect_camera;
+
+    #[test]
+    fn test_reflect_camera() {
+        let mirror = PortalPos {
+            world: 0,
+            pos: vector![0.0, 5.0, 1.0],
+            out_normal: -Vector3::y(),
+            up: Vector3::z(),
+            width: 1.0,
+        };
+        let mut camera = Camera::new(point![1.0, 2.0, 1.5]);
+        camera.target = vector![0.0, 1.0, 0.25];
+        let reflected = reflect_camera(&camera, &mirror);
+        assert!((reflected.eye - point![1.0, 8.0, 1.5]).norm() < 1e-5);
+        assert!((reflected.target - vector![0.0, -1.0, 0.25]).norm() < 1e-5);
+        // reflected back
+        let back = reflect_camera(&reflected, &mirror);
+        assert!((back.eye - camera.eye).norm() < 1e-5);
+        assert!((back.target - camera.target).norm() < 1e-5);
+    }
+}
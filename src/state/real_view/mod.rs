@@ -4,3 +4,33 @@ mod renderer;
 mod level0;
 mod level_rooms;
 mod level_loop;
+mod level_gen;
+mod room_builder;
+mod hint;
+mod sound;
+mod multiplayer;
+mod chat;
+mod preview;
+mod meshes;
+mod bounds;
+mod interact;
+pub mod smoke;
+mod depth;
+mod build;
+mod body;
+mod transition;
+mod ghost;
+mod gate;
+mod grab;
+mod platform;
+mod trigger;
+mod gun;
+mod opening;
+mod mirror;
+mod labels;
+mod glow;
+pub mod dedicated;
+pub mod scene;
+mod pip;
+pub mod replay;
+pub mod session;
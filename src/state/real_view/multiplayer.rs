@@ -0,0 +1,505 @@
+use std::collections::HashMap;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+use log::{info, warn};
+use nalgebra::{Vector2, Vector3};
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+use wgpu::{BindGroup, BindGroupDescriptor, BindGroupEntry, BindingResource, RenderPass};
+
+use crate::engine::{ResourceManager, WgpuData};
+use crate::engine::global::NET_RUNTIME;
+use crate::engine::lifecycle::Lifecycle;
+use crate::engine::network::message::{decode_data, encode_data, TypedDataHandler};
+use crate::engine::network::channel::Channel;
+use crate::engine::network::NetworkMessage;
+use crate::engine::network::peer::Peer;
+use crate::engine::network::rendezvous::punch;
+use crate::engine::network::server::Server;
+use crate::engine::render::camera::Camera;
+use crate::engine::renderer3d::renderer3d::{PlaneObject, PlaneRenderer, Planes, StaticPlanes};
+use crate::state::real_view::chat::{MAX_CHAT_LEN, SharedChat};
+
+/// The port to host and join if not in the address.
+pub const DEFAULT_PORT: u16 = 23333;
+/// The seconds between sending my state.
+const SEND_INTERVAL: f32 = 0.05;
+/// The remote player is removed if no state received in the time.
+const REMOTE_TIMEOUT: Duration = Duration::from_secs(5);
+/// The away player is removed if no keepalive received in the time.
+const AWAY_TIMEOUT: Duration = Duration::from_secs(30);
+/// The time between telling the others I am away while suspended.
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The state of the player replicated to the others.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlayerState {
+    pub id: u64,
+    pub world: usize,
+    /// The camera eye
+    pub position: [f32; 3],
+    /// The camera looking direction
+    pub target: [f32; 3],
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum PlayerMessage {
+    State(PlayerState),
+    /// My state with the movement since the last sent, simulated by the dedicated server.
+    Input { state: PlayerState, movement: [f32; 3] },
+    /// The state resolved by the dedicated server for the player moved into the colliders.
+    Correct(PlayerState),
+    Leave(u64),
+    Chat { id: u64, text: String },
+    /// The player is suspended and kept by the others, or came back.
+    Away { id: u64, away: bool },
+}
+
+#[allow(unused)]
+impl PlayerMessage {
+    pub fn encode(&self) -> anyhow::Result<Vec<u8>> {
+        encode_data(self)
+    }
+
+    pub fn decode(data: &[u8]) -> anyhow::Result<Self> {
+        decode_data(data)
+    }
+
+    /// The states are replaced by the newer, the others must arrive.
+    pub fn channel(&self) -> Channel {
+        match self {
+            PlayerMessage::State(_) | PlayerMessage::Input { .. } | PlayerMessage::Correct(_) => Channel::UNRELIABLE,
+            _ => Channel::RELIABLE,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct RemotePlayer {
+    pub state: PlayerState,
+    pub updated: Instant,
+    pub away: bool,
+}
+
+impl RemotePlayer {
+    pub(crate) fn is_timeout(&self) -> bool {
+        self.updated.elapsed() >= if self.away { AWAY_TIMEOUT } else { REMOTE_TIMEOUT }
+    }
+}
+
+/// Save the states received, the host relays the messages to the other peers.
+#[derive(Clone)]
+pub(crate) struct ReplicationHandler {
+    remote: Arc<Mutex<HashMap<u64, RemotePlayer>>>,
+    changed: Arc<AtomicBool>,
+    chat: SharedChat,
+    /// The server to relay, only set for the host.
+    relay: Arc<OnceCell<Server>>,
+    /// My id and the last correction to me.
+    me: Option<u64>,
+    correction: Arc<Mutex<Option<PlayerState>>>,
+}
+
+impl ReplicationHandler {
+    /// The handler of the host relaying by the server set later.
+    pub(crate) fn relaying(remote: Arc<Mutex<HashMap<u64, RemotePlayer>>>, chat: SharedChat, relay: Arc<OnceCell<Server>>) -> Self {
+        Self {
+            remote,
+            changed: Default::default(),
+            chat,
+            relay,
+            me: None,
+            correction: Default::default(),
+        }
+    }
+}
+
+impl TypedDataHandler for ReplicationHandler {
+    type Data = PlayerMessage;
+
+    fn handle(&self, src: &Peer, msg: PlayerMessage) -> bool {
+        if let Some(server) = self.relay.get() {
+            match msg.encode() {
+                Ok(data) => {
+                    server.broadcast(msg.channel(), &data, Some(src.addr));
+                }
+                Err(e) => warn!(target: "multiplayer", "Encode message to relay failed for {:?}", e),
+            }
+        }
+        match msg {
+            PlayerMessage::State(state) | PlayerMessage::Input { state, .. } => {
+                self.remote.lock().unwrap().insert(state.id, RemotePlayer { state, updated: Instant::now(), away: false });
+                self.changed.store(true, Ordering::Release);
+            }
+            PlayerMessage::Leave(id) => {
+                self.remote.lock().unwrap().remove(&id);
+                self.changed.store(true, Ordering::Release);
+            }
+            PlayerMessage::Chat { id, text } => {
+                let text = text.chars().take(MAX_CHAT_LEN).collect();
+                self.chat.lock().unwrap().push(Some(id), text);
+            }
+            PlayerMessage::Away { id, away } => {
+                if let Some(x) = self.remote.lock().unwrap().get_mut(&id) {
+                    x.away = away;
+                    x.updated = Instant::now();
+                }
+            }
+            PlayerMessage::Correct(state) => {
+                if self.me == Some(state.id) {
+                    *self.correction.lock().unwrap() = Some(state);
+                }
+            }
+        }
+        true
+    }
+}
+
+/// The simple meshes of the remote players.
+#[derive(Default)]
+pub struct Avatars {
+    texture_bind: Option<BindGroup>,
+    /// (world, avatar)
+    planes: Vec<(usize, StaticPlanes)>,
+}
+
+#[allow(unused)]
+impl Avatars {
+    /// Rebuild the avatars for the players.
+    pub fn rebuild<'a>(&mut self, gpu: &WgpuData, pr: &PlaneRenderer, res: &ResourceManager,
+                       players: impl Iterator<Item=&'a PlayerState>) {
+        if self.texture_bind.is_none() {
+            if let Some(pf) = res.textures.by_name("pf") {
+                self.texture_bind = Some(gpu.device.create_bind_group(&BindGroupDescriptor {
+                    label: Some("Avatar bind group"),
+                    layout: &pr.obj_layout,
+                    entries: &[BindGroupEntry {
+                        binding: 0,
+                        resource: BindingResource::TextureView(pf.srgb_view()),
+                    }],
+                }));
+            }
+        }
+        self.planes = players.map(|x| {
+            let eye = Vector3::from(x.position);
+            let forward = Vector3::new(x.target[0], x.target[1], 0.0)
+                .try_normalize(f32::EPSILON)
+                .unwrap_or_else(Vector3::x);
+            let mut objs = cube(&eye, 0.2, &forward);
+            objs.extend(cube(&(eye - Vector3::z() * 0.6), 0.3, &forward));
+            (x.world, Planes { objs, texture_bind: None }.to_static(&gpu.device))
+        }).collect();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.planes.is_empty()
+    }
+
+    /// Render the avatars in the world with the pipeline set.
+    pub fn render<'a>(&'a self, rp: &mut RenderPass<'a>, gpu: &WgpuData, pr: &'a PlaneRenderer, world: usize) {
+        let bind = match self.texture_bind.as_ref() {
+            Some(x) => x,
+            None => return,
+        };
+        rp.set_bind_group(1, bind, &[]);
+        for (_, planes) in self.planes.iter().filter(|x| x.0 == world) {
+            pr.render_static(rp, gpu, std::slice::from_ref(planes));
+        }
+    }
+}
+
+/// The six faces of the cube facing `forward`
+fn cube(center: &Vector3<f32>, r: f32, forward: &Vector3<f32>) -> Vec<PlaneObject> {
+    let right = forward.cross(&Vector3::z());
+    let faces = [
+        (Vector3::z(), right),
+        (-Vector3::z(), right),
+        (*forward, right),
+        (-forward, right),
+        (right, *forward),
+        (-right, *forward),
+    ];
+    faces.iter()
+        .map(|(up, right)| PlaneObject::new(&(center + up * r), r, &Vector2::zeros(), r * 0.5, up, right))
+        .collect()
+}
+
+/// Replicate my state to the others by hosting or joining.
+pub struct Multiplayer {
+    pub id: u64,
+    remote: Arc<Mutex<HashMap<u64, RemotePlayer>>>,
+    changed: Arc<AtomicBool>,
+    chat: SharedChat,
+    relay: Arc<OnceCell<Server>>,
+    correction: Arc<Mutex<Option<PlayerState>>>,
+    server: Option<Server>,
+    client: Option<Peer>,
+    /// Punching the hole to the other player by the rendezvous server.
+    punching: Option<JoinHandle<anyhow::Result<Peer>>>,
+    /// Telling the others I am away while suspended.
+    keepalive: Option<JoinHandle<()>>,
+    addr: SocketAddr,
+    send_timer: f32,
+    /// My world and position sent last, the movement is sent from it.
+    last_sent: Option<(usize, Vector3<f32>)>,
+}
+
+#[allow(unused)]
+impl Multiplayer {
+    fn new(addr: SocketAddr, chat: SharedChat) -> Self {
+        Self {
+            id: rand::random(),
+            remote: Default::default(),
+            changed: Default::default(),
+            chat,
+            relay: Default::default(),
+            correction: Default::default(),
+            server: None,
+            client: None,
+            punching: None,
+            keepalive: None,
+            addr,
+            send_timer: 0.0,
+            last_sent: None,
+        }
+    }
+
+    fn handler(&self) -> ReplicationHandler {
+        ReplicationHandler {
+            remote: self.remote.clone(),
+            changed: self.changed.clone(),
+            chat: self.chat.clone(),
+            relay: self.relay.clone(),
+            me: Some(self.id),
+            correction: self.correction.clone(),
+        }
+    }
+
+    /// Resolve the address in the config, use [`DEFAULT_PORT`] if the port is not set.
+    pub fn resolve(addr: &str) -> anyhow::Result<SocketAddr> {
+        let addr = if addr.is_empty() { "127.0.0.1" } else { addr };
+        match addr.to_socket_addrs() {
+            Ok(mut x) => x.next(),
+            Err(_) => (addr, DEFAULT_PORT).to_socket_addrs()?.next(),
+        }.ok_or(anyhow!("No address for {}", addr))
+    }
+
+    /// Host at the port of the address, the messages from the peers are relayed.
+    ///
+    /// The chat messages received are pushed to `chat` and the outgoing are sent.
+    pub fn host(addr: SocketAddr, chat: SharedChat) -> anyhow::Result<Self> {
+        let mut this = Self::new(addr, chat);
+        let handler = this.handler();
+        let listen = SocketAddr::new([0, 0, 0, 0].into(), addr.port());
+        let server = NET_RUNTIME.block_on(Server::new_typed(listen, handler))?;
+        let _ = this.relay.set(server.clone());
+        this.server = Some(server);
+        info!(target: "multiplayer", "Hosting at {:?}", listen);
+        Ok(this)
+    }
+
+    pub fn join(addr: SocketAddr, chat: SharedChat) -> anyhow::Result<Self> {
+        let mut this = Self::new(addr, chat);
+        let handler = this.handler();
+        this.client = Some(NET_RUNTIME.block_on(Peer::connect_typed(addr, handler))?);
+        Ok(this)
+    }
+
+    /// Meet the other player in the session at the rendezvous server and connect directly.
+    ///
+    /// It is connecting in the background, see [`Multiplayer::is_punching`]
+    pub fn punch(rendezvous: SocketAddr, session: String, chat: SharedChat) -> Self {
+        let mut this = Self::new(rendezvous, chat);
+        let handler = this.handler();
+        this.punching = Some(NET_RUNTIME.spawn(async move {
+            let punched = punch(rendezvous, &session).await?;
+            Peer::connect_punched_typed(punched, handler).await
+        }));
+        this
+    }
+
+    pub fn is_punching(&self) -> bool {
+        self.punching.is_some()
+    }
+
+    pub fn is_host(&self) -> bool {
+        self.server.is_some()
+    }
+
+    /// Return false if the connection to the host is lost.
+    pub fn is_connected(&self) -> bool {
+        self.server.is_some() || self.punching.is_some() || self.client.as_ref().map(|x| x.listening.load(Ordering::Acquire)).unwrap_or(false)
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// My state resolved by the dedicated server if I moved into the colliders there, taken once.
+    pub fn take_correction(&mut self) -> Option<PlayerState> {
+        let correction = self.correction.lock().unwrap().take();
+        if correction.is_some() {
+            // the teleport is not a movement
+            self.last_sent = None;
+        }
+        correction
+    }
+
+    /// The players except me.
+    pub fn remote_count(&self) -> usize {
+        self.remote.lock().unwrap().len()
+    }
+
+    fn send(&self, msg: &PlayerMessage) {
+        let data = match msg.encode() {
+            Ok(data) => data,
+            Err(e) => {
+                warn!(target: "multiplayer", "Encode message failed for {:?}", e);
+                return;
+            }
+        };
+        if let Some(client) = self.client.as_ref() {
+            let _ = client.send_on(msg.channel(), data);
+        } else if let Some(server) = self.server.as_ref() {
+            server.broadcast(msg.channel(), &data, None);
+        }
+    }
+
+    /// Send my state and update the avatars of the remote players.
+    pub fn update(&mut self, dt: f32, me_world: usize, camera: &Camera, avatars: &mut Avatars,
+                  gpu: &WgpuData, pr: &PlaneRenderer, res: &ResourceManager) {
+        if self.punching.as_ref().map(|x| x.is_finished()).unwrap_or(false) {
+            let result = NET_RUNTIME.block_on(self.punching.take().unwrap())
+                .map_err(anyhow::Error::from)
+                .and_then(|x| x);
+            match result {
+                Ok(peer) => {
+                    info!(target: "multiplayer", "Connected to {:?} by the rendezvous {:?}", peer.addr, self.addr);
+                    self.client = Some(peer);
+                }
+                Err(e) => warn!(target: "multiplayer", "Punch by the rendezvous {:?} failed for {:?}", self.addr, e),
+            }
+        }
+        self.send_timer += dt;
+        if self.send_timer >= SEND_INTERVAL {
+            self.send_timer = 0.0;
+            let state = PlayerState {
+                id: self.id,
+                world: me_world,
+                position: camera.eye.coords.into(),
+                target: camera.target.into(),
+            };
+            // the portals crossed are not a movement
+            let movement = self.last_sent
+                .filter(|x| x.0 == me_world)
+                .map_or_else(Vector3::zeros, |x| camera.eye.coords - x.1);
+            self.last_sent = Some((me_world, camera.eye.coords));
+            self.send(&PlayerMessage::Input { state, movement: movement.into() });
+        }
+        let outgoing = self.chat.lock().unwrap().take_outgoing();
+        for text in outgoing {
+            self.send(&PlayerMessage::Chat { id: self.id, text });
+        }
+
+        let mut remote = self.remote.lock().unwrap();
+        let count = remote.len();
+        remote.retain(|_, x| !x.is_timeout());
+        if remote.len() != count || self.changed.swap(false, Ordering::AcqRel) {
+            avatars.rebuild(gpu, pr, res, remote.values().map(|x| &x.state));
+        }
+    }
+}
+
+impl Lifecycle for Multiplayer {
+    /// Tell the others I am away and keep telling in the background, the updates stop when suspended.
+    fn suspend(&mut self) {
+        let away = PlayerMessage::Away { id: self.id, away: true };
+        self.send(&away);
+        let data = match away.encode() {
+            Ok(data) => data,
+            Err(e) => {
+                warn!(target: "multiplayer", "Encode message failed for {:?}", e);
+                return;
+            }
+        };
+        // not clone the peer, the clone stops the peer when dropped.
+        let sender = self.client.as_ref().map(|x| x.sender.clone());
+        let relay = self.relay.clone();
+        if let Some(keepalive) = self.keepalive.replace(NET_RUNTIME.spawn(async move {
+            loop {
+                tokio::time::sleep(KEEPALIVE_INTERVAL).await;
+                if let Some(sender) = sender.as_ref() {
+                    if sender.send(NetworkMessage::Channel(away.channel(), data.clone())).is_err() {
+                        break;
+                    }
+                } else if let Some(server) = relay.get() {
+                    server.broadcast(away.channel(), &data, None);
+                }
+            }
+        })) {
+            keepalive.abort();
+        }
+    }
+
+    fn resume(&mut self) {
+        if let Some(keepalive) = self.keepalive.take() {
+            keepalive.abort();
+        }
+        self.send(&PlayerMessage::Away { id: self.id, away: false });
+        // send my state now
+        self.send_timer = SEND_INTERVAL;
+    }
+}
+
+impl Drop for Multiplayer {
+    fn drop(&mut self) {
+        if let Some(punching) = self.punching.take() {
+            punching.abort();
+        }
+        if let Some(keepalive) = self.keepalive.take() {
+            keepalive.abort();
+        }
+        // best effort, the others will remove me by the timeout if lost.
+        self.send(&PlayerMessage::Leave(self.id));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::engine::network::channel::Channel;
+    use crate::state::real_view::multiplayer::{PlayerMessage, PlayerState};
+
+    #[test]
+    fn test_message_round_trip() {
+        let msg = PlayerMessage::State(PlayerState {
+            id: 233,
+            world: 2,
+            position: [1.0, -2.0, 3.5],
+            target: [0.0, 1.0, 0.0],
+        });
+        let data = msg.encode().unwrap();
+        assert_eq!(PlayerMessage::decode(&data).unwrap(), msg);
+        assert!(PlayerMessage::decode(&data[..3]).is_err());
+
+        let chat = PlayerMessage::Chat { id: 233, text: "传送门".into() };
+        assert_eq!(PlayerMessage::decode(&chat.encode().unwrap()).unwrap(), chat);
+        assert_eq!(chat.channel(), Channel::RELIABLE);
+        assert_eq!(msg.channel(), Channel::UNRELIABLE);
+
+        let away = PlayerMessage::Away { id: 233, away: true };
+        assert_eq!(PlayerMessage::decode(&away.encode().unwrap()).unwrap(), away);
+        assert_eq!(away.channel(), Channel::RELIABLE);
+
+        let state = PlayerState { id: 233, world: 1, position: [0.0; 3], target: [1.0, 0.0, 0.0] };
+        let input = PlayerMessage::Input { state, movement: [0.1, 0.0, -0.05] };
+        assert_eq!(PlayerMessage::decode(&input.encode().unwrap()).unwrap(), input);
+        assert_eq!(input.channel(), Channel::UNRELIABLE);
+        let correct = PlayerMessage::Correct(state);
+        assert_eq!(PlayerMessage::decode(&correct.encode().unwrap()).unwrap(), correct);
+    }
+}
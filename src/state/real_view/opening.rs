@@ -0,0 +1,102 @@
+/// The seconds to open or close the portal.
+const OPEN_SECS: f32 = 0.4;
+
+/// The opening of the portal, scaling its quad and fading its view.
+///
+/// The portal is passable only when fully opened.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PortalOpening {
+    open: bool,
+    /// From 0 closed to 1 opened, linear in the time.
+    progress: f32,
+}
+
+impl Default for PortalOpening {
+    fn default() -> Self {
+        Self {
+            open: true,
+            progress: 1.0,
+        }
+    }
+}
+
+#[allow(unused)]
+impl PortalOpening {
+    pub fn closed() -> Self {
+        Self {
+            open: false,
+            progress: 0.0,
+        }
+    }
+
+    /// Start opening or closing from the current progress.
+    pub fn set_open(&mut self, open: bool) {
+        self.open = open;
+    }
+
+    /// Fully opened.
+    pub fn is_open(&self) -> bool {
+        self.open && self.progress >= 1.0
+    }
+
+    /// Fully closed, not rendered.
+    pub fn is_closed(&self) -> bool {
+        !self.open && self.progress <= 0.0
+    }
+
+    pub fn is_active(&self) -> bool {
+        !self.is_open() && !self.is_closed()
+    }
+
+    /// Advance the animation, return true if changed.
+    pub fn tick(&mut self, dt: f32) -> bool {
+        if !self.is_active() {
+            return false;
+        }
+        let delta = dt / OPEN_SECS;
+        self.progress = if self.open {
+            (self.progress + delta).min(1.0)
+        } else {
+            (self.progress - delta).max(0.0)
+        };
+        true
+    }
+
+    /// The scale of the quad and the alpha of the view, eased.
+    pub fn current(&self) -> f32 {
+        let t = self.progress.clamp(0.0, 1.0);
+        t * t * (3.0 - 2.0 * t)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::state::real_view::opening::PortalOpening;
+
+    #[test]
+    fn test_portal_opening() {
+        let mut opening = PortalOpening::default();
+        assert!(opening.is_open());
+        assert!(!opening.tick(0.1));
+
+        opening.set_open(false);
+        assert!(!opening.is_open());
+        assert!(opening.is_active());
+        assert!(opening.tick(0.2));
+        assert!(opening.current() > 0.0 && opening.current() < 1.0);
+        opening.tick(1.0);
+        assert!(opening.is_closed());
+        assert_eq!(opening.current(), 0.0);
+
+        // opened from where closing
+        let mut opening = PortalOpening::closed();
+        opening.set_open(true);
+        opening.tick(0.2);
+        opening.set_open(false);
+        assert!(opening.is_active());
+        opening.set_open(true);
+        opening.tick(1.0);
+        assert!(opening.is_open());
+        assert_eq!(opening.current(), 1.0);
+    }
+}
@@ -0,0 +1,91 @@
+use nalgebra::Vector3;
+use wgpu::{Color, CommandEncoderDescriptor, LoadOp, TextureFormat};
+
+use crate::engine::render::blit::BlitRenderer;
+use crate::engine::render::camera::Camera;
+use crate::engine::render::target::{TargetDesc, TargetSize};
+use crate::engine::render_ext::CommandEncoderExt;
+use crate::engine::renderer3d::renderer3d::PlaneRenderer;
+use crate::engine::WgpuData;
+use crate::state::real_view::level::MagicLevel;
+
+/// The extra views of the picture in picture.
+pub const PIP_TARGET: &str = "pip";
+pub const PIP_DEPTH: &str = "pip depth";
+/// The size of the picture in the screen size.
+const PIP_SCALE: f32 = 0.25;
+/// The space between the picture and the corner in pixels.
+const PIP_MARGIN: f32 = 16.0;
+/// The eye in front of the other end, not to see the wall behind it.
+const PIP_OFFSET: f32 = 0.05;
+
+/// The rect `[x, y, width, height]` of the picture at the right bottom of the screen in pixels.
+pub fn pip_rect((width, height): (u32, u32)) -> [f32; 4] {
+    let (w, h) = ((width as f32 * PIP_SCALE).floor().max(1.0), (height as f32 * PIP_SCALE).floor().max(1.0));
+    [(width as f32 - w - PIP_MARGIN).max(0.0), (height as f32 - h - PIP_MARGIN).max(0.0), w, h]
+}
+
+#[allow(unused)]
+impl MagicLevel {
+    /// The world and the camera looking out of the other end of the portal nearest to my eye.
+    pub(crate) fn pip_camera(&self, me: &Camera) -> Option<(usize, Camera)> {
+        let portal = self.levels[self.me_world].portals.iter()
+            .filter(|x| !x.mirror && !x.opening.is_closed())
+            .min_by(|a, b| (me.eye.coords - a.this.pos).norm().total_cmp(&(me.eye.coords - b.this.pos).norm()))?;
+        let other = &self.levels[portal.connecting.0].portals[portal.connecting.1].this;
+        let mut target = other.out_normal;
+        if target.cross(&Vector3::z()).norm() < 0.01 {
+            // not along the up axis of the view
+            target = (target + other.up * 0.01).normalize();
+        }
+        Some((other.world, Camera {
+            eye: (other.pos + other.out_normal * PIP_OFFSET).into(),
+            target,
+            ..*me
+        }))
+    }
+
+    /// Render the picture in picture and draw it over the screen.
+    ///
+    /// The camera uniform is written by the queue, so called after the scene submitted and restored to mine after.
+    pub(crate) fn render_pip(&mut self, me: &Camera, gpu: &mut WgpuData, pr: &PlaneRenderer, blit: &BlitRenderer) {
+        let (world, mut camera) = if let Some(x) = self.pip_camera(me) { x } else {
+            return;
+        };
+        let rect = pip_rect((gpu.surface_cfg.width, gpu.surface_cfg.height));
+        let size = TargetSize::Fixed(rect[2] as u32, rect[3] as u32);
+        camera.aspect = rect[2] / rect[3];
+        gpu.views.create_extra(PIP_TARGET, &gpu.device, TargetDesc { size, format: gpu.formats.color });
+        gpu.views.create_extra(PIP_DEPTH, &gpu.device, TargetDesc { size, format: TextureFormat::Depth32Float });
+        gpu.uniforms.data.camera.update_view_proj(&camera);
+        gpu.uniforms.update(&gpu.queue);
+
+        let mut encoder = gpu.device.create_command_encoder(&CommandEncoderDescriptor { label: Some("Picture in picture encoder") });
+        if let (Some(target), Some(depth)) = (gpu.views.get_extra(PIP_TARGET), gpu.views.get_extra(PIP_DEPTH)) {
+            {
+                let mut rp = encoder.begin_with_depth(&target.view, LoadOp::Clear(Color::BLACK),
+                                                      &depth.view, LoadOp::Clear(1.0));
+                self.render_world(&mut rp, gpu, pr, world);
+            }
+            let bind = self.pip_bind.get(&gpu.views, &[PIP_TARGET], |x| blit.bind(gpu, &x[0].view));
+            if let Some(bind) = bind {
+                blit.blit_viewport_bound(&mut encoder, bind, &gpu.views.get_screen().view, rect);
+            }
+        }
+        gpu.queue.submit(Some(encoder.finish()));
+        gpu.uniforms.data.camera.update_view_proj(me);
+        gpu.uniforms.update(&gpu.queue);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::state::real_view::pip::pip_rect;
+
+    #[test]
+    fn test_pip_rect() {
+        assert_eq!(pip_rect((1600, 900)), [1184.0, 659.0, 400.0, 225.0]);
+        // still in the screen if too small
+        assert_eq!(pip_rect((8, 8)), [0.0, 0.0, 2.0, 2.0]);
+    }
+}
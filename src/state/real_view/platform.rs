@@ -0,0 +1,187 @@
+use nalgebra::{vector, Vector2, Vector3};
+use rapier3d::prelude::{ColliderBuilder, RigidBodyBuilder, RigidBodyHandle};
+use wgpu::{BindGroup, BufferUsages};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+
+use crate::engine::physics::event::ColliderTag;
+use crate::engine::physics::state::RapierData;
+use crate::engine::render::arena::BufferRange;
+use crate::engine::renderer3d::renderer3d::{PlaneObject, StaticPlanes};
+use crate::engine::WgpuData;
+use crate::state::real_view::level::Level;
+
+/// The thickness of the platform slab.
+pub const PLATFORM_THICKNESS: f32 = 0.1;
+
+/// The path of the platform, back and forth through the points or looped.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlatformPath {
+    pub points: Vec<Vector3<f32>>,
+    /// The distance moved per second.
+    pub speed: f32,
+    /// The seconds waited at each point.
+    pub wait: f32,
+    /// Go to the first point from the last, else back the same way.
+    pub looped: bool,
+}
+
+impl PlatformPath {
+    /// The (from, to) of the legs in a period.
+    fn legs(&self) -> Vec<(Vector3<f32>, Vector3<f32>)> {
+        let n = self.points.len();
+        let forward = self.points.windows(2).map(|x| (x[0], x[1]));
+        if self.looped {
+            forward.chain((n >= 2).then(|| (self.points[n - 1], self.points[0]))).collect()
+        } else {
+            let back = self.points.windows(2).rev().map(|x| (x[1], x[0]));
+            forward.chain(back).collect()
+        }
+    }
+
+    /// The seconds to go through the path and back to the first point.
+    pub fn period(&self) -> f32 {
+        self.legs().iter()
+            .map(|(a, b)| self.wait + (b - a).norm() / self.speed)
+            .sum()
+    }
+
+    /// The position after the seconds from the first point.
+    pub fn position(&self, time: f32) -> Vector3<f32> {
+        let first = self.points.first().copied().unwrap_or_else(Vector3::zeros);
+        let period = self.period();
+        if !(self.speed > 0.0 && period > 0.0 && period.is_finite()) {
+            return first;
+        }
+        let mut t = time.rem_euclid(period);
+        for (a, b) in self.legs() {
+            if t < self.wait {
+                return a;
+            }
+            t -= self.wait;
+            let secs = (b - a).norm() / self.speed;
+            if t < secs {
+                return a.lerp(&b, t / secs);
+            }
+            t -= secs;
+        }
+        first
+    }
+}
+
+/// The kinematic slab moving along the path, with its planes moved by the body.
+#[derive(Debug)]
+pub struct Platform {
+    pub body: RigidBodyHandle,
+    pub path: PlatformPath,
+    time: f32,
+    /// The planes around the body origin.
+    objs: Vec<PlaneObject>,
+    pub(crate) planes: StaticPlanes,
+    /// The translation in the last step.
+    pub(crate) delta: Vector3<f32>,
+}
+
+#[allow(unused)]
+impl Platform {
+    /// Move the body to the position of the path after the step, not moved until the physics stepped.
+    pub fn step(&mut self, p: &mut RapierData, dt: f32) {
+        self.time += dt;
+        let next = self.path.position(self.time);
+        if let Some(body) = p.rigid_body_set.get_mut(self.body) {
+            self.delta = next - body.translation();
+            body.set_next_kinematic_translation(next);
+        }
+    }
+
+    /// Write the planes moved to the translation.
+    pub fn update_planes(&self, gpu: &WgpuData, translation: &Vector3<f32>) {
+        let objs = self.objs.iter().map(|x| {
+            let mut x = *x;
+            x.vertex.iter_mut().for_each(|v| v.pos += translation);
+            x
+        }).collect::<Vec<_>>();
+        self.planes.buffer.write(&gpu.queue, bytemuck::cast_slice(&objs[..]));
+    }
+}
+
+#[allow(unused)]
+impl Level {
+    /// Add the square slab of the half size `r` facing `up` moving along the path, return the platform index.
+    ///
+    /// The texture bind is from the planes created by the plane renderer.
+    pub fn add_platform(&mut self, p: &mut RapierData, gpu: &WgpuData, texture_bind: Option<BindGroup>,
+                        up: &Vector3<f32>, r: f32, path: PlatformPath) -> usize {
+        let start = path.position(0.0);
+        let body = p.rigid_body_set.insert(RigidBodyBuilder::kinematic_position_based()
+            .translation(start)
+            .build());
+        let v = (vector![1.0, 1.0, 1.0] - up.abs()) * r + up.abs() * PLATFORM_THICKNESS / 2.0;
+        p.collider_set.insert_with_parent(ColliderBuilder::cuboid(v.x, v.y, v.z)
+                                              .friction(1.0)
+                                              .user_data(ColliderTag::for_plane(up).into())
+                                              .build(), body, &mut p.rigid_body_set);
+        let right = if up.x.abs() > 0.5 { Vector3::y() } else { Vector3::x() };
+        let half = up * PLATFORM_THICKNESS / 2.0;
+        let objs = vec![
+            PlaneObject::new(&half, r, &Vector2::zeros(), r / 2.0, up, &right),
+            PlaneObject::new(&-half, r, &Vector2::zeros(), r / 2.0, &-up, &right),
+        ];
+        let buffer = gpu.device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("platform planes"),
+            contents: bytemuck::cast_slice(&objs[..]),
+            usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+        });
+        let platform = Platform {
+            body,
+            path,
+            time: 0.0,
+            objs,
+            planes: StaticPlanes {
+                count: 2,
+                buffer: BufferRange::whole(buffer),
+                texture_bind,
+            },
+            delta: Vector3::zeros(),
+        };
+        platform.update_planes(gpu, &start);
+        self.platforms.push(platform);
+        self.platforms.len() - 1
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use nalgebra::{vector, Vector3};
+
+    use crate::state::real_view::platform::PlatformPath;
+
+    #[test]
+    fn test_platform_path() {
+        let mut path = PlatformPath {
+            points: vec![Vector3::zeros(), vector![0.0, 0.0, 2.0], vector![2.0, 0.0, 2.0]],
+            speed: 1.0,
+            wait: 1.0,
+            looped: false,
+        };
+        assert_eq!(path.period(), 12.0);
+        assert_eq!(path.position(0.5), Vector3::zeros());
+        assert_eq!(path.position(2.0), vector![0.0, 0.0, 1.0]);
+        assert_eq!(path.position(3.5), vector![0.0, 0.0, 2.0]);
+        assert_eq!(path.position(5.5), vector![1.5, 0.0, 2.0]);
+        // back the same way
+        assert_eq!(path.position(6.5), vector![2.0, 0.0, 2.0]);
+        assert_eq!(path.position(8.0), vector![1.0, 0.0, 2.0]);
+        assert_eq!(path.position(13.0), path.position(1.0));
+
+        path.looped = true;
+        assert!((path.period() - (7.0 + 8.0f32.sqrt())).abs() < 1e-4);
+        let back = path.position(7.0 + 2.0f32.sqrt());
+        assert!((back - vector![1.0, 0.0, 1.0]).norm() < 1e-4, "{:?}", back);
+
+        path.speed = 0.0;
+        assert_eq!(path.position(3.0), Vector3::zeros());
+        path.points.clear();
+        path.speed = 1.0;
+        assert_eq!(path.position(3.0), Vector3::zeros());
+    }
+}
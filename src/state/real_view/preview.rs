@@ -0,0 +1,68 @@
+use egui::{Context, TextureId};
+use wgpu::FilterMode;
+
+use crate::engine::WgpuData;
+use crate::state::real_view::level::MagicLevel;
+
+/// The width of the preview image in points.
+const PREVIEW_WIDTH: f32 = 240.0;
+
+/// Show the other side of the portal looked at in a small window.
+///
+/// The image is the portal view already rendered for the frame, so it is the same as the portal shows.
+#[derive(Default)]
+pub struct PortalPreview {
+    /// The texture registered to egui and the id of the portal view it is from.
+    texture: Option<(TextureId, u64)>,
+}
+
+#[allow(unused)]
+impl PortalPreview {
+    /// Show the preview after the level rendered.
+    pub fn show(&mut self, ctx: &Context, level: &MagicLevel, gpu: &WgpuData, renderer: &mut egui_wgpu::Renderer) {
+        let pv = if let Some(pv) = level.portal_views.first() { pv } else {
+            return;
+        };
+        let view = &pv.color;
+        let texture = match self.texture {
+            Some((id, view_id)) if view_id == pv.id => id,
+            Some((id, _)) => {
+                // the portal views are recreated for the new size or level
+                renderer.update_egui_texture_from_wgpu_texture(&gpu.device, &view.view, FilterMode::Linear, id);
+                self.texture = Some((id, pv.id));
+                id
+            }
+            None => {
+                let id = renderer.register_native_texture(&gpu.device, &view.view, FilterMode::Linear);
+                self.texture = Some((id, pv.id));
+                id
+            }
+        };
+
+        egui::Window::new("Portal preview")
+            .resizable(false)
+            .collapsible(true)
+            .anchor(egui::Align2::RIGHT_TOP, [-8.0, 8.0])
+            .show(ctx, |ui| {
+                match level.looked_portal {
+                    Some(looked) => {
+                        let connecting = level.levels[looked.world].portals[looked.idx].connecting;
+                        ui.label(format!("World {} [{}] -> World {} [{}]", looked.world, looked.idx, connecting.0, connecting.1));
+                        let (w, h) = (view.info.width as f32 * looked.uv.width(), view.info.height as f32 * looked.uv.height());
+                        let size = egui::vec2(PREVIEW_WIDTH, PREVIEW_WIDTH * h / w.max(1.0));
+                        ui.add(egui::Image::new(texture, size).uv(looked.uv));
+                    }
+                    None => {
+                        ui.label("No portal in sight");
+                    }
+                }
+            });
+    }
+
+    /// Free the texture registered.
+    pub fn clear(&mut self, renderer: &mut egui_wgpu::Renderer) {
+        if let Some((id, _)) = self.texture.take() {
+            renderer.free_texture(&id);
+        }
+    }
+}
@@ -1,5 +1,14 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use wgpu::util::align_to;
+
+use crate::engine::glft::instance::InstanceRaw;
+use crate::engine::glft::model::ModelVertex;
+use crate::engine::pacing::mark_frame_event;
 use crate::engine::prelude::*;
 use crate::engine::renderer3d::renderer3d::{PlaneRenderer, PlaneVertex};
+use crate::engine::uniform::UniformStager;
 
 /// Extends normal 3d renderer
 /// render view on the portal
@@ -8,15 +17,81 @@ pub struct PortalRenderer {
     pub depth_bind_layout: BindGroupLayout,
     /// Render the scenes in the portal view
     pub portal_view_rp: RenderPipeline,
+    /// Render the models in the portal view
+    pub portal_model_rp: RenderPipeline,
+    /// Render the planes of the texture arrays in the portal view
+    pub portal_array_rp: RenderPipeline,
+    /// Render the planes of the materials in the portal view
+    pub portal_pbr_rp: RenderPipeline,
+    /// Render the rims of the portals to the glow target.
+    pub rim_rp: RenderPipeline,
     pub render_portal_view_rp: RenderPipeline,
+    /// Render the portals deeper than the depth rendered by the fallback color.
+    pub fallback_rp: RenderPipeline,
+    /// The bindgroup of the fallback color for group 1 (object)
+    pub fallback_bind: BindGroup,
+    /// Render the view in the first depth to the screen, faded.
+    pub screen_view_rp: RenderPipeline,
+    /// Render the view of the mirror, mirrored horizontally.
+    pub mirror_view_rp: RenderPipeline,
+    /// Render the view of the mirror in the first depth to the screen.
+    pub screen_mirror_rp: RenderPipeline,
+    /// The fades of the portals for group 3, offset by the slot of the portal.
+    pub fade_bind: BindGroup,
+    fade_layout: BindGroupLayout,
+    fade_buffer: Buffer,
+    /// The bytes between the fades, aligned for the dynamic offset.
+    fade_stride: BufferAddress,
+    /// The slots of the fade buffer, grown for more portals.
+    fade_slots: usize,
+}
+
+/// The color in the portals not rendered.
+const FALLBACK_COLOR: [u8; 4] = [40, 16, 56, 255];
+/// The slots of the fades at first, doubled when the portals are more.
+const FADE_SLOTS: usize = 64;
+/// The size of the fade uniform, the alpha padded.
+const FADE_SIZE: BufferAddress = 16;
+/// Blend the view over the things behind the portal by the fade alpha.
+const FADE_BLEND: BlendState = BlendState {
+    color: BlendComponent {
+        src_factor: BlendFactor::SrcAlpha,
+        dst_factor: BlendFactor::OneMinusSrcAlpha,
+        operation: BlendOperation::Add,
+    },
+    alpha: BlendComponent::REPLACE,
+};
+
+/// The buffer of the fades in the slots and the bind of it.
+fn create_fades(device: &Device, layout: &BindGroupLayout, stride: BufferAddress, slots: usize) -> (Buffer, BindGroup) {
+    let buffer = device.create_buffer(&BufferDescriptor {
+        label: Some("portal fades"),
+        size: stride * slots as BufferAddress,
+        usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    let bind = device.create_bind_group(&BindGroupDescriptor {
+        label: Some("portal fade bind"),
+        layout,
+        entries: &[BindGroupEntry {
+            binding: 0,
+            resource: BindingResource::Buffer(BufferBinding {
+                buffer: &buffer,
+                offset: 0,
+                size: BufferSize::new(FADE_SIZE),
+            }),
+        }],
+    });
+    (buffer, bind)
 }
 
 impl PortalRenderer {
     pub fn new(gpu: &WgpuData, pr: &PlaneRenderer) -> Self {
+        mark_frame_event("portal pipelines creation");
         let device = &gpu.device;
         let shader_module = device.create_shader_module(ShaderModuleDescriptor {
             label: Some("Portal 3d renderer"),
-            source: ShaderSource::Wgsl(include_str!("portal.wgsl").into()),
+            source: ShaderSource::Wgsl(concat!(include_str!("../../../engine/render/color.wgsl"), include_str!("portal.wgsl")).into()),
         });
 
         let depth_bind_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
@@ -33,11 +108,32 @@ impl PortalRenderer {
             }],
         });
 
+        let fade_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("portal fade layout"),
+            entries: &[BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: true,
+                    min_binding_size: BufferSize::new(FADE_SIZE),
+                },
+                count: None,
+            }],
+        });
+        let fade_stride = align_to(FADE_SIZE, device.limits().min_uniform_buffer_offset_alignment as BufferAddress);
+        let (fade_buffer, fade_bind) = create_fades(device, &fade_layout, fade_stride, FADE_SLOTS);
+
         let rp_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
             label: None,
             bind_group_layouts: &[&pr.base_bind_layout, &pr.obj_layout, &depth_bind_layout],
             push_constant_ranges: &[],
         });
+        let fade_rp_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("portal fade pipeline layout"),
+            bind_group_layouts: &[&pr.base_bind_layout, &pr.obj_layout, &depth_bind_layout, &fade_layout],
+            push_constant_ranges: &[],
+        });
 
 
         let portal_view_rp = device.create_render_pipeline(&RenderPipelineDescriptor {
@@ -65,16 +161,113 @@ impl PortalRenderer {
                 module: &shader_module,
                 entry_point: "portal_fs",
                 targets: &[Some(ColorTargetState {
-                    format: gpu.surface_cfg.format,
+                    format: gpu.formats.color,
+                    blend: Some(BlendState::REPLACE),
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+        });
+        let array_rp_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("portal array pipeline layout"),
+            bind_group_layouts: &[&pr.base_bind_layout, &pr.array_layout, &depth_bind_layout],
+            push_constant_ranges: &[],
+        });
+        let portal_array_rp = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("portal array pipeline"),
+            layout: Some(&array_rp_layout),
+            vertex: VertexState {
+                module: &shader_module,
+                entry_point: "plane_array_vs",
+                buffers: &[PlaneVertex::desc()],
+            },
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleStrip,
+                cull_mode: None,
+                ..Default::default()
+            },
+            depth_stencil: Some(DepthStencilState {
+                format: TextureFormat::Depth32Float,
+                depth_write_enabled: true,
+                depth_compare: CompareFunction::LessEqual,
+                stencil: Default::default(),
+                bias: Default::default(),
+            }),
+            multisample: Default::default(),
+            fragment: Some(FragmentState {
+                module: &shader_module,
+                entry_point: "portal_array_fs",
+                targets: &[Some(ColorTargetState {
+                    format: gpu.formats.color,
+                    blend: Some(BlendState::REPLACE),
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+        });
+        let material_rp_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("portal material pipeline layout"),
+            bind_group_layouts: &[&pr.base_bind_layout, &pr.material_layout, &depth_bind_layout],
+            push_constant_ranges: &[],
+        });
+        let mut portal_pbr_desc = RenderPipelineDescriptor {
+            label: Some("portal material pipeline"),
+            layout: Some(&material_rp_layout),
+            vertex: VertexState {
+                module: &shader_module,
+                entry_point: "plane_pbr_vs",
+                buffers: &[PlaneVertex::desc()],
+            },
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleStrip,
+                cull_mode: None,
+                ..Default::default()
+            },
+            depth_stencil: Some(DepthStencilState {
+                format: TextureFormat::Depth32Float,
+                depth_write_enabled: true,
+                depth_compare: CompareFunction::LessEqual,
+                stencil: Default::default(),
+                bias: Default::default(),
+            }),
+            multisample: Default::default(),
+            fragment: Some(FragmentState {
+                module: &shader_module,
+                entry_point: "portal_pbr_fs",
+                targets: &[Some(ColorTargetState {
+                    format: gpu.formats.color,
                     blend: Some(BlendState::REPLACE),
                     write_mask: ColorWrites::ALL,
                 })],
             }),
             multiview: None,
+        };
+        let portal_pbr_rp = device.create_render_pipeline(&portal_pbr_desc);
+        let model_buffers = [ModelVertex::desc(), InstanceRaw::desc()];
+        portal_pbr_desc.label = Some("portal model pipeline");
+        portal_pbr_desc.vertex.entry_point = "model_pbr_vs";
+        portal_pbr_desc.vertex.buffers = &model_buffers;
+        portal_pbr_desc.primitive.topology = PrimitiveTopology::TriangleList;
+        let portal_model_rp = device.create_render_pipeline(&portal_pbr_desc);
+        let rim_rp_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("portal rim pipeline layout"),
+            bind_group_layouts: &[&pr.base_bind_layout],
+            push_constant_ranges: &[],
         });
-        let render_portal_view_rp = device.create_render_pipeline(&RenderPipelineDescriptor {
+        let plane_buffers = [PlaneVertex::desc()];
+        portal_pbr_desc.label = Some("portal rim pipeline");
+        portal_pbr_desc.layout = Some(&rim_rp_layout);
+        portal_pbr_desc.vertex.entry_point = "plane_vs";
+        portal_pbr_desc.vertex.buffers = &plane_buffers;
+        portal_pbr_desc.primitive.topology = PrimitiveTopology::TriangleStrip;
+        if let Some(depth) = portal_pbr_desc.depth_stencil.as_mut() {
+            depth.depth_write_enabled = false;
+        }
+        portal_pbr_desc.fragment.as_mut().unwrap().entry_point = "portal_rim_fs";
+        let rim_rp = device.create_render_pipeline(&portal_pbr_desc);
+        let mut render_portal_view_desc = RenderPipelineDescriptor {
             label: None,
-            layout: Some(&rp_layout),
+            layout: Some(&fade_rp_layout),
             vertex: VertexState {
                 module: &shader_module,
                 entry_point: "plane_vs",
@@ -97,19 +290,79 @@ impl PortalRenderer {
                 module: &shader_module,
                 entry_point: "render_portal_view_fs",
                 targets: &[Some(ColorTargetState {
-                    format: gpu.surface_cfg.format,
-                    blend: Some(BlendState::REPLACE),
+                    format: gpu.formats.color,
+                    blend: Some(FADE_BLEND),
                     write_mask: ColorWrites::ALL,
                 })],
             }),
             multiview: None,
+        };
+        let render_portal_view_rp = device.create_render_pipeline(&render_portal_view_desc);
+        render_portal_view_desc.label = Some("portal fallback pipeline");
+        render_portal_view_desc.fragment.as_mut().unwrap().entry_point = "portal_fallback_fs";
+        let fallback_rp = device.create_render_pipeline(&render_portal_view_desc);
+        render_portal_view_desc.label = Some("portal screen view pipeline");
+        render_portal_view_desc.fragment.as_mut().unwrap().entry_point = "screen_portal_view_fs";
+        let screen_view_rp = device.create_render_pipeline(&render_portal_view_desc);
+        render_portal_view_desc.label = Some("mirror view pipeline");
+        render_portal_view_desc.fragment.as_mut().unwrap().entry_point = "render_mirror_view_fs";
+        let mirror_view_rp = device.create_render_pipeline(&render_portal_view_desc);
+        render_portal_view_desc.label = Some("mirror screen view pipeline");
+        render_portal_view_desc.fragment.as_mut().unwrap().entry_point = "screen_mirror_view_fs";
+        let screen_mirror_rp = device.create_render_pipeline(&render_portal_view_desc);
+
+        let fallback = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, image::Rgba(FALLBACK_COLOR)));
+        let fallback = TextureWrapper::from_image(device, &gpu.queue, &fallback, Some("portal fallback"), ColorSpace::Srgb)
+            .expect("Create the fallback texture failed");
+        let fallback_bind = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("portal fallback bind"),
+            layout: &pr.obj_layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(fallback.srgb_view()),
+            }],
         });
         Self {
             depth_bind_layout,
             portal_view_rp,
+            portal_array_rp,
+            portal_pbr_rp,
+            rim_rp,
+            portal_model_rp,
             render_portal_view_rp,
+            fallback_rp,
+            fallback_bind,
+            screen_view_rp,
+            mirror_view_rp,
+            screen_mirror_rp,
+            fade_bind,
+            fade_layout,
+            fade_buffer,
+            fade_stride,
+            fade_slots: FADE_SLOTS,
         }
     }
+
+    /// Write the fades of the portals in the slots, the buffer grown first if the portals are more.
+    pub fn write_fades(&mut self, device: &Device, stager: &mut UniformStager, fades: &[f32]) {
+        if fades.is_empty() {
+            return;
+        }
+        if fades.len() > self.fade_slots {
+            self.fade_slots = fades.len().next_power_of_two();
+            (self.fade_buffer, self.fade_bind) = create_fades(device, &self.fade_layout, self.fade_stride, self.fade_slots);
+        }
+        let mut data = vec![0u8; (self.fade_stride as usize) * fades.len()];
+        for (x, fade) in data.chunks_exact_mut(self.fade_stride as usize).zip(fades) {
+            x[..4].copy_from_slice(&fade.to_le_bytes());
+        }
+        stager.write_bytes(&self.fade_buffer, 0, &data);
+    }
+
+    /// The dynamic offset of the fade in the slot.
+    pub fn fade_offset(&self, slot: usize) -> DynamicOffset {
+        (slot.min(self.fade_slots - 1) as BufferAddress * self.fade_stride) as DynamicOffset
+    }
 }
 
 pub struct PortalDepthTexture {
@@ -118,8 +371,8 @@ pub struct PortalDepthTexture {
 }
 
 impl PortalDepthTexture {
-    pub fn new(gpu: &WgpuData, pr: &PortalRenderer) -> Self {
-        let texture = TextureWrapper::create_depth_texture(&gpu.device, &gpu.surface_cfg, "portal depth");
+    pub fn new(gpu: &WgpuData, pr: &PortalRenderer, size: (u32, u32)) -> Self {
+        let texture = TextureWrapper::new_with_size(&gpu.device, TextureFormat::Depth32Float, size);
         let bindgroup = gpu.device.create_bind_group(&BindGroupDescriptor {
             label: Some("portal depth bind"),
             layout: &pr.depth_bind_layout,
@@ -142,12 +395,17 @@ pub struct PortalView {
     pub pd: PortalDepthTexture,
     /// The bindgroup for plane 3d renderer group 1 (object)
     pub color_bind: BindGroup,
+    /// Unique for the views created, to know the textures changed.
+    pub id: u64,
 }
 
+static NEXT_VIEW_ID: AtomicU64 = AtomicU64::new(0);
+
 impl PortalView {
-    pub fn new(gpu: &WgpuData, pr: &PlaneRenderer, apr: &PortalRenderer) -> Self {
-        let color = TextureWrapper::new_with_size(&gpu.device, gpu.surface_cfg.format, (gpu.surface_cfg.width, gpu.surface_cfg.height));
-        let depth = TextureWrapper::new_with_size(&gpu.device, TextureFormat::Depth32Float, (gpu.surface_cfg.width, gpu.surface_cfg.height));
+    /// The textures are in the size, the smaller are sampled scaled when rendered to the view outside.
+    pub fn new(gpu: &WgpuData, pr: &PlaneRenderer, apr: &PortalRenderer, size: (u32, u32)) -> Self {
+        let color = TextureWrapper::new_with_size(&gpu.device, gpu.formats.color, size);
+        let depth = TextureWrapper::new_with_size(&gpu.device, TextureFormat::Depth32Float, size);
         let color_bind = gpu.device.create_bind_group(&BindGroupDescriptor {
             label: Some("portal color bind"),
             layout: &pr.obj_layout,
@@ -156,12 +414,51 @@ impl PortalView {
                 resource: BindingResource::TextureView(&color.view),
             }],
         });
-        let pd = PortalDepthTexture::new(gpu, apr);
+        let pd = PortalDepthTexture::new(gpu, apr, size);
         Self {
             color,
             depth,
             color_bind,
             pd,
+            id: NEXT_VIEW_ID.fetch_add(1, Ordering::Relaxed),
         }
     }
-}
\ No newline at end of file
+}
+/// The portal views not used by the levels, shared by the levels built later.
+#[derive(Default)]
+pub struct PortalViewPool {
+    views: HashMap<((u32, u32), TextureFormat), Vec<PortalView>>,
+    /// The render size and the format of the views pooled, all dropped when changed.
+    target: Option<((u32, u32), TextureFormat)>,
+}
+
+#[allow(unused)]
+impl PortalViewPool {
+    /// Take the view in the size pooled or create it.
+    pub fn take(&mut self, gpu: &WgpuData, pr: &PlaneRenderer, apr: &PortalRenderer, size: (u32, u32)) -> PortalView {
+        let target = (gpu.get_render_size(), gpu.formats.color);
+        if self.target != Some(target) {
+            self.views.clear();
+            self.target = Some(target);
+        }
+        self.views.get_mut(&(size, target.1))
+            .and_then(|x| x.pop())
+            .unwrap_or_else(|| PortalView::new(gpu, pr, apr, size))
+    }
+
+    /// Keep the view for the later, dropped when the surface changed.
+    pub fn put(&mut self, view: PortalView) {
+        let key = ((view.color.info.width, view.color.info.height), view.color.texture.format());
+        if self.target.is_some_and(|x| x.1 == key.1) {
+            self.views.entry(key).or_default().push(view);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.views.values().map(|x| x.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
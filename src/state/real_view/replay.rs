@@ -0,0 +1,283 @@
+use std::path::Path;
+
+use anyhow::bail;
+use egui::{Align2, Context};
+use log::{error, info, warn};
+use nalgebra::{Point3, Vector3};
+use serde::{Deserialize, Serialize};
+use wgpu::CommandEncoderDescriptor;
+use winit::event::VirtualKeyCode;
+
+use crate::engine::{GameState, LoadContext, LoopState, StateData, StateEvent, Trans};
+use crate::engine::console;
+use crate::engine::render::camera::Camera;
+use crate::engine::renderer3d::renderer3d::General3DRenderer;
+use crate::state::real_view::build::{LevelPlan, LevelSpec, LevelTask};
+use crate::state::real_view::level::MagicLevel;
+use crate::state::real_view::renderer::portal::{PortalRenderer, PortalViewPool};
+use crate::state::real_view::scene::SharedScene;
+
+/// The version of the replay file, the other versions are rejected.
+pub const REPLAY_VERSION: u32 = 1;
+/// My eye farther than it from the recorded is diverged.
+const DIVERGED_DISTANCE: f32 = 1e-3;
+
+/// My input in the frame and where I was after it.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReplayFrame {
+    pub dt: f32,
+    /// The direction moved by the keys.
+    pub ddr: [f32; 3],
+    pub running: bool,
+    /// The camera target before the update.
+    pub target: [f32; 3],
+    /// My eye and world after the update, to check the playback.
+    pub eye: [f32; 3],
+    pub world: u32,
+}
+
+impl ReplayFrame {
+    /// The distance from the eye recorded, infinity if in the other world.
+    pub fn error(&self, eye: &Point3<f32>, world: usize) -> f32 {
+        if world != self.world as usize {
+            return f32::INFINITY;
+        }
+        (eye.coords - Vector3::from(self.eye)).norm()
+    }
+}
+
+/// The frames recorded from the level built by the spec.
+///
+/// The cubes thrown and the other players are not recorded.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Replay {
+    pub version: u32,
+    pub spec: LevelSpec,
+    pub frames: Vec<ReplayFrame>,
+}
+
+#[allow(unused)]
+impl Replay {
+    pub fn new(spec: LevelSpec) -> Self {
+        Self {
+            version: REPLAY_VERSION,
+            spec,
+            frames: vec![],
+        }
+    }
+
+    pub fn record(&mut self, dt: f32, ddr: &Vector3<f32>, running: bool, target: &Vector3<f32>, eye: &Point3<f32>, world: usize) {
+        self.frames.push(ReplayFrame {
+            dt,
+            ddr: (*ddr).into(),
+            running,
+            target: (*target).into(),
+            eye: eye.coords.into(),
+            world: world as u32,
+        });
+    }
+
+    pub fn encode(&self) -> anyhow::Result<Vec<u8>> {
+        Ok(bincode::serialize(self)?)
+    }
+
+    pub fn decode(data: &[u8]) -> anyhow::Result<Self> {
+        let replay: Self = bincode::deserialize(data)?;
+        if replay.version != REPLAY_VERSION {
+            bail!("The replay version {} is not {}", replay.version, REPLAY_VERSION);
+        }
+        Ok(replay)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        std::fs::write(path, self.encode()?)?;
+        Ok(())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        Self::decode(&std::fs::read(path)?)
+    }
+}
+
+/// Play the replay in the level built again by its spec, replacing the level shared.
+///
+/// The frames are fed to the fixed time physics as recorded and my eye is checked against the recorded.
+pub struct ReplayState {
+    replay: Replay,
+    scene: SharedScene,
+    camera: Camera,
+    task: Option<LevelTask>,
+    pr: Option<PortalRenderer>,
+    /// The next frame to play.
+    frame: usize,
+    /// The first frame diverged.
+    diverged: Option<usize>,
+    max_error: f32,
+}
+
+impl ReplayState {
+    pub fn new(replay: Replay, scene: SharedScene) -> Self {
+        let camera = scene.lock().unwrap().camera;
+        Self {
+            replay,
+            scene,
+            camera,
+            task: None,
+            pr: None,
+            frame: 0,
+            diverged: None,
+            max_error: 0.0,
+        }
+    }
+
+    fn load(&mut self, s: &mut StateData) {
+        if let (Some(gpu), Some(g3d)) = (s.app.gpu.as_ref(), s.app.world.try_fetch::<General3DRenderer>()) {
+            self.pr = Some(PortalRenderer::new(gpu, &g3d.plane_renderer));
+        }
+    }
+
+    /// Build the level planned and put it in the scene, planned again if the textures are loading.
+    fn build(&mut self, s: &mut StateData, plan: LevelPlan) -> anyhow::Result<()> {
+        let gpu = if let Some(x) = s.app.gpu.as_ref() { x } else {
+            bail!("No gpu to build the level");
+        };
+        let mut g3d = if let Some(x) = s.app.world.try_fetch_mut::<General3DRenderer>() { x } else {
+            bail!("No renderer to build the level");
+        };
+        match MagicLevel::from_plan(plan, gpu, &mut g3d.plane_renderer, &s.app.res) {
+            Ok(level) => {
+                if let Some(mut old) = self.scene.lock().unwrap().level.replace(level) {
+                    old.despawn(&s.app.world);
+                }
+                Ok(())
+            }
+            Err(_) if s.app.res.reload_wanted(&LoadContext::new(gpu)) > 0 => {
+                self.task = Some(LevelTask::spawn(self.replay.spec.clone()));
+                Ok(())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn finish(&self) {
+        match self.diverged {
+            Some(frame) => warn!(target: "replay", "Played {} frames, diverged at the frame {} by {} at most", self.frame, frame, self.max_error),
+            None => info!(target: "replay", "Played {} frames, the error is {} at most", self.frame, self.max_error),
+        }
+    }
+}
+
+impl GameState for ReplayState {
+    fn start(&mut self, s: &mut StateData) {
+        info!(target: "replay", "Playing {} frames in {:?}", self.replay.frames.len(), self.replay.spec);
+        self.task = Some(LevelTask::spawn(self.replay.spec.clone()));
+        self.load(s);
+    }
+
+    fn update(&mut self, s: &mut StateData) -> (Trans, LoopState) {
+        if !console::is_typing(&s.app.world) && s.app.inputs.is_pressed(&[VirtualKeyCode::Escape]) {
+            self.finish();
+            return (Trans::Pop, LoopState::WAIT);
+        }
+        if let Some(task) = self.task.as_mut() {
+            if let Some(plan) = task.poll() {
+                self.task = None;
+                if let Err(e) = self.build(s, plan) {
+                    error!(target: "replay", "Build the level {:?} failed for {:?}", self.replay.spec, e);
+                    return (Trans::Pop, LoopState::WAIT);
+                }
+            }
+            return (Trans::None, LoopState::POLL);
+        }
+        let frame = if let Some(x) = self.replay.frames.get(self.frame) { *x } else {
+            self.finish();
+            return (Trans::Pop, LoopState::WAIT);
+        };
+        let mut scene = self.scene.lock().unwrap();
+        let level = if let Some(x) = scene.level.as_mut() { x } else {
+            return (Trans::Pop, LoopState::WAIT);
+        };
+        self.camera.target = Vector3::from(frame.target);
+        level.update(s, frame.dt, &mut self.camera, &Vector3::from(frame.ddr), frame.running);
+        if let (Some(gpu), Some(g3d)) = (s.app.gpu.as_ref(), s.app.world.try_fetch::<General3DRenderer>()) {
+            level.meshes.rebuild(&s.app.world, gpu, &g3d.plane_renderer, &s.app.res);
+            level.rebuild_body(gpu, &self.camera);
+        }
+        let error = frame.error(&self.camera.eye, level.me_world);
+        if error > DIVERGED_DISTANCE && self.diverged.is_none() {
+            warn!(target: "replay", "Diverged at the frame {} by {}, at {:?} in world {} but recorded {:?} in world {}",
+                self.frame, error, self.camera.eye, level.me_world, frame.eye, frame.world);
+            self.diverged = Some(self.frame);
+        }
+        self.max_error = self.max_error.max(error);
+        self.frame += 1;
+        scene.camera = self.camera;
+        (Trans::None, LoopState::POLL)
+    }
+
+    fn render(&mut self, s: &mut StateData, ctx: &Context) -> Trans {
+        egui::Window::new("Replay")
+            .resizable(false)
+            .anchor(Align2::LEFT_BOTTOM, [8.0, -8.0])
+            .show(ctx, |ui| {
+                if self.task.is_some() {
+                    ui.label("Building the level");
+                } else {
+                    ui.label(format!("Frame {} / {}", self.frame, self.replay.frames.len()));
+                }
+                if let Some(frame) = self.diverged {
+                    ui.label(format!("Diverged at the frame {}, {:.4} at most", frame, self.max_error));
+                }
+            });
+        let gpu = if let Some(x) = s.app.gpu.as_mut() { x } else {
+            return Trans::None;
+        };
+        self.camera.aspect = gpu.surface_cfg.width as f32 / gpu.surface_cfg.height.max(1) as f32;
+        let mut encoder = gpu.device.create_command_encoder(&CommandEncoderDescriptor { label: Some("Replay Encoder") });
+        gpu.uniforms.data.camera.update_view_proj(&self.camera);
+        gpu.uniforms.update(&gpu.queue);
+        let mut scene = self.scene.lock().unwrap();
+        if let (Some(mut g3d), Some(mut pool), Some(pr), Some(level)) = (s.app.world.try_fetch_mut::<General3DRenderer>(), s.app.world.try_fetch_mut::<PortalViewPool>(), self.pr.as_mut(), scene.level.as_mut()) {
+            g3d.plane_renderer.sky.update(&gpu.device, &s.app.res);
+            level.render(self.camera, &mut encoder, gpu, &mut g3d.plane_renderer, pr, &mut pool);
+            if let Some(render) = s.app.render.as_mut() {
+                render.blit.blit_scene(gpu, &mut encoder);
+            }
+        }
+        gpu.queue.submit(Some(encoder.finish()));
+        Trans::None
+    }
+
+    fn on_event(&mut self, s: &mut StateData, e: StateEvent) {
+        if let StateEvent::ReloadGPU = e {
+            self.load(s);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use nalgebra::{point, vector};
+
+    use crate::state::real_view::build::LevelSpec;
+    use crate::state::real_view::level_rooms::RoomTextures;
+    use crate::state::real_view::replay::{Replay, REPLAY_VERSION};
+
+    #[test]
+    fn test_replay_file() {
+        let mut replay = Replay::new(LevelSpec::Rooms(3, RoomTextures::Seeded(42)));
+        replay.record(0.016, &vector![1.0, 0.0, 0.0], false, &vector![0.0, 1.0, 0.0], &point![1.0, 2.0, 1.0], 0);
+        replay.record(0.017, &vector![0.0, 0.0, 0.0], true, &vector![0.0, 1.0, 0.0], &point![1.5, 2.0, 1.0], 1);
+        let data = replay.encode().unwrap();
+        let decoded = Replay::decode(&data).unwrap();
+        assert_eq!(decoded, replay);
+
+        let frame = decoded.frames[1];
+        assert_eq!(frame.error(&point![1.5, 2.0, 1.0], 1), 0.0);
+        assert!((frame.error(&point![1.5, 2.5, 1.0], 1) - 0.5).abs() < 1e-6);
+        assert_eq!(frame.error(&point![1.5, 2.0, 1.0], 0), f32::INFINITY);
+
+        replay.version = REPLAY_VERSION + 1;
+        assert!(Replay::decode(&replay.encode().unwrap()).is_err());
+    }
+}
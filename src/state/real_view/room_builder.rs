@@ -0,0 +1,195 @@
+use anyhow::bail;
+use nalgebra::*;
+
+use crate::engine::physics::state::RapierData;
+use crate::engine::prelude::*;
+use crate::engine::renderer3d::renderer3d::*;
+use crate::state::real_view::bounds::WorldBounds;
+use crate::state::real_view::level::*;
+use crate::state::real_view::level_gen::PORTAL_INSET;
+use crate::state::real_view::level_rooms::get_color_level;
+
+/// The distance between the hidden worlds along z, by the world index.
+const HIDDEN_SPACING: f32 = Z_OFFSET * 20.0;
+
+/// The room behind the doorway, built in the hidden world and entered by the scaled portals.
+///
+/// The room inside is along +x from the doorway, the doorway at the middle of the wall at x = 0.
+#[derive(Debug, Clone)]
+pub(crate) struct RoomBuilder {
+    /// The doorway outside, the normal to where entered from.
+    door: PortalPos,
+    /// The half size of the doorway outside.
+    r: f32,
+    /// The scale inside, the doorway inside is `r * scale`.
+    scale: f32,
+    /// The length, the width and the height inside, in the size inside.
+    size: Vector3<f32>,
+    /// The floor under the doorway inside, away from the other worlds if none.
+    offset: Option<Vector3<f32>>,
+    texture: String,
+}
+
+#[allow(unused)]
+impl RoomBuilder {
+    /// The room by the doorway of the half size `r`, everything going in is scaled by `scale`.
+    ///
+    /// The room is as wide and as high as the doorway inside, and twice as long.
+    pub fn bigger_inside(door: PortalPos, r: f32, scale: f32) -> Self {
+        let d = 2.0 * r * scale;
+        Self {
+            door,
+            r,
+            scale,
+            size: vector![2.0 * d, d, d],
+            offset: None,
+            texture: "pf".into(),
+        }
+    }
+
+    /// The length along the way in, the width and the height in the size inside.
+    pub fn interior(mut self, length: f32, width: f32, height: f32) -> Self {
+        self.size = vector![length, width, height];
+        self
+    }
+
+    pub fn offset(mut self, offset: Vector3<f32>) -> Self {
+        self.offset = Some(offset);
+        self
+    }
+
+    pub fn texture(mut self, name: impl Into<String>) -> Self {
+        self.texture = name.into();
+        self
+    }
+
+    /// The half size of the doorway inside.
+    pub fn inside_r(&self) -> f32 {
+        self.r * self.scale
+    }
+
+    fn origin(&self, world: usize) -> Vector3<f32> {
+        self.offset.unwrap_or_else(|| vector![0.0, 0.0, HIDDEN_SPACING * world as f32])
+    }
+
+    /// Check the doorway fits in the room.
+    pub fn check(&self) -> anyhow::Result<()> {
+        let r = self.inside_r();
+        if !(self.scale > 0.0 && self.r > 0.0) {
+            bail!("The doorway {} scaled by {} is empty", self.r, self.scale);
+        }
+        if self.size.y < 2.0 * r || self.size.z < 2.0 * r {
+            bail!("The doorway {} does not fit the room {:?}", 2.0 * r, self.size);
+        }
+        if self.size.x <= PORTAL_INSET {
+            bail!("The room length {} is too short", self.size.x);
+        }
+        Ok(())
+    }
+
+    /// The doorway inside the world, facing into the room.
+    pub fn inside(&self, world: usize) -> PortalPos {
+        let r = self.inside_r();
+        PortalPos {
+            world,
+            pos: self.origin(world) + vector![PORTAL_INSET, 0.0, r],
+            out_normal: Vector3::x(),
+            up: Vector3::z(),
+            width: r,
+        }
+    }
+
+    /// The box able to be in, with the margin by the scale.
+    pub fn bounds(&self, world: usize) -> WorldBounds {
+        let o = self.origin(world);
+        let margin = Vector3::repeat(self.scale);
+        WorldBounds {
+            min: o + vector![0.0, -self.size.y / 2.0, 0.0] - margin,
+            max: o + vector![self.size.x, self.size.y / 2.0, self.size.z] + margin,
+        }
+    }
+
+    /// The floor, the ceiling and the walls, each as large as the longest side to close the room.
+    pub fn planes(&self, p: &mut RapierData, world: usize) -> Planes {
+        let mut gfs = Planes { objs: vec![], texture_bind: None };
+        let o = self.origin(world);
+        let (l, w, h) = (self.size.x, self.size.y, self.size.z);
+        let s = self.size.max() / 2.0;
+        let faces = [
+            (vector![l / 2.0, 0.0, 0.0], Vector3::z(), Vector3::x()),
+            (vector![l / 2.0, 0.0, h], -Vector3::z(), Vector3::x()),
+            (vector![0.0, 0.0, h / 2.0], Vector3::x(), Vector3::y()),
+            (vector![l, 0.0, h / 2.0], -Vector3::x(), Vector3::y()),
+            (vector![l / 2.0, w / 2.0, h / 2.0], -Vector3::y(), Vector3::x()),
+            (vector![l / 2.0, -w / 2.0, h / 2.0], Vector3::y(), Vector3::x()),
+        ];
+        for (center, up, right) in faces {
+            add_plane(p, &mut gfs, &(o + center), s, &Vector2::zeros(), s / 2.0, &up, &right);
+        }
+        gfs
+    }
+
+    /// Add the room as the new world and the portals to it, return the (world, portal index) of the doorway outside and inside.
+    pub fn build(&self, level: &mut MagicLevel, gpu: &WgpuData, pr: &mut PlaneRenderer, res: &ResourceManager) -> anyhow::Result<[(usize, usize); 2]> {
+        self.check()?;
+        let world = level.levels.len();
+        let planes = self.planes(&mut level.p, world);
+        level.levels.push(get_color_level(&self.texture, planes, gpu, pr, res)?);
+        if let Some(x) = res.textures.handle(&self.texture) {
+            level.textures.insert(x);
+        }
+        if !level.world_names.is_empty() {
+            level.world_names.push(format!("room {}", world));
+        }
+        level.bounds.set_world(world, self.bounds(world));
+        let r = self.inside_r();
+        Ok(level.add_portal(gpu, pr, self.door, self.inside(world), self.r, self.r / 2.0, r, r / 2.0, self.scale))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use nalgebra::{vector, Vector3};
+
+    use crate::engine::physics::state::RapierData;
+    use crate::state::real_view::level::PortalPos;
+    use crate::state::real_view::level_gen::PORTAL_INSET;
+    use crate::state::real_view::room_builder::RoomBuilder;
+
+    #[test]
+    fn test_room_builder() {
+        let door = PortalPos {
+            world: 0,
+            pos: vector![0.0, 0.0, 1.0],
+            out_normal: Vector3::x(),
+            up: Vector3::z(),
+            width: 1.0,
+        };
+        let room = RoomBuilder::bigger_inside(door, 1.0, 3.0);
+        room.check().unwrap();
+        assert_eq!(room.inside_r(), 3.0);
+
+        let inside = room.inside(2);
+        assert_eq!(inside.world, 2);
+        assert_eq!(inside.width, 3.0);
+        let bounds = room.bounds(2);
+        assert!(bounds.contains(&inside.pos));
+        assert!(bounds.contains(&(inside.pos + vector![11.0, 2.9, 2.9])));
+        assert!(!bounds.contains(&(inside.pos + vector![20.0, 0.0, 0.0])));
+        // not the same place as the other hidden rooms
+        assert!(!room.bounds(3).contains(&inside.pos));
+
+        let mut p = RapierData::new();
+        let planes = room.planes(&mut p, 2);
+        assert_eq!(planes.objs.len(), 6);
+        assert_eq!(p.collider_set.len(), 6);
+
+        let room = room.offset(vector![100.0, 0.0, 0.0]).interior(20.0, 8.0, 6.0);
+        room.check().unwrap();
+        assert_eq!(room.inside(2).pos, vector![100.0 + PORTAL_INSET, 0.0, 3.0]);
+
+        assert!(room.clone().interior(20.0, 5.0, 6.0).check().is_err());
+        assert!(room.clone().interior(0.0, 8.0, 6.0).check().is_err());
+        assert!(RoomBuilder::bigger_inside(door, 1.0, 0.0).check().is_err());
+    }
+}
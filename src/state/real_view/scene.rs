@@ -0,0 +1,193 @@
+use std::sync::{Arc, Mutex};
+
+use egui::Context;
+use nalgebra::{vector, Vector3};
+use wgpu::{BindGroup, Color, CommandEncoder, CommandEncoderDescriptor, LoadOp, RenderPass};
+use winit::dpi::{PhysicalPosition, PhysicalSize};
+use winit::window::WindowBuilder;
+
+use crate::engine::{GameState, LoopState, StateData, StateEvent, Trans, WgpuData};
+use crate::engine::render::camera::Camera;
+use crate::engine::render_ext::CommandEncoderExt;
+use crate::engine::renderer3d::renderer3d::{General3DRenderer, PlaneRenderer};
+use crate::engine::window::WindowInstance;
+use crate::state::real_view::level::MagicLevel;
+
+/// The height of the map camera above me by default.
+pub const MAP_HEIGHT: f32 = 12.0;
+
+/// The level and my camera shared with the views in the other windows, written by the state playing the level.
+pub struct SceneHandle {
+    pub level: Option<MagicLevel>,
+    /// My camera in the last update.
+    pub camera: Camera,
+    /// The size and the position of the window playing the level.
+    pub size: (u32, u32),
+    pub loc: PhysicalPosition<i32>,
+    /// The portal texture for the overlay.
+    pub purple: Option<BindGroup>,
+}
+
+/// The scene shared by the windows, locked in turn by the states in the event loop.
+pub type SharedScene = Arc<Mutex<SceneHandle>>;
+
+impl SceneHandle {
+    pub fn new(camera: Camera) -> Self {
+        Self {
+            level: None,
+            camera,
+            size: (0, 0),
+            loc: Default::default(),
+            purple: None,
+        }
+    }
+}
+
+/// Where the view in the other window looks from.
+#[allow(unused)]
+#[derive(Debug, Copy, Clone)]
+pub enum ViewCamera {
+    /// Above me by the height looking down, the top of the map is where I look.
+    TopDown(f32),
+    /// Fixed in the world.
+    Fixed(Camera),
+}
+
+impl ViewCamera {
+    pub fn camera(&self, me: &Camera) -> Camera {
+        match *self {
+            ViewCamera::TopDown(height) => {
+                let forward = vector![me.target.x, me.target.y, 0.0].try_normalize(f32::EPSILON).unwrap_or_else(Vector3::x);
+                Camera {
+                    eye: me.eye + Vector3::z() * height,
+                    // not straight down to keep the up axis of the view
+                    target: (forward * 0.01 - Vector3::z()).normalize(),
+                    ..*me
+                }
+            }
+            ViewCamera::Fixed(camera) => camera,
+        }
+    }
+}
+
+#[allow(unused)]
+impl MagicLevel {
+    /// Render my world seen by the camera to the scene without the portals, for the views in the other windows.
+    pub(crate) fn render_overview(&self, camera: &Camera, ce: &mut CommandEncoder, gpu: &mut WgpuData, pr: &PlaneRenderer) {
+        gpu.uniforms.data.camera.update_view_proj(camera);
+        gpu.uniforms.update(&gpu.queue);
+        let mut rp = ce.begin_with_depth(&gpu.views.get_scene().view, LoadOp::Clear(Color::BLACK),
+                                         &gpu.views.get_depth_view().view, LoadOp::Clear(1.0));
+        self.render_world(&mut rp, gpu, pr, self.me_world);
+    }
+
+    /// Render the world without the portals and the bundles, seen by the camera in the uniforms.
+    ///
+    /// The bundles are bound to the uniforms of the main view, so the planes in them are drawn again.
+    pub(crate) fn render_world<'a>(&'a self, rp: &mut RenderPass<'a>, gpu: &WgpuData, pr: &'a PlaneRenderer, world: usize) {
+        self.levels[world].render_unbundled(rp, gpu, pr);
+        pr.bind(rp);
+        rp.set_pipeline(&pr.no_cull_rp);
+        self.avatars.render(rp, gpu, pr, world);
+        self.meshes.render(rp, gpu, pr, world);
+        self.body.render(rp, gpu, pr, world, true);
+        pr.bind(rp);
+        pr.sky.render(rp);
+    }
+}
+
+/// The level shared rendered from another camera, closed after the state playing it dropped.
+pub struct SceneView {
+    scene: SharedScene,
+    camera: ViewCamera,
+}
+
+impl SceneView {
+    pub fn new(scene: SharedScene, camera: ViewCamera) -> Self {
+        Self { scene, camera }
+    }
+
+    /// Open the window viewing the scene by the gpu of the current window.
+    pub fn open(s: &mut StateData, title: &str, scene: SharedScene, camera: ViewCamera) -> anyhow::Result<()> {
+        let gpu = if let Some(x) = s.app.gpu.as_ref() { x } else {
+            anyhow::bail!("No gpu to share");
+        };
+        let size = s.app.window.inner_size();
+        let mut window = WindowInstance::new_with_gpu(title, |x: WindowBuilder| x.with_inner_size(PhysicalSize::new(size.width / 2, size.height / 2)), s.wd.el, gpu)?;
+        let mut sd = StateData {
+            app: &mut window.app,
+            wd: s.wd,
+            dt: 0.0,
+        };
+        let mut view = Self::new(scene, camera);
+        view.start(&mut sd);
+        window.states.push(Box::new(view));
+        s.wd.new_windows.push(window);
+        Ok(())
+    }
+}
+
+impl GameState for SceneView {
+    fn start(&mut self, s: &mut StateData) {
+        if let Some(gpu) = s.app.gpu.as_ref() {
+            s.app.world.insert(General3DRenderer::new(gpu));
+        }
+    }
+
+    fn update(&mut self, _: &mut StateData) -> (Trans, LoopState) {
+        if Arc::strong_count(&self.scene) == 1 {
+            return (Trans::Exit, LoopState::WAIT);
+        }
+        (Trans::None, LoopState::POLL)
+    }
+
+    fn render(&mut self, s: &mut StateData, _: &Context) -> Trans {
+        let scene = self.scene.lock().unwrap();
+        let level = if let Some(x) = scene.level.as_ref() { x } else {
+            return Trans::None;
+        };
+        let gpu = if let Some(x) = s.app.gpu.as_mut() { x } else {
+            return Trans::None;
+        };
+        let mut camera = self.camera.camera(&scene.camera);
+        camera.aspect = gpu.surface_cfg.width as f32 / gpu.surface_cfg.height.max(1) as f32;
+        if let Some(mut g3d) = s.app.world.try_fetch_mut::<General3DRenderer>() {
+            g3d.plane_renderer.sky.update(&gpu.device, &s.app.res);
+            let mut encoder = gpu.device.create_command_encoder(&CommandEncoderDescriptor { label: Some("Scene View Encoder") });
+            level.render_overview(&camera, &mut encoder, gpu, &g3d.plane_renderer);
+            if let Some(render) = s.app.render.as_ref() {
+                render.blit.blit_scene(gpu, &mut encoder);
+            }
+            gpu.queue.submit(Some(encoder.finish()));
+        }
+        Trans::None
+    }
+
+    fn on_event(&mut self, s: &mut StateData, e: StateEvent) {
+        if let StateEvent::ReloadGPU = e {
+            self.start(s);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use nalgebra::{point, vector};
+
+    use crate::engine::render::camera::Camera;
+    use crate::state::real_view::scene::ViewCamera;
+
+    #[test]
+    fn test_top_down() {
+        let mut me = Camera::new(point![1.0, 2.0, 1.0]);
+        me.target = vector![0.0, 1.0, 0.5].normalize();
+        let camera = ViewCamera::TopDown(10.0).camera(&me);
+        assert_eq!(camera.eye, point![1.0, 2.0, 11.0]);
+        assert!(camera.target.z < -0.99);
+        // the top of the map is where I look
+        assert!(camera.target.y > 0.0 && camera.target.x.abs() < 1e-6);
+
+        me.target = vector![0.0, 0.0, -1.0];
+        assert!(ViewCamera::TopDown(10.0).camera(&me).target.x > 0.0);
+    }
+}
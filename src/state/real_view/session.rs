@@ -0,0 +1,104 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::bail;
+use nalgebra::{Point3, Vector3};
+use serde::{Deserialize, Serialize};
+
+use crate::engine::global::GLOBAL_DATA;
+use crate::state::real_view::build::LevelSpec;
+use crate::state::real_view::gate::PortalKeys;
+use crate::state::real_view::transition::ScaleTransition;
+
+/// The version of the session file, the other versions are ignored.
+pub const SESSION_VERSION: u32 = 2;
+/// The file of the session saved when exited, beside the config.
+pub const SESSION_FILE: &str = "session.bin";
+
+/// The path of the session file in the directory of the config.
+pub fn session_path() -> PathBuf {
+    GLOBAL_DATA.cfg_data.read().unwrap().data_path(SESSION_FILE)
+}
+
+/// Where I was in the level when exited, to continue in the next run.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Session {
+    pub version: u32,
+    pub spec: LevelSpec,
+    pub eye: [f32; 3],
+    /// The direction looking at.
+    pub target: [f32; 3],
+    pub world: u32,
+    /// My scale by the portals gone through, with the easing in progress.
+    pub scale: ScaleTransition,
+    pub keys: PortalKeys,
+}
+
+#[allow(unused)]
+impl Session {
+    pub fn new(spec: LevelSpec, eye: &Point3<f32>, target: &Vector3<f32>, world: usize, scale: ScaleTransition, keys: PortalKeys) -> Self {
+        Self {
+            version: SESSION_VERSION,
+            spec,
+            eye: eye.coords.into(),
+            target: (*target).into(),
+            world: world as u32,
+            scale,
+            keys,
+        }
+    }
+
+    pub fn encode(&self) -> anyhow::Result<Vec<u8>> {
+        Ok(bincode::serialize(self)?)
+    }
+
+    pub fn decode(data: &[u8]) -> anyhow::Result<Self> {
+        let session: Self = bincode::deserialize(data)?;
+        if session.version != SESSION_VERSION {
+            bail!("The session version {} is not {}", session.version, SESSION_VERSION);
+        }
+        Ok(session)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        std::fs::write(path, self.encode()?)?;
+        Ok(())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        Self::decode(&std::fs::read(path)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use nalgebra::{point, vector};
+
+    use crate::state::real_view::build::LevelSpec;
+    use crate::state::real_view::gate::PortalKeys;
+    use crate::state::real_view::level_rooms::RoomTextures;
+    use crate::state::real_view::session::{Session, SESSION_VERSION};
+    use crate::state::real_view::transition::ScaleTransition;
+
+    #[test]
+    fn test_session_file() {
+        // scaling down through the portal
+        let mut scale = ScaleTransition::default();
+        scale.traversed(0.5);
+        scale.tick(0.1);
+        let mut keys = PortalKeys::default();
+        keys.give("red");
+        let mut session = Session::new(LevelSpec::Rooms(4, RoomTextures::Seeded(7)), &point![1.0, 2.0, 0.5], &vector![0.0, 1.0, 0.0], 2, scale, keys);
+        assert_eq!(session.eye, [1.0, 2.0, 0.5]);
+        assert_eq!(session.world, 2);
+        let decoded = Session::decode(&session.encode().unwrap()).unwrap();
+        assert_eq!(decoded, session);
+        assert!(decoded.scale.is_active());
+        assert_eq!(decoded.scale.target(), 0.5);
+        assert_eq!(decoded.scale.current(), scale.current());
+        assert!(decoded.keys.has("red"));
+
+        session.version = SESSION_VERSION + 1;
+        assert!(Session::decode(&session.encode().unwrap()).is_err());
+        assert!(Session::decode(&[1, 2]).is_err());
+    }
+}
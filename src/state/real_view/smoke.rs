@@ -0,0 +1,112 @@
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Context};
+use nalgebra::{Point3, Vector3};
+use specs::{World, WorldExt};
+
+use crate::engine::{LoadContext, ResourceManager, WgpuData};
+use crate::engine::ecs::{PortalTraveler, setup};
+use crate::engine::render::camera::Camera;
+use crate::engine::renderer3d::renderer3d::General3DRenderer;
+use crate::state::load_texture;
+use crate::state::real_view::level::MagicLevel;
+use crate::state::real_view::level_rooms::RoomTextures;
+
+/// The frame time stepped.
+const SMOKE_DT: f32 = 1.0 / 60.0;
+/// The seed of the rooms, the same rooms in each run.
+const SMOKE_SEED: u64 = 233;
+/// The room counts of the rooms levels built-in.
+const ROOM_COUNTS: RangeInclusive<usize> = 2..=9;
+
+/// The level passed the smoke test.
+#[derive(Debug, Clone)]
+pub struct SmokeResult {
+    pub name: String,
+    /// The portals gone through.
+    pub traversed: u32,
+    /// The world of me at the end.
+    pub world: usize,
+}
+
+/// Construct every built-in level without the window, walk me through a portal and step the physics for the seconds.
+pub fn smoke_levels(seconds: f32) -> anyhow::Result<Vec<SmokeResult>> {
+    let gpu = WgpuData::headless(1600, 900)?;
+    let res = Arc::new(ResourceManager::new()?);
+    load_texture(&res, &LoadContext::new(&gpu), &[]);
+    futures::executor::block_on(res.wait_loading())?;
+    let mut g3d = General3DRenderer::new(&gpu);
+    let pr = &mut g3d.plane_renderer;
+
+    let mut results = vec![];
+    results.push(smoke_level("level0", MagicLevel::level0(&gpu, pr, &res), seconds)?);
+    results.push(smoke_level("level_loop", MagicLevel::level_loop(&gpu, pr, &res), seconds)?);
+    for cnt in ROOM_COUNTS {
+        let level = MagicLevel::level_rooms(&gpu, cnt, &RoomTextures::Seeded(SMOKE_SEED), pr, &res);
+        results.push(smoke_level(&format!("level_rooms({})", cnt), level, seconds)?);
+    }
+    Ok(results)
+}
+
+/// Check the portals are in their worlds and connected in pairs.
+fn check_portals(level: &MagicLevel) -> anyhow::Result<()> {
+    for (world, x) in level.levels.iter().enumerate() {
+        for (idx, portal) in x.portals.iter().enumerate() {
+            if portal.this.world != world {
+                bail!("The portal {:?} is in the world {}", (world, idx), portal.this.world);
+            }
+            let connecting = level.levels.get(portal.connecting.0)
+                .and_then(|x| x.portals.get(portal.connecting.1))
+                .ok_or_else(|| anyhow!("The portal {:?} connects to the missing portal {:?}", (world, idx), portal.connecting))?;
+            if connecting.connecting != (world, idx) {
+                bail!("The portal {:?} connects to {:?} connecting to {:?}", (world, idx), portal.connecting, connecting.connecting);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Walk me to the first wall portal in my world, then stand still until the time passed.
+fn smoke_level(name: &str, level: anyhow::Result<MagicLevel>, seconds: f32) -> anyhow::Result<SmokeResult> {
+    let mut level = level.with_context(|| format!("Construct the level {} failed", name))?;
+    check_portals(&level).with_context(|| format!("The portals of the level {} are wrong", name))?;
+    let mut world = World::new();
+    setup(&mut world);
+    level.spawn(&mut world);
+
+    let (start, normal) = level.levels[level.me_world].portals.iter()
+        .map(|x| &x.this)
+        .find(|x| x.out_normal.z.abs() < 0.5)
+        .map(|x| (x.pos + x.out_normal, x.out_normal))
+        .ok_or_else(|| anyhow!("No portal on the wall in the world {} of the level {}", level.me_world, name))?;
+    level.p.rigid_body_set[level.me.handle].set_translation(start, true);
+    level.scheduler.teleported(&level.p, level.me.handle);
+    level.bounds.set_checkpoint(level.me_world, start);
+
+    let me = level.entities.me.ok_or_else(|| anyhow!("Me not spawned in the level {}", name))?;
+    let mut camera = Camera::new(Point3::from(start));
+    let mut traversed = 0;
+    for frame in 0..(seconds / SMOKE_DT).ceil() as u32 {
+        let ddr = if traversed == 0 { -normal } else { Vector3::zeros() };
+        level.step_physics(&world, &mut None, SMOKE_DT, &mut camera, &ddr, false);
+        let pos = *level.p.rigid_body_set[level.me.handle].translation();
+        if level.bounds.recovered > 0 || level.bounds.is_out(level.me_world, &pos) {
+            bail!("Out of the bounds at {:?} in the world {} of the level {} in the frame {}", pos, level.me_world, name, frame);
+        }
+        let traveler = *world.read_storage::<PortalTraveler>().get(me)
+            .ok_or_else(|| anyhow!("No traveler of me in the level {}", name))?;
+        if traveler.world != level.me_world {
+            bail!("The traveler is in the world {} but me in {} of the level {}", traveler.world, level.me_world, name);
+        }
+        traversed = traveler.traversed;
+    }
+    if traversed == 0 {
+        bail!("Not went through the portal in the level {} from {:?}", name, start);
+    }
+    Ok(SmokeResult {
+        name: name.to_string(),
+        traversed,
+        world: level.me_world,
+    })
+}
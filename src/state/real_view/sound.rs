@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+
+use kira::sound::static_sound::StaticSoundData;
+use log::warn;
+use rapier3d::prelude::ColliderHandle;
+
+use crate::engine::{AudioData, Handle, ResourceManager};
+use crate::engine::physics::event::{ColliderTag, ContactImpact};
+
+/// The distance walked between two footsteps.
+const STEP_DISTANCE: f32 = 0.8;
+/// The contact force to play the impact in full volume.
+const FULL_IMPACT_FORCE: f32 = 30.0;
+/// The seconds before the same colliders play the impact again,
+/// for the forces are sent every step while pushing.
+const IMPACT_COOLDOWN: f32 = 0.3;
+
+/// Play the footstep and impact sounds of the level by the physics.
+#[derive(Default)]
+pub struct LevelSounds {
+    /// The footstep sounds by the tag of the ground.
+    footsteps: HashMap<ColliderTag, Handle<StaticSoundData>>,
+    /// The impact sounds by the tag of the collider hit.
+    impacts: HashMap<ColliderTag, Handle<StaticSoundData>>,
+    walked: f32,
+    cooldowns: HashMap<(ColliderHandle, ColliderHandle), f32>,
+}
+
+#[allow(unused)]
+impl LevelSounds {
+    pub fn set_footstep(&mut self, tag: ColliderTag, sound: Handle<StaticSoundData>) {
+        self.footsteps.insert(tag, sound);
+    }
+
+    pub fn set_impact(&mut self, tag: ColliderTag, sound: Handle<StaticSoundData>) {
+        self.impacts.insert(tag, sound);
+    }
+
+    /// Play the sounds for the step.
+    ///
+    /// `walked` is the horizontal distance walked in the step,
+    /// `ground` is the tag of the collider under me, None if in the air.
+    pub fn update(&mut self, audio: &mut AudioData, res: &ResourceManager, dt: f32, me: ColliderHandle,
+                  walked: f32, ground: Option<ColliderTag>, impacts: &[ContactImpact]) {
+        self.cooldowns.retain(|_, x| {
+            *x -= dt;
+            *x > 0.0
+        });
+
+        match ground {
+            Some(tag) => {
+                self.walked += walked;
+                if self.walked >= STEP_DISTANCE {
+                    self.walked %= STEP_DISTANCE;
+                    if let Some(sound) = self.footsteps.get(&tag).copied() {
+                        if let Err(e) = audio.play_sfx(res, sound) {
+                            warn!("Play footstep failed for {:?}", e);
+                        }
+                    }
+                }
+            }
+            None => self.walked = 0.0,
+        }
+
+        for impact in impacts {
+            // the sound of the collider hit by me, or the sound of the second one.
+            let tag = impact.other(me).map(|x| x.1).unwrap_or(impact.tag2);
+            let sound = match self.impacts.get(&tag).or_else(|| self.impacts.get(&impact.tag1)) {
+                Some(x) => *x,
+                None => continue,
+            };
+            let key = (impact.collider1, impact.collider2);
+            if self.cooldowns.contains_key(&key) {
+                continue;
+            }
+            self.cooldowns.insert(key, IMPACT_COOLDOWN);
+            let volume = (impact.total_force / FULL_IMPACT_FORCE).clamp(0.0, 1.0) as f64;
+            if let Err(e) = audio.play_sfx_with_volume(res, sound, volume) {
+                warn!("Play impact failed for {:?}", e);
+            }
+        }
+    }
+}
@@ -1,58 +1,254 @@
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
-use anyhow::anyhow;
+use anyhow::{anyhow, bail};
 
-use egui::{Context, Frame};
-use nalgebra::{point, vector};
+use egui::{Context, Frame, Slider};
+use log::{error, info, warn};
+use nalgebra::{point, vector, Vector3};
 use num::Zero;
 use rand::{Rng, thread_rng};
-use wgpu::{BindGroup, BindGroupDescriptor, BindGroupEntry, BindingResource, Color, CommandEncoderDescriptor, Extent3d, ImageCopyTexture, LoadOp, Origin3d, TextureFormat};
+use rapier3d::prelude::SharedShape;
+use wgpu::{BindGroupDescriptor, BindGroupEntry, BindingResource, Color, CommandEncoderDescriptor, Extent3d, ImageCopyTexture, LoadOp, Origin3d, TextureFormat};
 use winit::dpi::PhysicalPosition;
 use winit::event::{ElementState, MouseButton, VirtualKeyCode, WindowEvent};
 use winit::window::WindowLevel;
 
-use crate::engine::{GameState, LoopState, StateData, StateEvent, Trans};
+use crate::engine::{GameState, LoadContext, LoopState, StateData, StateEvent, Trans};
+use crate::engine::console::{self, Console};
+use crate::engine::config::SettingsChanged;
+use crate::engine::ecs::{Mesh, MeshShape, Spawn, Transform};
+use crate::engine::global::GLOBAL_DATA;
+use crate::engine::lifecycle::Lifecycle;
+use crate::engine::metrics::Phase;
+use crate::engine::physics::capture::{CaptureRecord, PhysicsCapture};
 use crate::engine::render::camera::{Camera, CameraController};
+use crate::engine::render::post::{GLOW_TARGET, PostEffects};
+use crate::engine::render::target::{TargetDesc, TargetSize};
+use crate::engine::render::texture::set_color_validation;
 use crate::engine::render_ext::CommandEncoderExt;
-use crate::engine::renderer3d::renderer3d::{General3DRenderer, LightUniform, PlaneRenderer};
+use crate::engine::stats::Statistics;
+use crate::engine::touch::TouchControls;
+use crate::engine::renderer3d::renderer3d::{General3DRenderer, LightUniform, MAX_POINT_LIGHTS, PlaneRenderer};
 use crate::engine::window::WindowInstance;
+use crate::state::real_view::chat::ChatOverlay;
 use crate::state::real_view::level::MagicLevel;
-use crate::state::real_view::renderer::portal::PortalRenderer;
+use crate::state::real_view::level_rooms::RoomTextures;
+use crate::state::real_view::multiplayer::Multiplayer;
+use crate::state::real_view::preview::PortalPreview;
+use crate::state::real_view::pip::{PIP_DEPTH, PIP_TARGET};
+use crate::state::real_view::build::{LevelSpec, LevelTask};
+use crate::state::real_view::renderer::portal::{PortalRenderer, PortalViewPool};
+use crate::state::real_view::replay::{Replay, ReplayState};
+use crate::state::real_view::scene::{MAP_HEIGHT, SceneHandle, SceneView, SharedScene, ViewCamera};
+use crate::state::real_view::session::{Session, session_path};
+use crate::state::pause::PauseState;
 
 pub struct Test3DState {
     last_update: Option<Instant>,
     camera: Camera,
     controller: CameraController,
-    level: Option<MagicLevel>,
+    touch: TouchControls,
+    /// The level shared with the views in the other windows.
+    scene: SharedScene,
     pr: Option<PortalRenderer>,
-    size: (u32, u32),
-    loc: PhysicalPosition<i32>,
-    purple: Option<BindGroup>,
+    multiplayer: Option<Multiplayer>,
+    chat: ChatOverlay,
+    preview: PortalPreview,
+    /// The level waiting for the evicted textures loaded.
+    pending_level: Option<LevelSpec>,
+    /// The level planning in the io pool, the old level is shown until it is built.
+    level_task: Option<LevelTask>,
+    /// Clicked to interact with the target since the last update.
+    clicked: bool,
+    /// Covered by the pause menu, the physics and the camera stopped and the scene rendered in the shadow render.
+    paused: bool,
+    /// The console commands to run in the next update.
+    commands: Arc<Mutex<Vec<DevCommand>>>,
+    /// The spec of the level playing, built again to record.
+    spec: LevelSpec,
+    /// Start recording when the level built.
+    record_next: bool,
+    recorder: Option<Replay>,
+    /// The replay to play in the next update.
+    replay: Option<Replay>,
+    /// The session to continue, applied after its level built.
+    resume: Option<Session>,
 }
 
+/// The console commands run by the state.
+#[derive(Debug, Clone, PartialEq)]
+enum DevCommand {
+    /// Move me to the position in the world, my world if none.
+    Teleport(Vector3<f32>, Option<usize>),
+    LoadLevel(VirtualKeyCode),
+    /// My gravity along the up axis.
+    MeGravity(f32),
+    /// The world gravity of the props.
+    PropsGravity(Vector3<f32>),
+    /// Host or join the server, the configured if none.
+    Connect { host: bool, addr: Option<String> },
+    /// Meet in the session at the rendezvous, the configured if none.
+    Punch(Option<String>),
+    /// Open or close the portal and the connecting one, remove them if none.
+    Portal((usize, usize), Option<bool>),
+    /// Record my movement from the current level built again.
+    Record,
+    /// Save the recording to the file.
+    SaveReplay(String),
+    /// Play the replay in the file.
+    PlayReplay(String),
+    /// Start or stop capturing the physics steps of the level.
+    Capture(bool),
+    /// Dump the physics steps captured to the file.
+    DumpCapture(String),
+    /// Warn the textures bound in the other color space.
+    ValidateColor(bool),
+}
+
+/// The names of the console commands registered by the state.
+const COMMANDS: [&str; 8] = ["teleport", "load_level", "set_gravity", "net_connect", "portal", "replay", "capture", "validate_color"];
+
 pub struct OverlayView {
-    state: &'static Test3DState,
+    scene: SharedScene,
 }
 
 impl Default for Test3DState {
     fn default() -> Self {
+        let mut controller = CameraController::new();
+        controller.set_bindings(&GLOBAL_DATA.cfg_data.read().unwrap().settings().key_bindings);
+        let camera = Camera::new(point![-3.0, 0.0, 1.0]);
         Self {
             last_update: None,
-            camera: Camera::new(point![-3.0, 0.0, 1.0]),
-            controller: CameraController::new(),
-            size: (0, 0),
-            loc: Default::default(),
-            level: None,
+            camera,
+            controller,
+            touch: Default::default(),
+            scene: Arc::new(Mutex::new(SceneHandle::new(camera))),
             pr: None,
-            purple: None,
+            multiplayer: None,
+            chat: Default::default(),
+            preview: Default::default(),
+            pending_level: None,
+            level_task: None,
+            clicked: false,
+            paused: false,
+            commands: Default::default(),
+            spec: LevelSpec::Level0,
+            record_next: false,
+            recorder: None,
+            replay: None,
+            resume: None,
         }
     }
 }
 
 
+/// The keys to switch the level.
+const LEVEL_KEYS: [VirtualKeyCode; 9] = [
+    VirtualKeyCode::F1, VirtualKeyCode::F2, VirtualKeyCode::F3,
+    VirtualKeyCode::F4, VirtualKeyCode::F5, VirtualKeyCode::F6,
+    VirtualKeyCode::F7, VirtualKeyCode::F8, VirtualKeyCode::F9,
+];
+
+/// The half size of the cubes spawned.
+const CUBE_HALF: f32 = 0.125;
+
+/// The rooms with a random seed, logged to get the same rooms again.
+fn random_rooms() -> RoomTextures {
+    let seed = thread_rng().gen();
+    info!(target: "level", "The rooms seed is {}", seed);
+    RoomTextures::Seeded(seed)
+}
+
+fn level_spec(key: VirtualKeyCode) -> LevelSpec {
+    match key {
+        VirtualKeyCode::F1 => LevelSpec::Level0,
+        VirtualKeyCode::F2 => LevelSpec::Rooms(3, random_rooms()),
+        VirtualKeyCode::F3 => LevelSpec::Rooms(4, random_rooms()),
+        VirtualKeyCode::F4 => LevelSpec::Rooms(5, random_rooms()),
+        VirtualKeyCode::F5 => LevelSpec::Rooms(6, random_rooms()),
+        VirtualKeyCode::F6 => LevelSpec::Rooms(7, random_rooms()),
+        VirtualKeyCode::F7 => LevelSpec::Rooms(8, random_rooms()),
+        VirtualKeyCode::F8 => LevelSpec::Loop,
+        _ => {
+            let mut rng = thread_rng();
+            let seed = rng.gen();
+            let cnt = rng.gen_range(3..=8);
+            info!(target: "level", "Generating {} rooms by the seed {}", cnt, seed);
+            LevelSpec::Generated(seed, cnt)
+        }
+    }
+}
+
+fn parse_f32(x: &str) -> anyhow::Result<f32> {
+    x.parse().map_err(|_| anyhow!("{} is not a number", x))
+}
+
+fn parse_command(name: &str, args: &[&str]) -> anyhow::Result<DevCommand> {
+    let command = match (name, args) {
+        ("teleport", [x, y, z, world @ ..]) if world.len() <= 1 => {
+            let world = world.first().map(|x| x.parse().map_err(|_| anyhow!("{} is not a world", x))).transpose()?;
+            DevCommand::Teleport(vector![parse_f32(x)?, parse_f32(y)?, parse_f32(z)?], world)
+        }
+        ("load_level", [x]) => {
+            let idx = x.parse::<usize>().ok().filter(|x| (1..=LEVEL_KEYS.len()).contains(x))
+                .ok_or_else(|| anyhow!("The level is 1 to {}", LEVEL_KEYS.len()))?;
+            DevCommand::LoadLevel(LEVEL_KEYS[idx - 1])
+        }
+        ("set_gravity", [z]) => DevCommand::MeGravity(parse_f32(z)?),
+        ("set_gravity", [x, y, z]) => DevCommand::PropsGravity(vector![parse_f32(x)?, parse_f32(y)?, parse_f32(z)?]),
+        ("net_connect", [role, addr @ ..]) if addr.len() <= 1 => {
+            let addr = addr.first().map(|x| x.to_string());
+            match *role {
+                "host" => DevCommand::Connect { host: true, addr },
+                "join" => DevCommand::Connect { host: false, addr },
+                "punch" => DevCommand::Punch(addr),
+                _ => bail!("Unknown role {}, host, join or punch", role),
+            }
+        }
+        ("portal", [action, world, idx]) => {
+            let portal = (world.parse().map_err(|_| anyhow!("{} is not a world", world))?,
+                          idx.parse().map_err(|_| anyhow!("{} is not a portal index", idx))?);
+            match *action {
+                "open" => DevCommand::Portal(portal, Some(true)),
+                "close" => DevCommand::Portal(portal, Some(false)),
+                "remove" => DevCommand::Portal(portal, None),
+                _ => bail!("Unknown action {}, open, close or remove", action),
+            }
+        }
+        ("replay", ["record"]) => DevCommand::Record,
+        ("replay", ["save", path]) => DevCommand::SaveReplay(path.to_string()),
+        ("replay", ["play", path]) => DevCommand::PlayReplay(path.to_string()),
+        ("capture", ["on"]) => DevCommand::Capture(true),
+        ("capture", ["off"]) => DevCommand::Capture(false),
+        ("capture", ["dump", path]) => DevCommand::DumpCapture(path.to_string()),
+        ("validate_color", ["on"]) => DevCommand::ValidateColor(true),
+        ("validate_color", ["off"]) => DevCommand::ValidateColor(false),
+        _ => bail!("Wrong arguments, see help {}", name),
+    };
+    Ok(command)
+}
+
 impl Test3DState {
+    /// Build the level of the session and continue where I was.
+    pub fn continue_session(session: Session) -> Self {
+        Self {
+            pending_level: Some(session.spec.clone()),
+            resume: Some(session),
+            ..Self::default()
+        }
+    }
+
     fn load(&mut self, s: &mut StateData) {
+        // the level loaded before is built again, its views are put to the pool dropped below
+        let old = self.scene.lock().unwrap().level.take();
+        let rebuilt = old.is_some();
+        if let Some(mut old) = old {
+            old.despawn(&s.app.world);
+        }
         let gpu = s.app.gpu.as_ref().unwrap();
         s.app.world.insert(General3DRenderer::new(&gpu));
+        // the views pooled may be of the device lost
+        s.app.world.insert(PortalViewPool::default());
 
 
         let mut g3d = s.app.world.fetch_mut::<General3DRenderer>();
@@ -65,19 +261,211 @@ impl Test3DState {
         });
 
         let pr = PortalRenderer::new(gpu, plane_renderer);
-        let pf = s.app.res.textures.get("pf").ok_or(anyhow!("NO TEXTURE")).unwrap();
+        let pf = s.app.res.textures.by_name("pf").ok_or(anyhow!("NO TEXTURE")).unwrap();
 
-        self.level = Some(MagicLevel::level_rooms(gpu, 3, plane_renderer, &pr, s.app.res.as_ref()).unwrap());
-        self.purple = Some(gpu.device.create_bind_group(&BindGroupDescriptor {
+        // planned in the level task like the levels switched
+        if self.pending_level.is_none() && self.level_task.is_none() {
+            self.pending_level = Some(if rebuilt { self.spec.clone() } else { LevelSpec::Rooms(3, random_rooms()) });
+        }
+        self.scene.lock().unwrap().purple = Some(gpu.device.create_bind_group(&BindGroupDescriptor {
             label: None,
             layout: &plane_renderer.obj_layout,
             entries: &[BindGroupEntry {
                 binding: 0,
-                resource: BindingResource::TextureView(&pf.view),
+                resource: BindingResource::TextureView(pf.srgb_view()),
             }],
         }));
         self.pr = Some(pr);
     }
+
+    /// Queue the commands to the state by the console.
+    fn register_commands(&self, console: &mut Console) {
+        let usages = [
+            ("<x> <y> <z> [world]", "Move me to the position in the world"),
+            ("<1-9>", "Load the level of the F key"),
+            ("<z> | <x> <y> <z>", "Set my gravity along the up axis, or the gravity of the props"),
+            ("<host|join|punch> [address]", "Start the multiplayer with the server or the rendezvous configured if no address"),
+            ("<open|close|remove> <world> <index>", "Open, close or remove the portal with the connecting one"),
+            ("record | save <file> | play <file>", "Record my movement from the level built again, save it or play the file"),
+            ("on | off | dump <file>", "Capture the physics steps of the level, dumped to the file or when panicked"),
+            ("on | off", "Warn the textures bound in the other color space"),
+        ];
+        for (name, (usage, help)) in COMMANDS.into_iter().zip(usages) {
+            let commands = self.commands.clone();
+            console.register(name, usage, help, move |_, args| {
+                let command = parse_command(name, args)?;
+                commands.lock().unwrap().push(command);
+                Ok(String::new())
+            });
+        }
+    }
+
+    fn run_command(&mut self, s: &mut StateData, command: DevCommand) {
+        match command {
+            DevCommand::Teleport(to, world) => {
+                if let Some(level) = self.scene.lock().unwrap().level.as_mut() {
+                    let world = world.unwrap_or(level.me_world);
+                    match level.teleport(&s.app.world, world, to) {
+                        Ok(_) => info!(target: "console", "Teleported to {:?} in world {}", to, world),
+                        Err(e) => warn!(target: "console", "Teleport failed for {:?}", e),
+                    }
+                }
+            }
+            DevCommand::LoadLevel(key) => self.pending_level = Some(level_spec(key)),
+            DevCommand::MeGravity(g) => {
+                if let Some(level) = self.scene.lock().unwrap().level.as_mut() {
+                    level.me.gravity = g;
+                }
+            }
+            DevCommand::PropsGravity(g) => {
+                if let Some(level) = self.scene.lock().unwrap().level.as_mut() {
+                    level.p.g = g;
+                }
+            }
+            DevCommand::Connect { host, addr } => {
+                let server = addr.unwrap_or_else(|| GLOBAL_DATA.cfg_data.read().unwrap().settings().network.server.clone());
+                self.connect(host, &server);
+            }
+            DevCommand::Punch(addr) => {
+                let mut network = GLOBAL_DATA.cfg_data.read().unwrap().settings().network.clone();
+                if let Some(addr) = addr {
+                    network.rendezvous = addr;
+                }
+                self.punch(&network.rendezvous, network.session);
+            }
+            DevCommand::Record => {
+                // the same level from the start
+                self.pending_level = Some(self.spec.clone());
+                self.record_next = true;
+            }
+            DevCommand::SaveReplay(path) => {
+                match self.recorder.take() {
+                    Some(recorder) => match recorder.save(&path) {
+                        Ok(_) => info!(target: "console", "Saved {} frames to {}", recorder.frames.len(), path),
+                        Err(e) => warn!(target: "console", "Save the replay to {} failed for {:?}", path, e),
+                    },
+                    None => warn!(target: "console", "Not recording"),
+                }
+            }
+            DevCommand::PlayReplay(path) => {
+                match Replay::load(&path) {
+                    Ok(replay) => {
+                        self.recorder = None;
+                        self.replay = Some(replay);
+                    }
+                    Err(e) => warn!(target: "console", "Load the replay {} failed for {:?}", path, e),
+                }
+            }
+            DevCommand::Capture(on) => {
+                if let Some(level) = self.scene.lock().unwrap().level.as_mut() {
+                    if on && level.capture.is_none() {
+                        let capture = PhysicsCapture::default();
+                        capture.dump_on_panic();
+                        capture.record(CaptureRecord::Note(format!("{:?}", self.spec)));
+                        level.capture = Some(capture);
+                        info!(target: "console", "Capturing the physics in {:?}", self.spec);
+                    } else if !on {
+                        level.capture = None;
+                    }
+                }
+            }
+            DevCommand::DumpCapture(path) => {
+                let scene = self.scene.lock().unwrap();
+                match scene.level.as_ref().and_then(|x| x.capture.as_ref()) {
+                    Some(capture) => match capture.dump(&path) {
+                        Ok(_) => info!(target: "console", "Dumped the physics capture to {}", path),
+                        Err(e) => warn!(target: "console", "Dump the physics capture to {} failed for {:?}", path, e),
+                    },
+                    None => warn!(target: "console", "Not capturing"),
+                }
+            }
+            DevCommand::ValidateColor(on) => {
+                set_color_validation(on);
+                info!(target: "console", "Color space validation {}", if on { "on" } else { "off" });
+            }
+            DevCommand::Portal(portal, open) => {
+                if let Some(level) = self.scene.lock().unwrap().level.as_mut() {
+                    let result = match open {
+                        Some(open) => level.set_portal_open(portal, open),
+                        None => level.remove_portal(portal),
+                    };
+                    if let Err(e) = result {
+                        warn!(target: "console", "Change the portal {:?} failed for {:?}", portal, e);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Host or join the server, stopped the old multiplayer.
+    fn connect(&mut self, host: bool, server: &str) {
+        self.multiplayer = None;
+        if let Some(level) = self.scene.lock().unwrap().level.as_mut() {
+            level.avatars = Default::default();
+        }
+        let mp = Multiplayer::resolve(server)
+            .and_then(|addr| if host { Multiplayer::host(addr, self.chat.log()) } else { Multiplayer::join(addr, self.chat.log()) });
+        match mp {
+            Ok(mp) => {
+                info!(target: "multiplayer", "Started multiplayer as {} with {:?}", mp.id, mp.addr());
+                self.multiplayer = Some(mp);
+            }
+            Err(e) => warn!(target: "multiplayer", "Start multiplayer with {:?} failed for {:?}", server, e),
+        }
+    }
+
+    /// Meet the peer in the session by the rendezvous, stopped the old multiplayer.
+    fn punch(&mut self, rendezvous: &str, session: String) {
+        self.multiplayer = None;
+        if let Some(level) = self.scene.lock().unwrap().level.as_mut() {
+            level.avatars = Default::default();
+        }
+        match Multiplayer::resolve(rendezvous) {
+            Ok(addr) => {
+                info!(target: "multiplayer", "Meeting in the session {:?} at {:?}", session, addr);
+                self.multiplayer = Some(Multiplayer::punch(addr, session, self.chat.log()));
+            }
+            Err(e) => warn!(target: "multiplayer", "Resolve the rendezvous {:?} failed for {:?}", rendezvous, e),
+        }
+    }
+
+    /// Save where I am to continue in the next run.
+    fn save_session(&self) {
+        let session = if let Some(x) = self.scene.lock().unwrap().level.as_ref() {
+            Session::new(self.spec.clone(), &self.camera.eye, &self.camera.target, x.me_world, x.transition, x.keys.clone())
+        } else {
+            return;
+        };
+        match session.save(session_path()) {
+            Ok(_) => info!(target: "session", "Saved the session in {:?}", self.spec),
+            Err(e) => warn!(target: "session", "Save the session failed for {:?}", e),
+        }
+    }
+
+    /// Stop turning the camera by the mouse.
+    fn release_mouse(&mut self, s: &mut StateData) {
+        self.controller.is_mouse_right_pressed = false;
+        self.controller.is_mouse_right_tracked = false;
+        s.app.window.set_cursor_visible(true);
+    }
+
+    /// Render the scene without the HUD, for the menu over it.
+    fn render_scene(&mut self, s: &mut StateData) {
+        let gpu = if let Some(gpu) = s.app.gpu.as_mut() { gpu } else {
+            return;
+        };
+        let mut encoder = gpu.device.create_command_encoder(&CommandEncoderDescriptor { label: Some("Paused Scene Encoder") });
+        gpu.uniforms.data.camera.update_view_proj(&self.camera);
+        gpu.uniforms.update(&gpu.queue);
+        if let (Some(mut g3d), Some(mut pool), Some(apr), Some(level)) = (s.app.world.try_fetch_mut::<General3DRenderer>(), s.app.world.try_fetch_mut::<PortalViewPool>(), self.pr.as_mut(), self.scene.lock().unwrap().level.as_mut()) {
+            g3d.plane_renderer.sky.update(&gpu.device, &s.app.res);
+            level.render(self.camera, &mut encoder, gpu, &mut g3d.plane_renderer, apr, &mut pool);
+            if let Some(render) = s.app.render.as_mut() {
+                render.blit.blit_scene(gpu, &mut encoder);
+            }
+        }
+        gpu.queue.submit(Some(encoder.finish()));
+    }
 }
 
 impl GameState for Test3DState {
@@ -85,49 +473,183 @@ impl GameState for Test3DState {
         if s.app.gpu.is_some() {
             self.load(s);
         }
+        if let Some(mut console) = s.app.world.try_fetch_mut::<Console>() {
+            self.register_commands(&mut console);
+        }
+    }
+
+    fn stop(&mut self, s: &mut StateData) {
+        self.save_session();
+        // the entities of the level are in the world shared with the next state
+        if let Some(mut level) = self.scene.lock().unwrap().level.take() {
+            level.despawn(&s.app.world);
+        }
+        if let Some(mut console) = s.app.world.try_fetch_mut::<Console>() {
+            for x in COMMANDS {
+                console.unregister(x);
+            }
+        }
+    }
+
+    fn on_pause(&mut self, s: &mut StateData) {
+        self.controller.release_keys();
+        self.release_mouse(s);
+        // the physics not stepped for the time paused
+        self.last_update = None;
+    }
+
+    fn on_resume(&mut self, _: &mut StateData) {
+        self.paused = false;
     }
 
     fn update(&mut self, s: &mut StateData) -> (Trans, LoopState) {
         let now = Instant::now();
+        // the keys are typed into the chat or the console
+        let typing = self.chat.is_typing() || console::is_typing(&s.app.world);
+        let commands = std::mem::take(&mut *self.commands.lock().unwrap());
+        for x in commands {
+            self.run_command(s, x);
+        }
         if let Some(gpu) = s.app.gpu.as_ref() {
-            if let Some(apr) = self.pr.as_mut() {
+            if self.pr.is_some() {
                 if let Some(mut g3d) = s.app.world.try_fetch_mut::<General3DRenderer>() {
                     let pr = &mut g3d.plane_renderer;
-                    if s.app.inputs.is_pressed(&[VirtualKeyCode::F1]) {
-                        self.level = Some(MagicLevel::level0(gpu, pr, apr, &s.app.res).unwrap());
-                    } else if s.app.inputs.is_pressed(&[VirtualKeyCode::F2]) {
-                        self.level = Some(MagicLevel::level_rooms(gpu, 3, pr, apr, &s.app.res).unwrap());
-                    } else if s.app.inputs.is_pressed(&[VirtualKeyCode::F3]) {
-                        self.level = Some(MagicLevel::level_rooms(gpu, 4, pr, apr, &s.app.res).unwrap());
-                    } else if s.app.inputs.is_pressed(&[VirtualKeyCode::F4]) {
-                        self.level = Some(MagicLevel::level_rooms(gpu, 5, pr, apr, &s.app.res).unwrap());
-                    } else if s.app.inputs.is_pressed(&[VirtualKeyCode::F5]) {
-                        self.level = Some(MagicLevel::level_rooms(gpu, 6, pr, apr, &s.app.res).unwrap());
-                    } else if s.app.inputs.is_pressed(&[VirtualKeyCode::F6]) {
-                        self.level = Some(MagicLevel::level_rooms(gpu, 7, pr, apr, &s.app.res).unwrap());
-                    } else if s.app.inputs.is_pressed(&[VirtualKeyCode::F7]) {
-                        self.level = Some(MagicLevel::level_rooms(gpu, 8, pr, apr, &s.app.res).unwrap());
-                    } else if s.app.inputs.is_pressed(&[VirtualKeyCode::F8]) {
-                        self.level = Some(MagicLevel::level_loop(gpu, pr, apr, &s.app.res).unwrap());
-                    } else if s.app.inputs.is_pressed(&[VirtualKeyCode::F9]) {
-                        let mut rng = thread_rng();
-                        let cnt = rng.gen_range(2..=9);
-                        self.level = Some(MagicLevel::level_rooms(gpu, cnt, pr, apr, &s.app.res).unwrap());
+                    let spec = LEVEL_KEYS.into_iter().find(|x| !typing && s.app.inputs.is_pressed(&[*x]))
+                        .map(level_spec)
+                        .or_else(|| if s.app.res.is_loading() { None } else { self.pending_level.take() });
+                    if let Some(spec) = spec {
+                        self.pending_level = None;
+                        // the planning before is dropped
+                        self.level_task = Some(LevelTask::spawn(spec));
+                    }
+                    let planned = self.level_task.as_mut().and_then(|x| x.poll().map(|plan| (x.spec.clone(), plan)));
+                    if let Some((spec, plan)) = planned {
+                        self.level_task = None;
+                        match MagicLevel::from_plan(plan, gpu, pr, &s.app.res) {
+                            Ok(mut level) => {
+                                level.hints.bind_keys(&GLOBAL_DATA.cfg_data.read().unwrap().settings().key_bindings);
+                                let mut scene = self.scene.lock().unwrap();
+                                // keep capturing in the new level
+                                level.capture = scene.level.as_mut().and_then(|x| x.capture.take());
+                                if let Some(capture) = level.capture.as_ref() {
+                                    capture.record(CaptureRecord::Note(format!("{:?}", spec)));
+                                }
+                                if let Some(mut old) = scene.level.replace(level) {
+                                    old.despawn(&s.app.world);
+                                    drop(old);
+                                    pr.arena.defragment();
+                                }
+                                if std::mem::take(&mut self.record_next) {
+                                    info!(target: "replay", "Recording in {:?}", spec);
+                                    self.recorder = Some(Replay::new(spec.clone()));
+                                } else if self.recorder.take().is_some() {
+                                    warn!(target: "replay", "The level changed, the recording is dropped");
+                                }
+                                if let Some(session) = self.resume.take().filter(|x| x.spec == spec) {
+                                    if let Some(level) = scene.level.as_mut() {
+                                        match level.teleport(&s.app.world, session.world as usize, Vector3::from(session.eye)) {
+                                            Ok(_) => {
+                                                level.restore(&s.app.world, session.scale, session.keys);
+                                                self.camera.target = Vector3::from(session.target);
+                                                info!(target: "session", "Continued in {:?} at {:?} in world {}", spec, session.eye, session.world);
+                                            }
+                                            Err(e) => warn!(target: "session", "Continue the session failed for {:?}", e),
+                                        }
+                                    }
+                                }
+                                self.spec = spec;
+                            }
+                            // the textures evicted are loading, build it after loaded.
+                            Err(_) if s.app.res.reload_wanted(&LoadContext::new(gpu)) > 0 => self.pending_level = Some(spec),
+                            Err(e) => error!("Build the level {:?} failed for {:?}", spec, e),
+                        }
                     }
                 }
             }
+            let mut resident = self.scene.lock().unwrap().level.as_ref().map(|x| x.textures.clone()).unwrap_or_default();
+            resident.extend(s.app.res.textures.handle("pf"));
+            s.app.res.evict_textures(&resident);
         }
         let old_camera = (self.camera.eye, self.camera.target);
         let dt = self.last_update.map(|x| now.duration_since(x))
             .map(|x| x.as_secs_f32())
             .map(|x| if x > 0.05 { 0.0 } else { x })
             .unwrap_or(0.016666666666);
-        let ddr = self.controller.update_direction(&mut self.camera);
-        if let Some(level) = self.level.as_mut() {
-            level.update(s, dt, &mut self.camera, &ddr);
+        self.controller.set_input(&GLOBAL_DATA.cfg_data.read().unwrap().settings().input);
+        if !typing {
+            self.touch.update(&s.app.inputs.points, s.app.window.inner_size(), &mut self.controller);
+        }
+        let ddr = self.controller.update_direction(&mut self.camera, dt);
+        if let Some(level) = self.scene.lock().unwrap().level.as_mut() {
+            let target = self.camera.target;
+            level.update(s, dt, &mut self.camera, &ddr, self.controller.is_down_pressed());
+            self.touch.apply_zoom(&mut self.camera);
+            if let Some(recorder) = self.recorder.as_mut() {
+                recorder.record(dt, &ddr, self.controller.is_down_pressed(), &target, &self.camera.eye, level.me_world);
+            }
+            if let (Some(gpu), Some(g3d)) = (s.app.gpu.as_ref(), s.app.world.try_fetch::<General3DRenderer>()) {
+                level.meshes.rebuild(&s.app.world, gpu, &g3d.plane_renderer, &s.app.res);
+                level.rebuild_body(gpu, &self.camera);
+            }
+            if !typing && s.app.inputs.is_pressed(&[VirtualKeyCode::C]) {
+                // throw the cube forward
+                let forward = self.camera.target.try_normalize(f32::EPSILON).unwrap_or_else(Vector3::x);
+                let texture = s.app.res.textures.handle("pf");
+                s.commands().spawn(Spawn::new(Transform { position: self.camera.eye + forward * 0.75, ..Default::default() })
+                    .mesh(Mesh { world: level.me_world, shape: MeshShape::Cube { half: CUBE_HALF }, texture })
+                    .body(SharedShape::cuboid(CUBE_HALF, CUBE_HALF, CUBE_HALF))
+                    .velocity(forward * 4.0, Vector3::zeros()));
+            }
+            if !typing && s.app.inputs.is_pressed(&[VirtualKeyCode::G]) {
+                let holding = level.toggle_grab(&self.camera);
+                info!(target: "grab", "Holding {}", holding);
+            }
+            for (end, key) in [VirtualKeyCode::Key1, VirtualKeyCode::Key2].into_iter().enumerate() {
+                if typing || !s.app.inputs.is_pressed(&[key]) {
+                    continue;
+                }
+                if let (Some(gpu), Some(g3d)) = (s.app.gpu.as_ref(), s.app.world.try_fetch::<General3DRenderer>()) {
+                    match level.shoot_portal(gpu, &g3d.plane_renderer, &self.camera, end) {
+                        Ok(Some(portal)) => info!(target: "gun", "Placed the portal {} as {:?}", end, portal),
+                        Ok(None) => info!(target: "gun", "Aimed the portal {}, waiting the other end", end),
+                        Err(e) => warn!(target: "gun", "Cannot place the portal {} for {:?}", end, e),
+                    }
+                }
+            }
+            if std::mem::take(&mut self.clicked) && !level.interactions.interact() {
+                if let Some(x) = level.interactions.target.as_ref() {
+                    info!(target: "interact", "Clicked {:?} at {:?} in world {} through {} portals", x.hit.tag, x.hit.point, x.world, x.portals);
+                }
+            }
+            if !typing && s.app.inputs.is_pressed(&[VirtualKeyCode::F10]) {
+                level.physics_debug = match level.physics_debug {
+                    Some(_) => None,
+                    None => Some(Default::default()),
+                };
+            }
+            if let Some(audio) = s.app.audio.as_mut() {
+                audio.update_spatial(&self.camera, level.me_world, |world, pos| level.route_sound(world, pos));
+            }
+            if let Some(mp) = self.multiplayer.as_mut() {
+                if let (Some(gpu), Some(g3d)) = (s.app.gpu.as_ref(), s.app.world.try_fetch::<General3DRenderer>()) {
+                    mp.update(dt, level.me_world, &self.camera, &mut level.avatars, gpu, &g3d.plane_renderer, &s.app.res);
+                }
+                if let Some(state) = mp.take_correction() {
+                    // the dedicated server stopped me in its colliders
+                    if let Err(e) = level.teleport(&s.app.world, state.world, Vector3::from(state.position)) {
+                        warn!(target: "multiplayer", "Apply the correction failed for {:?}", e);
+                    }
+                }
+                if !mp.is_connected() {
+                    warn!(target: "multiplayer", "Lost the connection to {:?}", mp.addr());
+                    self.multiplayer = None;
+                    level.avatars = Default::default();
+                }
+            }
         }
 
         self.last_update = Some(now);
+        self.scene.lock().unwrap().camera = self.camera;
         if self.controller.is_mouse_right_tracked {
             let size = s.app.window.inner_size();
             let x = self.controller.mouse_initial_position.x * size.width as f32;
@@ -135,8 +657,28 @@ impl GameState for Test3DState {
             let _ = s.app.window.set_cursor_position(PhysicalPosition::new(x, y));
         }
         let current_camera = (self.camera.eye, self.camera.target);
+        let hint_showing = self.scene.lock().unwrap().level.as_ref().map(|x| x.hints.is_active()).unwrap_or(false);
+
+        if self.multiplayer.is_none() {
+            // no one to send, only shown to me
+            self.chat.log().lock().unwrap().take_outgoing();
+        }
+
+        if !typing && (s.app.inputs.is_pressed(&[VirtualKeyCode::Key7]) || s.app.inputs.is_pressed(&[VirtualKeyCode::Key8])) {
+            let host = s.app.inputs.is_pressed(&[VirtualKeyCode::Key7]);
+            let server = GLOBAL_DATA.cfg_data.read().unwrap().settings().network.server.clone();
+            self.connect(host, &server);
+        }
 
-        if s.app.inputs.is_pressed(&[VirtualKeyCode::Numpad6]) || s.app.inputs.is_pressed(&[VirtualKeyCode::Key6]) {
+        if !typing && s.app.inputs.is_pressed(&[VirtualKeyCode::Key9]) {
+            let network = GLOBAL_DATA.cfg_data.read().unwrap().settings().network.clone();
+            self.punch(&network.rendezvous, network.session);
+        }
+
+        // keep sending my state and receiving the others, or wait the textures loading and the level planning
+        let replicating = self.multiplayer.is_some() || self.pending_level.is_some() || self.level_task.is_some();
+
+        if !typing && (s.app.inputs.is_pressed(&[VirtualKeyCode::Numpad6]) || s.app.inputs.is_pressed(&[VirtualKeyCode::Key6])) {
             let mut window = WindowInstance::new_with_gpu("See portal?",
                                                           |x| x.with_transparent(true)
                                                               .with_window_level(WindowLevel::AlwaysOnTop),
@@ -147,13 +689,28 @@ impl GameState for Test3DState {
                 dt: 0.0,
             };
             window.states.push(Box::new(OverlayView {
-                state: unsafe { std::mem::transmute(self) },
+                scene: self.scene.clone(),
             }));
             window.states.last_mut().unwrap().start(&mut sd);
             s.wd.new_windows.push(window);
         }
 
-        let state = if current_camera == old_camera && ddr.is_zero() {
+        if !typing && s.app.inputs.is_pressed(&[VirtualKeyCode::M]) {
+            if let Err(e) = SceneView::open(s, "Map", self.scene.clone(), ViewCamera::TopDown(MAP_HEIGHT)) {
+                warn!("Open the map failed for {:?}", e);
+            }
+        }
+
+        if let Some(replay) = self.replay.take() {
+            return (Trans::Push(Box::new(ReplayState::new(replay, self.scene.clone()))), LoopState::POLL);
+        }
+
+        if !typing && s.app.inputs.is_pressed(&[VirtualKeyCode::Escape]) {
+            self.paused = true;
+            return (Trans::Push(Box::new(PauseState)), LoopState::WAIT);
+        }
+
+        let state = if current_camera == old_camera && ddr.is_zero() && !hint_showing && !replicating && !typing {
             LoopState::WAIT_ALL
         } else {
             LoopState::POLL
@@ -161,26 +718,75 @@ impl GameState for Test3DState {
         (Trans::None, state)
     }
 
+    fn shadow_render(&mut self, s: &mut StateData, ctx: &Context) {
+        if self.paused {
+            self.render_scene(s);
+        }
+        self.chat.shadow_render(s, ctx);
+    }
+
     fn render(&mut self, s: &mut StateData, ctx: &Context) -> Trans {
+        let video = GLOBAL_DATA.cfg_data.read().unwrap().settings().video.clone();
+        let preview = video.portal_preview;
+        let frame = s.app.pacing.intervals().back().copied().unwrap_or(0.0);
+        self.touch.show(ctx, &s.app.inputs.points, s.app.window.inner_size());
         let gpu = s.app.gpu.as_mut().unwrap();
-        let cfg = &gpu.surface_cfg;
-        self.size.0 = cfg.width;
-        self.size.1 = cfg.height;
-        self.loc = s.app.window.inner_position().unwrap();
+        {
+            let mut scene = self.scene.lock().unwrap();
+            scene.size = (gpu.surface_cfg.width, gpu.surface_cfg.height);
+            scene.loc = s.app.window.inner_position().unwrap();
+        }
         let mut encoder = gpu.device.create_command_encoder(&CommandEncoderDescriptor { label: Some("Main Window Encoder") });
         gpu.uniforms.data.camera.update_view_proj(&self.camera);
         gpu.uniforms.update(&gpu.queue);
 
-        if let Some(mut g3d) = s.app.world.try_fetch_mut::<General3DRenderer>() {
+        if let (Some(mut g3d), Some(mut pool)) = (s.app.world.try_fetch_mut::<General3DRenderer>(), s.app.world.try_fetch_mut::<PortalViewPool>()) {
             if let Some(apr) = self.pr.as_mut() {
-                if let Some(level) = self.level.as_mut() {
+                if let Some(level) = self.scene.lock().unwrap().level.as_mut() {
                     egui::CentralPanel::default()
                         .frame(Frame::none())
                         .show(ctx, |ui| {
                             ui.label(format!("Eye: {:?}", self.camera.eye));
                             ui.label(format!("See dir: {:?}", self.camera.target));
-                            ui.label(format!("World {}", level.me_world))
+                            match level.world_names.get(level.me_world) {
+                                Some(name) => ui.label(format!("World {} ({})", level.me_world, name)),
+                                None => ui.label(format!("World {}", level.me_world)),
+                            };
+                            if !level.world_names.is_empty() {
+                                let names = level.world_names.iter().enumerate()
+                                    .map(|(i, x)| format!("{} {}", i, x))
+                                    .collect::<Vec<_>>();
+                                ui.label(format!("Worlds: {}", names.join(", ")));
+                            }
+                            if let Some(x) = level.interactions.target.as_ref() {
+                                ui.label(format!("Target: {:?} in world {} through {} portals", x.hit.tag, x.world, x.portals));
+                            }
+                            s.app.pacing.show(ui);
+                            level.counters.show(ui);
+                            ui.collapsing("Renderer", |ui| {
+                                let mut config = g3d.config().clone();
+                                let mut rgb = [config.ambient[0], config.ambient[1], config.ambient[2]].map(|x| x.min(255) as u8);
+                                ui.horizontal(|ui| {
+                                    ui.label("Ambient");
+                                    ui.color_edit_button_srgb(&mut rgb);
+                                });
+                                config.ambient = [rgb[0] as u32, rgb[1] as u32, rgb[2] as u32, config.ambient[3]];
+                                ui.add(Slider::new(&mut config.max_lights, 0..=MAX_POINT_LIGHTS).text("Max lights"));
+                                g3d.set_config(gpu, config);
+                            });
+                            if let Some(mp) = self.multiplayer.as_ref() {
+                                let role = if mp.is_host() {
+                                    "Hosting"
+                                } else if mp.is_punching() {
+                                    "Punching by"
+                                } else {
+                                    "Joined"
+                                };
+                                ui.label(format!("{} {:?}, {} remote players", role, mp.addr(), mp.remote_count()));
+                            }
                         });
+                    level.interactions.paint_crosshair(ctx);
+                    level.hints.render(ctx);
                     // {
                     //     let mut encoder = gpu.device.create_command_encoder(&CommandEncoderDescriptor { label: Some("overlay encoder") });
                     //
@@ -198,33 +804,104 @@ impl GameState for Test3DState {
                     //     }
                     //     gpu.queue.submit(std::iter::once(encoder.finish()));
                     // }
-                    level.render(self.camera, &mut encoder, gpu, &mut g3d.plane_renderer, apr);
+                    level.view_falloff = video.portal_view_falloff;
+                    g3d.plane_renderer.text.enabled = video.portal_labels;
+                    // the frames are slow on purpose in the background
+                    level.depth.set_background(!s.app.focused);
+                    if s.app.focused {
+                        level.depth.update(frame, video.portal_depth,
+                                           video.adaptive_portal_depth.then_some(video.frame_budget_ms / 1000.0));
+                    }
+                    g3d.plane_renderer.sky.update(&gpu.device, &s.app.res);
+                    let start = Instant::now();
+                    let depth = level.render(self.camera, &mut encoder, gpu, &mut g3d.plane_renderer, apr, &mut pool);
+                    s.app.metrics.add(Phase::Portals, start.elapsed());
+                    s.app.metrics.set_portals(depth as u32, level.counters.portals_recursed);
+                    if let Some(mut stats) = s.wd.world.try_fetch_mut::<Statistics>() {
+                        stats.witness_recursion_depth(depth as u64);
+                    }
+                    let glow = video.bloom > 0.0;
+                    if glow {
+                        let desc = TargetDesc { size: TargetSize::Scene, format: gpu.formats.color };
+                        gpu.views.create_extra(GLOW_TARGET, &gpu.device, desc);
+                        level.render_glow(&mut encoder, gpu, &g3d.plane_renderer, apr);
+                    }
+                    if let Some(render) = s.app.render.as_mut() {
+                        render.blit.blit_scene(gpu, &mut encoder);
+                        let mut effects = level.nearest_portal(level.me_world, &self.camera.eye)
+                            .map(PostEffects::near_portal)
+                            .unwrap_or_default();
+                        effects.glow = glow;
+                        render.post.effects = effects;
+                    }
+                    if let Some(renderer) = s.app.ui.renderer.as_mut() {
+                        if preview {
+                            self.preview.show(ctx, level, gpu, renderer);
+                        } else {
+                            self.preview.clear(renderer);
+                        }
+                    }
                 }
             }
         }
 
 
         gpu.queue.submit(Some(encoder.finish()));
+        if video.pip {
+            if let (Some(level), Some(g3d), Some(render)) = (self.scene.lock().unwrap().level.as_mut(), s.app.world.try_fetch::<General3DRenderer>(), s.app.render.as_ref()) {
+                level.render_pip(&self.camera, gpu, &g3d.plane_renderer, &render.blit);
+            }
+        } else {
+            gpu.views.release_extra(PIP_TARGET);
+            gpu.views.release_extra(PIP_DEPTH);
+        }
 
 
         Trans::None
     }
 
     fn on_event(&mut self, s: &mut StateData, e: StateEvent) {
+        let console_typing = console::is_typing(&s.app.world);
+        let typing = self.chat.is_typing() || console_typing;
+        if !console_typing {
+            self.chat.on_event(s, e);
+        }
         match e {
             StateEvent::ReloadGPU => {
                 self.load(s);
             }
+            StateEvent::Suspended => {
+                if let Some(mp) = self.multiplayer.as_mut() {
+                    mp.suspend();
+                }
+                // may be killed in the background
+                self.save_session();
+            }
+            StateEvent::Resumed => {
+                if let Some(mp) = self.multiplayer.as_mut() {
+                    mp.resume();
+                }
+            }
+            StateEvent::Custom(e) if e.is::<SettingsChanged>() => {
+                let bindings = GLOBAL_DATA.cfg_data.read().unwrap().settings().key_bindings.clone();
+                self.controller.set_bindings(&bindings);
+                if let Some(level) = self.scene.lock().unwrap().level.as_mut() {
+                    level.hints.bind_keys(&bindings);
+                }
+            }
             StateEvent::Window(e) => {
                 match e {
                     WindowEvent::Focused(false) => {
-                        self.controller.is_mouse_right_pressed = false;
-                        self.controller.is_mouse_right_tracked = false;
-                        s.app.window.set_cursor_visible(true);
+                        self.release_mouse(s);
                     }
+                    // the camera not controlled while paused
+                    WindowEvent::KeyboardInput { .. } | WindowEvent::CursorMoved { .. } | WindowEvent::MouseInput { .. } if self.paused => {}
                     WindowEvent::KeyboardInput { device_id: _, input, is_synthetic: _ } => {
                         if let Some(key) = input.virtual_keycode.as_ref() {
-                            self.controller.process_events(&input.state, key);
+                            // release the keys held before typing
+                            if !typing || input.state == ElementState::Released {
+                                self.controller.process_events(&input.state, key);
+                            }
                         }
                     }
                     WindowEvent::CursorMoved { position, .. } => {
@@ -247,6 +924,9 @@ impl GameState for Test3DState {
                     }
                     WindowEvent::MouseInput { device_id, state, button, .. } => {
                         self.controller.process_mouse_input(device_id, state, button);
+                        if button == &MouseButton::Left && state == &ElementState::Pressed && !typing {
+                            self.clicked = true;
+                        }
                         if button == &MouseButton::Right {
                             if state == &ElementState::Released {
                                 s.app.window.set_cursor_visible(true);
@@ -279,10 +959,10 @@ impl GameState for OverlayView {
     }
 
     fn render(&mut self, s: &mut StateData, _: &Context) -> Trans {
-        let this = self.state;
+        let this = self.scene.lock().unwrap();
         if let Some(render) = s.app.gpu.as_mut() {
             render.views.check_extra_with_size("main screen", &render.device,
-                                               (this.size.0, this.size.1), TextureFormat::Bgra8Unorm);
+                                               (this.size.0, this.size.1), render.formats.color);
             render.views.check_extra_with_size("main screen depth", &render.device,
                                                (this.size.0, this.size.1), TextureFormat::Depth32Float);
 
@@ -304,8 +984,8 @@ impl GameState for OverlayView {
                                                       }),
                                                       &dep.view,
                                                       LoadOp::Clear(1.0));
-                    if let Some(level) = self.state.level.as_ref() {
-                        level.render_portal(self.state.camera.clone(), rp, gpu, renderer, self.state.purple.as_ref().unwrap());
+                    if let (Some(level), Some(purple)) = (this.level.as_ref(), this.purple.as_ref()) {
+                        level.render_portal(this.camera, rp, gpu, renderer, purple);
                     }
                 }
                 // gpu.queue.submit(std::iter::once(encoder.finish()));
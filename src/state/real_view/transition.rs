@@ -0,0 +1,106 @@
+use serde::{Deserialize, Serialize};
+
+use crate::engine::render::camera::Camera;
+
+/// The seconds to ease my scale after going through the scaled portal.
+const TRANSITION_SECS: f32 = 0.3;
+/// The field of view eased within the degrees.
+const FOVY_RANGE: (f32, f32) = (20.0, 140.0);
+
+/// Ease my scale after going through the scaled portal, with the near plane and the field of view of the camera.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScaleTransition {
+    /// The scale after the transition.
+    scale: f32,
+    /// The scale when the transition started.
+    from: f32,
+    elapsed: f32,
+}
+
+impl Default for ScaleTransition {
+    fn default() -> Self {
+        Self {
+            scale: 1.0,
+            from: 1.0,
+            elapsed: TRANSITION_SECS,
+        }
+    }
+}
+
+#[allow(unused)]
+impl ScaleTransition {
+    /// Start easing from the current scale to the scale multiplied.
+    pub fn traversed(&mut self, factor: f32) {
+        self.from = self.current();
+        self.scale *= factor;
+        self.elapsed = 0.0;
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.elapsed < TRANSITION_SECS
+    }
+
+    /// Return whether the scale changed in the time.
+    pub fn tick(&mut self, dt: f32) -> bool {
+        let active = self.is_active();
+        self.elapsed = (self.elapsed + dt).min(TRANSITION_SECS);
+        active
+    }
+
+    /// The scale eased by the smoothstep.
+    pub fn current(&self) -> f32 {
+        let t = (self.elapsed / TRANSITION_SECS).clamp(0.0, 1.0);
+        let t = t * t * (3.0 - 2.0 * t);
+        self.from + (self.scale - self.from) * t
+    }
+
+    /// The scale after the transition.
+    pub fn target(&self) -> f32 {
+        self.scale
+    }
+
+    /// Scale the near plane by the current scale, and zoom by the current to the target to settle to the default field of view.
+    pub fn apply(&self, camera: &mut Camera) {
+        let Camera { fovy, z_near, .. } = Camera::new(camera.eye);
+        let current = self.current();
+        camera.z_near = z_near * current.min(1.0);
+        let zoom = (fovy * 0.5).tan() * current / self.scale;
+        camera.fovy = (zoom.atan() * 2.0).clamp(FOVY_RANGE.0.to_radians(), FOVY_RANGE.1.to_radians());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use nalgebra::point;
+
+    use crate::engine::render::camera::Camera;
+    use crate::state::real_view::transition::ScaleTransition;
+
+    #[test]
+    fn test_scale_transition() {
+        let mut transition = ScaleTransition::default();
+        assert!(!transition.tick(0.1));
+        transition.traversed(2.0);
+        assert_eq!(transition.current(), 1.0);
+        assert!(transition.tick(0.15));
+        assert!((transition.current() - 1.5).abs() < 1e-4);
+
+        let mut camera = Camera::new(point![0.0, 0.0, 0.0]);
+        let fovy = camera.fovy;
+        transition.apply(&mut camera);
+        // zoomed in to look smaller
+        assert!(camera.fovy < fovy);
+        assert!(transition.tick(1.0));
+        assert!(!transition.tick(1.0));
+        assert_eq!(transition.current(), 2.0);
+        transition.apply(&mut camera);
+        assert!((camera.fovy - fovy).abs() < 1e-4);
+
+        // the near plane only smaller
+        transition.traversed(0.125);
+        transition.tick(1.0);
+        transition.apply(&mut camera);
+        assert!((transition.target() - 0.25).abs() < 1e-6);
+        assert!((camera.z_near - 0.0001 * 0.25).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+
+use kira::sound::static_sound::StaticSoundData;
+use log::{debug, warn};
+use nalgebra::Vector3;
+use rapier3d::prelude::{ActiveCollisionTypes, ActiveEvents, ColliderBuilder, ColliderHandle};
+
+use crate::engine::{AudioData, Handle, ResourceManager};
+use crate::engine::physics::event::ColliderTag;
+use crate::engine::physics::state::RapierData;
+use crate::engine::renderer3d::renderer3d::PointLight;
+use crate::state::real_view::gate::PortalGate;
+use crate::state::real_view::level::MagicLevel;
+
+/// The script called with the level when the trigger fired.
+pub type TriggerScript = Box<dyn FnMut(&mut MagicLevel) + Send>;
+
+/// What to do when I walk in or out of the trigger.
+#[allow(unused)]
+pub enum TriggerAction {
+    /// Open or close the portal and the connecting one.
+    SetPortalOpen((usize, usize), bool),
+    SetPortalGate((usize, usize), PortalGate),
+    GiveKey(String),
+    /// Fire the hint event.
+    Hint(String),
+    PlaySound(Handle<StaticSoundData>),
+    /// Replace the point lights, applied when rendered.
+    SetLights(Vec<PointLight>),
+    Script(TriggerScript),
+}
+
+/// The sensor box firing the actions when I walk in or out.
+#[allow(unused)]
+pub struct TriggerVolume {
+    pub collider: ColliderHandle,
+    pub on_enter: Vec<TriggerAction>,
+    pub on_exit: Vec<TriggerAction>,
+    /// Fire only the first enter and exit.
+    pub once: bool,
+    inside: bool,
+    /// The times entered.
+    pub entered: u32,
+}
+
+/// The trigger volumes of the level and the enters and exits not run yet.
+#[derive(Default)]
+pub struct Triggers {
+    volumes: Vec<TriggerVolume>,
+    by_collider: HashMap<ColliderHandle, usize>,
+    /// (trigger, entered) in the order touched in the steps.
+    fired: Vec<(usize, bool)>,
+    /// The lights set by the triggers and not applied.
+    pub(crate) lights: Option<Vec<PointLight>>,
+}
+
+#[allow(unused)]
+impl Triggers {
+    /// Add the box of the half extents at the center, return the trigger.
+    pub fn add(&mut self, p: &mut RapierData, center: Vector3<f32>, half: Vector3<f32>) -> usize {
+        let collider = p.collider_set.insert(ColliderBuilder::cuboid(half.x, half.y, half.z)
+            .sensor(true)
+            .translation(center)
+            .user_data(ColliderTag::Trigger.into())
+            .active_collision_types(ActiveCollisionTypes::default() | ActiveCollisionTypes::KINEMATIC_FIXED)
+            .active_events(ActiveEvents::COLLISION_EVENTS)
+            .build());
+        let idx = self.volumes.len();
+        self.by_collider.insert(collider, idx);
+        self.volumes.push(TriggerVolume {
+            collider,
+            on_enter: vec![],
+            on_exit: vec![],
+            once: false,
+            inside: false,
+            entered: 0,
+        });
+        idx
+    }
+
+    pub fn get_mut(&mut self, trigger: usize) -> Option<&mut TriggerVolume> {
+        self.volumes.get_mut(trigger)
+    }
+
+    pub fn len(&self) -> usize {
+        self.volumes.len()
+    }
+
+    /// Record the enter or exit if one of the colliders is `me` and the other is the trigger.
+    ///
+    /// Return true if the trigger is touched.
+    pub fn touch(&mut self, me: ColliderHandle, c1: ColliderHandle, c2: ColliderHandle, started: bool) -> bool {
+        let other = if c1 == me { c2 } else if c2 == me { c1 } else {
+            return false;
+        };
+        let idx = if let Some(x) = self.by_collider.get(&other) { *x } else {
+            return false;
+        };
+        let volume = &mut self.volumes[idx];
+        if volume.inside == started {
+            return true;
+        }
+        volume.inside = started;
+        if started {
+            volume.entered += 1;
+        }
+        if !volume.once || volume.entered <= 1 {
+            self.fired.push((idx, started));
+        }
+        true
+    }
+
+    pub fn take_fired(&mut self) -> Vec<(usize, bool)> {
+        std::mem::take(&mut self.fired)
+    }
+
+    fn actions(&mut self, trigger: usize, entered: bool) -> &mut Vec<TriggerAction> {
+        let volume = &mut self.volumes[trigger];
+        if entered { &mut volume.on_enter } else { &mut volume.on_exit }
+    }
+}
+
+impl MagicLevel {
+    /// Run the actions of the triggers entered or exited in the steps.
+    pub(crate) fn run_triggers(&mut self, mut audio: Option<&mut AudioData>, res: &ResourceManager) {
+        for (trigger, entered) in self.triggers.take_fired() {
+            debug!(target: "level", "Trigger {} {}", trigger, if entered { "entered" } else { "exited" });
+            // taken out for the scripts changing the level
+            let mut actions = std::mem::take(self.triggers.actions(trigger, entered));
+            for action in actions.iter_mut() {
+                self.run_action(action, audio.as_deref_mut(), res);
+            }
+            let now = self.triggers.actions(trigger, entered);
+            actions.append(now);
+            *now = actions;
+        }
+    }
+
+    fn run_action(&mut self, action: &mut TriggerAction, audio: Option<&mut AudioData>, res: &ResourceManager) {
+        let result = match action {
+            TriggerAction::SetPortalOpen(portal, open) => self.set_portal_open(*portal, *open),
+            TriggerAction::SetPortalGate(portal, gate) => self.set_portal_gate(*portal, gate.clone()),
+            TriggerAction::GiveKey(key) => {
+                self.keys.give(key.clone());
+                Ok(())
+            }
+            TriggerAction::Hint(event) => {
+                self.hints.fire(event);
+                Ok(())
+            }
+            TriggerAction::PlaySound(sound) => match audio {
+                Some(audio) => audio.play_sfx(res, *sound).map(|_| ()),
+                None => Ok(()),
+            },
+            TriggerAction::SetLights(lights) => {
+                self.triggers.lights = Some(lights.clone());
+                Ok(())
+            }
+            TriggerAction::Script(script) => {
+                script(self);
+                Ok(())
+            }
+        };
+        if let Err(e) = result {
+            warn!(target: "level", "Run the trigger action failed for {:?}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use nalgebra::vector;
+
+    use crate::engine::physics::event::ColliderTag;
+    use crate::engine::physics::state::RapierData;
+    use crate::state::real_view::trigger::Triggers;
+
+    #[test]
+    fn test_triggers() {
+        let mut p = RapierData::new();
+        let me = p.collider_set.insert(rapier3d::prelude::ColliderBuilder::ball(0.5).build());
+        let mut triggers = Triggers::default();
+        let trigger = triggers.add(&mut p, vector![0.0, 0.0, 1.0], vector![1.0, 1.0, 1.0]);
+        let once = triggers.add(&mut p, vector![4.0, 0.0, 1.0], vector![1.0, 1.0, 1.0]);
+        triggers.get_mut(once).unwrap().once = true;
+        let collider = triggers.get_mut(trigger).unwrap().collider;
+        let once_collider = triggers.get_mut(once).unwrap().collider;
+        assert_eq!(ColliderTag::of(&p.collider_set[collider]), ColliderTag::Trigger);
+
+        // not me
+        assert!(!triggers.touch(me, collider, once_collider, true));
+        assert!(triggers.touch(me, collider, me, true));
+        // entered already
+        assert!(triggers.touch(me, me, collider, true));
+        assert!(triggers.touch(me, me, collider, false));
+        for _ in 0..2 {
+            triggers.touch(me, me, once_collider, true);
+            triggers.touch(me, once_collider, me, false);
+        }
+        assert_eq!(triggers.take_fired(), vec![(trigger, true), (trigger, false), (once, true), (once, false)]);
+        assert!(triggers.take_fired().is_empty());
+        assert_eq!(triggers.get_mut(once).unwrap().entered, 2);
+    }
+}
@@ -0,0 +1,127 @@
+use std::collections::BTreeMap;
+
+use log::warn;
+
+use crate::engine::GameState;
+use crate::engine::global::GLOBAL_DATA;
+use crate::state::about::AboutState;
+use crate::state::assets::AssetBrowserState;
+use crate::state::menu::MainMenuState;
+use crate::state::real_view::test_view::Test3DState;
+use crate::state::settings::SettingState;
+use crate::state::stats::StatisticsState;
+
+/// The state booted into if not selected.
+pub const DEFAULT_START_STATE: &str = "menu";
+/// The command line option to select the start state, as `--state name` or `--state=name`
+const STATE_ARG: &str = "--state";
+
+pub type StateConstructor = Box<dyn Fn() -> Box<dyn GameState + Send> + Send + Sync>;
+
+/// The constructors of the states by name to select the start state.
+pub struct StateRegistry {
+    states: BTreeMap<String, StateConstructor>,
+    default: String,
+}
+
+impl Default for StateRegistry {
+    /// The registry with the built-in states.
+    fn default() -> Self {
+        let mut this = Self::empty(DEFAULT_START_STATE);
+        this.register("menu", MainMenuState::default)
+            .register("test3d", Test3DState::default)
+            .register("settings", SettingState::default)
+            .register("stats", || StatisticsState)
+            .register("assets", AssetBrowserState::default)
+            .register("about", || AboutState);
+        this
+    }
+}
+
+#[allow(unused)]
+impl StateRegistry {
+    /// The registry without any state, `default` must be registered before booting.
+    pub fn empty(default: impl Into<String>) -> Self {
+        Self {
+            states: Default::default(),
+            default: default.into(),
+        }
+    }
+
+    /// Register the constructor, replace the old one with the same name.
+    pub fn register<S: GameState + Send + 'static>(&mut self, name: impl Into<String>, f: impl Fn() -> S + Send + Sync + 'static) -> &mut Self {
+        self.states.insert(name.into(), Box::new(move || Box::new(f())));
+        self
+    }
+
+    pub fn set_default(&mut self, name: impl Into<String>) -> &mut Self {
+        self.default = name.into();
+        self
+    }
+
+    pub fn default_name(&self) -> &str {
+        &self.default
+    }
+
+    pub fn names(&self) -> impl Iterator<Item=&str> {
+        self.states.keys().map(|x| x.as_str())
+    }
+
+    pub fn create(&self, name: &str) -> Option<Box<dyn GameState + Send>> {
+        self.states.get(name).map(|f| f())
+    }
+
+    /// Create the start state selected by the command line, then the config, then the default.
+    ///
+    /// The unknown name is warned and the default is used.
+    pub fn create_start(&self) -> anyhow::Result<Box<dyn GameState + Send>> {
+        let name = state_arg(std::env::args().skip(1))
+            .or_else(|| Some(GLOBAL_DATA.cfg_data.read().unwrap().settings().start.state.clone()))
+            .filter(|x| !x.is_empty())
+            .unwrap_or_else(|| self.default.clone());
+        if let Some(state) = self.create(&name) {
+            log::info!("Starting with the state {}", name);
+            return Ok(state);
+        }
+        warn!("No state named {}, the states are {:?}", name, self.names().collect::<Vec<_>>());
+        self.create(&self.default).ok_or(anyhow::anyhow!("The default state {} is not registered", self.default))
+    }
+}
+
+/// Get the state name from the command line arguments.
+fn state_arg(mut args: impl Iterator<Item=String>) -> Option<String> {
+    while let Some(arg) = args.next() {
+        if arg == STATE_ARG {
+            return args.next();
+        }
+        if let Some(name) = arg.strip_prefix(STATE_ARG).and_then(|x| x.strip_prefix('=')) {
+            return Some(name.to_string());
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use crate::engine::GameState;
+    use crate::state::registry::{state_arg, StateRegistry};
+
+    struct EmptyState;
+
+    impl GameState for EmptyState {}
+
+    #[test]
+    fn test_registry() {
+        let args = |x: &[&str]| x.iter().map(|x| x.to_string()).collect::<Vec<_>>().into_iter();
+        assert_eq!(state_arg(args(&["--state", "menu"])), Some("menu".into()));
+        assert_eq!(state_arg(args(&["-v", "--state=bench"])), Some("bench".into()));
+        assert_eq!(state_arg(args(&["--state"])), None);
+        assert_eq!(state_arg(args(&["--stately"])), None);
+
+        let mut registry = StateRegistry::empty("empty");
+        registry.register("empty", || EmptyState);
+        assert!(registry.create("empty").is_some());
+        assert!(registry.create("menu").is_none());
+        assert_eq!(registry.names().collect::<Vec<_>>(), ["empty"]);
+    }
+}
@@ -1,11 +1,20 @@
 use egui::{Context, Frame};
+use winit::event::VirtualKeyCode;
 
 use crate::engine::{GameState, LoopState, StateData, Trans};
+use crate::engine::config::{SettingsChanged, WindowMode};
+use crate::engine::state::bus;
+use crate::engine::global::GLOBAL_DATA;
 use crate::state::settings::SettingCategory::*;
+use crate::state::about::AboutState;
+use crate::state::assets::AssetBrowserState;
+use crate::state::stats::StatisticsState;
 
 #[derive(Default)]
 pub struct SettingState {
     cur_cat: SettingCategory,
+    /// The ui scale dragged, applied after released not to move the slider under the pointer.
+    ui_scale: Option<f32>,
 }
 
 
@@ -22,12 +31,34 @@ impl Default for SettingCategory {
     }
 }
 
+fn window_mode_name(mode: WindowMode) -> &'static str {
+    match mode {
+        WindowMode::Windowed => "窗口",
+        WindowMode::Borderless => "无边框",
+        WindowMode::Fullscreen => "全屏",
+    }
+}
+
 impl GameState for SettingState {
-    fn update(&mut self, _: &mut StateData) -> (Trans, LoopState) {
+    fn update(&mut self, s: &mut StateData) -> (Trans, LoopState) {
+        if s.app.inputs.is_pressed(&[VirtualKeyCode::Escape]) {
+            return (Trans::Pop, LoopState::WAIT);
+        }
         (Trans::None, LoopState::WAIT)
     }
 
-    fn render(&mut self, _s: &mut StateData, ctx: &Context) -> Trans {
+    fn stop(&mut self, s: &mut StateData) {
+        GLOBAL_DATA.cfg_data.write().unwrap().save_if_dirty();
+        bus::publish(s.wd.world, SettingsChanged);
+    }
+
+    fn render(&mut self, s: &mut StateData, ctx: &Context) -> Trans {
+        let mut tran = Trans::None;
+        // edited in the copy, not holding the lock in the ui and the calls to the window
+        let old = GLOBAL_DATA.cfg_data.read().unwrap().settings().clone();
+        let mut settings = old.clone();
+        // applied after the settings written, the resizing by it reads them
+        let mut window_mode = None;
         egui::SidePanel::left("cats")
             .resizable(false)
             .default_width(128.0)
@@ -40,13 +71,241 @@ impl GameState for SettingState {
                 });
             });
         egui::CentralPanel::default().frame(Frame::none())
-            .show(ctx, |_ui| {
+            .show(ctx, |ui| {
                 match self.cur_cat {
-                    General => {}
-                    Video => {}
-                    Audio => {}
+                    General => {
+                        if ui.button("统计").clicked() {
+                            tran = Trans::Push(Box::new(StatisticsState));
+                        }
+                        if ui.button("资源").clicked() {
+                            tran = Trans::Push(Box::new(AssetBrowserState::default()));
+                        }
+                        if ui.button("关于").clicked() {
+                            tran = Trans::Push(Box::new(AboutState));
+                        }
+                        let mut input = settings.input.clone();
+                        ui.horizontal(|ui| {
+                            ui.label("鼠标灵敏度");
+                            ui.add(egui::Slider::new(&mut input.mouse_sensitivity, 0.1..=5.0).logarithmic(true));
+                        });
+                        ui.horizontal(|ui| {
+                            ui.label("视角平滑");
+                            ui.add(egui::Slider::new(&mut input.look_smoothing, 0.0..=0.2).suffix("s"));
+                        });
+                        ui.checkbox(&mut input.invert_y, "反转Y轴");
+                        ui.horizontal(|ui| {
+                            ui.label("摇杆死区");
+                            ui.add(egui::Slider::new(&mut input.dead_zone, 0.0..=0.5));
+                        });
+                        if input != settings.input {
+                            settings.input = input;
+                        }
+                        ui.horizontal(|ui| {
+                            ui.label("服务器地址");
+                            let mut server = settings.network.server.clone();
+                            if ui.text_edit_singleline(&mut server).changed() {
+                                settings.network.server = server;
+                            }
+                        });
+                        ui.horizontal(|ui| {
+                            ui.label("会合服务器");
+                            let mut rendezvous = settings.network.rendezvous.clone();
+                            if ui.text_edit_singleline(&mut rendezvous).changed() {
+                                settings.network.rendezvous = rendezvous;
+                            }
+                        });
+                        ui.horizontal(|ui| {
+                            ui.label("房间");
+                            let mut session = settings.network.session.clone();
+                            if ui.text_edit_singleline(&mut session).changed() {
+                                settings.network.session = session;
+                            }
+                        });
+                    }
+                    Video => {
+                        if let Some(gpu) = s.app.gpu.as_mut() {
+                            let mut scale = gpu.render_scale * 100.0;
+                            ui.horizontal(|ui| {
+                                ui.label("渲染比例");
+                                if ui.add(egui::Slider::new(&mut scale, 50.0..=200.0).suffix("%")).changed() {
+                                    gpu.set_render_scale(scale / 100.0);
+                                    settings.video.render_scale = gpu.render_scale;
+                                }
+                            });
+                            let mut vsync = gpu.vsync();
+                            if ui.checkbox(&mut vsync, "垂直同步").changed() {
+                                gpu.set_vsync(vsync);
+                                settings.video.vsync = vsync;
+                            }
+                            let mut hdr = gpu.hdr;
+                            ui.horizontal(|ui| {
+                                if ui.checkbox(&mut hdr, "HDR").changed() {
+                                    settings.video.hdr = hdr;
+                                    // the pipelines are in the formats, created again with the gpu data in the next frame
+                                    gpu.mark_lost();
+                                }
+                                if gpu.hdr && !gpu.formats.is_hdr_output() {
+                                    ui.label("(显示器不支持HDR输出)");
+                                }
+                            });
+                        }
+                        let mut preview = settings.video.portal_preview;
+                        if ui.checkbox(&mut preview, "传送门预览").changed() {
+                            settings.video.portal_preview = preview;
+                        }
+                        let mut pip = settings.video.pip;
+                        if ui.checkbox(&mut pip, "画中画").changed() {
+                            settings.video.pip = pip;
+                        }
+                        let mut perf_hud = settings.video.perf_hud;
+                        if ui.checkbox(&mut perf_hud, "性能面板").changed() {
+                            settings.video.perf_hud = perf_hud;
+                        }
+                        let mut depth = settings.video.portal_depth;
+                        ui.horizontal(|ui| {
+                            ui.label("传送门深度");
+                            if ui.add(egui::Slider::new(&mut depth, 1..=16)).changed() {
+                                settings.video.portal_depth = depth;
+                            }
+                        });
+                        let mut falloff = settings.video.portal_view_falloff * 100.0;
+                        ui.horizontal(|ui| {
+                            ui.label("深层传送门分辨率");
+                            if ui.add(egui::Slider::new(&mut falloff, 25.0..=100.0).suffix("%")).changed() {
+                                settings.video.portal_view_falloff = falloff / 100.0;
+                            }
+                        });
+                        let mut labels = settings.video.portal_labels;
+                        if ui.checkbox(&mut labels, "传送门标签").changed() {
+                            settings.video.portal_labels = labels;
+                        }
+                        let mut tonemap = settings.video.tonemap;
+                        if ui.checkbox(&mut tonemap, "色调映射").changed() {
+                            settings.video.tonemap = tonemap;
+                        }
+                        if tonemap {
+                            let mut exposure = settings.video.exposure;
+                            ui.horizontal(|ui| {
+                                ui.label("曝光");
+                                if ui.add(egui::Slider::new(&mut exposure, 0.25..=4.0)).changed() {
+                                    settings.video.exposure = exposure;
+                                }
+                            });
+                        }
+                        let mut fxaa = settings.video.fxaa;
+                        if ui.checkbox(&mut fxaa, "抗锯齿 (FXAA)").changed() {
+                            settings.video.fxaa = fxaa;
+                        }
+                        let mut bloom = settings.video.bloom;
+                        ui.horizontal(|ui| {
+                            ui.label("泛光强度");
+                            if ui.add(egui::Slider::new(&mut bloom, 0.0..=2.0)).changed() {
+                                settings.video.bloom = bloom;
+                            }
+                        });
+                        let mut effects = settings.video.portal_effects;
+                        if ui.checkbox(&mut effects, "传送门特效").changed() {
+                            settings.video.portal_effects = effects;
+                        }
+                        let mut adaptive = settings.video.adaptive_portal_depth;
+                        if ui.checkbox(&mut adaptive, "自适应深度").changed() {
+                            settings.video.adaptive_portal_depth = adaptive;
+                        }
+                        let mut background_fps = settings.video.background_fps;
+                        ui.horizontal(|ui| {
+                            ui.label("后台帧率");
+                            if ui.add(egui::Slider::new(&mut background_fps, 1..=60)).changed() {
+                                settings.video.background_fps = background_fps;
+                            }
+                        });
+                        let mut ui_scale = self.ui_scale.unwrap_or(settings.video.ui_scale) * 100.0;
+                        ui.horizontal(|ui| {
+                            ui.label("界面缩放");
+                            let response = ui.add(egui::Slider::new(&mut ui_scale, 50.0..=300.0).suffix("%"));
+                            if response.dragged() {
+                                self.ui_scale = Some(ui_scale / 100.0);
+                            } else if response.changed() || self.ui_scale.is_some() {
+                                self.ui_scale = None;
+                                settings.video.ui_scale = ui_scale / 100.0;
+                                s.app.ui.set_ui_scale(&s.app.window, ui_scale / 100.0);
+                            }
+                        });
+                        let video = settings.video.clone();
+                        let mut mode = video.window_mode;
+                        let mut size = video.fullscreen_size;
+                        ui.horizontal(|ui| {
+                            ui.label("窗口模式");
+                            egui::ComboBox::from_id_source("window mode")
+                                .selected_text(window_mode_name(mode))
+                                .show_ui(ui, |ui| {
+                                    for x in [WindowMode::Windowed, WindowMode::Borderless, WindowMode::Fullscreen] {
+                                        ui.selectable_value(&mut mode, x, window_mode_name(x));
+                                    }
+                                });
+                        });
+                        if mode == WindowMode::Fullscreen {
+                            ui.horizontal(|ui| {
+                                ui.label("分辨率");
+                                let size_name = |x: Option<(u32, u32)>| x.map(|(w, h)| format!("{}x{}", w, h))
+                                    .unwrap_or_else(|| "默认".into());
+                                egui::ComboBox::from_id_source("fullscreen size")
+                                    .selected_text(size_name(size))
+                                    .show_ui(ui, |ui| {
+                                        ui.selectable_value(&mut size, None, size_name(None));
+                                        for x in s.app.fullscreen_sizes() {
+                                            ui.selectable_value(&mut size, Some(x), size_name(Some(x)));
+                                        }
+                                    });
+                            });
+                        }
+                        if mode != video.window_mode || size != video.fullscreen_size {
+                            let video = &mut settings.video;
+                            video.window_mode = mode;
+                            video.fullscreen_size = size;
+                            let window = &settings.window;
+                            window_mode = Some((mode, match mode {
+                                WindowMode::Windowed => Some((window.width, window.height)),
+                                _ => size,
+                            }));
+                        }
+                    }
+                    Audio => {
+                        if let Some(audio) = s.app.audio.as_mut() {
+                            let volume_slider = |ui: &mut egui::Ui, label: &str, volume: f64, set: &mut dyn FnMut(f64) -> anyhow::Result<()>| {
+                                let mut volume = volume * 100.0;
+                                ui.horizontal(|ui| {
+                                    ui.label(label);
+                                    if ui.add(egui::Slider::new(&mut volume, 0.0..=100.0).suffix("%")).changed() {
+                                        if let Err(e) = set(volume / 100.0) {
+                                            log::warn!("Set volume failed for {:?}", e);
+                                        }
+                                    }
+                                });
+                            };
+                            volume_slider(ui, "主音量", audio.master_volume(), &mut |x| {
+                                settings.audio.master_volume = x;
+                                audio.set_master_volume(x)
+                            });
+                            volume_slider(ui, "音乐", audio.music_volume(), &mut |x| {
+                                settings.audio.music_volume = x;
+                                audio.set_music_volume(x)
+                            });
+                            volume_slider(ui, "音效", audio.sfx_volume(), &mut |x| {
+                                settings.audio.sfx_volume = x;
+                                audio.set_sfx_volume(x)
+                            });
+                        } else {
+                            ui.label("没有音频设备");
+                        }
+                    }
                 }
             });
-        Trans::None
+        if settings != old {
+            *GLOBAL_DATA.cfg_data.write().unwrap().settings_mut() = settings;
+        }
+        if let Some((mode, size)) = window_mode {
+            s.app.set_window_mode(mode, size);
+        }
+        tran
     }
 }
\ No newline at end of file
@@ -0,0 +1,64 @@
+use egui::{Context, Grid};
+use winit::event::VirtualKeyCode;
+
+use crate::engine::{GameState, LoopState, StateData, Trans};
+use crate::engine::stats::Statistics;
+
+/// Show the statistics and achievements.
+#[derive(Default)]
+pub struct StatisticsState;
+
+impl GameState for StatisticsState {
+    fn update(&mut self, s: &mut StateData) -> (Trans, LoopState) {
+        if s.app.inputs.is_pressed(&[VirtualKeyCode::Escape]) {
+            return (Trans::Pop, LoopState::WAIT);
+        }
+        (Trans::None, LoopState::WAIT)
+    }
+
+    fn render(&mut self, s: &mut StateData, ctx: &Context) -> Trans {
+        let mut tran = Trans::None;
+        egui::CentralPanel::default()
+            .show(ctx, |ui| {
+                if ui.button("返回").clicked() {
+                    tran = Trans::Pop;
+                }
+                let stats = if let Some(stats) = s.wd.world.try_fetch::<Statistics>() { stats } else {
+                    ui.label("没有统计数据");
+                    return;
+                };
+                ui.heading("统计");
+                Grid::new("stats").striped(true).show(ui, |ui| {
+                    ui.label("穿过传送门");
+                    ui.label(stats.portals_traversed.to_string());
+                    ui.end_row();
+                    ui.label("行走距离");
+                    ui.label(format!("{:.1} m", stats.distance_walked));
+                    ui.end_row();
+                    ui.label("完成关卡");
+                    ui.label(stats.levels_completed.to_string());
+                    ui.end_row();
+                    ui.label("最大递归深度");
+                    ui.label(stats.max_recursion_depth.to_string());
+                    ui.end_row();
+                    for (k, v) in &stats.custom {
+                        ui.label(k);
+                        ui.label(v.to_string());
+                        ui.end_row();
+                    }
+                });
+
+                ui.separator();
+                ui.heading("成就");
+                Grid::new("achievements").striped(true).show(ui, |ui| {
+                    for x in stats.achievements() {
+                        ui.label(if stats.is_unlocked(x.id()) { "✔" } else { "　" });
+                        ui.label(x.name());
+                        ui.label(x.description());
+                        ui.end_row();
+                    }
+                });
+            });
+        tran
+    }
+}